@@ -0,0 +1,80 @@
+//! Schema diff benchmark: `cargo bench --bench diff [-- <tables>]`
+//!
+//! Diffs a synthetic multi-tenant schema (one set of tables per tenant)
+//! against a database that has drifted from it, and reports the time taken
+//! by each step. No database is needed.
+
+use serde_json::{json, Map, Value};
+use std::time::{Duration, Instant};
+use stratus::diff::{compare_schemas, DbSchema};
+use stratus::schema::Schema;
+
+const COLUMNS: usize = 20;
+const RUNS: u32 = 5;
+const TYPES: [&str; 5] = ["bigint", "text", "varchar", "timestamptz", "jsonb"];
+
+/// `tables` tables of `COLUMNS` columns; `variant` shifts which ones exist
+fn schema(tables: usize, variant: usize) -> Schema {
+    let tables: Map<String, Value> = (0..tables)
+        .filter(|t| !(t + variant).is_multiple_of(50))
+        .map(|t| {
+            let columns: Map<String, Value> = (0..COLUMNS)
+                .filter(|c| !(t + c + variant).is_multiple_of(97))
+                .map(|c| {
+                    let name = format!("col_{}", c);
+                    let column = json!({
+                        "name": name,
+                        "type": TYPES[c % 5],
+                        "isPrimaryKey": c == 0,
+                        "isNotNull": c.is_multiple_of(3),
+                    });
+                    (name, column)
+                })
+                .collect();
+            (
+                format!("tenant_{}_table_{}", t / 25, t % 25),
+                json!({ "columns": columns }),
+            )
+        })
+        .collect();
+    serde_json::from_value(json!({ "dialect": "postgresql", "tables": tables })).unwrap()
+}
+
+fn time<T>(label: &str, mut f: impl FnMut() -> T) -> T {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        result = Some(f());
+        best = best.min(started.elapsed());
+    }
+    println!("{:<24} {:>10.2?} (best of {})", label, best, RUNS);
+    result.unwrap()
+}
+
+fn main() {
+    let tables = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(5_000);
+    let desired = schema(tables, 0);
+    let current = DbSchema::from_json_schema(&schema(tables, 1));
+    println!("diffing {} tables of up to {} columns", tables, COLUMNS);
+
+    let diff = time("compare_schemas", || compare_schemas(&desired, &current));
+    time("write_sql", || {
+        let mut sql = String::new();
+        diff.write_sql(&mut sql).unwrap();
+        sql
+    });
+    time("compare_schemas (noop)", || {
+        compare_schemas(&desired, &DbSchema::from_json_schema(&desired))
+    });
+    println!(
+        "{} tables created, {} dropped, {} altered; {} bytes of SQL",
+        diff.create_tables.len(),
+        diff.drop_tables.len(),
+        diff.create_columns.len() + diff.drop_columns.len(),
+        diff.sql().len()
+    );
+}
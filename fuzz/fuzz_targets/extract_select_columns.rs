@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|sql: &str| {
+    let _ = stratus::parser::extract_select_columns(sql);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// TypeSQL from an editor buffer: parsing must report, never panic
+fuzz_target!(|input: &str| {
+    let _ = stratus::parser::parse(input);
+    let (query_file, _) = stratus::parser::parse_with_diagnostics(input);
+    for query in &query_file.queries {
+        let _ = stratus::infer::table_refs(&query.sql);
+    }
+});
@@ -0,0 +1,41 @@
+/*
+ * Stratus C API
+ *
+ * Build with `cargo build --release --no-default-features --features ffi`
+ * and link against libstratus.
+ *
+ * Strings are NUL-terminated UTF-8. Every returned `char *` belongs to the
+ * caller and must be released with stratus_string_free(). A NULL result
+ * means failure; stratus_last_error() then returns the reason for the
+ * calling thread.
+ */
+#ifndef STRATUS_H
+#define STRATUS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Parse TypeSQL; returns the queries as JSON. */
+char *stratus_parse(const char *input);
+
+/* Table names referenced by an SQL statement, as a JSON array. */
+char *stratus_extract_tables(const char *sql);
+
+/* TypeScript client for a TypeSQL file; schema_json (schema.json) may be NULL. */
+char *stratus_generate_ts(const char *input, const char *schema_json);
+
+/* Why the last call on this thread returned NULL, or NULL after a success. */
+char *stratus_last_error(void);
+
+/* Release a string returned by this library; NULL is ignored. */
+void stratus_string_free(char *s);
+
+/* Library version; static, do not free. */
+const char *stratus_version(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* STRATUS_H */
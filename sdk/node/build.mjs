@@ -0,0 +1,17 @@
+// Build the native addon: cargo build with the `node` feature, then copy the library to stratus.node
+import { execFileSync } from 'node:child_process';
+import { copyFileSync } from 'node:fs';
+import { fileURLToPath } from 'node:url';
+import path from 'node:path';
+
+const root = path.resolve(path.dirname(fileURLToPath(import.meta.url)), '../..');
+execFileSync('cargo', ['build', '--release', '--lib', '--no-default-features', '--features', 'node'], {
+  cwd: root,
+  stdio: 'inherit',
+});
+
+const library = {
+  darwin: 'libstratus.dylib',
+  win32: 'stratus.dll',
+}[process.platform] ?? 'libstratus.so';
+copyFileSync(path.join(root, 'target/release', library), new URL('./stratus.node', import.meta.url));
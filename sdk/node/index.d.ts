@@ -0,0 +1,115 @@
+export interface Query {
+  name: string;
+  returnType: string;
+  sql: string;
+  params: { name: string; type: string; ordinal: number }[];
+  /** `@cache(ttl=...)` in the header */
+  cache?: { ttlSeconds: number };
+}
+
+export interface Diagnostic {
+  severity: 'error' | 'warning' | 'info';
+  span: { line: number; column: number; endLine: number; endColumn: number };
+  message: string;
+  code: string;
+}
+
+export interface DbColumn {
+  name: string;
+  data_type: string;
+  is_nullable: boolean;
+  is_primary_key: boolean;
+  default_value: string | null;
+  size: number | null;
+}
+
+export interface DbForeignKey {
+  name: string;
+  columns: string[];
+  references_table: string;
+  references_columns: string[];
+  /** e.g. `CASCADE`; absent for NO ACTION */
+  on_delete?: string;
+  on_update?: string;
+}
+
+export interface DbConstraint {
+  name: string;
+  constraint_type: 'check' | 'unique' | 'exclude';
+  columns?: string[];
+  /** The CHECK expression, or what follows EXCLUDE */
+  expression?: string;
+}
+
+export interface DbIndex {
+  name: string;
+  unique: boolean;
+  /** CREATE INDEX statement as reported by the database */
+  definition: string;
+}
+
+/** One DDL step of a diff, in the order it runs */
+export type Operation =
+  | { op: 'drop_column'; table: string; column: string }
+  | { op: 'drop_table'; table: string }
+  | { op: 'create_table'; table: string; definition: object }
+  | { op: 'add_column'; table: string; column: DbColumn }
+  | {
+      op: 'alter_column';
+      table: string;
+      column: DbColumn;
+      from: DbColumn;
+      changes: ('type' | 'nullability' | 'default')[];
+    }
+  | { op: 'add_foreign_key' | 'drop_foreign_key'; table: string; foreign_key: DbForeignKey }
+  | { op: 'add_constraint' | 'drop_constraint'; table: string; constraint: DbConstraint }
+  | { op: 'create_index'; table: string; index: object }
+  | { op: 'drop_index'; table: string; index: DbIndex }
+  | { op: 'create_enum' | 'drop_enum'; name: string; values: string[] }
+  | { op: 'add_enum_value'; name: string; value: string; after?: string; before?: string }
+  | { op: 'create_role'; role: string }
+  | { op: 'grant' | 'revoke'; role: string; table: string; privileges: string[]; columns?: string[] };
+
+export interface SchemaDiff {
+  operations: Operation[];
+  create_tables: string[];
+  alter_tables: string[];
+  drop_tables: string[];
+  create_columns: Record<string, DbColumn[]>;
+  alter_columns: Record<string, DbColumn[]>;
+  drop_columns: Record<string, string[]>;
+  add_foreign_keys: string[];
+  drop_foreign_keys: string[];
+  add_constraints: string[];
+  drop_constraints: string[];
+  create_indexes: string[];
+  drop_indexes: string[];
+  create_enums: string[];
+  add_enum_values: Record<string, string[]>;
+  drop_enums: string[];
+  create_roles: string[];
+  grants: string[];
+  revokes: string[];
+  data_loss_warning: string[];
+  /** Changes the SQL cannot make, such as removing an enum value */
+  warnings: string[];
+  sql: string;
+  dialect: string;
+}
+
+/** Parse TypeSQL; throws on the first error diagnostic. */
+export function parse(input: string): { queries: Query[] };
+
+/** Diagnostics for a TypeSQL file, checked against a schema.json object when given. */
+export function check(input: string, schema?: object | null): Diagnostic[];
+
+/** Run a generator (`ts`, `py`, `sql`, `ts-types`, `py-types`) and return the code. */
+export function generate(
+  provider: string,
+  input?: string | null,
+  schema?: object | null,
+  options?: object | null,
+): string;
+
+/** Changes from one schema.json object to another, with the migration SQL. */
+export function diff(from: object, to: object): SchemaDiff;
@@ -0,0 +1,18 @@
+// Smoke test for the native addon; run `npm run build` first
+import assert from 'node:assert/strict';
+import { createRequire } from 'node:module';
+
+const { parse, check, generate, diff } = createRequire(import.meta.url)('./index.js');
+
+const source = '# name: GetUser :one id: number\nSELECT id, email FROM users WHERE id = $1;\n';
+const users = { tables: { users: { columns: { id: { name: 'id', type: 'bigint' } } } } };
+const withEmail = structuredClone(users);
+withEmail.tables.users.columns.email = { name: 'email', type: 'text' };
+
+assert.equal(parse(source).queries[0].name, 'GetUser');
+assert.throws(() => parse('# name: Empty :one\n'), /empty-query/);
+assert.equal(check(source, users)[0].code, 'unknown-column');
+assert.match(generate('ts', source, withEmail), /GetUser|getUser/);
+assert.deepEqual(diff(users, withEmail).create_columns.users.map((c) => c.name), ['email']);
+
+console.log('ok');
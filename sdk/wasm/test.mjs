@@ -1,57 +1,40 @@
-// Test file for WASM parser
-import init, { parse_typesql, validate_typesql, extract_tables, extract_columns, get_version } from './pkg/stratus';
+// Smoke test for the nodejs build; run `npm run build:node` first
+import assert from 'node:assert/strict';
+import { createRequire } from 'node:module';
 
-async function runTests() {
-  console.log('Initializing WASM module...');
-  await init();
-  console.log(`WASM version: ${get_version()}`);
+const wasm = createRequire(import.meta.url)('./node/stratus.js');
 
-  // Test parse_typesql
-  console.log('\n--- Testing parse_typesql ---');
-  const sql = `
+const source = `
 # name: GetUser :one id: number
-SELECT * FROM users WHERE id = $1;
+SELECT id, email FROM users WHERE id = $1;
 
 # name: ListUsers :many
-SELECT * FROM users ORDER BY created_at DESC;
+SELECT * FROM users ORDER BY id DESC;
 `;
+const users = { version: '1', tables: { users: { columns: { id: { name: 'id', type: 'bigint' } } } } };
+const withEmail = structuredClone(users);
+withEmail.tables.users.columns.email = { name: 'email', type: 'text' };
 
-  const result = parse_typesql(sql);
-  if (result.ok) {
-    console.log('Parsed successfully:');
-    console.log(JSON.parse(result.val).queries.map(q => ({
-      name: q.name,
-      returnType: q.return_type,
-      sql: q.sql.substring(0, 50) + '...'
-    })));
-  } else {
-    console.error('Parse failed:', result.val);
-  }
-
-  // Test validate_typesql
-  console.log('\n--- Testing validate_typesql ---');
-  const validSql = '# name: GetUser :one id: number\nSELECT * FROM users WHERE id = $1;';
-  const invalidSql = 'SELECT * FROM users WHERE id = $1;';
-
-  console.log('Valid SQL:', validate_typesql(validSql));
-  console.log('Invalid SQL (no header):', validate_typesql(invalidSql));
-
-  // Test extract_tables
-  console.log('\n--- Testing extract_tables ---');
-  const selectSql = 'SELECT u.id, u.email, o.order_number FROM users u JOIN orders o ON u.id = o.user_id WHERE u.id = $1';
-  const tablesResult = extract_tables(selectSql);
-  if (tablesResult.ok) {
-    console.log('Tables found:', JSON.parse(tablesResult.val));
-  }
-
-  // Test extract_columns
-  console.log('\n--- Testing extract_columns ---');
-  const columnsResult = extract_columns('SELECT u.id, u.email, o.order_number FROM users u');
-  if (columnsResult.ok) {
-    console.log('Columns found:', JSON.parse(columnsResult.val));
-  }
-
-  console.log('\n✓ All tests completed!');
-}
-
-runTests().catch(console.error);
+console.log(`WASM version: ${wasm.get_version()}`);
+
+const parsed = JSON.parse(wasm.parse_typesql(source));
+assert.deepEqual(parsed.queries.map((q) => q.name), ['GetUser', 'ListUsers']);
+assert.equal(wasm.validate_typesql(source), true);
+
+const diagnostics = JSON.parse(wasm.check_typesql(source, JSON.stringify(users)));
+assert.equal(diagnostics[0].code, 'unknown-column');
+
+assert.match(wasm.generate_ts(source, JSON.stringify(withEmail)), /GetUser|getUser/);
+assert.match(wasm.generate_py(source), /get_user|GetUser/);
+assert.match(wasm.generate_ts_types_only(JSON.stringify(withEmail)), /email/);
+
+assert.equal(JSON.parse(wasm.validate_schema(JSON.stringify(users))).valid, true);
+assert.equal(JSON.parse(wasm.validate_schema('{"tables": {}}')).valid, false);
+const diff = JSON.parse(wasm.diff_schemas(JSON.stringify(users), JSON.stringify(withEmail)));
+assert.deepEqual(diff.create_columns.users.map((c) => c.name), ['email']);
+assert.throws(() => wasm.diff_schemas('{', '{}'));
+
+const tables = JSON.parse(wasm.extract_tables('SELECT * FROM users u JOIN orders o ON u.id = o.user_id'));
+assert.deepEqual([...tables].sort(), ['orders', 'users']);
+
+console.log('✓ All tests passed');
@@ -0,0 +1,156 @@
+// Functions exported by the Stratus WASM module, shared by the web and nodejs builds.
+//
+// Functions returning `string` return JSON (except the generators, which
+// return source code) and throw a string on failure.
+
+export interface ParsedQuery {
+  name: string;
+  /** `one`, `many`, `exec`, ... as written in the header */
+  returnType: string;
+  sql: string;
+  params: { name: string; type: string; ordinal: number }[];
+  /** `@cache(ttl=...)` in the header */
+  cache?: { ttlSeconds: number };
+}
+
+/** JSON returned by `parse_typesql` */
+export interface ParsedQueryFile {
+  queries: ParsedQuery[];
+}
+
+/** One problem in a source file; lines and columns are 1-based, the end is exclusive */
+export interface Diagnostic {
+  severity: 'error' | 'warning' | 'info';
+  span: { line: number; column: number; endLine: number; endColumn: number };
+  message: string;
+  code: string;
+}
+
+/** JSON returned by `extract_columns` */
+export interface SelectColumn {
+  table_name: string | null;
+  column_name: string;
+  alias: string | null;
+  is_wildcard: boolean;
+}
+
+/** JSON returned by `validate_schema` */
+export interface SchemaValidation {
+  valid: boolean;
+  diagnostics: Diagnostic[];
+}
+
+export interface DbColumn {
+  name: string;
+  data_type: string;
+  is_nullable: boolean;
+  is_primary_key: boolean;
+  default_value: string | null;
+  size: number | null;
+}
+
+export interface DbForeignKey {
+  name: string;
+  columns: string[];
+  references_table: string;
+  references_columns: string[];
+  /** e.g. `CASCADE`; absent for NO ACTION */
+  on_delete?: string;
+  on_update?: string;
+}
+
+export interface DbConstraint {
+  name: string;
+  constraint_type: 'check' | 'unique' | 'exclude';
+  columns?: string[];
+  /** The CHECK expression, or what follows EXCLUDE */
+  expression?: string;
+}
+
+export interface DbIndex {
+  name: string;
+  unique: boolean;
+  /** CREATE INDEX statement as reported by the database */
+  definition: string;
+}
+
+/** One DDL step of a diff, in the order it runs */
+export type Operation =
+  | { op: 'drop_column'; table: string; column: string }
+  | { op: 'drop_table'; table: string }
+  | { op: 'create_table'; table: string; definition: object }
+  | { op: 'add_column'; table: string; column: DbColumn }
+  | {
+      op: 'alter_column';
+      table: string;
+      column: DbColumn;
+      from: DbColumn;
+      changes: ('type' | 'nullability' | 'default')[];
+    }
+  | { op: 'add_foreign_key' | 'drop_foreign_key'; table: string; foreign_key: DbForeignKey }
+  | { op: 'add_constraint' | 'drop_constraint'; table: string; constraint: DbConstraint }
+  | { op: 'create_index'; table: string; index: object }
+  | { op: 'drop_index'; table: string; index: DbIndex }
+  | { op: 'create_enum' | 'drop_enum'; name: string; values: string[] }
+  | { op: 'add_enum_value'; name: string; value: string; after?: string; before?: string }
+  | { op: 'create_role'; role: string }
+  | { op: 'grant' | 'revoke'; role: string; table: string; privileges: string[]; columns?: string[] };
+
+/** JSON returned by `diff_schemas` */
+export interface SchemaDiff {
+  operations: Operation[];
+  create_tables: string[];
+  alter_tables: string[];
+  drop_tables: string[];
+  create_columns: Record<string, DbColumn[]>;
+  alter_columns: Record<string, DbColumn[]>;
+  drop_columns: Record<string, string[]>;
+  add_foreign_keys: string[];
+  drop_foreign_keys: string[];
+  add_constraints: string[];
+  drop_constraints: string[];
+  create_indexes: string[];
+  drop_indexes: string[];
+  create_enums: string[];
+  add_enum_values: Record<string, string[]>;
+  drop_enums: string[];
+  create_roles: string[];
+  grants: string[];
+  revokes: string[];
+  data_loss_warning: string[];
+  /** Changes the SQL cannot make, such as removing an enum value */
+  warnings: string[];
+  sql: string;
+  dialect: string;
+}
+
+/** Parse TypeSQL; returns `ParsedQueryFile` JSON */
+export function parse_typesql(input: string): string;
+
+/** Check TypeSQL, against schema.json content when given; returns `Diagnostic[]` JSON */
+export function check_typesql(input: string, schema_json?: string | null): string;
+
+/** TypeScript client for a TypeSQL file */
+export function generate_ts(input: string, schema_json?: string | null, options_json?: string | null): string;
+
+/** Python client for a TypeSQL file */
+export function generate_py(input: string, schema_json?: string | null, options_json?: string | null): string;
+
+/** TypeScript types for every table of a schema */
+export function generate_ts_types_only(schema_json: string, options_json?: string | null): string;
+
+/** Validate schema.json content; returns `SchemaValidation` JSON */
+export function validate_schema(json: string): string;
+
+/** Diff two versions of schema.json; returns `SchemaDiff` JSON */
+export function diff_schemas(from_json: string, to_json: string): string;
+
+/** Table names referenced by an SQL statement; returns `string[]` JSON */
+export function extract_tables(sql: string): string;
+
+/** Columns of a SELECT statement; returns `SelectColumn[]` JSON */
+export function extract_columns(sql: string): string;
+
+export function validate_typesql(input: string): boolean;
+
+export function get_version(): string;
@@ -0,0 +1,6 @@
+export * from './stratus';
+
+/** Load and instantiate the module; call once before any other function */
+export default function init(
+  module_or_path?: RequestInfo | URL | Response | BufferSource | WebAssembly.Module,
+): Promise<unknown>;
@@ -0,0 +1,427 @@
+/**
+ * Stratus Index Advisor Module
+ *
+ * Reads the WHERE, JOIN ... ON and ORDER BY clauses of the TypeSQL queries
+ * and suggests the indexes schema.json lacks for them. A query wants one
+ * index per table it filters: the columns compared for equality, then one
+ * column compared by range or, failing that, the columns it sorts by. The
+ * want is met when an index, the primary key or a unique key starts with any
+ * column the query can seek on.
+ */
+use crate::ast::Query;
+use crate::dialect::{Dialect, Postgres};
+use crate::format::{tokenize, Token, TokenKind, KEYWORDS};
+use crate::infer::{column_table, table_refs};
+use crate::schema::{Schema, Table};
+use serde::Serialize;
+
+/// Longest identifier PostgreSQL keeps
+const MAX_NAME: usize = 63;
+
+/// A missing index and the queries it would serve
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexAdvice {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub name: String,
+    pub queries: Vec<String>,
+}
+
+impl IndexAdvice {
+    /// The CREATE INDEX statement, for a migration
+    pub fn sql(&self, dialect: &dyn Dialect) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| dialect.quote_ident(c))
+            .collect();
+        format!(
+            "CREATE INDEX {} ON {} ({});",
+            dialect.quote_ident(&self.name),
+            dialect.quote_ident(&self.table),
+            columns.join(", ")
+        )
+    }
+}
+
+/// The dialect suggestions are written in: schema.json's, else PostgreSQL
+pub fn dialect(schema: &Schema) -> &'static dyn Dialect {
+    schema
+        .dialect
+        .as_deref()
+        .and_then(crate::dialect::for_name)
+        .unwrap_or(&Postgres)
+}
+
+/// How a clause uses a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Use {
+    Equal,
+    Range,
+    Order,
+}
+
+/// The clause being read, per parenthesis level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Clause {
+    Other,
+    Where,
+    On,
+    OrderBy,
+    /// Arguments of a function call, which a plain index does not serve
+    Call,
+}
+
+/// Suggest the indexes the queries want and the schema lacks, by table in
+/// schema order; wants that are a prefix of another want are folded into it
+pub fn advise_indexes(queries: &[Query], schema: &Schema) -> Vec<IndexAdvice> {
+    let mut advice: Vec<IndexAdvice> = Vec::new();
+    for query in queries {
+        for (table, columns) in wanted(query, schema) {
+            match advice
+                .iter_mut()
+                .find(|a| a.table == table && a.columns == columns)
+            {
+                Some(a) if !a.queries.contains(&query.name) => a.queries.push(query.name.clone()),
+                Some(_) => {}
+                None => advice.push(IndexAdvice {
+                    name: index_name(&table, &columns),
+                    table,
+                    columns,
+                    queries: vec![query.name.clone()],
+                }),
+            }
+        }
+    }
+
+    // An index on (a, b) also serves what wants (a)
+    let mut i = 0;
+    while i < advice.len() {
+        let longer = advice.iter().position(|other| {
+            other.table == advice[i].table
+                && other.columns.len() > advice[i].columns.len()
+                && other.columns.starts_with(&advice[i].columns)
+        });
+        match longer {
+            Some(j) => {
+                let folded = advice.remove(i);
+                let j = if j > i { j - 1 } else { j };
+                for name in folded.queries {
+                    if !advice[j].queries.contains(&name) {
+                        advice[j].queries.push(name);
+                    }
+                }
+            }
+            None => i += 1,
+        }
+    }
+
+    let position = |table: &str| schema.tables.get_index_of(table).unwrap_or(usize::MAX);
+    advice.sort_by(|a, b| {
+        position(&a.table)
+            .cmp(&position(&b.table))
+            .then_with(|| a.columns.cmp(&b.columns))
+    });
+    advice
+}
+
+/// The index columns a query wants on each table it filters or sorts, where
+/// no existing index serves it
+fn wanted(query: &Query, schema: &Schema) -> Vec<(String, Vec<String>)> {
+    let sql = query.sql.as_str();
+    let tokens: Vec<Token<'_>> = tokenize(sql)
+        .into_iter()
+        .filter(|t| {
+            t.kind != TokenKind::LineComment
+                && !(t.kind == TokenKind::Quoted && t.text.starts_with("/*"))
+        })
+        .collect();
+    let refs = table_refs(sql);
+    let is = |i: usize, word: &str| {
+        tokens
+            .get(i)
+            .is_some_and(|t| t.text.eq_ignore_ascii_case(word))
+    };
+
+    // A WHERE with OR in it filters on no single column set
+    let mut disjunctive = false;
+    // Each use with the subquery it is in, as the tables of different
+    // subqueries want separate indexes even when they are the same table
+    let mut uses: Vec<(usize, String, String, Use, Clause)> = Vec::new();
+    let mut clauses = vec![(0, Clause::Other)];
+    let mut subqueries = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let (scope, clause) = *clauses.last().unwrap_or(&(0, Clause::Other));
+        if token.text == "(" {
+            let call = i > 0
+                && tokens[i - 1].kind == TokenKind::Word
+                && !KEYWORDS.contains(&tokens[i - 1].text.to_ascii_lowercase().as_str());
+            if is(i + 1, "select") {
+                subqueries += 1;
+                clauses.push((subqueries, Clause::Other));
+            } else {
+                clauses.push((scope, if call { Clause::Call } else { clause }));
+            }
+            continue;
+        }
+        if token.text == ")" {
+            if clauses.len() > 1 {
+                clauses.pop();
+            }
+            continue;
+        }
+        if token.kind != TokenKind::Word {
+            continue;
+        }
+        let lower = token.text.to_ascii_lowercase();
+        let next = match lower.as_str() {
+            "where" => Some(Clause::Where),
+            "on" if !is(i + 1, "conflict") => Some(Clause::On),
+            "order" if is(i + 1, "by") => Some(Clause::OrderBy),
+            "select" | "from" | "join" | "group" | "having" | "limit" | "offset" | "returning"
+            | "union" | "intersect" | "except" | "window" | "set" | "values" | "for" => {
+                Some(Clause::Other)
+            }
+            _ => None,
+        };
+        if let Some(next) = next {
+            if let Some((_, last)) = clauses.last_mut() {
+                if *last != Clause::Call {
+                    *last = next;
+                }
+            }
+            continue;
+        }
+        if lower == "or" && clause == Clause::Where {
+            disjunctive = true;
+        }
+        if !matches!(clause, Clause::Where | Clause::On | Clause::OrderBy)
+            || tokens
+                .get(i + 1)
+                .is_some_and(|t| t.text == "." || t.text == "(")
+        {
+            continue;
+        }
+        let qualifier =
+            (i >= 2 && tokens[i - 1].text == ".").then(|| tokens[i - 2].text.to_ascii_lowercase());
+        let Some(table) = column_table(qualifier.as_deref(), &lower, &refs, Some(schema)) else {
+            continue;
+        };
+        let kind = if clause == Clause::OrderBy {
+            Some(Use::Order)
+        } else {
+            comparison(&tokens, i)
+        };
+        if let Some(kind) = kind {
+            uses.push((scope, table, lower, kind, clause));
+        }
+    }
+    if disjunctive {
+        uses.retain(|(_, _, _, _, clause)| *clause != Clause::Where);
+    }
+
+    let mut wants = Vec::new();
+    let mut tables: Vec<(usize, &String)> = Vec::new();
+    for (scope, table, _, _, _) in &uses {
+        if !tables.contains(&(*scope, table)) {
+            tables.push((*scope, table));
+        }
+    }
+    for (scope, table) in tables {
+        let columns = |kind: Use| {
+            let mut columns: Vec<String> = Vec::new();
+            for (s, t, column, k, _) in &uses {
+                if *s == scope && t == table && *k == kind && !columns.contains(column) {
+                    columns.push(column.clone());
+                }
+            }
+            columns
+        };
+        let equal = columns(Use::Equal);
+        let range = columns(Use::Range).into_iter().find(|c| !equal.contains(c));
+        let mut key = equal.clone();
+        let seekable: Vec<String> = match range {
+            Some(range) => {
+                key.push(range);
+                key.clone()
+            }
+            None => {
+                let order: Vec<String> = columns(Use::Order)
+                    .into_iter()
+                    .filter(|c| !equal.contains(c))
+                    .collect();
+                key.extend(order);
+                if equal.is_empty() {
+                    key.iter().take(1).cloned().collect()
+                } else {
+                    equal
+                }
+            }
+        };
+        if key.is_empty() || served(&schema.tables[table.as_str()], &seekable) {
+            continue;
+        }
+        if !wants.contains(&(table.clone(), key.clone())) {
+            wants.push((table.clone(), key));
+        }
+    }
+    wants
+}
+
+/// How the column at `tokens[i]` is compared, from the operator on either side
+fn comparison(tokens: &[Token<'_>], i: usize) -> Option<Use> {
+    let text = |i: usize| tokens.get(i).map(|t| t.text.to_ascii_lowercase());
+    // Operators come a character per token, so `<>` is `<` then `>`
+    match text(i + 1).as_deref() {
+        Some("=" | "in" | "is") => return Some(Use::Equal),
+        Some("<") if text(i + 2).as_deref() == Some(">") => return None,
+        Some("<" | ">" | "between") => return Some(Use::Range),
+        Some(_) if i == 0 => return None,
+        _ => {}
+    }
+    let operator = i.checked_sub(2).and_then(text);
+    match i.checked_sub(1).and_then(text).as_deref() {
+        Some("=") => match operator.as_deref() {
+            Some("<" | ">") => Some(Use::Range),
+            Some("!") => None,
+            _ => Some(Use::Equal),
+        },
+        Some(">") if operator.as_deref() == Some("<") => None,
+        Some("<" | ">") => Some(Use::Range),
+        _ => None,
+    }
+}
+
+/// Whether an index, the primary key or a unique key of the table starts
+/// with one of the columns
+fn served(table: &Table, seekable: &[String]) -> bool {
+    // The primary key in declaration order; unique_keys() sorts it
+    let primary = table
+        .columns
+        .iter()
+        .find(|(_, c)| c.is_primary_key())
+        .map(|(name, _)| name.clone());
+    let mut leading: Vec<String> = primary.into_iter().collect();
+    leading.extend(
+        table
+            .unique_keys()
+            .into_iter()
+            .filter_map(|key| key.into_iter().next()),
+    );
+    leading.extend(
+        table
+            .indexes
+            .iter()
+            .flatten()
+            .filter(|index| index.where_clause.is_none())
+            .filter_map(|index| index.columns.first().cloned()),
+    );
+    leading.iter().any(|c| seekable.contains(c))
+}
+
+/// `idx_{table}_{columns}`, as the schema examples name indexes
+fn index_name(table: &str, columns: &[String]) -> String {
+    let mut name = format!("idx_{}_{}", table, columns.join("_"));
+    if name.len() > MAX_NAME {
+        let mut end = MAX_NAME;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "email": {"name": "email", "type": "text", "isUnique": true},
+                    "status": {"name": "status", "type": "text"}
+                }},
+                "posts": {
+                    "columns": {
+                        "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                        "user_id": {"name": "user_id", "type": "bigint"},
+                        "published": {"name": "published", "type": "boolean"},
+                        "created_at": {"name": "created_at", "type": "timestamp"},
+                        "title": {"name": "title", "type": "text"}
+                    },
+                    "indexes": [{"name": "idx_posts_title", "columns": ["title"]}]
+                }
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    fn advise(sql: &[&str]) -> Vec<(String, Vec<String>, Vec<String>)> {
+        let source: String = sql
+            .iter()
+            .enumerate()
+            .map(|(i, sql)| format!("# name: Q{} :many\n{}\n\n", i + 1, sql))
+            .collect();
+        let queries = crate::parser::parse(&source).unwrap().queries;
+        advise_indexes(&queries, &schema())
+            .into_iter()
+            .map(|a| (a.table, a.columns, a.queries))
+            .collect()
+    }
+
+    fn want(table: &str, columns: &[&str], queries: &[&str]) -> (String, Vec<String>, Vec<String>) {
+        (
+            table.to_string(),
+            columns.iter().map(|c| c.to_string()).collect(),
+            queries.iter().map(|q| q.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_indexed_queries_want_nothing() {
+        assert!(advise(&[
+            "SELECT * FROM users WHERE id = $1;",
+            "SELECT * FROM users WHERE email = $1 AND status = 'active';",
+            "SELECT * FROM posts WHERE title = $1 ORDER BY created_at;",
+            "SELECT * FROM users WHERE lower(status) = $1;",
+            "SELECT * FROM users WHERE status = $1 OR email = $2;",
+            "SELECT * FROM users;",
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn test_missing_indexes_are_suggested() {
+        assert_eq!(
+            advise(&[
+                "SELECT * FROM posts WHERE user_id = $1;",
+                "SELECT p.* FROM posts p WHERE p.user_id = $1 AND p.created_at > $2 ORDER BY p.created_at;",
+                "SELECT u.email, p.title FROM users u JOIN posts p ON p.user_id = u.id WHERE u.status = $1;",
+                "SELECT * FROM posts WHERE id IN (SELECT id FROM posts WHERE published = true) ORDER BY created_at;",
+                "SELECT * FROM posts ORDER BY created_at DESC LIMIT 10;",
+            ]),
+            vec![
+                want("users", &["status"], &["Q3"]),
+                want("posts", &["created_at"], &["Q5"]),
+                want("posts", &["published"], &["Q4"]),
+                want("posts", &["user_id", "created_at"], &["Q2", "Q1", "Q3"]),
+            ]
+        );
+
+        let advice = advise_indexes(
+            &crate::parser::parse(
+                "# name: ByUser :many\nSELECT * FROM posts WHERE user_id = $1;\n",
+            )
+            .unwrap()
+            .queries,
+            &schema(),
+        );
+        assert_eq!(
+            advice[0].sql(dialect(&schema())),
+            "CREATE INDEX \"idx_posts_user_id\" ON \"posts\" (\"user_id\");"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+/**
+ * Stratus Query Analysis Module
+ *
+ * Checks TypeSQL queries against schema.json: referenced tables must exist,
+ * and plainly named columns must belong to the table they are read from.
+ */
+use crate::ast::Query;
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::format::{tokenize, Token, TokenKind, KEYWORDS};
+use crate::parser::extract_select_columns;
+use crate::schema::Schema;
+use std::ops::Range;
+
+/// Keywords followed by a table name
+pub(crate) const TABLE_KEYWORDS: &[&str] = &["from", "join", "into", "update"];
+
+/// A problem in a query: diagnostic code, message and byte range in the SQL
+struct Problem {
+    code: &'static str,
+    message: String,
+    range: Range<usize>,
+}
+
+/// Problems found in a query, one message each; empty when the query checks out
+pub fn check_query(query: &Query, schema: &Schema) -> Vec<String> {
+    problems(query, schema)
+        .into_iter()
+        .map(|p| p.message)
+        .collect()
+}
+
+/// Parse a TypeSQL file and check its queries against the schema.
+///
+/// Combines the parser's diagnostics with unknown tables and columns, each
+/// pointing at the offending name, and warnings where a query's `:one` or
+/// `:many` does not match what its SQL returns.
+pub fn check_source(input: &str, schema: &Schema) -> Diagnostics {
+    let (_, mut diagnostics) = crate::parser::parse_with_diagnostics(input);
+    let mut ignored = Diagnostics::new();
+    for block in crate::parser::query_blocks(input, &mut ignored) {
+        for problem in problems(&block.query, schema) {
+            diagnostics.push(Diagnostic::error(
+                problem.code,
+                block.span(problem.range),
+                problem.message,
+            ));
+        }
+        for warning in crate::infer::check_cardinality(&block.query, schema) {
+            diagnostics.push(Diagnostic::warning(
+                warning.code,
+                block.span(warning.range),
+                warning.message,
+            ));
+        }
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Byte range of a token within the SQL it was cut from
+pub(crate) fn range(sql: &str, token: &Token<'_>) -> Range<usize> {
+    let start = token.text.as_ptr() as usize - sql.as_ptr() as usize;
+    start..start + token.text.len()
+}
+
+fn problems(query: &Query, schema: &Schema) -> Vec<Problem> {
+    let tokens = tokenize(&query.sql);
+    let ctes = cte_names(&tokens);
+    let mut problems = Vec::new();
+    let mut tables: Vec<(String, Range<usize>)> = Vec::new();
+    let mut insert_columns = Vec::new();
+
+    // Whether each open parenthesis holds a subquery (as opposed to e.g. `extract(x FROM y)`)
+    let mut scopes: Vec<bool> = Vec::new();
+    let mut prev_word = String::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Other if token.text == "(" => {
+                let next = tokens.get(i + 1).map(|t| t.text.to_ascii_lowercase());
+                scopes.push(matches!(next.as_deref(), Some("select" | "with")));
+            }
+            TokenKind::Other if token.text == ")" => {
+                scopes.pop();
+            }
+            TokenKind::Word => {
+                let lower = token.text.to_ascii_lowercase();
+                let in_query = scopes.last().copied().unwrap_or(true);
+                if TABLE_KEYWORDS.contains(&lower.as_str()) && in_query && prev_word != "distinct" {
+                    if let Some(table) = table_ref(&tokens, i + 1, lower == "into") {
+                        if lower == "into" {
+                            insert_columns = column_list(&tokens, i + 2)
+                                .into_iter()
+                                .map(|t| (t.text.to_ascii_lowercase(), range(&query.sql, t)))
+                                .collect();
+                        }
+                        if !tables.iter().any(|(t, _)| *t == table) {
+                            tables.push((table, range(&query.sql, &tokens[i + 1])));
+                        }
+                    }
+                }
+                prev_word = lower;
+            }
+            _ => {}
+        }
+    }
+
+    for (table, range) in &tables {
+        if !schema.tables.contains_key(table) && !ctes.contains(table) {
+            let mut message = format!("table '{}' is not in the schema", table);
+            if let Some(similar) =
+                crate::config::suggest(table, schema.tables.keys().map(String::as_str))
+            {
+                message.push_str(&format!(" (did you mean '{}'?)", similar));
+            }
+            problems.push(Problem {
+                code: "unknown-table",
+                message,
+                range: range.clone(),
+            });
+        }
+    }
+
+    // Columns are only checked where they can't belong to another table or alias
+    let [(table, _)] = tables.as_slice() else {
+        return problems;
+    };
+    let Some(columns) = schema.tables.get(table).map(|t| &t.columns) else {
+        return problems;
+    };
+
+    let selected: Vec<(String, Range<usize>)> = if query
+        .sql
+        .trim_start()
+        .to_ascii_lowercase()
+        .starts_with("select")
+    {
+        extract_select_columns(&query.sql)
+            .into_iter()
+            .filter(|c| !c.is_wildcard && c.table_name.as_ref().is_none_or(|t| t == table))
+            .map(|c| {
+                // The first mention of the name, which is in the select list
+                let range = tokens
+                    .iter()
+                    .find(|t| t.text == c.column_name.as_str())
+                    .map_or(0..0, |t| range(&query.sql, t));
+                (c.column_name, range)
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for (column, range) in insert_columns.iter().chain(&selected) {
+        let plain = column
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && column
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !KEYWORDS.contains(&column.to_ascii_lowercase().as_str());
+        if plain && !columns.contains_key(column.as_str()) {
+            let mut message = format!("column '{}' is not in table '{}'", column, table);
+            if let Some(similar) =
+                crate::config::suggest(column, columns.keys().map(String::as_str))
+            {
+                message.push_str(&format!(" (did you mean '{}'?)", similar));
+            }
+            problems.push(Problem {
+                code: "unknown-column",
+                message,
+                range: range.clone(),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Names defined by `WITH name AS (...)`
+fn cte_names(tokens: &[Token<'_>]) -> Vec<String> {
+    tokens
+        .windows(3)
+        .filter(|w| {
+            w[0].kind == TokenKind::Word && w[1].text.eq_ignore_ascii_case("as") && w[2].text == "("
+        })
+        .map(|w| w[0].text.to_ascii_lowercase())
+        .collect()
+}
+
+/// Table named at `tokens[i]`; None for subqueries, functions and schema-qualified names.
+///
+/// `column_list` allows a parenthesis after the name, as in `INSERT INTO t (a, b)`.
+pub(crate) fn table_ref(tokens: &[Token<'_>], i: usize, column_list: bool) -> Option<String> {
+    let token = tokens.get(i)?;
+    if matches!(tokens.get(i + 1), Some(next) if next.text == "." || (next.text == "(" && !column_list))
+    {
+        return None;
+    }
+    match token.kind {
+        TokenKind::Word => {
+            let lower = token.text.to_ascii_lowercase();
+            (!KEYWORDS.contains(&lower.as_str()) && lower != "lateral" && lower != "only")
+                .then_some(lower)
+        }
+        TokenKind::Quoted if token.text.starts_with('"') => {
+            Some(token.text.trim_matches('"').replace("\"\"", "\""))
+        }
+        _ => None,
+    }
+}
+
+/// Identifiers of a parenthesized column list at `tokens[i]`, as in `INSERT INTO t (a, b)`
+fn column_list<'a, 't>(tokens: &'t [Token<'a>], i: usize) -> Vec<&'t Token<'a>> {
+    if tokens.get(i).is_none_or(|t| t.text != "(") {
+        return Vec::new();
+    }
+    tokens[i + 1..]
+        .iter()
+        .take_while(|t| t.text != ")")
+        .filter(|t| t.kind == TokenKind::Word)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint", "isPrimaryKey": true}, "email": {"name": "email", "type": "text"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}, "user_id": {"name": "user_id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    fn problems(sql: &str) -> Vec<String> {
+        let query = crate::parser::parse(&format!("# name: Q :many\n{}\n", sql))
+            .unwrap()
+            .queries
+            .remove(0);
+        check_query(&query, &schema())
+    }
+
+    #[test]
+    fn test_known_tables_and_columns_pass() {
+        assert!(problems("SELECT id, email FROM users WHERE id = $1;").is_empty());
+        assert!(
+            problems("SELECT u.id, p.id FROM users u JOIN posts p ON p.user_id = u.id;").is_empty()
+        );
+        assert!(problems("INSERT INTO users (id, email) VALUES ($1, $2) RETURNING id;").is_empty());
+        assert!(problems(
+            "WITH recent AS (SELECT * FROM posts) SELECT count(*) FROM recent \
+             WHERE extract(year FROM now()) > 2000;"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_unknown_tables_and_columns_fail() {
+        assert_eq!(
+            problems("SELECT * FROM user WHERE id = $1;"),
+            vec!["table 'user' is not in the schema (did you mean 'users'?)"]
+        );
+        assert_eq!(
+            problems("SELECT id, emial FROM users;"),
+            vec!["column 'emial' is not in table 'users' (did you mean 'email'?)"]
+        );
+        assert_eq!(
+            problems("INSERT INTO posts (id, author_id) VALUES ($1, $2);"),
+            vec!["column 'author_id' is not in table 'posts'"]
+        );
+        assert_eq!(
+            problems("DELETE FROM comments WHERE id IN (SELECT id FROM posts);"),
+            vec!["table 'comments' is not in the schema"]
+        );
+    }
+
+    #[test]
+    fn test_check_source_points_at_names() {
+        let input = "# name: GetUser :one id: number\nSELECT id, emial\nFROM user WHERE id = $1;\n";
+        let found: Vec<String> = check_source(input, &schema())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec!["3:6: error[unknown-table]: table 'user' is not in the schema (did you mean 'users'?)"]
+        );
+
+        let input =
+            "# name: GetUser :one id: number\nSELECT id, emial\nFROM users WHERE id = $1;\n";
+        let found: Vec<String> = check_source(input, &schema())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec!["2:12: error[unknown-column]: column 'emial' is not in table 'users' (did you mean 'email'?)"]
+        );
+        let input = "# name: Posts :one id: number\nSELECT id FROM posts WHERE user_id = $1;\n";
+        let found: Vec<String> = check_source(input, &schema())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec!["2:16: warning[one-not-unique]: `:one` query does not filter on a unique key of 'posts'; add LIMIT 1 or use :many"]
+        );
+    }
+}
@@ -13,6 +13,17 @@ pub struct Query {
     pub return_type: String,
     pub sql: String,
     pub params: Vec<Param>,
+    /// `@cache(...)` after the header's params
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheDirective>,
+}
+
+/// `@cache(ttl=60s)`: generated clients may serve the query's result from a
+/// cache for up to `ttl_seconds`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheDirective {
+    #[serde(rename = "ttlSeconds")]
+    pub ttl_seconds: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -23,6 +34,13 @@ pub struct Param {
     pub ordinal: usize,
 }
 
+impl Query {
+    /// The query's SQL parsed into a statement AST
+    pub fn statement(&self) -> Result<crate::sql::ast::Statement, crate::sql::SqlError> {
+        crate::sql::parse_statement(&self.sql)
+    }
+}
+
 impl fmt::Display for QueryFile {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "QueryFile {{")?;
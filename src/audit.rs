@@ -0,0 +1,354 @@
+/**
+ * Stratus SQL Escaping Audit Module
+ *
+ * Opt-in checks, run by `stratus check --audit`, for SQL assembled from
+ * text rather than passed as parameters: template placeholders left in
+ * TypeSQL queries, parameters concatenated or formatted into SQL text that
+ * is then run, and schema.json names that the DDL writers emit unquoted.
+ * Findings are warnings in the usual diagnostics form.
+ */
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::format::{tokenize, Token, TokenKind, KEYWORDS};
+use crate::schema::Schema;
+use std::ops::Range;
+
+/// Reserved words that break DDL as unquoted names, besides the query
+/// keywords of [`KEYWORDS`]
+const DDL_KEYWORDS: &[&str] = &[
+    "alter",
+    "any",
+    "array",
+    "check",
+    "column",
+    "constraint",
+    "create",
+    "current_user",
+    "default",
+    "drop",
+    "foreign",
+    "grant",
+    "primary",
+    "references",
+    "table",
+    "to",
+    "unique",
+    "user",
+    "window",
+];
+
+/// Words that make a string literal read as SQL text rather than a value
+const SQL_TEXT_WORDS: &[&str] = &[
+    "alter", "create", "delete", "drop", "from", "group", "having", "insert", "into", "join",
+    "limit", "order", "select", "union", "update", "values", "where",
+];
+
+/// A finding in a query: diagnostic code, message and byte range in the SQL
+struct Finding {
+    code: &'static str,
+    message: String,
+    range: Range<usize>,
+}
+
+/// Audit the queries of a TypeSQL file
+pub fn audit_source(input: &str) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+    let mut ignored = Diagnostics::new();
+    for block in crate::parser::query_blocks(input, &mut ignored) {
+        for finding in findings(&block.query.sql) {
+            diagnostics.push(Diagnostic::warning(
+                finding.code,
+                block.span(finding.range),
+                finding.message,
+            ));
+        }
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Audit the names schema.json gives tables, columns, enum types and roles,
+/// which CREATE TABLE, ALTER TABLE and GRANT write as they are
+pub fn audit_schema(json: &str, schema: &Schema) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+    let mut check = |what: &str, name: &str, keys: &[&str]| {
+        if let Some(problem) = identifier_problem(name) {
+            diagnostics.push(Diagnostic::warning(
+                "unquoted-identifier",
+                crate::schema::locate(json, keys),
+                format!(
+                    "{} '{}' is written into DDL unquoted, {}",
+                    what, name, problem
+                ),
+            ));
+        }
+    };
+    for (table_name, table) in &schema.tables {
+        check("table name", table_name, &["tables", table_name]);
+        for column_name in table.columns.keys() {
+            check(
+                "column name",
+                column_name,
+                &["tables", table_name, "columns", column_name],
+            );
+        }
+    }
+    for name in schema.enums.iter().flat_map(|enums| enums.keys()) {
+        check("enum name", name, &["enums", name]);
+    }
+    for role in &schema.roles {
+        check("role name", role, &["roles"]);
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Why `name` is unsafe unquoted, if it is
+fn identifier_problem(name: &str) -> Option<&'static str> {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let lower = name.to_ascii_lowercase();
+    if !plain {
+        Some("so it must be letters, digits and underscores")
+    } else if KEYWORDS.contains(&lower.as_str()) || DDL_KEYWORDS.contains(&lower.as_str()) {
+        Some("where it is read as a keyword; rename it")
+    } else if lower != name {
+        Some("so the database folds it to lowercase")
+    } else {
+        None
+    }
+}
+
+fn findings(sql: &str) -> Vec<Finding> {
+    let tokens: Vec<Token<'_>> = tokenize(sql)
+        .into_iter()
+        .filter(|t| t.kind != TokenKind::LineComment && !t.text.starts_with("/*"))
+        .collect();
+    let mut findings = placeholders(sql, &tokens);
+
+    for (i, token) in tokens.iter().enumerate() {
+        // A parameter in a `||` chain with SQL text builds a statement
+        if is_parameter(token)
+            && (is_concat(&tokens, i + 1) || (i >= 2 && is_concat(&tokens, i - 2)))
+        {
+            let sql_text = concat_chain(&tokens, i)
+                .into_iter()
+                .find(|operand| is_sql_text(operand));
+            if let Some(text) = sql_text {
+                findings.push(Finding {
+                    code: "sql-concatenation",
+                    message: format!(
+                        "{} is concatenated into SQL text ({}); pass it as a parameter, \
+                         or quote it with quote_ident() or quote_literal()",
+                        token.text, text.text
+                    ),
+                    range: crate::analyze::range(sql, token),
+                });
+            }
+        }
+
+        // format('... %s ...', $1) splices the value in as it is
+        if token.kind == TokenKind::Word
+            && token.text.eq_ignore_ascii_case("format")
+            && tokens.get(i + 1).is_some_and(|t| t.text == "(")
+        {
+            let Some(template) = tokens.get(i + 2).filter(|t| t.text.starts_with('\'')) else {
+                continue;
+            };
+            let mut depth = 0;
+            let mut arguments = tokens[i + 1..].iter().take_while(|t| {
+                match t.text {
+                    "(" => depth += 1,
+                    ")" => depth -= 1,
+                    _ => {}
+                }
+                depth > 0
+            });
+            if template.text.contains("%s") && arguments.any(is_parameter) {
+                findings.push(Finding {
+                    code: "sql-concatenation",
+                    message: "format() splices a parameter in with %s; use %L for values \
+                              and %I for identifiers"
+                        .to_string(),
+                    range: crate::analyze::range(sql, template),
+                });
+            }
+        }
+    }
+
+    findings.sort_by_key(|f| f.range.start);
+    findings
+}
+
+/// `$1`, or `?` where parameters are positional
+fn is_parameter(token: &Token<'_>) -> bool {
+    token.kind == TokenKind::Other
+        && (token.text == "?" || (token.text.starts_with('$') && token.text.len() > 1))
+}
+
+/// Whether `tokens[i..i + 2]` is the `||` operator
+fn is_concat(tokens: &[Token<'_>], i: usize) -> bool {
+    matches!(tokens.get(i..i + 2), Some([a, b]) if a.text == "|" && b.text == "|")
+}
+
+/// The operands of the `||` chain through `tokens[i]`, each a single token
+fn concat_chain<'t, 'a>(tokens: &'t [Token<'a>], i: usize) -> Vec<&'t Token<'a>> {
+    let mut operands = vec![&tokens[i]];
+    let mut left = i;
+    while left >= 3 && is_concat(tokens, left - 2) {
+        left -= 3;
+        operands.push(&tokens[left]);
+    }
+    let mut right = i;
+    while is_concat(tokens, right + 1) && right + 3 < tokens.len() {
+        right += 3;
+        operands.push(&tokens[right]);
+    }
+    operands
+}
+
+/// A string literal with SQL words in it, such as `'SELECT * FROM '`
+fn is_sql_text(token: &Token<'_>) -> bool {
+    token.kind == TokenKind::Quoted
+        && token.text.starts_with('\'')
+        && token
+            .text
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .any(|word| SQL_TEXT_WORDS.contains(&word.to_ascii_lowercase().as_str()))
+}
+
+/// Template placeholders of the host language: `${x}`, `#{x}` and `{{ x }}`
+/// anywhere, and `{x}`, `%s` and `%(x)s` outside string literals
+fn placeholders(sql: &str, tokens: &[Token<'_>]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut report = |start: usize, end: usize| {
+        let text = &sql[start..end];
+        findings.push(Finding {
+            code: "sql-interpolation",
+            message: format!(
+                "{} splices text into the SQL; use a parameter such as $1 instead",
+                text
+            ),
+            range: start..end,
+        });
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        let start = crate::analyze::range(sql, token).start;
+        if token.kind == TokenKind::Quoted {
+            for (offset, _) in token.text.match_indices(['$', '#', '{']) {
+                if let Some(len) = placeholder_len(&token.text[offset..], true) {
+                    report(start + offset, start + offset + len);
+                }
+            }
+            i += 1;
+            continue;
+        }
+        // Outside literals a placeholder spans several tokens; match on the text
+        let rest = &sql[start..];
+        match token.text {
+            "$" | "#" | "{" | "%" => {
+                if let Some(len) = placeholder_len(rest, false) {
+                    report(start, start + len);
+                    let end = start + len;
+                    while i < tokens.len() && crate::analyze::range(sql, &tokens[i]).start < end {
+                        i += 1;
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    findings
+}
+
+/// Length of the placeholder `text` starts with, if it starts with one
+fn placeholder_len(text: &str, quoted: bool) -> Option<usize> {
+    let name_len = |s: &str| {
+        let len = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(s.len());
+        (len > 0 && !s.starts_with(|c: char| c.is_ascii_digit())).then_some(len)
+    };
+    let wrapped = |open: &str, close: &str| {
+        let inner = text.strip_prefix(open)?;
+        let padding = inner.len() - inner.trim_start().len();
+        let len = name_len(&inner[padding..])?;
+        let after = &inner[padding + len..];
+        let closing = after.len() - after.trim_start().len();
+        after
+            .trim_start()
+            .starts_with(close)
+            .then_some(open.len() + padding + len + closing + close.len())
+    };
+    wrapped("${", "}")
+        .or_else(|| wrapped("#{", "}"))
+        .or_else(|| wrapped("{{", "}}"))
+        .or_else(|| {
+            if quoted {
+                return None;
+            }
+            wrapped("{", "}")
+                .or_else(|| wrapped("%(", ")s"))
+                .or_else(|| {
+                    ["%s", "%d"]
+                        .iter()
+                        .find(|p| text.starts_with(**p))
+                        .map(|p| p.len())
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_flags_spliced_sql_and_unquoted_names() {
+        let source = "# name: Search :many term: string\n\
+             SELECT * FROM users WHERE name LIKE '%' || $1 || '%' AND id > 0;\n\n\
+             # name: ByTable :many table: string\n\
+             SELECT * FROM ${table} WHERE note = '#{note}' AND age = %s;\n\n\
+             # name: Dynamic :many col: string\n\
+             SELECT run_sql('SELECT * FROM users ORDER BY ' || $1), format('%s = %L', $1, 'x');\n";
+        let diagnostics = audit_source(source);
+        let found: Vec<(&str, usize)> = diagnostics.iter().map(|d| (d.code, d.span.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("sql-interpolation", 5),
+                ("sql-interpolation", 5),
+                ("sql-interpolation", 5),
+                ("sql-concatenation", 8),
+                ("sql-concatenation", 8),
+            ]
+        );
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert!(messages[0].starts_with("${table} splices"));
+        assert!(messages[3].starts_with("$1 is concatenated into SQL text ('SELECT"));
+
+        let json = r#"{"version": "1", "roles": ["app"], "tables": {
+            "user": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+            "orders": {"columns": {
+                "Total": {"name": "Total", "type": "integer"},
+                "ship to": {"name": "ship to", "type": "text"}
+            }}
+        }}"#;
+        let schema: Schema = serde_json::from_str(json).unwrap();
+        let messages: Vec<String> = audit_schema(json, &schema)
+            .iter()
+            .map(|d| d.message.clone())
+            .collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("table name 'user'"));
+        assert!(messages[0].ends_with("read as a keyword; rename it"));
+        assert!(messages[1].contains("'Total'") && messages[1].ends_with("lowercase"));
+        assert!(messages[2].contains("'ship to'"));
+    }
+}
@@ -0,0 +1,133 @@
+/**
+ * Generator Fixtures
+ *
+ * A fixture is a directory holding `queries.sql` and, optionally,
+ * `schema.json`: the inputs of one code generation run. The snapshot suite in
+ * tests/snapshots.rs renders every fixture under tests/fixtures with every
+ * provider; authors of their own generators can load the same layout and
+ * snapshot their output with the tool of their choice.
+ *
+ * ```no_run
+ * use stratus::codegen::fixture::Fixture;
+ *
+ * for fixture in Fixture::all("tests/fixtures")? {
+ *     let output = fixture.render("ts", &Default::default())?;
+ *     println!("{}", fixture.snapshot_name("ts"));
+ * }
+ * # Ok::<(), String>(())
+ * ```
+ */
+use super::GeneratorOptions;
+use crate::ast::QueryFile;
+use crate::schema::Schema;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Queries file of a fixture
+pub const QUERIES_FILE: &str = "queries.sql";
+/// Optional schema file of a fixture
+pub const SCHEMA_FILE: &str = "schema.json";
+
+/// The parsed inputs of one fixture directory
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// Directory name
+    pub name: String,
+    pub dir: PathBuf,
+    pub queries: QueryFile,
+    pub schema: Option<Schema>,
+}
+
+impl Fixture {
+    /// Load the fixture in `dir`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        let read = |file: &str| {
+            let path = dir.join(file);
+            fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+        };
+
+        let queries = crate::parser::parse(&read(QUERIES_FILE)?)
+            .map_err(|e| format!("{}: {}", dir.join(QUERIES_FILE).display(), e))?;
+        let schema = if dir.join(SCHEMA_FILE).exists() {
+            Some(
+                serde_json::from_str(&read(SCHEMA_FILE)?)
+                    .map_err(|e| format!("{}: {}", dir.join(SCHEMA_FILE).display(), e))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name: dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            dir: dir.to_path_buf(),
+            queries,
+            schema,
+        })
+    }
+
+    /// Every fixture directory directly under `root`, by name
+    pub fn all(root: impl AsRef<Path>) -> Result<Vec<Self>, String> {
+        let root = root.as_ref();
+        let mut dirs: Vec<PathBuf> = fs::read_dir(root)
+            .map_err(|e| format!("Cannot read {}: {}", root.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.join(QUERIES_FILE).is_file())
+            .collect();
+        dirs.sort();
+        dirs.iter().map(Self::load).collect()
+    }
+
+    /// Run a built-in generator on the fixture
+    pub fn render(&self, provider: &str, options: &GeneratorOptions) -> Result<String, String> {
+        super::generate(provider, Some(&self.queries), self.schema.as_ref(), options)
+    }
+
+    /// A snapshot name unique to this fixture and generator, e.g. `shop__ts`
+    pub fn snapshot_name(&self, generator: &str) -> String {
+        format!("{}__{}", self.name, generator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixtures_load_by_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for (name, schema) in [("b_plain", false), ("a_schema", true)] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            fs::write(
+                root.join(name).join(QUERIES_FILE),
+                "# name: ListUsers :many\nSELECT id FROM users;\n",
+            )
+            .unwrap();
+            if schema {
+                fs::write(
+                    root.join(name).join(SCHEMA_FILE),
+                    r#"{"tables": {"users": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#,
+                )
+                .unwrap();
+            }
+        }
+        fs::create_dir_all(root.join("not_a_fixture")).unwrap();
+
+        let fixtures = Fixture::all(root).unwrap();
+        let names: Vec<&str> = fixtures.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a_schema", "b_plain"]);
+        assert!(fixtures[0].schema.is_some() && fixtures[1].schema.is_none());
+        assert_eq!(fixtures[1].snapshot_name("ts"), "b_plain__ts");
+        assert!(fixtures[1]
+            .render("sql", &GeneratorOptions::default())
+            .unwrap()
+            .contains("-- name: ListUsers"));
+        assert!(fixtures[1]
+            .render("ts-types", &GeneratorOptions::default())
+            .is_err());
+    }
+}
@@ -1,7 +1,267 @@
+pub mod fixture;
+pub mod paginate;
 pub mod py;
+pub mod runtime;
 pub mod sql;
 pub mod ts;
 
-pub use py::{generate_py, generate_py_types_only};
+pub use py::{
+    generate_py, generate_py_types_only, generate_py_types_only_with_options,
+    generate_py_with_options,
+};
 pub use sql::generate_sql;
-pub use ts::{generate_ts, generate_ts_types_only};
+pub use ts::{
+    generate_ts, generate_ts_types_only, generate_ts_types_only_with_options,
+    generate_ts_with_options,
+};
+
+use crate::ast::{CacheDirective, Query, QueryFile};
+use crate::infer::Cardinality;
+use crate::schema::Schema;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Naming strategy for generated query functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NamingStrategy {
+    #[serde(rename = "camelCase")]
+    CamelCase,
+    #[serde(rename = "snake_case")]
+    SnakeCase,
+    #[serde(rename = "PascalCase")]
+    PascalCase,
+}
+
+impl NamingStrategy {
+    /// Convert a query name (e.g. `GetUserById`) to this naming strategy
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            Self::CamelCase => {
+                let mut chars = name.chars();
+                match chars.next() {
+                    Some(c) => c.to_lowercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+            Self::SnakeCase => {
+                let mut result = String::new();
+                for (i, c) in name.chars().enumerate() {
+                    if c.is_uppercase() {
+                        if i > 0 {
+                            result.push('_');
+                        }
+                        result.extend(c.to_lowercase());
+                    } else {
+                        result.push(c);
+                    }
+                }
+                result
+            }
+            Self::PascalCase => {
+                let mut chars = name.chars();
+                match chars.next() {
+                    Some(c) => c.to_uppercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+        }
+    }
+}
+
+/// Module system the generated TypeScript is compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleFormat {
+    /// Node ES modules, whose relative imports need the `.js` extension
+    Esm,
+    /// CommonJS, or a bundler resolving extensionless imports
+    CommonJs,
+}
+
+/// Options passed from stratus.json `generators[].options` to the backends
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorOptions {
+    /// Naming strategy for query functions (defaults per language)
+    #[serde(default)]
+    pub naming: Option<NamingStrategy>,
+    /// SQL type name -> target language type overrides
+    #[serde(default)]
+    #[serde(rename = "typeOverrides")]
+    pub type_overrides: HashMap<String, String>,
+    /// Import path of an existing runtime helper module, used instead of
+    /// writing the generated one
+    #[serde(default)]
+    pub runtime: Option<String>,
+    /// Module system of the TypeScript output (default: commonjs)
+    #[serde(default)]
+    #[serde(rename = "moduleFormat")]
+    pub module_format: Option<ModuleFormat>,
+}
+
+impl GeneratorOptions {
+    /// Validate raw options for a provider and convert them
+    pub fn from_map(
+        provider: &str,
+        options: &IndexMap<String, serde_json::Value>,
+    ) -> Result<Self, String> {
+        let allowed: &[&str] = match provider {
+            "ts" | "typescript" => &["naming", "typeOverrides", "runtime", "moduleFormat"],
+            "py" | "python" => &["naming", "typeOverrides", "runtime"],
+            "ts-types" | "typescript-types" | "py-types" | "python-types" => &["typeOverrides"],
+            _ => &[],
+        };
+
+        for key in options.keys() {
+            if !allowed.contains(&key.as_str()) {
+                return Err(if allowed.is_empty() {
+                    format!("Generator '{}' does not accept options", provider)
+                } else {
+                    format!(
+                        "Unknown option '{}' for generator '{}' (supported: {})",
+                        key,
+                        provider,
+                        allowed.join(", ")
+                    )
+                });
+            }
+        }
+
+        let value = serde_json::Value::Object(options.clone().into_iter().collect());
+        serde_json::from_value(value)
+            .map_err(|e| format!("Invalid options for generator '{}': {}", provider, e))
+    }
+
+    /// Look up a type override for an SQL type name
+    pub fn type_override(&self, sql_type: &str) -> Option<&str> {
+        self.type_overrides
+            .get(&sql_type.to_lowercase())
+            .or_else(|| self.type_overrides.get(sql_type))
+            .map(|s| s.as_str())
+    }
+
+    /// Name a query function, falling back to the language default
+    pub fn function_name(&self, query_name: &str, default: NamingStrategy) -> String {
+        self.naming.unwrap_or(default).apply(query_name)
+    }
+}
+
+/// Generator providers understood by [`generate`]
+pub const PROVIDERS: &[&str] = &["ts", "py", "sql", "ts-types", "py-types"];
+
+/// Run a generator by provider name
+///
+/// Query-based providers (`ts`, `py`, `sql`) need a parsed query file; the
+/// types-only providers (`ts-types`, `py-types`) need a schema.
+pub fn generate(
+    provider: &str,
+    query_file: Option<&QueryFile>,
+    schema: Option<&Schema>,
+    options: &GeneratorOptions,
+) -> Result<String, String> {
+    let span = tracing::info_span!(
+        "codegen",
+        provider,
+        queries = query_file.map_or(0, |f| f.queries.len()),
+        tables = schema.map_or(0, |s| s.tables.len()),
+        bytes = tracing::field::Empty,
+        elapsed_ms = tracing::field::Empty
+    );
+    let _guard = span.enter();
+    let started = std::time::Instant::now();
+    let output = render(provider, query_file, schema, options);
+    if let Ok(code) = &output {
+        span.record("bytes", code.len());
+    }
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+    output
+}
+
+/// A query's `@cache` directive, as the `ts` and `py` clients honor it;
+/// `:exec` queries are never cached
+pub(crate) fn cache(query: &Query) -> Option<CacheDirective> {
+    query
+        .cache
+        .filter(|_| Cardinality::from_return_type(&query.return_type) != Cardinality::Exec)
+}
+
+fn render(
+    provider: &str,
+    query_file: Option<&QueryFile>,
+    schema: Option<&Schema>,
+    options: &GeneratorOptions,
+) -> Result<String, String> {
+    let queries = || query_file.ok_or_else(|| format!("Generator '{}' requires queries", provider));
+    let schema_only =
+        || schema.ok_or_else(|| format!("Generator '{}' requires a schema", provider));
+
+    match provider {
+        "ts" | "typescript" => Ok(generate_ts_with_options(queries()?, schema, options)),
+        "py" | "python" => Ok(generate_py_with_options(queries()?, schema, options)),
+        "sql" => Ok(generate_sql(queries()?)),
+        "ts-types" | "typescript-types" => {
+            Ok(generate_ts_types_only_with_options(schema_only()?, options))
+        }
+        "py-types" | "python-types" => {
+            Ok(generate_py_types_only_with_options(schema_only()?, options))
+        }
+        _ => Err(format!(
+            "Unsupported generator provider: {} (expected one of: {})",
+            provider,
+            PROVIDERS.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_by_provider() {
+        let qf = crate::parser::parse("# name: ListUsers :many\nSELECT * FROM users;\n").unwrap();
+        let schema = Schema::default();
+        let opts = GeneratorOptions::default();
+
+        assert!(generate("sql", Some(&qf), None, &opts)
+            .unwrap()
+            .contains("-- name: ListUsers"));
+        assert!(generate("ts-types", None, Some(&schema), &opts).is_ok());
+        assert!(generate("ts", None, Some(&schema), &opts).is_err());
+        assert!(generate("go", Some(&qf), None, &opts).is_err());
+    }
+
+    #[test]
+    fn test_generator_options_validation() {
+        let options: IndexMap<String, serde_json::Value> = serde_json::from_str(
+            r#"{ "naming": "snake_case", "typeOverrides": { "uuid": "UUID" } }"#,
+        )
+        .unwrap();
+
+        let opts = GeneratorOptions::from_map("ts", &options).unwrap();
+        assert_eq!(opts.naming, Some(NamingStrategy::SnakeCase));
+        assert_eq!(opts.type_override("UUID"), Some("UUID"));
+        assert_eq!(
+            opts.function_name("GetUserById", NamingStrategy::CamelCase),
+            "get_user_by_id"
+        );
+
+        // naming is only meaningful for query generators
+        assert!(GeneratorOptions::from_map("ts-types", &options).is_err());
+        assert!(GeneratorOptions::from_map("sql", &options).is_err());
+
+        let options: IndexMap<String, serde_json::Value> =
+            serde_json::from_str(r#"{ "moduleFormat": "esm" }"#).unwrap();
+        assert_eq!(
+            GeneratorOptions::from_map("ts", &options)
+                .unwrap()
+                .module_format,
+            Some(ModuleFormat::Esm)
+        );
+        assert!(GeneratorOptions::from_map("py", &options).is_err());
+        let options: IndexMap<String, serde_json::Value> =
+            serde_json::from_str(r#"{ "moduleFormat": "amd" }"#).unwrap();
+        assert!(GeneratorOptions::from_map("ts", &options).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+/**
+ * Stratus Pagination Detection
+ *
+ * Finds the `:many` queries that page through their rows, so the `ts` and
+ * `py` generators can add a `...Page` variant next to the query function.
+ * Offset paging is `LIMIT $n OFFSET $m`; keyset paging is a `column > $n`
+ * (or `<`) predicate on the first ORDER BY column with a `LIMIT $m`, where
+ * the column is in the result row so the next cursor can be read from it.
+ * Each variant fetches one row more than the page size to tell whether
+ * another page follows.
+ */
+use crate::ast::Query;
+use crate::infer::{Cardinality, Row, Signature};
+use crate::sql::ast::{BinaryOperator, Expr, SetExpr, Statement, Value};
+use crate::sql::ident_name;
+
+/// How a `:many` query pages through its rows; fields are parameter names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pagination {
+    /// `LIMIT $n OFFSET $m`
+    Offset { limit: String, offset: String },
+    /// `WHERE column > $n ... ORDER BY column LIMIT $m`; the next page
+    /// starts after the last row's `property`
+    Keyset {
+        cursor: String,
+        limit: String,
+        property: String,
+    },
+}
+
+/// How a query pages, if it is a `:many` query that does
+pub fn detect(query: &Query, signature: &Signature) -> Option<Pagination> {
+    if signature.cardinality != Cardinality::Many {
+        return None;
+    }
+    let Ok(Statement::Query(statement)) = query.statement() else {
+        return None;
+    };
+    let param = |expr: &Expr| {
+        let ordinal = placeholder(expr)?;
+        signature
+            .params
+            .iter()
+            .find(|p| p.ordinal == ordinal)
+            .map(|p| p.name.clone())
+    };
+    let limit = statement.limit.as_ref().and_then(param)?;
+    if let Some(offset) = statement.offset.as_ref().and_then(|o| param(&o.value)) {
+        return Some(Pagination::Offset { limit, offset });
+    }
+
+    let order = statement.order_by.as_ref()?.exprs.first()?;
+    let column = column_name(&order.expr)?;
+    let SetExpr::Select(select) = statement.body.as_ref() else {
+        return None;
+    };
+    let cursor = conjuncts(select.selection.as_ref()?)
+        .into_iter()
+        .find_map(|expr| match expr {
+            Expr::BinaryOp { left, op, right }
+                if matches!(
+                    op,
+                    BinaryOperator::Gt
+                        | BinaryOperator::GtEq
+                        | BinaryOperator::Lt
+                        | BinaryOperator::LtEq
+                ) && column_name(left).as_ref() == Some(&column) =>
+            {
+                param(right)
+            }
+            _ => None,
+        })?;
+    let Row::Columns(columns) = &signature.row else {
+        return None;
+    };
+    let property = columns
+        .iter()
+        .find(|c| c.column_name == column)?
+        .name
+        .clone();
+    Some(Pagination::Keyset {
+        cursor,
+        limit,
+        property,
+    })
+}
+
+/// 1-based ordinal of a `$n` placeholder, cast or not
+fn placeholder(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Value(Value::Placeholder(p)) => p.strip_prefix('$')?.parse().ok(),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => placeholder(expr),
+        _ => None,
+    }
+}
+
+fn column_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(ident) => Some(ident_name(ident)),
+        Expr::CompoundIdentifier(parts) => parts.last().map(ident_name),
+        _ => None,
+    }
+}
+
+/// The terms of a chain of ANDs
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut terms = conjuncts(left);
+            terms.extend(conjuncts(right));
+            terms
+        }
+        Expr::Nested(inner) => conjuncts(inner),
+        expr => vec![expr],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pagination(source: &str) -> Option<Pagination> {
+        let schema: crate::schema::Schema = serde_json::from_str(
+            r#"{"tables": {"posts": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "author_id": {"name": "author_id", "type": "bigint"}
+            }}}}"#,
+        )
+        .unwrap();
+        let query = crate::parser::parse(source).unwrap().queries.remove(0);
+        detect(&query, &crate::infer::infer(&query, Some(&schema)))
+    }
+
+    #[test]
+    fn test_detect_offset_and_keyset_paging() {
+        assert_eq!(
+            pagination("# name: Q :many\nSELECT * FROM posts ORDER BY id LIMIT $1 OFFSET $2;\n"),
+            Some(Pagination::Offset {
+                limit: "limit".to_string(),
+                offset: "offset".to_string()
+            })
+        );
+        assert_eq!(
+            pagination(
+                "# name: Q :many after: number size: number\nSELECT p.id AS post_id FROM posts p\nWHERE p.author_id = $3 AND p.id > $1 ORDER BY p.id LIMIT $2;\n"
+            ),
+            Some(Pagination::Keyset {
+                cursor: "after".to_string(),
+                limit: "size".to_string(),
+                property: "post_id".to_string()
+            })
+        );
+        // No cursor on the sort column, a fixed limit, or not :many
+        assert!(pagination(
+            "# name: Q :many\nSELECT id FROM posts WHERE author_id > $1 ORDER BY id LIMIT $2;\n"
+        )
+        .is_none());
+        assert!(
+            pagination("# name: Q :many\nSELECT id FROM posts LIMIT 10 OFFSET $1;\n").is_none()
+        );
+        assert!(pagination("# name: Q :one\nSELECT id FROM posts LIMIT $1 OFFSET $2;\n").is_none());
+    }
+}
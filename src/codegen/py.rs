@@ -1,8 +1,31 @@
+use super::paginate::{self, Pagination};
+use super::{cache, runtime, GeneratorOptions, NamingStrategy};
 use crate::ast::{Query, QueryFile};
-use crate::schema::{Column, Index, Partition, Schema, Table, TableConstraint};
+use crate::infer::{infer, Cardinality, ColumnOrigin, Row, Signature};
+use crate::schema::{ref_name, Column, Schema};
+use serde_json::Value;
 
 pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
+    generate_py_with_options(query_file, schema, &GeneratorOptions::default())
+}
+
+pub fn generate_py_with_options(
+    query_file: &QueryFile,
+    schema: Option<&Schema>,
+    options: &GeneratorOptions,
+) -> String {
     let mut output = String::new();
+    let signatures: Vec<Signature> = query_file
+        .queries
+        .iter()
+        .map(|query| infer(query, schema))
+        .collect();
+    let paginations: Vec<Option<Pagination>> = query_file
+        .queries
+        .iter()
+        .zip(&signatures)
+        .map(|(query, signature)| paginate::detect(query, signature))
+        .collect();
 
     output.push_str("# Auto-generated Python types and functions\n");
     output.push_str("# Generated by Stratus TypeSQL Compiler (PostgreSQL)\n\n");
@@ -11,19 +34,34 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
     output.push_str("from dataclasses import dataclass, field\n");
     output.push_str("from datetime import datetime, date, time, timedelta\n");
     output.push_str("import uuid\n\n");
+    let caches = query_file.queries.iter().any(|q| cache(q).is_some());
+    let pages = paginations.iter().any(Option::is_some);
+    let mut imports = vec!["Connection"];
+    imports.extend(pages.then_some("Page"));
+    imports.extend(caches.then_some("cached"));
+    imports.push("execute");
+    imports.extend(pages.then_some("row_value"));
+    imports.extend(pages.then_some("to_page"));
+    imports.extend(["transaction", "with_transaction"]);
+    output.push_str(&format!(
+        "from {} import {}\n\n",
+        runtime::module(runtime::for_provider("py").expect("py runtime"), options),
+        imports.join(", ")
+    ));
 
     // Generate schema-based types
     if let Some(schema) = schema {
+        output.push_str(&shape_classes(schema));
         output.push_str("# ==================== Schema Types ====================\n\n");
 
         for (table_name, table) in &schema.tables {
             let class_name = to_pascal_case(table_name);
             output.push_str(&format!("# Table: {}\n", table_name));
-            output.push_str(&format!("@dataclass\n"));
+            output.push_str("@dataclass\n");
             output.push_str(&format!("class {}:\n", class_name));
 
             for (col_name, col) in &table.columns {
-                let py_type = map_sql_type_to_py(col);
+                let py_type = map_sql_type_to_py(col, options);
                 let default = get_py_default(col);
                 let identity_marker = if col.identity.is_some() {
                     "  # identity"
@@ -36,18 +74,16 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
                     ""
                 };
                 output.push_str(&format!(
-                    "    {}: {}{}{}\n",
-                    col_name, py_type, default, identity_marker
+                    "    {}: {}{}{}{}\n",
+                    col_name, py_type, default, identity_marker, generated_marker
                 ));
             }
-            output.push_str("\n");
+            output.push('\n');
 
             // Generate Insert class
-            output.push_str(&format!("@dataclass\n"));
+            output.push_str("@dataclass\n");
             output.push_str(&format!("class Insert{}:\n", class_name));
-            output.push_str(&format!(
-                "    pass  # All fields are optional for insert\n\n"
-            ));
+            output.push_str("    pass  # All fields are optional for insert\n\n");
         }
 
         // Generate enums
@@ -79,9 +115,9 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
             .collect();
         if !partitioned_tables.is_empty() {
             output.push_str("# ==================== Partitioned Tables ====================\n\n");
-            for (table_name, table) in partitioned_tables {
+            for (table_name, _) in partitioned_tables {
                 let class_name = to_pascal_case(table_name);
-                output.push_str(&format!("@dataclass\n"));
+                output.push_str("@dataclass\n");
                 output.push_str(&format!("class {}Partition:\n", class_name));
                 output.push_str("    partition_name: str\n");
                 output.push_str("    partition_values: str\n\n");
@@ -91,49 +127,46 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
 
     // Generate query parameter types
     output.push_str("# ==================== Query Parameters ====================\n\n");
-    for query in &query_file.queries {
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
         let class_name = format!("{}Params", query.name);
-        output.push_str(&format!("@dataclass\n"));
+        output.push_str("@dataclass\n");
         output.push_str(&format!("class {}:\n", class_name));
-        if query.params.is_empty() {
+        if signature.params.is_empty() {
             output.push_str("    pass\n\n");
         } else {
-            for param in &query.params {
-                let py_type = map_param_type_to_py(&param.type_);
+            for param in &signature.params {
+                // Declared types win; undeclared parameters take their column's type
+                let py_type = match &param.declared {
+                    Some(declared) => map_param_type_to_py(declared).to_string(),
+                    None => param
+                        .sql_column(schema)
+                        .map_or_else(|| "Any".to_string(), |c| map_sql_type_to_py(&c, options)),
+                };
                 output.push_str(&format!("    {}: {}\n", param.name, py_type));
             }
-            output.push_str("\n");
+            output.push('\n');
         }
     }
 
     // Generate query result types
     output.push_str("# ==================== Query Results ====================\n\n");
-    for query in &query_file.queries {
-        // Use JOIN-aware type generation
-        if let Some(schema) = schema {
-            let result_class = generate_py_query_result_class(&query.name, &query.sql, schema);
-            output.push_str(&result_class);
-        } else {
-            let class_name = format!("{}Result", query.name);
-            output.push_str(&format!("@dataclass\n"));
-            output.push_str(&format!("class {}:\n", class_name));
-            output.push_str("    pass  # Schema required for type inference\n\n");
-        }
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
+        output.push_str(&result_class(query, &signature.row, options));
     }
 
     // Generate query registry
     output.push_str("# ==================== Query Registry ====================\n\n");
     output.push_str("QUERIES: Dict[str, Dict[str, Any]] = {\n");
-    for query in &query_file.queries {
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
         output.push_str(&format!("    \"{}\": {{\n", query.name));
         output.push_str(&format!(
             "        \"sql\": \"{}\",\n",
             query.sql.replace("\"", "\\\"")
         ));
-        let params_tuple = if query.params.is_empty() {
+        let params_tuple = if signature.params.is_empty() {
             "()".to_string()
         } else {
-            let params: Vec<_> = query
+            let params: Vec<_> = signature
                 .params
                 .iter()
                 .map(|p| format!("\"{}\"", p.name))
@@ -141,20 +174,15 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
             format!("({})", params.join(", "))
         };
         output.push_str(&format!("        \"params\": {},\n", params_tuple));
-        output.push_str(&format!("    }},\n"));
+        output.push_str("    },\n");
     }
     output.push_str("}\n\n");
 
-    // Generate execute stub
-    output.push_str("# ==================== Database Driver ====================\n\n");
-    output.push_str("async def execute(query_name: str, sql: str, params: list) -> Any:\n");
-    output.push_str("    \"\"\"Execute query - connect to your PostgreSQL driver\"\"\"\n");
-    output.push_str("    # TODO: Connect to native PostgreSQL driver (asyncpg, psycopg2, etc.)\n");
-    output.push_str("    raise NotImplementedError(\"Connect to PostgreSQL driver\")\n\n");
-
     // Generate type-safe query functions
     output.push_str("# ==================== Type-Safe Query Functions ====================\n\n");
-    for query in &query_file.queries {
+    for ((query, signature), pagination) in
+        query_file.queries.iter().zip(&signatures).zip(&paginations)
+    {
         let params_type = format!("{}Params", query.name);
         let return_type = format!("{}Result", query.name);
         let return_type_hint = if query.return_type == "many" {
@@ -162,58 +190,133 @@ pub fn generate_py(query_file: &QueryFile, schema: Option<&Schema>) -> String {
         } else {
             format!("Optional[{}]", return_type)
         };
-        let func_name = to_snake_case(&query.name);
+        let func_name = options.function_name(&query.name, NamingStrategy::SnakeCase);
 
+        let cache = cache(query);
         output.push_str(&format!(
-            "async def {}(params: {}) -> {}:\n",
-            func_name, params_type, return_type_hint
+            "async def {}(params: {}, db: Optional[Connection] = None{}) -> {}:\n",
+            func_name,
+            params_type,
+            if cache.is_some() {
+                ", bypass_cache: bool = False"
+            } else {
+                ""
+            },
+            return_type_hint
         ));
+        if let Some(cache) = cache {
+            output.push_str(&format!(
+                "    \"\"\"Cached for {}s unless run on `db`; pass bypass_cache=True to run it regardless\"\"\"\n",
+                cache.ttl_seconds
+            ));
+        }
         output.push_str(&format!(
             "    sql = \"{}\"\n",
             query.sql.replace("\"", "\\\"")
         ));
 
-        if query.params.is_empty() {
-            output.push_str("    return await execute(\"\", sql, [])\n");
+        let cardinality = Cardinality::from_return_type(&query.return_type);
+        let values = if signature.params.is_empty() {
+            "[]"
         } else {
             output.push_str("    params_list = [\n");
-            for param in &query.params {
+            for param in &signature.params {
                 output.push_str(&format!(
                     "        params.{},  # ${}\n",
                     param.name, param.ordinal
                 ));
             }
             output.push_str("    ]\n");
-            output.push_str(&format!(
-                "    return await execute(\"{}\", sql, params_list)\n",
-                query.name
-            ));
+            "params_list"
+        };
+        let execute = format!(
+            "execute(\"{}\", sql, {}, {}, \"{}\", db)",
+            query.name,
+            values,
+            return_type,
+            cardinality.as_str()
+        );
+        match cache {
+            Some(cache) => output.push_str(&format!(
+                "    return await cached(\"{}\", {}, {}, bypass_cache, db, lambda: {})\n",
+                query.name, values, cache.ttl_seconds, execute
+            )),
+            None => output.push_str(&format!("    return await {}\n", execute)),
+        }
+        output.push('\n');
+        if let Some(pagination) = pagination {
+            output.push_str(&page_function(query, pagination, options));
         }
-        output.push_str("\n");
     }
 
     output
 }
 
+/// A `..._page` variant of a query function, fetching one row more than the
+/// page size to tell whether another page follows
+fn page_function(query: &Query, pagination: &Pagination, options: &GeneratorOptions) -> String {
+    let function = options.function_name(&query.name, NamingStrategy::SnakeCase);
+    let (doc, cursor, args, next) = match pagination {
+        Pagination::Offset { limit, offset } => (
+            "pages count from 1".to_string(),
+            "page: int",
+            format!("{}=page_size + 1, {}=(page - 1) * page_size", limit, offset),
+            "lambda _: page + 1".to_string(),
+        ),
+        Pagination::Keyset {
+            cursor,
+            limit,
+            property,
+        } => (
+            format!("each page starts after the last row's {}", property),
+            "cursor: Any",
+            format!("{}=cursor, {}=page_size + 1", cursor, limit),
+            format!("lambda row: row_value(row, \"{}\")", property),
+        ),
+    };
+    let mut output = format!(
+        "async def {}_page({}, page_size: int, db: Optional[Connection] = None, **params: Any) -> Page[{}Result]:\n",
+        function, cursor, query.name
+    );
+    output.push_str(&format!(
+        "    \"\"\"{} a page at a time; {}\"\"\"\n",
+        query.name, doc
+    ));
+    output.push_str(&format!(
+        "    rows = await {}({}Params(**params, {}), db)\n",
+        function, query.name, args
+    ));
+    output.push_str(&format!(
+        "    return to_page(rows, page_size, {})\n\n",
+        next
+    ));
+    output
+}
+
 pub fn generate_py_types_only(schema: &Schema) -> String {
+    generate_py_types_only_with_options(schema, &GeneratorOptions::default())
+}
+
+pub fn generate_py_types_only_with_options(schema: &Schema, options: &GeneratorOptions) -> String {
     let mut output = String::new();
 
     output.push_str("# Auto-generated Python types from PostgreSQL schema\n");
     output.push_str("# Generated by Stratus TypeSQL Compiler\n\n");
 
     output.push_str("from dataclasses import dataclass\n");
-    output.push_str("from typing import Optional, List\n");
+    output.push_str("from typing import Any, Dict, List, Optional, Union\n");
     output.push_str("from datetime import datetime, date, time, timedelta\n");
     output.push_str("import uuid\n\n");
+    output.push_str(&shape_classes(schema));
 
     for (table_name, table) in &schema.tables {
         let class_name = to_pascal_case(table_name);
         output.push_str(&format!("# Table: {}\n", table_name));
-        output.push_str(&format!("@dataclass\n"));
+        output.push_str("@dataclass\n");
         output.push_str(&format!("class {}:\n", class_name));
 
         for (col_name, col) in &table.columns {
-            let py_type = map_sql_type_to_py(col);
+            let py_type = map_sql_type_to_py(col, options);
             let default = get_py_default(col);
             let identity_marker = if col.identity.is_some() {
                 "  # identity"
@@ -225,55 +328,181 @@ pub fn generate_py_types_only(schema: &Schema) -> String {
                 col_name, py_type, default, identity_marker
             ));
         }
-        output.push_str("\n");
+        output.push('\n');
 
         // Generate Insert class
-        output.push_str(&format!("@dataclass\n"));
+        output.push_str("@dataclass\n");
         output.push_str(&format!("class Insert{}:\n", class_name));
-        output.push_str(&format!("    pass\n\n"));
+        output.push_str("    pass\n\n");
     }
 
     output
 }
 
-fn map_sql_type_to_py(col: &Column) -> String {
-    let base_type = col.data_type.to_lowercase();
-    let is_array = col.array_dimensions.is_some();
+/// Dataclasses, or aliases when not objects, for the JSON shapes that
+/// json/jsonb columns follow
+fn shape_classes(schema: &Schema) -> String {
+    let shapes = schema.shapes();
+    if shapes.is_empty() {
+        return String::new();
+    }
+    let mut output = String::from("# ==================== JSON Shapes ====================\n\n");
+    for (name, shape) in shapes {
+        let name = to_pascal_case(&name);
+        let Some(properties) = shape.get("properties").and_then(Value::as_object) else {
+            output.push_str(&format!("{} = {}\n\n", name, json_schema_to_py(shape)));
+            continue;
+        };
+        let required: Vec<&str> = shape
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|r| r.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        output.push_str("@dataclass\n");
+        output.push_str(&format!("class {}:\n", name));
+        if properties.is_empty() {
+            output.push_str("    pass\n");
+        }
+        // Fields with defaults must follow those without
+        for (key, property) in properties
+            .iter()
+            .filter(|(k, _)| required.contains(&k.as_str()))
+        {
+            output.push_str(&format!("    {}: {}\n", key, json_schema_to_py(property)));
+        }
+        for (key, property) in properties
+            .iter()
+            .filter(|(k, _)| !required.contains(&k.as_str()))
+        {
+            let py_type = json_schema_to_py(property);
+            let py_type = if py_type.starts_with("Optional[") {
+                py_type
+            } else {
+                format!("Optional[{}]", py_type)
+            };
+            output.push_str(&format!("    {}: {} = None\n", key, py_type));
+        }
+        output.push('\n');
+    }
+    output
+}
 
-    let result = match base_type.as_str() {
-        "serial" | "bigserial" | "integer" | "int" | "int4" | "int8" | "bigint" | "smallint" => {
-            "int"
+/// The Python type of values matching a JSON Schema; references to other
+/// shapes are quoted, as they may be declared further down
+fn json_schema_to_py(shape: &Value) -> String {
+    if let Some(reference) = shape.get("$ref").and_then(Value::as_str) {
+        return format!("\"{}\"", to_pascal_case(ref_name(reference)));
+    }
+    if let Some(values) = shape.get("enum").and_then(Value::as_array) {
+        return if values.iter().all(Value::is_string) {
+            "str".to_string()
+        } else if values.iter().all(Value::is_i64) {
+            "int".to_string()
+        } else {
+            "Any".to_string()
+        };
+    }
+    let union = |types: Vec<String>| {
+        let nullable = types.iter().any(|t| t == "None");
+        let types: Vec<String> = types.into_iter().filter(|t| t != "None").collect();
+        let inner = match types.len() {
+            0 => return "None".to_string(),
+            1 => types[0].clone(),
+            _ => format!("Union[{}]", types.join(", ")),
+        };
+        if nullable {
+            format!("Optional[{}]", inner)
+        } else {
+            inner
         }
-        "float" | "double precision" | "real" | "decimal" | "numeric" => "float",
-        "varchar" | "char" | "bpchar" | "text" => "str",
-        "boolean" | "bool" => "bool",
-        "date" => "date",
-        "timestamp"
-        | "timestamptz"
-        | "timestamp with time zone"
-        | "timestamp without time zone" => "datetime",
-        "time" | "timetz" => "time",
-        "interval" => "timedelta",
-        "json" | "jsonb" => "Any",
-        "uuid" => "uuid.UUID",
-        "xml" => "str",
-        "bytea" => "bytes",
-        "cidr" | "inet" | "macaddr" | "macaddr8" => "str",
-        "point" | "line" | "lseg" | "box" | "path" | "polygon" | "circle" => "str",
-        "tsvector" => "str",
-        "tsquery" => "str",
-        "hstore" => "Dict[str, Any]",
-        "ltree" => "str",
-        "money" => "float",
-        "any" | "anyelement" | "anyarray" | "anynonarray" | "anyenum" | "anyrange" => "Any",
-        _ => "Any",
     };
+    if let Some(variants) = shape
+        .get("anyOf")
+        .or_else(|| shape.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        return union(variants.iter().map(json_schema_to_py).collect());
+    }
+    let type_of = |name: &str| match name {
+        "string" => "str".to_string(),
+        "integer" => "int".to_string(),
+        "number" => "float".to_string(),
+        "boolean" => "bool".to_string(),
+        "null" => "None".to_string(),
+        "array" => format!(
+            "List[{}]",
+            shape
+                .get("items")
+                .map_or_else(|| "Any".to_string(), json_schema_to_py)
+        ),
+        "object" => match shape.get("additionalProperties") {
+            Some(Value::Object(values)) if shape.get("properties").is_none() => format!(
+                "Dict[str, {}]",
+                json_schema_to_py(&Value::Object(values.clone()))
+            ),
+            _ => "Dict[str, Any]".to_string(),
+        },
+        _ => "Any".to_string(),
+    };
+    match shape.get("type") {
+        Some(Value::String(name)) => type_of(name),
+        Some(Value::Array(names)) => union(
+            names
+                .iter()
+                .filter_map(Value::as_str)
+                .map(type_of)
+                .collect(),
+        ),
+        _ if shape.get("properties").is_some() => type_of("object"),
+        _ => "Any".to_string(),
+    }
+}
 
-    if is_array {
-        format!("List[{}]", result)
-    } else {
-        result.to_string()
+fn map_sql_type_to_py(col: &Column, options: &GeneratorOptions) -> String {
+    let (base_type, dims) = col.element_type();
+
+    if let Some(shape) = col.shape_name() {
+        return list_of(&to_pascal_case(&shape), dims);
     }
+
+    let result = if let Some(overridden) = options.type_override(&base_type) {
+        overridden
+    } else {
+        match base_type.as_str() {
+            "serial" | "bigserial" | "integer" | "int" | "int4" | "int8" | "bigint"
+            | "smallint" => "int",
+            "float" | "double precision" | "real" | "decimal" | "numeric" => "float",
+            "varchar" | "char" | "bpchar" | "text" => "str",
+            "boolean" | "bool" => "bool",
+            "date" => "date",
+            "timestamp"
+            | "timestamptz"
+            | "timestamp with time zone"
+            | "timestamp without time zone" => "datetime",
+            "time" | "timetz" => "time",
+            "interval" => "timedelta",
+            "json" | "jsonb" => "Any",
+            "uuid" => "uuid.UUID",
+            "xml" => "str",
+            "bytea" => "bytes",
+            "cidr" | "inet" | "macaddr" | "macaddr8" => "str",
+            "point" | "line" | "lseg" | "box" | "path" | "polygon" | "circle" => "str",
+            "tsvector" => "str",
+            "tsquery" => "str",
+            "hstore" => "Dict[str, Any]",
+            "ltree" => "str",
+            "money" => "float",
+            "any" | "anyelement" | "anyarray" | "anynonarray" | "anyenum" | "anyrange" => "Any",
+            _ => "Any",
+        }
+    };
+
+    list_of(result, dims)
+}
+
+/// `element` nested in `dims` lists
+fn list_of(element: &str, dims: usize) -> String {
+    (0..dims).fold(element.to_string(), |inner, _| format!("List[{}]", inner))
 }
 
 fn map_param_type_to_py(sql_type: &str) -> &str {
@@ -334,187 +563,58 @@ fn to_pascal_case(s: &str) -> String {
     result
 }
 
-fn to_snake_case(name: &str) -> String {
-    let mut result = String::new();
-    for (i, c) in name.chars().enumerate() {
-        if c.is_uppercase() {
-            if i > 0 {
-                result.push('_');
-            }
-            result.push(c.to_lowercase().to_string().chars().next().unwrap());
-        } else {
-            result.push(c);
-        }
-    }
-    result
-}
-
-fn extract_table_from_query(sql: &str) -> Option<String> {
-    let sql_lower = sql.to_lowercase();
-    if let Some(from_pos) = sql_lower.find("from") {
-        let after_from = &sql[from_pos + 4..];
-        let tokens: Vec<&str> = after_from.split_whitespace().collect();
-        if !tokens.is_empty() {
-            let table = tokens[0].trim_matches(|c| c == '"' || c == '`' || c == '\'');
-            return Some(table.to_string());
-        }
-    }
-    None
-}
-
 /// Generate query result class with JOIN support
 pub fn generate_py_query_result_class(query_name: &str, sql: &str, schema: &Schema) -> String {
-    use crate::parser::{extract_select_columns, extract_tables_from_sql};
-
-    let tables = extract_tables_from_sql(sql);
-    let columns = extract_select_columns(sql);
-    let class_name = format!("{}Result", query_name);
-
-    // Track used property names to detect conflicts
-    let mut used_property_names: std::collections::HashSet<String> =
-        std::collections::HashSet::new();
-
-    let mut result = format!("@dataclass\n");
-    result.push_str(&format!("class {}:\n", class_name));
-
-    if !tables.is_empty() && !columns.is_empty() {
-        // Track full column path for deduplication
-        let mut processed_columns: std::collections::HashSet<String> =
-            std::collections::HashSet::new();
-
-        for col in &columns {
-            // Handle table.* wildcard
-            if col.is_wildcard && col.table_name.is_some() {
-                let table_name = col.table_name.as_ref().unwrap();
-                if let Some(table) = schema.tables.get(table_name) {
-                    for (col_name, column) in &table.columns {
-                        let key = format!("{}.{}", table_name, col_name);
-                        if !processed_columns.contains(&key) {
-                            processed_columns.insert(key);
-                            let py_type = map_sql_type_to_py(column);
-                            let default = get_py_default(column);
-                            let property_name = get_unique_property_name(
-                                col_name,
-                                table_name,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!("    # From {}\n", table_name));
-                            result.push_str(&format!(
-                                "    {}: {}{}\n",
-                                property_name, py_type, default
-                            ));
-                        }
-                    }
-                }
-            }
-            // Handle * wildcard (all tables)
-            else if col.is_wildcard && col.table_name.is_none() {
-                for table_name in &tables {
-                    if let Some(table) = schema.tables.get(table_name) {
-                        for (col_name, column) in &table.columns {
-                            let key = format!("{}.{}", table_name, col_name);
-                            if !processed_columns.contains(&key) {
-                                processed_columns.insert(key);
-                                let py_type = map_sql_type_to_py(column);
-                                let default = get_py_default(column);
-                                let property_name = get_unique_property_name(
-                                    col_name,
-                                    table_name,
-                                    &mut used_property_names,
-                                );
-                                result.push_str(&format!("    # From {}\n", table_name));
-                                result.push_str(&format!(
-                                    "    {}: {}{}\n",
-                                    property_name, py_type, default
-                                ));
-                            }
-                        }
-                    }
-                }
-            }
-            // Handle specific column (table.column or column)
-            else {
-                let table_name = col.table_name.clone().or_else(|| tables.first().cloned());
-
-                if let Some(tname) = table_name {
-                    if let Some(table) = schema.tables.get(&tname) {
-                        if let Some(column) = table.columns.get(&col.column_name) {
-                            let py_type = map_sql_type_to_py(column);
-                            let default = get_py_default(column);
-                            let property_name = get_unique_property_name(
-                                &col.column_name,
-                                &tname,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!("    # From {}\n", tname));
-                            result.push_str(&format!(
-                                "    {}: {}{}\n",
-                                property_name, py_type, default
-                            ));
-                        } else {
-                            // Column not found in schema
-                            let property_name = get_unique_property_name(
-                                &col.column_name,
-                                &tname,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!(
-                                "    # {} (unknown type)\n    {}: Any = None\n",
-                                col.column_name, property_name
-                            ));
-                        }
+    let query = Query {
+        name: query_name.to_string(),
+        return_type: "one".to_string(),
+        sql: sql.to_string(),
+        params: Vec::new(),
+        cache: None,
+    };
+    let signature = infer(&query, Some(schema));
+    result_class(&query, &signature.row, &GeneratorOptions::default())
+}
+
+fn result_class(query: &Query, row: &Row, options: &GeneratorOptions) -> String {
+    let mut result = "@dataclass\n".to_string();
+    result.push_str(&format!("class {}Result:\n", query.name));
+
+    match row {
+        Row::NoSchema => result.push_str("    pass  # Schema required for type inference\n"),
+        Row::Unresolved(Some(_)) => result.push_str("    pass  # Table not found in schema\n"),
+        Row::Unresolved(None) => result.push_str("    pass  # Use schema to infer types\n"),
+        Row::Columns(columns) => {
+            for col in columns {
+                let Some(column) = &col.column else {
+                    let reason = if col.table_known {
+                        "unknown type"
                     } else {
-                        // Table not found
-                        let property_name = get_unique_property_name(
-                            &col.column_name,
-                            &tname,
-                            &mut used_property_names,
-                        );
-                        result.push_str(&format!(
-                            "    # {} (table not found)\n    {}: Any = None\n",
-                            col.column_name, property_name
-                        ));
-                    }
+                        "table not found"
+                    };
+                    result.push_str(&format!(
+                        "    # {} ({})\n    {}: Any = None\n",
+                        col.column_name, reason, col.name
+                    ));
+                    continue;
+                };
+                if col.origin != ColumnOrigin::Table {
+                    result.push_str(&format!("    # From {}\n", col.table));
                 }
+                result.push_str(&format!(
+                    "    {}: {}{}\n",
+                    col.name,
+                    map_sql_type_to_py(column, options),
+                    get_py_default(column)
+                ));
             }
         }
-    } else if let Some(table_name) = tables.first() {
-        if let Some(table) = schema.tables.get(table_name) {
-            for (col_name, column) in &table.columns {
-                let py_type = map_sql_type_to_py(column);
-                let default = get_py_default(column);
-                result.push_str(&format!("    {}: {}{}\n", col_name, py_type, default));
-            }
-        } else {
-            result.push_str("    pass  # Table not found in schema\n");
-        }
-    } else {
-        result.push_str("    pass  # Use schema to infer types\n");
     }
 
-    result.push_str("\n");
+    result.push('\n');
     result
 }
 
-/// Get a unique property name, adding table prefix if there's a conflict
-fn get_unique_property_name(
-    column_name: &str,
-    table_name: &str,
-    used_names: &mut std::collections::HashSet<String>,
-) -> String {
-    let mut property_name = column_name.to_string();
-    let mut counter = 1;
-
-    while used_names.contains(&property_name) {
-        // Conflict detected, use table prefix with counter
-        property_name = format!("{}_{}_{}", table_name, column_name, counter);
-        counter += 1;
-    }
-
-    used_names.insert(property_name.clone());
-    property_name
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -526,35 +626,72 @@ mod tests {
     }
 
     #[test]
-    fn test_to_snake_case() {
-        assert_eq!(to_snake_case("GetUser"), "get_user");
-        assert_eq!(to_snake_case("ListUsers"), "list_users");
+    fn test_function_naming() {
+        let opts = GeneratorOptions::default();
+        assert_eq!(
+            opts.function_name("GetUser", NamingStrategy::SnakeCase),
+            "get_user"
+        );
+        assert_eq!(
+            opts.function_name("ListUsers", NamingStrategy::SnakeCase),
+            "list_users"
+        );
     }
 
     #[test]
-    fn test_get_unique_property_name_no_conflict() {
-        let mut used = std::collections::HashSet::new();
-        assert_eq!(get_unique_property_name("id", "users", &mut used), "id");
-        assert!(used.contains("id"));
+    fn test_array_columns_nest_by_dimension() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"grids": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "tags": {"name": "tags", "type": "text", "arrayDimensions": 1, "isNotNull": true},
+                "cells": {"name": "cells", "type": "integer", "arrayDimensions": 2, "isNotNull": true},
+                "labels": {"name": "labels", "type": "varchar[][]", "isNotNull": true}
+            }}}}"#,
+        )
+        .unwrap();
+        for py in [
+            generate_py_types_only(&schema),
+            generate_py_query_result_class(
+                "Grid",
+                "SELECT tags, cells, labels FROM grids",
+                &schema,
+            ),
+        ] {
+            assert!(py.contains("tags: List[str]"), "{}", py);
+            assert!(py.contains("cells: List[List[int]]"), "{}", py);
+            assert!(py.contains("labels: List[List[str]]"), "{}", py);
+        }
     }
 
     #[test]
-    fn test_get_unique_property_name_with_conflict() {
-        let mut used = std::collections::HashSet::new();
-        used.insert("id".to_string());
-        assert_eq!(
-            get_unique_property_name("id", "orders", &mut used),
-            "orders_id_1"
+    fn test_json_columns_use_their_shapes() {
+        let schema: Schema = serde_json::from_str(
+            r##"{"tables": {"users": {"columns": {
+                "prefs": {"name": "prefs", "type": "jsonb", "shape": {"$ref": "#/shapes/prefs"}},
+                "addresses": {"name": "addresses", "type": "jsonb", "arrayDimensions": 1, "shape": "address"},
+                "meta": {"name": "meta", "type": "json", "shape": {"type": "object", "properties": {"source": {"type": "string"}}}}
+            }}}, "shapes": {
+                "address": {"type": "object", "required": ["street"], "properties": {"street": {"type": "string"}, "zip": {"type": ["string", "null"]}}},
+                "prefs": {"type": "object", "required": ["theme"], "properties": {"theme": {"enum": ["dark", "light"]}, "home": {"$ref": "#/shapes/address"}}}
+            }}"##,
+        )
+        .unwrap();
+        let py = generate_py_types_only(&schema);
+        assert!(py.contains("class Address:\n    street: str\n    zip: Optional[str] = None\n"));
+        assert!(
+            py.contains("class Prefs:\n    theme: str\n    home: Optional[\"Address\"] = None\n")
         );
-        assert!(used.contains("orders_id_1"));
+        assert!(py.contains("prefs: Prefs = None"));
+        assert!(py.contains("addresses: List[Address] = None"));
+        assert!(py.contains("meta: Meta = None"));
     }
 
     #[test]
     fn test_generate_py_query_result_class_with_join_conflicts() {
         use crate::schema::{Column, Schema, Table};
 
-        let mut tables = std::collections::HashMap::new();
-        let mut users_cols = std::collections::HashMap::new();
+        let mut tables = indexmap::IndexMap::new();
+        let mut users_cols = indexmap::IndexMap::new();
         users_cols.insert(
             "id".to_string(),
             Column {
@@ -577,7 +714,7 @@ mod tests {
             },
         );
 
-        let mut orders_cols = std::collections::HashMap::new();
+        let mut orders_cols = indexmap::IndexMap::new();
         orders_cols.insert(
             "id".to_string(),
             Column {
@@ -0,0 +1,95 @@
+/**
+ * Stratus Client Runtime
+ *
+ * The support module code from the `ts` and `py` generators calls into: a
+ * connection wrapper any node-postgres or asyncpg style driver plugs into,
+ * transactions (`withTransaction` / `with_transaction`, each query function
+ * taking the transaction's connection as its last argument), a row mapper,
+ * the `Page` type paginated query variants return, the pluggable cache
+ * `@cache` queries are served from, an instrumentation hook told each query's
+ * name, duration, row count and error, and the errors queries throw. It is
+ * written next to the generated file, unless the `runtime` generator option
+ * names a helper module the project already has, which must export the same
+ * names.
+ */
+use super::GeneratorOptions;
+
+/// A runtime module for one language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Runtime {
+    /// File name, written next to the generated file
+    pub file_name: &'static str,
+    /// Import path the generated code uses by default
+    pub module: &'static str,
+    pub source: &'static str,
+}
+
+const TS: Runtime = Runtime {
+    file_name: "stratus-runtime.ts",
+    module: "./stratus-runtime",
+    source: include_str!("runtime/stratus-runtime.ts"),
+};
+
+const PY: Runtime = Runtime {
+    file_name: "stratus_runtime.py",
+    module: ".stratus_runtime",
+    source: include_str!("runtime/stratus_runtime.py"),
+};
+
+/// The runtime a provider's code imports, if it uses one
+pub fn for_provider(provider: &str) -> Option<Runtime> {
+    match provider {
+        "ts" | "typescript" => Some(TS),
+        "py" | "python" => Some(PY),
+        _ => None,
+    }
+}
+
+/// The runtime file to write with a provider's output; None when the
+/// provider needs none or the options point at an existing module
+pub fn emitted(provider: &str, options: &GeneratorOptions) -> Option<Runtime> {
+    for_provider(provider).filter(|_| options.runtime.is_none())
+}
+
+/// Module the generated code imports the runtime from
+pub(crate) fn module(runtime: Runtime, options: &GeneratorOptions) -> &str {
+    options.runtime.as_deref().unwrap_or(runtime.module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_is_emitted_unless_replaced() {
+        let defaults = GeneratorOptions::default();
+        assert_eq!(
+            emitted("ts", &defaults).unwrap().file_name,
+            "stratus-runtime.ts"
+        );
+        assert!(emitted("sql", &defaults).is_none());
+
+        let qf = crate::parser::parse("# name: ListUsers :many\nSELECT * FROM users;\n").unwrap();
+        let ts = crate::codegen::generate_ts(&qf, None);
+        assert!(ts.contains("import { execute, type Connection } from './stratus-runtime';"));
+        assert!(ts.contains("export { withTransaction } from './stratus-runtime';"));
+        assert!(ts.contains("listUsers(_params: ListUsersParams, db?: Connection)"));
+        assert!(ts.contains("return execute('ListUsers', sql, [], 'many', db);"));
+
+        let esm: GeneratorOptions = serde_json::from_str(r#"{ "moduleFormat": "esm" }"#).unwrap();
+        let ts = crate::codegen::generate_ts_with_options(&qf, None, &esm);
+        assert!(ts.contains("from './stratus-runtime.js';"));
+        assert!(ts.contains("export { withTransaction } from './stratus-runtime.js';"));
+
+        let options: GeneratorOptions =
+            serde_json::from_str(r#"{ "runtime": "app.db.helpers" }"#).unwrap();
+        assert!(emitted("py", &options).is_none());
+        let py = crate::codegen::generate_py_with_options(&qf, None, &options);
+        assert!(py.contains(
+            "from app.db.helpers import Connection, execute, transaction, with_transaction"
+        ));
+        assert!(py.contains(
+            "return await execute(\"ListUsers\", sql, [], ListUsersResult, \"many\", db)"
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+// Runtime support for code generated by Stratus
+// Written by `stratus generate`; changes here are overwritten
+
+/** Anything with a node-postgres style `query` method, such as pg.Pool or pg.Client */
+export interface Connection {
+  query(
+    sql: string,
+    params: unknown[]
+  ): Promise<{ rows: Record<string, unknown>[] }>;
+}
+
+/** A connection checked out of a pool, returned with `release` */
+export interface PooledConnection extends Connection {
+  release(): void;
+}
+
+/** A pool handing out connections, such as pg.Pool */
+export interface Pool extends Connection {
+  connect(): Promise<PooledConnection>;
+  totalCount: number;
+}
+
+/** How many rows a query returns, from its `:one`, `:many` or `:exec` directive */
+export type Cardinality = 'one' | 'many' | 'exec';
+
+/** Turns a driver row into the row a query function returns */
+export type RowMapper = (row: Record<string, unknown>) => unknown;
+
+/** One page of a paginated query; `nextCursor` is null on the last page */
+export interface Page<T, C> {
+  items: T[];
+  hasMore: boolean;
+  nextCursor: C | null;
+}
+
+/**
+ * Where the results of queries with a `@cache` directive are kept, such as a
+ * `MemoryCache` or a wrapper around Redis; `get` returns undefined on a miss
+ */
+export interface Cache {
+  get(key: string): unknown | Promise<unknown>;
+  set(key: string, value: unknown, ttlSeconds: number): void | Promise<void>;
+}
+
+/** Per-call options of the query functions with a `@cache` directive */
+export interface CacheOptions {
+  /** Run the query even when a result is cached, and cache its result */
+  bypass?: boolean;
+}
+
+/** What the instrumentation hook hears about each query that runs */
+export interface QueryEvent {
+  queryName: string;
+  durationMs: number;
+  /** Rows the driver returned; 0 when the query failed */
+  rowCount: number;
+  /** The driver's error when the query failed */
+  error?: unknown;
+}
+
+/** Called after every query, e.g. to record an OpenTelemetry span or Datadog metric */
+export type Instrumentation = (event: QueryEvent) => void;
+
+/** Base class of the errors generated query functions throw */
+export class StratusError extends Error {
+  constructor(message: string, readonly sql?: string, readonly cause?: unknown) {
+    super(message);
+    this.name = new.target.name;
+  }
+}
+
+/** A query ran before `connect` */
+export class NotConnectedError extends StratusError {}
+
+/** The driver rejected a query; `cause` holds its error */
+export class QueryError extends StratusError {}
+
+let current: Connection | null = null;
+let mapper: RowMapper = (row) => row;
+let cache: Cache | null = null;
+let instrumentation: Instrumentation | null = null;
+
+/** Use this connection (or pool) for every generated query */
+export function connect(connection: Connection): void {
+  current = connection;
+}
+
+/** The connection generated queries run on */
+export function connection(): Connection {
+  if (current === null) {
+    throw new NotConnectedError('No connection: call connect() from stratus-runtime first');
+  }
+  return current;
+}
+
+/** Replace the row mapper, e.g. to convert column values */
+export function setRowMapper(next: RowMapper): void {
+  mapper = next;
+}
+
+export function mapRow<T>(row: Record<string, unknown>): T {
+  return mapper(row) as T;
+}
+
+/** Report every query to `hook`; null stops reporting */
+export function setInstrumentation(hook: Instrumentation | null): void {
+  instrumentation = hook;
+}
+
+function report(event: QueryEvent): void {
+  try {
+    instrumentation?.(event);
+  } catch {
+    // A failing hook must not fail the query
+  }
+}
+
+function isPool(connection: Connection): connection is Pool {
+  return 'totalCount' in connection && typeof (connection as Pool).connect === 'function';
+}
+
+/**
+ * Run `fn` in a transaction: committed when it resolves, rolled back when it
+ * throws. Pass `tx` to the query functions called inside. A pool lends one
+ * of its connections for the length of the transaction.
+ */
+export async function withTransaction<T>(fn: (tx: Connection) => Promise<T>): Promise<T> {
+  const current = connection();
+  const pooled = isPool(current) ? await current.connect() : null;
+  const tx = pooled ?? current;
+  try {
+    await tx.query('BEGIN', []);
+    let result: T;
+    try {
+      result = await fn(tx);
+    } catch (e) {
+      await tx.query('ROLLBACK', []).catch(() => undefined);
+      throw e;
+    }
+    await tx.query('COMMIT', []);
+    return result;
+  } finally {
+    pooled?.release();
+  }
+}
+
+/**
+ * Run a query, on `db` or else the connection, and shape its rows by
+ * cardinality; the instrumentation hook hears how it went
+ */
+export async function execute<T>(
+  queryName: string,
+  sql: string,
+  params: unknown[],
+  cardinality: Cardinality,
+  db?: Connection
+): Promise<T> {
+  const started = performance.now();
+  let result;
+  try {
+    result = await (db ?? connection()).query(sql, params);
+  } catch (cause) {
+    report({ queryName, durationMs: performance.now() - started, rowCount: 0, error: cause });
+    if (cause instanceof StratusError) {
+      throw cause;
+    }
+    const message = cause instanceof Error ? cause.message : String(cause);
+    throw new QueryError(message, sql, cause);
+  }
+  report({ queryName, durationMs: performance.now() - started, rowCount: result.rows.length });
+  switch (cardinality) {
+    case 'many':
+      return result.rows.map((row) => mapRow(row)) as T;
+    case 'exec':
+      return undefined as T;
+    default:
+      return (result.rows.length > 0 ? mapRow(result.rows[0]) : null) as T;
+  }
+}
+
+/** Cut rows fetched with a limit of `pageSize + 1` down to a page */
+export function toPage<T, C>(rows: T[], pageSize: number, next: (last: T) => C): Page<T, C> {
+  const hasMore = rows.length > pageSize;
+  const items = rows.slice(0, pageSize);
+  return { items, hasMore, nextCursor: hasMore ? next(items[items.length - 1]) : null };
+}
+
+/** A cache in this process's memory; entries expire after their TTL */
+export class MemoryCache implements Cache {
+  private entries = new Map<string, { value: unknown; expires: number }>();
+
+  get(key: string): unknown {
+    const entry = this.entries.get(key);
+    if (entry !== undefined && entry.expires <= Date.now()) {
+      this.entries.delete(key);
+      return undefined;
+    }
+    return entry?.value;
+  }
+
+  set(key: string, value: unknown, ttlSeconds: number): void {
+    this.entries.set(key, { value, expires: Date.now() + ttlSeconds * 1000 });
+  }
+}
+
+/** Cache the results of `@cache` queries here; until then (or with null) they always run */
+export function setCache(next: Cache | null): void {
+  cache = next;
+}
+
+/**
+ * Key of a cached result: `<query name>:<hash>`, the hash being the 64-bit
+ * FNV-1a of the parameters as compact JSON, in 16 hex digits. The Python
+ * runtime computes the same keys, so both clients can share one cache.
+ */
+export function cacheKey(queryName: string, params: unknown[]): string {
+  const text = JSON.stringify(params, (_, value) =>
+    typeof value === 'bigint' ? value.toString() : value
+  );
+  let hash = 0xcbf29ce484222325n;
+  for (const byte of new TextEncoder().encode(text)) {
+    hash = ((hash ^ BigInt(byte)) * 0x100000001b3n) & 0xffffffffffffffffn;
+  }
+  return `${queryName}:${hash.toString(16).padStart(16, '0')}`;
+}
+
+/**
+ * Serve a `@cache` query from the cache while its result is fresh, else run it
+ * and cache the result. A query on an explicit `db`, such as a transaction,
+ * always runs and is not cached: it may see rows that are never committed.
+ */
+export async function cached<T>(
+  queryName: string,
+  params: unknown[],
+  ttlSeconds: number,
+  options: CacheOptions | undefined,
+  db: Connection | undefined,
+  run: () => Promise<T>
+): Promise<T> {
+  if (cache === null || db !== undefined) {
+    return run();
+  }
+  const key = cacheKey(queryName, params);
+  if (!options?.bypass) {
+    const hit = await cache.get(key);
+    if (hit !== undefined) {
+      return hit as T;
+    }
+  }
+  const result = await run();
+  await cache.set(key, result, ttlSeconds);
+  return result;
+}
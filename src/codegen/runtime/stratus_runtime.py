@@ -0,0 +1,262 @@
+# Runtime support for code generated by Stratus
+# Written by `stratus generate`; changes here are overwritten
+
+import contextlib
+import dataclasses
+import json
+import time
+from typing import (
+    Any,
+    AsyncIterator,
+    Awaitable,
+    Callable,
+    Dict,
+    Generic,
+    List,
+    Optional,
+    Protocol,
+    Sequence,
+    Tuple,
+    Type,
+    TypeVar,
+)
+
+T = TypeVar("T")
+
+
+class Connection(Protocol):
+    """Anything with an asyncpg style `fetch` method, such as a Pool or Connection"""
+
+    async def fetch(self, query: str, *args: Any) -> Sequence[Any]: ...
+
+
+RowMapper = Callable[[Type[Any], Dict[str, Any]], Any]
+
+
+@dataclasses.dataclass
+class QueryEvent:
+    """What the instrumentation hook hears about each query that runs;
+    `row_count` is 0 and `error` the driver's exception when it failed"""
+
+    query_name: str
+    duration_ms: float
+    row_count: int
+    error: Optional[BaseException] = None
+
+
+# Called after every query, e.g. to record an OpenTelemetry span or Datadog metric
+Instrumentation = Callable[[QueryEvent], None]
+
+
+@dataclasses.dataclass
+class Page(Generic[T]):
+    """One page of a paginated query; `next_cursor` is None on the last page"""
+
+    items: List[T]
+    has_more: bool
+    next_cursor: Any = None
+
+
+class Cache(Protocol):
+    """Where the results of queries with a `@cache` directive are kept, such as
+    a `MemoryCache` or a wrapper around Redis; `get` returns None on a miss.
+    Results are stored as one-element tuples, so a None result is cached too"""
+
+    async def get(self, key: str) -> Any: ...
+
+    async def set(self, key: str, value: Any, ttl_seconds: int) -> None: ...
+
+
+class StratusError(Exception):
+    """Base class of the errors generated query functions raise"""
+
+    def __init__(self, message: str, query_name: Optional[str] = None):
+        super().__init__(message)
+        self.query_name = query_name
+
+
+class NotConnectedError(StratusError):
+    """A query ran before `connect`"""
+
+
+class QueryError(StratusError):
+    """The driver rejected a query; `__cause__` holds its error"""
+
+
+_connection: Optional[Connection] = None
+_cache: Optional[Cache] = None
+_instrumentation: Optional[Instrumentation] = None
+
+
+def _dataclass_row(result_type: Type[Any], row: Dict[str, Any]) -> Any:
+    names = set()
+    if dataclasses.is_dataclass(result_type):
+        names = {f.name for f in dataclasses.fields(result_type)}
+    # Result types without columns, e.g. generated without a schema, keep the dict
+    if not names:
+        return row
+    return result_type(**{k: v for k, v in row.items() if k in names})
+
+
+_mapper: RowMapper = _dataclass_row
+
+
+def connect(connection: Connection) -> None:
+    """Use this connection (or pool) for every generated query"""
+    global _connection
+    _connection = connection
+
+
+def connection() -> Connection:
+    """The connection generated queries run on"""
+    if _connection is None:
+        raise NotConnectedError("No connection: call stratus_runtime.connect() first")
+    return _connection
+
+
+@contextlib.asynccontextmanager
+async def transaction() -> AsyncIterator[Connection]:
+    """A transaction, committed when the block exits and rolled back when it
+    raises; pass the connection it yields to the query functions called
+    inside. A pool lends one of its connections for the length of it."""
+    current: Any = connection()
+    if hasattr(current, "acquire"):
+        async with current.acquire() as tx:
+            async with tx.transaction():
+                yield tx
+    else:
+        async with current.transaction():
+            yield current
+
+
+async def with_transaction(fn: Callable[[Connection], Awaitable[T]]) -> T:
+    """Run `fn` in a `transaction()`, passing it the transaction's connection"""
+    async with transaction() as tx:
+        return await fn(tx)
+
+
+def set_instrumentation(hook: Optional[Instrumentation]) -> None:
+    """Report every query to `hook`; None stops reporting"""
+    global _instrumentation
+    _instrumentation = hook
+
+
+def _report(event: QueryEvent) -> None:
+    if _instrumentation is None:
+        return
+    try:
+        _instrumentation(event)
+    except Exception:
+        # A failing hook must not fail the query
+        pass
+
+
+def set_row_mapper(mapper: RowMapper) -> None:
+    """Replace the row mapper, e.g. to build pydantic models"""
+    global _mapper
+    _mapper = mapper
+
+
+def map_row(result_type: Type[Any], row: Any) -> Any:
+    return _mapper(result_type, dict(row))
+
+
+async def execute(
+    query_name: str,
+    sql: str,
+    params: List[Any],
+    result_type: Type[Any],
+    cardinality: str,
+    db: Optional[Connection] = None,
+) -> Any:
+    """Run a query, on `db` or else the connection, and shape its rows by
+    cardinality (`one`, `many` or `exec`); the instrumentation hook hears how
+    it went"""
+    started = time.perf_counter()
+    try:
+        rows = await (db if db is not None else connection()).fetch(sql, *params)
+    except Exception as e:
+        _report(QueryEvent(query_name, (time.perf_counter() - started) * 1000, 0, e))
+        if isinstance(e, StratusError):
+            raise
+        raise QueryError(str(e), query_name) from e
+    _report(QueryEvent(query_name, (time.perf_counter() - started) * 1000, len(rows)))
+    if cardinality == "many":
+        return [map_row(result_type, row) for row in rows]
+    if cardinality == "exec":
+        return None
+    return map_row(result_type, rows[0]) if rows else None
+
+
+def to_page(rows: List[T], page_size: int, next_cursor: Callable[[T], Any]) -> Page[T]:
+    """Cut rows fetched with a limit of `page_size + 1` down to a page"""
+    has_more = len(rows) > page_size
+    items = rows[:page_size]
+    return Page(items, has_more, next_cursor(items[-1]) if has_more else None)
+
+
+def row_value(row: Any, name: str) -> Any:
+    """A column of a mapped row, whether a dataclass or a dict"""
+    return row[name] if isinstance(row, dict) else getattr(row, name)
+
+
+class MemoryCache:
+    """A cache in this process's memory; entries expire after their TTL"""
+
+    def __init__(self) -> None:
+        self._entries: Dict[str, Tuple[Any, float]] = {}
+
+    async def get(self, key: str) -> Any:
+        entry = self._entries.get(key)
+        if entry is None:
+            return None
+        value, expires = entry
+        if expires <= time.monotonic():
+            del self._entries[key]
+            return None
+        return value
+
+    async def set(self, key: str, value: Any, ttl_seconds: int) -> None:
+        self._entries[key] = (value, time.monotonic() + ttl_seconds)
+
+
+def set_cache(cache: Optional[Cache]) -> None:
+    """Cache the results of `@cache` queries here; until then (or with None)
+    they always run"""
+    global _cache
+    _cache = cache
+
+
+def cache_key(query_name: str, params: List[Any]) -> str:
+    """Key of a cached result: `<query name>:<hash>`, the hash being the 64-bit
+    FNV-1a of the parameters as compact JSON, in 16 hex digits. The TypeScript
+    runtime computes the same keys, so both clients can share one cache"""
+    text = json.dumps(params, default=str, separators=(",", ":"), ensure_ascii=False)
+    digest = 0xCBF29CE484222325
+    for byte in text.encode():
+        digest = ((digest ^ byte) * 0x100000001B3) & 0xFFFFFFFFFFFFFFFF
+    return f"{query_name}:{digest:016x}"
+
+
+async def cached(
+    query_name: str,
+    params: List[Any],
+    ttl_seconds: int,
+    bypass: bool,
+    db: Optional[Connection],
+    run: Callable[[], Awaitable[T]],
+) -> T:
+    """Serve a `@cache` query from the cache while its result is fresh, else
+    run it and cache the result; `bypass` runs it regardless. A query on an
+    explicit `db`, such as a transaction, always runs and is not cached: it
+    may see rows that are never committed"""
+    if _cache is None or db is not None:
+        return await run()
+    key = cache_key(query_name, params)
+    if not bypass:
+        hit = await _cache.get(key)
+        if hit is not None:
+            return hit[0]
+    result = await run()
+    await _cache.set(key, (result,), ttl_seconds)
+    return result
@@ -1,4 +1,4 @@
-use crate::ast::{Query, QueryFile};
+use crate::ast::QueryFile;
 
 pub fn generate_sql(query_file: &QueryFile) -> String {
     let mut output = String::new();
@@ -33,6 +33,7 @@ mod tests {
                     type_: "number".to_string(),
                     ordinal: 1,
                 }],
+                cache: None,
             }],
         };
 
@@ -1,23 +1,77 @@
+use super::paginate::{self, Pagination};
+use super::{cache, runtime, GeneratorOptions, ModuleFormat, NamingStrategy};
 use crate::ast::{Query, QueryFile};
-use crate::schema::{Column, Index, Partition, Schema, Table, TableConstraint};
+use crate::infer::{infer, Cardinality, ColumnOrigin, Row, Signature};
+use crate::schema::{ref_name, Column, Schema, Table, TableVariants};
+use serde_json::Value;
 
 pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
+    generate_ts_with_options(query_file, schema, &GeneratorOptions::default())
+}
+
+pub fn generate_ts_with_options(
+    query_file: &QueryFile,
+    schema: Option<&Schema>,
+    options: &GeneratorOptions,
+) -> String {
     let mut output = String::new();
+    let signatures: Vec<Signature> = query_file
+        .queries
+        .iter()
+        .map(|query| infer(query, schema))
+        .collect();
+    let paginations: Vec<Option<Pagination>> = query_file
+        .queries
+        .iter()
+        .zip(&signatures)
+        .map(|(query, signature)| paginate::detect(query, signature))
+        .collect();
 
     output.push_str("// Auto-generated TypeScript types and functions\n");
     output.push_str("// Generated by Stratus TypeSQL Compiler (PostgreSQL)\n\n");
+    let module = runtime::module(runtime::for_provider("ts").expect("ts runtime"), options);
+    // ES modules import the written runtime by its compiled file name
+    let module = match options.module_format {
+        Some(ModuleFormat::Esm) if options.runtime.is_none() => format!("{}.js", module),
+        _ => module.to_string(),
+    };
+    let caches = query_file.queries.iter().any(|q| cache(q).is_some());
+    let pages = paginations.iter().any(Option::is_some);
+    let mut imports: Vec<&str> = caches.then_some("cached").into_iter().collect();
+    imports.push("execute");
+    imports.extend(pages.then_some("toPage"));
+    imports.push("type Connection");
+    imports.extend(caches.then_some("type CacheOptions"));
+    imports.extend(pages.then_some("type Page"));
+    output.push_str(&format!(
+        "import {{ {} }} from '{}';\n",
+        imports.join(", "),
+        module
+    ));
+    output.push_str(&format!(
+        "export {{ withTransaction }} from '{}';\n\n",
+        module
+    ));
 
     // Generate schema-based types
     if let Some(schema) = schema {
+        output.push_str(&shape_types(schema));
         output.push_str("// ==================== Schema Types ====================\n\n");
 
         for (table_name, table) in &schema.tables {
             let pascal_name = to_pascal_case(table_name);
             output.push_str(&format!("// Table: {}\n", table_name));
-            output.push_str(&format!("export interface {} {{\n", pascal_name));
+            let varied = varied_columns(table);
+            output.push_str(&format!(
+                "export interface {} {{\n",
+                interface_name(&pascal_name, table)
+            ));
 
             for (col_name, col) in &table.columns {
-                let ts_type = map_sql_type_to_ts(col);
+                if varied.contains(&col_name.as_str()) {
+                    continue;
+                }
+                let ts_type = map_sql_type_to_ts(col, options);
                 let optional = if !col.is_not_null() && !col.is_primary_key() {
                     "?"
                 } else {
@@ -26,6 +80,15 @@ pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
                 output.push_str(&format!("  {}{}: {};\n", col_name, optional, ts_type));
             }
             output.push_str("}\n\n");
+            if let Some(variants) = &table.variants {
+                output.push_str(&variant_types(
+                    &pascal_name,
+                    table,
+                    variants,
+                    schema,
+                    options,
+                ));
+            }
 
             // Generate Insert type
             output.push_str(&format!(
@@ -51,7 +114,7 @@ pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
                         ));
                     }
                 }
-                output.push_str("\n");
+                output.push('\n');
             }
         }
 
@@ -79,11 +142,11 @@ pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
             .collect();
         if !partitioned_tables.is_empty() {
             output.push_str("// ==================== Partitioned Tables ====================\n\n");
-            for (table_name, table) in partitioned_tables {
+            for (table_name, _) in partitioned_tables {
                 let pascal_name = to_pascal_case(table_name);
                 output.push_str(&format!("export interface {}Partition {{\n", pascal_name));
-                output.push_str(&format!("  partition_name: string;\n"));
-                output.push_str(&format!("  partition_values: string;\n"));
+                output.push_str("  partition_name: string;\n");
+                output.push_str("  partition_values: string;\n");
                 output.push_str("}\n\n");
             }
         }
@@ -91,48 +154,29 @@ pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
 
     // Generate query parameter interfaces
     output.push_str("// ==================== Query Parameters ====================\n\n");
-    for query in &query_file.queries {
-        let param_interface_name = format!("{}Params", query.name);
-        output.push_str(&format!("export interface {} {{\n", param_interface_name));
-        if query.params.is_empty() {
-            output.push_str("  // No parameters\n");
-        } else {
-            for param in &query.params {
-                let ts_type = map_param_type_to_ts(&param.type_);
-                output.push_str(&format!("  {}: {};\n", param.name, ts_type));
-            }
-        }
-        output.push_str("}\n\n");
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
+        output.push_str(&params_interface(query, signature, schema, options));
+        output.push('\n');
     }
 
     // Generate query result types
     output.push_str("// ==================== Query Results ====================\n\n");
-    for query in &query_file.queries {
-        // Use JOIN-aware type generation
-        if let Some(schema) = schema {
-            let result_type = generate_query_result_type(&query.name, &query.sql, schema);
-            output.push_str(&result_type);
-            output.push_str("\n");
-        } else {
-            let return_type_name = format!("{}Result", query.name);
-            output.push_str(&format!("export type {} = {{\n", return_type_name));
-            output.push_str("  // Schema required for type inference\n");
-            output.push_str("  [key: string]: unknown;\n");
-            output.push_str("};\n\n");
-        }
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
+        output.push_str(&result_type(query, &signature.row, options));
+        output.push('\n');
     }
 
     // Generate query registry
     output.push_str("// ==================== Query Registry ====================\n\n");
     output.push_str("export const queries = {\n");
-    for query in &query_file.queries {
+    for (query, signature) in query_file.queries.iter().zip(&signatures) {
         let param_interface_name = format!("{}Params", query.name);
         let return_type_name = format!("{}Result", query.name);
         output.push_str(&format!("  {}: {{\n", query.name));
         output.push_str(&format!("    sql: `{}`,\n", query.sql.replace("`", "\\`")));
         output.push_str(&format!(
             "    params: {} as unknown as {},\n",
-            if query.params.is_empty() {
+            if signature.params.is_empty() {
                 "undefined"
             } else {
                 "{}"
@@ -143,70 +187,99 @@ pub fn generate_ts(query_file: &QueryFile, schema: Option<&Schema>) -> String {
             "    result: null as unknown as {},\n",
             return_type_name
         ));
-        output.push_str(&format!("  }},\n"));
+        output.push_str("  },\n");
     }
     output.push_str("} as const;\n\n");
 
-    // Generate execute stub
-    output.push_str("// ==================== Database Driver ====================\n\n");
-    output.push_str("export async function execute<T>(\n");
-    output.push_str("  sql: string,\n");
-    output.push_str("  params: unknown[]\n");
-    output.push_str("): Promise<T> {\n");
-    output.push_str("  // TODO: Connect to native PostgreSQL driver (pg, node-postgres)\n");
-    output.push_str("  throw new Error('Not implemented: connect to PostgreSQL driver');\n");
-    output.push_str("}\n\n");
-
     // Generate type-safe query functions
     output.push_str("// ==================== Type-Safe Query Functions ====================\n\n");
-    for query in &query_file.queries {
+    for ((query, signature), pagination) in
+        query_file.queries.iter().zip(&signatures).zip(&paginations)
+    {
         let param_interface_name = format!("{}Params", query.name);
-        let return_type_name = format!("{}Result", query.name);
+        let return_type_name = match query.return_type.as_str() {
+            "many" => format!("{}Result[]", query.name),
+            _ => format!("{}Result | null", query.name),
+        };
+        let cache = cache(query);
+        if let Some(cache) = cache {
+            output.push_str(&format!(
+                "/** Cached for {}s unless run on `db`; pass {{ bypass: true }} to run it regardless */\n",
+                cache.ttl_seconds
+            ));
+        }
         output.push_str(&format!(
-            "export async function {}({}: {}): Promise<{}> {{\n",
-            to_camel_case(&query.name),
-            if query.params.is_empty() {
+            "export async function {}({}: {}, db?: Connection{}): Promise<{}> {{\n",
+            options.function_name(&query.name, NamingStrategy::CamelCase),
+            if signature.params.is_empty() {
                 "_params"
             } else {
                 "params"
             },
             param_interface_name,
+            if cache.is_some() {
+                ", options?: CacheOptions"
+            } else {
+                ""
+            },
             return_type_name
         ));
         output.push_str(&format!(
             "  const sql = `{}`;\n",
             query.sql.replace("`", "\\`")
         ));
-        if query.params.is_empty() {
-            output.push_str("  return execute(sql, []);\n");
+        let cardinality = Cardinality::from_return_type(&query.return_type);
+        let values = if signature.params.is_empty() {
+            "[]"
         } else {
-            output.push_str("  const params = [\n");
-            for param in &query.params {
+            output.push_str("  const values = [\n");
+            for param in &signature.params {
                 output.push_str(&format!(
                     "    params.{}, // ${{{}}}\n",
                     param.name, param.ordinal
                 ));
             }
             output.push_str("  ];\n");
-            output.push_str("  return execute(sql, params);\n");
+            "values"
+        };
+        let execute = format!(
+            "execute('{}', sql, {}, '{}', db)",
+            query.name,
+            values,
+            cardinality.as_str()
+        );
+        match cache {
+            Some(cache) => output.push_str(&format!(
+                "  return cached('{}', {}, {}, options, db, () => {});\n",
+                query.name, values, cache.ttl_seconds, execute
+            )),
+            None => output.push_str(&format!("  return {};\n", execute)),
         }
         output.push_str("}\n\n");
+        if let Some(pagination) = pagination {
+            output.push_str(&page_function(query, pagination, options));
+        }
     }
 
     output
 }
 
 pub fn generate_ts_types_only(schema: &Schema) -> String {
+    generate_ts_types_only_with_options(schema, &GeneratorOptions::default())
+}
+
+pub fn generate_ts_types_only_with_options(schema: &Schema, options: &GeneratorOptions) -> String {
     let mut output = String::new();
 
     output.push_str("// Auto-generated TypeScript types from PostgreSQL schema\n");
     output.push_str("// Generated by Stratus TypeSQL Compiler\n\n");
+    output.push_str(&shape_types(schema));
 
     for (table_name, table) in &schema.tables {
         let pascal_name = to_pascal_case(table_name);
 
         // Table JSDoc
-        output.push_str(&format!("/**\n"));
+        output.push_str("/**\n");
         output.push_str(&format!(" * Table: {}\n", table_name));
         if let Some(options) = &table.options.tablespace {
             output.push_str(&format!(" * Tablespace: {}\n", options));
@@ -217,10 +290,17 @@ pub fn generate_ts_types_only(schema: &Schema) -> String {
         output.push_str(" */\n");
 
         // Generate table interface
-        output.push_str(&format!("export interface {} {{\n", pascal_name));
+        let varied = varied_columns(table);
+        output.push_str(&format!(
+            "export interface {} {{\n",
+            interface_name(&pascal_name, table)
+        ));
 
         for (col_name, col) in &table.columns {
-            let ts_type = map_sql_type_to_ts(col);
+            if varied.contains(&col_name.as_str()) {
+                continue;
+            }
+            let ts_type = map_sql_type_to_ts(col, options);
             let optional = if !col.is_not_null() && !col.is_primary_key() {
                 "?"
             } else {
@@ -260,6 +340,15 @@ pub fn generate_ts_types_only(schema: &Schema) -> String {
             ));
         }
         output.push_str("}\n\n");
+        if let Some(variants) = &table.variants {
+            output.push_str(&variant_types(
+                &pascal_name,
+                table,
+                variants,
+                schema,
+                options,
+            ));
+        }
 
         // Generate Insert type
         output.push_str(&format!(
@@ -351,46 +440,395 @@ pub fn generate_ts_types_only(schema: &Schema) -> String {
     output
 }
 
-fn map_sql_type_to_ts(col: &Column) -> String {
-    let base_type = col.data_type.to_lowercase();
-    let is_array = col.array_dimensions.is_some();
+/// The parameter interface and result type generated for one query
+/// A `...Page` variant of a query function, fetching one row more than the
+/// page size to tell whether another page follows
+fn page_function(query: &Query, pagination: &Pagination, options: &GeneratorOptions) -> String {
+    let function = options.function_name(&query.name, NamingStrategy::CamelCase);
+    let params = format!("{}Params", query.name);
+    let (doc, omitted, cursor, args, next) = match pagination {
+        Pagination::Offset { limit, offset } => (
+            "pages count from 1".to_string(),
+            [limit, offset],
+            "page: number".to_string(),
+            format!("{}: pageSize + 1, {}: (page - 1) * pageSize", limit, offset),
+            ("number".to_string(), "() => page + 1".to_string()),
+        ),
+        Pagination::Keyset {
+            cursor,
+            limit,
+            property,
+        } => (
+            format!("each page starts after the last row's {}", property),
+            [cursor, limit],
+            format!("cursor: {}['{}']", params, cursor),
+            format!("{}: cursor, {}: pageSize + 1", cursor, limit),
+            (
+                format!("{}['{}']", params, cursor),
+                format!("(row) => row.{} as {}['{}']", property, params, cursor),
+            ),
+        ),
+    };
+    let mut output = format!("/** {} a page at a time; {} */\n", query.name, doc);
+    output.push_str(&format!(
+        "export async function {}Page(params: Omit<{}, '{}' | '{}'>, {}, pageSize: number, db?: Connection): Promise<Page<{}Result, {}>> {{\n",
+        function, params, omitted[0], omitted[1], cursor, query.name, next.0
+    ));
+    output.push_str(&format!(
+        "  const rows = await {}({{ ...params, {} }}, db);\n",
+        function, args
+    ));
+    output.push_str(&format!("  return toPage(rows, pageSize, {});\n", next.1));
+    output.push_str("}\n\n");
+    output
+}
+
+pub fn query_types(query: &Query, schema: Option<&Schema>, options: &GeneratorOptions) -> String {
+    let signature = infer(query, schema);
+    format!(
+        "{}\n{}",
+        params_interface(query, &signature, schema, options),
+        result_type(query, &signature.row, options)
+    )
+}
 
-    let result = match base_type.as_str() {
-        "serial" | "bigserial" | "integer" | "int" | "int4" | "int8" | "bigint" | "smallint" => {
-            "number"
+fn params_interface(
+    query: &Query,
+    signature: &Signature,
+    schema: Option<&Schema>,
+    options: &GeneratorOptions,
+) -> String {
+    let mut output = format!("export interface {}Params {{\n", query.name);
+    if signature.params.is_empty() {
+        output.push_str("  // No parameters\n");
+    } else {
+        for param in &signature.params {
+            // Declared types win; undeclared parameters take their column's type
+            let ts_type = match &param.declared {
+                Some(declared) => map_param_type_to_ts(declared).to_string(),
+                None => param.sql_column(schema).map_or_else(
+                    || "unknown".to_string(),
+                    |c| map_sql_type_to_ts(&c, options),
+                ),
+            };
+            output.push_str(&format!("  {}: {};\n", param.name, ts_type));
         }
-        "float" | "double precision" | "real" | "decimal" | "numeric" => "number",
-        "varchar" | "char" | "bpchar" | "text" => "string",
-        "boolean" | "bool" => "boolean",
-        "date"
-        | "timestamp"
-        | "timestamptz"
-        | "timestamp with time zone"
-        | "timestamp without time zone"
-        | "time"
-        | "timetz" => "Date",
-        "interval" => "string",
-        "json" => "Record<string, unknown>",
-        "jsonb" => "Record<string, unknown>",
-        "uuid" => "string",
-        "xml" => "string",
-        "bytea" => "Uint8Array",
-        "cidr" | "inet" | "macaddr" | "macaddr8" => "string",
-        "point" | "line" | "lseg" | "box" | "path" | "polygon" | "circle" => "string",
-        "tsvector" => "string",
-        "tsquery" => "string",
-        "hstore" => "Record<string, unknown>",
-        "ltree" => "string",
-        "money" => "number",
-        "any" | "anyelement" | "anyarray" | "anynonarray" | "anyenum" | "anyrange" => "unknown",
-        _ => "unknown",
+    }
+    output.push_str("}\n");
+    output
+}
+
+fn result_type(query: &Query, row: &Row, options: &GeneratorOptions) -> String {
+    let name = format!("{}Result", query.name);
+    let columns = match row {
+        Row::NoSchema => {
+            return format!(
+                "export type {} = {{\n  // Schema required for type inference\n  [key: string]: unknown;\n}};\n",
+                name
+            )
+        }
+        Row::Unresolved(_) => return format!("export type {} = Record<string, unknown>;\n", name),
+        Row::Columns(columns) => columns,
     };
 
-    if is_array {
-        format!("{}[]", result)
+    let mut result = format!("export type {} = {{\n", name);
+    for col in columns {
+        let Some(column) = &col.column else {
+            let reason = if col.table_known {
+                "unknown type"
+            } else {
+                "table not found"
+            };
+            result.push_str(&format!(
+                "  /** {} ({}) */\n  {}?: unknown;\n",
+                col.column_name, reason, col.name
+            ));
+            continue;
+        };
+        let ts_type = map_sql_type_to_ts(column, options);
+        match col.origin {
+            ColumnOrigin::Table => {
+                let optional = if !column.is_not_null() && !column.is_primary_key() {
+                    "?"
+                } else {
+                    ""
+                };
+                result.push_str(&format!("  {}{}: {};\n", col.name, optional, ts_type));
+            }
+            ColumnOrigin::Unqualified => {
+                result.push_str(&format!(
+                    "  /** Default */\n  {}?: {};\n",
+                    col.name, ts_type
+                ));
+            }
+            ColumnOrigin::Wildcard | ColumnOrigin::Qualified => {
+                result.push_str(&format!(
+                    "  /** From {} */\n  {}?: {};\n",
+                    col.table, col.name, ts_type
+                ));
+            }
+        }
+    }
+    result.push_str("};\n");
+    result
+}
+
+/// Declarations for the JSON shapes that json/jsonb columns follow
+fn shape_types(schema: &Schema) -> String {
+    let shapes = schema.shapes();
+    if shapes.is_empty() {
+        return String::new();
+    }
+    let mut output = String::from("// ==================== JSON Shapes ====================\n\n");
+    for (name, shape) in shapes {
+        let name = to_pascal_case(&name);
+        match shape.get("properties").and_then(Value::as_object) {
+            Some(_) => output.push_str(&format!(
+                "export interface {} {}\n\n",
+                name,
+                json_schema_to_ts(shape)
+            )),
+            None => output.push_str(&format!(
+                "export type {} = {};\n\n",
+                name,
+                json_schema_to_ts(shape)
+            )),
+        }
+    }
+    output
+}
+
+/// The interface of a table's columns: all of them, or those every variant
+/// shares when its rows are a discriminated union
+fn interface_name(pascal_name: &str, table: &Table) -> String {
+    match table.variants {
+        Some(_) => format!("{}Base", pascal_name),
+        None => pascal_name.to_string(),
+    }
+}
+
+/// The columns the variants of a table type differently: the discriminator
+/// and the JSON columns the cases give shapes to
+fn varied_columns(table: &Table) -> Vec<&str> {
+    let Some(variants) = &table.variants else {
+        return Vec::new();
+    };
+    let mut columns = vec![variants.discriminator.as_str()];
+    columns.extend(variants.payload_columns());
+    columns
+}
+
+/// One interface per value of the discriminator, extending the table's base
+/// interface with the value and the JSON columns shaped for it, and the table
+/// type as their union
+fn variant_types(
+    pascal_name: &str,
+    table: &Table,
+    variants: &TableVariants,
+    schema: &Schema,
+    options: &GeneratorOptions,
+) -> String {
+    let values: Vec<&str> = table
+        .columns
+        .get(&variants.discriminator)
+        .and_then(|column| schema.enums.as_ref()?.get(&column.data_type))
+        .map(|values| values.iter().map(String::as_str).collect())
+        .unwrap_or_else(|| variants.cases.keys().map(String::as_str).collect());
+    let mut output = String::new();
+    let mut members = Vec::new();
+    for value in values {
+        let identifier = value.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        let member = format!("{}{}", pascal_name, to_pascal_case(&identifier));
+        output.push_str(&format!(
+            "export interface {} extends {}Base {{\n",
+            member, pascal_name
+        ));
+        output.push_str(&format!(
+            "  {}: '{}';\n",
+            variants.discriminator,
+            value.replace('\'', "\\'")
+        ));
+        let case = variants.cases.get(value);
+        for name in variants.payload_columns() {
+            let Some(col) = table.columns.get(name) else {
+                continue;
+            };
+            let ts_type = match case.and_then(|case| case.get(name)) {
+                Some(Value::Null) => "null".to_string(),
+                Some(Value::String(shape)) => {
+                    array_of(&to_pascal_case(shape), col.element_type().1)
+                }
+                Some(shape) => array_of(&json_schema_to_ts(shape), col.element_type().1),
+                None => map_sql_type_to_ts(col, options),
+            };
+            let optional = if !col.is_not_null() && !col.is_primary_key() {
+                "?"
+            } else {
+                ""
+            };
+            output.push_str(&format!("  {}{}: {};\n", name, optional, ts_type));
+        }
+        output.push_str("}\n\n");
+        members.push(member);
+    }
+    if members.is_empty() {
+        members.push(format!("{}Base", pascal_name));
+    }
+    output.push_str(&format!(
+        "export type {} = {};\n\n",
+        pascal_name,
+        members.join(" | ")
+    ));
+    output
+}
+
+/// The TypeScript type of values matching a JSON Schema
+fn json_schema_to_ts(shape: &Value) -> String {
+    if let Some(reference) = shape.get("$ref").and_then(Value::as_str) {
+        return to_pascal_case(ref_name(reference));
+    }
+    if let Some(values) = shape.get("enum").and_then(Value::as_array) {
+        return values
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    if let Some(value) = shape.get("const") {
+        return value.to_string();
+    }
+    if let Some(variants) = shape
+        .get("anyOf")
+        .or_else(|| shape.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        return variants
+            .iter()
+            .map(json_schema_to_ts)
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    let type_of = |name: &str| match name {
+        "string" => "string".to_string(),
+        "integer" | "number" => "number".to_string(),
+        "boolean" => "boolean".to_string(),
+        "null" => "null".to_string(),
+        "array" => match shape.get("items") {
+            Some(items) => {
+                let item = json_schema_to_ts(items);
+                if item.contains(" | ") {
+                    format!("({})[]", item)
+                } else {
+                    format!("{}[]", item)
+                }
+            }
+            None => "unknown[]".to_string(),
+        },
+        "object" => match shape.get("properties").and_then(Value::as_object) {
+            Some(properties) => {
+                let required: Vec<&str> = shape
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .map(|r| r.iter().filter_map(Value::as_str).collect())
+                    .unwrap_or_default();
+                let mut fields = String::from("{\n");
+                for (key, property) in properties {
+                    let key = if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                        && !key.starts_with(|c: char| c.is_ascii_digit())
+                    {
+                        key.clone()
+                    } else {
+                        Value::from(key.as_str()).to_string()
+                    };
+                    let optional = if required.contains(&key.as_str()) {
+                        ""
+                    } else {
+                        "?"
+                    };
+                    let field = json_schema_to_ts(property).replace('\n', "\n  ");
+                    fields.push_str(&format!("  {}{}: {};\n", key, optional, field));
+                }
+                fields.push('}');
+                fields
+            }
+            None => match shape.get("additionalProperties") {
+                Some(Value::Object(_)) => format!(
+                    "Record<string, {}>",
+                    json_schema_to_ts(&shape["additionalProperties"])
+                ),
+                _ => "Record<string, unknown>".to_string(),
+            },
+        },
+        _ => "unknown".to_string(),
+    };
+    match shape.get("type") {
+        Some(Value::String(name)) => type_of(name),
+        Some(Value::Array(names)) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .map(type_of)
+            .collect::<Vec<_>>()
+            .join(" | "),
+        _ if shape.get("properties").is_some() => type_of("object"),
+        _ => "unknown".to_string(),
+    }
+}
+
+pub(crate) fn map_sql_type_to_ts(col: &Column, options: &GeneratorOptions) -> String {
+    let (base_type, dims) = col.element_type();
+
+    if let Some(shape) = col.shape_name() {
+        return array_of(&to_pascal_case(&shape), dims);
+    }
+
+    let result = if let Some(overridden) = options.type_override(&base_type) {
+        overridden
     } else {
-        result.to_string()
+        match base_type.as_str() {
+            "serial" | "bigserial" | "integer" | "int" | "int4" | "int8" | "bigint"
+            | "smallint" => "number",
+            "float" | "double precision" | "real" | "decimal" | "numeric" => "number",
+            "varchar" | "char" | "bpchar" | "text" => "string",
+            "boolean" | "bool" => "boolean",
+            "date"
+            | "timestamp"
+            | "timestamptz"
+            | "timestamp with time zone"
+            | "timestamp without time zone"
+            | "time"
+            | "timetz" => "Date",
+            "interval" => "string",
+            "json" => "Record<string, unknown>",
+            "jsonb" => "Record<string, unknown>",
+            "uuid" => "string",
+            "xml" => "string",
+            "bytea" => "Uint8Array",
+            "cidr" | "inet" | "macaddr" | "macaddr8" => "string",
+            "point" | "line" | "lseg" | "box" | "path" | "polygon" | "circle" => "string",
+            "tsvector" => "string",
+            "tsquery" => "string",
+            "hstore" => "Record<string, unknown>",
+            "ltree" => "string",
+            "money" => "number",
+            "any" | "anyelement" | "anyarray" | "anynonarray" | "anyenum" | "anyrange" => "unknown",
+            _ => "unknown",
+        }
+    };
+
+    array_of(result, dims)
+}
+
+/// `element` nested in `dims` arrays, parenthesized when it is a union
+fn array_of(element: &str, dims: usize) -> String {
+    if dims == 0 {
+        return element.to_string();
     }
+    let element = if element.contains('|') {
+        format!("({})", element)
+    } else {
+        element.to_string()
+    };
+    format!("{}{}", element, "[]".repeat(dims))
 }
 
 fn map_param_type_to_ts(sql_type: &str) -> &str {
@@ -420,210 +858,17 @@ fn to_pascal_case(s: &str) -> String {
     result
 }
 
-fn to_camel_case(s: &str) -> String {
-    let pascal = to_pascal_case(s);
-    let mut chars = pascal.chars();
-    match chars.next() {
-        Some(c) => c.to_lowercase().to_string() + chars.as_str(),
-        None => String::new(),
-    }
-}
-
-fn extract_table_from_query(sql: &str) -> Option<String> {
-    let sql_lower = sql.to_lowercase();
-    if let Some(from_pos) = sql_lower.find("from") {
-        let after_from = &sql[from_pos + 4..];
-        let tokens: Vec<&str> = after_from.split_whitespace().collect();
-        if !tokens.is_empty() {
-            let table = tokens[0].trim_matches(|c| c == '"' || c == '`' || c == '\'');
-            return Some(table.to_string());
-        }
-    }
-    None
-}
-
 /// Generate query result type with JOIN support
 pub fn generate_query_result_type(query_name: &str, sql: &str, schema: &Schema) -> String {
-    use crate::parser::{extract_select_columns, extract_tables_from_sql};
-
-    let tables = extract_tables_from_sql(sql);
-    let columns = extract_select_columns(sql);
-
-    let return_type_name = format!("{}Result", query_name);
-
-    // If we have schema and tables/columns, generate proper type
-    if !tables.is_empty() && !columns.is_empty() {
-        let mut result = format!("export type {} = {{\n", return_type_name);
-
-        // Track used property names to detect conflicts
-        let mut used_property_names: std::collections::HashSet<String> =
-            std::collections::HashSet::new();
-        // Track full column path for deduplication
-        let mut processed_columns: std::collections::HashSet<String> =
-            std::collections::HashSet::new();
-
-        for col in &columns {
-            // Handle table.* wildcard
-            if col.is_wildcard && col.table_name.is_some() {
-                let table_name = col.table_name.as_ref().unwrap();
-                if let Some(table) = schema.tables.get(table_name) {
-                    for (col_name, column) in &table.columns {
-                        let key = format!("{}.{}", table_name, col_name);
-                        if !processed_columns.contains(&key) {
-                            processed_columns.insert(key);
-                            let ts_type = map_sql_type_to_ts(column);
-                            let optional = if !column.is_not_null() && !column.is_primary_key() {
-                                "?"
-                            } else {
-                                ""
-                            };
-                            // Use alias format for JOIN results, handle conflicts with table prefix
-                            let property_name = get_unique_property_name(
-                                col_name,
-                                table_name,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!("  /** From {} */\n", table_name));
-                            result.push_str(&format!("  {}?: {};\n", property_name, ts_type));
-                        }
-                    }
-                }
-            }
-            // Handle * wildcard (all tables)
-            else if col.is_wildcard && col.table_name.is_none() {
-                for table_name in &tables {
-                    if let Some(table) = schema.tables.get(table_name) {
-                        for (col_name, column) in &table.columns {
-                            let key = format!("{}.{}", table_name, col_name);
-                            if !processed_columns.contains(&key) {
-                                processed_columns.insert(key);
-                                let ts_type = map_sql_type_to_ts(column);
-                                let optional = if !column.is_not_null() && !column.is_primary_key()
-                                {
-                                    "?"
-                                } else {
-                                    ""
-                                };
-                                let property_name = get_unique_property_name(
-                                    col_name,
-                                    table_name,
-                                    &mut used_property_names,
-                                );
-                                result.push_str(&format!("  /** From {} */\n", table_name));
-                                result.push_str(&format!("  {}?: {};\n", property_name, ts_type));
-                            }
-                        }
-                    }
-                }
-            }
-            // Handle specific column (table.column or column)
-            else {
-                let table_name = col.table_name.clone().or_else(|| {
-                    // If no table specified, find from tables list
-                    tables.first().cloned()
-                });
-
-                if let Some(tname) = table_name {
-                    if let Some(table) = schema.tables.get(&tname) {
-                        if let Some(column) = table.columns.get(&col.column_name) {
-                            let ts_type = map_sql_type_to_ts(column);
-                            let optional = if !column.is_not_null() && !column.is_primary_key() {
-                                "?"
-                            } else {
-                                ""
-                            };
-                            let source = if col.table_name.is_some() {
-                                format!("From {}", col.table_name.as_ref().unwrap())
-                            } else {
-                                "Default".to_string()
-                            };
-                            let property_name = get_unique_property_name(
-                                &col.column_name,
-                                &tname,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!("  /** {} */\n", source));
-                            result.push_str(&format!("  {}?: {};\n", property_name, ts_type));
-                        } else {
-                            // Column not found in schema
-                            let property_name = get_unique_property_name(
-                                &col.column_name,
-                                &tname,
-                                &mut used_property_names,
-                            );
-                            result.push_str(&format!(
-                                "  /** {} (unknown type) */\n  {}?: unknown;\n",
-                                col.column_name, property_name
-                            ));
-                        }
-                    } else {
-                        // Table not found
-                        let property_name = get_unique_property_name(
-                            &col.column_name,
-                            &tname,
-                            &mut used_property_names,
-                        );
-                        result.push_str(&format!(
-                            "  /** {} (table not found) */\n  {}?: unknown;\n",
-                            col.column_name, property_name
-                        ));
-                    }
-                }
-            }
-        }
-
-        result.push_str("};\n");
-        result
-    } else {
-        // Fallback to schema inference for single table
-        if let Some(table_name) = tables.first() {
-            if let Some(table) = schema.tables.get(table_name) {
-                let mut result = format!("export type {} = {{\n", return_type_name);
-                let mut used_property_names: std::collections::HashSet<String> =
-                    std::collections::HashSet::new();
-                for (col_name, column) in &table.columns {
-                    let ts_type = map_sql_type_to_ts(column);
-                    let optional = if !column.is_not_null() && !column.is_primary_key() {
-                        "?"
-                    } else {
-                        ""
-                    };
-                    result.push_str(&format!("  {}{}: {};\n", col_name, optional, ts_type));
-                }
-                result.push_str("};\n");
-                result
-            } else {
-                format!(
-                    "export type {} = Record<string, unknown>;\n",
-                    return_type_name
-                )
-            }
-        } else {
-            format!(
-                "export type {} = Record<string, unknown>;\n",
-                return_type_name
-            )
-        }
-    }
-}
-
-/// Get a unique property name, adding table prefix if there's a conflict
-fn get_unique_property_name(
-    column_name: &str,
-    table_name: &str,
-    used_names: &mut std::collections::HashSet<String>,
-) -> String {
-    let mut property_name = column_name.to_string();
-    let mut counter = 1;
-
-    while used_names.contains(&property_name) {
-        // Conflict detected, use table prefix with counter
-        property_name = format!("{}_{}_{}", table_name, column_name, counter);
-        counter += 1;
-    }
-
-    used_names.insert(property_name.clone());
-    property_name
+    let query = Query {
+        name: query_name.to_string(),
+        return_type: "one".to_string(),
+        sql: sql.to_string(),
+        params: Vec::new(),
+        cache: None,
+    };
+    let signature = infer(&query, Some(schema));
+    result_type(&query, &signature.row, &GeneratorOptions::default())
 }
 
 #[cfg(test)]
@@ -637,46 +882,24 @@ mod tests {
     }
 
     #[test]
-    fn test_to_camel_case() {
-        assert_eq!(to_camel_case("GetUser"), "getUser");
-        assert_eq!(to_camel_case("ListUsers"), "listUsers");
-    }
-
-    #[test]
-    fn test_get_unique_property_name_no_conflict() {
-        let mut used = std::collections::HashSet::new();
-        assert_eq!(get_unique_property_name("id", "users", &mut used), "id");
-        assert!(used.contains("id"));
-    }
-
-    #[test]
-    fn test_get_unique_property_name_with_conflict() {
-        let mut used = std::collections::HashSet::new();
-        used.insert("id".to_string());
+    fn test_function_naming() {
+        let opts = GeneratorOptions::default();
         assert_eq!(
-            get_unique_property_name("id", "orders", &mut used),
-            "orders_id_1"
+            opts.function_name("GetUser", NamingStrategy::CamelCase),
+            "getUser"
+        );
+        assert_eq!(
+            opts.function_name("ListUsers", NamingStrategy::CamelCase),
+            "listUsers"
         );
-        assert!(used.contains("orders_id_1"));
-    }
-
-    #[test]
-    fn test_get_unique_property_name_multiple_conflicts() {
-        let mut used = std::collections::HashSet::new();
-        used.insert("id".to_string());
-        used.insert("orders_id".to_string());
-        // This would create another conflict, so we'd get orders_id again
-        // but since it's already used, it would continue
-        let result = get_unique_property_name("id", "orders", &mut used);
-        assert!(result.starts_with("orders_id"));
     }
 
     #[test]
     fn test_generate_query_result_type_with_join_conflicts() {
         use crate::schema::{Column, Schema, Table};
 
-        let mut tables = std::collections::HashMap::new();
-        let mut users_cols = std::collections::HashMap::new();
+        let mut tables = indexmap::IndexMap::new();
+        let mut users_cols = indexmap::IndexMap::new();
         users_cols.insert(
             "id".to_string(),
             Column {
@@ -699,7 +922,7 @@ mod tests {
             },
         );
 
-        let mut orders_cols = std::collections::HashMap::new();
+        let mut orders_cols = indexmap::IndexMap::new();
         orders_cols.insert(
             "id".to_string(),
             Column {
@@ -764,4 +987,108 @@ mod tests {
             "Should have orders.total as total"
         );
     }
+
+    #[test]
+    fn test_undeclared_params_take_column_types() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {"id": {"name": "id", "type": "bigint"}, "email": {"name": "email", "type": "text"}}}}}"#,
+        )
+        .unwrap();
+        let qf =
+            crate::parser::parse("# name: ByEmail :one\nSELECT id FROM users WHERE email = $1;\n")
+                .unwrap();
+        let ts = generate_ts(&qf, Some(&schema));
+        assert!(ts.contains("export interface ByEmailParams {\n  email: string;\n}"));
+        assert!(ts.contains("    params.email, // ${1}\n"));
+    }
+
+    #[test]
+    fn test_array_columns_nest_by_dimension() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"grids": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "tags": {"name": "tags", "type": "text", "arrayDimensions": 1, "isNotNull": true},
+                "cells": {"name": "cells", "type": "integer", "arrayDimensions": 2, "isNotNull": true},
+                "labels": {"name": "labels", "type": "varchar[][]", "isNotNull": true}
+            }}}}"#,
+        )
+        .unwrap();
+        for ts in [
+            generate_ts_types_only(&schema),
+            generate_query_result_type("Grid", "SELECT tags, cells, labels FROM grids", &schema),
+        ] {
+            let ts = ts.replace("?:", ":");
+            assert!(ts.contains("tags: string[];"), "{}", ts);
+            assert!(ts.contains("cells: number[][];"), "{}", ts);
+            assert!(ts.contains("labels: string[][];"), "{}", ts);
+        }
+        assert_eq!(array_of("string | null", 1), "(string | null)[]");
+    }
+
+    #[test]
+    fn test_json_columns_use_their_shapes() {
+        let schema: Schema = serde_json::from_str(
+            r##"{"tables": {"users": {"columns": {
+                "prefs": {"name": "prefs", "type": "jsonb", "shape": {"$ref": "#/shapes/prefs"}},
+                "addresses": {"name": "addresses", "type": "jsonb", "arrayDimensions": 1, "shape": "address"},
+                "meta": {"name": "meta", "type": "json", "shape": {"type": "object", "properties": {"source": {"type": "string"}}}}
+            }}}, "shapes": {
+                "address": {"type": "object", "required": ["street"], "properties": {"street": {"type": "string"}, "zip": {"type": ["string", "null"]}}},
+                "prefs": {"type": "object", "required": ["theme"], "properties": {"theme": {"enum": ["dark", "light"]}, "home": {"$ref": "#/shapes/address"}}}
+            }}"##,
+        )
+        .unwrap();
+        let ts = generate_ts_types_only(&schema);
+        assert!(
+            ts.contains("export interface Address {\n  street: string;\n  zip?: string | null;\n}")
+        );
+        assert!(ts.contains(
+            "export interface Prefs {\n  home?: Address;\n  theme: \"dark\" | \"light\";\n}"
+        ));
+        assert!(ts.contains("export interface Meta {\n  source?: string;\n}"));
+        assert!(ts.contains("prefs?: Prefs;"));
+        assert!(ts.contains("addresses?: Address[];"));
+        assert!(ts.contains("meta?: Meta;"));
+    }
+
+    #[test]
+    fn test_variants_generate_a_discriminated_union() {
+        let schema: Schema = serde_json::from_str(
+            r##"{"tables": {"events": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "kind": {"name": "kind", "type": "event_kind", "isNotNull": true},
+                "payload": {"name": "payload", "type": "jsonb", "isNotNull": true},
+                "meta": {"name": "meta", "type": "jsonb"}
+            }, "variants": {"discriminator": "kind", "cases": {
+                "sign_up": {"payload": "sign_up", "meta": null},
+                "purchase": {"payload": {"$ref": "#/shapes/purchase"}}
+            }}}},
+            "enums": {"event_kind": ["sign_up", "purchase", "log-out"]},
+            "shapes": {
+                "sign_up": {"type": "object", "properties": {"referrer": {"type": "string"}}},
+                "purchase": {"type": "object", "required": ["total"], "properties": {"total": {"type": "number"}}}
+            }}"##,
+        )
+        .unwrap();
+        let ts = generate_ts_types_only(&schema);
+        assert!(ts.contains("export interface EventsBase {\n  id: number; // PK\n}"));
+        assert!(ts.contains(
+            "export interface EventsSignUp extends EventsBase {\n  kind: 'sign_up';\n  payload: SignUp;\n  meta?: null;\n}"
+        ));
+        assert!(ts.contains(
+            "export interface EventsPurchase extends EventsBase {\n  kind: 'purchase';\n  payload: Purchase;\n  meta?: Record<string, unknown>;\n}"
+        ));
+        assert!(ts.contains(
+            "export interface EventsLogOut extends EventsBase {\n  kind: 'log-out';\n  payload: Record<string, unknown>;\n"
+        ));
+        assert!(ts.contains("export type Events = EventsSignUp | EventsPurchase | EventsLogOut;"));
+        assert!(ts.contains("export type InsertEvents = Partial<Events>;"));
+
+        let file = QueryFile {
+            queries: Vec::new(),
+        };
+        let ts = generate_ts(&file, Some(&schema));
+        assert!(ts.contains("export interface EventsBase {\n  id: number;\n}"));
+        assert!(ts.contains("export type Events = EventsSignUp | EventsPurchase | EventsLogOut;"));
+    }
 }
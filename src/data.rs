@@ -0,0 +1,1265 @@
+/**
+ * Stratus Data Module
+ *
+ * Table data in files: reading CSV and JSON fixtures into rows for a
+ * schema.json table, with every value checked against its column's type so
+ * bad rows are rejected up front instead of aborting a COPY halfway, and
+ * writing rows back out with the column types deciding their JSON form.
+ * Sampling copies a masked subset of one database into another.
+ */
+use crate::config::SampleConfig;
+use crate::db::CopyFormat;
+use crate::migrate::{quote_ident, quote_literal};
+use crate::schema::{Column, MaskRule, Schema};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use thiserror::Error;
+
+/// Data file errors
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum DataError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+
+    #[error("Table '{0}' is not in the schema")]
+    UnknownTable(String),
+
+    #[error("Table '{table}' has no column(s) {}", .columns.join(", "))]
+    UnknownColumns { table: String, columns: Vec<String> },
+
+    #[error("Required column(s) {} of '{table}' are missing from the file", .columns.join(", "))]
+    MissingColumns { table: String, columns: Vec<String> },
+
+    #[error("Unsupported data format {0} (expected csv, json, jsonl or binary)")]
+    UnknownFormat(String),
+
+    #[error("Binary COPY data is loaded as is and has no records to parse")]
+    BinaryRecords,
+
+    #[error("Mask on {column}: {reason}")]
+    InvalidMask { column: String, reason: String },
+
+    #[error("Hashed masks need a key; set {0}")]
+    MissingMaskSecret(String),
+}
+
+pub type DataResult<T> = Result<T, DataError>;
+
+/// Data file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    Csv,
+    /// An array of objects, or one object per line
+    Json,
+    /// Postgres binary COPY data, with every schema column in order
+    Binary,
+}
+
+impl DataFormat {
+    /// The format a file's extension names
+    pub fn from_path(path: &Path) -> DataResult<Self> {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("csv") => Ok(Self::Csv),
+            Some("json" | "jsonl" | "ndjson") => Ok(Self::Json),
+            Some("bin" | "pgcopy") => Ok(Self::Binary),
+            _ => Err(DataError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    /// The format named by `--format`: csv, json, jsonl or binary
+    pub fn from_name(name: &str) -> DataResult<Self> {
+        match name.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" | "jsonl" | "ndjson" => Ok(Self::Json),
+            "binary" | "bin" | "pgcopy" => Ok(Self::Binary),
+            _ => Err(DataError::UnknownFormat(name.to_string())),
+        }
+    }
+}
+
+/// One row of a data file; `None` is NULL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// 1-based line the row starts on
+    pub line: usize,
+    pub values: Vec<Option<String>>,
+}
+
+/// The rows of a data file under its column headers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Records {
+    pub headers: Vec<String>,
+    pub records: Vec<Record>,
+}
+
+impl Records {
+    pub fn parse(input: &str, format: DataFormat) -> DataResult<Self> {
+        match format {
+            DataFormat::Csv => parse_csv(input),
+            DataFormat::Json => parse_json(input),
+            DataFormat::Binary => Err(DataError::BinaryRecords),
+        }
+    }
+}
+
+/// Parse RFC 4180 CSV with a header row.
+///
+/// An empty unquoted field is NULL; `""` is the empty string.
+pub fn parse_csv(input: &str) -> DataResult<Records> {
+    let mut rows: Vec<Record> = Vec::new();
+    let mut values: Vec<Option<String>> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            '"' => {
+                return Err(DataError::Syntax {
+                    line,
+                    message: "quote inside an unquoted field".to_string(),
+                })
+            }
+            ',' => values.push(take_field(&mut field, &mut quoted)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                values.push(take_field(&mut field, &mut quoted));
+                push_row(&mut rows, std::mem::take(&mut values), row_line);
+                line += 1;
+                row_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(DataError::Syntax {
+            line: row_line,
+            message: "unterminated quoted field".to_string(),
+        });
+    }
+    if !field.is_empty() || quoted || !values.is_empty() {
+        values.push(take_field(&mut field, &mut quoted));
+        push_row(&mut rows, values, row_line);
+    }
+
+    let mut rows = rows.into_iter();
+    let headers = match rows.next() {
+        Some(header) => header
+            .values
+            .into_iter()
+            .map(|h| h.unwrap_or_default().trim().to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Records {
+        headers,
+        records: rows.collect(),
+    })
+}
+
+fn take_field(field: &mut String, quoted: &mut bool) -> Option<String> {
+    let value = std::mem::take(field);
+    let was_quoted = std::mem::take(quoted);
+    (was_quoted || !value.is_empty()).then_some(value)
+}
+
+/// Add a row, skipping blank lines
+fn push_row(rows: &mut Vec<Record>, values: Vec<Option<String>>, line: usize) {
+    if values.len() > 1 || values.first().is_some_and(|v| v.is_some()) {
+        rows.push(Record { line, values });
+    }
+}
+
+/// Parse a JSON array of objects, or JSON lines.
+///
+/// Headers are the keys of every object together; a missing key or
+/// `null` is NULL, and nested values are kept as JSON text.
+pub fn parse_json(input: &str) -> DataResult<Records> {
+    let objects: Vec<(usize, serde_json::Value)> = if input.trim_start().starts_with('[') {
+        let value: serde_json::Value =
+            serde_json::from_str(input).map_err(|e| DataError::Syntax {
+                line: e.line(),
+                message: e.to_string(),
+            })?;
+        // Line numbers of array elements are not tracked; number them instead
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .cloned()
+            .enumerate()
+            .map(|(i, v)| (i + 1, v))
+            .collect()
+    } else {
+        input
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(i, l)| {
+                serde_json::from_str(l)
+                    .map(|v| (i + 1, v))
+                    .map_err(|e| DataError::Syntax {
+                        line: i + 1,
+                        message: e.to_string(),
+                    })
+            })
+            .collect::<DataResult<_>>()?
+    };
+
+    let mut headers: Vec<String> = Vec::new();
+    for (line, object) in &objects {
+        let object = object.as_object().ok_or_else(|| DataError::Syntax {
+            line: *line,
+            message: "expected an object".to_string(),
+        })?;
+        for key in object.keys() {
+            if !headers.contains(key) {
+                headers.push(key.clone());
+            }
+        }
+    }
+    let records = objects
+        .iter()
+        .map(|(line, object)| Record {
+            line: *line,
+            values: headers
+                .iter()
+                .map(|h| match object.get(h) {
+                    None | Some(serde_json::Value::Null) => None,
+                    Some(serde_json::Value::String(s)) => Some(s.clone()),
+                    Some(other) => Some(other.to_string()),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(Records { headers, records })
+}
+
+/// A row left out of a load, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Rows ready for COPY, in the table's column names
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prepared {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+    pub rejected: Vec<Rejected>,
+}
+
+/// Match headers to the table's columns and check every value
+pub fn prepare(records: Records, table_name: &str, schema: &Schema) -> DataResult<Prepared> {
+    let table = schema
+        .tables
+        .get(table_name)
+        .ok_or_else(|| DataError::UnknownTable(table_name.to_string()))?;
+
+    let unknown: Vec<String> = records
+        .headers
+        .iter()
+        .filter(|h| !table.columns.contains_key(*h))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(DataError::UnknownColumns {
+            table: table_name.to_string(),
+            columns: unknown,
+        });
+    }
+    let missing: Vec<String> = table
+        .columns
+        .iter()
+        .filter(|(name, column)| is_required(column) && !records.headers.contains(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    if !missing.is_empty() {
+        return Err(DataError::MissingColumns {
+            table: table_name.to_string(),
+            columns: missing,
+        });
+    }
+
+    let columns: Vec<&Column> = records.headers.iter().map(|h| &table.columns[h]).collect();
+    let mut prepared = Prepared {
+        columns: records.headers.clone(),
+        ..Prepared::default()
+    };
+    for record in records.records {
+        match prepare_row(record.values, &records.headers, &columns, schema) {
+            Ok(row) => prepared.rows.push(row),
+            Err(reason) => prepared.rejected.push(Rejected {
+                line: record.line,
+                reason,
+            }),
+        }
+    }
+    Ok(prepared)
+}
+
+/// NOT NULL without a default or a generated value
+fn is_required(column: &Column) -> bool {
+    column.is_not_null()
+        && !column.element_type().0.ends_with("serial")
+        && column.default.is_none()
+        && column.identity.is_none()
+        && column.generated.is_none()
+}
+
+fn prepare_row(
+    values: Vec<Option<String>>,
+    headers: &[String],
+    columns: &[&Column],
+    schema: &Schema,
+) -> Result<Vec<Option<String>>, String> {
+    if values.len() != headers.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            headers.len(),
+            values.len()
+        ));
+    }
+    values
+        .into_iter()
+        .zip(headers.iter().zip(columns))
+        .map(|(value, (name, column))| match value {
+            None if column.is_not_null() => Err(format!("{} cannot be NULL", name)),
+            None => Ok(None),
+            Some(value) => coerce(&value, column, schema)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", name, e)),
+        })
+        .collect()
+}
+
+/// Check a value against a column's type, normalized for COPY
+pub fn coerce(value: &str, column: &Column, schema: &Schema) -> Result<String, String> {
+    let (base, dims) = column.element_type();
+    if dims > 0 {
+        return coerce_array(value);
+    }
+    let trimmed = value.trim();
+    let invalid = |kind: &str| Err(format!("'{}' is not a valid {}", value, kind));
+
+    match base.as_str() {
+        "smallint" | "int2" | "smallserial" => match trimmed.parse::<i16>() {
+            Ok(n) => Ok(n.to_string()),
+            Err(_) => invalid("smallint"),
+        },
+        "integer" | "int" | "int4" | "serial" => match trimmed.parse::<i32>() {
+            Ok(n) => Ok(n.to_string()),
+            Err(_) => invalid("integer"),
+        },
+        "bigint" | "int8" | "bigserial" => match trimmed.parse::<i64>() {
+            Ok(n) => Ok(n.to_string()),
+            Err(_) => invalid("bigint"),
+        },
+        "real" | "float4" | "float" | "float8" | "double" | "double precision" | "decimal"
+        | "numeric" => match trimmed.parse::<f64>() {
+            Ok(_) => Ok(trimmed.to_string()),
+            Err(_) => invalid("number"),
+        },
+        "boolean" | "bool" => match trimmed.to_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "on" | "1" => Ok("true".to_string()),
+            "false" | "f" | "no" | "n" | "off" | "0" => Ok("false".to_string()),
+            _ => invalid("boolean"),
+        },
+        "json" | "jsonb" => match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(_) => Ok(value.to_string()),
+            Err(_) => invalid("JSON value"),
+        },
+        "uuid" => {
+            let hex: String = trimmed.chars().filter(|c| *c != '-').collect();
+            if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                Ok(trimmed.to_string())
+            } else {
+                invalid("UUID")
+            }
+        }
+        "date" => match chrono::NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+            Ok(_) => Ok(trimmed.to_string()),
+            Err(_) => invalid("date (YYYY-MM-DD)"),
+        },
+        "timestamp"
+        | "timestamptz"
+        | "timestamp with time zone"
+        | "timestamp without time zone" => {
+            if is_timestamp(trimmed) {
+                Ok(trimmed.to_string())
+            } else {
+                invalid("timestamp")
+            }
+        }
+        "varchar" | "char" | "character varying" | "character" => match column.size {
+            Some(size) if value.chars().count() > size => {
+                Err(format!("'{}' is longer than {} characters", value, size))
+            }
+            _ => Ok(value.to_string()),
+        },
+        _ => match schema
+            .enums
+            .iter()
+            .flatten()
+            .find(|(name, _)| name.to_lowercase() == base)
+        {
+            Some((_, labels)) if !labels.iter().any(|l| l == trimmed) => {
+                Err(format!("'{}' is not one of {}", value, labels.join(", ")))
+            }
+            Some(_) => Ok(trimmed.to_string()),
+            None => Ok(value.to_string()),
+        },
+    }
+}
+
+fn is_timestamp(value: &str) -> bool {
+    chrono::DateTime::parse_from_rfc3339(value).is_ok()
+        || [
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M",
+        ]
+        .iter()
+        .any(|f| chrono::NaiveDateTime::parse_from_str(value, f).is_ok())
+        || ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%d %H:%M:%S%.f%:z"]
+            .iter()
+            .any(|f| chrono::DateTime::parse_from_str(value, f).is_ok())
+        || chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok()
+}
+
+/// Arrays come as Postgres literals (`{a,b}`) or JSON arrays (`["a","b"]`)
+fn coerce_array(value: &str) -> Result<String, String> {
+    let trimmed = value.trim();
+    if trimmed.starts_with('{') {
+        return Ok(trimmed.to_string());
+    }
+    match serde_json::from_str::<serde_json::Value>(trimmed) {
+        Ok(array @ serde_json::Value::Array(_)) => Ok(array_literal(&array)),
+        _ => Err(format!("'{}' is not an array", value)),
+    }
+}
+
+fn array_literal(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Array(items) => format!(
+            "{{{}}}",
+            items
+                .iter()
+                .map(array_literal)
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::String(s) => {
+            format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// How an exported column's text becomes a JSON value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonKind {
+    Integer,
+    Float,
+    Boolean,
+    /// Selected as `to_json(...)`: json, jsonb and arrays
+    Json,
+    String,
+}
+
+impl JsonKind {
+    fn of(column: &Column) -> Self {
+        let (base, dims) = column.element_type();
+        if dims > 0 {
+            return Self::Json;
+        }
+        match base.as_str() {
+            "smallint" | "int2" | "smallserial" | "integer" | "int" | "int4" | "serial"
+            | "bigint" | "int8" | "bigserial" => Self::Integer,
+            "real" | "float4" | "float" | "float8" | "double" | "double precision" => Self::Float,
+            "boolean" | "bool" => Self::Boolean,
+            "json" | "jsonb" => Self::Json,
+            _ => Self::String,
+        }
+    }
+}
+
+/// A table export: the query to stream and how to write its rows
+#[derive(Debug, Clone)]
+pub struct Export {
+    /// Selects every schema column, as text unless the format is binary
+    pub sql: String,
+    pub columns: Vec<String>,
+    format: DataFormat,
+    kinds: Vec<JsonKind>,
+}
+
+impl Export {
+    /// Export a schema table's columns, optionally filtered by a SQL condition
+    pub fn new(
+        table_name: &str,
+        schema: &Schema,
+        format: DataFormat,
+        filter: Option<&str>,
+    ) -> DataResult<Self> {
+        let table = schema
+            .tables
+            .get(table_name)
+            .ok_or_else(|| DataError::UnknownTable(table_name.to_string()))?;
+        let kinds: Vec<JsonKind> = table.columns.values().map(JsonKind::of).collect();
+        let select: Vec<String> = table
+            .columns
+            .keys()
+            .zip(&kinds)
+            .map(|(name, kind)| match (format, kind) {
+                (DataFormat::Json, JsonKind::Json) => {
+                    format!("to_json({})::text", quote_ident(name))
+                }
+                (DataFormat::Binary, _) => quote_ident(name),
+                _ => format!("{}::text", quote_ident(name)),
+            })
+            .collect();
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            select.join(", "),
+            quote_ident(table_name)
+        );
+        if let Some(filter) = filter {
+            sql.push_str(&format!(" WHERE {}", filter));
+        }
+        Ok(Self {
+            sql,
+            columns: table.columns.keys().cloned().collect(),
+            format,
+            kinds,
+        })
+    }
+
+    /// How `copy_out` writes this export, when the server can write it
+    /// directly; JSON rows go through `write_row` instead
+    pub fn copy_format(&self) -> Option<CopyFormat> {
+        match self.format {
+            DataFormat::Csv => Some(CopyFormat::Csv { header: true }),
+            DataFormat::Json => None,
+            DataFormat::Binary => Some(CopyFormat::Binary),
+        }
+    }
+
+    /// Write what comes before the rows: the CSV header
+    pub fn write_header(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        if self.format == DataFormat::Csv {
+            let header: Vec<Option<String>> = self.columns.iter().cloned().map(Some).collect();
+            write_csv_row(out, &header)?;
+        }
+        Ok(())
+    }
+
+    /// Write one row of `query_each_values` output
+    pub fn write_row(
+        &self,
+        out: &mut impl std::io::Write,
+        row: &[Option<String>],
+    ) -> std::io::Result<()> {
+        match self.format {
+            DataFormat::Csv => write_csv_row(out, row),
+            DataFormat::Json => {
+                // Written by hand to keep the columns in table order
+                out.write_all(b"{")?;
+                for (i, ((name, kind), value)) in
+                    self.columns.iter().zip(&self.kinds).zip(row).enumerate()
+                {
+                    if i > 0 {
+                        out.write_all(b",")?;
+                    }
+                    serde_json::to_writer(&mut *out, name)?;
+                    out.write_all(b":")?;
+                    serde_json::to_writer(&mut *out, &json_value(*kind, value.as_deref()))?;
+                }
+                out.write_all(b"}")?;
+                out.write_all(b"\n")
+            }
+            DataFormat::Binary => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "binary exports are written by COPY",
+            )),
+        }
+    }
+}
+
+fn json_value(kind: JsonKind, value: Option<&str>) -> serde_json::Value {
+    use serde_json::Value;
+    let Some(value) = value else {
+        return Value::Null;
+    };
+    let parsed = match kind {
+        JsonKind::Integer => value.parse::<i64>().ok().map(Value::from),
+        JsonKind::Float => value
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        JsonKind::Boolean => match value {
+            "t" | "true" => Some(Value::Bool(true)),
+            "f" | "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        JsonKind::Json => serde_json::from_str(value).ok(),
+        JsonKind::String => None,
+    };
+    // NaN, Infinity and anything unexpected stay text
+    parsed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Write a CSV row that `parse_csv` reads back: NULL is an empty field and
+/// the empty string is `""`
+fn write_csv_row(out: &mut impl std::io::Write, row: &[Option<String>]) -> std::io::Result<()> {
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        match value {
+            None => {}
+            Some(value) if value.is_empty() || value.contains([',', '"', '\n', '\r']) => {
+                write!(out, "\"{}\"", value.replace('"', "\"\""))?;
+            }
+            Some(value) => out.write_all(value.as_bytes())?,
+        }
+    }
+    out.write_all(b"\n")
+}
+
+/// Environment variable holding the masking key, unless `sample.secretEnv` names another
+pub const MASK_SECRET_ENV: &str = "STRATUS_MASK_SECRET";
+
+/// The masking key, from the variable `sample.secretEnv` names or STRATUS_MASK_SECRET
+pub fn mask_secret(config: Option<&SampleConfig>) -> Option<String> {
+    std::env::var(secret_env(config))
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+fn secret_env(config: Option<&SampleConfig>) -> &str {
+    config
+        .and_then(|c| c.secret_env.as_deref())
+        .unwrap_or(MASK_SECRET_ENV)
+}
+
+/// One table of a sample
+#[derive(Debug, Clone)]
+pub struct SampleTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub limit: u64,
+    pub filter: Option<String>,
+    masks: Vec<Option<(MaskRule, Column)>>,
+    /// Column index, parent table and parent column of each reference to a
+    /// table sampled earlier
+    parents: Vec<(usize, String, String)>,
+}
+
+/// Copies a subset of each table, parents before children, keeping only
+/// child rows whose parents were copied and masking columns on the way
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub tables: Vec<SampleTable>,
+    /// Source values of referenced columns, by table and column
+    keys: HashMap<(String, String), HashSet<String>>,
+    /// HMAC key of the hashed masks
+    secret: Vec<u8>,
+}
+
+impl Sampler {
+    /// Plan a sample of the configured tables, or of every table; `secret`
+    /// keys the hashed masks and is required when any are used
+    pub fn new(
+        schema: &Schema,
+        config: Option<&SampleConfig>,
+        secret: Option<&str>,
+    ) -> DataResult<Self> {
+        let default_limit = config.map_or(1000, |c| c.limit);
+        let mut names: Vec<&String> = match config {
+            Some(c) if !c.tables.is_empty() => c.tables.keys().collect(),
+            _ => schema.tables.keys().collect(),
+        };
+        if let Some(unknown) = names.iter().find(|n| !schema.tables.contains_key(**n)) {
+            return Err(DataError::UnknownTable(unknown.to_string()));
+        }
+
+        let configured_masks = config.map(|c| &c.masks);
+        for key in configured_masks.into_iter().flat_map(|m| m.keys()) {
+            let (table, column) = key.split_once('.').unwrap_or((key, ""));
+            if !schema
+                .tables
+                .get(table)
+                .is_some_and(|t| t.columns.contains_key(column))
+            {
+                return Err(DataError::UnknownColumns {
+                    table: table.to_string(),
+                    columns: vec![column.to_string()],
+                });
+            }
+        }
+
+        // Parents first; tables in a reference cycle keep their order
+        let references = |name: &str| -> Vec<(String, String, String)> {
+            let table = &schema.tables[name];
+            let columns = table
+                .columns
+                .iter()
+                .filter_map(|(column, c)| Some((column.clone(), c.references.as_ref()?)));
+            let constraints =
+                table
+                    .constraints
+                    .iter()
+                    .flatten()
+                    .filter_map(|c| match &c.columns[..] {
+                        [column] => Some((column.clone(), c.references.as_ref()?)),
+                        _ => None,
+                    });
+            columns
+                .chain(constraints)
+                .filter(|(_, fk)| fk.table != name)
+                .map(|(column, fk)| (column, fk.table.clone(), fk.column.clone()))
+                .collect()
+        };
+        let mut ordered: Vec<&String> = Vec::new();
+        while !names.is_empty() {
+            let next = names
+                .iter()
+                .position(|name| {
+                    references(name)
+                        .iter()
+                        .all(|(_, parent, _)| !names.contains(&parent) || parent == *name)
+                })
+                .unwrap_or(0);
+            ordered.push(names.remove(next));
+        }
+
+        let mut tables = Vec::new();
+        let mut keys = HashMap::new();
+        for name in ordered {
+            let table = &schema.tables[name];
+            let settings = config.and_then(|c| c.tables.get(name));
+            let mut masks = Vec::new();
+            for (column_name, column) in &table.columns {
+                let rule = configured_masks
+                    .and_then(|m| m.get(&format!("{}.{}", name, column_name)))
+                    .or(column.mask.as_ref());
+                if rule == Some(&MaskRule::Null) && column.is_not_null() {
+                    return Err(DataError::InvalidMask {
+                        column: format!("{}.{}", name, column_name),
+                        reason: "the column is NOT NULL".to_string(),
+                    });
+                }
+                if rule == Some(&MaskRule::Hash) && !hashable(column) {
+                    return Err(DataError::InvalidMask {
+                        column: format!("{}.{}", name, column_name),
+                        reason: format!("hash does not apply to {} columns", column.data_type),
+                    });
+                }
+                if rule.is_some_and(|r| r.is_hashed()) && secret.is_none() {
+                    return Err(DataError::MissingMaskSecret(secret_env(config).to_string()));
+                }
+                masks.push(rule.map(|r| (r.clone(), column.clone())));
+            }
+            let parents: Vec<(usize, String, String)> = references(name)
+                .into_iter()
+                .filter(|(_, parent, _)| tables.iter().any(|t: &SampleTable| &t.name == parent))
+                .filter_map(|(column, parent, parent_column)| {
+                    Some((table.columns.get_index_of(&column)?, parent, parent_column))
+                })
+                .collect();
+            for (_, parent, parent_column) in &parents {
+                keys.insert((parent.clone(), parent_column.clone()), HashSet::new());
+            }
+            tables.push(SampleTable {
+                name: name.clone(),
+                columns: table.columns.keys().cloned().collect(),
+                limit: settings.and_then(|s| s.limit).unwrap_or(default_limit),
+                filter: settings.and_then(|s| s.filter.clone()),
+                masks,
+                parents,
+            });
+        }
+        Ok(Self {
+            tables,
+            keys,
+            secret: secret.unwrap_or_default().as_bytes().to_vec(),
+        })
+    }
+
+    /// The query reading table `index` from the source, once its parents are copied
+    pub fn select_sql(&self, index: usize) -> String {
+        let table = &self.tables[index];
+        let mut conditions: Vec<String> = table.filter.iter().map(|f| format!("({})", f)).collect();
+        for (column, parent, parent_column) in &table.parents {
+            let column = quote_ident(&table.columns[*column]);
+            let mut copied: Vec<String> = self.keys[&(parent.clone(), parent_column.clone())]
+                .iter()
+                .map(|k| quote_literal(k))
+                .collect();
+            copied.sort();
+            conditions.push(if copied.is_empty() {
+                format!("{} IS NULL", column)
+            } else {
+                format!(
+                    "({} IS NULL OR {}::text IN ({}))",
+                    column,
+                    column,
+                    copied.join(", ")
+                )
+            });
+        }
+        let select: Vec<String> = table
+            .columns
+            .iter()
+            .map(|c| format!("{}::text", quote_ident(c)))
+            .collect();
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            select.join(", "),
+            quote_ident(&table.name)
+        );
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        sql.push_str(&format!(" LIMIT {}", table.limit));
+        sql
+    }
+
+    /// Note a source row of table `index` as copied and mask it for the target
+    pub fn take(&mut self, index: usize, row: Vec<Option<String>>) -> Vec<Option<String>> {
+        let table = &self.tables[index];
+        for (column, value) in table.columns.iter().zip(&row) {
+            if let (Some(keys), Some(value)) = (
+                self.keys.get_mut(&(table.name.clone(), column.clone())),
+                value,
+            ) {
+                keys.insert(value.clone());
+            }
+        }
+        row.into_iter()
+            .zip(&table.masks)
+            .map(|(value, rule)| match (value, rule) {
+                (Some(value), Some((rule, column))) => mask(rule, &value, column, &self.secret),
+                (value, _) => value,
+            })
+            .collect()
+    }
+}
+
+/// Whether `hash` yields a valid value for the column: text, an integer or a UUID
+fn hashable(column: &Column) -> bool {
+    let (base, dims) = column.element_type();
+    dims == 0
+        && matches!(
+            base.as_str(),
+            "smallint"
+                | "int2"
+                | "smallserial"
+                | "integer"
+                | "int"
+                | "int4"
+                | "serial"
+                | "bigint"
+                | "int8"
+                | "bigserial"
+                | "uuid"
+                | "text"
+                | "citext"
+                | "varchar"
+                | "char"
+                | "character varying"
+                | "character"
+                | "bpchar"
+        )
+}
+
+/// Apply a masking rule to a non-NULL value; hashed rules derive their value
+/// from an HMAC-SHA256 of it under `secret`, so masks cannot be recomputed
+/// from guessed inputs without the key
+pub fn mask(rule: &MaskRule, value: &str, column: &Column, secret: &[u8]) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(value.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    let number = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+    let masked = match rule {
+        MaskRule::Null => return None,
+        MaskRule::Hash => match column.element_type().0.as_str() {
+            "smallint" | "int2" | "smallserial" => (number % i16::MAX as u64).to_string(),
+            "integer" | "int" | "int4" | "serial" => (number % i32::MAX as u64).to_string(),
+            "bigint" | "int8" | "bigserial" => (number % i64::MAX as u64).to_string(),
+            // Version 8 (custom) UUID from the first 16 bytes
+            "uuid" => format!(
+                "{}-{}-8{}-{:x}{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[13..16],
+                0x8 | (digest[8] >> 6),
+                &hex[17..20],
+                &hex[20..32]
+            ),
+            _ => hex,
+        },
+        MaskRule::Email => format!("user_{}@example.com", &hex[..10]),
+        MaskRule::Name => format!("Person {}", &hex[..8]),
+        MaskRule::Phone => format!("+1555{:07}", number % 10_000_000),
+        MaskRule::Redact => "*".repeat(value.chars().count()),
+        MaskRule::Fixed(fixed) => fixed.clone(),
+    };
+    Some(match column.size {
+        Some(size) => masked.chars().take(size).collect(),
+        None => masked,
+    })
+}
+
+/// Append a row to COPY text-format input
+pub fn write_copy_row(out: &mut Vec<u8>, row: &[Option<String>]) {
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            out.push(b'\t');
+        }
+        match value {
+            None => out.extend_from_slice(b"\\N"),
+            Some(value) => {
+                for c in value.bytes() {
+                    match c {
+                        b'\\' => out.extend_from_slice(b"\\\\"),
+                        b'\t' => out.extend_from_slice(b"\\t"),
+                        b'\n' => out.extend_from_slice(b"\\n"),
+                        b'\r' => out.extend_from_slice(b"\\r"),
+                        c => out.push(c),
+                    }
+                }
+            }
+        }
+    }
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isNotNull": true, "identity": {"always": false}},
+                "email": {"name": "email", "type": "varchar", "size": 12, "isNotNull": true},
+                "active": {"name": "active", "type": "boolean"},
+                "role": {"name": "role", "type": "user_role"},
+                "tags": {"name": "tags", "type": "text[]"},
+                "born": {"name": "born", "type": "date"}
+            }}}, "enums": {"user_role": ["admin", "member"]}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_csv_quotes_and_nulls() {
+        let records = parse_csv(
+            "\u{feff}email,active,tags\r\n\"a,b@x.io\",,\"\"\n\n\"multi\nline\",\"say \"\"hi\"\"\",x\n",
+        )
+        .unwrap();
+        assert_eq!(records.headers, vec!["email", "active", "tags"]);
+        assert_eq!(records.records.len(), 2);
+        assert_eq!(
+            records.records[0].values,
+            vec![Some("a,b@x.io".to_string()), None, Some(String::new())]
+        );
+        assert_eq!(records.records[1].line, 4);
+        assert_eq!(
+            records.records[1].values[..2],
+            [
+                Some("multi\nline".to_string()),
+                Some("say \"hi\"".to_string())
+            ]
+        );
+        assert!(matches!(
+            parse_csv("a\n\"open\n"),
+            Err(DataError::Syntax { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_json_arrays_and_lines() {
+        let lines = parse_json("{\"email\": \"a@x.io\"}\n\n{\"tags\": [\"a\"], \"email\": null}\n")
+            .unwrap();
+        assert_eq!(lines.headers, vec!["email", "tags"]);
+        assert_eq!(lines.records[1].line, 3);
+        assert_eq!(
+            lines.records[1].values,
+            vec![None, Some("[\"a\"]".to_string())]
+        );
+        let array = parse_json(r#"[{"email": "a@x.io", "active": true}]"#).unwrap();
+        let active = array.headers.iter().position(|h| h == "active").unwrap();
+        assert_eq!(array.records[0].values[active].as_deref(), Some("true"));
+        assert!(parse_json("[1]").is_err());
+    }
+
+    #[test]
+    fn test_prepare_checks_values_against_the_schema() {
+        let schema = schema();
+        let records = parse_csv(
+            "email,active,role,tags,born\n\
+             a@x.io,yes,admin,\"[\"\"a b\"\",null]\",2001-02-03\n\
+             b@x.io,maybe,admin,,\n\
+             ,true,member,,\n\
+             waytoolong@x.io,,,,\n\
+             c@x.io,,owner,,\n\
+             d@x.io,,,,2001-02-30\n\
+             e@x.io,1\n",
+        )
+        .unwrap();
+
+        let prepared = prepare(records, "users", &schema).unwrap();
+        assert_eq!(
+            prepared.rows,
+            vec![vec![
+                Some("a@x.io".to_string()),
+                Some("true".to_string()),
+                Some("admin".to_string()),
+                Some("{\"a b\",NULL}".to_string()),
+                Some("2001-02-03".to_string()),
+            ]]
+        );
+        let rejected: Vec<(usize, &str)> = prepared
+            .rejected
+            .iter()
+            .map(|r| (r.line, r.reason.as_str()))
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (3, "active: 'maybe' is not a valid boolean"),
+                (4, "email cannot be NULL"),
+                (5, "email: 'waytoolong@x.io' is longer than 12 characters"),
+                (6, "role: 'owner' is not one of admin, member"),
+                (7, "born: '2001-02-30' is not a valid date (YYYY-MM-DD)"),
+                (8, "expected 5 fields, found 2"),
+            ]
+        );
+
+        let unknown = prepare(parse_csv("email,nick\n").unwrap(), "users", &schema);
+        assert_eq!(
+            unknown.unwrap_err().to_string(),
+            "Table 'users' has no column(s) nick"
+        );
+        let missing = prepare(parse_csv("active\n").unwrap(), "users", &schema);
+        assert!(matches!(missing, Err(DataError::MissingColumns { .. })));
+        assert!(prepare(Records::default(), "ghosts", &schema).is_err());
+    }
+
+    #[test]
+    fn test_export_round_trips_through_the_loader() {
+        let schema = schema();
+        let csv = Export::new("users", &schema, DataFormat::Csv, Some("id > 1")).unwrap();
+        assert_eq!(
+            csv.sql,
+            "SELECT \"id\"::text, \"email\"::text, \"active\"::text, \"role\"::text, \
+             \"tags\"::text, \"born\"::text FROM \"users\" WHERE id > 1"
+        );
+        let row = vec![
+            Some("7".to_string()),
+            Some("a,\"b\"@x.io".to_string()),
+            Some("t".to_string()),
+            None,
+            Some("{a,\"b c\"}".to_string()),
+            Some(String::new()),
+        ];
+        let mut out = Vec::new();
+        csv.write_header(&mut out).unwrap();
+        csv.write_row(&mut out, &row).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "id,email,active,role,tags,born\n7,\"a,\"\"b\"\"@x.io\",t,,\"{a,\"\"b c\"\"}\",\"\"\n"
+        );
+        assert_eq!(parse_csv(&text).unwrap().records[0].values, row);
+
+        let json = Export::new("users", &schema, DataFormat::Json, None).unwrap();
+        assert!(json.sql.contains("to_json(\"tags\")::text"));
+        assert_eq!(json.copy_format(), None);
+
+        let binary = Export::new("users", &schema, DataFormat::Binary, None).unwrap();
+        assert_eq!(
+            binary.sql,
+            "SELECT \"id\", \"email\", \"active\", \"role\", \"tags\", \"born\" FROM \"users\""
+        );
+        assert_eq!(binary.copy_format(), Some(CopyFormat::Binary));
+        assert_eq!(
+            DataFormat::from_path(Path::new("users.pgcopy")).unwrap(),
+            DataFormat::Binary
+        );
+        let mut out = Vec::new();
+        json.write_header(&mut out).unwrap();
+        let row = vec![
+            Some("7".to_string()),
+            Some("a@x.io".to_string()),
+            Some("f".to_string()),
+            None,
+            Some("[\"a\",\"b c\"]".to_string()),
+            Some("2001-02-03".to_string()),
+        ];
+        json.write_row(&mut out, &row).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"id\":7,\"email\":\"a@x.io\",\"active\":false,\"role\":null,\
+             \"tags\":[\"a\",\"b c\"],\"born\":\"2001-02-03\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_sampler_copies_parents_first_and_masks() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {
+                "orders": {"columns": {
+                    "id": {"name": "id", "type": "integer"},
+                    "user_id": {"name": "user_id", "type": "integer",
+                                "references": {"table": "users", "column": "id"}}
+                }},
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "integer"},
+                    "email": {"name": "email", "type": "varchar", "size": 20, "mask": "email"},
+                    "phone": {"name": "phone", "type": "text", "isNotNull": true}
+                }}
+            }}"#,
+        )
+        .unwrap();
+        let config: SampleConfig = serde_json::from_str(
+            r#"{"limit": 5, "tables": {"orders": {"where": "id > 1"}, "users": {"limit": 2}},
+                "masks": {"users.phone": "phone"}}"#,
+        )
+        .unwrap();
+
+        let mut sampler = Sampler::new(&schema, Some(&config), Some("key")).unwrap();
+        let names: Vec<&str> = sampler.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["users", "orders"]);
+        assert_eq!(
+            sampler.select_sql(0),
+            "SELECT \"id\"::text, \"email\"::text, \"phone\"::text FROM \"users\" LIMIT 2"
+        );
+
+        let masked = sampler.take(
+            0,
+            vec![
+                Some("7".to_string()),
+                Some("ada@lovelace.io".to_string()),
+                Some("555-0100".to_string()),
+            ],
+        );
+        assert_eq!(masked[0].as_deref(), Some("7"));
+        let email = masked[1].as_deref().unwrap();
+        assert!(
+            email.starts_with("user_") && email.chars().count() == 20,
+            "{}",
+            email
+        );
+        assert!(masked[2].as_deref().unwrap().starts_with("+1555"));
+        // Equal inputs mask alike
+        let again = sampler.take(
+            0,
+            vec![
+                None,
+                Some("ada@lovelace.io".to_string()),
+                Some(String::new()),
+            ],
+        );
+        assert_eq!(again[1], masked[1]);
+        assert_eq!(again[0], None);
+
+        assert_eq!(
+            sampler.select_sql(1),
+            "SELECT \"id\"::text, \"user_id\"::text FROM \"orders\" WHERE (id > 1) \
+             AND (\"user_id\" IS NULL OR \"user_id\"::text IN ('7')) LIMIT 5"
+        );
+
+        // The key changes every hashed mask
+        let other = Sampler::new(&schema, Some(&config), Some("other"))
+            .unwrap()
+            .take(0, vec![None, Some("ada@lovelace.io".to_string()), None]);
+        assert_ne!(other[1], masked[1]);
+        assert!(matches!(
+            Sampler::new(&schema, Some(&config), None),
+            Err(DataError::MissingMaskSecret(env)) if env == MASK_SECRET_ENV
+        ));
+
+        let config: SampleConfig =
+            serde_json::from_str(r#"{"masks": {"users.phone": "null"}}"#).unwrap();
+        assert!(matches!(
+            Sampler::new(&schema, Some(&config), Some("key")),
+            Err(DataError::InvalidMask { .. })
+        ));
+        let config: SampleConfig =
+            serde_json::from_str(r#"{"masks": {"users.ssn": "hash"}}"#).unwrap();
+        assert!(Sampler::new(&schema, Some(&config), Some("key")).is_err());
+    }
+
+    #[test]
+    fn test_hash_masks_keep_the_column_type() {
+        let column = |type_: &str| -> Column {
+            serde_json::from_value(serde_json::json!({"name": "c", "type": type_})).unwrap()
+        };
+        let uuid = mask(&MaskRule::Hash, "ada", &column("uuid"), b"key").unwrap();
+        let parts: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(parts, vec![8, 4, 4, 4, 12]);
+        assert!(uuid.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        assert_eq!(&uuid[14..15], "8");
+        assert!("89ab".contains(&uuid[19..20]));
+        assert!(mask(&MaskRule::Hash, "ada", &column("int"), b"key")
+            .unwrap()
+            .parse::<i32>()
+            .is_ok());
+
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "born": {"name": "born", "type": "date", "mask": "hash"}
+            }}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Sampler::new(&schema, None, Some("key")),
+            Err(DataError::InvalidMask { reason, .. }) if reason.contains("date")
+        ));
+    }
+
+    #[test]
+    fn test_copy_rows_escape_text_format() {
+        let mut out = Vec::new();
+        write_copy_row(&mut out, &[Some("a\tb\\c\nd".to_string()), None]);
+        assert_eq!(out, b"a\\tb\\\\c\\nd\t\\N\n");
+    }
+}
@@ -1,11 +1,22 @@
 /**
  * Stratus Database Operations Module
  *
- * Handles database connections, schema introspection, DDL generation, and execution.
+ * Handles database connections, schema introspection and execution; the
+ * schema types and diffing are re-exported from the diff module.
  */
-use postgres::{Client, Config, NoTls};
-use serde::{Deserialize, Serialize};
+use crate::config::{ConnectionOptions, DatabaseProvider, SslConfig, SslMode};
+use crate::dialect::{Dialect, Postgres};
+use crate::migrate::{Migration, MigrationsTable};
+use indexmap::IndexMap;
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres::Client;
+use postgres_native_tls::MakeTlsConnector;
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, field, info_span};
 
 /// Database connection configuration
 #[derive(Debug, Clone)]
@@ -14,8 +25,32 @@ pub struct DbConfig {
     pub connection_string: String,
     /// Maximum pool size (for future connection pooling)
     pub max_connections: u32,
+    /// Database provider (selects the introspection backend)
+    pub provider: DatabaseProvider,
+    /// TLS settings
+    pub ssl: SslConfig,
+    /// Session settings applied after connecting
+    pub options: ConnectionOptions,
+    /// Directory caching introspected schemas, when enabled
+    pub introspection_cache: Option<PathBuf>,
 }
 
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+mod catalog;
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "mysql")]
+pub use self::mysql::MySqlClient;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteClient;
+
+pub use crate::diff::{
+    compare_schemas, generate_create_table_sql, print_diff_summary, DbColumn, DbConstraint,
+    DbForeignKey, DbGrant, DbIndex, DbSchema, DbTable, Operation, SchemaDiff,
+};
+
 /// Database connection result
 pub type DbResult<T> = Result<T, DbError>;
 
@@ -40,133 +75,731 @@ pub enum DbError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[error("SQL error: {0}")]
+    #[error("SQL error: {}", server_message(.0))]
     Sql(#[from] postgres::Error),
 }
 
-/// Table column definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbColumn {
-    pub name: String,
-    pub data_type: String,
-    pub is_nullable: bool,
-    pub is_primary_key: bool,
-    pub default_value: Option<String>,
-    pub size: Option<usize>,
+/// The error as the server reported it, with its DETAIL and HINT.
+///
+/// tokio-postgres displays every server error as just "db error".
+fn server_message(e: &postgres::Error) -> String {
+    let Some(db) = e.as_db_error() else {
+        return e.to_string();
+    };
+    let mut message = db.message().to_string();
+    if let Some(constraint) = db.constraint().filter(|c| !message.contains(c)) {
+        message.push_str(&format!(" (constraint {})", constraint));
+    }
+    if let Some(detail) = db.detail() {
+        message.push_str(&format!("\n  detail: {}", detail));
+    }
+    if let Some(hint) = db.hint() {
+        message.push_str(&format!("\n  hint: {}", hint));
+    }
+    message
 }
 
-/// Table definition from database
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbTable {
-    pub name: String,
-    pub columns: HashMap<String, DbColumn>,
-    pub primary_key: Vec<String>,
+fn query_error(e: postgres::Error) -> DbError {
+    DbError::Query(server_message(&e))
+}
+
+/// An I/O error of a COPY stream, which carries the server's error when the
+/// server ended the COPY
+fn copy_error(e: std::io::Error) -> DbError {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<postgres::Error>())
+    {
+        Some(server) => DbError::Query(server_message(server)),
+        None => DbError::Query(e.to_string()),
+    }
+}
+
+/// Build the TLS connector for a datasource's SSL settings.
+///
+/// Like libpq, `prefer` and `require` only verify the server when a root
+/// certificate is given; `verify-ca` skips the host name check.
+fn tls_connector(ssl: &SslConfig) -> DbResult<MakeTlsConnector> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|e| DbError::Connection(format!("{}: {}", path, e)))
+    };
+    let tls_error = |e: native_tls::Error| DbError::Connection(format!("TLS setup failed: {}", e));
+
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = &ssl.root_cert {
+        builder.add_root_certificate(Certificate::from_pem(&read(path)?).map_err(tls_error)?);
+    }
+    if let (Some(cert), Some(key)) = (&ssl.cert, &ssl.key) {
+        builder.identity(Identity::from_pkcs8(&read(cert)?, &read(key)?).map_err(tls_error)?);
+    }
+    match ssl.mode.unwrap_or_default() {
+        SslMode::VerifyFull => {}
+        SslMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        _ if ssl.root_cert.is_some() => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        _ => {
+            builder.danger_accept_invalid_certs(true);
+        }
+    }
+
+    let connector = builder.build().map_err(tls_error)?;
+    Ok(MakeTlsConnector::new(connector))
+}
+
+/// The database name and a digest of the public schema's catalog rows.
+///
+/// Catalog rows get a new `xmin` whenever DDL touches them, so the digest
+/// changes with any change to tables, columns, defaults, constraints, types or
+/// privileges; roles are shared by the cluster and counted by name.
+const CATALOG_VERSION_SQL: &str =
+    "SELECT current_database()::text, md5(coalesce(string_agg(v, ',' ORDER BY v), ''))
+     FROM (
+         SELECT 'c' || c.oid || ':' || c.xmin AS v FROM pg_class c
+         WHERE c.relnamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 'a' || a.attrelid || '.' || a.attnum || ':' || a.xmin FROM pg_attribute a
+         JOIN pg_class c ON c.oid = a.attrelid WHERE c.relnamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 'd' || d.oid || ':' || d.xmin FROM pg_attrdef d
+         JOIN pg_class c ON c.oid = d.adrelid WHERE c.relnamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 'k' || k.oid || ':' || k.xmin FROM pg_constraint k
+         WHERE k.connamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 't' || t.oid || ':' || t.xmin FROM pg_type t
+         WHERE t.typnamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 'e' || e.oid || ':' || e.xmin FROM pg_enum e
+         JOIN pg_type t ON t.oid = e.enumtypid WHERE t.typnamespace = 'public'::regnamespace
+         UNION ALL
+         SELECT 'r' || r.oid || ':' || r.rolname FROM pg_roles r
+     ) catalog";
+
+/// Version of the introspection cache format; bump it whenever [`DbSchema`]
+/// serializes differently, so entries written before are not read back
+const CACHE_FORMAT: u32 = 1;
+
+/// An introspected schema and the catalog version it was read at
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedSchema {
+    /// [`CACHE_FORMAT`] and the version of Stratus that wrote the entry
+    format: String,
+    version: String,
+    schema: DbSchema,
+}
+
+impl CachedSchema {
+    /// The format entries are written in by this build
+    fn format() -> String {
+        format!("{}/{}", CACHE_FORMAT, env!("CARGO_PKG_VERSION"))
+    }
+}
+
+/// Bytes read or written per round of a streamed COPY
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Data format of a COPY
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// Tab-separated Postgres text format, `\N` for NULL
+    Text,
+    /// CSV; with `header`, the first line holds the column names
+    Csv { header: bool },
+    /// Postgres binary format; values keep their column types
+    Binary,
+}
+
+impl CopyFormat {
+    fn options(self) -> &'static str {
+        match self {
+            Self::Text => "",
+            Self::Csv { header: false } => " (FORMAT csv)",
+            Self::Csv { header: true } => " (FORMAT csv, HEADER)",
+            Self::Binary => " (FORMAT binary)",
+        }
+    }
+}
+
+/// Rows and bytes through a COPY so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyCount {
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// Counts the rows of a COPY stream as its chunks go by
+struct RowCounter {
+    format: CopyFormat,
+    lines: u64,
+    /// CSV: inside a quoted field
+    quoted: bool,
+    binary: BinaryRows,
+}
+
+impl RowCounter {
+    fn new(format: CopyFormat) -> Self {
+        Self {
+            format,
+            lines: 0,
+            quoted: false,
+            binary: BinaryRows::default(),
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        match self.format {
+            // Newlines in values are escaped
+            CopyFormat::Text => self.lines += chunk.iter().filter(|b| **b == b'\n').count() as u64,
+            // Newlines in values are quoted, and `""` toggles twice
+            CopyFormat::Csv { .. } => {
+                for byte in chunk {
+                    match byte {
+                        b'"' => self.quoted = !self.quoted,
+                        b'\n' if !self.quoted => self.lines += 1,
+                        _ => {}
+                    }
+                }
+            }
+            CopyFormat::Binary => self.binary.feed(chunk),
+        }
+    }
+
+    fn rows(&self) -> u64 {
+        match self.format {
+            CopyFormat::Text | CopyFormat::Csv { header: false } => self.lines,
+            CopyFormat::Csv { header: true } => self.lines.saturating_sub(1),
+            CopyFormat::Binary => self.binary.rows,
+        }
+    }
 }
 
-/// Database schema
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbSchema {
-    pub tables: HashMap<String, DbTable>,
-    pub enums: HashMap<String, Vec<String>>,
-    pub dialect: String,
+/// Walks the binary COPY layout: an 11-byte signature, flags, a sized
+/// header extension, then tuples of a field count and sized fields, ending
+/// with a field count of -1
+struct BinaryRows {
+    rows: u64,
+    /// Bytes to pass over before the next number
+    skip: u64,
+    /// Bytes of the number being read
+    word: Vec<u8>,
+    stage: BinaryStage,
+    fields: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BinaryStage {
+    ExtensionLength,
+    FieldCount,
+    FieldLength,
+    Trailer,
+}
+
+impl Default for BinaryRows {
+    fn default() -> Self {
+        Self {
+            rows: 0,
+            skip: 15,
+            word: Vec::with_capacity(4),
+            stage: BinaryStage::ExtensionLength,
+            fields: 0,
+        }
+    }
+}
+
+impl BinaryRows {
+    fn feed(&mut self, mut chunk: &[u8]) {
+        while !chunk.is_empty() && self.stage != BinaryStage::Trailer {
+            if self.skip > 0 {
+                let passed = self.skip.min(chunk.len() as u64);
+                self.skip -= passed;
+                chunk = &chunk[passed as usize..];
+                continue;
+            }
+            let width = if self.stage == BinaryStage::FieldCount {
+                2
+            } else {
+                4
+            };
+            let taken = (width - self.word.len()).min(chunk.len());
+            self.word.extend_from_slice(&chunk[..taken]);
+            chunk = &chunk[taken..];
+            if self.word.len() < width {
+                break;
+            }
+            let value = match width {
+                2 => i16::from_be_bytes([self.word[0], self.word[1]]) as i32,
+                _ => i32::from_be_bytes([self.word[0], self.word[1], self.word[2], self.word[3]]),
+            };
+            self.word.clear();
+            match self.stage {
+                BinaryStage::ExtensionLength => {
+                    self.skip = value.max(0) as u64;
+                    self.stage = BinaryStage::FieldCount;
+                }
+                BinaryStage::FieldCount if value < 0 => self.stage = BinaryStage::Trailer,
+                BinaryStage::FieldCount if value == 0 => self.rows += 1,
+                BinaryStage::FieldCount => {
+                    self.fields = value;
+                    self.stage = BinaryStage::FieldLength;
+                }
+                BinaryStage::FieldLength => {
+                    // -1 is NULL, with no bytes following
+                    self.skip = value.max(0) as u64;
+                    self.fields -= 1;
+                    if self.fields == 0 {
+                        self.rows += 1;
+                        self.stage = BinaryStage::FieldCount;
+                    }
+                }
+                BinaryStage::Trailer => {}
+            }
+        }
+    }
 }
 
 /// Database client wrapper
 pub struct StratusClient {
     client: Client,
     connection_string: String,
+    introspection_cache: Option<PathBuf>,
 }
 
 impl StratusClient {
     /// Connect to database
     pub fn connect(config: &DbConfig) -> DbResult<Self> {
-        let client = Client::connect(&config.connection_string, NoTls)
-            .map_err(|e| DbError::Connection(e.to_string()))?;
+        if config.provider != DatabaseProvider::Postgresql {
+            return Err(DbError::Connection(format!(
+                "Provider '{}' is not supported by this command yet",
+                config.provider
+            )));
+        }
 
-        Ok(Self {
+        let mut pg_config: postgres::Config = config
+            .connection_string
+            .parse()
+            .map_err(|e: postgres::Error| DbError::Connection(e.to_string()))?;
+        if let Some(mode) = config.ssl.mode {
+            pg_config.ssl_mode(match mode {
+                SslMode::Disable => postgres::config::SslMode::Disable,
+                SslMode::Prefer => postgres::config::SslMode::Prefer,
+                SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => {
+                    postgres::config::SslMode::Require
+                }
+            });
+        }
+
+        let options = &config.options;
+        if let Some(secs) = options.connect_timeout {
+            pg_config.connect_timeout(std::time::Duration::from_secs(secs));
+        }
+        if let Some(name) = &options.application_name {
+            pg_config.application_name(name);
+        }
+
+        debug!(hosts = ?pg_config.get_hosts(), "connecting");
+        let client = pg_config
+            .connect(tls_connector(&config.ssl)?)
+            .map_err(|e| DbError::Connection(server_message(&e)))?;
+
+        let mut client = Self {
             client,
             connection_string: config.connection_string.clone(),
-        })
+            introspection_cache: config.introspection_cache.clone(),
+        };
+        client.apply_session_options(options)?;
+        Ok(client)
+    }
+
+    /// The SQL dialect of the connected database
+    pub fn dialect(&self) -> &'static dyn Dialect {
+        &Postgres
+    }
+
+    /// Apply per-session settings that have no connection parameter
+    fn apply_session_options(&mut self, options: &ConnectionOptions) -> DbResult<()> {
+        let mut sql = String::new();
+        if let Some(ms) = options.statement_timeout {
+            sql.push_str(&format!("SET statement_timeout = {};", ms));
+        }
+        if let Some(path) = &options.search_path {
+            let schemas: Vec<String> = path
+                .iter()
+                .map(|s| format!("\"{}\"", s.replace('"', "\"\"")))
+                .collect();
+            sql.push_str(&format!("SET search_path TO {};", schemas.join(", ")));
+        }
+        if !sql.is_empty() {
+            self.execute(&sql).map_err(|e| {
+                DbError::Connection(format!("Failed to apply session options: {}", e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Connection string this client was opened with
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
     }
 
     /// Test connection
     pub fn ping(&mut self) -> DbResult<()> {
-        self.client
-            .simple_query("SELECT 1")
-            .map_err(|e| DbError::Query(e.to_string()))?;
+        debug!(target: "stratus::sql", "SELECT 1");
+        self.client.simple_query("SELECT 1").map_err(query_error)?;
         Ok(())
     }
 
     /// Execute DDL statement
     pub fn execute(&mut self, sql: &str) -> DbResult<()> {
-        self.client
-            .batch_execute(sql)
-            .map_err(|e| DbError::Query(e.to_string()))?;
+        debug!(target: "stratus::sql", "{}", sql);
+        self.client.batch_execute(sql).map_err(query_error)?;
         Ok(())
     }
 
     /// Execute query and return results
     pub fn query(&mut self, sql: &str) -> DbResult<Vec<HashMap<String, String>>> {
-        let rows = self
-            .client
-            .query(sql, &[])
-            .map_err(|e| DbError::Query(e.to_string()))?;
+        debug!(target: "stratus::sql", "{}", sql);
+        let rows = self.client.query(sql, &[]).map_err(query_error)?;
 
-        let mut results = Vec::new();
-        for row in &rows {
-            let mut map = HashMap::new();
-            for (i, col) in row.columns().iter().enumerate() {
-                let value: Option<String> = row.get(i);
-                map.insert(
-                    col.name().to_string(),
-                    value.unwrap_or_else(|| "NULL".to_string()),
-                );
+        Ok(rows.iter().map(row_to_map).collect())
+    }
+
+    /// Execute query and hand its rows to `on_row` one at a time, fetching
+    /// `fetch_size` rows per round trip; returns how many rows were handed over
+    ///
+    /// Only one batch is held in memory, so results of any size can be
+    /// exported. Returning `ControlFlow::Break` stops early.
+    pub fn query_each(
+        &mut self,
+        sql: &str,
+        fetch_size: usize,
+        mut on_row: impl FnMut(HashMap<String, String>) -> ControlFlow<()>,
+    ) -> DbResult<u64> {
+        self.each_row(sql, fetch_size, |row| on_row(row_to_map(row)))
+    }
+
+    /// Like `query_each`, but rows are values in column order with NULL as
+    /// `None`; every column must be text, so cast others with `::text`
+    pub fn query_each_values(
+        &mut self,
+        sql: &str,
+        fetch_size: usize,
+        mut on_row: impl FnMut(Vec<Option<String>>) -> ControlFlow<()>,
+    ) -> DbResult<u64> {
+        self.each_row(sql, fetch_size, |row| {
+            on_row((0..row.len()).map(|i| row.get(i)).collect())
+        })
+    }
+
+    fn each_row(
+        &mut self,
+        sql: &str,
+        fetch_size: usize,
+        mut on_row: impl FnMut(&postgres::Row) -> ControlFlow<()>,
+    ) -> DbResult<u64> {
+        debug!(target: "stratus::sql", fetch_size, "{}", sql);
+        let fetch_size = fetch_size.clamp(1, i32::MAX as usize);
+        let mut transaction = self.client.transaction().map_err(query_error)?;
+        let portal = transaction.bind(sql, &[]).map_err(query_error)?;
+
+        let mut count = 0;
+        'fetch: loop {
+            let rows = transaction
+                .query_portal(&portal, fetch_size as i32)
+                .map_err(query_error)?;
+            for row in &rows {
+                count += 1;
+                if on_row(row).is_break() {
+                    break 'fetch;
+                }
+            }
+            if rows.len() < fetch_size {
+                break;
+            }
+        }
+
+        transaction.commit().map_err(query_error)?;
+        Ok(count)
+    }
+
+    /// The `COPY {table} ({columns}) FROM STDIN` statement for `format`
+    fn copy_in_sql(&self, table: &str, columns: &[String], format: CopyFormat) -> String {
+        let dialect = self.dialect();
+        format!(
+            "COPY {} ({}) FROM STDIN{}",
+            dialect.quote_ident(table),
+            columns
+                .iter()
+                .map(|c| dialect.quote_ident(c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            format.options()
+        )
+    }
+
+    /// Load rows into a table with `COPY ... FROM STDIN`, one COPY per
+    /// `batch_size` rows, all in one transaction; returns the rows copied
+    ///
+    /// Values are text in Postgres input syntax; `None` is NULL. `progress`
+    /// is called with the running total after each batch.
+    pub fn copy_rows(
+        &mut self,
+        table: &str,
+        columns: &[String],
+        rows: &[Vec<Option<String>>],
+        batch_size: usize,
+        mut progress: impl FnMut(CopyCount),
+    ) -> DbResult<u64> {
+        let sql = self.copy_in_sql(table, columns, CopyFormat::Text);
+        debug!(target: "stratus::sql", rows = rows.len(), "{}", sql);
+        let mut transaction = self.client.transaction().map_err(query_error)?;
+
+        let mut count = CopyCount::default();
+        let mut buffer = Vec::new();
+        for batch in rows.chunks(batch_size.max(1)) {
+            buffer.clear();
+            for row in batch {
+                crate::data::write_copy_row(&mut buffer, row);
             }
-            results.push(map);
+            let mut writer = transaction.copy_in(&sql).map_err(query_error)?;
+            writer.write_all(&buffer).map_err(copy_error)?;
+            count.rows += writer.finish().map_err(query_error)?;
+            count.bytes += buffer.len() as u64;
+            progress(count);
         }
 
-        Ok(results)
+        transaction.commit().map_err(query_error)?;
+        Ok(count.rows)
+    }
+
+    /// Stream `input`, already in `format`, into a table with one
+    /// `COPY ... FROM STDIN`; returns the rows and bytes copied
+    ///
+    /// Nothing is validated on the way: the server rejects the whole COPY on
+    /// the first bad row. `progress` is called after each chunk sent.
+    pub fn copy_in(
+        &mut self,
+        table: &str,
+        columns: &[String],
+        format: CopyFormat,
+        input: &mut impl Read,
+        mut progress: impl FnMut(CopyCount),
+    ) -> DbResult<CopyCount> {
+        let sql = self.copy_in_sql(table, columns, format);
+        debug!(target: "stratus::sql", "{}", sql);
+        let mut writer = self.client.copy_in(&sql).map_err(query_error)?;
+
+        let mut rows = RowCounter::new(format);
+        let mut bytes = 0;
+        let mut chunk = vec![0; COPY_CHUNK];
+        loop {
+            let read = input.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&chunk[..read]).map_err(copy_error)?;
+            bytes += read as u64;
+            rows.feed(&chunk[..read]);
+            progress(CopyCount {
+                rows: rows.rows(),
+                bytes,
+            });
+        }
+        let rows = writer.finish().map_err(query_error)?;
+        Ok(CopyCount { rows, bytes })
+    }
+
+    /// Stream the rows of `query` to `out` in `format` with
+    /// `COPY (query) TO STDOUT`; returns the rows and bytes written
+    ///
+    /// `progress` is called after each chunk written.
+    pub fn copy_out(
+        &mut self,
+        query: &str,
+        format: CopyFormat,
+        out: &mut impl Write,
+        mut progress: impl FnMut(CopyCount),
+    ) -> DbResult<CopyCount> {
+        let sql = format!("COPY ({}) TO STDOUT{}", query, format.options());
+        debug!(target: "stratus::sql", "{}", sql);
+        let mut reader = self.client.copy_out(&sql).map_err(query_error)?;
+
+        let mut rows = RowCounter::new(format);
+        let mut bytes = 0;
+        let mut chunk = vec![0; COPY_CHUNK];
+        loop {
+            let read = reader.read(&mut chunk).map_err(copy_error)?;
+            if read == 0 {
+                break;
+            }
+            out.write_all(&chunk[..read])?;
+            bytes += read as u64;
+            rows.feed(&chunk[..read]);
+            progress(CopyCount {
+                rows: rows.rows(),
+                bytes,
+            });
+        }
+        Ok(CopyCount {
+            rows: rows.rows(),
+            bytes,
+        })
     }
 
     /// Get all tables
+    ///
+    /// With an introspection cache, a schema introspected earlier is reused
+    /// while the catalog version is unchanged and the entry was written in
+    /// this build's cache format; otherwise see `introspect`.
     pub fn get_schema(&mut self) -> DbResult<DbSchema> {
-        let mut tables = HashMap::new();
-        let mut enums = HashMap::new();
+        let Some(dir) = self.introspection_cache.clone() else {
+            return self.introspect();
+        };
+        let rows = self.query_rows(CATALOG_VERSION_SQL, &[])?;
+        let database: String = rows[0].get(0);
+        let version: String = rows[0].get(1);
+        let format = CachedSchema::format();
+        let key = crate::migrate::calculate_checksum(&format!(
+            "{}\n{}\n{}",
+            format, self.connection_string, database
+        ));
+        let path = dir.join(format!(
+            "introspect-{}.json",
+            &key.trim_start_matches("sha256:")[..16]
+        ));
+
+        if let Some(cached) = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<CachedSchema>(&json).ok())
+            .filter(|cached| cached.format == format && cached.version == version)
+        {
+            debug!(path = %path.display(), "introspection cache hit");
+            return Ok(cached.schema);
+        }
+        let schema = self.introspect()?;
+        // Only a speed-up: failing to write it costs the next run an introspection
+        let cached = CachedSchema {
+            format,
+            version,
+            schema,
+        };
+        if let Ok(json) = serde_json::to_string(&cached) {
+            let _ = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, json));
+        }
+        Ok(cached.schema)
+    }
 
-        // Get tables
-        let rows = self.client.query(
-            "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public' ORDER BY table_name",
-            &[]
-        ).map_err(|e| DbError::Query(e.to_string()))?;
+    /// Introspect the public schema
+    ///
+    /// A fixed handful of set-based catalog queries, assembled in memory, so
+    /// introspection costs the same few round trips however many tables there are.
+    fn introspect(&mut self) -> DbResult<DbSchema> {
+        let span = info_span!(
+            "introspect",
+            tables = field::Empty,
+            enums = field::Empty,
+            elapsed_ms = field::Empty
+        );
+        let _guard = span.enter();
+        let started = Instant::now();
+        let mut tables = IndexMap::new();
+        let mut enums = IndexMap::new();
+
+        let queries = self.dialect().introspection();
 
+        // Get tables
+        let rows = self.query_rows(queries.tables, &[])?;
         for row in &rows {
             let table_name: String = row.get(0);
-            let columns = self.get_table_columns(&table_name)?;
-            let primary_key = self.get_primary_key(&table_name)?;
-
             tables.insert(
                 table_name.clone(),
                 DbTable {
-                    name: table_name.clone(),
-                    columns,
-                    primary_key,
+                    name: table_name,
+                    columns: IndexMap::new(),
+                    primary_key: Vec::new(),
+                    foreign_keys: Vec::new(),
+                    constraints: Vec::new(),
+                    indexes: Vec::new(),
                 },
             );
         }
 
+        // Get columns of every table
+        let rows = self.query_rows(queries.columns, &[])?;
+        for row in &rows {
+            let table_name: String = row.get(0);
+            let Some(table) = tables.get_mut(&table_name) else {
+                continue;
+            };
+            let name: String = row.get(1);
+            let is_nullable: String = row.get(3);
+            let size: Option<i32> = row.get(5);
+            table.columns.insert(
+                name.clone(),
+                DbColumn {
+                    name,
+                    data_type: row.get(2),
+                    is_nullable: is_nullable == "YES",
+                    is_primary_key: false, // Set from the primary keys below
+                    default_value: row.get(4),
+                    size: size.map(|s| s as usize),
+                },
+            );
+        }
+
+        // Get primary key columns of every table
+        let rows = self.query_rows(queries.primary_keys, &[])?;
+        for row in &rows {
+            let table_name: String = row.get(0);
+            let Some(table) = tables.get_mut(&table_name) else {
+                continue;
+            };
+            let name: String = row.get(1);
+            if let Some(column) = table.columns.get_mut(&name) {
+                column.is_primary_key = true;
+            }
+            table.primary_key.push(name);
+        }
+
+        // Get foreign keys of every table
+        for (table_name, foreign_keys) in self.get_foreign_keys()? {
+            if let Some(table) = tables.get_mut(&table_name) {
+                table.foreign_keys = foreign_keys;
+            }
+        }
+
+        // Get CHECK, UNIQUE and EXCLUDE constraints of every table
+        let rows = match queries.constraints {
+            Some(sql) => self.query_rows(sql, &[])?,
+            None => Vec::new(),
+        };
+        for row in &rows {
+            let table_name: String = row.get(1);
+            let Some(table) = tables.get_mut(&table_name) else {
+                continue;
+            };
+            table.constraints.extend(DbConstraint::from_catalog(
+                row.get(0),
+                row.get(2),
+                row.get(3),
+                row.get(4),
+            ));
+        }
+
+        // Get indexes of every table, once its keys and constraints are known
+        for (table_name, indexes) in self.get_indexes()? {
+            if let Some(table) = tables.get_mut(&table_name) {
+                table.set_indexes(indexes);
+            }
+        }
+
         // Get enums
-        let enum_rows = self
-            .client
-            .query(
-                "SELECT t.typname, e.enumlabel 
-             FROM pg_type t 
-             JOIN pg_enum e ON t.oid = e.enumtypid 
-             JOIN pg_namespace n ON n.oid = t.typnamespace 
-             WHERE n.nspname = 'public'
-             ORDER BY t.typname, e.enumlabel",
-                &[],
-            )
-            .map_err(|e| DbError::Query(e.to_string()))?;
+        let enum_rows = match queries.enums {
+            Some(sql) => self.query_rows(sql, &[])?,
+            None => Vec::new(),
+        };
 
         let mut current_enum = String::new();
         let mut enum_values = Vec::new();
@@ -189,578 +822,502 @@ impl StratusClient {
             enums.insert(current_enum, enum_values);
         }
 
+        // Get roles and the privileges they hold
+        let roles = match queries.roles {
+            Some(sql) => self
+                .query_rows(sql, &[])?
+                .iter()
+                .map(|row| row.get(0))
+                .collect(),
+            None => Vec::new(),
+        };
+        let grants = match queries.grants {
+            Some(sql) => self
+                .query_rows(sql, &[])?
+                .iter()
+                .map(|row| DbGrant {
+                    role: row.get(0),
+                    table: row.get(1),
+                    column: row.get(2),
+                    privilege: row.get(3),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        span.record("tables", tables.len());
+        span.record("enums", enums.len());
+        span.record("elapsed_ms", started.elapsed().as_millis() as u64);
         Ok(DbSchema {
             tables,
             enums,
-            dialect: "postgresql".to_string(),
+            dialect: self.dialect().name().to_string(),
+            roles,
+            grants,
         })
     }
 
-    /// Get columns for a table
-    fn get_table_columns(&mut self, table_name: &str) -> DbResult<HashMap<String, DbColumn>> {
-        let rows = self.client.query(
-            "SELECT column_name, data_type, is_nullable, column_default, character_maximum_length
-             FROM information_schema.columns 
-             WHERE table_name = $1 AND table_schema = 'public'
-             ORDER BY ordinal_position",
-            &[&table_name]
-        ).map_err(|e| DbError::Query(e.to_string()))?;
+    /// Run an introspection query with parameters
+    fn query_rows(
+        &mut self,
+        sql: &str,
+        params: &[&(dyn postgres::types::ToSql + Sync)],
+    ) -> DbResult<Vec<postgres::Row>> {
+        debug!(target: "stratus::sql", "{}", sql);
+        self.client.query(sql, params).map_err(query_error)
+    }
+
+    /// Get indexes, keyed by table
+    pub fn get_indexes(&mut self) -> DbResult<HashMap<String, Vec<DbIndex>>> {
+        let rows = self.query_rows(self.dialect().introspection().indexes, &[])?;
 
-        let mut columns = HashMap::new();
+        let mut indexes: HashMap<String, Vec<DbIndex>> = HashMap::new();
         for row in &rows {
-            let name: String = row.get(0);
-            let data_type: String = row.get(1);
-            let is_nullable: String = row.get(2);
-            let default_value: Option<String> = row.get(3);
-            let size: Option<i32> = row.get(4);
-
-            columns.insert(
-                name.clone(),
-                DbColumn {
-                    name,
-                    data_type,
-                    is_nullable: is_nullable == "YES",
-                    is_primary_key: false, // Will be updated separately
-                    default_value,
-                    size: size.map(|s| s as usize),
-                },
-            );
+            let definition: String = row.get(2);
+            indexes.entry(row.get(0)).or_default().push(DbIndex {
+                name: row.get(1),
+                unique: definition.starts_with("CREATE UNIQUE"),
+                definition,
+            });
         }
 
-        Ok(columns)
-    }
-
-    /// Get primary key columns
-    fn get_primary_key(&mut self, table_name: &str) -> DbResult<Vec<String>> {
-        let rows = self
-            .client
-            .query(
-                "SELECT a.attname
-             FROM pg_index i
-             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
-             JOIN pg_class c ON c.oid = i.indrelid
-             JOIN pg_namespace n ON n.oid = c.relnamespace
-             WHERE i.indisprimary
-             AND c.relname = $1
-             AND n.nspname = 'public'
-             ORDER BY a.attnum",
-                &[&table_name],
-            )
-            .map_err(|e| DbError::Query(e.to_string()))?;
+        Ok(indexes)
+    }
+
+    /// Get foreign keys, keyed by referencing table
+    pub fn get_foreign_keys(&mut self) -> DbResult<HashMap<String, Vec<DbForeignKey>>> {
+        let rows = self.query_rows(self.dialect().introspection().foreign_keys, &[])?;
 
-        let mut pk = Vec::new();
+        let mut foreign_keys: HashMap<String, Vec<DbForeignKey>> = HashMap::new();
         for row in &rows {
-            let name: String = row.get(0);
-            pk.push(name);
+            foreign_keys
+                .entry(row.get(1))
+                .or_default()
+                .push(DbForeignKey {
+                    name: row.get(0),
+                    references_table: row.get(2),
+                    columns: row.get(3),
+                    references_columns: row.get(4),
+                    on_delete: crate::diff::referential_action(row.get(5)),
+                    on_update: crate::diff::referential_action(row.get(6)),
+                    deferrable: row.get(7),
+                    initially_deferred: row.get(8),
+                });
         }
 
-        Ok(pk)
+        Ok(foreign_keys)
+    }
+
+    /// Fetch up to `limit` rows of a table as JSON objects, keeping column types
+    pub fn preview_rows(&mut self, table: &str, limit: i64) -> DbResult<Vec<serde_json::Value>> {
+        let sql = format!(
+            "SELECT row_to_json(t)::text FROM {} t LIMIT $1",
+            self.dialect().quote_ident(table)
+        );
+        self.query_rows(&sql, &[&limit])?
+            .iter()
+            .map(|row| serde_json::from_str(row.get(0)).map_err(|e| DbError::Query(e.to_string())))
+            .collect()
+    }
+
+    /// Run EXPLAIN on a statement and return the plan lines.
+    ///
+    /// With `analyze` the statement really runs, inside a transaction that is
+    /// rolled back so writes are not kept.
+    pub fn explain(&mut self, sql: &str, analyze: bool) -> DbResult<Vec<String>> {
+        let options = if analyze { "ANALYZE, BUFFERS" } else { "COSTS" };
+        let sql = format!("EXPLAIN ({}) {}", options, sql.trim().trim_end_matches(';'));
+
+        self.begin()?;
+        let rows = self.query_rows(&sql, &[]);
+        self.rollback()?;
+        Ok(rows?.iter().map(|row| row.get(0)).collect())
     }
 
     /// Begin transaction
     pub fn begin(&mut self) -> DbResult<()> {
         self.execute("BEGIN")
-            .map_err(|e| DbError::Query(e.to_string()))?;
-        Ok(())
     }
 
     /// Commit transaction
     pub fn commit(&mut self) -> DbResult<()> {
         self.execute("COMMIT")
-            .map_err(|e| DbError::Query(e.to_string()))?;
-        Ok(())
     }
 
     /// Rollback transaction
     pub fn rollback(&mut self) -> DbResult<()> {
         self.execute("ROLLBACK")
-            .map_err(|e| DbError::Query(e.to_string()))?;
-        Ok(())
     }
 }
 
-/// Result of schema comparison
-#[derive(Debug, Default)]
-pub struct SchemaDiff {
-    pub create_tables: Vec<String>,
-    pub alter_tables: Vec<String>,
-    pub drop_tables: Vec<String>,
-    pub create_columns: HashMap<String, Vec<DbColumn>>,
-    pub alter_columns: HashMap<String, Vec<DbColumn>>,
-    pub drop_columns: HashMap<String, Vec<String>>,
-    pub create_enums: Vec<String>,
-    pub drop_enums: Vec<String>,
-    pub data_loss_warning: Vec<String>,
-    pub sql: String,
+/// A row of the migrations table
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    pub id: String,
+    pub name: String,
+    pub checksum: Option<String>,
+    pub applied_at: String,
+    /// How long the migration took to apply; None for rows recorded before
+    /// durations were
+    pub duration_ms: Option<u64>,
 }
 
-impl SchemaDiff {
-    pub fn has_changes(&self) -> bool {
-        !self.create_tables.is_empty()
-            || !self.alter_tables.is_empty()
-            || !self.drop_tables.is_empty()
-            || !self.create_columns.is_empty()
-            || !self.alter_columns.is_empty()
-            || !self.drop_columns.is_empty()
-    }
-
-    /// Calculate checksum of the SQL for deduplication
-    pub fn checksum(&self) -> String {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(&self.sql);
-        format!("sha256:{:x}", hasher.finalize())
-    }
+/// What sync, deploy and db push need from a database.
+///
+/// Implemented by [`StratusClient`], `MySqlClient` and `SqliteClient`, and by [`MemoryBackend`]
+/// so command logic can be tested without a server.
+pub trait DbBackend {
+    /// Introspect the current schema
+    fn get_schema(&mut self) -> DbResult<DbSchema>;
+    /// Execute one statement (or a batch without results)
+    fn execute(&mut self, sql: &str) -> DbResult<()>;
+    fn begin(&mut self) -> DbResult<()>;
+    fn commit(&mut self) -> DbResult<()>;
+    fn rollback(&mut self) -> DbResult<()>;
+    /// Create the migrations table (and its schema) if missing
+    fn create_migrations_table(&mut self, table: &MigrationsTable) -> DbResult<()>;
+    fn migrations_table_exists(&mut self, table: &MigrationsTable) -> DbResult<bool>;
+    /// Rows of the migrations table, ordered by ID
+    fn applied_migrations(&mut self, table: &MigrationsTable) -> DbResult<Vec<AppliedMigration>>;
+    /// Add a row to the migrations table; no duration for a migration
+    /// marked applied without running it
+    fn insert_applied(
+        &mut self,
+        table: &MigrationsTable,
+        migration: &Migration,
+        duration: Option<Duration>,
+    ) -> DbResult<()>;
+    /// Remove a migration's row from the migrations table, if it has one
+    fn delete_applied(&mut self, table: &MigrationsTable, id: &str) -> DbResult<()>;
+    /// Take the lock serializing migration runs; blocks while another process holds it
+    fn lock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()>;
+    fn unlock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()>;
 }
 
-/// Generate SQL DDL from JSON schema
-pub fn generate_create_table_sql(
-    table_name: &str,
-    table: &crate::schema::Table,
-    dialect: &str,
-) -> String {
-    let mut sql = format!("CREATE TABLE {} (\n", table_name);
-
-    let mut first = true;
-
-    // Primary key first
-    let pk_cols: Vec<String> = table
-        .columns
-        .iter()
-        .filter(|(_, c)| c.is_primary_key())
-        .map(|(name, _)| name.clone())
-        .collect();
-
-    if !pk_cols.is_empty() {
-        sql.push_str(&format!("  PRIMARY KEY ({})\n", pk_cols.join(", ")));
-        first = false;
-    }
-
-    // Other columns
-    for (col_name, col) in &table.columns {
-        if col.is_primary_key() {
-            continue;
-        }
-
-        if !first {
-            sql.push_str(",\n");
-        }
-        first = false;
-
-        sql.push_str(&format!("  {}", col_name));
-        sql.push_str(&format!(" {}", map_type_to_sql(&col.data_type, col.size)));
-
-        if !col.is_not_null() {
-            sql.push_str(" NULL");
-        } else {
-            sql.push_str(" NOT NULL");
-        }
-
-        if let Some(default) = &col.default {
-            sql.push_str(&format!(" DEFAULT {}", default));
-        }
+/// `duration_ms` column value, NULL without a duration
+fn duration_ms(duration: Option<Duration>) -> String {
+    duration.map_or_else(|| "NULL".to_string(), |d| d.as_millis().to_string())
+}
 
-        if col.generated.is_some() {
-            sql.push_str(" GENERATED ALWAYS AS IDENTITY");
-        }
+/// Add `duration_ms` to a migrations table created before durations were recorded
+fn upgrade_migrations_table(client: &mut dyn DbBackend, qualified_name: &str) -> DbResult<()> {
+    let probe = format!("SELECT duration_ms FROM {} WHERE 1 = 0", qualified_name);
+    if client.execute(&probe).is_ok() {
+        return Ok(());
     }
+    client.execute(&format!(
+        "ALTER TABLE {} ADD COLUMN duration_ms BIGINT",
+        qualified_name
+    ))
+}
 
-    sql.push_str("\n)");
-
-    // Table options
-    if let Some(opts) = &table.options.fillfactor {
-        sql.push_str(&format!(" WITH (fillfactor = {})", opts));
+/// Connect to a datasource through the client for its provider
+pub fn connect(config: &DbConfig) -> DbResult<Box<dyn DbBackend>> {
+    match config.provider {
+        #[cfg(feature = "mysql")]
+        DatabaseProvider::Mysql => Ok(Box::new(MySqlClient::connect(config)?)),
+        #[cfg(feature = "sqlite")]
+        DatabaseProvider::Sqlite => Ok(Box::new(SqliteClient::connect(config)?)),
+        _ => Ok(Box::new(StratusClient::connect(config)?)),
     }
-
-    sql.push_str(";");
-
-    sql
 }
 
-/// Map JSON schema type to SQL type
-fn map_type_to_sql(schema_type: &str, size: Option<usize>) -> String {
-    match schema_type {
-        "varchar" | "char" => {
-            if let Some(s) = size {
-                format!("VARCHAR({})", s)
-            } else {
-                "VARCHAR(255)".to_string()
-            }
-        }
-        "decimal" => "DECIMAL(10, 2)".to_string(),
-        "bigint" => "BIGINT".to_string(),
-        "integer" => "INTEGER".to_string(),
-        "smallint" => "SMALLINT".to_string(),
-        "float" | "double" => "DOUBLE PRECISION".to_string(),
-        "boolean" => "BOOLEAN".to_string(),
-        "date" => "DATE".to_string(),
-        "timestamp" | "timestamptz" => "TIMESTAMP WITH TIME ZONE".to_string(),
-        "json" => "JSON".to_string(),
-        "jsonb" => "JSONB".to_string(),
-        "text" => "TEXT".to_string(),
-        "uuid" => "UUID".to_string(),
-        "bytea" => "BYTEA".to_string(),
-        _ => schema_type.to_string(),
+impl DbBackend for StratusClient {
+    fn get_schema(&mut self) -> DbResult<DbSchema> {
+        StratusClient::get_schema(self)
     }
-}
 
-/// Compare JSON schema with database schema
-pub fn compare_schemas(json_schema: &crate::schema::Schema, db_schema: &DbSchema) -> SchemaDiff {
-    let mut diff = SchemaDiff::default();
+    fn execute(&mut self, sql: &str) -> DbResult<()> {
+        StratusClient::execute(self, sql)
+    }
 
-    // Find tables to create
-    for (table_name, table) in &json_schema.tables {
-        if !db_schema.tables.contains_key(table_name) {
-            diff.create_tables.push(table_name.clone());
-        }
+    fn begin(&mut self) -> DbResult<()> {
+        StratusClient::begin(self)
     }
 
-    // Find tables to drop
-    for (table_name, _) in &db_schema.tables {
-        if !json_schema.tables.contains_key(table_name) {
-            diff.drop_tables.push(table_name.clone());
-            diff.data_loss_warning.push(format!(
-                "Table '{}' will be dropped with all data",
-                table_name
-            ));
-        }
+    fn commit(&mut self) -> DbResult<()> {
+        StratusClient::commit(self)
     }
 
-    // Find columns to add
-    for (table_name, json_table) in &json_schema.tables {
-        if let Some(db_table) = db_schema.tables.get(table_name) {
-            for (col_name, json_col) in &json_table.columns {
-                if !db_table.columns.contains_key(col_name) {
-                    diff.create_columns
-                        .entry(table_name.clone())
-                        .or_insert_with(Vec::new)
-                        .push(DbColumn {
-                            name: col_name.clone(),
-                            data_type: json_col.data_type.clone(),
-                            is_nullable: !json_col.is_not_null(),
-                            is_primary_key: json_col.is_primary_key(),
-                            default_value: json_col.default.clone(),
-                            size: json_col.size,
-                        });
-                }
-            }
-        }
+    fn rollback(&mut self) -> DbResult<()> {
+        StratusClient::rollback(self)
     }
 
-    // Find columns to drop
-    for (table_name, db_table) in &db_schema.tables {
-        if let Some(json_table) = json_schema.tables.get(table_name) {
-            for (col_name, _) in &db_table.columns {
-                if !json_table.columns.contains_key(col_name) {
-                    diff.drop_columns
-                        .entry(table_name.clone())
-                        .or_insert_with(Vec::new)
-                        .push(col_name.clone());
-                    diff.data_loss_warning.push(format!(
-                        "Column '{}.{}' will be dropped",
-                        table_name, col_name
-                    ));
-                }
-            }
-        }
+    fn create_migrations_table(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let sql = self
+            .dialect()
+            .create_migrations_table(table.schema.as_deref(), &table.qualified_name());
+        StratusClient::execute(self, &sql)?;
+        upgrade_migrations_table(self, &table.qualified_name())
     }
 
-    // Generate SQL
-    let mut sql = String::new();
+    fn migrations_table_exists(&mut self, table: &MigrationsTable) -> DbResult<bool> {
+        let rows = self.query(&self.dialect().table_exists(&table.qualified_name()))?;
+        Ok(rows
+            .first()
+            .and_then(|row| row.get("name"))
+            .is_some_and(|name| name != "NULL"))
+    }
 
-    // Drop columns first
-    for (table, columns) in &diff.drop_columns {
-        for col in columns {
-            sql.push_str(&format!(
-                "ALTER TABLE {} DROP COLUMN IF EXISTS {};\n",
-                table, col
-            ));
-        }
+    fn applied_migrations(&mut self, table: &MigrationsTable) -> DbResult<Vec<AppliedMigration>> {
+        let rows = self.query(&format!(
+            "SELECT id, name, checksum, applied_at::text AS applied_at,
+                    duration_ms::text AS duration_ms
+             FROM {} ORDER BY id",
+            table.qualified_name()
+        ))?;
+        Ok(rows
+            .into_iter()
+            .map(|mut row| AppliedMigration {
+                id: row.remove("id").unwrap_or_default(),
+                name: row.remove("name").unwrap_or_default(),
+                checksum: row.remove("checksum").filter(|c| c != "NULL"),
+                applied_at: row.remove("applied_at").unwrap_or_default(),
+                duration_ms: row.remove("duration_ms").and_then(|d| d.parse().ok()),
+            })
+            .collect())
     }
 
-    // Drop tables
-    for table in &diff.drop_tables {
-        sql.push_str(&format!("DROP TABLE IF EXISTS {} CASCADE;\n", table));
+    fn insert_applied(
+        &mut self,
+        table: &MigrationsTable,
+        migration: &Migration,
+        duration: Option<Duration>,
+    ) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = format!(
+            "INSERT INTO {} (id, name, checksum, duration_ms) VALUES ({}, {}, {}, {})",
+            table.qualified_name(),
+            dialect.quote_literal(&migration.meta.id),
+            dialect.quote_literal(&migration.meta.name),
+            dialect.quote_literal(migration.checksum()),
+            duration_ms(duration)
+        );
+        StratusClient::execute(self, &sql)
     }
 
-    // Create tables
-    for table_name in &diff.create_tables {
-        if let Some(table) = json_schema.tables.get(table_name) {
-            sql.push_str(&format!("\n-- Create table {}\n", table_name));
-            sql.push_str(&generate_create_table_sql(table_name, table, "postgresql"));
-            sql.push('\n');
-        }
+    fn delete_applied(&mut self, table: &MigrationsTable, id: &str) -> DbResult<()> {
+        let sql = format!(
+            "DELETE FROM {} WHERE id = {}",
+            table.qualified_name(),
+            self.dialect().quote_literal(id)
+        );
+        StratusClient::execute(self, &sql)
     }
 
-    // Add columns
-    for (table, columns) in &diff.create_columns {
-        for col in columns {
-            sql.push_str(&format!(
-                "ALTER TABLE {} ADD COLUMN {} {} {};\n",
-                table,
-                col.name,
-                map_type_to_sql(&col.data_type, col.size),
-                if col.is_nullable { "NULL" } else { "NOT NULL" }
-            ));
-        }
+    fn lock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let sql = self.dialect().lock(&table.qualified_name());
+        StratusClient::execute(self, &sql)
     }
 
-    diff.sql = sql;
-    diff
+    fn unlock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let sql = self.dialect().unlock(&table.qualified_name());
+        StratusClient::execute(self, &sql)
+    }
 }
 
-/// Print schema diff summary
-pub fn print_diff_summary(diff: &SchemaDiff) {
-    println!();
-    println!("Schema diff summary:");
-    println!("{}", "=".repeat(60));
+/// An in-memory database for testing command logic: a fixed schema, a log of
+/// executed statements, and a migrations table, all with transactions
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    /// What introspection returns
+    pub schema: DbSchema,
+    /// Statements executed outside a transaction or committed, in order
+    pub executed: Vec<String>,
+    /// The migrations table, once created; one table whatever its name
+    pub migrations: Option<Vec<AppliedMigration>>,
+    /// Statements containing this text fail
+    pub fail_on: Option<String>,
+    /// Current migration lock depth
+    pub locks: usize,
+    saved: Option<(usize, Option<Vec<AppliedMigration>>)>,
+}
 
-    if !diff.create_tables.is_empty() {
-        println!("\nTables to CREATE ({}):", diff.create_tables.len());
-        for table in &diff.create_tables {
-            println!("  + {}", table);
+impl MemoryBackend {
+    pub fn new(schema: DbSchema) -> Self {
+        Self {
+            schema,
+            ..Self::default()
         }
     }
 
-    if !diff.alter_tables.is_empty() {
-        println!("\nTables to ALTER ({}):", diff.alter_tables.len());
-        for table in &diff.alter_tables {
-            println!("  ~ {}", table);
-        }
+    /// Whether a transaction is open
+    pub fn in_transaction(&self) -> bool {
+        self.saved.is_some()
     }
 
-    if !diff.drop_tables.is_empty() {
-        println!("\nTables to DROP ({}):", diff.drop_tables.len());
-        for table in &diff.drop_tables {
-            println!("  - {}", table);
-        }
+    fn migrations_table(&mut self) -> DbResult<&mut Vec<AppliedMigration>> {
+        self.migrations
+            .as_mut()
+            .ok_or_else(|| DbError::Query("migrations table does not exist".to_string()))
     }
+}
 
-    if !diff.create_columns.is_empty() {
-        println!("\nColumns to ADD ({} tables):", diff.create_columns.len());
-        for (table, columns) in &diff.create_columns {
-            for col in columns {
-                println!("  + {}.{}", table, col.name);
-            }
-        }
+impl DbBackend for MemoryBackend {
+    fn get_schema(&mut self) -> DbResult<DbSchema> {
+        Ok(self.schema.clone())
     }
 
-    if !diff.drop_columns.is_empty() {
-        println!("\nColumns to DROP ({} tables):", diff.drop_columns.len());
-        for (table, columns) in &diff.drop_columns {
-            for col in columns {
-                println!("  - {}.{}", table, col);
-            }
+    fn execute(&mut self, sql: &str) -> DbResult<()> {
+        if let Some(pattern) = self.fail_on.as_deref().filter(|p| sql.contains(p)) {
+            return Err(DbError::Query(format!("statement contains {}", pattern)));
         }
+        self.executed.push(sql.to_string());
+        Ok(())
     }
 
-    if !diff.data_loss_warning.is_empty() {
-        println!("\n⚠️  WARNING - Data loss may occur:");
-        for warning in &diff.data_loss_warning {
-            println!("  ! {}", warning);
+    fn begin(&mut self) -> DbResult<()> {
+        if self.saved.is_some() {
+            return Err(DbError::Query("transaction already open".to_string()));
         }
+        self.saved = Some((self.executed.len(), self.migrations.clone()));
+        Ok(())
     }
 
-    if !diff.has_changes() {
-        println!("\n✓ Schemas are in sync - no changes needed.");
-    } else if !diff.data_loss_warning.is_empty() {
-        println!("\n⚠️  Some changes may cause data loss.");
-        println!("Use --accept-data-loss flag to proceed.");
+    fn commit(&mut self) -> DbResult<()> {
+        self.saved
+            .take()
+            .map(|_| ())
+            .ok_or_else(|| DbError::Query("no transaction to commit".to_string()))
     }
 
-    println!();
-}
-
-impl DbSchema {
-    /// Convert DbSchema to JSON schema format
-    pub fn to_json_schema(&self) -> crate::schema::Schema {
-        let mut tables = std::collections::HashMap::new();
-
-        for (table_name, db_table) in &self.tables {
-            let mut columns = std::collections::HashMap::new();
-
-            for (col_name, db_col) in &db_table.columns {
-                columns.insert(
-                    col_name.clone(),
-                    crate::schema::Column {
-                        column_name: db_col.name.clone(),
-                        data_type: db_col.data_type.clone(),
-                        size: db_col.size,
-                        array_dimensions: None,
-                        is_primary_key: db_col.is_primary_key,
-                        is_not_null: !db_col.is_nullable,
-                        is_unique: false,
-                        default: db_col.default_value.clone(),
-                        identity: None,
-                        generated: None,
-                        collation: None,
-                        storage: None,
-                        statistics: None,
-                        attributes: crate::schema::ColumnAttributes::default(),
-                        references: None,
-                    },
-                );
-            }
+    fn rollback(&mut self) -> DbResult<()> {
+        let (executed, migrations) = self
+            .saved
+            .take()
+            .ok_or_else(|| DbError::Query("no transaction to roll back".to_string()))?;
+        self.executed.truncate(executed);
+        self.migrations = migrations;
+        Ok(())
+    }
 
-            tables.insert(
-                table_name.clone(),
-                crate::schema::Table {
-                    comment: None,
-                    columns,
-                    indexes: None,
-                    constraints: None,
-                    options: crate::schema::TableOptions::default(),
-                    partitions: Vec::new(),
-                    inherits: Vec::new(),
-                },
-            );
-        }
+    fn create_migrations_table(&mut self, _table: &MigrationsTable) -> DbResult<()> {
+        self.migrations.get_or_insert_with(Vec::new);
+        Ok(())
+    }
 
-        crate::schema::Schema {
-            version: Some("1".to_string()),
-            dialect: Some(self.dialect.clone()),
-            comment: None,
-            tables,
-            enums: Some(self.enums.clone()),
-        }
+    fn migrations_table_exists(&mut self, _table: &MigrationsTable) -> DbResult<bool> {
+        Ok(self.migrations.is_some())
     }
-}
 
-impl SchemaDiff {
-    /// Generate rollback SQL for the changes
-    pub fn generate_rollback(&self) -> String {
-        let mut sql = String::new();
+    fn applied_migrations(&mut self, _table: &MigrationsTable) -> DbResult<Vec<AppliedMigration>> {
+        let mut rows = self.migrations_table()?.clone();
+        rows.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(rows)
+    }
 
-        // Reverse the operations (inverse order)
-        for table in &self.create_tables {
-            sql.push_str(&format!("DROP TABLE IF EXISTS {} CASCADE;\n", table));
+    fn insert_applied(
+        &mut self,
+        _table: &MigrationsTable,
+        migration: &Migration,
+        duration: Option<Duration>,
+    ) -> DbResult<()> {
+        let rows = self.migrations_table()?;
+        if rows.iter().any(|row| row.id == migration.meta.id) {
+            return Err(DbError::Query(format!(
+                "duplicate migration id {}",
+                migration.meta.id
+            )));
         }
+        rows.push(AppliedMigration {
+            id: migration.meta.id.clone(),
+            name: migration.meta.name.clone(),
+            checksum: Some(migration.checksum().to_string()),
+            applied_at: format!("applied #{}", rows.len() + 1),
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+        });
+        Ok(())
+    }
 
-        for (table, columns) in &self.create_columns {
-            for col in columns {
-                sql.push_str(&format!(
-                    "ALTER TABLE {} DROP COLUMN IF EXISTS {};\n",
-                    table, col.name
-                ));
-            }
-        }
+    fn delete_applied(&mut self, _table: &MigrationsTable, id: &str) -> DbResult<()> {
+        self.migrations_table()?.retain(|row| row.id != id);
+        Ok(())
+    }
 
-        for table in &self.drop_tables {
-            sql.push_str(&format!(
-                "-- Recreate table {} (you may need to restore from backup)\n",
-                table
-            ));
-            sql.push_str("-- This is a placeholder - manual intervention may be required\n");
-        }
+    fn lock_migrations(&mut self, _table: &MigrationsTable) -> DbResult<()> {
+        self.locks += 1;
+        Ok(())
+    }
 
-        sql
+    fn unlock_migrations(&mut self, _table: &MigrationsTable) -> DbResult<()> {
+        self.locks = self.locks.saturating_sub(1);
+        Ok(())
     }
 }
 
+/// A row as column name to text, with NULL spelled out
+fn row_to_map(row: &postgres::Row) -> HashMap<String, String> {
+    row.columns()
+        .iter()
+        .enumerate()
+        .map(|(i, col)| {
+            let value: Option<String> = row.get(i);
+            (
+                col.name().to_string(),
+                value.unwrap_or_else(|| "NULL".to_string()),
+            )
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_db_column_serialization() {
-        let column = DbColumn {
-            name: "id".to_string(),
-            data_type: "bigint".to_string(),
-            is_nullable: false,
-            is_primary_key: true,
-            default_value: None,
-            size: None,
+    fn test_db_config() {
+        let config = DbConfig {
+            connection_string: "postgresql://localhost/test".to_string(),
+            max_connections: 5,
+            provider: DatabaseProvider::Postgresql,
+            ssl: SslConfig::default(),
+            options: ConnectionOptions::default(),
+            introspection_cache: None,
         };
-
-        let json = serde_json::to_string(&column).unwrap();
-        assert!(json.contains("id"));
-        assert!(json.contains("bigint"));
+        assert_eq!(config.max_connections, 5);
+        assert!(config.connection_string.contains("localhost"));
     }
 
     #[test]
-    fn test_db_table_serialization() {
-        let mut columns = std::collections::HashMap::new();
-        columns.insert(
-            "id".to_string(),
-            DbColumn {
-                name: "id".to_string(),
-                data_type: "bigint".to_string(),
-                is_nullable: false,
-                is_primary_key: true,
-                default_value: None,
-                size: None,
-            },
-        );
-
-        let table = DbTable {
-            name: "users".to_string(),
-            columns,
-            primary_key: vec!["id".to_string()],
+    fn test_copy_rows_are_counted_across_chunks() {
+        let count = |format: CopyFormat, data: &[u8]| {
+            // One byte at a time, so every number straddles chunks
+            let mut counter = RowCounter::new(format);
+            for byte in data {
+                counter.feed(std::slice::from_ref(byte));
+            }
+            counter.rows()
         };
-
-        let json = serde_json::to_string(&table).unwrap();
-        assert!(json.contains("users"));
-        assert!(json.contains("id"));
-    }
-
-    #[test]
-    fn test_db_schema_serialization() {
-        let mut tables = std::collections::HashMap::new();
-        tables.insert(
-            "users".to_string(),
-            DbTable {
-                name: "users".to_string(),
-                columns: std::collections::HashMap::new(),
-                primary_key: vec![],
-            },
+        assert_eq!(count(CopyFormat::Text, b"1\ta\\nb\n2\t\\N\n"), 2);
+        assert_eq!(
+            count(
+                CopyFormat::Csv { header: true },
+                b"id,note\n1,\"two\nlines\"\n2,\"say \"\"hi\"\"\"\n"
+            ),
+            2
         );
 
-        let mut enums = std::collections::HashMap::new();
-        enums.insert(
-            "user_status".to_string(),
-            vec!["active".to_string(), "inactive".to_string()],
+        let mut binary = b"PGCOPY\n\xff\r\n\0".to_vec();
+        binary.extend_from_slice(&0i32.to_be_bytes());
+        binary.extend_from_slice(&2i32.to_be_bytes());
+        binary.extend_from_slice(b"ex");
+        for value in [Some(&b"abc"[..]), None] {
+            binary.extend_from_slice(&2i16.to_be_bytes());
+            binary.extend_from_slice(&4i32.to_be_bytes());
+            binary.extend_from_slice(&7i32.to_be_bytes());
+            match value {
+                Some(v) => {
+                    binary.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                    binary.extend_from_slice(v);
+                }
+                None => binary.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        binary.extend_from_slice(&(-1i16).to_be_bytes());
+        assert_eq!(count(CopyFormat::Binary, &binary), 2);
+        assert_eq!(
+            CopyFormat::Csv { header: true }.options(),
+            " (FORMAT csv, HEADER)"
         );
-
-        let schema = DbSchema {
-            tables,
-            enums,
-            dialect: "postgresql".to_string(),
-        };
-
-        let json = serde_json::to_string(&schema).unwrap();
-        assert!(json.contains("postgresql"));
-        assert!(json.contains("users"));
-    }
-
-    #[test]
-    fn test_schema_diff_has_changes_empty() {
-        let diff = SchemaDiff::default();
-        assert!(!diff.has_changes());
-    }
-
-    #[test]
-    fn test_schema_diff_has_changes_with_tables() {
-        let mut diff = SchemaDiff::default();
-        diff.create_tables.push("users".to_string());
-        assert!(diff.has_changes());
-    }
-
-    #[test]
-    fn test_schema_diff_has_changes_with_columns() {
-        let mut diff = SchemaDiff::default();
-        let mut columns_map = std::collections::HashMap::new();
-        columns_map.insert("users".to_string(), vec![]);
-        diff.create_columns = columns_map;
-        assert!(diff.has_changes());
-    }
-
-    #[test]
-    fn test_db_config() {
-        let config = DbConfig {
-            connection_string: "postgresql://localhost/test".to_string(),
-            max_connections: 5,
-        };
-        assert_eq!(config.max_connections, 5);
-        assert!(config.connection_string.contains("localhost"));
     }
 }
@@ -0,0 +1,306 @@
+/**
+ * Catalog Rows
+ *
+ * Builds schemas, indexes, foreign keys and constraints from the rows of the
+ * dialect's introspection queries, read as text. Shared by the backends whose
+ * driver returns untyped rows (MySQL, SQLite); the PostgreSQL client reads its
+ * own.
+ */
+use super::{DbColumn, DbConstraint, DbForeignKey, DbGrant, DbIndex, DbSchema, DbTable};
+use crate::dialect::Dialect;
+use crate::diff::referential_action;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// A row of text values; `None` is NULL
+pub(super) type TextRow = Vec<Option<String>>;
+
+/// The first `N` values of a row, NULL as empty text
+fn text_columns<const N: usize>(row: TextRow) -> [String; N] {
+    let mut values = row.into_iter().map(Option::unwrap_or_default);
+    std::array::from_fn(|_| values.next().unwrap_or_default())
+}
+
+/// The rows of each introspection query; none for a query the dialect lacks
+#[derive(Default)]
+pub(super) struct CatalogRows {
+    pub tables: Vec<TextRow>,
+    pub columns: Vec<TextRow>,
+    pub primary_keys: Vec<TextRow>,
+    pub foreign_keys: Vec<TextRow>,
+    pub constraints: Vec<TextRow>,
+    pub indexes: Vec<TextRow>,
+    pub roles: Vec<TextRow>,
+    pub grants: Vec<TextRow>,
+}
+
+/// Assemble a schema from the rows of the introspection queries
+pub(super) fn build_schema(dialect: &dyn Dialect, rows: CatalogRows) -> DbSchema {
+    let CatalogRows {
+        tables,
+        columns,
+        primary_keys,
+        foreign_keys,
+        constraints,
+        indexes,
+        roles,
+        grants,
+    } = rows;
+    let mut schema_tables: IndexMap<String, DbTable> = tables
+        .into_iter()
+        .map(|row| {
+            let [name] = text_columns(row);
+            let table = DbTable {
+                name: name.clone(),
+                columns: IndexMap::new(),
+                primary_key: Vec::new(),
+                foreign_keys: Vec::new(),
+                constraints: Vec::new(),
+                indexes: Vec::new(),
+            };
+            (name, table)
+        })
+        .collect();
+
+    for row in columns {
+        let mut values = row.into_iter();
+        let mut next = || values.next().flatten();
+        let (Some(table_name), Some(name)) = (next(), next()) else {
+            continue;
+        };
+        let Some(table) = schema_tables.get_mut(&table_name) else {
+            continue;
+        };
+        let data_type = next().unwrap_or_default();
+        let is_nullable = next().as_deref() == Some("YES");
+        let default_value = next();
+        let size = next().and_then(|s| s.parse().ok());
+        table.columns.insert(
+            name.clone(),
+            DbColumn {
+                name,
+                data_type,
+                is_nullable,
+                is_primary_key: false, // Set from the primary keys below
+                default_value,
+                size,
+            },
+        );
+    }
+
+    for row in primary_keys {
+        let [table_name, name] = text_columns(row);
+        let Some(table) = schema_tables.get_mut(&table_name) else {
+            continue;
+        };
+        if let Some(column) = table.columns.get_mut(&name) {
+            column.is_primary_key = true;
+        }
+        table.primary_key.push(name);
+    }
+
+    for (table_name, table_foreign_keys) in self::foreign_keys(foreign_keys) {
+        if let Some(table) = schema_tables.get_mut(&table_name) {
+            table.foreign_keys = table_foreign_keys;
+        }
+    }
+
+    for row in constraints {
+        let [name, table_name, constraint_type, columns, definition] = text_columns(row);
+        let Some(table) = schema_tables.get_mut(&table_name) else {
+            continue;
+        };
+        let columns = columns
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(str::to_string)
+            .collect();
+        table.constraints.extend(DbConstraint::from_catalog(
+            name,
+            &constraint_type,
+            columns,
+            &definition,
+        ));
+    }
+
+    // Once the keys and constraints the indexes may be behind are known
+    for (table_name, table_indexes) in self::indexes(indexes) {
+        if let Some(table) = schema_tables.get_mut(&table_name) {
+            table.set_indexes(table_indexes);
+        }
+    }
+
+    DbSchema {
+        tables: schema_tables,
+        enums: IndexMap::new(),
+        dialect: dialect.name().to_string(),
+        roles: roles
+            .into_iter()
+            .map(|row| text_columns::<1>(row)[0].clone())
+            .collect(),
+        grants: grants
+            .into_iter()
+            .map(|row| {
+                let mut values = row.into_iter();
+                let mut next = || values.next().flatten();
+                DbGrant {
+                    role: next().unwrap_or_default(),
+                    table: next().unwrap_or_default(),
+                    column: next(),
+                    privilege: next().unwrap_or_default(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Index rows (table, name, definition), keyed by table
+pub(super) fn indexes(rows: Vec<TextRow>) -> HashMap<String, Vec<DbIndex>> {
+    let mut indexes: HashMap<String, Vec<DbIndex>> = HashMap::new();
+    for row in rows {
+        let [table, name, definition] = text_columns(row);
+        indexes.entry(table).or_default().push(DbIndex {
+            name,
+            unique: definition.starts_with("CREATE UNIQUE"),
+            definition,
+        });
+    }
+    indexes
+}
+
+/// Foreign key rows with comma-separated column lists and the delete and
+/// update rules, keyed by referencing table; neither MySQL nor SQLite
+/// constraints are read as deferrable
+pub(super) fn foreign_keys(rows: Vec<TextRow>) -> HashMap<String, Vec<DbForeignKey>> {
+    let mut foreign_keys: HashMap<String, Vec<DbForeignKey>> = HashMap::new();
+    for row in rows {
+        let [name, table, references_table, columns, references_columns, on_delete, on_update] =
+            text_columns(row);
+        foreign_keys.entry(table).or_default().push(DbForeignKey {
+            name,
+            columns: columns.split(',').map(str::to_string).collect(),
+            references_table,
+            references_columns: references_columns.split(',').map(str::to_string).collect(),
+            on_delete: referential_action(&on_delete),
+            on_update: referential_action(&on_update),
+            deferrable: false,
+            initially_deferred: false,
+        });
+    }
+    foreign_keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[Option<&str>]) -> TextRow {
+        values.iter().map(|v| v.map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_build_schema_from_catalog_rows() {
+        let rows = CatalogRows {
+            tables: vec![row(&[Some("users")])],
+            columns: vec![
+                row(&[
+                    Some("users"),
+                    Some("id"),
+                    Some("bigint"),
+                    Some("NO"),
+                    None,
+                    None,
+                ]),
+                row(&[
+                    Some("users"),
+                    Some("email"),
+                    Some("varchar"),
+                    Some("YES"),
+                    Some("x@y.z"),
+                    Some("120"),
+                ]),
+                row(&[
+                    Some("gone"),
+                    Some("id"),
+                    Some("int"),
+                    Some("NO"),
+                    None,
+                    None,
+                ]),
+            ],
+            primary_keys: vec![row(&[Some("users"), Some("id")])],
+            foreign_keys: vec![row(&[
+                Some("users_manager_id_fkey"),
+                Some("users"),
+                Some("users"),
+                Some("manager_id"),
+                Some("id"),
+                Some("SET NULL"),
+                Some("NO ACTION"),
+            ])],
+            constraints: vec![
+                row(&[
+                    Some("users_email_key"),
+                    Some("users"),
+                    Some("UNIQUE"),
+                    Some("email"),
+                    None,
+                ]),
+                row(&[
+                    Some("users_id_check"),
+                    Some("users"),
+                    Some("CHECK"),
+                    None,
+                    Some("(`id` > 0)"),
+                ]),
+            ],
+            indexes: vec![
+                row(&[
+                    Some("users"),
+                    Some("users_email_key"),
+                    Some("CREATE UNIQUE INDEX `users_email_key` ON `users` (`email`)"),
+                ]),
+                row(&[
+                    Some("users"),
+                    Some("users_manager_id_fkey"),
+                    Some("CREATE INDEX `users_manager_id_fkey` ON `users` (`manager_id`)"),
+                ]),
+                row(&[
+                    Some("users"),
+                    Some("idx_users_email_id"),
+                    Some("CREATE INDEX `idx_users_email_id` ON `users` (`email`, `id`)"),
+                ]),
+            ],
+            roles: vec![row(&[Some("reporting")])],
+            grants: vec![row(&[
+                Some("reporting"),
+                Some("users"),
+                None,
+                Some("SELECT"),
+            ])],
+        };
+        let schema = build_schema(&crate::dialect::Mysql, rows);
+
+        assert_eq!(schema.dialect, "mysql");
+        let users = &schema.tables["users"];
+        assert_eq!(users.primary_key, vec!["id"]);
+        assert!(users.columns["id"].is_primary_key);
+        let email = &users.columns["email"];
+        assert!(email.is_nullable);
+        assert_eq!(email.size, Some(120));
+        assert_eq!(email.default_value.as_deref(), Some("x@y.z"));
+        assert!(!schema.tables.contains_key("gone"));
+        let manager = &users.foreign_keys[0];
+        assert_eq!(manager.columns, vec!["manager_id"]);
+        assert_eq!(manager.on_delete.as_deref(), Some("SET NULL"));
+        assert_eq!(manager.on_update, None);
+        assert_eq!(users.constraints[0].columns, vec!["email"]);
+        assert_eq!(users.constraints[1].definition(), "CHECK (`id` > 0)");
+        // The indexes behind the unique constraint and the foreign key are theirs
+        assert_eq!(users.indexes.len(), 1);
+        assert_eq!(users.indexes[0].name, "idx_users_email_id");
+        assert_eq!(users.indexes[0].columns(), vec!["email", "id"]);
+        assert_eq!(schema.roles, vec!["reporting"]);
+        assert_eq!(schema.grants[0].column, None);
+    }
+}
@@ -0,0 +1,298 @@
+/**
+ * Stratus MySQL Backend
+ *
+ * A client for MySQL 8 and MariaDB that introspects through
+ * information_schema and runs migrations, so sync, deploy and db push work
+ * against MySQL datasources. Data commands (load, export, sample, studio)
+ * remain PostgreSQL-only.
+ */
+use super::catalog::{self, TextRow};
+use super::{AppliedMigration, DbBackend, DbConfig, DbError, DbResult};
+use crate::config::SslMode;
+use crate::dialect::{Dialect, Mysql};
+use crate::diff::{DbForeignKey, DbIndex, DbSchema};
+use crate::migrate::{Migration, MigrationsTable};
+use mysql::prelude::Queryable;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+
+/// MySQL database client
+pub struct MySqlClient {
+    conn: mysql::Conn,
+    connection_string: String,
+}
+
+impl MySqlClient {
+    /// Connect to a `mysql://` URL
+    ///
+    /// TLS follows the datasource's `sslmode` as for PostgreSQL: `prefer`
+    /// tries TLS without verifying the certificate and falls back to a plain
+    /// connection when the server does not support it. Client certificates
+    /// are not supported.
+    pub fn connect(config: &DbConfig) -> DbResult<Self> {
+        let ssl = &config.ssl;
+        let mode = ssl.mode.unwrap_or_default();
+        let ssl_opts = match mode {
+            SslMode::Disable => None,
+            mode => {
+                let mut ssl_opts = mysql::SslOpts::default()
+                    .with_danger_accept_invalid_certs(
+                        matches!(mode, SslMode::Prefer | SslMode::Require)
+                            && ssl.root_cert.is_none(),
+                    )
+                    .with_danger_skip_domain_validation(mode != SslMode::VerifyFull);
+                if let Some(path) = &ssl.root_cert {
+                    ssl_opts = ssl_opts.with_root_cert_path(Some(std::path::PathBuf::from(path)));
+                }
+                Some(ssl_opts)
+            }
+        };
+
+        match Self::open(config, ssl_opts) {
+            Err(mysql::Error::DriverError(mysql::DriverError::TlsNotSupported))
+                if mode == SslMode::Prefer =>
+            {
+                debug!("server does not support TLS, connecting without it");
+                Self::open(config, None)
+            }
+            result => result,
+        }
+        .map_err(|e| DbError::Connection(e.to_string()))
+    }
+
+    fn open(config: &DbConfig, ssl_opts: Option<mysql::SslOpts>) -> mysql::Result<Self> {
+        let opts = mysql::Opts::from_url(&config.connection_string)?;
+
+        let options = &config.options;
+        let mut init = Vec::new();
+        if let Some(ms) = options.statement_timeout {
+            init.push(format!("SET SESSION max_execution_time = {}", ms));
+        }
+        let builder = mysql::OptsBuilder::from_opts(opts)
+            .ssl_opts(ssl_opts)
+            .tcp_connect_timeout(options.connect_timeout.map(Duration::from_secs))
+            .init(init);
+
+        debug!("connecting");
+        let conn = mysql::Conn::new(builder)?;
+        Ok(Self {
+            conn,
+            connection_string: config.connection_string.clone(),
+        })
+    }
+
+    /// The SQL dialect of the connected database
+    pub fn dialect(&self) -> &'static dyn Dialect {
+        &Mysql
+    }
+
+    /// Connection string this client was opened with
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
+    }
+
+    /// Test connection
+    pub fn ping(&mut self) -> DbResult<()> {
+        self.execute("SELECT 1")
+    }
+
+    /// Execute statements without results
+    pub fn execute(&mut self, sql: &str) -> DbResult<()> {
+        debug!(target: "stratus::sql", "{}", sql);
+        self.conn
+            .query_drop(sql)
+            .map_err(|e| DbError::Query(e.to_string()))
+    }
+
+    /// Execute query and return results, with NULL as `"NULL"`
+    pub fn query(&mut self, sql: &str) -> DbResult<Vec<HashMap<String, String>>> {
+        debug!(target: "stratus::sql", "{}", sql);
+        let rows: Vec<mysql::Row> = self
+            .conn
+            .query(sql)
+            .map_err(|e| DbError::Query(e.to_string()))?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let names: Vec<String> = row
+                    .columns_ref()
+                    .iter()
+                    .map(|c| c.name_str().into_owned())
+                    .collect();
+                names
+                    .into_iter()
+                    .zip(row.unwrap().into_iter().map(value_text))
+                    .map(|(name, value)| (name, value.unwrap_or_else(|| "NULL".to_string())))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Run a catalog query, with every value as text
+    fn query_text(&mut self, sql: &str) -> DbResult<Vec<TextRow>> {
+        debug!(target: "stratus::sql", "{}", sql);
+        let rows: Vec<mysql::Row> = self
+            .conn
+            .query(sql)
+            .map_err(|e| DbError::Query(e.to_string()))?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.unwrap().into_iter().map(value_text).collect())
+            .collect())
+    }
+
+    /// Introspect the current database
+    pub fn get_schema(&mut self) -> DbResult<DbSchema> {
+        let queries = self.dialect().introspection();
+        let optional = |client: &mut Self, sql: Option<&str>| match sql {
+            Some(sql) => client.query_text(sql),
+            None => Ok(Vec::new()),
+        };
+        let rows = catalog::CatalogRows {
+            tables: self.query_text(queries.tables)?,
+            columns: self.query_text(queries.columns)?,
+            primary_keys: self.query_text(queries.primary_keys)?,
+            foreign_keys: self.query_text(queries.foreign_keys)?,
+            constraints: optional(self, queries.constraints)?,
+            indexes: self.query_text(queries.indexes)?,
+            roles: optional(self, queries.roles)?,
+            grants: optional(self, queries.grants)?,
+        };
+        Ok(catalog::build_schema(&Mysql, rows))
+    }
+
+    /// Get indexes, keyed by table
+    pub fn get_indexes(&mut self) -> DbResult<HashMap<String, Vec<DbIndex>>> {
+        let rows = self.query_text(self.dialect().introspection().indexes)?;
+        Ok(catalog::indexes(rows))
+    }
+
+    /// Get foreign keys, keyed by referencing table
+    pub fn get_foreign_keys(&mut self) -> DbResult<HashMap<String, Vec<DbForeignKey>>> {
+        let rows = self.query_text(self.dialect().introspection().foreign_keys)?;
+        Ok(catalog::foreign_keys(rows))
+    }
+}
+
+impl DbBackend for MySqlClient {
+    fn get_schema(&mut self) -> DbResult<DbSchema> {
+        MySqlClient::get_schema(self)
+    }
+
+    fn execute(&mut self, sql: &str) -> DbResult<()> {
+        MySqlClient::execute(self, sql)
+    }
+
+    fn begin(&mut self) -> DbResult<()> {
+        self.execute("START TRANSACTION")
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        self.execute("COMMIT")
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        self.execute("ROLLBACK")
+    }
+
+    fn create_migrations_table(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = dialect
+            .create_migrations_table(table.schema.as_deref(), &table.qualified_name_in(dialect));
+        self.execute(&sql)?;
+        super::upgrade_migrations_table(self, &table.qualified_name_in(dialect))
+    }
+
+    fn migrations_table_exists(&mut self, table: &MigrationsTable) -> DbResult<bool> {
+        let dialect = self.dialect();
+        let rows = self.query_text(&dialect.table_exists(&table.qualified_name_in(dialect)))?;
+        Ok(rows.first().is_some_and(|row| row[0].is_some()))
+    }
+
+    fn applied_migrations(&mut self, table: &MigrationsTable) -> DbResult<Vec<AppliedMigration>> {
+        let rows = self.query_text(&format!(
+            "SELECT id, name, checksum, CAST(applied_at AS CHAR), CAST(duration_ms AS CHAR)
+             FROM {} ORDER BY id",
+            table.qualified_name_in(self.dialect())
+        ))?;
+        Ok(rows
+            .into_iter()
+            .map(|mut row| AppliedMigration {
+                duration_ms: row.pop().flatten().and_then(|d| d.parse().ok()),
+                applied_at: row.pop().flatten().unwrap_or_default(),
+                checksum: row.pop().flatten(),
+                name: row.pop().flatten().unwrap_or_default(),
+                id: row.pop().flatten().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    fn insert_applied(
+        &mut self,
+        table: &MigrationsTable,
+        migration: &Migration,
+        duration: Option<Duration>,
+    ) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = format!(
+            "INSERT INTO {} (id, name, checksum, duration_ms) VALUES ({}, {}, {}, {})",
+            table.qualified_name_in(dialect),
+            dialect.quote_literal(&migration.meta.id),
+            dialect.quote_literal(&migration.meta.name),
+            dialect.quote_literal(migration.checksum()),
+            super::duration_ms(duration)
+        );
+        self.execute(&sql)
+    }
+
+    fn delete_applied(&mut self, table: &MigrationsTable, id: &str) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = format!(
+            "DELETE FROM {} WHERE id = {}",
+            table.qualified_name_in(dialect),
+            dialect.quote_literal(id)
+        );
+        self.execute(&sql)
+    }
+
+    fn lock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = dialect.lock(&table.qualified_name_in(dialect));
+        self.execute(&sql)
+    }
+
+    fn unlock_migrations(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = dialect.unlock(&table.qualified_name_in(dialect));
+        self.execute(&sql)
+    }
+}
+
+/// A value as text; the text protocol sends everything but NULL as bytes
+fn value_text(value: mysql::Value) -> Option<String> {
+    match value {
+        mysql::Value::NULL => None,
+        mysql::Value::Bytes(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        mysql::Value::Int(n) => Some(n.to_string()),
+        mysql::Value::UInt(n) => Some(n.to_string()),
+        mysql::Value::Float(n) => Some(n.to_string()),
+        mysql::Value::Double(n) => Some(n.to_string()),
+        other => Some(other.as_sql(true).trim_matches('\'').to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_as_text() {
+        assert_eq!(
+            value_text(mysql::Value::Bytes(b"abc".to_vec())).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(value_text(mysql::Value::Int(-4)).as_deref(), Some("-4"));
+        assert_eq!(value_text(mysql::Value::NULL), None);
+    }
+}
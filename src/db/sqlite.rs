@@ -0,0 +1,361 @@
+/**
+ * Stratus SQLite Backend
+ *
+ * A client for local SQLite files, so schemas can be prototyped with sync,
+ * db push, db pull and migrate dev without a database server. Datasource
+ * URLs are `sqlite:path`, `sqlite://path`, `file:` URIs or a bare path;
+ * `sqlite::memory:` opens a private in-memory database.
+ */
+use super::catalog::{self, TextRow};
+use super::{AppliedMigration, DbBackend, DbConfig, DbError, DbResult};
+use crate::dialect::{Dialect, Sqlite};
+use crate::diff::{DbForeignKey, DbIndex, DbSchema};
+use crate::migrate::{Migration, MigrationsTable};
+use rusqlite::types::ValueRef;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+
+/// SQLite database client
+pub struct SqliteClient {
+    conn: rusqlite::Connection,
+    connection_string: String,
+}
+
+impl SqliteClient {
+    /// Open (or create) the database file a URL names
+    pub fn connect(config: &DbConfig) -> DbResult<Self> {
+        let path = database_path(&config.connection_string);
+        debug!(path, "opening");
+        let conn =
+            rusqlite::Connection::open(path).map_err(|e| DbError::Connection(e.to_string()))?;
+        // Wait for other writers instead of failing at once
+        let timeout = config.options.connect_timeout.unwrap_or(5);
+        conn.busy_timeout(Duration::from_secs(timeout))
+            .and_then(|_| conn.execute_batch("PRAGMA foreign_keys = ON"))
+            .map_err(|e| DbError::Connection(e.to_string()))?;
+        Ok(Self {
+            conn,
+            connection_string: config.connection_string.clone(),
+        })
+    }
+
+    /// The SQL dialect of the connected database
+    pub fn dialect(&self) -> &'static dyn Dialect {
+        &Sqlite
+    }
+
+    /// Connection string this client was opened with
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
+    }
+
+    /// Test connection
+    pub fn ping(&mut self) -> DbResult<()> {
+        self.execute("SELECT 1")
+    }
+
+    /// Execute statements without results
+    pub fn execute(&mut self, sql: &str) -> DbResult<()> {
+        debug!(target: "stratus::sql", "{}", sql);
+        self.conn
+            .execute_batch(sql)
+            .map_err(|e| DbError::Query(e.to_string()))
+    }
+
+    /// Execute query and return results, with NULL as `"NULL"`
+    pub fn query(&mut self, sql: &str) -> DbResult<Vec<HashMap<String, String>>> {
+        let names: Vec<String> = {
+            let statement = self
+                .conn
+                .prepare(sql)
+                .map_err(|e| DbError::Query(e.to_string()))?;
+            statement
+                .column_names()
+                .into_iter()
+                .map(str::to_string)
+                .collect()
+        };
+        Ok(self
+            .query_text(sql)?
+            .into_iter()
+            .map(|row| {
+                names
+                    .iter()
+                    .cloned()
+                    .zip(row)
+                    .map(|(name, value)| (name, value.unwrap_or_else(|| "NULL".to_string())))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Run a catalog query, with every value as text
+    fn query_text(&mut self, sql: &str) -> DbResult<Vec<TextRow>> {
+        debug!(target: "stratus::sql", "{}", sql);
+        let query_error = |e: rusqlite::Error| DbError::Query(e.to_string());
+        let mut statement = self.conn.prepare(sql).map_err(query_error)?;
+        let count = statement.column_count();
+        let rows = statement
+            .query_map([], |row| {
+                (0..count)
+                    .map(|i| row.get_ref(i).map(value_text))
+                    .collect::<Result<TextRow, _>>()
+            })
+            .map_err(query_error)?;
+        rows.collect::<Result<_, _>>().map_err(query_error)
+    }
+
+    /// Introspect the `main` database
+    pub fn get_schema(&mut self) -> DbResult<DbSchema> {
+        let queries = self.dialect().introspection();
+        let rows = catalog::CatalogRows {
+            tables: self.query_text(queries.tables)?,
+            columns: self.query_text(queries.columns)?,
+            primary_keys: self.query_text(queries.primary_keys)?,
+            foreign_keys: self.query_text(queries.foreign_keys)?,
+            indexes: self.query_text(queries.indexes)?,
+            ..Default::default()
+        };
+        Ok(catalog::build_schema(&Sqlite, rows))
+    }
+
+    /// Get indexes, keyed by table
+    pub fn get_indexes(&mut self) -> DbResult<HashMap<String, Vec<DbIndex>>> {
+        let rows = self.query_text(self.dialect().introspection().indexes)?;
+        Ok(catalog::indexes(rows))
+    }
+
+    /// Get foreign keys, keyed by referencing table
+    pub fn get_foreign_keys(&mut self) -> DbResult<HashMap<String, Vec<DbForeignKey>>> {
+        let rows = self.query_text(self.dialect().introspection().foreign_keys)?;
+        Ok(catalog::foreign_keys(rows))
+    }
+}
+
+impl DbBackend for SqliteClient {
+    fn get_schema(&mut self) -> DbResult<DbSchema> {
+        SqliteClient::get_schema(self)
+    }
+
+    fn execute(&mut self, sql: &str) -> DbResult<()> {
+        SqliteClient::execute(self, sql)
+    }
+
+    // Takes the write lock up front, so a migration never waits halfway
+    fn begin(&mut self) -> DbResult<()> {
+        self.execute("BEGIN IMMEDIATE")
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        self.execute("COMMIT")
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        self.execute("ROLLBACK")
+    }
+
+    fn create_migrations_table(&mut self, table: &MigrationsTable) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = dialect
+            .create_migrations_table(table.schema.as_deref(), &table.qualified_name_in(dialect));
+        self.execute(&sql)?;
+        super::upgrade_migrations_table(self, &table.qualified_name_in(dialect))
+    }
+
+    fn migrations_table_exists(&mut self, table: &MigrationsTable) -> DbResult<bool> {
+        let dialect = self.dialect();
+        let rows = self.query_text(&dialect.table_exists(&table.qualified_name_in(dialect)))?;
+        Ok(rows.first().is_some_and(|row| row[0].is_some()))
+    }
+
+    fn applied_migrations(&mut self, table: &MigrationsTable) -> DbResult<Vec<AppliedMigration>> {
+        let rows = self.query_text(&format!(
+            "SELECT id, name, checksum, applied_at, duration_ms FROM {} ORDER BY id",
+            table.qualified_name_in(self.dialect())
+        ))?;
+        Ok(rows
+            .into_iter()
+            .map(|mut row| AppliedMigration {
+                duration_ms: row.pop().flatten().and_then(|d| d.parse().ok()),
+                applied_at: row.pop().flatten().unwrap_or_default(),
+                checksum: row.pop().flatten(),
+                name: row.pop().flatten().unwrap_or_default(),
+                id: row.pop().flatten().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    fn insert_applied(
+        &mut self,
+        table: &MigrationsTable,
+        migration: &Migration,
+        duration: Option<Duration>,
+    ) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = format!(
+            "INSERT INTO {} (id, name, checksum, duration_ms) VALUES ({}, {}, {}, {})",
+            table.qualified_name_in(dialect),
+            dialect.quote_literal(&migration.meta.id),
+            dialect.quote_literal(&migration.meta.name),
+            dialect.quote_literal(migration.checksum()),
+            super::duration_ms(duration)
+        );
+        self.execute(&sql)
+    }
+
+    fn delete_applied(&mut self, table: &MigrationsTable, id: &str) -> DbResult<()> {
+        let dialect = self.dialect();
+        let sql = format!(
+            "DELETE FROM {} WHERE id = {}",
+            table.qualified_name_in(dialect),
+            dialect.quote_literal(id)
+        );
+        self.execute(&sql)
+    }
+
+    fn lock_migrations(&mut self, _table: &MigrationsTable) -> DbResult<()> {
+        Ok(())
+    }
+
+    fn unlock_migrations(&mut self, _table: &MigrationsTable) -> DbResult<()> {
+        Ok(())
+    }
+}
+
+/// The file (or `file:` URI) a connection string names
+fn database_path(url: &str) -> &str {
+    match url.strip_prefix("sqlite:") {
+        Some(":memory:") => ":memory:",
+        Some(rest) => rest.strip_prefix("//").unwrap_or(rest),
+        None => url,
+    }
+}
+
+fn value_text(value: ValueRef<'_>) -> Option<String> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(n) => Some(n.to_string()),
+        ValueRef::Real(n) => Some(n.to_string()),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_introspect_in_memory() {
+        assert_eq!(database_path("sqlite://dev.db"), "dev.db");
+        assert_eq!(database_path("sqlite:./data/dev.db"), "./data/dev.db");
+        assert_eq!(database_path("file:dev.db?mode=ro"), "file:dev.db?mode=ro");
+
+        let config = crate::engine::connection_config(None, None, None, "sqlite::memory:", false);
+        assert_eq!(config.provider, crate::config::DatabaseProvider::Sqlite);
+        let mut client = SqliteClient::connect(&config).unwrap();
+        let schema: crate::schema::Schema = serde_json::from_str(
+            r#"{ "dialect": "sqlite", "tables": {
+                "users": { "columns": {
+                    "id": { "name": "id", "type": "bigint", "isPrimaryKey": true, "identity": {} },
+                    "email": { "name": "email", "type": "varchar", "size": 120, "isNotNull": true }
+                } },
+                "posts": { "columns": {
+                    "id": { "name": "id", "type": "integer", "isPrimaryKey": true },
+                    "user_id": { "name": "user_id", "type": "bigint" }
+                } }
+            } }"#,
+        )
+        .unwrap();
+        let diff = crate::diff::compare_schemas(&schema, &client.get_schema().unwrap());
+        client.execute(&diff.sql()).unwrap();
+        client
+            .execute(
+                "CREATE INDEX idx_posts_user_id ON posts (user_id);
+                 INSERT INTO users (email) VALUES ('a@b.c');",
+            )
+            .unwrap();
+
+        let db = client.get_schema().unwrap();
+        assert_eq!(db.dialect, "sqlite");
+        assert_eq!(db.tables.keys().collect::<Vec<_>>(), vec!["posts", "users"]);
+        let email = &db.tables["users"].columns["email"];
+        assert_eq!(
+            (email.data_type.as_str(), email.size),
+            ("varchar", Some(120))
+        );
+        assert!(!email.is_nullable);
+        assert_eq!(db.tables["users"].primary_key, vec!["id"]);
+        assert!(!crate::diff::compare_schemas(&schema, &db).has_changes());
+        assert_eq!(
+            client.get_indexes().unwrap()["posts"][0].name,
+            "idx_posts_user_id"
+        );
+        assert_eq!(client.query("SELECT id FROM users").unwrap()[0]["id"], "1");
+
+        let table = MigrationsTable::default();
+        assert!(!client.migrations_table_exists(&table).unwrap());
+        // A table from before durations were recorded gains the column
+        client
+            .execute(
+                "CREATE TABLE _stratus_migrations (id TEXT PRIMARY KEY, name TEXT NOT NULL,
+                 checksum TEXT, applied_at TEXT NOT NULL DEFAULT '')",
+            )
+            .unwrap();
+        client.create_migrations_table(&table).unwrap();
+        assert!(client.migrations_table_exists(&table).unwrap());
+        assert!(client.applied_migrations(&table).unwrap().is_empty());
+
+        let migration = Migration::new(
+            serde_json::from_value(serde_json::json!({
+                "id": "1", "name": "init", "created_at": "", "dialect": "sqlite", "checksum": null
+            }))
+            .unwrap(),
+            "",
+            "",
+        );
+        client
+            .insert_applied(&table, &migration, Some(Duration::from_millis(42)))
+            .unwrap();
+        let applied = client.applied_migrations(&table).unwrap();
+        assert_eq!(
+            (applied[0].id.as_str(), applied[0].duration_ms),
+            ("1", Some(42))
+        );
+
+        // Reset drops a parent table before the child referencing it
+        client
+            .execute(
+                "CREATE TABLE visits (id INTEGER PRIMARY KEY, user_id BIGINT REFERENCES users (id));
+                 INSERT INTO visits (user_id) VALUES (1);",
+            )
+            .unwrap();
+        let reset =
+            crate::migrate::reset_sql(client.dialect(), &client.get_schema().unwrap(), &table);
+        client.begin().unwrap();
+        client.execute(&reset).unwrap();
+        client.commit().unwrap();
+        assert!(client.get_schema().unwrap().tables.is_empty());
+        assert!(!client.migrations_table_exists(&table).unwrap());
+
+        // So does the force reset of `db push`
+        client
+            .execute(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);
+                 CREATE TABLE visits (id INTEGER PRIMARY KEY, user_id BIGINT REFERENCES users (id));
+                 INSERT INTO users (id) VALUES (1);
+                 INSERT INTO visits (user_id) VALUES (1);",
+            )
+            .unwrap();
+        let drops = crate::migrate::drop_tables_sql(
+            client.dialect(),
+            &["users".to_string(), "visits".to_string()],
+        );
+        client.begin().unwrap();
+        client.execute(&drops).unwrap();
+        client.commit().unwrap();
+        assert!(client.get_schema().unwrap().tables.is_empty());
+    }
+}
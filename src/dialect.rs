@@ -0,0 +1,1651 @@
+/**
+ * Stratus Dialect Module
+ *
+ * What differs from one database to the next, behind one interface:
+ * quoting, the SQL type of each schema.json type, the catalog queries
+ * introspection runs, and the DDL diffs and the migrations table are written
+ * in. PostgreSQL, MySQL and SQLite are implemented.
+ */
+use crate::config::DatabaseProvider;
+use crate::diff::{ColumnChange, DbColumn, DbConstraint, DbForeignKey};
+use crate::schema::{ConstraintType, Index, Table};
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+/// Catalog queries run by introspection, one round trip each.
+///
+/// Each returns rows in the order noted, sorted by table.
+#[derive(Debug, Clone, Copy)]
+pub struct IntrospectionQueries {
+    /// table_name
+    pub tables: &'static str,
+    /// table_name, column_name, data_type, is_nullable ('YES'/'NO'),
+    /// column_default, character_maximum_length
+    pub columns: &'static str,
+    /// table_name, column_name, in key order
+    pub primary_keys: &'static str,
+    /// type_name, label; `None` where enums are not separate types
+    pub enums: Option<&'static str>,
+    /// table_name, index_name, CREATE INDEX statement
+    pub indexes: &'static str,
+    /// constraint_name, table_name, referenced_table, columns[], referenced_columns[],
+    /// delete_rule, update_rule; the column lists are comma-separated text where
+    /// there are no arrays, and the rules spelled as in SQL, e.g. `SET NULL`
+    pub foreign_keys: &'static str,
+    /// constraint_name, table_name, constraint_type (`CHECK`, `UNIQUE` or
+    /// `EXCLUDE`), columns[], definition (the expression for a CHECK); the
+    /// column list comma-separated text where there are no arrays. `None`
+    /// where the catalog does not list them
+    pub constraints: Option<&'static str>,
+    /// role_name; `None` where the database has no roles
+    pub roles: Option<&'static str>,
+    /// role_name, table_name, column_name (NULL for the whole table),
+    /// privilege; the owners' own privileges left out
+    pub grants: Option<&'static str>,
+}
+
+/// The SQL of one database
+pub trait Dialect: Sync {
+    fn provider(&self) -> DatabaseProvider;
+
+    /// Name used in schema.json and migration metadata
+    fn name(&self) -> &'static str {
+        self.provider().as_str()
+    }
+
+    fn quote_ident(&self, ident: &str) -> String;
+
+    fn quote_literal(&self, value: &str) -> String;
+
+    /// SQL type of a schema.json column type; unknown types pass through
+    fn column_type<'a>(&self, schema_type: &'a str, size: Option<usize>) -> Cow<'a, str>;
+
+    /// The SQL type, lowercase, of a type as schema.json or the catalog
+    /// spells it, so the two can be compared
+    fn comparable_type(&self, data_type: &str, size: Option<usize>) -> String {
+        let data_type = data_type.trim().to_lowercase();
+        self.column_type(schema_type_name(&data_type), size)
+            .to_lowercase()
+    }
+
+    fn introspection(&self) -> &'static IntrospectionQueries;
+
+    /// The CREATE TABLE statement for a schema.json table
+    fn write_create_table(&self, out: &mut dyn Write, name: &str, table: &Table) -> fmt::Result;
+
+    fn add_column(&self, table: &str, column: &DbColumn) -> String;
+
+    fn drop_column(&self, table: &str, column: &str) -> String;
+
+    /// Whether ALTER TABLE can change the columns and foreign keys of an
+    /// existing table; where it cannot, the diff reports those changes as
+    /// warnings instead of planning SQL that would change nothing
+    fn alters_in_place(&self) -> bool {
+        true
+    }
+
+    /// Whether UNIQUE, EXCLUDE and foreign key constraints can be
+    /// DEFERRABLE; where they cannot, they are created checked immediately
+    fn defers_constraints(&self) -> bool {
+        false
+    }
+
+    /// Change what `changes` lists about a column from what `from` has to
+    /// what `column` has
+    fn alter_column(
+        &self,
+        table: &str,
+        column: &DbColumn,
+        from: &DbColumn,
+        changes: &[ColumnChange],
+    ) -> String;
+
+    fn drop_table(&self, table: &str) -> String;
+
+    fn add_foreign_key(&self, table: &str, foreign_key: &DbForeignKey) -> String;
+
+    fn drop_foreign_key(&self, table: &str, name: &str) -> String;
+
+    /// Add a CHECK, UNIQUE or EXCLUDE constraint to an existing table
+    fn add_constraint(&self, table: &str, constraint: &DbConstraint) -> String;
+
+    fn drop_constraint(&self, table: &str, constraint: &DbConstraint) -> String;
+
+    /// Create an index schema.json declares
+    fn create_index(&self, table: &str, index: &Index) -> String;
+
+    fn drop_index(&self, table: &str, name: &str) -> String;
+
+    fn create_enum(&self, name: &str, values: &[String]) -> String;
+
+    /// Add `value` to an enum type, after or before an existing value when given
+    fn add_enum_value(
+        &self,
+        name: &str,
+        value: &str,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> String;
+
+    fn drop_enum(&self, name: &str) -> String;
+
+    fn create_role(&self, role: &str) -> String;
+
+    /// GRANT `privileges` on `table`, or on its `columns` when given, to `role`
+    fn grant(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String;
+
+    fn revoke(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String;
+
+    /// Create the migrations table `qualified_name` (and `schema`) if missing
+    fn create_migrations_table(&self, schema: Option<&str>, qualified_name: &str) -> String;
+
+    /// A query returning one `name` column, NULL when the table is missing
+    fn table_exists(&self, qualified_name: &str) -> String;
+
+    /// Take the lock serializing migration runs on `key`, blocking while held
+    fn lock(&self, key: &str) -> String;
+
+    fn unlock(&self, key: &str) -> String;
+}
+
+impl DatabaseProvider {
+    /// The provider's dialect
+    pub fn dialect(&self) -> &'static dyn Dialect {
+        match self {
+            Self::Postgresql => &Postgres,
+            Self::Mysql => &Mysql,
+            Self::Sqlite => &Sqlite,
+        }
+    }
+}
+
+/// schema.json's name for a lowercase type name as a catalog reports it,
+/// e.g. `varchar` for PostgreSQL's `character varying`
+pub(crate) fn schema_type_name(catalog_type: &str) -> &str {
+    match catalog_type {
+        "character varying" => "varchar",
+        "character" => "char",
+        "int" | "int4" => "integer",
+        "int8" => "bigint",
+        "int2" => "smallint",
+        // PostgreSQL's serials are these integers with a sequence default
+        "serial" | "serial4" => "integer",
+        "bigserial" | "serial8" => "bigint",
+        "smallserial" | "serial2" => "smallint",
+        "double precision" | "float8" => "double",
+        "bool" | "tinyint" => "boolean",
+        "numeric" => "decimal",
+        "timestamp with time zone" | "datetime" => "timestamptz",
+        "blob" | "longblob" => "bytea",
+        other => other,
+    }
+}
+
+/// Types that convert to one another by assignment, which fails on values
+/// that do not fit; an explicit cast would truncate or round them instead
+fn type_family(data_type: &str) -> &str {
+    match schema_type_name(data_type) {
+        "smallint" | "integer" | "bigint" | "decimal" | "real" | "float" | "double" => "number",
+        "varchar" | "char" | "text" => "text",
+        "date" | "timestamp" | "timestamptz" => "timestamp",
+        other => other,
+    }
+}
+
+/// Whether a type is one of PostgreSQL's serials, which are NOT NULL and
+/// default to the next value of a sequence of their own
+pub(crate) fn is_serial(data_type: &str) -> bool {
+    matches!(
+        data_type.trim().to_lowercase().as_str(),
+        "serial" | "serial4" | "bigserial" | "serial8" | "smallserial" | "serial2"
+    )
+}
+
+/// The dialect named in schema.json or migration metadata
+pub fn for_name(name: &str) -> Option<&'static dyn Dialect> {
+    Some(DatabaseProvider::from_name(name)?.dialect())
+}
+
+/// PostgreSQL
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postgres;
+
+static POSTGRES_INTROSPECTION: IntrospectionQueries = IntrospectionQueries {
+    tables: "SELECT table_name FROM information_schema.tables
+             WHERE table_schema = 'public' AND table_type = 'BASE TABLE'
+             ORDER BY table_name",
+    // Enum columns report their type's name rather than USER-DEFINED
+    columns: "SELECT table_name::text, column_name::text,
+                    CASE WHEN data_type = 'USER-DEFINED' THEN udt_name::text
+                         ELSE data_type::text END,
+                    is_nullable::text, column_default::text, character_maximum_length::int
+             FROM information_schema.columns
+             WHERE table_schema = 'public'
+             ORDER BY table_name, ordinal_position",
+    primary_keys: "SELECT c.relname::text, a.attname::text
+             FROM pg_index i
+             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+             JOIN pg_class c ON c.oid = i.indrelid
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             WHERE i.indisprimary
+             AND n.nspname = 'public'
+             ORDER BY c.relname, a.attnum",
+    enums: Some(
+        "SELECT t.typname, e.enumlabel
+             FROM pg_type t
+             JOIN pg_enum e ON t.oid = e.enumtypid
+             JOIN pg_namespace n ON n.oid = t.typnamespace
+             WHERE n.nspname = 'public'
+             ORDER BY t.typname, e.enumsortorder",
+    ),
+    indexes: "SELECT tablename::text, indexname::text, indexdef
+             FROM pg_indexes
+             WHERE schemaname = 'public'
+             ORDER BY tablename, indexname",
+    foreign_keys: "SELECT c.conname::text, t.relname::text, r.relname::text,
+                    ARRAY(SELECT a.attname::text FROM unnest(c.conkey) WITH ORDINALITY k(n, i)
+                          JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.n
+                          ORDER BY k.i),
+                    ARRAY(SELECT a.attname::text FROM unnest(c.confkey) WITH ORDINALITY k(n, i)
+                          JOIN pg_attribute a ON a.attrelid = c.confrelid AND a.attnum = k.n
+                          ORDER BY k.i),
+                    CASE c.confdeltype WHEN 'c' THEN 'CASCADE' WHEN 'n' THEN 'SET NULL'
+                         WHEN 'd' THEN 'SET DEFAULT' WHEN 'r' THEN 'RESTRICT'
+                         ELSE 'NO ACTION' END,
+                    CASE c.confupdtype WHEN 'c' THEN 'CASCADE' WHEN 'n' THEN 'SET NULL'
+                         WHEN 'd' THEN 'SET DEFAULT' WHEN 'r' THEN 'RESTRICT'
+                         ELSE 'NO ACTION' END,
+                    c.condeferrable, c.condeferred
+             FROM pg_constraint c
+             JOIN pg_class t ON t.oid = c.conrelid
+             JOIN pg_class r ON r.oid = c.confrelid
+             JOIN pg_namespace n ON n.oid = t.relnamespace
+             WHERE c.contype = 'f' AND n.nspname = 'public'
+             ORDER BY t.relname, c.conname",
+    constraints: Some(
+        "SELECT c.conname::text, t.relname::text,
+                    CASE c.contype WHEN 'c' THEN 'CHECK' WHEN 'u' THEN 'UNIQUE'
+                         ELSE 'EXCLUDE' END,
+                    ARRAY(SELECT a.attname::text FROM unnest(c.conkey) WITH ORDINALITY k(n, i)
+                          JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.n
+                          ORDER BY k.i),
+                    pg_get_constraintdef(c.oid)
+             FROM pg_constraint c
+             JOIN pg_class t ON t.oid = c.conrelid
+             JOIN pg_namespace n ON n.oid = t.relnamespace
+             WHERE c.contype IN ('c', 'u', 'x') AND n.nspname = 'public'
+             ORDER BY t.relname, c.conname",
+    ),
+    roles: Some("SELECT rolname::text FROM pg_roles WHERE rolname !~ '^pg_' ORDER BY rolname"),
+    grants: Some(
+        "SELECT r.rolname::text, c.relname::text, NULL::text, a.privilege_type::text
+             FROM pg_class c
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             CROSS JOIN LATERAL aclexplode(c.relacl) a
+             JOIN pg_roles r ON r.oid = a.grantee
+             WHERE n.nspname = 'public' AND c.relkind IN ('r', 'p', 'v')
+             AND a.grantee <> c.relowner
+             UNION ALL
+             SELECT r.rolname::text, c.relname::text, att.attname::text, a.privilege_type::text
+             FROM pg_attribute att
+             JOIN pg_class c ON c.oid = att.attrelid
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             CROSS JOIN LATERAL aclexplode(att.attacl) a
+             JOIN pg_roles r ON r.oid = a.grantee
+             WHERE n.nspname = 'public' AND att.attnum > 0 AND NOT att.attisdropped
+             AND a.grantee <> c.relowner
+             ORDER BY 2, 1, 3 NULLS FIRST, 4",
+    ),
+};
+
+/// `SELECT, UPDATE`, or `SELECT (a, b), UPDATE (a, b)` for columns
+fn privilege_list(privileges: &[String], columns: &[String]) -> String {
+    if columns.is_empty() {
+        return privileges.join(", ");
+    }
+    let columns = columns.join(", ");
+    privileges
+        .iter()
+        .map(|privilege| format!("{} ({})", privilege, columns))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `CONSTRAINT n FOREIGN KEY (a) REFERENCES t (b)` and its actions
+fn foreign_key_clause(foreign_key: &DbForeignKey) -> String {
+    let mut clause = format!(
+        "CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {} ({})",
+        foreign_key.name,
+        foreign_key.columns.join(", "),
+        foreign_key.references_table,
+        foreign_key.references_columns.join(", ")
+    );
+    if let Some(action) = &foreign_key.on_delete {
+        clause.push_str(&format!(" ON DELETE {}", action));
+    }
+    if let Some(action) = &foreign_key.on_update {
+        clause.push_str(&format!(" ON UPDATE {}", action));
+    }
+    clause.push_str(foreign_key.deferral());
+    clause
+}
+
+/// The foreign keys of a CREATE TABLE, each on a line of its own after the
+/// columns and primary key; where `database` names one, checked immediately
+fn write_foreign_keys(
+    out: &mut dyn Write,
+    name: &str,
+    table: &Table,
+    database: Option<&str>,
+) -> fmt::Result {
+    for mut foreign_key in DbForeignKey::from_table(name, table) {
+        if database.is_some() {
+            foreign_key.deferrable = false;
+            foreign_key.initially_deferred = false;
+        }
+        write!(out, ",\n  {}", foreign_key_clause(&foreign_key))?;
+    }
+    Ok(())
+}
+
+/// `CONSTRAINT n CHECK (a > 0)`, or `CONSTRAINT n UNIQUE (a) DEFERRABLE`
+fn constraint_clause(constraint: &DbConstraint) -> String {
+    format!(
+        "CONSTRAINT {} {}{}",
+        constraint.name,
+        constraint.definition(),
+        constraint.deferral()
+    )
+}
+
+/// The CHECK, UNIQUE and EXCLUDE constraints of a CREATE TABLE, after the
+/// foreign keys; where `database` names one without exclusion constraints,
+/// those are left as comments after the last clause and the rest are
+/// checked immediately
+fn write_constraints(
+    out: &mut dyn Write,
+    name: &str,
+    table: &Table,
+    database: Option<&str>,
+) -> fmt::Result {
+    let (skipped, constraints): (Vec<_>, Vec<_>) = DbConstraint::from_table(name, table)
+        .into_iter()
+        .partition(|c| database.is_some() && c.constraint_type == ConstraintType::Exclude);
+    for mut constraint in constraints {
+        if database.is_some() {
+            constraint.deferrable = false;
+            constraint.initially_deferred = false;
+        }
+        write!(out, ",\n  {}", constraint_clause(&constraint))?;
+    }
+    for constraint in &skipped {
+        write!(
+            out,
+            "\n  -- {} has no exclusion constraints: {} skipped",
+            database.unwrap_or_default(),
+            constraint_clause(constraint)
+        )?;
+    }
+    Ok(())
+}
+
+/// `CREATE UNIQUE INDEX IF NOT EXISTS n ON t`, the IF NOT EXISTS where
+/// `if_not_exists` allows it
+fn create_index_head(table: &str, index: &Index, if_not_exists: bool) -> String {
+    format!(
+        "CREATE {}INDEX {}{} ON {}",
+        if index.unique { "UNIQUE " } else { "" },
+        if if_not_exists && index.if_not_exists {
+            "IF NOT EXISTS "
+        } else {
+            ""
+        },
+        index.name,
+        table
+    )
+}
+
+impl Dialect for Postgres {
+    fn provider(&self) -> DatabaseProvider {
+        DatabaseProvider::Postgresql
+    }
+
+    fn quote_ident(&self, ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    fn quote_literal(&self, value: &str) -> String {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+
+    fn column_type<'a>(&self, schema_type: &'a str, size: Option<usize>) -> Cow<'a, str> {
+        // An array of the element type, sized as the element is
+        if let Some(element) = schema_type.strip_suffix("[]") {
+            return Cow::Owned(format!("{}[]", self.column_type(element.trim_end(), size)));
+        }
+        Cow::Borrowed(match schema_type {
+            "varchar" | "char" => {
+                return match size {
+                    Some(s) => Cow::Owned(format!("VARCHAR({})", s)),
+                    None => Cow::Borrowed("VARCHAR(255)"),
+                }
+            }
+            "decimal" => "DECIMAL(10, 2)",
+            "bigint" => "BIGINT",
+            "integer" => "INTEGER",
+            "smallint" => "SMALLINT",
+            "float" | "double" => "DOUBLE PRECISION",
+            "boolean" => "BOOLEAN",
+            "date" => "DATE",
+            "timestamp" | "timestamptz" => "TIMESTAMP WITH TIME ZONE",
+            "json" => "JSON",
+            "jsonb" => "JSONB",
+            "text" => "TEXT",
+            "uuid" => "UUID",
+            "bytea" => "BYTEA",
+            _ => schema_type,
+        })
+    }
+
+    fn introspection(&self) -> &'static IntrospectionQueries {
+        &POSTGRES_INTROSPECTION
+    }
+
+    fn defers_constraints(&self) -> bool {
+        true
+    }
+
+    fn write_create_table(&self, out: &mut dyn Write, name: &str, table: &Table) -> fmt::Result {
+        writeln!(out, "CREATE TABLE {} (", name)?;
+
+        for (i, (col_name, col)) in table.columns.iter().enumerate() {
+            if i > 0 {
+                out.write_str(",\n")?;
+            }
+
+            write!(
+                out,
+                "  {} {}",
+                col_name,
+                self.column_type(&col.declared_type(), col.size)
+            )?;
+
+            // A serial is always NOT NULL, and saying NULL is an error
+            if !col.is_not_null() && !is_serial(&col.data_type) {
+                out.write_str(" NULL")?;
+            } else {
+                out.write_str(" NOT NULL")?;
+            }
+
+            if let Some(default) = &col.default {
+                write!(out, " DEFAULT {}", default)?;
+            }
+
+            if let Some(identity) = &col.identity {
+                write!(
+                    out,
+                    " GENERATED {} AS IDENTITY",
+                    if identity.always {
+                        "ALWAYS"
+                    } else {
+                        "BY DEFAULT"
+                    }
+                )?;
+            } else if col.generated.is_some() {
+                out.write_str(" GENERATED ALWAYS AS IDENTITY")?;
+            }
+        }
+
+        let pk_cols: Vec<&str> = table
+            .columns
+            .iter()
+            .filter(|(_, c)| c.is_primary_key())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !pk_cols.is_empty() {
+            write!(out, ",\n  PRIMARY KEY ({})", pk_cols.join(", "))?;
+        }
+        write_foreign_keys(out, name, table, None)?;
+        write_constraints(out, name, table, None)?;
+
+        out.write_str("\n)")?;
+
+        // Table options
+        if let Some(opts) = &table.options.fillfactor {
+            write!(out, " WITH (fillfactor = {})", opts)?;
+        }
+
+        out.write_char(';')
+    }
+
+    // The default fills the rows already there, so NOT NULL can hold
+    fn add_column(&self, table: &str, column: &DbColumn) -> String {
+        let mut sql = format!(
+            "ALTER TABLE {} ADD COLUMN {} {} {}",
+            table,
+            column.name,
+            self.column_type(&column.data_type, column.size),
+            if column.is_nullable {
+                "NULL"
+            } else {
+                "NOT NULL"
+            }
+        );
+        if let Some(default) = &column.default_value {
+            sql.push_str(&format!(" DEFAULT {}", default));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_column(&self, table: &str, column: &str) -> String {
+        format!("ALTER TABLE {} DROP COLUMN IF EXISTS {};", table, column)
+    }
+
+    // A USING cast lets types without an implicit conversion change too. Only
+    // across families: within one it would truncate what should be an error
+    fn alter_column(
+        &self,
+        table: &str,
+        column: &DbColumn,
+        from: &DbColumn,
+        changes: &[ColumnChange],
+    ) -> String {
+        let prefix = format!("ALTER TABLE {} ALTER COLUMN {}", table, column.name);
+        changes
+            .iter()
+            .map(|change| match change {
+                ColumnChange::Type => {
+                    // A serial is no type to convert to, only its integer is
+                    let data_type = column.data_type.to_lowercase();
+                    let data_type = if is_serial(&data_type) {
+                        schema_type_name(&data_type)
+                    } else {
+                        &column.data_type
+                    };
+                    let sql_type = self.column_type(data_type, column.size);
+                    let from_type = from.data_type.to_lowercase();
+                    if type_family(&from_type) == type_family(&data_type.to_lowercase()) {
+                        format!("{} TYPE {};", prefix, sql_type)
+                    } else {
+                        format!(
+                            "{} TYPE {} USING {}::{};",
+                            prefix, sql_type, column.name, sql_type
+                        )
+                    }
+                }
+                ColumnChange::Nullability if column.is_nullable => {
+                    format!("{} DROP NOT NULL;", prefix)
+                }
+                ColumnChange::Nullability => format!("{} SET NOT NULL;", prefix),
+                ColumnChange::Default => match &column.default_value {
+                    Some(default) => format!("{} SET DEFAULT {};", prefix, default),
+                    None => format!("{} DROP DEFAULT;", prefix),
+                },
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn drop_table(&self, table: &str) -> String {
+        format!("DROP TABLE IF EXISTS {} CASCADE;", table)
+    }
+
+    fn add_foreign_key(&self, table: &str, foreign_key: &DbForeignKey) -> String {
+        format!(
+            "ALTER TABLE {} ADD {};",
+            table,
+            foreign_key_clause(foreign_key)
+        )
+    }
+
+    fn drop_foreign_key(&self, table: &str, name: &str) -> String {
+        format!("ALTER TABLE {} DROP CONSTRAINT IF EXISTS {};", table, name)
+    }
+
+    fn add_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        format!(
+            "ALTER TABLE {} ADD {};",
+            table,
+            constraint_clause(constraint)
+        )
+    }
+
+    fn drop_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        format!(
+            "ALTER TABLE {} DROP CONSTRAINT IF EXISTS {};",
+            table, constraint.name
+        )
+    }
+
+    fn create_index(&self, table: &str, index: &Index) -> String {
+        let mut sql = create_index_head(table, index, true);
+        if let Some(method) = index.method.as_ref().and_then(|m| m.sql()) {
+            sql.push_str(&format!(" USING {}", method));
+        }
+        sql.push_str(&format!(" ({})", index.columns.join(", ")));
+        if index.nulls_not_distinct == Some(true) {
+            sql.push_str(" NULLS NOT DISTINCT");
+        }
+        let parameters = index
+            .with
+            .as_ref()
+            .map(|w| w.parameters())
+            .unwrap_or_default();
+        if !parameters.is_empty() {
+            sql.push_str(&format!(" WITH ({})", parameters.join(", ")));
+        }
+        if let Some(tablespace) = &index.tablespace {
+            sql.push_str(&format!(" TABLESPACE {}", tablespace));
+        }
+        if let Some(condition) = &index.where_clause {
+            sql.push_str(&format!(" WHERE {}", condition));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_index(&self, _table: &str, name: &str) -> String {
+        format!("DROP INDEX IF EXISTS {};", name)
+    }
+
+    fn create_enum(&self, name: &str, values: &[String]) -> String {
+        let values: Vec<String> = values.iter().map(|v| self.quote_literal(v)).collect();
+        format!("CREATE TYPE {} AS ENUM ({});", name, values.join(", "))
+    }
+
+    // Runs in a transaction from PostgreSQL 12, but the new value cannot be
+    // used before it commits
+    fn add_enum_value(
+        &self,
+        name: &str,
+        value: &str,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> String {
+        let position = match (after, before) {
+            (Some(after), _) => format!(" AFTER {}", self.quote_literal(after)),
+            (None, Some(before)) => format!(" BEFORE {}", self.quote_literal(before)),
+            (None, None) => String::new(),
+        };
+        format!(
+            "ALTER TYPE {} ADD VALUE IF NOT EXISTS {}{};",
+            name,
+            self.quote_literal(value),
+            position
+        )
+    }
+
+    // No CASCADE: a column still of the type is an error, not dropped
+    fn drop_enum(&self, name: &str) -> String {
+        format!("DROP TYPE IF EXISTS {};", name)
+    }
+
+    fn create_role(&self, role: &str) -> String {
+        format!("CREATE ROLE {};", role)
+    }
+
+    fn grant(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "GRANT {} ON {} TO {};",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    fn revoke(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "REVOKE {} ON {} FROM {};",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    fn create_migrations_table(&self, schema: Option<&str>, qualified_name: &str) -> String {
+        let mut sql = String::new();
+        if let Some(schema) = schema {
+            sql.push_str(&format!(
+                "CREATE SCHEMA IF NOT EXISTS {};\n",
+                self.quote_ident(schema)
+            ));
+        }
+        sql.push_str(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n  \
+             id TEXT PRIMARY KEY,\n  \
+             name TEXT NOT NULL,\n  \
+             checksum TEXT,\n  \
+             applied_at TIMESTAMPTZ NOT NULL DEFAULT now(),\n  \
+             duration_ms BIGINT\n);",
+            qualified_name
+        ));
+        sql
+    }
+
+    fn table_exists(&self, qualified_name: &str) -> String {
+        format!(
+            "SELECT to_regclass({})::text AS name",
+            self.quote_literal(qualified_name)
+        )
+    }
+
+    fn lock(&self, key: &str) -> String {
+        format!(
+            "SELECT pg_advisory_lock(hashtext({}))",
+            self.quote_literal(key)
+        )
+    }
+
+    fn unlock(&self, key: &str) -> String {
+        format!(
+            "SELECT pg_advisory_unlock(hashtext({}))",
+            self.quote_literal(key)
+        )
+    }
+}
+
+/// MySQL 8, also spoken by MariaDB; the current database plays the part of
+/// PostgreSQL's `public` schema
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mysql;
+
+static MYSQL_INTROSPECTION: IntrospectionQueries = IntrospectionQueries {
+    tables: "SELECT table_name FROM information_schema.tables
+             WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE'
+             ORDER BY table_name",
+    columns: "SELECT table_name, column_name, data_type, is_nullable,
+                    column_default, character_maximum_length
+             FROM information_schema.columns
+             WHERE table_schema = DATABASE()
+             ORDER BY table_name, ordinal_position",
+    primary_keys: "SELECT table_name, column_name
+             FROM information_schema.key_column_usage
+             WHERE table_schema = DATABASE() AND constraint_name = 'PRIMARY'
+             ORDER BY table_name, ordinal_position",
+    enums: None,
+    indexes: "SELECT table_name, index_name,
+                    CONCAT(IF(non_unique = 0, 'CREATE UNIQUE INDEX ', 'CREATE INDEX '),
+                           '`', index_name, '` ON `', table_name, '` (',
+                           GROUP_CONCAT(CONCAT('`', column_name, '`')
+                                        ORDER BY seq_in_index SEPARATOR ', '),
+                           ')')
+             FROM information_schema.statistics
+             WHERE table_schema = DATABASE() AND index_name <> 'PRIMARY'
+             GROUP BY table_name, index_name, non_unique
+             ORDER BY table_name, index_name",
+    foreign_keys: "SELECT k.constraint_name, k.table_name, k.referenced_table_name,
+                    GROUP_CONCAT(k.column_name ORDER BY k.ordinal_position),
+                    GROUP_CONCAT(k.referenced_column_name ORDER BY k.ordinal_position),
+                    r.delete_rule, r.update_rule
+             FROM information_schema.key_column_usage k
+             JOIN information_schema.referential_constraints r
+               ON r.constraint_schema = k.table_schema
+              AND r.constraint_name = k.constraint_name AND r.table_name = k.table_name
+             WHERE k.table_schema = DATABASE() AND k.referenced_table_name IS NOT NULL
+             GROUP BY k.constraint_name, k.table_name, k.referenced_table_name,
+                      r.delete_rule, r.update_rule
+             ORDER BY k.table_name, k.constraint_name",
+    // CHECK constraints from MySQL 8.0.16 and MariaDB 10.2
+    constraints: Some(
+        "SELECT t.constraint_name, t.table_name, t.constraint_type,
+                    GROUP_CONCAT(k.column_name ORDER BY k.ordinal_position),
+                    MAX(c.check_clause)
+             FROM information_schema.table_constraints t
+             LEFT JOIN information_schema.key_column_usage k
+               ON k.constraint_schema = t.constraint_schema
+              AND k.constraint_name = t.constraint_name AND k.table_name = t.table_name
+             LEFT JOIN information_schema.check_constraints c
+               ON c.constraint_schema = t.constraint_schema
+              AND c.constraint_name = t.constraint_name
+             WHERE t.table_schema = DATABASE() AND t.constraint_type IN ('CHECK', 'UNIQUE')
+             GROUP BY t.constraint_name, t.table_name, t.constraint_type
+             ORDER BY t.table_name, t.constraint_name",
+    ),
+    // Roles are accounts created locked, with an expired empty password
+    roles: Some(
+        "SELECT user FROM mysql.user
+             WHERE account_locked = 'Y' AND password_expired = 'Y'
+             AND authentication_string = ''
+             ORDER BY user",
+    ),
+    // Grantees come as 'name'@'host'
+    grants: Some(
+        "SELECT SUBSTRING_INDEX(SUBSTRING(grantee, 2), '''@', 1),
+                    table_name, NULL, privilege_type
+             FROM information_schema.table_privileges
+             WHERE table_schema = DATABASE()
+             UNION ALL
+             SELECT SUBSTRING_INDEX(SUBSTRING(grantee, 2), '''@', 1),
+                    table_name, column_name, privilege_type
+             FROM information_schema.column_privileges
+             WHERE table_schema = DATABASE()
+             ORDER BY 2, 1, 3, 4",
+    ),
+};
+
+impl Dialect for Mysql {
+    fn provider(&self) -> DatabaseProvider {
+        DatabaseProvider::Mysql
+    }
+
+    fn quote_ident(&self, ident: &str) -> String {
+        format!("`{}`", ident.replace('`', "``"))
+    }
+
+    // Backslash escapes unless the server runs with NO_BACKSLASH_ESCAPES
+    fn quote_literal(&self, value: &str) -> String {
+        format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+    }
+
+    fn column_type<'a>(&self, schema_type: &'a str, size: Option<usize>) -> Cow<'a, str> {
+        Cow::Borrowed(match schema_type {
+            "varchar" | "char" => {
+                return match size {
+                    Some(s) => Cow::Owned(format!("VARCHAR({})", s)),
+                    None => Cow::Borrowed("VARCHAR(255)"),
+                }
+            }
+            "decimal" => "DECIMAL(10, 2)",
+            "bigint" => "BIGINT",
+            "integer" => "INT",
+            "smallint" => "SMALLINT",
+            "float" | "double" => "DOUBLE",
+            "boolean" => "BOOLEAN",
+            "date" => "DATE",
+            "timestamp" | "timestamptz" => "DATETIME(6)",
+            "json" | "jsonb" => "JSON",
+            "text" => "TEXT",
+            "uuid" => "CHAR(36)",
+            "bytea" => "LONGBLOB",
+            _ => schema_type,
+        })
+    }
+
+    // CHAR(36) is how UUIDs are stored, not a VARCHAR
+    fn comparable_type(&self, data_type: &str, size: Option<usize>) -> String {
+        let data_type = data_type.trim().to_lowercase();
+        match (data_type.as_str(), size) {
+            ("char", Some(36)) => "char(36)".to_string(),
+            (data_type, size) => self
+                .column_type(schema_type_name(data_type), size)
+                .to_lowercase(),
+        }
+    }
+
+    fn introspection(&self) -> &'static IntrospectionQueries {
+        &MYSQL_INTROSPECTION
+    }
+
+    fn write_create_table(&self, out: &mut dyn Write, name: &str, table: &Table) -> fmt::Result {
+        writeln!(out, "CREATE TABLE {} (", name)?;
+
+        for (i, (col_name, col)) in table.columns.iter().enumerate() {
+            if i > 0 {
+                out.write_str(",\n")?;
+            }
+            write!(
+                out,
+                "  {} {} {}",
+                col_name,
+                self.column_type(&col.data_type, col.size),
+                if col.is_not_null() {
+                    "NOT NULL"
+                } else {
+                    "NULL"
+                }
+            )?;
+            if let Some(default) = &col.default {
+                write!(out, " DEFAULT {}", default)?;
+            }
+            if col.identity.is_some() {
+                out.write_str(" AUTO_INCREMENT")?;
+            }
+            if let Some(expression) = col.generated.as_ref().and_then(|g| g.expression.as_ref()) {
+                write!(out, " GENERATED ALWAYS AS ({}) STORED", expression)?;
+            }
+        }
+
+        let pk_cols: Vec<&str> = table
+            .columns
+            .iter()
+            .filter(|(_, c)| c.is_primary_key())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !pk_cols.is_empty() {
+            write!(out, ",\n  PRIMARY KEY ({})", pk_cols.join(", "))?;
+        }
+        write_foreign_keys(out, name, table, Some("MySQL"))?;
+        write_constraints(out, name, table, Some("MySQL"))?;
+
+        out.write_str("\n);")
+    }
+
+    // The default fills the rows already there, so NOT NULL can hold
+    fn add_column(&self, table: &str, column: &DbColumn) -> String {
+        let mut sql = format!(
+            "ALTER TABLE {} ADD COLUMN {} {} {}",
+            table,
+            column.name,
+            self.column_type(&column.data_type, column.size),
+            if column.is_nullable {
+                "NULL"
+            } else {
+                "NOT NULL"
+            }
+        );
+        if let Some(default) = &column.default_value {
+            sql.push_str(&format!(" DEFAULT {}", default));
+        }
+        sql.push(';');
+        sql
+    }
+
+    // There is no DROP COLUMN IF EXISTS
+    fn drop_column(&self, table: &str, column: &str) -> String {
+        format!("ALTER TABLE {} DROP COLUMN {};", table, column)
+    }
+
+    // MODIFY restates the whole column, whatever changed
+    fn alter_column(
+        &self,
+        table: &str,
+        column: &DbColumn,
+        _from: &DbColumn,
+        _changes: &[ColumnChange],
+    ) -> String {
+        let mut sql = format!(
+            "ALTER TABLE {} MODIFY COLUMN {} {} {}",
+            table,
+            column.name,
+            self.column_type(&column.data_type, column.size),
+            if column.is_nullable {
+                "NULL"
+            } else {
+                "NOT NULL"
+            }
+        );
+        if let Some(default) = &column.default_value {
+            sql.push_str(&format!(" DEFAULT {}", default));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_table(&self, table: &str) -> String {
+        format!("DROP TABLE IF EXISTS {};", table)
+    }
+
+    fn add_foreign_key(&self, table: &str, foreign_key: &DbForeignKey) -> String {
+        format!(
+            "ALTER TABLE {} ADD {};",
+            table,
+            foreign_key_clause(foreign_key)
+        )
+    }
+
+    fn drop_foreign_key(&self, table: &str, name: &str) -> String {
+        format!("ALTER TABLE {} DROP FOREIGN KEY {};", table, name)
+    }
+
+    fn add_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        match constraint.constraint_type {
+            ConstraintType::Exclude => format!(
+                "-- MySQL has no exclusion constraints: ALTER TABLE {} ADD {} skipped",
+                table,
+                constraint_clause(constraint)
+            ),
+            _ => format!(
+                "ALTER TABLE {} ADD {};",
+                table,
+                constraint_clause(constraint)
+            ),
+        }
+    }
+
+    // A unique constraint is its index; DROP CHECK is spoken by MariaDB too
+    fn drop_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        match constraint.constraint_type {
+            ConstraintType::Unique => {
+                format!("ALTER TABLE {} DROP INDEX {};", table, constraint.name)
+            }
+            ConstraintType::Exclude => format!(
+                "-- MySQL has no exclusion constraints: DROP CONSTRAINT {} skipped",
+                constraint.name
+            ),
+            _ => format!("ALTER TABLE {} DROP CHECK {};", table, constraint.name),
+        }
+    }
+
+    // Partial indexes and the other methods and options are PostgreSQL's
+    fn create_index(&self, table: &str, index: &Index) -> String {
+        let mut sql = format!(
+            "{} ({})",
+            create_index_head(table, index, false),
+            index.columns.join(", ")
+        );
+        if let Some(method @ ("btree" | "hash")) = index.method.as_ref().and_then(|m| m.sql()) {
+            sql.push_str(&format!(" USING {}", method.to_uppercase()));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_index(&self, table: &str, name: &str) -> String {
+        format!("DROP INDEX {} ON {};", name, table)
+    }
+
+    // Enums are column types, ENUM('a', 'b'), not types of their own
+    fn create_enum(&self, name: &str, _values: &[String]) -> String {
+        format!("-- MySQL has no enum types: CREATE TYPE {} skipped", name)
+    }
+
+    fn add_enum_value(
+        &self,
+        name: &str,
+        value: &str,
+        _after: Option<&str>,
+        _before: Option<&str>,
+    ) -> String {
+        format!(
+            "-- MySQL has no enum types: ALTER TYPE {} ADD VALUE {} skipped",
+            name,
+            self.quote_literal(value)
+        )
+    }
+
+    fn drop_enum(&self, name: &str) -> String {
+        format!("-- MySQL has no enum types: DROP TYPE {} skipped", name)
+    }
+
+    fn create_role(&self, role: &str) -> String {
+        format!("CREATE ROLE {};", role)
+    }
+
+    fn grant(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "GRANT {} ON {} TO {};",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    fn revoke(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "REVOKE {} ON {} FROM {};",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    fn create_migrations_table(&self, schema: Option<&str>, qualified_name: &str) -> String {
+        let mut sql = String::new();
+        if let Some(schema) = schema {
+            sql.push_str(&format!(
+                "CREATE DATABASE IF NOT EXISTS {};\n",
+                self.quote_ident(schema)
+            ));
+        }
+        sql.push_str(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n  \
+             id VARCHAR(255) PRIMARY KEY,\n  \
+             name TEXT NOT NULL,\n  \
+             checksum TEXT,\n  \
+             applied_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),\n  \
+             duration_ms BIGINT\n);",
+            qualified_name
+        ));
+        sql
+    }
+
+    // Matched against the quoted name, bare or qualified by its database
+    fn table_exists(&self, qualified_name: &str) -> String {
+        format!(
+            "SELECT MAX(table_name) AS name FROM information_schema.tables \
+             WHERE CONCAT('`', table_schema, '`.`', table_name, '`') = {0} \
+             OR (table_schema = DATABASE() AND CONCAT('`', table_name, '`') = {0})",
+            self.quote_literal(qualified_name)
+        )
+    }
+
+    fn lock(&self, key: &str) -> String {
+        format!("SELECT GET_LOCK({}, -1)", self.quote_literal(key))
+    }
+
+    fn unlock(&self, key: &str) -> String {
+        format!("SELECT RELEASE_LOCK({})", self.quote_literal(key))
+    }
+}
+
+/// SQLite 3.35 or later, for local file databases; the `main` database plays
+/// the part of PostgreSQL's `public` schema. There are no roles or
+/// privileges, so their statements are written as comments.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sqlite;
+
+static SQLITE_INTROSPECTION: IntrospectionQueries = IntrospectionQueries {
+    tables: "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND substr(name, 1, 7) <> 'sqlite_'
+             ORDER BY name",
+    // Declared types are split into name and length, as in VARCHAR(40)
+    columns: "SELECT m.name, p.name,
+                    lower(trim(CASE WHEN instr(p.type, '(') > 0
+                                    THEN substr(p.type, 1, instr(p.type, '(') - 1)
+                                    ELSE p.type END)),
+                    CASE WHEN p.\"notnull\" = 0 AND p.pk = 0 THEN 'YES' ELSE 'NO' END,
+                    p.dflt_value,
+                    CASE WHEN lower(p.type) LIKE '%char%(%'
+                         THEN CAST(substr(p.type, instr(p.type, '(') + 1) AS INTEGER) END
+             FROM sqlite_master m
+             JOIN pragma_table_info(m.name) p
+             WHERE m.type = 'table' AND substr(m.name, 1, 7) <> 'sqlite_'
+             ORDER BY m.name, p.cid",
+    primary_keys: "SELECT m.name, p.name
+             FROM sqlite_master m
+             JOIN pragma_table_info(m.name) p
+             WHERE m.type = 'table' AND p.pk > 0
+             ORDER BY m.name, p.pk",
+    enums: None,
+    // The indexes behind PRIMARY KEY and UNIQUE have no SQL
+    indexes: "SELECT tbl_name, name, sql FROM sqlite_master
+             WHERE type = 'index' AND sql IS NOT NULL
+             ORDER BY tbl_name, name",
+    // Foreign keys are unnamed, so they get PostgreSQL's default names
+    foreign_keys: "SELECT m.name || '_' || group_concat(f.\"from\", '_') || '_fkey', m.name,
+                    f.\"table\", group_concat(f.\"from\"), group_concat(f.\"to\"),
+                    f.on_delete, f.on_update
+             FROM sqlite_master m
+             JOIN pragma_foreign_key_list(m.name) f
+             WHERE m.type = 'table'
+             GROUP BY m.name, f.id, f.\"table\"
+             ORDER BY m.name, 1",
+    // Constraints are only in the CREATE TABLE text
+    constraints: None,
+    roles: None,
+    grants: None,
+};
+
+impl Dialect for Sqlite {
+    fn provider(&self) -> DatabaseProvider {
+        DatabaseProvider::Sqlite
+    }
+
+    fn quote_ident(&self, ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    fn quote_literal(&self, value: &str) -> String {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+
+    // Names SQLite gives the intended affinity; JSON and UUID would be NUMERIC
+    fn column_type<'a>(&self, schema_type: &'a str, size: Option<usize>) -> Cow<'a, str> {
+        Cow::Borrowed(match schema_type {
+            "varchar" | "char" => {
+                return match size {
+                    Some(s) => Cow::Owned(format!("VARCHAR({})", s)),
+                    None => Cow::Borrowed("VARCHAR(255)"),
+                }
+            }
+            "decimal" => "NUMERIC",
+            "bigint" | "integer" | "smallint" => "INTEGER",
+            "float" | "double" => "REAL",
+            "boolean" => "BOOLEAN",
+            "date" => "DATE",
+            "timestamp" | "timestamptz" => "TIMESTAMP",
+            "json" | "jsonb" | "text" | "uuid" => "TEXT",
+            "bytea" => "BLOB",
+            _ => schema_type,
+        })
+    }
+
+    fn introspection(&self) -> &'static IntrospectionQueries {
+        &SQLITE_INTROSPECTION
+    }
+
+    fn write_create_table(&self, out: &mut dyn Write, name: &str, table: &Table) -> fmt::Result {
+        writeln!(out, "CREATE TABLE {} (", name)?;
+
+        let pk_cols: Vec<&str> = table
+            .columns
+            .iter()
+            .filter(|(_, c)| c.is_primary_key())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        // Only a lone INTEGER PRIMARY KEY column can count up by itself
+        let rowid = match pk_cols.as_slice() {
+            [pk] if table.columns[*pk].identity.is_some() => Some(*pk),
+            _ => None,
+        };
+
+        for (i, (col_name, col)) in table.columns.iter().enumerate() {
+            if i > 0 {
+                out.write_str(",\n")?;
+            }
+            if rowid == Some(col_name.as_str()) {
+                write!(out, "  {} INTEGER PRIMARY KEY AUTOINCREMENT", col_name)?;
+                continue;
+            }
+            write!(
+                out,
+                "  {} {}",
+                col_name,
+                self.column_type(&col.data_type, col.size)
+            )?;
+            if col.is_not_null() {
+                out.write_str(" NOT NULL")?;
+            }
+            if let Some(default) = &col.default {
+                write!(out, " DEFAULT {}", default)?;
+            }
+            if let Some(expression) = col.generated.as_ref().and_then(|g| g.expression.as_ref()) {
+                write!(out, " GENERATED ALWAYS AS ({}) STORED", expression)?;
+            }
+        }
+
+        if !pk_cols.is_empty() && rowid.is_none() {
+            write!(out, ",\n  PRIMARY KEY ({})", pk_cols.join(", "))?;
+        }
+        write_foreign_keys(out, name, table, Some("SQLite"))?;
+        write_constraints(out, name, table, Some("SQLite"))?;
+
+        out.write_str("\n);")
+    }
+
+    // Only rebuilding the table changes a column or foreign key
+    fn alters_in_place(&self) -> bool {
+        false
+    }
+
+    // A NOT NULL column can only be added with a default
+    fn add_column(&self, table: &str, column: &DbColumn) -> String {
+        let mut sql = format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table,
+            column.name,
+            self.column_type(&column.data_type, column.size)
+        );
+        if !column.is_nullable {
+            sql.push_str(" NOT NULL");
+        }
+        if let Some(default) = &column.default_value {
+            sql.push_str(&format!(" DEFAULT {}", default));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_column(&self, table: &str, column: &str) -> String {
+        format!("ALTER TABLE {} DROP COLUMN {};", table, column)
+    }
+
+    // Columns cannot be altered in place, only by rebuilding the table
+    fn alter_column(
+        &self,
+        table: &str,
+        column: &DbColumn,
+        _from: &DbColumn,
+        changes: &[ColumnChange],
+    ) -> String {
+        let changes: Vec<&str> = changes.iter().map(ColumnChange::as_str).collect();
+        format!(
+            "-- SQLite cannot alter columns: rebuild {} to change the {} of {}",
+            table,
+            changes.join(", "),
+            column.name
+        )
+    }
+
+    fn drop_table(&self, table: &str) -> String {
+        format!("DROP TABLE IF EXISTS {};", table)
+    }
+
+    // Foreign keys are part of the table definition, fixed at CREATE TABLE
+    fn add_foreign_key(&self, table: &str, foreign_key: &DbForeignKey) -> String {
+        format!(
+            "-- SQLite cannot add a foreign key to an existing table: rebuild {} with {}",
+            table,
+            foreign_key_clause(foreign_key)
+        )
+    }
+
+    fn drop_foreign_key(&self, table: &str, name: &str) -> String {
+        format!(
+            "-- SQLite cannot drop a foreign key from an existing table: rebuild {} without {}",
+            table, name
+        )
+    }
+
+    // Constraints too are fixed at CREATE TABLE
+    fn add_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        format!(
+            "-- SQLite cannot add a constraint to an existing table: rebuild {} with {}",
+            table,
+            constraint_clause(constraint)
+        )
+    }
+
+    fn drop_constraint(&self, table: &str, constraint: &DbConstraint) -> String {
+        format!(
+            "-- SQLite cannot drop a constraint from an existing table: rebuild {} without {}",
+            table, constraint.name
+        )
+    }
+
+    // Indexes, unlike constraints, come and go; partial ones included
+    fn create_index(&self, table: &str, index: &Index) -> String {
+        let mut sql = format!(
+            "{} ({})",
+            create_index_head(table, index, true),
+            index.columns.join(", ")
+        );
+        if let Some(condition) = &index.where_clause {
+            sql.push_str(&format!(" WHERE {}", condition));
+        }
+        sql.push(';');
+        sql
+    }
+
+    fn drop_index(&self, _table: &str, name: &str) -> String {
+        format!("DROP INDEX IF EXISTS {};", name)
+    }
+
+    fn create_enum(&self, name: &str, _values: &[String]) -> String {
+        format!("-- SQLite has no enum types: CREATE TYPE {} skipped", name)
+    }
+
+    fn add_enum_value(
+        &self,
+        name: &str,
+        value: &str,
+        _after: Option<&str>,
+        _before: Option<&str>,
+    ) -> String {
+        format!(
+            "-- SQLite has no enum types: ALTER TYPE {} ADD VALUE {} skipped",
+            name,
+            self.quote_literal(value)
+        )
+    }
+
+    fn drop_enum(&self, name: &str) -> String {
+        format!("-- SQLite has no enum types: DROP TYPE {} skipped", name)
+    }
+
+    fn create_role(&self, role: &str) -> String {
+        format!("-- SQLite has no roles: CREATE ROLE {} skipped", role)
+    }
+
+    fn grant(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "-- SQLite has no privileges: GRANT {} ON {} TO {} skipped",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    fn revoke(&self, role: &str, table: &str, privileges: &[String], columns: &[String]) -> String {
+        format!(
+            "-- SQLite has no privileges: REVOKE {} ON {} FROM {} skipped",
+            privilege_list(privileges, columns),
+            table,
+            role
+        )
+    }
+
+    // Attached databases are the only other schemas, and are not created here
+    fn create_migrations_table(&self, _schema: Option<&str>, qualified_name: &str) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n  \
+             id TEXT PRIMARY KEY,\n  \
+             name TEXT NOT NULL,\n  \
+             checksum TEXT,\n  \
+             applied_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),\n  \
+             duration_ms BIGINT\n);",
+            qualified_name
+        )
+    }
+
+    // Matched against the quoted name, bare or qualified by `main`
+    fn table_exists(&self, qualified_name: &str) -> String {
+        format!(
+            "SELECT (SELECT name FROM sqlite_master WHERE type = 'table' \
+             AND ('\"' || replace(name, '\"', '\"\"') || '\"' = {0} \
+             OR '\"main\".\"' || replace(name, '\"', '\"\"') || '\"' = {0})) AS name",
+            self.quote_literal(qualified_name)
+        )
+    }
+
+    // Writers are serialized by the database file's lock, taken by each
+    // migration's BEGIN IMMEDIATE, so there is nothing more to hold
+    fn lock(&self, _key: &str) -> String {
+        "SELECT 1".to_string()
+    }
+
+    fn unlock(&self, _key: &str) -> String {
+        "SELECT 1".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialect_lookup_and_postgres_sql() {
+        assert_eq!(for_name("postgres").unwrap().name(), "postgresql");
+        assert_eq!(for_name("mariadb").unwrap().name(), "mysql");
+        assert_eq!(for_name("sqlite").unwrap().name(), "sqlite");
+        assert!(for_name("oracle").is_none());
+
+        let pg = DatabaseProvider::Postgresql.dialect();
+        assert_eq!(pg.quote_ident("my \"table\""), "\"my \"\"table\"\"\"");
+        assert_eq!(pg.quote_literal("it's"), "'it''s'");
+        assert_eq!(pg.column_type("varchar", Some(40)), "VARCHAR(40)");
+        assert_eq!(pg.column_type("citext", None), "citext");
+        assert_eq!(pg.column_type("varchar[][]", Some(20)), "VARCHAR(20)[][]");
+        assert_eq!(
+            pg.drop_column("users", "age"),
+            "ALTER TABLE users DROP COLUMN IF EXISTS age;"
+        );
+        let active = DbColumn {
+            name: "active".to_string(),
+            data_type: "boolean".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default_value: Some("true".to_string()),
+            size: None,
+        };
+        assert_eq!(
+            pg.add_column("users", &active),
+            "ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;"
+        );
+        let select = ["SELECT".to_string(), "UPDATE".to_string()];
+        assert_eq!(
+            pg.grant(
+                "app",
+                "users",
+                &select,
+                &["id".to_string(), "email".to_string()]
+            ),
+            "GRANT SELECT (id, email), UPDATE (id, email) ON users TO app;"
+        );
+        assert_eq!(
+            pg.revoke("app", "users", &select[..1], &[]),
+            "REVOKE SELECT ON users FROM app;"
+        );
+
+        let table: Table = serde_json::from_str(
+            r#"{ "name": "posts", "columns": {
+                "id": { "name": "id", "type": "bigint", "isPrimaryKey": true, "isNotNull": true },
+                "author_id": { "name": "author_id", "type": "bigint", "isNotNull": true,
+                               "references": { "table": "users", "column": "id",
+                                               "onDelete": "cascade" } }
+            } }"#,
+        )
+        .unwrap();
+        let mut sql = String::new();
+        pg.write_create_table(&mut sql, "posts", &table).unwrap();
+        assert_eq!(
+            sql,
+            "CREATE TABLE posts (\n  id BIGINT NOT NULL,\n  author_id BIGINT NOT NULL,\n  \
+             PRIMARY KEY (id),\n  CONSTRAINT posts_author_id_fkey FOREIGN KEY (author_id) \
+             REFERENCES users (id) ON DELETE CASCADE\n);"
+        );
+        assert_eq!(
+            pg.drop_foreign_key("posts", "posts_author_id_fkey"),
+            "ALTER TABLE posts DROP CONSTRAINT IF EXISTS posts_author_id_fkey;"
+        );
+    }
+
+    #[test]
+    fn test_index_sql() {
+        let index: Index = serde_json::from_str(
+            r#"{ "name": "idx_users_tags", "columns": ["tags"], "unique": true,
+                 "method": "gin", "with": { "fillfactor": 90, "fastupdate": false },
+                 "where": "tags IS NOT NULL" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Postgres.create_index("users", &index),
+            "CREATE UNIQUE INDEX idx_users_tags ON users USING gin (tags) \
+             WITH (fillfactor = 90, fastupdate = off) WHERE tags IS NOT NULL;"
+        );
+        assert_eq!(
+            Mysql.create_index("users", &index),
+            "CREATE UNIQUE INDEX idx_users_tags ON users (tags);"
+        );
+        assert_eq!(
+            Sqlite.create_index("users", &index),
+            "CREATE UNIQUE INDEX idx_users_tags ON users (tags) WHERE tags IS NOT NULL;"
+        );
+        assert_eq!(
+            Postgres.drop_index("users", "idx_users_tags"),
+            "DROP INDEX IF EXISTS idx_users_tags;"
+        );
+        assert_eq!(
+            Mysql.drop_index("users", "idx_users_tags"),
+            "DROP INDEX idx_users_tags ON users;"
+        );
+    }
+
+    #[test]
+    fn test_mysql_sql() {
+        let my = DatabaseProvider::Mysql.dialect();
+        assert_eq!(my.quote_ident("my `table`"), "`my ``table```");
+        assert_eq!(my.quote_literal(r"it's C:\"), r"'it''s C:\\'");
+        assert_eq!(my.column_type("uuid", None), "CHAR(36)");
+        assert_eq!(my.column_type("timestamptz", None), "DATETIME(6)");
+        assert_eq!(
+            my.drop_column("users", "age"),
+            "ALTER TABLE users DROP COLUMN age;"
+        );
+
+        let table: Table = serde_json::from_str(
+            r#"{ "name": "users", "columns": {
+                "id": { "name": "id", "type": "bigint", "isPrimaryKey": true, "isNotNull": true,
+                        "identity": {} },
+                "email": { "name": "email", "type": "varchar", "size": 120, "isNotNull": true },
+                "bio": { "name": "bio", "type": "text", "nullable": true }
+            } }"#,
+        )
+        .unwrap();
+        let mut sql = String::new();
+        my.write_create_table(&mut sql, "users", &table).unwrap();
+        assert_eq!(
+            sql,
+            "CREATE TABLE users (\n  id BIGINT NOT NULL AUTO_INCREMENT,\n  \
+             email VARCHAR(120) NOT NULL,\n  bio TEXT NULL,\n  PRIMARY KEY (id)\n);"
+        );
+        let email = DbColumn {
+            name: "email".to_string(),
+            data_type: "varchar".to_string(),
+            is_nullable: false,
+            is_primary_key: false,
+            default_value: Some("''".to_string()),
+            size: Some(80),
+        };
+        assert_eq!(
+            my.alter_column("users", &email, &email, &[ColumnChange::Type]),
+            "ALTER TABLE users MODIFY COLUMN email VARCHAR(80) NOT NULL DEFAULT '';"
+        );
+        assert_eq!(
+            my.add_column("users", &email),
+            "ALTER TABLE users ADD COLUMN email VARCHAR(80) NOT NULL DEFAULT '';"
+        );
+        assert_eq!(
+            my.comparable_type("char", Some(36)),
+            my.comparable_type("uuid", None)
+        );
+        assert_eq!(
+            my.comparable_type("tinyint", None),
+            my.comparable_type("boolean", None)
+        );
+        let unique = DbConstraint {
+            name: "users_email_key".to_string(),
+            constraint_type: ConstraintType::Unique,
+            columns: vec!["email".to_string()],
+            expression: None,
+            deferrable: false,
+            initially_deferred: false,
+        };
+        assert_eq!(
+            my.drop_constraint("users", &unique),
+            "ALTER TABLE users DROP INDEX users_email_key;"
+        );
+        assert_eq!(
+            my.lock("`_stratus_migrations`"),
+            "SELECT GET_LOCK('`_stratus_migrations`', -1)"
+        );
+    }
+
+    #[test]
+    fn test_sqlite_sql() {
+        let lite = DatabaseProvider::Sqlite.dialect();
+        assert_eq!(lite.column_type("uuid", None), "TEXT");
+        assert_eq!(lite.column_type("bigint", None), "INTEGER");
+
+        let table: Table = serde_json::from_str(
+            r#"{ "name": "users", "columns": {
+                "id": { "name": "id", "type": "bigint", "isPrimaryKey": true, "isNotNull": true,
+                        "identity": {} },
+                "email": { "name": "email", "type": "varchar", "size": 120, "isNotNull": true },
+                "bio": { "name": "bio", "type": "text", "nullable": true }
+            } }"#,
+        )
+        .unwrap();
+        let mut sql = String::new();
+        lite.write_create_table(&mut sql, "users", &table).unwrap();
+        assert_eq!(
+            sql,
+            "CREATE TABLE users (\n  id INTEGER PRIMARY KEY AUTOINCREMENT,\n  \
+             email VARCHAR(120) NOT NULL,\n  bio TEXT\n);"
+        );
+        let table: Table = serde_json::from_str(
+            r#"{ "columns": {
+                "room": { "name": "room", "type": "integer", "isUnique": true }
+            }, "constraints": [
+                { "name": "no_overlap", "constraintType": "exclude",
+                  "expression": "USING gist (room WITH =)" }
+            ] }"#,
+        )
+        .unwrap();
+        let mut sql = String::new();
+        lite.write_create_table(&mut sql, "bookings", &table)
+            .unwrap();
+        assert_eq!(
+            sql,
+            "CREATE TABLE bookings (\n  room INTEGER,\n  \
+             CONSTRAINT bookings_room_key UNIQUE (room)\n  \
+             -- SQLite has no exclusion constraints: \
+             CONSTRAINT no_overlap EXCLUDE USING gist (room WITH =) skipped\n);"
+        );
+        assert_eq!(
+            lite.create_role("app"),
+            "-- SQLite has no roles: CREATE ROLE app skipped"
+        );
+    }
+}
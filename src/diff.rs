@@ -0,0 +1,3307 @@
+/**
+ * Stratus Schema Diff Module
+ *
+ * Database schema snapshots and the diff from one to the desired schema.json,
+ * with the DDL to get there. Needs no database driver, so it is available in
+ * every build; introspection lives in the db module.
+ */
+use crate::dialect::{Dialect, Postgres};
+use crate::output::Mark;
+use crate::schema::ConstraintType;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::time::Instant;
+use tracing::{field, info_span};
+
+/// Table column definition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbColumn {
+    pub name: String,
+    pub data_type: String,
+    pub is_nullable: bool,
+    pub is_primary_key: bool,
+    pub default_value: Option<String>,
+    pub size: Option<usize>,
+}
+
+/// Table definition from database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbTable {
+    pub name: String,
+    pub columns: IndexMap<String, DbColumn>,
+    pub primary_key: Vec<String>,
+    /// Not defaulted, like `constraints`
+    pub foreign_keys: Vec<DbForeignKey>,
+    /// CHECK, UNIQUE and EXCLUDE constraints. Not defaulted, so introspection
+    /// caches written before constraints were read are missed, not read as
+    /// having none
+    pub constraints: Vec<DbConstraint>,
+    /// Indexes other than those behind the primary key, constraints and
+    /// foreign keys. Not defaulted, like `constraints`
+    pub indexes: Vec<DbIndex>,
+}
+
+impl DbTable {
+    /// Set the table's indexes from all those the catalog lists, leaving
+    /// out the ones that are the primary key's, a constraint's or a foreign
+    /// key's: those are diffed as what they back
+    pub fn set_indexes(&mut self, indexes: Vec<DbIndex>) {
+        self.indexes = indexes
+            .into_iter()
+            .filter(|index| {
+                let backs_key = self.constraints.iter().any(|c| c.name == index.name)
+                    || self.foreign_keys.iter().any(|fk| fk.name == index.name)
+                    || (index.unique && index.columns() == self.primary_key);
+                !backs_key
+            })
+            .collect();
+    }
+}
+
+/// Index definition from database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbIndex {
+    pub name: String,
+    pub unique: bool,
+    /// CREATE INDEX statement as reported by the database
+    pub definition: String,
+}
+
+/// What a CREATE INDEX statement says, read back from it
+#[derive(Default)]
+struct IndexParts {
+    /// As `USING` names it, lowercase
+    method: Option<String>,
+    /// Column names, unquoted, or expressions
+    columns: Vec<String>,
+    where_clause: Option<String>,
+}
+
+impl DbIndex {
+    /// The index as schema.json declares it, in `dialect`'s SQL
+    pub fn from_schema(dialect: &dyn Dialect, table: &str, index: &crate::schema::Index) -> Self {
+        let definition = dialect.create_index(table, index);
+        DbIndex {
+            name: index.name.clone(),
+            unique: index.unique,
+            definition: definition.trim_end_matches(';').to_string(),
+        }
+    }
+
+    /// The indexed columns or expressions
+    pub fn columns(&self) -> Vec<String> {
+        self.parts().columns
+    }
+
+    /// The definition's method, columns and condition: the list in
+    /// parentheses after `ON` and the table, what `USING` names before it
+    /// and what `WHERE` says after it
+    fn parts(&self) -> IndexParts {
+        let definition = &self.definition;
+        // ASCII lowercase keeps the byte offsets
+        let lower = definition.to_ascii_lowercase();
+        let Some(on) = lower.find(" on ") else {
+            return IndexParts::default();
+        };
+        let Some(open) = lower[on..].find('(').map(|i| on + i) else {
+            return IndexParts::default();
+        };
+        let method = lower[on..open]
+            .split_once(" using ")
+            .map(|(_, method)| method.trim().to_string());
+        let mut columns = Vec::new();
+        let mut column = String::new();
+        let mut depth = 0;
+        let mut close = definition.len();
+        for (i, c) in definition[open + 1..].char_indices() {
+            match c {
+                ')' if depth == 0 => {
+                    close = open + 1 + i;
+                    break;
+                }
+                ',' if depth == 0 => columns.push(std::mem::take(&mut column)),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            if !(c == ',' && depth == 0) {
+                column.push(c);
+            }
+        }
+        columns.push(column);
+        let columns = columns
+            .iter()
+            .map(|c| c.trim().trim_matches(|q| q == '"' || q == '`').to_string())
+            .filter(|c| !c.is_empty())
+            .collect();
+        let rest = &definition[(close + 1).min(definition.len())..];
+        let where_clause = rest
+            .to_ascii_lowercase()
+            .find(" where ")
+            .map(|i| unwrap_parentheses(rest[i + 7..].trim()).to_string());
+        IndexParts {
+            method,
+            columns,
+            where_clause,
+        }
+    }
+
+    /// Whether `index`, as schema.json declares it, is this one: the same
+    /// name, uniqueness and columns, compared as expressions are by
+    /// [`DbConstraint::same_as`], and the same method where the definition
+    /// names one. Conditions and storage options are not compared
+    pub fn same_as(&self, index: &crate::schema::Index) -> bool {
+        let parts = self.parts();
+        let method = index.method.as_ref().map_or(Some("btree"), |m| m.sql());
+        self.name == index.name
+            && self.unique == index.unique
+            && parts.columns.len() == index.columns.len()
+            && parts
+                .columns
+                .iter()
+                .zip(&index.columns)
+                .all(|(a, b)| comparable_expression(a) == comparable_expression(b))
+            && match (&parts.method, method) {
+                (Some(current), Some(desired)) => current == desired,
+                _ => true,
+            }
+    }
+
+    /// The index as schema.json would declare it
+    pub fn to_schema(&self) -> crate::schema::Index {
+        let parts = self.parts();
+        crate::schema::Index {
+            name: self.name.clone(),
+            columns: parts.columns,
+            unique: self.unique,
+            if_not_exists: false,
+            method: parts
+                .method
+                .filter(|m| m != "btree")
+                .and_then(|m| serde_json::from_value(serde_json::Value::String(m)).ok())
+                .filter(|m: &crate::schema::IndexMethod| m.sql().is_some()),
+            tablespace: None,
+            with: None,
+            where_clause: parts.where_clause,
+            nulls_not_distinct: None,
+        }
+    }
+}
+
+/// `UNIQUE idx_users_email ON users (email)`
+fn describe_index(table: &str, name: &str, unique: bool, columns: &[String]) -> String {
+    format!(
+        "{}{} ON {} ({})",
+        if unique { "UNIQUE " } else { "" },
+        name,
+        table,
+        columns.join(", ")
+    )
+}
+
+/// Foreign key from database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbForeignKey {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub references_table: String,
+    pub references_columns: Vec<String>,
+    /// ON DELETE action as SQL spells it, e.g. `CASCADE`; `None` for NO ACTION
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_delete: Option<String>,
+    /// ON UPDATE action, likewise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_update: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deferrable: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub initially_deferred: bool,
+}
+
+impl DbForeignKey {
+    /// PostgreSQL's name for an unnamed foreign key, `<table>_<columns>_fkey`
+    pub fn default_name(table: &str, columns: &[String]) -> String {
+        format!("{}_{}_fkey", table, columns.join("_"))
+    }
+
+    /// The foreign keys a schema.json table declares: `references` on a
+    /// column, and foreign key table constraints
+    pub fn from_table(table_name: &str, table: &crate::schema::Table) -> Vec<DbForeignKey> {
+        let mut foreign_keys = Vec::new();
+        for (column_name, column) in &table.columns {
+            if let Some(references) = &column.references {
+                let columns = vec![column_name.clone()];
+                foreign_keys.push(DbForeignKey {
+                    name: Self::default_name(table_name, &columns),
+                    columns,
+                    references_table: references.table.clone(),
+                    references_columns: vec![references.column.clone()],
+                    on_delete: references
+                        .on_delete
+                        .as_ref()
+                        .and_then(|a| a.sql())
+                        .map(str::to_string),
+                    on_update: references
+                        .on_update
+                        .as_ref()
+                        .and_then(|a| a.sql())
+                        .map(str::to_string),
+                    deferrable: false,
+                    initially_deferred: false,
+                });
+            }
+        }
+        for constraint in table.constraints.iter().flatten() {
+            let (ConstraintType::ForeignKey, Some(references)) =
+                (&constraint.constraint_type, &constraint.references)
+            else {
+                continue;
+            };
+            foreign_keys.push(DbForeignKey {
+                name: constraint
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| Self::default_name(table_name, &constraint.columns)),
+                columns: constraint.columns.clone(),
+                references_table: references.table.clone(),
+                references_columns: references
+                    .column
+                    .split(',')
+                    .map(|c| c.trim().to_string())
+                    .collect(),
+                on_delete: references
+                    .on_delete
+                    .as_ref()
+                    .and_then(|a| a.sql())
+                    .map(str::to_string),
+                on_update: references
+                    .on_update
+                    .as_ref()
+                    .and_then(|a| a.sql())
+                    .map(str::to_string),
+                deferrable: constraint.deferrable,
+                initially_deferred: constraint.initially_deferred,
+            });
+        }
+        foreign_keys
+    }
+
+    /// Whether both keys enforce the same reference, whatever their names
+    pub fn same_as(&self, other: &DbForeignKey) -> bool {
+        self.columns == other.columns
+            && self.references_table == other.references_table
+            && self.references_columns == other.references_columns
+            && self.on_delete == other.on_delete
+            && self.on_update == other.on_update
+            && self.deferral() == other.deferral()
+    }
+
+    /// ` DEFERRABLE`, ` DEFERRABLE INITIALLY DEFERRED` or nothing
+    pub fn deferral(&self) -> &'static str {
+        deferral(self.deferrable, self.initially_deferred)
+    }
+
+    /// `posts (user_id) -> users (id) ON DELETE CASCADE`
+    fn describe(&self, table: &str) -> String {
+        let mut text = format!(
+            "{} ({}) -> {} ({})",
+            table,
+            self.columns.join(", "),
+            self.references_table,
+            self.references_columns.join(", ")
+        );
+        if let Some(action) = &self.on_delete {
+            text.push_str(&format!(" ON DELETE {}", action));
+        }
+        if let Some(action) = &self.on_update {
+            text.push_str(&format!(" ON UPDATE {}", action));
+        }
+        text.push_str(self.deferral());
+        text
+    }
+}
+
+/// How a constraint is checked, as SQL appends it to the constraint; an
+/// initially deferred constraint is deferrable whatever `deferrable` says
+fn deferral(deferrable: bool, initially_deferred: bool) -> &'static str {
+    match (deferrable, initially_deferred) {
+        (_, true) => " DEFERRABLE INITIALLY DEFERRED",
+        (true, false) => " DEFERRABLE",
+        (false, false) => "",
+    }
+}
+
+/// `definition` without a trailing `DEFERRABLE [INITIALLY DEFERRED]`, and
+/// the deferral it had
+fn strip_deferral(definition: &str) -> (&str, bool, bool) {
+    if let Some(rest) = definition.strip_suffix(" DEFERRABLE INITIALLY DEFERRED") {
+        (rest, true, true)
+    } else if let Some(rest) = definition.strip_suffix(" DEFERRABLE") {
+        (rest, true, false)
+    } else {
+        (definition, false, false)
+    }
+}
+
+/// A referential action as a catalog reports it, in [`DbForeignKey`]'s form
+#[cfg(feature = "db")]
+pub(crate) fn referential_action(rule: &str) -> Option<String> {
+    crate::schema::OnDeleteAction::from_sql(rule)
+        .sql()
+        .map(str::to_string)
+}
+
+/// CHECK, UNIQUE or EXCLUDE constraint from database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbConstraint {
+    pub name: String,
+    pub constraint_type: ConstraintType,
+    /// The unique columns; for a CHECK or EXCLUDE, the columns it names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
+    /// The CHECK expression, or what follows EXCLUDE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deferrable: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub initially_deferred: bool,
+}
+
+impl DbConstraint {
+    /// PostgreSQL's name for an unnamed constraint, e.g. `<table>_<columns>_key`
+    pub fn default_name(
+        table: &str,
+        constraint_type: ConstraintType,
+        columns: &[String],
+    ) -> String {
+        let suffix = match constraint_type {
+            ConstraintType::Unique => "key",
+            ConstraintType::Exclude => "excl",
+            ConstraintType::PrimaryKey => "pkey",
+            ConstraintType::ForeignKey => "fkey",
+            ConstraintType::Check => "check",
+        };
+        if columns.is_empty() {
+            format!("{}_{}", table, suffix)
+        } else {
+            format!("{}_{}_{}", table, columns.join("_"), suffix)
+        }
+    }
+
+    /// The constraints a schema.json table declares: `isUnique` on a column,
+    /// and CHECK, UNIQUE and EXCLUDE table constraints. Unnamed ones are
+    /// named as PostgreSQL would, numbered when the name is taken.
+    pub fn from_table(table_name: &str, table: &crate::schema::Table) -> Vec<DbConstraint> {
+        let mut constraints: Vec<DbConstraint> = Vec::new();
+        let mut push = |name: Option<&String>, constraint: DbConstraint| {
+            let mut unique_name = name.cloned().unwrap_or_else(|| constraint.name.clone());
+            let mut n = 0;
+            while name.is_none() && constraints.iter().any(|c| c.name == unique_name) {
+                n += 1;
+                unique_name = format!("{}{}", constraint.name, n);
+            }
+            constraints.push(DbConstraint {
+                name: unique_name,
+                ..constraint
+            });
+        };
+        for (column_name, column) in &table.columns {
+            if column.is_unique() && !column.is_primary_key() {
+                let columns = vec![column_name.clone()];
+                push(
+                    None,
+                    DbConstraint {
+                        name: Self::default_name(table_name, ConstraintType::Unique, &columns),
+                        constraint_type: ConstraintType::Unique,
+                        columns,
+                        expression: None,
+                        deferrable: false,
+                        initially_deferred: false,
+                    },
+                );
+            }
+        }
+        for constraint in table.constraints.iter().flatten() {
+            let constraint_type = constraint.constraint_type;
+            if !matches!(
+                constraint_type,
+                ConstraintType::Check | ConstraintType::Unique | ConstraintType::Exclude
+            ) {
+                continue;
+            }
+            push(
+                constraint.name.as_ref(),
+                DbConstraint {
+                    name: Self::default_name(table_name, constraint_type, &constraint.columns),
+                    constraint_type,
+                    columns: constraint.columns.clone(),
+                    expression: match constraint_type {
+                        ConstraintType::Unique => None,
+                        _ => constraint.expression.clone(),
+                    },
+                    deferrable: constraint.deferrable,
+                    initially_deferred: constraint.initially_deferred,
+                },
+            );
+        }
+        constraints
+    }
+
+    /// A constraint as a catalog reports it: its type keyword, and its
+    /// definition as `pg_get_constraintdef` writes it or the bare expression
+    pub fn from_catalog(
+        name: String,
+        constraint_type: &str,
+        columns: Vec<String>,
+        definition: &str,
+    ) -> Option<DbConstraint> {
+        let constraint_type = ConstraintType::from_sql(constraint_type)?;
+        let definition = definition.trim();
+        let definition = definition.strip_suffix(" NOT VALID").unwrap_or(definition);
+        let (definition, deferrable, initially_deferred) = strip_deferral(definition);
+        let expression = match constraint_type {
+            ConstraintType::Unique => None,
+            _ => {
+                let expression = definition
+                    .strip_prefix(constraint_type.sql())
+                    .unwrap_or(definition)
+                    .trim();
+                // CHECK ((a > 0)) has one pair of parentheses to spare
+                let expression = match constraint_type {
+                    ConstraintType::Check => unwrap_parentheses(expression),
+                    _ => expression,
+                };
+                Some(expression.to_string())
+            }
+        };
+        Some(DbConstraint {
+            name,
+            constraint_type,
+            columns,
+            expression,
+            deferrable,
+            initially_deferred,
+        })
+    }
+
+    /// Whether both constraints enforce the same rule: UNIQUE ones over the
+    /// same columns whatever their names, CHECK and EXCLUDE ones of the same
+    /// name and expression
+    ///
+    /// Expressions are compared without spaces, parentheses, quotes, casts
+    /// or case, and with PostgreSQL's `= ANY (ARRAY[...])` read as `IN`, so
+    /// `price > 0` matches its `(price > (0)::numeric)`; other rewrites read
+    /// as a change.
+    pub fn same_as(&self, other: &DbConstraint) -> bool {
+        let expression = |c: &DbConstraint| c.expression.as_deref().map(comparable_expression);
+        self.constraint_type == other.constraint_type
+            && self.deferral() == other.deferral()
+            && match self.constraint_type {
+                ConstraintType::Unique => self.columns == other.columns,
+                _ => self.name == other.name && expression(self) == expression(other),
+            }
+    }
+
+    /// `CHECK (total >= 0)`, or `UNIQUE (a, b)`
+    pub fn definition(&self) -> String {
+        match self.constraint_type {
+            ConstraintType::Check => {
+                format!("CHECK ({})", self.expression.as_deref().unwrap_or_default())
+            }
+            ConstraintType::Exclude => {
+                format!("EXCLUDE {}", self.expression.as_deref().unwrap_or_default())
+            }
+            constraint_type => format!("{} ({})", constraint_type.sql(), self.columns.join(", ")),
+        }
+    }
+
+    /// ` DEFERRABLE`, ` DEFERRABLE INITIALLY DEFERRED` or nothing
+    pub fn deferral(&self) -> &'static str {
+        deferral(self.deferrable, self.initially_deferred)
+    }
+
+    /// `orders CHECK (total >= 0)`
+    fn describe(&self, table: &str) -> String {
+        format!("{} {}{}", table, self.definition(), self.deferral())
+    }
+}
+
+/// `expression` without one pair of parentheses around all of it
+fn unwrap_parentheses(expression: &str) -> &str {
+    let Some(inner) = expression
+        .strip_prefix('(')
+        .and_then(|e| e.strip_suffix(')'))
+    else {
+        return expression;
+    };
+    // (a) AND (b) is wrapped twice, not once
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return expression,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    inner
+}
+
+/// An expression without what catalogs add or drop when they print it:
+/// spaces, parentheses, identifier quotes, `::type` casts and case, and with
+/// `= ANY (ARRAY[...])` and `<> ALL (ARRAY[...])` as the `IN` lists they were
+fn comparable_expression(expression: &str) -> String {
+    let mut text: String = expression
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | '`'))
+        .collect::<String>()
+        .to_lowercase();
+    while let Some(i) = text.find("::") {
+        let end = text[i + 2..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '[' | ']')))
+            .map_or(text.len(), |n| i + 2 + n);
+        text.replace_range(i..end, "");
+    }
+    for (array, list) in [("=anyarray[", "in"), ("<>allarray[", "notin")] {
+        while let Some(i) = text.find(array) {
+            let close = text[i..].find(']').map_or(text.len(), |n| i + n);
+            text.replace_range(close..(close + 1).min(text.len()), "");
+            text.replace_range(i..i + array.len(), list);
+        }
+    }
+    text
+}
+
+/// One privilege of a role, on a table or one of its columns
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DbGrant {
+    pub role: String,
+    pub table: String,
+    /// `None` for a privilege on the whole table
+    pub column: Option<String>,
+    pub privilege: String,
+}
+
+impl DbGrant {
+    /// The privileges schema.json grants, one per column and privilege
+    pub fn from_schema(grants: &[crate::schema::Grant]) -> Vec<DbGrant> {
+        let mut rows = Vec::new();
+        for grant in grants {
+            for privilege in grant.privilege_list() {
+                let columns: Vec<Option<String>> = if grant.columns.is_empty() {
+                    vec![None]
+                } else {
+                    grant.columns.iter().cloned().map(Some).collect()
+                };
+                for column in columns {
+                    rows.push(DbGrant {
+                        role: grant.role.clone(),
+                        table: grant.table.clone(),
+                        column,
+                        privilege: privilege.clone(),
+                    });
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// Database schema
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DbSchema {
+    pub tables: IndexMap<String, DbTable>,
+    pub enums: IndexMap<String, Vec<String>>,
+    pub dialect: String,
+    /// Roles that exist. Not defaulted, like `DbTable::constraints`
+    pub roles: Vec<String>,
+    /// Privileges held by roles other than the table owners
+    pub grants: Vec<DbGrant>,
+}
+
+/// What about a column an [`Operation::AlterColumn`] changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnChange {
+    /// Type or size
+    Type,
+    Nullability,
+    Default,
+}
+
+impl ColumnChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Type => "type",
+            Self::Nullability => "nullability",
+            Self::Default => "default",
+        }
+    }
+}
+
+/// One DDL step of a schema diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    DropColumn {
+        table: String,
+        column: String,
+    },
+    DropTable {
+        table: String,
+    },
+    CreateTable {
+        table: String,
+        definition: crate::schema::Table,
+    },
+    AddColumn {
+        table: String,
+        column: DbColumn,
+    },
+    /// Change a column's type, nullability or default to the ones `column` has
+    AlterColumn {
+        table: String,
+        column: DbColumn,
+        /// The column as it is, for the rollback and the data loss warning
+        from: DbColumn,
+        changes: Vec<ColumnChange>,
+    },
+    AddForeignKey {
+        table: String,
+        foreign_key: DbForeignKey,
+    },
+    /// Drop `foreign_key`, named as in the database; kept whole for the rollback
+    DropForeignKey {
+        table: String,
+        foreign_key: DbForeignKey,
+    },
+    AddConstraint {
+        table: String,
+        constraint: DbConstraint,
+    },
+    /// Drop `constraint`, named as in the database; kept whole for the rollback
+    DropConstraint {
+        table: String,
+        constraint: DbConstraint,
+    },
+    CreateIndex {
+        table: String,
+        index: crate::schema::Index,
+    },
+    /// Drop `index`, named as in the database; kept whole for the rollback
+    DropIndex {
+        table: String,
+        index: DbIndex,
+    },
+    CreateEnum {
+        name: String,
+        values: Vec<String>,
+    },
+    /// Add `value` to an enum type, placed after or before an existing value
+    AddEnumValue {
+        name: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        after: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        before: Option<String>,
+    },
+    /// Drop an enum type; its `values` are kept for the rollback
+    DropEnum {
+        name: String,
+        values: Vec<String>,
+    },
+    CreateRole {
+        role: String,
+    },
+    /// Privileges on a table, or on its `columns` when there are any
+    Grant {
+        role: String,
+        table: String,
+        privileges: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        columns: Vec<String>,
+    },
+    Revoke {
+        role: String,
+        table: String,
+        privileges: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        columns: Vec<String>,
+    },
+}
+
+impl Operation {
+    /// The table the operation changes, if it changes one
+    pub fn table(&self) -> Option<&str> {
+        match self {
+            Self::DropColumn { table, .. }
+            | Self::DropTable { table }
+            | Self::CreateTable { table, .. }
+            | Self::AddColumn { table, .. }
+            | Self::AlterColumn { table, .. }
+            | Self::AddForeignKey { table, .. }
+            | Self::DropForeignKey { table, .. }
+            | Self::AddConstraint { table, .. }
+            | Self::DropConstraint { table, .. }
+            | Self::CreateIndex { table, .. }
+            | Self::DropIndex { table, .. }
+            | Self::Grant { table, .. }
+            | Self::Revoke { table, .. } => Some(table),
+            Self::CreateEnum { .. }
+            | Self::AddEnumValue { .. }
+            | Self::DropEnum { .. }
+            | Self::CreateRole { .. } => None,
+        }
+    }
+
+    /// What the operation loses, for operations that drop data
+    pub fn data_loss(&self) -> Option<String> {
+        match self {
+            Self::DropColumn { table, column } => {
+                Some(format!("Column '{}.{}' will be dropped", table, column))
+            }
+            Self::DropTable { table } => {
+                Some(format!("Table '{}' will be dropped with all data", table))
+            }
+            Self::AlterColumn {
+                table,
+                column,
+                from,
+                changes,
+            } if changes.contains(&ColumnChange::Type) && !widens(from, column) => Some(format!(
+                "Column '{}.{}' changes type from {} to {}; values that do not convert are lost",
+                table,
+                column.name,
+                type_label(from),
+                type_label(column)
+            )),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation only creates or adds, leaving everything that
+    /// exists as it is
+    pub fn is_additive(&self) -> bool {
+        match self {
+            Self::CreateTable { .. }
+            | Self::AddColumn { .. }
+            | Self::AddForeignKey { .. }
+            | Self::AddConstraint { .. }
+            | Self::CreateIndex { .. }
+            | Self::CreateEnum { .. }
+            | Self::AddEnumValue { .. }
+            | Self::CreateRole { .. } => true,
+            Self::DropColumn { .. }
+            | Self::DropTable { .. }
+            | Self::AlterColumn { .. }
+            | Self::DropForeignKey { .. }
+            | Self::DropConstraint { .. }
+            | Self::DropIndex { .. }
+            | Self::DropEnum { .. }
+            | Self::Grant { .. }
+            | Self::Revoke { .. } => false,
+        }
+    }
+
+    /// The operation as JSON with sorted keys (serde_json's map is ordered
+    /// by key), lowercase type names and sorted privilege lists
+    fn canonical(&self) -> String {
+        let mut operation = self.clone();
+        match &mut operation {
+            Self::AddColumn { column, .. } => {
+                column.data_type = canonical_type(&column.data_type);
+            }
+            Self::AlterColumn { column, from, .. } => {
+                column.data_type = canonical_type(&column.data_type);
+                from.data_type = canonical_type(&from.data_type);
+            }
+            Self::CreateTable { definition, .. } => {
+                for column in definition.columns.values_mut() {
+                    column.data_type = canonical_type(&column.data_type);
+                }
+            }
+            Self::Grant {
+                privileges,
+                columns,
+                ..
+            }
+            | Self::Revoke {
+                privileges,
+                columns,
+                ..
+            } => {
+                privileges.sort();
+                columns.sort();
+            }
+            Self::DropColumn { .. }
+            | Self::DropTable { .. }
+            | Self::AddForeignKey { .. }
+            | Self::DropForeignKey { .. }
+            | Self::AddConstraint { .. }
+            | Self::DropConstraint { .. }
+            | Self::CreateIndex { .. }
+            | Self::DropIndex { .. }
+            | Self::CreateEnum { .. }
+            | Self::AddEnumValue { .. }
+            | Self::DropEnum { .. }
+            | Self::CreateRole { .. } => {}
+        }
+        serde_json::to_value(&operation)
+            .expect("operations serialize")
+            .to_string()
+    }
+
+    /// Write the operation's SQL in `dialect`
+    pub fn write_sql(&self, dialect: &dyn Dialect, out: &mut dyn Write) -> fmt::Result {
+        match self {
+            Self::DropColumn { table, column } => {
+                writeln!(out, "{}", dialect.drop_column(table, column))
+            }
+            Self::DropTable { table } => writeln!(out, "{}", dialect.drop_table(table)),
+            Self::CreateTable { table, definition } => {
+                write!(out, "\n-- Create table {}\n", table)?;
+                dialect.write_create_table(out, table, definition)?;
+                out.write_char('\n')
+            }
+            Self::AddColumn { table, column } => {
+                writeln!(out, "{}", dialect.add_column(table, column))
+            }
+            Self::AlterColumn {
+                table,
+                column,
+                from,
+                changes,
+            } => writeln!(
+                out,
+                "{}",
+                dialect.alter_column(table, column, from, changes)
+            ),
+            Self::AddForeignKey { table, foreign_key } => {
+                writeln!(out, "{}", dialect.add_foreign_key(table, foreign_key))
+            }
+            Self::DropForeignKey { table, foreign_key } => {
+                writeln!(
+                    out,
+                    "{}",
+                    dialect.drop_foreign_key(table, &foreign_key.name)
+                )
+            }
+            Self::AddConstraint { table, constraint } => {
+                writeln!(out, "{}", dialect.add_constraint(table, constraint))
+            }
+            Self::DropConstraint { table, constraint } => {
+                writeln!(out, "{}", dialect.drop_constraint(table, constraint))
+            }
+            Self::CreateIndex { table, index } => {
+                writeln!(out, "{}", dialect.create_index(table, index))
+            }
+            Self::DropIndex { table, index } => {
+                writeln!(out, "{}", dialect.drop_index(table, &index.name))
+            }
+            Self::CreateEnum { name, values } => {
+                writeln!(out, "{}", dialect.create_enum(name, values))
+            }
+            Self::AddEnumValue {
+                name,
+                value,
+                after,
+                before,
+            } => writeln!(
+                out,
+                "{}",
+                dialect.add_enum_value(name, value, after.as_deref(), before.as_deref())
+            ),
+            Self::DropEnum { name, .. } => writeln!(out, "{}", dialect.drop_enum(name)),
+            Self::CreateRole { role } => writeln!(out, "{}", dialect.create_role(role)),
+            Self::Grant {
+                role,
+                table,
+                privileges,
+                columns,
+            } => writeln!(out, "{}", dialect.grant(role, table, privileges, columns)),
+            Self::Revoke {
+                role,
+                table,
+                privileges,
+                columns,
+            } => writeln!(out, "{}", dialect.revoke(role, table, privileges, columns)),
+        }
+    }
+}
+
+/// `SELECT, UPDATE on t to r`, or `SELECT (a, b) on t to r` for columns
+fn describe_privileges(
+    privileges: &[String],
+    table: &str,
+    columns: &[String],
+    preposition: &str,
+    role: &str,
+) -> String {
+    if columns.is_empty() {
+        format!(
+            "{} on {} {} {}",
+            privileges.join(", "),
+            table,
+            preposition,
+            role
+        )
+    } else {
+        format!(
+            "{} ({}) on {} {} {}",
+            privileges.join(", "),
+            columns.join(", "),
+            table,
+            preposition,
+            role
+        )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DropColumn { table, column } => write!(f, "drop column {}.{}", table, column),
+            Self::DropTable { table } => write!(f, "drop table {}", table),
+            Self::CreateTable { table, .. } => write!(f, "create table {}", table),
+            Self::AddColumn { table, column } => {
+                write!(f, "add column {}.{}", table, column.name)
+            }
+            Self::AlterColumn {
+                table,
+                column,
+                changes,
+                ..
+            } => {
+                let changes: Vec<&str> = changes.iter().map(ColumnChange::as_str).collect();
+                write!(
+                    f,
+                    "alter column {}.{} ({})",
+                    table,
+                    column.name,
+                    changes.join(", ")
+                )
+            }
+            Self::AddForeignKey { table, foreign_key } => {
+                write!(f, "add foreign key {}.{}", table, foreign_key.name)
+            }
+            Self::DropForeignKey { table, foreign_key } => {
+                write!(f, "drop foreign key {}.{}", table, foreign_key.name)
+            }
+            Self::AddConstraint { table, constraint } => {
+                write!(f, "add constraint {}.{}", table, constraint.name)
+            }
+            Self::DropConstraint { table, constraint } => {
+                write!(f, "drop constraint {}.{}", table, constraint.name)
+            }
+            Self::CreateIndex { table, index } => {
+                write!(f, "create index {}.{}", table, index.name)
+            }
+            Self::DropIndex { table, index } => {
+                write!(f, "drop index {}.{}", table, index.name)
+            }
+            Self::CreateEnum { name, .. } => write!(f, "create enum {}", name),
+            Self::AddEnumValue { name, value, .. } => {
+                write!(f, "add enum value {}.{}", name, value)
+            }
+            Self::DropEnum { name, .. } => write!(f, "drop enum {}", name),
+            Self::CreateRole { role } => write!(f, "create role {}", role),
+            Self::Grant {
+                role,
+                table,
+                privileges,
+                columns,
+            } => write!(
+                f,
+                "grant {}",
+                describe_privileges(privileges, table, columns, "to", role)
+            ),
+            Self::Revoke {
+                role,
+                table,
+                privileges,
+                columns,
+            } => write!(
+                f,
+                "revoke {}",
+                describe_privileges(privileges, table, columns, "from", role)
+            ),
+        }
+    }
+}
+
+/// `varchar(40)`, or `text` for a type without a size
+fn type_label(column: &DbColumn) -> String {
+    match column.size {
+        Some(size) => format!("{}({})", column.data_type, size),
+        None => column.data_type.clone(),
+    }
+}
+
+/// Whether every value of `from`'s type fits `to`'s: a longer string, a
+/// wider integer or float, or text
+fn widens(from: &DbColumn, to: &DbColumn) -> bool {
+    const INTEGERS: [&str; 3] = ["smallint", "integer", "bigint"];
+    let (from_type, to_type) = (from.data_type.to_lowercase(), to.data_type.to_lowercase());
+    let from_type = crate::dialect::schema_type_name(&from_type);
+    let to_type = crate::dialect::schema_type_name(&to_type);
+    let rank = |name: &str| INTEGERS.iter().position(|t| *t == name);
+    match (from_type, to_type) {
+        (_, "text") => true,
+        ("varchar" | "char", "varchar") => match (from.size, to.size) {
+            (Some(from_size), Some(to_size)) => from_size <= to_size,
+            (_, None) => true,
+            (None, Some(_)) => false,
+        },
+        ("float", "double") => true,
+        (from_type, to_type) => match (rank(from_type), rank(to_type)) {
+            (Some(from_rank), Some(to_rank)) => from_rank <= to_rank,
+            _ => false,
+        },
+    }
+}
+
+/// A column default in one spelling, so the catalog's form of it matches
+/// schema.json's: PostgreSQL's `::type` casts and wrapping parentheses
+/// removed, string literals unquoted (MySQL reports them bare), booleans as
+/// 1 and 0 and the rest lowercase
+fn comparable_default(default: &str) -> Option<String> {
+    let mut value = default.trim();
+    loop {
+        match value.rfind("::") {
+            Some(i) if !value[i..].contains('\'') => value = value[..i].trim_end(),
+            _ if value.len() > 1 && value.starts_with('(') && value.ends_with(')') => {
+                value = value[1..value.len() - 1].trim()
+            }
+            _ => break,
+        }
+    }
+    if value.len() > 1 && value.starts_with('\'') && value.ends_with('\'') {
+        return Some(value[1..value.len() - 1].replace("''", "'"));
+    }
+    match value.to_lowercase().as_str() {
+        "null" => None,
+        "true" => Some("1".to_string()),
+        "false" => Some("0".to_string()),
+        "now()" | "current_timestamp()" => Some("current_timestamp".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// How `current` must change to match the schema.json column `desired`
+///
+/// Types are compared as the dialect writes them, so `character varying(40)`
+/// from the catalog matches `varchar` of size 40. Array types, sequence
+/// defaults and the nullability of primary keys are left alone.
+fn column_changes(
+    dialect: &dyn Dialect,
+    desired: &crate::schema::Column,
+    current: &DbColumn,
+) -> Vec<ColumnChange> {
+    let mut changes = Vec::new();
+    let array =
+        desired.array_dimensions.is_some() || current.data_type.eq_ignore_ascii_case("array");
+    if !array
+        && dialect.comparable_type(&desired.data_type, desired.size)
+            != dialect.comparable_type(&current.data_type, current.size)
+    {
+        changes.push(ColumnChange::Type);
+    }
+    // A serial is NOT NULL without saying so
+    let serial = crate::dialect::is_serial(&desired.data_type);
+    if !desired.is_primary_key() && (desired.is_not_null() || serial) == current.is_nullable {
+        changes.push(ColumnChange::Nullability);
+    }
+    let generated = serial
+        || desired.identity.is_some()
+        || desired.generated.is_some()
+        || current
+            .default_value
+            .as_deref()
+            .is_some_and(|d| d.starts_with("nextval("));
+    if !generated
+        && desired.default.as_deref().and_then(comparable_default)
+            != current
+                .default_value
+                .as_deref()
+                .and_then(comparable_default)
+    {
+        changes.push(ColumnChange::Default);
+    }
+    changes
+}
+
+/// A type name with its case and spacing normalized, e.g. `character varying`
+fn canonical_type(data_type: &str) -> String {
+    data_type
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Result of schema comparison: the operations to run, in order, and the
+/// summaries derived from them. The SQL is written from the operations on
+/// demand, with [`SchemaDiff::write_sql`] or [`SchemaDiff::sql`]
+#[derive(Debug, Default, Serialize)]
+pub struct SchemaDiff {
+    pub operations: Vec<Operation>,
+    pub create_tables: Vec<String>,
+    pub alter_tables: Vec<String>,
+    pub drop_tables: Vec<String>,
+    pub create_columns: IndexMap<String, Vec<DbColumn>>,
+    pub alter_columns: IndexMap<String, Vec<DbColumn>>,
+    pub drop_columns: IndexMap<String, Vec<String>>,
+    /// Foreign keys to add, as `posts (user_id) -> users (id)`
+    pub add_foreign_keys: Vec<String>,
+    /// Foreign keys to drop, likewise
+    pub drop_foreign_keys: Vec<String>,
+    /// CHECK, UNIQUE and EXCLUDE constraints to add, as `orders CHECK (total >= 0)`
+    pub add_constraints: Vec<String>,
+    /// Constraints to drop, likewise
+    pub drop_constraints: Vec<String>,
+    /// Indexes to create, as `UNIQUE idx_users_email ON users (email)`
+    pub create_indexes: Vec<String>,
+    /// Indexes to drop, likewise
+    pub drop_indexes: Vec<String>,
+    pub create_enums: Vec<String>,
+    /// Values to add, by enum type
+    pub add_enum_values: IndexMap<String, Vec<String>>,
+    pub drop_enums: Vec<String>,
+    pub create_roles: Vec<String>,
+    /// Privileges to grant, as `SELECT on t to r`
+    pub grants: Vec<String>,
+    /// Privileges to revoke, as `SELECT on t from r`
+    pub revokes: Vec<String>,
+    pub data_loss_warning: Vec<String>,
+    /// Changes schema.json asks for that the SQL cannot make, such as
+    /// removing an enum value
+    pub warnings: Vec<String>,
+    /// Dialect of the database diffed, which the SQL is written in
+    pub dialect: String,
+}
+
+/// A diff serialized together with its SQL
+#[derive(Debug, Serialize)]
+pub struct DiffWithSql<'a> {
+    #[serde(flatten)]
+    pub diff: &'a SchemaDiff,
+    pub sql: String,
+}
+
+impl SchemaDiff {
+    pub fn has_changes(&self) -> bool {
+        !self.create_tables.is_empty()
+            || !self.alter_tables.is_empty()
+            || !self.drop_tables.is_empty()
+            || !self.create_columns.is_empty()
+            || !self.alter_columns.is_empty()
+            || !self.drop_columns.is_empty()
+            || !self.add_foreign_keys.is_empty()
+            || !self.drop_foreign_keys.is_empty()
+            || !self.add_constraints.is_empty()
+            || !self.drop_constraints.is_empty()
+            || !self.create_indexes.is_empty()
+            || !self.drop_indexes.is_empty()
+            || !self.create_enums.is_empty()
+            || !self.add_enum_values.is_empty()
+            || !self.drop_enums.is_empty()
+            || !self.create_roles.is_empty()
+            || !self.grants.is_empty()
+            || !self.revokes.is_empty()
+    }
+
+    /// The dialect the SQL is written in; PostgreSQL, the default provider,
+    /// when the name is empty or has no dialect yet
+    pub fn sql_dialect(&self) -> &'static dyn Dialect {
+        crate::dialect::for_name(&self.dialect).unwrap_or(&Postgres)
+    }
+
+    /// Checksum of the logical change, for deduplication
+    ///
+    /// Hashes the operations in canonical form rather than the SQL, so the
+    /// SQL's layout and comments, the order of tables and columns in
+    /// schema.json and the case of type names leave it unchanged.
+    pub fn checksum(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let mut operations: Vec<String> =
+            self.operations.iter().map(Operation::canonical).collect();
+        operations.sort();
+        let mut hasher = Sha256::new();
+        hasher.update(self.sql_dialect().name());
+        for operation in &operations {
+            hasher.update(b"\n");
+            hasher.update(operation);
+        }
+        format!("sha256:{:x}", hasher.finalize())
+    }
+}
+
+/// Generate SQL DDL from JSON schema
+pub fn generate_create_table_sql(
+    table_name: &str,
+    table: &crate::schema::Table,
+    dialect: &str,
+) -> String {
+    let dialect = crate::dialect::for_name(dialect).unwrap_or(&Postgres);
+    let mut sql = String::new();
+    dialect
+        .write_create_table(&mut sql, table_name, table)
+        .expect("writing to a String");
+    sql
+}
+
+/// Kahn's algorithm over tables `0..n`: a table is ready once every table it
+/// must follow is taken, and the lowest-numbered ready table goes first, so
+/// the order follows the schema's where the foreign keys allow
+struct DependencyOrder {
+    pending: BTreeSet<usize>,
+    ready: BTreeSet<usize>,
+    /// Tables each one still waits for
+    waiting: Vec<usize>,
+    /// Tables that wait for each one
+    followers: Vec<Vec<usize>>,
+}
+
+impl DependencyOrder {
+    /// `edges` are `(before, after)` pairs; repeats and self-references are
+    /// ignored
+    fn new(n: usize, edges: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let edges: HashSet<(usize, usize)> = edges.into_iter().filter(|(a, b)| a != b).collect();
+        let mut waiting = vec![0; n];
+        let mut followers = vec![Vec::new(); n];
+        for (before, after) in edges {
+            waiting[after] += 1;
+            followers[before].push(after);
+        }
+        DependencyOrder {
+            pending: (0..n).collect(),
+            ready: (0..n).filter(|&i| waiting[i] == 0).collect(),
+            waiting,
+            followers,
+        }
+    }
+
+    fn next_ready(&self) -> Option<usize> {
+        self.ready.first().copied()
+    }
+
+    /// The lowest-numbered table left, to break a cycle with
+    fn first_pending(&self) -> Option<usize> {
+        self.pending.first().copied()
+    }
+
+    fn is_pending(&self, table: usize) -> bool {
+        self.pending.contains(&table)
+    }
+
+    fn take(&mut self, table: usize) {
+        self.pending.remove(&table);
+        self.ready.remove(&table);
+        for &follower in &self.followers[table] {
+            self.waiting[follower] -= 1;
+            if self.waiting[follower] == 0 && self.pending.contains(&follower) {
+                self.ready.insert(follower);
+            }
+        }
+    }
+}
+
+/// Compare JSON schema with database schema
+///
+/// One hash lookup per table and column on each side, and tables are ordered
+/// by their foreign keys with [`DependencyOrder`], so the cost grows linearly
+/// with the size of the schemas (up to a log factor for the ordering).
+pub fn compare_schemas(json_schema: &crate::schema::Schema, db_schema: &DbSchema) -> SchemaDiff {
+    let span = info_span!(
+        "diff",
+        desired_tables = json_schema.tables.len(),
+        current_tables = db_schema.tables.len(),
+        create_tables = field::Empty,
+        drop_tables = field::Empty,
+        changed_columns = field::Empty,
+        elapsed_ms = field::Empty
+    );
+    let _guard = span.enter();
+    let started = Instant::now();
+    let dialect = crate::dialect::for_name(&db_schema.dialect).unwrap_or(&Postgres);
+    let mut drop_foreign_keys = Vec::new();
+    let mut drop_constraints = Vec::new();
+    let mut drop_columns = Vec::new();
+    let mut create_tables = Vec::new();
+    let mut add_columns = Vec::new();
+    let mut alter_columns = Vec::new();
+    let mut add_constraints = Vec::new();
+    let mut drop_indexes = Vec::new();
+    let mut create_indexes = Vec::new();
+    let mut add_foreign_keys = Vec::new();
+    // Constraints are diffed where the catalog can be read for them
+    let constraints = dialect.introspection().constraints.is_some();
+    // Changes to existing tables ALTER TABLE cannot make, reported instead
+    let in_place = dialect.alters_in_place();
+    let mut warnings = Vec::new();
+    // Where constraints cannot be deferred, schema.json's deferral is
+    // reported and left out of the diff
+    let defers = dialect.defers_constraints();
+    if !defers {
+        for (table_name, table) in &json_schema.tables {
+            for constraint in table.constraints.iter().flatten() {
+                if constraint.deferrable || constraint.initially_deferred {
+                    warnings.push(format!(
+                        "Constraint {} on '{}' is checked immediately: {} has no \
+                         deferrable constraints",
+                        constraint.name.as_deref().unwrap_or("(unnamed)"),
+                        table_name,
+                        dialect.name()
+                    ));
+                }
+            }
+        }
+    }
+
+    // Tables to create, parents before the tables referencing them; a foreign
+    // key to a table not created yet (a cycle) is added once both exist
+    let pending: Vec<&String> = json_schema
+        .tables
+        .keys()
+        .filter(|name| !db_schema.tables.contains_key(*name))
+        .collect();
+    let index: HashMap<&str, usize> = pending
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect();
+    let mut order = DependencyOrder::new(
+        pending.len(),
+        pending.iter().enumerate().flat_map(|(i, name)| {
+            DbForeignKey::from_table(name, &json_schema.tables[*name])
+                .into_iter()
+                .filter_map(|fk| index.get(fk.references_table.as_str()))
+                .map(move |&parent| (parent, i))
+                .collect::<Vec<_>>()
+        }),
+    );
+    while let Some(next) = order.next_ready().or_else(|| order.first_pending()) {
+        order.take(next);
+        let table_name = pending[next];
+        let is_pending = |table: &str| index.get(table).is_some_and(|&i| order.is_pending(i));
+        let mut definition = json_schema.tables[table_name].clone();
+        let deferred = |fk: &crate::schema::ForeignKey| is_pending(&fk.table);
+        for fk in DbForeignKey::from_table(table_name, &definition) {
+            if is_pending(&fk.references_table) {
+                add_foreign_keys.push(Operation::AddForeignKey {
+                    table: table_name.clone(),
+                    foreign_key: fk,
+                });
+            }
+        }
+        for column in definition.columns.values_mut() {
+            if column.references.as_ref().is_some_and(deferred) {
+                column.references = None;
+            }
+        }
+        if let Some(constraints) = &mut definition.constraints {
+            constraints.retain(|c| !c.references.as_ref().is_some_and(deferred));
+        }
+        create_indexes.extend(definition.indexes.iter().flatten().map(|index| {
+            Operation::CreateIndex {
+                table: table_name.clone(),
+                index: index.clone(),
+            }
+        }));
+        create_tables.push(Operation::CreateTable {
+            table: table_name.clone(),
+            definition,
+        });
+    }
+
+    // Columns, foreign keys and constraints to add to or change in the
+    // tables that exist
+    for (table_name, json_table) in &json_schema.tables {
+        let Some(db_table) = db_schema.tables.get(table_name) else {
+            continue;
+        };
+        let mut desired = DbForeignKey::from_table(table_name, json_table);
+        if !defers {
+            for fk in &mut desired {
+                fk.deferrable = false;
+                fk.initially_deferred = false;
+            }
+        }
+        for fk in &db_table.foreign_keys {
+            if desired.iter().any(|d| d.same_as(fk)) {
+                continue;
+            }
+            if !in_place {
+                warnings.push(format!(
+                    "Foreign key {} stays: foreign keys cannot be dropped in place, \
+                     so rebuild '{}' to remove it",
+                    fk.describe(table_name),
+                    table_name
+                ));
+                continue;
+            }
+            drop_foreign_keys.push(Operation::DropForeignKey {
+                table: table_name.clone(),
+                foreign_key: fk.clone(),
+            });
+        }
+        for fk in desired {
+            if db_table.foreign_keys.iter().any(|c| c.same_as(&fk)) {
+                continue;
+            }
+            if !in_place {
+                warnings.push(format!(
+                    "Foreign key {} is not added: foreign keys cannot be added in place, \
+                     so rebuild '{}' to add it",
+                    fk.describe(table_name),
+                    table_name
+                ));
+                continue;
+            }
+            add_foreign_keys.push(Operation::AddForeignKey {
+                table: table_name.clone(),
+                foreign_key: fk,
+            });
+        }
+        if constraints {
+            let mut desired = DbConstraint::from_table(table_name, json_table);
+            if !defers {
+                for constraint in &mut desired {
+                    constraint.deferrable = false;
+                    constraint.initially_deferred = false;
+                }
+            }
+            for constraint in &db_table.constraints {
+                if !desired.iter().any(|d| d.same_as(constraint)) {
+                    drop_constraints.push(Operation::DropConstraint {
+                        table: table_name.clone(),
+                        constraint: constraint.clone(),
+                    });
+                }
+            }
+            for constraint in desired {
+                if !db_table.constraints.iter().any(|c| c.same_as(&constraint)) {
+                    add_constraints.push(Operation::AddConstraint {
+                        table: table_name.clone(),
+                        constraint,
+                    });
+                }
+            }
+        }
+        // A table without `indexes` leaves its indexes unmanaged; a changed
+        // index is dropped and created
+        if let Some(desired) = &json_table.indexes {
+            for index in &db_table.indexes {
+                if !desired.iter().any(|d| index.same_as(d)) {
+                    drop_indexes.push(Operation::DropIndex {
+                        table: table_name.clone(),
+                        index: index.clone(),
+                    });
+                }
+            }
+            for index in desired {
+                if !db_table.indexes.iter().any(|c| c.same_as(index)) {
+                    create_indexes.push(Operation::CreateIndex {
+                        table: table_name.clone(),
+                        index: index.clone(),
+                    });
+                }
+            }
+        }
+        for (col_name, json_col) in &json_table.columns {
+            let column = DbColumn {
+                name: col_name.clone(),
+                data_type: json_col.declared_type().into_owned(),
+                is_nullable: !json_col.is_not_null()
+                    && !crate::dialect::is_serial(&json_col.data_type),
+                is_primary_key: json_col.is_primary_key(),
+                default_value: json_col.default.clone(),
+                size: json_col.size,
+            };
+            let Some(db_col) = db_table.columns.get(col_name) else {
+                add_columns.push(Operation::AddColumn {
+                    table: table_name.clone(),
+                    column,
+                });
+                continue;
+            };
+            let changes = column_changes(dialect, json_col, db_col);
+            if !changes.is_empty() && !in_place {
+                let changes: Vec<&str> = changes.iter().map(ColumnChange::as_str).collect();
+                warnings.push(format!(
+                    "Column '{}.{}' keeps its {}: columns cannot be altered in place, \
+                     so rebuild the table to change it",
+                    table_name,
+                    col_name,
+                    changes.join(", ")
+                ));
+            } else if !changes.is_empty() {
+                alter_columns.push(Operation::AlterColumn {
+                    table: table_name.clone(),
+                    column,
+                    from: db_col.clone(),
+                    changes,
+                });
+            }
+        }
+    }
+
+    // Tables to drop, those referencing others first; in a cycle the foreign
+    // keys between the remaining tables go before any of them
+    let mut drop_tables = Vec::new();
+    let pending: Vec<&DbTable> = db_schema
+        .tables
+        .values()
+        .filter(|table| !json_schema.tables.contains_key(&table.name))
+        .collect();
+    let index: HashMap<&str, usize> = pending
+        .iter()
+        .enumerate()
+        .map(|(i, table)| (table.name.as_str(), i))
+        .collect();
+    let mut order = DependencyOrder::new(
+        pending.len(),
+        pending.iter().enumerate().flat_map(|(i, table)| {
+            table
+                .foreign_keys
+                .iter()
+                .filter_map(|fk| index.get(fk.references_table.as_str()))
+                .map(move |&parent| (i, parent))
+        }),
+    );
+    while let Some(next) = order.next_ready() {
+        order.take(next);
+        drop_tables.push(Operation::DropTable {
+            table: pending[next].name.clone(),
+        });
+    }
+    let cycle: Vec<usize> = order.pending.iter().copied().collect();
+    for &i in &cycle {
+        for fk in &pending[i].foreign_keys {
+            if index
+                .get(fk.references_table.as_str())
+                .is_some_and(|&parent| order.is_pending(parent))
+            {
+                drop_foreign_keys.push(Operation::DropForeignKey {
+                    table: pending[i].name.clone(),
+                    foreign_key: fk.clone(),
+                });
+            }
+        }
+    }
+    drop_tables.extend(cycle.into_iter().map(|i| Operation::DropTable {
+        table: pending[i].name.clone(),
+    }));
+
+    // Columns to drop from the tables that stay
+    for (table_name, db_table) in &db_schema.tables {
+        let Some(json_table) = json_schema.tables.get(table_name) else {
+            continue;
+        };
+        for col_name in db_table.columns.keys() {
+            if !json_table.columns.contains_key(col_name) {
+                drop_columns.push(Operation::DropColumn {
+                    table: table_name.clone(),
+                    column: col_name.clone(),
+                });
+            }
+        }
+    }
+
+    // Enum types, where the database has them apart from their columns; a
+    // schema.json without `enums` leaves them unmanaged
+    let mut enums = EnumChanges::default();
+    if let (Some(_), Some(desired)) = (dialect.introspection().enums, &json_schema.enums) {
+        enums = EnumChanges::between(desired, &db_schema.enums);
+    }
+
+    // Privileges of the managed roles; those on tables about to be dropped go with them
+    let create_roles = json_schema
+        .roles
+        .iter()
+        .filter(|role| !db_schema.roles.contains(role))
+        .map(|role| Operation::CreateRole { role: role.clone() });
+    let desired: BTreeSet<DbGrant> = DbGrant::from_schema(&json_schema.grants)
+        .into_iter()
+        .collect();
+    let current: BTreeSet<DbGrant> = db_schema
+        .grants
+        .iter()
+        .filter(|grant| json_schema.roles.contains(&grant.role))
+        .filter(|grant| match json_schema.tables.get(&grant.table) {
+            Some(table) => grant
+                .column
+                .as_ref()
+                .is_none_or(|column| table.columns.contains_key(column)),
+            None => false,
+        })
+        .cloned()
+        .collect();
+    let revokes = privilege_groups(current.difference(&desired))
+        .into_iter()
+        .map(|g| Operation::Revoke {
+            role: g.role,
+            table: g.table,
+            privileges: g.privileges,
+            columns: g.columns,
+        });
+    let grants = privilege_groups(desired.difference(&current))
+        .into_iter()
+        .map(|g| Operation::Grant {
+            role: g.role,
+            table: g.table,
+            privileges: g.privileges,
+            columns: g.columns,
+        });
+
+    // Drops run first, so a recreated name is free again, foreign keys
+    // before the unique constraints they may rest on; enum types are created
+    // before the columns using them and dropped once none does; constraints
+    // and then indexes are added once the columns they name have their new
+    // types, foreign keys after them and once the columns and tables they
+    // name exist, and grants run last, once the tables and roles they name
+    // exist
+    let mut operations: Vec<Operation> = revokes.collect();
+    operations.extend(drop_foreign_keys);
+    operations.extend(drop_constraints);
+    operations.extend(drop_indexes);
+    operations.extend(drop_columns);
+    operations.extend(drop_tables);
+    operations.extend(create_roles);
+    operations.extend(enums.create);
+    operations.extend(enums.add_values);
+    operations.extend(create_tables);
+    operations.extend(add_columns);
+    operations.extend(alter_columns);
+    operations.extend(enums.drop);
+    operations.extend(add_constraints);
+    operations.extend(create_indexes);
+    operations.extend(add_foreign_keys);
+    operations.extend(grants);
+    let mut diff = SchemaDiff::from_operations(db_schema.dialect.clone(), operations);
+    diff.warnings = warnings;
+    diff.warnings.extend(enums.warnings);
+
+    let changed_columns = [&diff.create_columns, &diff.alter_columns]
+        .iter()
+        .flat_map(|columns| columns.values())
+        .map(Vec::len)
+        .chain(diff.drop_columns.values().map(Vec::len))
+        .sum::<usize>();
+    span.record("create_tables", diff.create_tables.len());
+    span.record("drop_tables", diff.drop_tables.len());
+    span.record("changed_columns", changed_columns);
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+    diff
+}
+
+/// What it takes to turn the database's enum types into schema.json's
+#[derive(Default)]
+struct EnumChanges {
+    create: Vec<Operation>,
+    add_values: Vec<Operation>,
+    drop: Vec<Operation>,
+    warnings: Vec<String>,
+}
+
+impl EnumChanges {
+    fn between(
+        desired: &IndexMap<String, Vec<String>>,
+        current: &IndexMap<String, Vec<String>>,
+    ) -> Self {
+        let mut changes = Self::default();
+        for (name, values) in desired {
+            let Some(existing) = current.get(name) else {
+                changes.create.push(Operation::CreateEnum {
+                    name: name.clone(),
+                    values: values.clone(),
+                });
+                continue;
+            };
+            // Each new value goes after the value schema.json lists before
+            // it, which exists by then; a new first value goes before the
+            // current first one
+            for (i, value) in values.iter().enumerate() {
+                if existing.contains(value) {
+                    continue;
+                }
+                let after = i.checked_sub(1).map(|previous| values[previous].clone());
+                let before = match after {
+                    Some(_) => None,
+                    None => existing.first().cloned(),
+                };
+                changes.add_values.push(Operation::AddEnumValue {
+                    name: name.clone(),
+                    value: value.clone(),
+                    after,
+                    before,
+                });
+            }
+            for value in existing.iter().filter(|value| !values.contains(value)) {
+                changes.warnings.push(format!(
+                    "Enum '{}' keeps value '{}': enum values cannot be dropped, \
+                     so recreate the type to remove it",
+                    name, value
+                ));
+            }
+        }
+        for (name, values) in current {
+            if !desired.contains_key(name) {
+                changes.drop.push(Operation::DropEnum {
+                    name: name.clone(),
+                    values: values.clone(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// Privilege rows as GRANT/REVOKE-sized groups: one per role and table for
+/// whole-table privileges, and one per privilege for column ones
+fn privilege_groups<'a>(rows: impl Iterator<Item = &'a DbGrant>) -> Vec<crate::schema::Grant> {
+    let mut groups: IndexMap<(&str, &str, Option<&str>), crate::schema::Grant> = IndexMap::new();
+    for row in rows {
+        let key = (
+            row.role.as_str(),
+            row.table.as_str(),
+            row.column.as_ref().map(|_| row.privilege.as_str()),
+        );
+        let group = groups.entry(key).or_insert_with(|| crate::schema::Grant {
+            role: row.role.clone(),
+            table: row.table.clone(),
+            privileges: Vec::new(),
+            columns: Vec::new(),
+        });
+        if !group.privileges.contains(&row.privilege) {
+            group.privileges.push(row.privilege.clone());
+        }
+        group.columns.extend(row.column.clone());
+    }
+    groups.into_values().collect()
+}
+
+/// Print schema diff summary
+pub fn print_diff_summary(diff: &SchemaDiff) {
+    println!();
+    println!("Schema diff summary:");
+    println!("{}", "=".repeat(60));
+
+    if !diff.create_tables.is_empty() {
+        println!("\nTables to CREATE ({}):", diff.create_tables.len());
+        for table in &diff.create_tables {
+            println!("  + {}", table);
+        }
+    }
+
+    if !diff.alter_tables.is_empty() {
+        println!("\nTables to ALTER ({}):", diff.alter_tables.len());
+        for table in &diff.alter_tables {
+            println!("  ~ {}", table);
+        }
+    }
+
+    if !diff.drop_tables.is_empty() {
+        println!("\nTables to DROP ({}):", diff.drop_tables.len());
+        for table in &diff.drop_tables {
+            println!("  - {}", table);
+        }
+    }
+
+    if !diff.create_columns.is_empty() {
+        println!("\nColumns to ADD ({} tables):", diff.create_columns.len());
+        for (table, columns) in &diff.create_columns {
+            for col in columns {
+                println!("  + {}.{}", table, col.name);
+            }
+        }
+    }
+
+    if !diff.alter_columns.is_empty() {
+        println!("\nColumns to ALTER ({} tables):", diff.alter_columns.len());
+        for operation in &diff.operations {
+            if let Operation::AlterColumn {
+                table,
+                column,
+                from,
+                changes,
+            } = operation
+            {
+                let detail: Vec<String> = changes
+                    .iter()
+                    .map(|change| match change {
+                        ColumnChange::Type => {
+                            format!("{} -> {}", type_label(from), type_label(column))
+                        }
+                        ColumnChange::Nullability if column.is_nullable => "NULL".to_string(),
+                        ColumnChange::Nullability => "NOT NULL".to_string(),
+                        ColumnChange::Default => match &column.default_value {
+                            Some(default) => format!("DEFAULT {}", default),
+                            None => "no default".to_string(),
+                        },
+                    })
+                    .collect();
+                println!("  ~ {}.{}: {}", table, column.name, detail.join(", "));
+            }
+        }
+    }
+
+    if !diff.drop_columns.is_empty() {
+        println!("\nColumns to DROP ({} tables):", diff.drop_columns.len());
+        for (table, columns) in &diff.drop_columns {
+            for col in columns {
+                println!("  - {}.{}", table, col);
+            }
+        }
+    }
+
+    if !diff.add_foreign_keys.is_empty() {
+        println!("\nForeign keys to ADD ({}):", diff.add_foreign_keys.len());
+        for foreign_key in &diff.add_foreign_keys {
+            println!("  + {}", foreign_key);
+        }
+    }
+
+    if !diff.drop_foreign_keys.is_empty() {
+        println!("\nForeign keys to DROP ({}):", diff.drop_foreign_keys.len());
+        for foreign_key in &diff.drop_foreign_keys {
+            println!("  - {}", foreign_key);
+        }
+    }
+
+    if !diff.add_constraints.is_empty() {
+        println!("\nConstraints to ADD ({}):", diff.add_constraints.len());
+        for constraint in &diff.add_constraints {
+            println!("  + {}", constraint);
+        }
+    }
+
+    if !diff.drop_constraints.is_empty() {
+        println!("\nConstraints to DROP ({}):", diff.drop_constraints.len());
+        for constraint in &diff.drop_constraints {
+            println!("  - {}", constraint);
+        }
+    }
+
+    if !diff.create_indexes.is_empty() {
+        println!("\nIndexes to CREATE ({}):", diff.create_indexes.len());
+        for index in &diff.create_indexes {
+            println!("  + {}", index);
+        }
+    }
+
+    if !diff.drop_indexes.is_empty() {
+        println!("\nIndexes to DROP ({}):", diff.drop_indexes.len());
+        for index in &diff.drop_indexes {
+            println!("  - {}", index);
+        }
+    }
+
+    if !diff.create_enums.is_empty() {
+        println!("\nEnums to CREATE ({}):", diff.create_enums.len());
+        for name in &diff.create_enums {
+            println!("  + {}", name);
+        }
+    }
+
+    if !diff.add_enum_values.is_empty() {
+        println!("\nEnum values to ADD:");
+        for (name, values) in &diff.add_enum_values {
+            println!("  {}:", name);
+            for value in values {
+                println!("    + {}", value);
+            }
+        }
+    }
+
+    if !diff.drop_enums.is_empty() {
+        println!("\nEnums to DROP ({}):", diff.drop_enums.len());
+        for name in &diff.drop_enums {
+            println!("  - {}", name);
+        }
+    }
+
+    if !diff.create_roles.is_empty() {
+        println!("\nRoles to CREATE ({}):", diff.create_roles.len());
+        for role in &diff.create_roles {
+            println!("  + {}", role);
+        }
+    }
+
+    if !diff.grants.is_empty() {
+        println!("\nPrivileges to GRANT ({}):", diff.grants.len());
+        for grant in &diff.grants {
+            println!("  + {}", grant);
+        }
+    }
+
+    if !diff.revokes.is_empty() {
+        println!("\nPrivileges to REVOKE ({}):", diff.revokes.len());
+        for revoke in &diff.revokes {
+            println!("  - {}", revoke);
+        }
+    }
+
+    if !diff.warnings.is_empty() {
+        println!("\n{} WARNING - Not applied:", Mark::Warn);
+        for warning in &diff.warnings {
+            println!("  ! {}", warning);
+        }
+    }
+
+    if !diff.data_loss_warning.is_empty() {
+        println!("\n{} WARNING - Data loss may occur:", Mark::Warn);
+        for warning in &diff.data_loss_warning {
+            println!("  ! {}", warning);
+        }
+    }
+
+    if !diff.has_changes() {
+        println!("\n{} Schemas are in sync - no changes needed.", Mark::Ok);
+    } else if !diff.data_loss_warning.is_empty() {
+        println!("\n{} Some changes may cause data loss.", Mark::Warn);
+        println!("Use --accept-data-loss flag to proceed.");
+    }
+
+    println!();
+}
+
+/// Diff between two schema.json versions, as if `from` were the database
+pub fn diff_schemas(from: &crate::schema::Schema, to: &crate::schema::Schema) -> SchemaDiff {
+    compare_schemas(to, &DbSchema::from_json_schema(from))
+}
+
+impl DbSchema {
+    /// The database a schema.json describes
+    pub fn from_json_schema(schema: &crate::schema::Schema) -> Self {
+        let dialect = schema
+            .dialect
+            .as_deref()
+            .and_then(crate::dialect::for_name)
+            .unwrap_or(&Postgres);
+        let tables = schema
+            .tables
+            .iter()
+            .map(|(table_name, table)| {
+                let columns: IndexMap<String, DbColumn> = table
+                    .columns
+                    .iter()
+                    .map(|(col_name, col)| {
+                        let column = DbColumn {
+                            name: col_name.clone(),
+                            data_type: col.declared_type().into_owned(),
+                            is_nullable: !col.is_not_null()
+                                && !crate::dialect::is_serial(&col.data_type),
+                            is_primary_key: col.is_primary_key(),
+                            default_value: col.default.clone(),
+                            size: col.size,
+                        };
+                        (col_name.clone(), column)
+                    })
+                    .collect();
+                let mut primary_key: Vec<String> = columns
+                    .values()
+                    .filter(|c| c.is_primary_key)
+                    .map(|c| c.name.clone())
+                    .collect();
+                primary_key.sort();
+                let table = DbTable {
+                    name: table_name.clone(),
+                    columns,
+                    primary_key,
+                    foreign_keys: DbForeignKey::from_table(table_name, table),
+                    constraints: DbConstraint::from_table(table_name, table),
+                    indexes: table
+                        .indexes
+                        .iter()
+                        .flatten()
+                        .map(|index| DbIndex::from_schema(dialect, table_name, index))
+                        .collect(),
+                };
+                (table_name.clone(), table)
+            })
+            .collect();
+
+        Self {
+            tables,
+            enums: schema.enums.clone().unwrap_or_default(),
+            roles: schema.roles.clone(),
+            grants: DbGrant::from_schema(&schema.grants),
+            dialect: schema
+                .dialect
+                .clone()
+                .unwrap_or_else(|| crate::config::DatabaseProvider::default().to_string()),
+        }
+    }
+
+    /// Convert DbSchema to JSON schema format
+    pub fn to_json_schema(&self) -> crate::schema::Schema {
+        let mut tables = IndexMap::new();
+
+        for (table_name, db_table) in &self.tables {
+            let mut columns = IndexMap::new();
+
+            for (col_name, db_col) in &db_table.columns {
+                columns.insert(
+                    col_name.clone(),
+                    crate::schema::Column {
+                        column_name: db_col.name.clone(),
+                        data_type: db_col.data_type.clone(),
+                        size: db_col.size,
+                        array_dimensions: None,
+                        is_primary_key: db_col.is_primary_key,
+                        is_not_null: !db_col.is_nullable,
+                        is_unique: false,
+                        default: db_col.default_value.clone(),
+                        identity: None,
+                        generated: None,
+                        collation: None,
+                        storage: None,
+                        statistics: None,
+                        attributes: crate::schema::ColumnAttributes::default(),
+                        references: None,
+                        shape: None,
+                        mask: None,
+                    },
+                );
+            }
+
+            // A single-column foreign key is the column's `references`; one
+            // over several columns is a table constraint
+            let mut constraints = Vec::new();
+            for fk in &db_table.foreign_keys {
+                let references = crate::schema::ForeignKey {
+                    table: fk.references_table.clone(),
+                    column: fk.references_columns.join(", "),
+                    on_delete: fk
+                        .on_delete
+                        .as_deref()
+                        .map(crate::schema::OnDeleteAction::from_sql),
+                    on_update: fk
+                        .on_update
+                        .as_deref()
+                        .map(crate::schema::OnUpdateAction::from_sql),
+                    match_type: None,
+                };
+                let column = match fk.columns.as_slice() {
+                    [column] => columns.get_mut(column),
+                    _ => None,
+                };
+                // `references` cannot say it is deferrable
+                match column {
+                    Some(column) if column.references.is_none() && fk.deferral().is_empty() => {
+                        column.references = Some(references)
+                    }
+                    _ => constraints.push(crate::schema::TableConstraint {
+                        name: Some(fk.name.clone()),
+                        constraint_type: ConstraintType::ForeignKey,
+                        columns: fk.columns.clone(),
+                        expression: None,
+                        references: Some(references),
+                        deferrable: fk.deferrable,
+                        initially_deferred: fk.initially_deferred,
+                    }),
+                }
+            }
+
+            // A unique column under PostgreSQL's name for it is `isUnique`;
+            // the other constraints are table constraints
+            for constraint in &db_table.constraints {
+                let default_name = DbConstraint::default_name(
+                    table_name,
+                    ConstraintType::Unique,
+                    &constraint.columns,
+                );
+                let column = match (constraint.constraint_type, constraint.columns.as_slice()) {
+                    (ConstraintType::Unique, [column])
+                        if constraint.name == default_name && constraint.deferral().is_empty() =>
+                    {
+                        columns.get_mut(column)
+                    }
+                    _ => None,
+                };
+                match column {
+                    Some(column) => column.is_unique = true,
+                    None => constraints.push(crate::schema::TableConstraint {
+                        name: Some(constraint.name.clone()),
+                        constraint_type: constraint.constraint_type,
+                        columns: constraint.columns.clone(),
+                        expression: constraint.expression.clone(),
+                        references: None,
+                        deferrable: constraint.deferrable,
+                        initially_deferred: constraint.initially_deferred,
+                    }),
+                }
+            }
+
+            let indexes: Vec<crate::schema::Index> =
+                db_table.indexes.iter().map(DbIndex::to_schema).collect();
+
+            tables.insert(
+                table_name.clone(),
+                crate::schema::Table {
+                    comment: None,
+                    columns,
+                    indexes: (!indexes.is_empty()).then_some(indexes),
+                    constraints: (!constraints.is_empty()).then_some(constraints),
+                    options: crate::schema::TableOptions::default(),
+                    partitions: Vec::new(),
+                    inherits: Vec::new(),
+                    variants: None,
+                },
+            );
+        }
+
+        let grants = privilege_groups(self.grants.iter());
+        let mut roles: Vec<String> = grants.iter().map(|g| g.role.clone()).collect();
+        roles.sort();
+        roles.dedup();
+
+        crate::schema::Schema {
+            version: Some("1".to_string()),
+            dialect: Some(self.dialect.clone()),
+            comment: None,
+            tables,
+            enums: Some(self.enums.clone()),
+            shapes: IndexMap::new(),
+            roles,
+            grants,
+        }
+    }
+}
+
+impl SchemaDiff {
+    /// The diff that runs `operations` in order, with its summaries and SQL
+    pub fn from_operations(dialect: impl Into<String>, operations: Vec<Operation>) -> Self {
+        let mut diff = SchemaDiff {
+            dialect: dialect.into(),
+            ..SchemaDiff::default()
+        };
+        for operation in &operations {
+            diff.data_loss_warning.extend(operation.data_loss());
+            match operation {
+                Operation::DropColumn { table, column } => diff
+                    .drop_columns
+                    .entry(table.clone())
+                    .or_default()
+                    .push(column.clone()),
+                Operation::DropTable { table } => diff.drop_tables.push(table.clone()),
+                Operation::CreateTable { table, .. } => diff.create_tables.push(table.clone()),
+                Operation::AddColumn { table, column } => diff
+                    .create_columns
+                    .entry(table.clone())
+                    .or_default()
+                    .push(column.clone()),
+                Operation::AlterColumn { table, column, .. } => diff
+                    .alter_columns
+                    .entry(table.clone())
+                    .or_default()
+                    .push(column.clone()),
+                Operation::AddForeignKey { table, foreign_key } => {
+                    diff.add_foreign_keys.push(foreign_key.describe(table))
+                }
+                Operation::DropForeignKey { table, foreign_key } => {
+                    diff.drop_foreign_keys.push(foreign_key.describe(table))
+                }
+                Operation::AddConstraint { table, constraint } => {
+                    diff.add_constraints.push(constraint.describe(table))
+                }
+                Operation::DropConstraint { table, constraint } => {
+                    diff.drop_constraints.push(constraint.describe(table))
+                }
+                Operation::CreateIndex { table, index } => diff.create_indexes.push(
+                    describe_index(table, &index.name, index.unique, &index.columns),
+                ),
+                Operation::DropIndex { table, index } => diff.drop_indexes.push(describe_index(
+                    table,
+                    &index.name,
+                    index.unique,
+                    &index.columns(),
+                )),
+                Operation::CreateEnum { name, .. } => diff.create_enums.push(name.clone()),
+                Operation::AddEnumValue { name, value, .. } => diff
+                    .add_enum_values
+                    .entry(name.clone())
+                    .or_default()
+                    .push(value.clone()),
+                Operation::DropEnum { name, .. } => diff.drop_enums.push(name.clone()),
+                Operation::CreateRole { role } => diff.create_roles.push(role.clone()),
+                Operation::Grant {
+                    role,
+                    table,
+                    privileges,
+                    columns,
+                } => diff
+                    .grants
+                    .push(describe_privileges(privileges, table, columns, "to", role)),
+                Operation::Revoke {
+                    role,
+                    table,
+                    privileges,
+                    columns,
+                } => diff.revokes.push(describe_privileges(
+                    privileges, table, columns, "from", role,
+                )),
+            }
+        }
+        diff.operations = operations;
+        diff
+    }
+
+    /// Keep only the operations `keep` accepts, e.g. the ones a policy
+    /// allows, and rebuild the summaries from what is left
+    pub fn retain(&mut self, keep: impl FnMut(&Operation) -> bool) {
+        let mut operations = std::mem::take(&mut self.operations);
+        operations.retain(keep);
+        let warnings = std::mem::take(&mut self.warnings);
+        *self = Self::from_operations(std::mem::take(&mut self.dialect), operations);
+        self.warnings = warnings;
+    }
+
+    /// Write the migration SQL statement by statement, without building it in
+    /// memory first
+    pub fn write_sql(&self, out: &mut impl Write) -> fmt::Result {
+        let dialect = self.sql_dialect();
+        for operation in &self.operations {
+            operation.write_sql(dialect, out)?;
+        }
+        Ok(())
+    }
+
+    /// The migration SQL as a string, for callers that need all of it at once
+    pub fn sql(&self) -> String {
+        self.render(self.sql_dialect())
+    }
+
+    /// The diff as `--format json` and the SDKs return it, with the SQL
+    /// written out under `sql`
+    pub fn with_sql(&self) -> DiffWithSql<'_> {
+        DiffWithSql {
+            diff: self,
+            sql: self.sql(),
+        }
+    }
+
+    /// The SQL of the operations in another dialect
+    pub fn render(&self, dialect: &dyn Dialect) -> String {
+        let mut sql = String::with_capacity(self.estimated_sql_len());
+        for operation in &self.operations {
+            operation
+                .write_sql(dialect, &mut sql)
+                .expect("writing to a String");
+        }
+        sql
+    }
+
+    /// Rough size of the SQL, so it can be written without reallocating
+    fn estimated_sql_len(&self) -> usize {
+        self.operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::CreateTable { definition, .. } => 64 + definition.columns.len() * 48,
+                _ => 64,
+            })
+            .sum()
+    }
+
+    /// Generate rollback SQL for the changes
+    pub fn generate_rollback(&self) -> String {
+        let dialect = self.sql_dialect();
+        let mut sql = String::new();
+
+        // Reverse the operations (inverse order): foreign keys and then
+        // constraints added to tables that stay go before the tables they
+        // reference, and tables are dropped in the reverse of the order they
+        // were created in
+        for operation in &self.operations {
+            if let Operation::AddForeignKey { table, foreign_key } = operation {
+                if !self.create_tables.contains(table) {
+                    sql.push_str(&dialect.drop_foreign_key(table, &foreign_key.name));
+                    sql.push('\n');
+                }
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::CreateIndex { table, index } = operation {
+                if !self.create_tables.contains(table) {
+                    sql.push_str(&dialect.drop_index(table, &index.name));
+                    sql.push('\n');
+                }
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::AddConstraint { table, constraint } = operation {
+                sql.push_str(&dialect.drop_constraint(table, constraint));
+                sql.push('\n');
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::DropEnum { name, values } = operation {
+                sql.push_str(&dialect.create_enum(name, values));
+                sql.push('\n');
+            }
+        }
+
+        for table in self.create_tables.iter().rev() {
+            sql.push_str(&dialect.drop_table(table));
+            sql.push('\n');
+        }
+
+        for (table, columns) in &self.create_columns {
+            for col in columns {
+                sql.push_str(&dialect.drop_column(table, &col.name));
+                sql.push('\n');
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::AlterColumn {
+                table,
+                column,
+                from,
+                changes,
+            } = operation
+            {
+                sql.push_str(&dialect.alter_column(table, from, column, changes));
+                sql.push('\n');
+            }
+        }
+
+        for (name, values) in &self.add_enum_values {
+            for value in values {
+                sql.push_str(&format!(
+                    "-- Enum values cannot be dropped: recreate {} without '{}' to remove it\n",
+                    name, value
+                ));
+            }
+        }
+
+        for name in self.create_enums.iter().rev() {
+            sql.push_str(&dialect.drop_enum(name));
+            sql.push('\n');
+        }
+
+        for table in &self.drop_tables {
+            sql.push_str(&format!(
+                "-- Recreate table {} (you may need to restore from backup)\n",
+                table
+            ));
+            sql.push_str("-- This is a placeholder - manual intervention may be required\n");
+        }
+
+        for operation in &self.operations {
+            if let Operation::DropConstraint { table, constraint } = operation {
+                sql.push_str(&dialect.add_constraint(table, constraint));
+                sql.push('\n');
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::DropIndex { index, .. } = operation {
+                sql.push_str(&index.definition);
+                sql.push_str(";\n");
+            }
+        }
+
+        for operation in &self.operations {
+            if let Operation::DropForeignKey { table, foreign_key } = operation {
+                sql.push_str(&dialect.add_foreign_key(table, foreign_key));
+                sql.push('\n');
+            }
+        }
+
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_column_serialization() {
+        let column = DbColumn {
+            name: "id".to_string(),
+            data_type: "bigint".to_string(),
+            is_nullable: false,
+            is_primary_key: true,
+            default_value: None,
+            size: None,
+        };
+
+        let json = serde_json::to_string(&column).unwrap();
+        assert!(json.contains("id"));
+        assert!(json.contains("bigint"));
+    }
+
+    #[test]
+    fn test_db_table_serialization() {
+        let mut columns = IndexMap::new();
+        columns.insert(
+            "id".to_string(),
+            DbColumn {
+                name: "id".to_string(),
+                data_type: "bigint".to_string(),
+                is_nullable: false,
+                is_primary_key: true,
+                default_value: None,
+                size: None,
+            },
+        );
+
+        let table = DbTable {
+            name: "users".to_string(),
+            columns,
+            primary_key: vec!["id".to_string()],
+            foreign_keys: Vec::new(),
+            constraints: Vec::new(),
+            indexes: Vec::new(),
+        };
+
+        let json = serde_json::to_string(&table).unwrap();
+        assert!(json.contains("users"));
+        assert!(json.contains("id"));
+    }
+
+    #[test]
+    fn test_db_schema_serialization() {
+        let mut tables = IndexMap::new();
+        tables.insert(
+            "users".to_string(),
+            DbTable {
+                name: "users".to_string(),
+                columns: IndexMap::new(),
+                primary_key: vec![],
+                foreign_keys: vec![],
+                constraints: vec![],
+                indexes: vec![],
+            },
+        );
+
+        let mut enums = IndexMap::new();
+        enums.insert(
+            "user_status".to_string(),
+            vec!["active".to_string(), "inactive".to_string()],
+        );
+
+        let schema = DbSchema {
+            tables,
+            enums,
+            dialect: "postgresql".to_string(),
+            ..DbSchema::default()
+        };
+
+        let json = serde_json::to_string(&schema).unwrap();
+        assert!(json.contains("postgresql"));
+        assert!(json.contains("users"));
+    }
+
+    #[test]
+    fn test_schema_diff_has_changes_empty() {
+        let diff = SchemaDiff::default();
+        assert!(!diff.has_changes());
+    }
+
+    #[test]
+    fn test_schema_diff_has_changes_with_tables() {
+        let mut diff = SchemaDiff::default();
+        diff.create_tables.push("users".to_string());
+        assert!(diff.has_changes());
+    }
+
+    #[test]
+    fn test_schema_diff_has_changes_with_columns() {
+        let mut diff = SchemaDiff::default();
+        let mut columns_map = IndexMap::new();
+        columns_map.insert("users".to_string(), vec![]);
+        diff.create_columns = columns_map;
+        assert!(diff.has_changes());
+    }
+
+    #[test]
+    fn test_diff_schemas() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint", "isPrimaryKey": true}, "nick": {"name": "nick", "type": "text"}}},
+                "legacy": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        );
+        let to = schema(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint", "isPrimaryKey": true}, "email": {"name": "email", "type": "text"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        assert_eq!(diff.create_tables, vec!["posts"]);
+        assert_eq!(diff.drop_tables, vec!["legacy"]);
+        assert_eq!(diff.create_columns["users"][0].name, "email");
+        assert_eq!(diff.drop_columns["users"], vec!["nick"]);
+        assert!(!diff_schemas(&to, &to).has_changes());
+    }
+
+    #[test]
+    fn test_diff_follows_schema_order() {
+        let to: crate::schema::Schema = serde_json::from_str(
+            r#"{"tables": {
+                "zebras": {"columns": {"name": {"name": "name", "type": "text"}, "age": {"name": "age", "type": "integer"}}},
+                "apples": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap();
+
+        let diff = diff_schemas(&crate::schema::Schema::default(), &to);
+        assert_eq!(diff.create_tables, vec!["zebras", "apples"]);
+        let sql = diff.sql();
+        assert!(sql.contains("CREATE TABLE zebras (\n  name TEXT NULL,\n  age INTEGER NULL\n);"));
+        assert!(sql.find("zebras") < sql.find("apples"));
+        assert_eq!(
+            diff.checksum(),
+            diff_schemas(&crate::schema::Schema::default(), &to).checksum()
+        );
+    }
+
+    #[test]
+    fn test_column_type_nullability_and_default_changes() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "email": {"name": "email", "type": "varchar", "size": 40, "isNotNull": true},
+                "age": {"name": "age", "type": "integer"},
+                "status": {"name": "status", "type": "text", "default": "'new'"}
+            }}}}"#,
+        );
+        let to = schema(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "email": {"name": "email", "type": "varchar", "size": 20, "isNotNull": true},
+                "age": {"name": "age", "type": "bigint", "isNotNull": true},
+                "status": {"name": "status", "type": "text", "default": "'active'"}
+            }}}}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        let altered: Vec<&str> = diff.alter_columns["users"]
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(altered, vec!["email", "age", "status"]);
+        // Within a type family there is no USING cast, so the narrowed email
+        // fails on values too long for it instead of truncating them
+        assert_eq!(
+            diff.sql(),
+            "ALTER TABLE users ALTER COLUMN email TYPE VARCHAR(20);\n\
+             ALTER TABLE users ALTER COLUMN age TYPE BIGINT;\n\
+             ALTER TABLE users ALTER COLUMN age SET NOT NULL;\n\
+             ALTER TABLE users ALTER COLUMN status SET DEFAULT 'active';\n"
+        );
+        // Only the narrowing loses data
+        assert_eq!(diff.data_loss_warning.len(), 1);
+        assert!(diff.data_loss_warning[0].contains("users.email"));
+        assert!(diff
+            .generate_rollback()
+            .contains("ALTER TABLE users ALTER COLUMN email TYPE VARCHAR(40);"));
+
+        // The catalog's spellings of the same columns are no change
+        let mut current = DbSchema::from_json_schema(&to);
+        let columns = &mut current.tables["users"].columns;
+        columns["email"].data_type = "character varying".to_string();
+        columns["age"].data_type = "int8".to_string();
+        columns["status"].default_value = Some("'active'::text".to_string());
+        assert!(!compare_schemas(&to, &current).has_changes());
+    }
+
+    #[test]
+    fn test_diff_foreign_keys() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint", "isPrimaryKey": true}}},
+                "posts": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "author_id": {"name": "author_id", "type": "bigint",
+                                  "references": {"table": "users", "column": "id"}}
+                }}
+            }}"#,
+        );
+        let to = schema(
+            r#"{"tables": {
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "team_id": {"name": "team_id", "type": "bigint",
+                                "references": {"table": "teams", "column": "id"}}
+                }},
+                "posts": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "author_id": {"name": "author_id", "type": "bigint",
+                                  "references": {"table": "users", "column": "id",
+                                                 "onDelete": "cascade"}}
+                }},
+                "teams": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "lead_id": {"name": "lead_id", "type": "bigint",
+                                "references": {"table": "leads", "column": "id"}}
+                }},
+                "leads": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "team_id": {"name": "team_id", "type": "bigint",
+                                "references": {"table": "teams", "column": "id"}}
+                }}
+            }}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        // A changed action is a drop and an add
+        assert_eq!(
+            diff.drop_foreign_keys,
+            vec!["posts (author_id) -> users (id)"]
+        );
+        assert_eq!(
+            diff.add_foreign_keys,
+            vec![
+                "teams (lead_id) -> leads (id)",
+                "users (team_id) -> teams (id)",
+                "posts (author_id) -> users (id) ON DELETE CASCADE",
+            ]
+        );
+        // teams and leads reference each other: teams goes first, without its
+        // foreign key, which is added once leads exists
+        assert_eq!(diff.create_tables, vec!["teams", "leads"]);
+        let sql = diff.sql();
+        assert!(sql.contains(
+            "CREATE TABLE teams (\n  id BIGINT NULL,\n  lead_id BIGINT NULL,\n  PRIMARY KEY (id)\n);"
+        ));
+        assert!(sql.contains(
+            "CONSTRAINT leads_team_id_fkey FOREIGN KEY (team_id) REFERENCES teams (id)\n);"
+        ));
+        let drop = sql
+            .find("DROP CONSTRAINT IF EXISTS posts_author_id_fkey")
+            .unwrap();
+        let add_team = sql.find("ADD CONSTRAINT users_team_id_fkey").unwrap();
+        assert!(drop < sql.find("CREATE TABLE teams").unwrap());
+        assert!(sql.find("ADD COLUMN team_id").unwrap() < add_team);
+
+        let rollback = diff.generate_rollback();
+        assert!(rollback.starts_with(
+            "ALTER TABLE users DROP CONSTRAINT IF EXISTS users_team_id_fkey;\n\
+             ALTER TABLE posts DROP CONSTRAINT IF EXISTS posts_author_id_fkey;\n\
+             DROP TABLE IF EXISTS leads CASCADE;\nDROP TABLE IF EXISTS teams CASCADE;\n"
+        ));
+        assert!(rollback.ends_with(
+            "ALTER TABLE posts ADD CONSTRAINT posts_author_id_fkey FOREIGN KEY (author_id) REFERENCES users (id);\n"
+        ));
+
+        // The foreign keys survive a trip through the database's form, and
+        // tables referencing others are dropped first
+        let current = DbSchema::from_json_schema(&to);
+        assert!(!compare_schemas(&current.to_json_schema(), &current).has_changes());
+        let diff = compare_schemas(&crate::schema::Schema::default(), &current);
+        assert_eq!(diff.drop_tables, vec!["posts", "users", "teams", "leads"]);
+        assert_eq!(
+            diff.drop_foreign_keys,
+            vec![
+                "teams (lead_id) -> leads (id)",
+                "leads (team_id) -> teams (id)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_constraints() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"tables": {
+                "orders": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "total": {"name": "total", "type": "decimal"},
+                    "code": {"name": "code", "type": "text", "isUnique": true}
+                }, "constraints": [
+                    {"constraintType": "check", "columns": ["total"], "expression": "total >= 0"}
+                ]}
+            }}"#,
+        );
+        let to = schema(
+            r#"{"tables": {
+                "orders": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "total": {"name": "total", "type": "decimal"},
+                    "code": {"name": "code", "type": "text"}
+                }, "constraints": [
+                    {"constraintType": "check", "columns": ["total"], "expression": "total > 0"},
+                    {"constraintType": "unique", "columns": ["id", "code"]}
+                ]},
+                "bookings": {"columns": {
+                    "room": {"name": "room", "type": "integer"},
+                    "during": {"name": "during", "type": "tstzrange"}
+                }, "constraints": [
+                    {"name": "no_overlap", "constraintType": "exclude",
+                     "expression": "USING gist (room WITH =, during WITH &&)"},
+                    {"constraintType": "check", "expression": "room > 0"},
+                    {"constraintType": "check", "expression": "NOT isempty(during)"}
+                ]}
+            }}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        // A changed expression is a drop and an add
+        assert_eq!(
+            diff.drop_constraints,
+            vec!["orders UNIQUE (code)", "orders CHECK (total >= 0)"]
+        );
+        assert_eq!(
+            diff.add_constraints,
+            vec!["orders CHECK (total > 0)", "orders UNIQUE (id, code)"]
+        );
+        let sql = diff.sql();
+        assert!(sql.starts_with(
+            "ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_code_key;\n\
+             ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_total_check;\n"
+        ));
+        assert!(
+            sql.contains("ALTER TABLE orders ADD CONSTRAINT orders_id_code_key UNIQUE (id, code);")
+        );
+        // New tables get theirs in CREATE TABLE, unnamed ones numbered
+        assert!(sql.contains(
+            "  during tstzrange NULL,\n  \
+             CONSTRAINT no_overlap EXCLUDE USING gist (room WITH =, during WITH &&),\n  \
+             CONSTRAINT bookings_check CHECK (room > 0),\n  \
+             CONSTRAINT bookings_check1 CHECK (NOT isempty(during))\n)"
+        ));
+        assert!(diff.generate_rollback().ends_with(
+            "ALTER TABLE orders ADD CONSTRAINT orders_code_key UNIQUE (code);\n\
+             ALTER TABLE orders ADD CONSTRAINT orders_total_check CHECK (total >= 0);\n"
+        ));
+
+        // The catalog's spelling of an expression matches schema.json's
+        let desired = &DbConstraint::from_table("orders", &to.tables["orders"])[0];
+        let current = DbConstraint::from_catalog(
+            "orders_total_check".to_string(),
+            "CHECK",
+            vec!["total".to_string()],
+            "CHECK ((total > (0)::numeric))",
+        )
+        .unwrap();
+        assert_eq!(
+            current.expression.as_deref(),
+            Some("(total > (0)::numeric)")
+        );
+        assert!(current.same_as(desired));
+        let status = |definition: &str| DbConstraint {
+            name: "orders_status_check".to_string(),
+            constraint_type: ConstraintType::Check,
+            columns: Vec::new(),
+            expression: Some(definition.to_string()),
+            deferrable: false,
+            initially_deferred: false,
+        };
+        assert!(status("status NOT IN ('new', 'paid')").same_as(&status(
+            "((status)::text <> ALL ((ARRAY['new'::character varying, 'paid'::character varying])::text[]))"
+        )));
+
+        // The constraints survive a trip through the database's form
+        let current = DbSchema::from_json_schema(&to);
+        let json = current.to_json_schema();
+        assert!(!compare_schemas(&json, &current).has_changes());
+        let unique = DbSchema::from_json_schema(&from).to_json_schema();
+        assert!(unique.tables["orders"].columns["code"].is_unique());
+    }
+
+    #[test]
+    fn test_diff_deferrable_constraints() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let tables = |deferral: &str| {
+            format!(
+                r#""tables": {{
+                    "users": {{"columns": {{
+                        "id": {{"name": "id", "type": "bigint", "isPrimaryKey": true}}
+                    }}}},
+                    "orders": {{"columns": {{
+                        "id": {{"name": "id", "type": "bigint", "isPrimaryKey": true}},
+                        "user_id": {{"name": "user_id", "type": "bigint"}},
+                        "code": {{"name": "code", "type": "text"}}
+                    }}, "constraints": [
+                        {{"constraintType": "unique", "columns": ["code"]{deferral}}},
+                        {{"name": "orders_user_fk", "constraintType": "foreign key",
+                          "columns": ["user_id"],
+                          "references": {{"table": "users", "column": "id"}}{deferral}}}
+                    ]}}
+                }}"#
+            )
+        };
+        let from = schema(&format!("{{{}}}", tables("")));
+        let to = schema(&format!(
+            "{{{}}}",
+            tables(r#", "deferrable": true, "initiallyDeferred": true"#)
+        ));
+
+        // A changed deferral is a drop and an add
+        let diff = diff_schemas(&from, &to);
+        assert_eq!(
+            diff.add_constraints,
+            vec!["orders UNIQUE (code) DEFERRABLE INITIALLY DEFERRED"]
+        );
+        let sql = diff.sql();
+        assert!(sql.contains(
+            "ALTER TABLE orders ADD CONSTRAINT orders_code_key UNIQUE (code) \
+             DEFERRABLE INITIALLY DEFERRED;"
+        ));
+        assert!(sql.contains(
+            "ALTER TABLE orders ADD CONSTRAINT orders_user_fk FOREIGN KEY (user_id) \
+             REFERENCES users (id) DEFERRABLE INITIALLY DEFERRED;"
+        ));
+        let mut sql = String::new();
+        Postgres
+            .write_create_table(&mut sql, "orders", &to.tables["orders"])
+            .unwrap();
+        assert!(sql.contains("UNIQUE (code) DEFERRABLE INITIALLY DEFERRED"));
+
+        // The catalog's definition carries the deferral
+        let current = DbConstraint::from_catalog(
+            "orders_code_key".to_string(),
+            "UNIQUE",
+            vec!["code".to_string()],
+            "UNIQUE (code) DEFERRABLE INITIALLY DEFERRED",
+        )
+        .unwrap();
+        assert!(current.deferrable && current.initially_deferred);
+        assert!(current.same_as(&DbConstraint::from_table("orders", &to.tables["orders"])[0]));
+        let current = DbSchema::from_json_schema(&to);
+        assert!(!compare_schemas(&current.to_json_schema(), &current).has_changes());
+
+        // SQLite checks them immediately, and says so
+        let sqlite =
+            |deferral: &str| schema(&format!(r#"{{"dialect": "sqlite", {}}}"#, tables(deferral)));
+        let diff = diff_schemas(&sqlite(""), &sqlite(r#", "deferrable": true"#));
+        assert!(!diff.sql().contains("DEFERRABLE"));
+        assert_eq!(diff.warnings.len(), 2);
+        assert!(diff.warnings[0].contains("checked immediately"));
+    }
+
+    #[test]
+    fn test_diff_enums() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"enums": {"status": ["active", "archived"], "mood": ["happy"]}, "tables": {}}"#,
+        );
+        let to = schema(
+            r#"{"enums": {"status": ["draft", "active", "paused", "deleted"], "role": ["admin"]},
+                "tables": {"users": {"columns": {
+                    "role": {"name": "role", "type": "role", "isNotNull": true}
+                }}}}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        assert_eq!(diff.create_enums, vec!["role"]);
+        assert_eq!(
+            diff.add_enum_values["status"],
+            vec!["draft", "paused", "deleted"]
+        );
+        assert_eq!(diff.drop_enums, vec!["mood"]);
+        assert_eq!(diff.warnings.len(), 1);
+        assert!(diff.warnings[0].contains("'archived'"));
+        assert_eq!(
+            diff.sql(),
+            "CREATE TYPE role AS ENUM ('admin');\n\
+             ALTER TYPE status ADD VALUE IF NOT EXISTS 'draft' BEFORE 'active';\n\
+             ALTER TYPE status ADD VALUE IF NOT EXISTS 'paused' AFTER 'active';\n\
+             ALTER TYPE status ADD VALUE IF NOT EXISTS 'deleted' AFTER 'paused';\n\
+             \n-- Create table users\nCREATE TABLE users (\n  role role NOT NULL\n);\n\
+             DROP TYPE IF EXISTS mood;\n"
+        );
+        let rollback = diff.generate_rollback();
+        assert!(rollback.starts_with("CREATE TYPE mood AS ENUM ('happy');\n"));
+        assert!(rollback.ends_with("DROP TYPE IF EXISTS role;\n"));
+
+        // A value left behind is a warning, not a change
+        let mut current = DbSchema::from_json_schema(&to);
+        current.enums["status"].push("archived".to_string());
+        let diff = compare_schemas(&to, &current);
+        assert!(!diff.has_changes());
+        assert_eq!(diff.warnings.len(), 1);
+
+        // Without `enums` the database's enum types are left alone; an empty
+        // map still drops them
+        let mut unmanaged = to.clone();
+        unmanaged.enums = None;
+        let diff = compare_schemas(&unmanaged, &current);
+        assert!(diff.drop_enums.is_empty() && diff.warnings.is_empty());
+        unmanaged.enums = Some(IndexMap::new());
+        assert_eq!(compare_schemas(&unmanaged, &current).drop_enums.len(), 2);
+
+        // Databases without enum types have nothing to diff
+        let mut mysql = from.clone();
+        mysql.dialect = Some("mysql".to_string());
+        assert!(diff_schemas(&mysql, &to).create_enums.is_empty());
+    }
+
+    #[test]
+    fn test_checksum_ignores_cosmetic_differences() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let to = schema(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}, "email": {"name": "email", "type": "varchar"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        );
+        let reordered = schema(
+            r#"{"tables": {
+                "posts": {"columns": {"id": {"name": "id", "type": "BIGINT"}}},
+                "users": {"columns": {"email": {"name": "email", "type": "VARCHAR"}, "id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        );
+        let empty = crate::schema::Schema::default();
+
+        let diff = diff_schemas(&empty, &to);
+        assert_eq!(diff.checksum(), diff_schemas(&empty, &reordered).checksum());
+
+        let mut changed = to.clone();
+        changed.tables["users"].columns["email"].data_type = "text".to_string();
+        assert_ne!(diff.checksum(), diff_schemas(&empty, &changed).checksum());
+    }
+
+    #[test]
+    fn test_serial_columns_match_their_introspected_form() {
+        let schema: crate::schema::Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigserial", "isPrimaryKey": true},
+                "n": {"name": "n", "type": "serial"},
+                "s": {"name": "s", "type": "smallserial", "isNotNull": true}
+            }}}}"#,
+        )
+        .unwrap();
+        // As the catalog reports them: integers, NOT NULL, with a sequence default
+        let mut current = DbSchema::from_json_schema(&schema);
+        for (column, data_type) in [("id", "bigint"), ("n", "integer"), ("s", "smallint")] {
+            let column = &mut current.tables["users"].columns[column];
+            column.data_type = data_type.to_string();
+            column.is_nullable = false;
+            column.default_value = Some(format!("nextval('users_{}_seq'::regclass)", column.name));
+        }
+
+        let diff = compare_schemas(&schema, &current);
+        assert!(diff.operations.is_empty(), "{:?}", diff.operations);
+
+        // A real type change converts to the serial's integer
+        current.tables["users"].columns["n"].data_type = "text".to_string();
+        assert_eq!(
+            compare_schemas(&schema, &current).sql(),
+            "ALTER TABLE users ALTER COLUMN n TYPE INTEGER USING n::INTEGER;\n"
+        );
+    }
+
+    #[test]
+    fn test_changes_sqlite_cannot_make_are_warnings() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let mut current = DbSchema::from_json_schema(&schema(
+            r#"{"tables": {
+                "teams": {"columns": {"id": {"name": "id", "type": "integer", "isPrimaryKey": true}}},
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "integer", "isPrimaryKey": true},
+                    "active": {"name": "active", "type": "boolean", "default": "true"},
+                    "team_id": {"name": "team_id", "type": "integer"}
+                }}
+            }}"#,
+        ));
+        current.dialect = "sqlite".to_string();
+        let desired = schema(
+            r#"{"tables": {
+                "teams": {"columns": {"id": {"name": "id", "type": "integer", "isPrimaryKey": true}}},
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "integer", "isPrimaryKey": true},
+                    "active": {"name": "active", "type": "boolean", "default": "false"},
+                    "team_id": {"name": "team_id", "type": "integer",
+                                "references": {"table": "teams", "column": "id"}},
+                    "nick": {"name": "nick", "type": "text"}
+                }}
+            }}"#,
+        );
+
+        let diff = compare_schemas(&desired, &current);
+        assert_eq!(diff.sql(), "ALTER TABLE users ADD COLUMN nick TEXT;\n");
+        assert_eq!(
+            diff.warnings,
+            vec![
+                "Foreign key users (team_id) -> teams (id) is not added: foreign keys cannot \
+                 be added in place, so rebuild 'users' to add it",
+                "Column 'users.active' keeps its default: columns cannot be altered in place, \
+                 so rebuild the table to change it",
+            ]
+        );
+
+        current.dialect = "postgresql".to_string();
+        let diff = compare_schemas(&desired, &current);
+        assert!(diff.warnings.is_empty());
+        assert_eq!(diff.alter_columns["users"][0].name, "active");
+    }
+
+    #[test]
+    fn test_added_columns_get_their_default_in_one_sync() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let desired = schema(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "active": {"name": "active", "type": "boolean", "isNotNull": true, "default": "true"}
+            }}}}"#,
+        );
+        for dialect in ["postgresql", "mysql"] {
+            let mut current = DbSchema::from_json_schema(&schema(
+                r#"{"tables": {"users": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true}
+                }}}}"#,
+            ));
+            current.dialect = dialect.to_string();
+
+            let diff = compare_schemas(&desired, &current);
+            assert_eq!(
+                diff.sql(),
+                "ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;\n"
+            );
+            // The column as the database then has it leaves nothing to do
+            for (table, columns) in &diff.create_columns {
+                for column in columns {
+                    current.tables[table]
+                        .columns
+                        .insert(column.name.clone(), column.clone());
+                }
+            }
+            assert!(!compare_schemas(&desired, &current).has_changes());
+        }
+    }
+
+    #[test]
+    fn test_write_sql_streams_the_diff_sql() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from =
+            schema(r#"{"tables": {"old": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#);
+        let to = schema(
+            r#"{"tables": {"new": {"columns": {"name": {"name": "name", "type": "varchar", "size": 40, "isNotNull": true}}}}}"#,
+        );
+
+        let diff = diff_schemas(&from, &to);
+        let sql = diff.sql();
+        assert_eq!(
+            sql,
+            "DROP TABLE IF EXISTS old CASCADE;\n\n-- Create table new\nCREATE TABLE new (\n  name VARCHAR(40) NOT NULL\n);\n"
+        );
+        let mut streamed = String::new();
+        diff.write_sql(&mut streamed).unwrap();
+        assert_eq!(streamed, sql);
+        assert_eq!(diff.render(&Postgres), sql);
+
+        let json = serde_json::to_value(diff.with_sql()).unwrap();
+        assert_eq!(json["sql"], sql);
+        assert_eq!(json["drop_tables"], serde_json::json!(["old"]));
+    }
+
+    #[test]
+    fn test_diff_indexes() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let mut current = DbSchema::from_json_schema(&schema(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "email": {"name": "email", "type": "text", "isUnique": true},
+                "name": {"name": "name", "type": "text"},
+                "team_id": {"name": "team_id", "type": "bigint"}
+            }}}}"#,
+        ));
+        let index = |name: &str, definition: &str| DbIndex {
+            name: name.to_string(),
+            unique: definition.starts_with("CREATE UNIQUE"),
+            definition: definition.to_string(),
+        };
+        let users = current.tables.get_mut("users").unwrap();
+        users.set_indexes(vec![
+            index(
+                "users_pkey",
+                "CREATE UNIQUE INDEX users_pkey ON public.users USING btree (id)",
+            ),
+            index(
+                "users_email_key",
+                "CREATE UNIQUE INDEX users_email_key ON public.users USING btree (email)",
+            ),
+            index(
+                "idx_users_lower_email",
+                "CREATE INDEX idx_users_lower_email ON public.users USING btree (lower(email))",
+            ),
+            index(
+                "idx_users_name",
+                "CREATE INDEX idx_users_name ON public.users USING btree (name)",
+            ),
+            index(
+                "idx_users_team",
+                "CREATE INDEX idx_users_team ON public.users USING btree (team_id) \
+                 WHERE (team_id IS NOT NULL)",
+            ),
+        ]);
+        // The primary key's and the unique constraint's indexes are theirs
+        let names: Vec<&str> = users.indexes.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["idx_users_lower_email", "idx_users_name", "idx_users_team"]
+        );
+
+        let desired = schema(
+            r#"{"tables": {
+                "users": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "email": {"name": "email", "type": "text", "isUnique": true},
+                    "name": {"name": "name", "type": "text"},
+                    "team_id": {"name": "team_id", "type": "bigint"}
+                }, "indexes": [
+                    {"name": "idx_users_lower_email", "columns": ["LOWER(email)"]},
+                    {"name": "idx_users_team", "columns": ["team_id"], "unique": true}
+                ]},
+                "posts": {"columns": {
+                    "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                    "user_id": {"name": "user_id", "type": "bigint"}
+                }, "indexes": [{"name": "idx_posts_user_id", "columns": ["user_id"]}]}
+            }}"#,
+        );
+        let diff = compare_schemas(&desired, &current);
+        let operations: Vec<String> = diff.operations.iter().map(|o| o.to_string()).collect();
+        assert_eq!(
+            operations,
+            vec![
+                "drop index users.idx_users_name",
+                "drop index users.idx_users_team",
+                "create table posts",
+                "create index posts.idx_posts_user_id",
+                "create index users.idx_users_team",
+            ]
+        );
+        assert_eq!(
+            diff.drop_indexes,
+            vec![
+                "idx_users_name ON users (name)",
+                "idx_users_team ON users (team_id)"
+            ]
+        );
+        assert_eq!(
+            diff.create_indexes[1],
+            "UNIQUE idx_users_team ON users (team_id)"
+        );
+        assert!(diff.sql().ends_with(
+            "CREATE INDEX idx_posts_user_id ON posts (user_id);\n\
+             CREATE UNIQUE INDEX idx_users_team ON users (team_id);\n"
+        ));
+        let rollback = diff.generate_rollback();
+        assert!(rollback.starts_with("DROP INDEX IF EXISTS idx_users_team;\n"));
+        assert!(rollback.contains(
+            "CREATE INDEX idx_users_team ON public.users USING btree (team_id) \
+             WHERE (team_id IS NOT NULL);\n"
+        ));
+
+        // Without `indexes`, a table's indexes are left alone
+        let mut unmanaged = desired.clone();
+        unmanaged.tables.get_mut("users").unwrap().indexes = None;
+        assert!(compare_schemas(&unmanaged, &current)
+            .operations
+            .iter()
+            .all(|o| o.table() == Some("posts")));
+
+        // Pulled indexes, partial and expression ones included, are in sync
+        let pulled = current.to_json_schema();
+        let team = &pulled.tables["users"].indexes.as_ref().unwrap()[2];
+        assert_eq!(team.where_clause.as_deref(), Some("team_id IS NOT NULL"));
+        assert!(!compare_schemas(&pulled, &current).has_changes());
+    }
+
+    #[test]
+    fn test_diff_grants_of_managed_roles() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let tables = r#""users": {"columns": {"id": {"name": "id", "type": "bigint"}, "email": {"name": "email", "type": "text"}}}"#;
+        let from = schema(&format!(
+            r#"{{"tables": {{{}}}, "roles": ["app", "admin"], "grants": [
+                {{"role": "app", "table": "users", "privileges": ["SELECT", "INSERT", "UPDATE"]}},
+                {{"role": "admin", "table": "users", "privileges": ["DELETE"]}}
+            ]}}"#,
+            tables
+        ));
+        let to = schema(&format!(
+            r#"{{"tables": {{{}}}, "roles": ["app", "reporting"], "grants": [
+                {{"role": "app", "table": "users", "privileges": ["select", "insert"]}},
+                {{"role": "reporting", "table": "users", "privileges": ["SELECT"], "columns": ["id", "email"]}}
+            ]}}"#,
+            tables
+        ));
+
+        let diff = diff_schemas(&from, &to);
+        assert_eq!(diff.create_roles, vec!["reporting"]);
+        assert_eq!(diff.revokes, vec!["UPDATE on users from app"]);
+        assert_eq!(
+            diff.grants,
+            vec!["SELECT (email, id) on users to reporting"]
+        );
+        assert_eq!(
+            diff.sql(),
+            "REVOKE UPDATE ON users FROM app;\nCREATE ROLE reporting;\nGRANT SELECT (email, id) ON users TO reporting;\n"
+        );
+        assert!(diff.data_loss_warning.is_empty());
+
+        let round_trip = DbSchema::from_json_schema(&to).to_json_schema();
+        assert_eq!(round_trip.roles, vec!["app", "reporting"]);
+        assert!(!diff_schemas(&round_trip, &to).has_changes());
+    }
+
+    #[test]
+    fn test_operations_serialize_and_filter() {
+        let schema = |json: &str| -> crate::schema::Schema { serde_json::from_str(json).unwrap() };
+        let from = schema(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}, "nick": {"name": "nick", "type": "text"}}},
+                "legacy": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        );
+        let to = schema(
+            r#"{"tables": {"users": {"columns": {"id": {"name": "id", "type": "bigint"}, "age": {"name": "age", "type": "integer"}}}}}"#,
+        );
+
+        let mut diff = diff_schemas(&from, &to);
+        let ops: Vec<(&str, &str)> = diff
+            .operations
+            .iter()
+            .map(|op| {
+                (
+                    op.table().unwrap(),
+                    op.data_loss().map_or("keep", |_| "lose"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            vec![("users", "lose"), ("legacy", "lose"), ("users", "keep")]
+        );
+        let additive: Vec<bool> = diff.operations.iter().map(Operation::is_additive).collect();
+        assert_eq!(additive, vec![false, false, true]);
+
+        let json = serde_json::to_value(&diff.operations).unwrap();
+        assert_eq!(json[0]["op"], "drop_column");
+        assert_eq!(json[2]["column"]["name"], "age");
+        let round_trip: Vec<Operation> = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            SchemaDiff::from_operations("postgresql", round_trip).sql(),
+            diff.sql()
+        );
+
+        diff.retain(|op| !matches!(op, Operation::DropTable { .. }));
+        assert!(diff.drop_tables.is_empty());
+        assert_eq!(
+            diff.data_loss_warning,
+            vec!["Column 'users.nick' will be dropped"]
+        );
+        assert_eq!(
+            diff.sql(),
+            "ALTER TABLE users DROP COLUMN IF EXISTS nick;\nALTER TABLE users ADD COLUMN age INTEGER NULL;\n"
+        );
+    }
+}
@@ -0,0 +1,2303 @@
+/**
+ * Stratus Engine Module
+ *
+ * The workflows behind `stratus sync`, `deploy`, `migrate dev`, `migrate
+ * reset`, `db push` and `generate`, for embedding in other Rust programs:
+ * stratus.json resolution, connecting, diffing, writing and applying
+ * migrations, and running generators. Nothing here prints; results come
+ * back as reports and progress goes to a `Progress`.
+ */
+use crate::ast::QueryFile;
+use crate::config::{
+    ConfigError, ConfigManager, DatabaseProvider, EnvironmentConfig, GeneratorConfig,
+};
+use crate::db::{DbBackend, DbConfig, DbError, DbSchema, Operation, SchemaDiff, StratusClient};
+use crate::migrate::{MigrateError, Migration, MigrationsTable, PlanDecision};
+use crate::parser::ParseError;
+use crate::policy::Change;
+use crate::schema::Schema;
+use crate::snapshot::{Snapshot, SnapshotError};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Engine errors
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Db(#[from] DbError),
+
+    #[error("stratus.json not found")]
+    NoConfig,
+
+    #[error("Datasource required")]
+    DatasourceRequired,
+
+    #[error("No database URL provided")]
+    MissingUrl,
+
+    #[error("Cannot read {}: {}", .path.display(), .source)]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Cannot write {}: {}", .path.display(), .source)]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid schema {}: {}", .path.display(), .message)]
+    InvalidSchema { path: PathBuf, message: String },
+
+    #[error("Failed to parse {}: {}", .path.display(), .source)]
+    Parse { path: PathBuf, source: ParseError },
+
+    #[error(transparent)]
+    Migrate(#[from] MigrateError),
+
+    #[error("Failed to introspect database: {0}")]
+    Introspect(DbError),
+
+    /// `applied` counts the migrations of the batch committed before this one
+    #[error("Migration {id}_{name} failed at {message}")]
+    MigrationFailed {
+        id: String,
+        name: String,
+        message: String,
+        applied: usize,
+    },
+
+    #[error("No generators configured in stratus.json")]
+    NoGenerators,
+
+    /// Another process holds the lock on an output directory
+    #[error("{} is locked by another stratus process{}", .dir.display(), .holder.map(|pid| format!(" (pid {})", pid)).unwrap_or_default())]
+    OutputLocked { dir: PathBuf, holder: Option<u32> },
+
+    /// A baseline was asked for on a database with migrations recorded
+    #[error("The database already has {0} migration(s) recorded")]
+    HasMigrations(usize),
+
+    /// The environment sets `forbidForceReset`
+    #[error("Environment '{0}' forbids a force reset")]
+    ResetForbidden(String),
+
+    /// The shadow database cannot be reached, or the migration history does
+    /// not replay cleanly on it
+    #[error("Shadow database: {0}")]
+    Shadow(#[source] Box<crate::error::StratusError>),
+
+    /// A statement of a push or reset failed; the transaction was rolled back
+    #[error("{step} failed at statement {statement}: {source}")]
+    StatementFailed {
+        step: &'static str,
+        statement: usize,
+        source: DbError,
+    },
+
+    /// The environment's policies refuse the SQL; one message per broken rule
+    #[error("Environment '{env}' policies refuse this change: {}", .violations.join("; "))]
+    Policy {
+        env: String,
+        violations: Vec<String>,
+    },
+}
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+/// Observer of statement execution, e.g. a progress bar
+pub trait Progress {
+    /// Number of statements about to run
+    fn start(&mut self, _statements: usize) {}
+    /// A statement is about to run; `summary` is its first line
+    fn statement(&mut self, _summary: &str) {}
+    /// The current statement finished
+    fn statement_done(&mut self) {}
+    /// A migration was committed
+    fn migration_applied(&mut self, _migration: &Migration, _elapsed: Duration) {}
+    /// A migration was rolled back
+    fn migration_failed(&mut self, _migration: &Migration) {}
+}
+
+/// Reports nothing
+impl Progress for () {}
+
+/// Where the engine's stratus.json comes from
+#[derive(Debug, Default)]
+enum ConfigSource {
+    /// Searched for upward from the current directory; optional
+    #[default]
+    Discover,
+    /// An explicit path, which must exist
+    Path(PathBuf),
+    /// Already loaded, or deliberately absent
+    Loaded(Option<Box<ConfigManager>>),
+}
+
+/// Builder for [`Engine`]
+#[derive(Debug, Default)]
+pub struct EngineBuilder {
+    config: ConfigSource,
+    schema: Option<PathBuf>,
+    migrations_dir: Option<PathBuf>,
+    datasource: Option<String>,
+    env: Option<String>,
+    url: Option<String>,
+    no_cache: bool,
+}
+
+impl EngineBuilder {
+    /// Load stratus.json from this path instead of searching for it
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config = ConfigSource::Path(path.into());
+        self
+    }
+
+    /// Use an already loaded configuration
+    pub fn config(mut self, config: ConfigManager) -> Self {
+        self.config = ConfigSource::Loaded(Some(Box::new(config)));
+        self
+    }
+
+    /// Run without stratus.json; the URL comes from `url` or DATABASE_URL
+    pub fn without_config(mut self) -> Self {
+        self.config = ConfigSource::Loaded(None);
+        self
+    }
+
+    /// Override the schema.json path
+    pub fn schema(mut self, path: impl Into<PathBuf>) -> Self {
+        self.schema = Some(path.into());
+        self
+    }
+
+    /// Override the migrations directory
+    pub fn migrations_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.migrations_dir = Some(path.into());
+        self
+    }
+
+    /// Target datasource from stratus.json
+    pub fn datasource(mut self, name: impl Into<String>) -> Self {
+        self.datasource = Some(name.into());
+        self
+    }
+
+    /// Environment, which selects the datasource and connection settings
+    pub fn env(mut self, name: impl Into<String>) -> Self {
+        self.env = Some(name.into());
+        self
+    }
+
+    /// Connection URL, overriding the datasource's
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Introspect the database even when the datasource enables introspectionCache
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
+
+    /// Load the configuration and resolve paths and the datasource
+    pub fn build(self) -> EngineResult<Engine> {
+        let config = match self.config {
+            ConfigSource::Discover => match ConfigManager::load(None) {
+                Ok(config) => Some(config),
+                Err(ConfigError::NotFound(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
+            ConfigSource::Path(path) => Some(ConfigManager::load(Some(&path))?),
+            ConfigSource::Loaded(config) => config.map(|c| *c),
+        };
+
+        let datasource = match &config {
+            Some(cfg) => {
+                cfg.resolve_datasource_name(self.datasource.as_deref(), self.env.as_deref())?
+            }
+            None => self.datasource,
+        };
+        let schema_path = self
+            .schema
+            .or_else(|| config.as_ref().map(|cfg| cfg.get_schema_path()))
+            .unwrap_or_else(|| PathBuf::from("schema.json"));
+        let migrations_dir = self
+            .migrations_dir
+            .or_else(|| config.as_ref().map(|cfg| cfg.get_migrations_path()))
+            .unwrap_or_else(|| PathBuf::from("migrations"));
+
+        Ok(Engine {
+            config,
+            schema_path,
+            migrations_dir,
+            datasource,
+            env: self.env,
+            url: self.url,
+            no_cache: self.no_cache,
+        })
+    }
+}
+
+/// A resolved project: configuration, paths and target database
+#[derive(Debug)]
+pub struct Engine {
+    config: Option<ConfigManager>,
+    schema_path: PathBuf,
+    migrations_dir: PathBuf,
+    datasource: Option<String>,
+    env: Option<String>,
+    url: Option<String>,
+    no_cache: bool,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub fn config(&self) -> Option<&ConfigManager> {
+        self.config.as_ref()
+    }
+
+    pub fn schema_path(&self) -> &Path {
+        &self.schema_path
+    }
+
+    pub fn migrations_dir(&self) -> &Path {
+        &self.migrations_dir
+    }
+
+    /// Datasource name, given directly or through the environment
+    pub fn datasource(&self) -> Option<&str> {
+        self.datasource.as_deref()
+    }
+
+    pub fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+
+    /// The environment's entry in stratus.json, if any
+    pub fn environment(&self) -> Option<&EnvironmentConfig> {
+        self.config
+            .as_ref()
+            .zip(self.env.as_deref())
+            .and_then(|(cfg, env)| cfg.get_environment(env))
+    }
+
+    /// Rules of the environment's `policies` that `changes` break
+    pub fn policy_violations(&self, changes: &[Change]) -> Vec<String> {
+        self.environment()
+            .map(|env| crate::policy::check(&env.policies, changes))
+            .unwrap_or_default()
+    }
+
+    /// Fail with [`EngineError::Policy`] when `changes` break a policy
+    fn enforce_policies(&self, changes: &[Change]) -> EngineResult<()> {
+        self.refuse(self.policy_violations(changes))
+    }
+
+    /// Fail with [`EngineError::Policy`] when the SQL of `diff`, generated
+    /// by `name`, breaks a policy; see [`crate::policy::check_plan`]
+    pub fn enforce_plan_policies(&self, name: &str, diff: &SchemaDiff) -> EngineResult<()> {
+        let sql = diff.sql();
+        let violations = self
+            .environment()
+            .map(|env| crate::policy::check_plan(&env.policies, &Change::unreviewed(name, &sql)))
+            .unwrap_or_default();
+        self.refuse(violations)
+    }
+
+    fn refuse(&self, violations: Vec<String>) -> EngineResult<()> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(EngineError::Policy {
+            env: self.env.clone().unwrap_or_default(),
+            violations,
+        })
+    }
+
+    /// Whether the URL was given directly rather than through stratus.json
+    pub fn has_url_override(&self) -> bool {
+        self.url.is_some()
+    }
+
+    pub fn migrations_table(&self) -> MigrationsTable {
+        self.config
+            .as_ref()
+            .map(|cfg| cfg.migrations_table())
+            .unwrap_or_default()
+    }
+
+    /// Database URL: the override, then the datasource, then DATABASE_URL
+    pub fn database_url(&self) -> EngineResult<String> {
+        resolve_database_url(
+            self.config.as_ref(),
+            self.datasource.as_deref(),
+            self.url.as_deref(),
+        )
+    }
+
+    /// Connection settings for the target database
+    pub fn db_config(&self) -> EngineResult<DbConfig> {
+        Ok(connection_config(
+            self.config.as_ref(),
+            self.datasource.as_deref(),
+            self.env.as_deref(),
+            &self.database_url()?,
+            self.no_cache,
+        ))
+    }
+
+    pub fn connect(&self) -> EngineResult<StratusClient> {
+        Ok(StratusClient::connect(&self.db_config()?)?)
+    }
+
+    pub fn load_schema(&self) -> EngineResult<Schema> {
+        read_schema(&self.schema_path)
+    }
+
+    /// Where schema.json snapshots are kept
+    pub fn snapshot_dir(&self) -> PathBuf {
+        crate::snapshot::snapshot_dir(self.config.as_ref())
+    }
+
+    /// Save schema.json to the snapshot history, unless it is unchanged
+    /// since the last snapshot or there is none
+    pub fn snapshot(&self) -> Result<Option<Snapshot>, SnapshotError> {
+        let contents = match std::fs::read_to_string(&self.schema_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(SnapshotError::Read {
+                    path: self.schema_path.clone(),
+                    source,
+                })
+            }
+        };
+        crate::snapshot::record(&self.snapshot_dir(), &contents)
+    }
+
+    /// [`Engine::snapshot`] after a sync or deploy, which a failure to save
+    /// one does not undo
+    fn snapshot_after_apply(&self) {
+        if let Err(e) = self.snapshot() {
+            tracing::warn!("Failed to save a schema snapshot: {}", e);
+        }
+    }
+
+    pub fn load_migrations(&self) -> EngineResult<Vec<Migration>> {
+        Ok(crate::migrate::load_migrations(&self.migrations_dir)?)
+    }
+
+    /// Introspect the database, leaving out the migrations table
+    pub fn introspect(&self, client: &mut dyn DbBackend) -> EngineResult<DbSchema> {
+        introspect(client, &self.migrations_table())
+    }
+
+    /// Apply migrations in order, one transaction each, recording them as applied
+    pub fn apply(
+        &self,
+        client: &mut dyn DbBackend,
+        migrations: &[&Migration],
+        progress: &mut dyn Progress,
+    ) -> EngineResult<Duration> {
+        apply_migrations(client, &self.migrations_table(), migrations, progress)
+    }
+
+    /// Diff schema.json against the database and turn the changes into a migration
+    pub fn sync(&self) -> Sync<'_> {
+        Sync {
+            engine: self,
+            name: None,
+            force: false,
+            dry_run: false,
+            override_policy: false,
+        }
+    }
+
+    /// Apply pending migrations
+    pub fn deploy(&self) -> Deploy<'_> {
+        Deploy {
+            engine: self,
+            override_policy: false,
+        }
+    }
+
+    /// Write a migration for the changes to schema.json and apply it, for
+    /// development databases
+    pub fn dev(&self) -> Dev<'_> {
+        Dev {
+            engine: self,
+            name: None,
+            shadow_url: None,
+        }
+    }
+
+    /// Drop everything in the database and re-apply every migration
+    pub fn reset(&self) -> Reset<'_> {
+        Reset {
+            engine: self,
+            override_policy: false,
+        }
+    }
+
+    /// Make the database match schema.json directly, without a migration
+    pub fn push(&self) -> Push<'_> {
+        Push {
+            engine: self,
+            force_reset: false,
+            override_policy: false,
+        }
+    }
+
+    /// Run the generators configured in stratus.json
+    pub fn generate(&self) -> Generate<'_> {
+        Generate {
+            engine: self,
+            schema: None,
+        }
+    }
+}
+
+// ==================== Sync ====================
+
+/// Builder for a sync run, from [`Engine::sync`]
+pub struct Sync<'a> {
+    engine: &'a Engine,
+    name: Option<String>,
+    force: bool,
+    dry_run: bool,
+    override_policy: bool,
+}
+
+/// What a sync would do, before anything is written
+#[derive(Debug)]
+pub struct SyncPlan {
+    pub schema: Schema,
+    pub db_schema: DbSchema,
+    pub diff: SchemaDiff,
+    /// Checksum of the diff, stored with the migration
+    pub checksum: String,
+    /// Migration name, given or generated from the changes
+    pub name: String,
+    /// Migrations on disk, with their applied status
+    pub existing: Vec<Migration>,
+    /// An existing migration with the same changes (never set with `force`)
+    pub duplicate: Option<String>,
+    /// Existing migrations touching the tables created or dropped here
+    pub conflicts: Vec<String>,
+    /// The operations as compared, which [`SyncPlan::edit`] decisions index
+    generated: Vec<Operation>,
+    decisions: Vec<PlanDecision>,
+    migrations_dir: PathBuf,
+    provider: DatabaseProvider,
+}
+
+/// Outcome of [`Sync::run`]
+#[derive(Debug)]
+pub struct SyncReport {
+    pub plan: SyncPlan,
+    /// The migration written, unless there were no changes or a duplicate exists
+    pub migration: Option<Migration>,
+    /// Time taken to apply the migration; None on a dry run
+    pub elapsed: Option<Duration>,
+}
+
+impl<'a> Sync<'a> {
+    /// Migration name (generated from the changes by default)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Write a migration even when one with the same changes exists
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Write the migration but don't apply it
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Plan and apply the migration even when it breaks the environment's
+    /// policies
+    pub fn override_policy(mut self, override_policy: bool) -> Self {
+        self.override_policy = override_policy;
+        self
+    }
+
+    /// Compare schema.json with the database, creating the migrations table if needed
+    pub fn plan(&self, client: &mut dyn DbBackend) -> EngineResult<SyncPlan> {
+        let engine = self.engine;
+        let schema = engine.load_schema()?;
+        let table = engine.migrations_table();
+        let mut existing = engine.load_migrations()?;
+        track_migrations(client, &table, &mut existing)?;
+
+        let db_schema = introspect(client, &table)?;
+        let diff = crate::db::compare_schemas(&schema, &db_schema);
+        if !self.override_policy {
+            engine.enforce_plan_policies("sync", &diff)?;
+        }
+        let checksum = diff.checksum();
+        let generated = diff.operations.clone();
+
+        let duplicate = existing
+            .iter()
+            .find(|m| !self.force && m.meta.checksum.as_ref() == Some(&checksum))
+            .map(|m| m.meta.name.clone());
+        let mut conflicts = Vec::new();
+        for m in &existing {
+            let up_sql = m.up_sql()?;
+            if diff
+                .create_tables
+                .iter()
+                .chain(&diff.drop_tables)
+                .any(|table| up_sql.contains(table.as_str()))
+            {
+                conflicts.push(m.meta.name.clone());
+            }
+        }
+        let name = self.name.clone().unwrap_or_else(|| {
+            crate::migrate::generate_migration_name(&db_schema.to_json_schema(), &schema)
+        });
+
+        Ok(SyncPlan {
+            schema,
+            db_schema,
+            diff,
+            checksum,
+            name,
+            existing,
+            duplicate,
+            conflicts,
+            generated,
+            decisions: Vec::new(),
+            migrations_dir: engine.migrations_dir.clone(),
+            provider: engine.db_config()?.provider,
+        })
+    }
+
+    /// Plan, write the migration and apply it (unless a dry run)
+    pub fn run(
+        self,
+        client: &mut dyn DbBackend,
+        progress: &mut dyn Progress,
+    ) -> EngineResult<SyncReport> {
+        let plan = self.plan(client)?;
+        if !plan.diff.has_changes() || plan.duplicate.is_some() {
+            return Ok(SyncReport {
+                plan,
+                migration: None,
+                elapsed: None,
+            });
+        }
+
+        let migration = plan.write_migration()?;
+        let elapsed = if self.dry_run {
+            None
+        } else {
+            if !self.override_policy {
+                self.engine
+                    .enforce_policies(&[Change::migration(&migration)?])?;
+            }
+            let elapsed = self.engine.apply(client, &[&migration], progress)?;
+            self.engine.snapshot_after_apply();
+            Some(elapsed)
+        };
+        Ok(SyncReport {
+            plan,
+            migration: Some(migration),
+            elapsed,
+        })
+    }
+}
+
+impl SyncPlan {
+    /// The operations as compared, before any edit
+    pub fn generated(&self) -> &[Operation] {
+        &self.generated
+    }
+
+    /// Decisions of the last [`SyncPlan::edit`], recorded in meta.json
+    pub fn decisions(&self) -> &[PlanDecision] {
+        &self.decisions
+    }
+
+    /// Reorder, exclude or annotate operations before the migration is
+    /// written. `decisions` name the generated operations by index, in the
+    /// order to write them. The checksum stays the generated plan's, so a
+    /// change left out on purpose is not proposed again as a new migration.
+    pub fn edit(&mut self, decisions: Vec<PlanDecision>) {
+        let operations = decisions
+            .iter()
+            .filter(|d| !d.excluded)
+            .filter_map(|d| self.generated.get(d.index).cloned())
+            .collect();
+        self.diff = SchemaDiff::from_operations(self.diff.dialect.clone(), operations);
+        self.decisions = decisions;
+    }
+
+    /// Write the diff as a draft migration with its rollback
+    pub fn write_migration(&self) -> EngineResult<Migration> {
+        let mut migration = crate::migrate::create_migration(
+            &self.migrations_dir,
+            &self.name,
+            &self.diff.sql(),
+            &self.diff.generate_rollback(),
+            self.provider.as_str(),
+            Some(self.checksum.clone()),
+        )?;
+        if !self.decisions.is_empty() {
+            crate::migrate::record_plan(
+                &self.migrations_dir,
+                &mut migration,
+                self.decisions.clone(),
+            )?;
+        }
+        Ok(migration)
+    }
+}
+
+// ==================== Deploy ====================
+
+/// Builder for a deploy run, from [`Engine::deploy`]
+pub struct Deploy<'a> {
+    engine: &'a Engine,
+    override_policy: bool,
+}
+
+/// Outcome of [`Deploy::run`]
+#[derive(Debug)]
+pub struct DeployReport {
+    pub applied: Vec<Migration>,
+    pub elapsed: Duration,
+}
+
+impl Deploy<'_> {
+    /// Apply migrations even when they break the environment's policies
+    pub fn override_policy(mut self, override_policy: bool) -> Self {
+        self.override_policy = override_policy;
+        self
+    }
+
+    /// Migrations not yet applied (and not marked failed), creating the migrations table if needed
+    pub fn pending(&self, client: &mut dyn DbBackend) -> EngineResult<Vec<Migration>> {
+        let mut migrations = self.engine.load_migrations()?;
+        track_migrations(client, &self.engine.migrations_table(), &mut migrations)?;
+        Ok(migrations
+            .into_iter()
+            .filter(|m| !m.applied && m.meta.status != "failed")
+            .collect())
+    }
+
+    /// Check the migrations table against the migrations directory: every
+    /// applied migration must still be on disk with the checksum it had
+    pub fn verify(&self, client: &mut dyn DbBackend) -> EngineResult<()> {
+        let table = self.engine.migrations_table();
+        crate::migrate::ensure_migrations_table(client, &table)?;
+        let problems =
+            crate::migrate::check_history(client, &table, &self.engine.load_migrations()?)?;
+        if !problems.is_empty() {
+            return Err(MigrateError::History(problems.join("; ")).into());
+        }
+        Ok(())
+    }
+
+    /// Apply every pending migration, unless the history does not match the
+    /// migrations directory or they break the environment's policies
+    pub fn run(
+        self,
+        client: &mut dyn DbBackend,
+        progress: &mut dyn Progress,
+    ) -> EngineResult<DeployReport> {
+        self.verify(client)?;
+        let applied = self.pending(client)?;
+        if !self.override_policy {
+            let changes = applied
+                .iter()
+                .map(Change::migration)
+                .collect::<Result<Vec<_>, _>>()?;
+            self.engine.enforce_policies(&changes)?;
+        }
+        let elapsed = self
+            .engine
+            .apply(client, &applied.iter().collect::<Vec<_>>(), progress)?;
+        self.engine.snapshot_after_apply();
+        Ok(DeployReport { applied, elapsed })
+    }
+
+    /// The databases of a multi-target datasource: its listed URLs, then the
+    /// ones its query returns
+    ///
+    /// `None` for a datasource with one database, or when a URL was given.
+    pub fn targets(&self) -> EngineResult<Option<Targets>> {
+        let engine = self.engine;
+        let configured = engine
+            .config
+            .as_ref()
+            .zip(engine.datasource.as_deref())
+            .and_then(|(cfg, ds)| cfg.get_datasource(ds)?.targets.as_ref());
+        let Some(configured) = configured.filter(|_| engine.url.is_none()) else {
+            return Ok(None);
+        };
+
+        let mut urls = configured.urls.clone();
+        if let Some(query) = &configured.query {
+            let mut client = engine.connect()?;
+            client.query_each_values(query, 1000, |row| {
+                urls.extend(row.into_iter().next().flatten());
+                ControlFlow::Continue(())
+            })?;
+        }
+        let mut seen = std::collections::HashSet::new();
+        urls.retain(|url| seen.insert(url.clone()));
+        Ok(Some(Targets {
+            urls,
+            concurrency: configured.concurrency,
+        }))
+    }
+
+    /// Apply pending migrations to every target, `concurrency` at a time,
+    /// calling `done` as each one finishes; reports come back in target order
+    pub fn run_targets(
+        &self,
+        targets: &Targets,
+        done: impl Fn(&TargetReport) + std::marker::Sync,
+    ) -> Vec<TargetReport> {
+        let engine = self.engine;
+        let next = AtomicUsize::new(0);
+        let reports: Mutex<Vec<Option<TargetReport>>> =
+            Mutex::new(targets.urls.iter().map(|_| None).collect());
+
+        std::thread::scope(|scope| {
+            for _ in 0..targets.concurrency.clamp(1, targets.urls.len().max(1)) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(url) = targets.urls.get(index) else {
+                        break;
+                    };
+                    let _span = tracing::info_span!("target", index).entered();
+                    let mut config = connection_config(
+                        engine.config.as_ref(),
+                        engine.datasource.as_deref(),
+                        engine.env.as_deref(),
+                        url,
+                        engine.no_cache,
+                    );
+                    // A target's own URL says which database it is
+                    if let Some(provider) = DatabaseProvider::from_url(url) {
+                        config.provider = provider;
+                    }
+                    let result = crate::db::connect(&config)
+                        .map_err(EngineError::from)
+                        .and_then(|mut client| {
+                            engine
+                                .deploy()
+                                .override_policy(self.override_policy)
+                                .run(&mut *client, &mut ())
+                        });
+                    let report = TargetReport {
+                        url: crate::config::redact_url(url),
+                        result,
+                    };
+                    done(&report);
+                    reports.lock().expect("no worker panicked")[index] = Some(report);
+                });
+            }
+        });
+        reports
+            .into_inner()
+            .expect("no worker panicked")
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// The databases a multi-target datasource deploys to
+#[derive(Debug, Clone)]
+pub struct Targets {
+    pub urls: Vec<String>,
+    /// Targets deployed at the same time
+    pub concurrency: usize,
+}
+
+/// How the deploy to one target went
+#[derive(Debug)]
+pub struct TargetReport {
+    /// The target's URL, without its password
+    pub url: String,
+    pub result: EngineResult<DeployReport>,
+}
+
+// ==================== Dev ====================
+
+/// Builder for a dev migration, from [`Engine::dev`]
+pub struct Dev<'a> {
+    engine: &'a Engine,
+    name: Option<String>,
+    shadow_url: Option<String>,
+}
+
+/// What a dev run found, before the migration is written
+#[derive(Debug)]
+pub struct DevPlan {
+    pub schema: Schema,
+    pub db_schema: DbSchema,
+    pub diff: SchemaDiff,
+    /// Migration name, given or generated from the changes
+    pub name: String,
+    /// The replay on the shadow database; None without one
+    pub shadow: Option<ShadowReport>,
+    migrations_dir: PathBuf,
+    provider: DatabaseProvider,
+}
+
+/// The migration history replayed on the shadow database
+#[derive(Debug)]
+pub struct ShadowReport {
+    /// Migrations replayed
+    pub replayed: usize,
+    /// How the database differs from the replayed history
+    pub drift: SchemaDiff,
+}
+
+impl Dev<'_> {
+    /// Migration name (generated from the changes by default)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Shadow database to replay the migrations on, instead of the datasource's
+    pub fn shadow_database_url(mut self, url: impl Into<String>) -> Self {
+        self.shadow_url = Some(url.into());
+        self
+    }
+
+    /// The shadow database: the one given, else the datasource's
+    pub fn shadow_url(&self) -> Option<String> {
+        let engine = self.engine;
+        self.shadow_url.clone().or_else(|| {
+            engine
+                .config
+                .as_ref()
+                .zip(engine.datasource.as_deref())
+                .and_then(|(cfg, ds)| cfg.get_datasource(ds)?.shadow_database_url.clone())
+        })
+    }
+
+    /// Write an empty migration to fill in by hand; needs no database
+    pub fn create_empty(&self) -> EngineResult<Migration> {
+        let provider = match self.engine.db_config() {
+            Ok(config) => config.provider,
+            Err(EngineError::MissingUrl) => DatabaseProvider::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(crate::migrate::create_migration(
+            &self.engine.migrations_dir,
+            self.name.as_deref().unwrap_or("empty-migration"),
+            "-- Empty migration\n-- Add your SQL here",
+            "-- Empty migration rollback",
+            provider.as_str(),
+            None,
+        )?)
+    }
+
+    /// Compare schema.json with the database, after replaying the migration
+    /// history on the shadow database to detect drift
+    pub fn plan(&self, client: &mut dyn DbBackend) -> EngineResult<DevPlan> {
+        let engine = self.engine;
+        let schema = engine.load_schema()?;
+        let shadow_url = self.shadow_url();
+        crate::config::check_shadow_url(
+            engine.datasource.as_deref().unwrap_or("(cli)"),
+            &engine.database_url()?,
+            shadow_url.as_deref(),
+        )?;
+
+        let db_schema = engine.introspect(client)?;
+        let shadow = match &shadow_url {
+            Some(url) => Some(self.replay(url, &db_schema)?),
+            None => None,
+        };
+        let diff = crate::db::compare_schemas(&schema, &db_schema);
+        let name = self.name.clone().unwrap_or_else(|| {
+            crate::migrate::generate_migration_name(&db_schema.to_json_schema(), &schema)
+        });
+        Ok(DevPlan {
+            schema,
+            db_schema,
+            diff,
+            name,
+            shadow,
+            migrations_dir: engine.migrations_dir.clone(),
+            provider: engine.db_config()?.provider,
+        })
+    }
+
+    /// Replay the migrations on an emptied shadow database and compare the
+    /// result with the database
+    fn replay(&self, url: &str, db_schema: &DbSchema) -> EngineResult<ShadowReport> {
+        let engine = self.engine;
+        let shadow_error = |e: crate::error::StratusError| EngineError::Shadow(Box::new(e));
+        let config = DbConfig {
+            max_connections: 1,
+            ..connection_config(
+                engine.config.as_ref(),
+                engine.datasource.as_deref(),
+                engine.env.as_deref(),
+                url,
+                engine.no_cache,
+            )
+        };
+        let table = engine.migrations_table();
+        let mut shadow = crate::db::connect(&config).map_err(|e| shadow_error(e.into()))?;
+        let replayed = crate::migrate::replay_on_shadow(
+            &mut *shadow,
+            config.provider.dialect(),
+            &table,
+            &engine.load_migrations()?,
+        )
+        .map_err(shadow_error)?;
+        let shadow_schema = introspect(&mut *shadow, &table).map_err(|e| shadow_error(e.into()))?;
+        Ok(ShadowReport {
+            replayed,
+            drift: crate::db::compare_schemas(&shadow_schema.to_json_schema(), db_schema),
+        })
+    }
+}
+
+impl DevPlan {
+    /// Write the diff as a migration with its rollback
+    pub fn write_migration(&self) -> EngineResult<Migration> {
+        let down_sql = format!(
+            "-- Rollback for {}\n{}",
+            self.name,
+            self.diff.generate_rollback()
+        );
+        Ok(crate::migrate::create_migration(
+            &self.migrations_dir,
+            &self.name,
+            &self.diff.sql(),
+            &down_sql,
+            self.provider.as_str(),
+            None,
+        )?)
+    }
+}
+
+// ==================== Reset ====================
+
+/// Builder for a reset, from [`Engine::reset`]
+pub struct Reset<'a> {
+    engine: &'a Engine,
+    override_policy: bool,
+}
+
+/// What a reset drops and re-applies, before anything runs
+#[derive(Debug)]
+pub struct ResetPlan {
+    pub db_schema: DbSchema,
+    /// SQL dropping every table and enum type, and the migrations table
+    pub sql: String,
+    /// Every migration on disk, re-applied in order
+    pub migrations: Vec<Migration>,
+}
+
+/// Outcome of [`Reset::run`]
+#[derive(Debug)]
+pub struct ResetReport {
+    pub applied: Vec<Migration>,
+    pub elapsed: Duration,
+}
+
+impl Reset<'_> {
+    /// Reset even when the drops break the environment's policies
+    pub fn override_policy(mut self, override_policy: bool) -> Self {
+        self.override_policy = override_policy;
+        self
+    }
+
+    /// Introspect what the reset drops, unless the environment forbids a
+    /// reset or its policies refuse the drops
+    pub fn plan(&self, client: &mut dyn DbBackend) -> EngineResult<ResetPlan> {
+        let engine = self.engine;
+        if engine.environment().is_some_and(|e| e.forbid_force_reset) {
+            return Err(EngineError::ResetForbidden(
+                engine.env.clone().unwrap_or_default(),
+            ));
+        }
+        let migrations = engine.load_migrations()?;
+        let db_schema = engine.introspect(client)?;
+        let sql = crate::migrate::reset_sql(
+            engine.db_config()?.provider.dialect(),
+            &db_schema,
+            &engine.migrations_table(),
+        );
+        if !self.override_policy {
+            engine.enforce_policies(&[Change::unreviewed("migrate reset", &sql)])?;
+        }
+        Ok(ResetPlan {
+            db_schema,
+            sql,
+            migrations,
+        })
+    }
+
+    /// Drop what `plan` lists in one transaction, then re-apply every
+    /// migration. MySQL commits each DROP TABLE, so a failure there keeps the
+    /// tables dropped before it.
+    pub fn run(
+        &self,
+        client: &mut dyn DbBackend,
+        plan: ResetPlan,
+        progress: &mut dyn Progress,
+    ) -> EngineResult<ResetReport> {
+        run_script("Reset", client, &plan.sql, progress)?;
+        let table = self.engine.migrations_table();
+        let mut applied = plan.migrations;
+        track_migrations(client, &table, &mut applied)?;
+        let elapsed = self
+            .engine
+            .apply(client, &applied.iter().collect::<Vec<_>>(), progress)?;
+        Ok(ResetReport { applied, elapsed })
+    }
+}
+
+// ==================== Push ====================
+
+/// Builder for a db push, from [`Engine::push`]
+pub struct Push<'a> {
+    engine: &'a Engine,
+    force_reset: bool,
+    override_policy: bool,
+}
+
+/// What a push would do, before anything runs
+#[derive(Debug)]
+pub struct PushPlan {
+    pub schema: Schema,
+    /// The database as introspected, before any reset
+    pub db_schema: DbSchema,
+    /// Tables of schema.json dropped first, sorted; empty without a force reset
+    pub reset_tables: Vec<String>,
+    /// SQL dropping `reset_tables`, run as previewed
+    pub reset_sql: String,
+    /// Changes from the database, once reset, to schema.json
+    pub diff: SchemaDiff,
+    /// The environment, when it requires confirmation of every change
+    confirm_env: Option<String>,
+}
+
+/// A question to answer yes to before [`Push::run`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushConfirmation {
+    /// The force reset drops these tables with all their data
+    Reset(Vec<String>),
+    /// The changes drop tables or columns; one warning each
+    DataLoss(Vec<String>),
+    /// The environment requires confirmation of every change
+    Environment(String),
+}
+
+impl Push<'_> {
+    /// Drop the tables of schema.json first, with all their data
+    pub fn force_reset(mut self, force_reset: bool) -> Self {
+        self.force_reset = force_reset;
+        self
+    }
+
+    /// Push even when the SQL breaks the environment's policies
+    pub fn override_policy(mut self, override_policy: bool) -> Self {
+        self.override_policy = override_policy;
+        self
+    }
+
+    /// Compare schema.json with the database, as it will be after the reset
+    pub fn plan(&self, client: &mut dyn DbBackend) -> EngineResult<PushPlan> {
+        let engine = self.engine;
+        let environment = engine.environment();
+        if self.force_reset && environment.is_some_and(|e| e.forbid_force_reset) {
+            return Err(EngineError::ResetForbidden(
+                engine.env.clone().unwrap_or_default(),
+            ));
+        }
+        let schema = engine.load_schema()?;
+        let db_schema = engine.introspect(client)?;
+
+        let mut reset_tables = Vec::new();
+        if self.force_reset {
+            reset_tables = schema.tables.keys().cloned().collect();
+            reset_tables.sort();
+        }
+        let dialect = engine.db_config()?.provider.dialect();
+        let reset_sql = crate::migrate::drop_tables_sql(dialect, &reset_tables);
+        let mut after_reset = db_schema.clone();
+        after_reset
+            .tables
+            .retain(|name, _| !reset_tables.contains(name));
+        after_reset
+            .grants
+            .retain(|grant| !reset_tables.contains(&grant.table));
+        let diff = crate::db::compare_schemas(&schema, &after_reset);
+
+        let plan = PushPlan {
+            schema,
+            db_schema,
+            reset_tables,
+            reset_sql,
+            diff,
+            confirm_env: environment
+                .filter(|e| e.require_confirmation)
+                .and(engine.env.clone()),
+        };
+        if !self.override_policy {
+            let sql = plan.diff.sql();
+            let violations = environment
+                .map(|e| {
+                    let reset = Change::unreviewed("--force-reset", &plan.reset_sql);
+                    let mut violations = crate::policy::check_plan(&e.policies, &reset);
+                    violations.extend(crate::policy::check_plan(
+                        &e.policies,
+                        &Change::unreviewed("db push", &sql),
+                    ));
+                    violations
+                })
+                .unwrap_or_default();
+            engine.refuse(violations)?;
+        }
+        Ok(plan)
+    }
+
+    /// Run the reset and the changes of `plan` in one transaction, stopping
+    /// at the first failure; returns the time taken
+    pub fn run(
+        &self,
+        client: &mut dyn DbBackend,
+        plan: &PushPlan,
+        progress: &mut dyn Progress,
+    ) -> EngineResult<Duration> {
+        let sql = plan.sql();
+        if !self.override_policy {
+            self.engine
+                .enforce_policies(&[Change::unreviewed("db push", &sql)])?;
+        }
+        let _span = tracing::info_span!("push").entered();
+        let started = Instant::now();
+        run_script("Push", client, &sql, progress)?;
+        Ok(started.elapsed())
+    }
+}
+
+impl PushPlan {
+    /// Whether the push would change the database
+    pub fn has_changes(&self) -> bool {
+        !self.reset_tables.is_empty() || self.diff.has_changes()
+    }
+
+    /// Everything the push runs: the reset, then the changes
+    pub fn sql(&self) -> String {
+        format!("{}{}", self.reset_sql, self.diff.sql())
+    }
+
+    /// What to confirm before running the plan, in the order to ask
+    pub fn confirmations(&self) -> Vec<PushConfirmation> {
+        let mut confirmations = Vec::new();
+        if !self.reset_tables.is_empty() {
+            confirmations.push(PushConfirmation::Reset(self.reset_tables.clone()));
+        }
+        if !self.diff.data_loss_warning.is_empty() {
+            confirmations.push(PushConfirmation::DataLoss(
+                self.diff.data_loss_warning.clone(),
+            ));
+        }
+        if let Some(env) = &self.confirm_env {
+            confirmations.push(PushConfirmation::Environment(env.clone()));
+        }
+        confirmations
+    }
+}
+
+// ==================== Generate ====================
+
+/// Builder for a generate run, from [`Engine::generate`]
+pub struct Generate<'a> {
+    engine: &'a Engine,
+    schema: Option<Schema>,
+}
+
+/// A generated file
+#[derive(Debug, Clone)]
+pub struct GeneratedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Result of one configured generator
+#[derive(Debug)]
+pub struct GeneratorOutput {
+    pub provider: String,
+    /// The generated file, then its runtime module if one is written; or
+    /// why this generator failed
+    pub result: Result<Vec<GeneratedFile>, String>,
+}
+
+impl Generate<'_> {
+    /// Use this schema instead of reading schema.json
+    pub fn schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Run every generator in memory
+    pub fn render(&self) -> EngineResult<Vec<GeneratorOutput>> {
+        let config = self
+            .engine
+            .config
+            .as_ref()
+            .ok_or(EngineError::NoGenerators)?;
+        let schema = match &self.schema {
+            Some(schema) => Some(schema.clone()),
+            None if self.engine.schema_path.exists() => Some(self.engine.load_schema()?),
+            None => None,
+        };
+        render_generators(config, schema.as_ref())
+    }
+
+    /// Run every generator and write the files of those that succeed
+    pub fn run(self) -> EngineResult<Vec<GeneratorOutput>> {
+        let outputs = self.render()?;
+        let files: Vec<GeneratedFile> = outputs
+            .iter()
+            .filter_map(|o| o.result.as_ref().ok())
+            .flatten()
+            .cloned()
+            .collect();
+        write_generated(&files)?;
+        Ok(outputs)
+    }
+}
+
+/// Run every generator in stratus.json in memory.
+///
+/// Generators without an `input` read the `queries.include` files. Unreadable
+/// inputs abort; other problems fail only their generator.
+pub fn render_generators(
+    config: &ConfigManager,
+    schema: Option<&Schema>,
+) -> EngineResult<Vec<GeneratorOutput>> {
+    let generators = config.generators();
+    if generators.is_empty() {
+        return Err(EngineError::NoGenerators);
+    }
+
+    let query_files = config.query_files()?;
+    let mut queries = QueryFile { queries: vec![] };
+    for file in &query_files {
+        queries.queries.extend(read_queries(file)?.queries);
+    }
+
+    generators
+        .into_iter()
+        .map(|generator| {
+            Ok(GeneratorOutput {
+                provider: generator.provider.as_deref().unwrap_or("ts").to_string(),
+                result: render_generator(config, generator, schema, &query_files, &queries)?,
+            })
+        })
+        .collect()
+}
+
+fn render_generator(
+    config: &ConfigManager,
+    generator: &GeneratorConfig,
+    schema: Option<&Schema>,
+    query_files: &[PathBuf],
+    queries: &QueryFile,
+) -> EngineResult<Result<Vec<GeneratedFile>, String>> {
+    let provider = generator.provider.as_deref().unwrap_or("ts");
+
+    let ast = match &generator.input {
+        Some(input) => Some(read_queries(&config.resolve_path(input))?),
+        None if !query_files.is_empty() => Some(queries.clone()),
+        None => None,
+    };
+
+    let Some(output) = &generator.output else {
+        return Ok(Err("missing 'output' in generator config".to_string()));
+    };
+
+    let path = config.resolve_path(output);
+    Ok(
+        crate::codegen::GeneratorOptions::from_map(provider, &generator.options).and_then(
+            |options| {
+                let contents = crate::codegen::generate(provider, ast.as_ref(), schema, &options)?;
+                let runtime = crate::codegen::runtime::emitted(provider, &options).map(|runtime| {
+                    GeneratedFile {
+                        path: path.with_file_name(runtime.file_name),
+                        contents: runtime.source.to_string(),
+                    }
+                });
+                Ok(std::iter::once(GeneratedFile { path, contents })
+                    .chain(runtime)
+                    .collect())
+            },
+        ),
+    )
+}
+
+// ==================== Shared steps ====================
+
+fn read(path: &Path) -> EngineResult<String> {
+    std::fs::read_to_string(path).map_err(|source| EngineError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Read and parse a schema.json file
+pub fn read_schema(path: &Path) -> EngineResult<Schema> {
+    serde_json::from_str(&read(path)?).map_err(|e| EngineError::InvalidSchema {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+/// Read and parse a TypeSQL file
+pub fn read_queries(path: &Path) -> EngineResult<QueryFile> {
+    crate::parser::parse(&read(path)?).map_err(|source| EngineError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Write a file, creating parent directories.
+///
+/// The contents go to a temporary file next to it that is then renamed over
+/// it, so readers see either the old file or the new one, never a mix.
+pub fn write_output(path: &Path, contents: &str) -> EngineResult<()> {
+    let cant_create = |source| EngineError::Write {
+        path: path.to_path_buf(),
+        source,
+    };
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        std::fs::create_dir_all(parent).map_err(cant_create)?;
+    }
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let temp = parent.unwrap_or(Path::new("")).join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let written = std::fs::write(&temp, contents).and_then(|_| std::fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written.map_err(cant_create)
+}
+
+/// Name of the lock file [`OutputLock`] keeps in an output directory while
+/// it writes there. It is removed on release, so only a run that was killed
+/// leaves one behind; it is safe to delete, and belongs in `.gitignore` next
+/// to `.stratus/` wherever generated code is committed
+pub const OUTPUT_LOCK_FILE: &str = ".stratus.lock";
+
+/// Exclusive hold on an output directory, so concurrent runs (`stratus dev`
+/// and a manual `generate`) do not interleave their files; released, and the
+/// lock file removed, on drop, or released by the operating system when the
+/// process exits
+#[derive(Debug)]
+pub struct OutputLock {
+    file: std::fs::File,
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Lock `dir`, creating it; fails at once when another process holds it
+    pub fn acquire(dir: &Path) -> EngineResult<Self> {
+        use std::io::{Read, Seek, Write};
+
+        let path = dir.join(OUTPUT_LOCK_FILE);
+        let cant_create = |source| EngineError::Write {
+            path: path.clone(),
+            source,
+        };
+        std::fs::create_dir_all(dir).map_err(cant_create)?;
+        let mut file = loop {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(cant_create)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    let mut holder = String::new();
+                    let _ = file.read_to_string(&mut holder);
+                    return Err(EngineError::OutputLocked {
+                        dir: dir.to_path_buf(),
+                        holder: holder.trim().parse().ok(),
+                    });
+                }
+                Err(std::fs::TryLockError::Error(e)) => return Err(cant_create(e)),
+            }
+            // The previous holder may have removed the file between our open
+            // and lock, leaving a lock on a file nobody else will see
+            if is_current(&file, &path) {
+                break file;
+            }
+        };
+        // The holder's pid, for the error others get
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .map_err(cant_create)?;
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so a run that opened it meanwhile
+        // notices the file is gone once it gets the lock
+        let _ = std::fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether `file` is still the one at `path`
+#[cfg(unix)]
+fn is_current(file: &std::fs::File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Whether `file` is still the one at `path`; a file open elsewhere cannot be
+/// replaced here, so it is enough that one exists
+#[cfg(not(unix))]
+fn is_current(_file: &std::fs::File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Write generated files, holding the lock of each directory they go to
+/// until all are written
+pub fn write_generated(files: &[GeneratedFile]) -> EngineResult<()> {
+    let mut dirs: Vec<&Path> = files
+        .iter()
+        .map(|file| file.path.parent().unwrap_or(Path::new("")))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    let _locks = dirs
+        .into_iter()
+        .map(|dir| {
+            OutputLock::acquire(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            })
+        })
+        .collect::<EngineResult<Vec<_>>>()?;
+    for file in files {
+        write_output(&file.path, &file.contents)?;
+    }
+    Ok(())
+}
+
+/// Database URL: `url`, then the datasource, then DATABASE_URL.
+///
+/// With a stratus.json present a datasource is required, so callers never
+/// silently fall back to whatever DATABASE_URL happens to be set.
+pub fn resolve_database_url(
+    config: Option<&ConfigManager>,
+    datasource: Option<&str>,
+    url: Option<&str>,
+) -> EngineResult<String> {
+    if let (Some(cfg), Some(ds)) = (config, datasource) {
+        if cfg.get_datasource(ds).is_none() {
+            return Err(ConfigError::DatasourceNotFound(ds.to_string()).into());
+        }
+    }
+    if let Some(url) = url {
+        return Ok(url.to_string());
+    }
+
+    match (config, datasource) {
+        (Some(cfg), Some(ds)) => Ok(cfg.datasource_url(ds)?),
+        (None, Some(_)) => Err(EngineError::NoConfig),
+        (Some(_), None) => Err(EngineError::DatasourceRequired),
+        (None, None) => std::env::var("DATABASE_URL").map_err(|_| EngineError::MissingUrl),
+    }
+}
+
+/// Connection settings for a URL, with the datasource's provider, TLS and session options;
+/// `no_cache` bypasses the datasource's introspection cache
+pub fn connection_config(
+    config: Option<&ConfigManager>,
+    datasource: Option<&str>,
+    env: Option<&str>,
+    url: &str,
+    no_cache: bool,
+) -> DbConfig {
+    let configured = config
+        .zip(datasource)
+        .and_then(|(cfg, ds)| cfg.get_datasource(ds));
+    DbConfig {
+        connection_string: url.to_string(),
+        max_connections: 5,
+        provider: configured
+            .and_then(|ds| ds.provider)
+            .or_else(|| DatabaseProvider::from_url(url))
+            .unwrap_or_default(),
+        ssl: config
+            .zip(datasource)
+            .map(|(cfg, ds)| cfg.ssl_config(ds, env))
+            .unwrap_or_default(),
+        options: configured
+            .and_then(|ds| ds.connection.clone())
+            .unwrap_or_default(),
+        introspection_cache: config
+            .filter(|_| configured.is_some_and(|ds| ds.introspection_cache))
+            .filter(|_| !no_cache)
+            .map(|cfg| cfg.base_dir().join(".stratus").join("cache")),
+    }
+}
+
+/// Introspect the database, leaving out the migrations table
+pub fn introspect(client: &mut dyn DbBackend, table: &MigrationsTable) -> EngineResult<DbSchema> {
+    let mut db_schema = client.get_schema().map_err(EngineError::Introspect)?;
+    table.exclude_from(&mut db_schema);
+    Ok(db_schema)
+}
+
+/// Create the migrations table and mark the migrations it records as applied
+pub fn track_migrations(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations: &mut [Migration],
+) -> EngineResult<()> {
+    crate::migrate::ensure_migrations_table(client, table)?;
+    Ok(crate::migrate::load_applied(client, table, migrations)?)
+}
+
+/// Write a `baseline` migration that creates `db_schema` from nothing and
+/// record it as applied, so a database that predates Stratus starts with a
+/// migration history matching what it already has. Refused once any
+/// migration is recorded.
+pub fn baseline(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations_dir: &Path,
+    db_schema: &DbSchema,
+) -> EngineResult<Migration> {
+    crate::migrate::ensure_migrations_table(client, table)?;
+    let recorded = crate::migrate::applied_ids(client, table)?.len();
+    if recorded > 0 {
+        return Err(EngineError::HasMigrations(recorded));
+    }
+    let empty = DbSchema {
+        dialect: db_schema.dialect.clone(),
+        ..DbSchema::default()
+    };
+    let diff = crate::db::compare_schemas(&db_schema.to_json_schema(), &empty);
+    let migration = crate::migrate::create_migration(
+        &migrations_dir.to_path_buf(),
+        "baseline",
+        &diff.sql(),
+        &diff.generate_rollback(),
+        &db_schema.dialect,
+        Some(diff.checksum()),
+    )?;
+    crate::migrate::resolve_applied(client, table, &migration)?;
+    Ok(migration)
+}
+
+/// Execute a script statement by statement, timing each one.
+///
+/// On failure returns the 1-based statement number with the error.
+pub fn execute_script(
+    client: &mut dyn DbBackend,
+    sql: &str,
+    progress: &mut dyn Progress,
+) -> Result<(), (usize, DbError)> {
+    for (n, statement) in crate::migrate::split_statements(sql)
+        .into_iter()
+        .enumerate()
+    {
+        let summary: String = statement
+            .lines()
+            .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with("--"))
+            .unwrap_or(statement)
+            .chars()
+            .take(60)
+            .collect();
+        progress.statement(&summary);
+
+        let started = Instant::now();
+        client.execute(statement).map_err(|e| (n + 1, e))?;
+        tracing::info!(
+            statement = n + 1,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "{}",
+            summary
+        );
+        progress.statement_done();
+    }
+    Ok(())
+}
+
+/// Run `sql` in one transaction, rolling it all back at the first failure
+fn run_script(
+    step: &'static str,
+    client: &mut dyn DbBackend,
+    sql: &str,
+    progress: &mut dyn Progress,
+) -> EngineResult<()> {
+    progress.start(crate::migrate::split_statements(sql).len());
+    client.begin()?;
+    if let Err((statement, source)) = execute_script(client, sql, progress) {
+        let _ = client.rollback();
+        return Err(EngineError::StatementFailed {
+            step,
+            statement,
+            source,
+        });
+    }
+    client.commit()?;
+    Ok(())
+}
+
+/// Apply migrations one transaction each, recording them in the migrations table.
+///
+/// Holds the migrations lock throughout and skips migrations another process
+/// applied in the meantime. Stops at the first failure, which is rolled back;
+/// returns the total time taken.
+pub fn apply_migrations(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations: &[&Migration],
+    progress: &mut dyn Progress,
+) -> EngineResult<Duration> {
+    crate::migrate::lock(client, table)?;
+    let result = apply_locked(client, table, migrations, progress);
+    let _ = crate::migrate::unlock(client, table);
+    result
+}
+
+fn apply_locked(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations: &[&Migration],
+    progress: &mut dyn Progress,
+) -> EngineResult<Duration> {
+    crate::migrate::ensure_migrations_table(client, table)?;
+    let applied = crate::migrate::applied_ids(client, table)?;
+    let migrations: Vec<&Migration> = migrations
+        .iter()
+        .copied()
+        .filter(|m| {
+            let done = applied.contains(&m.meta.id);
+            if done {
+                tracing::info!(id = %m.meta.id, "already applied, skipping");
+            }
+            !done
+        })
+        .collect();
+
+    let mut statements = 0;
+    for m in &migrations {
+        statements += crate::migrate::split_statements(m.up_sql()?).len();
+    }
+    let span = tracing::info_span!(
+        "apply",
+        migrations = migrations.len(),
+        statements,
+        applied = tracing::field::Empty,
+        elapsed_ms = tracing::field::Empty
+    );
+    let _guard = span.enter();
+    let started = Instant::now();
+    progress.start(statements);
+
+    for (applied, m) in migrations.iter().enumerate() {
+        let _span =
+            tracing::info_span!("migration", id = %m.meta.id, name = %m.meta.name).entered();
+        let migration_started = Instant::now();
+        client.begin()?;
+        let result = execute_script(client, m.up_sql()?, progress)
+            .map_err(|(n, e)| format!("statement {}: {}", n, e))
+            .and_then(|_| {
+                crate::migrate::record_applied(client, table, m, migration_started.elapsed())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(_) => {
+                client.commit()?;
+                let elapsed = migration_started.elapsed();
+                tracing::info!(elapsed_ms = elapsed.as_millis() as u64, "applied");
+                progress.migration_applied(m, elapsed);
+            }
+            Err(message) => {
+                let _ = client.rollback();
+                span.record("applied", applied);
+                tracing::error!(error = %message, "rolled back");
+                progress.migration_failed(m);
+                return Err(EngineError::MigrationFailed {
+                    id: m.meta.id.clone(),
+                    name: m.meta.name.clone(),
+                    message,
+                    applied,
+                });
+            }
+        }
+    }
+
+    let elapsed = started.elapsed();
+    span.record("applied", migrations.len());
+    span.record("elapsed_ms", elapsed.as_millis() as u64);
+    Ok(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_resolves_defaults_without_config() {
+        let engine = Engine::builder()
+            .without_config()
+            .url("postgresql://localhost/app")
+            .build()
+            .unwrap();
+        assert_eq!(engine.schema_path(), Path::new("schema.json"));
+        assert_eq!(engine.migrations_dir(), Path::new("migrations"));
+        assert_eq!(engine.database_url().unwrap(), "postgresql://localhost/app");
+        assert_eq!(
+            engine.db_config().unwrap().provider,
+            DatabaseProvider::Postgresql
+        );
+    }
+
+    #[test]
+    fn test_datasource_without_config_is_an_error() {
+        let engine = Engine::builder()
+            .without_config()
+            .datasource("main")
+            .build()
+            .unwrap();
+        assert!(matches!(engine.database_url(), Err(EngineError::NoConfig)));
+    }
+
+    #[test]
+    fn test_introspection_cache_is_opt_in_per_datasource() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let path = root.join(crate::config::CONFIG_FILE_NAME);
+        std::fs::write(
+            &path,
+            r#"{"version": 1, "datasources": {
+                "cached": {"url": "postgresql://localhost/a", "introspectionCache": true},
+                "plain": {"url": "postgresql://localhost/b"}
+            }}"#,
+        )
+        .unwrap();
+        let cache = |datasource: &str, no_cache: bool| {
+            Engine::builder()
+                .config_path(&path)
+                .datasource(datasource)
+                .no_cache(no_cache)
+                .build()
+                .unwrap()
+                .db_config()
+                .unwrap()
+                .introspection_cache
+        };
+        assert_eq!(
+            cache("cached", false),
+            Some(root.join(".stratus").join("cache"))
+        );
+        assert_eq!(cache("cached", true), None);
+        assert_eq!(cache("plain", false), None);
+    }
+
+    /// An engine over a fresh temp directory holding these migrations
+    fn project(migrations: &[(&str, &str)]) -> (tempfile::TempDir, Engine) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (id, up_sql) in migrations {
+            let dir = root.join("migrations").join(format!("{}_m{}", id, id));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("meta.json"),
+                format!(r#"{{"id": "{0}", "name": "m{0}", "created_at": "", "dialect": "postgresql", "checksum": null}}"#, id),
+            )
+            .unwrap();
+            std::fs::write(dir.join("up.sql"), up_sql).unwrap();
+        }
+        let engine = Engine::builder()
+            .without_config()
+            .url("postgresql://localhost/unused")
+            .schema(root.join("schema.json"))
+            .migrations_dir(root.join("migrations"))
+            .build()
+            .unwrap();
+        (dir, engine)
+    }
+
+    #[test]
+    fn test_deploy_stops_at_a_failure_and_resumes_in_order() {
+        use crate::db::MemoryBackend;
+        let (dir, engine) = project(&[
+            ("3", "CREATE TABLE c (id int);"),
+            ("1", "CREATE TABLE a (id int);"),
+            ("2", "CREATE TABLE b (id int);\nALTER TABLE b BROKEN;"),
+        ]);
+        let root = dir.path();
+        let mut db = MemoryBackend::default();
+        db.fail_on = Some("BROKEN".to_string());
+
+        let err = engine.deploy().run(&mut db, &mut ()).unwrap_err();
+        assert!(
+            matches!(err, EngineError::MigrationFailed { ref id, applied: 1, .. } if id == "2")
+        );
+        assert_eq!(db.executed, vec!["CREATE TABLE a (id int)"]);
+        assert_eq!(db.migrations.as_ref().unwrap().len(), 1);
+        assert!(db.migrations.as_ref().unwrap()[0].duration_ms.is_some());
+        assert!(db.locks == 0 && !db.in_transaction());
+
+        db.fail_on = None;
+        let report = engine.deploy().run(&mut db, &mut ()).unwrap();
+        let ids: Vec<&str> = report.applied.iter().map(|m| m.meta.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "3"]);
+        assert_eq!(db.executed.len(), 4);
+        assert_eq!(db.executed[3], "CREATE TABLE c (id int)");
+        assert!(engine.deploy().pending(&mut db).unwrap().is_empty());
+        let history = crate::migrate::check_history(
+            &mut db,
+            &engine.migrations_table(),
+            &engine.load_migrations().unwrap(),
+        );
+        assert_eq!(history.unwrap(), Vec::<String>::new());
+
+        // An applied migration edited afterwards stops the next deploy
+        std::fs::write(
+            root.join("migrations/1_m1/up.sql"),
+            "CREATE TABLE a (id bigint);",
+        )
+        .unwrap();
+        let executed = db.executed.len();
+        let err = engine.deploy().run(&mut db, &mut ()).unwrap_err();
+        assert!(
+            matches!(err, EngineError::Migrate(MigrateError::History(ref p)) if p.contains("1_m1"))
+        );
+        assert_eq!(db.executed.len(), executed);
+    }
+
+    #[test]
+    fn test_sync_plan_flags_conflicts_duplicates_and_data_loss() {
+        use crate::db::MemoryBackend;
+        let (dir, engine) = project(&[("1", "CREATE TABLE posts (id int);")]);
+        let root = dir.path();
+        std::fs::write(
+            root.join("schema.json"),
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap();
+        let current: Schema = serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+                "legacy": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap();
+        let mut db = MemoryBackend::new(DbSchema::from_json_schema(&current));
+
+        let plan = engine.sync().name("add-posts").plan(&mut db).unwrap();
+        assert_eq!(plan.diff.create_tables, vec!["posts"]);
+        assert_eq!(plan.diff.drop_tables, vec!["legacy"]);
+        assert_eq!(
+            plan.diff.data_loss_warning,
+            vec!["Table 'legacy' will be dropped with all data"]
+        );
+        assert_eq!(plan.conflicts, vec!["m1"]);
+        assert_eq!(plan.duplicate, None);
+
+        // The same diff again is a duplicate of the migration just written
+        plan.write_migration().unwrap();
+        let again = engine.sync().plan(&mut db).unwrap();
+        assert_eq!(again.duplicate.as_deref(), Some("add-posts"));
+        assert!(engine
+            .sync()
+            .force(true)
+            .plan(&mut db)
+            .unwrap()
+            .duplicate
+            .is_none());
+        assert!(db.executed.is_empty());
+    }
+
+    #[test]
+    fn test_edited_plan_is_written_and_recorded() {
+        use crate::db::MemoryBackend;
+        let (dir, engine) = project(&[]);
+        let root = dir.path();
+        std::fs::write(
+            root.join("schema.json"),
+            r#"{"tables": {"posts": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#,
+        )
+        .unwrap();
+        let current: Schema = serde_json::from_str(
+            r#"{"tables": {"legacy": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#,
+        )
+        .unwrap();
+        let mut db = MemoryBackend::new(DbSchema::from_json_schema(&current));
+
+        let mut plan = engine.sync().name("add-posts").plan(&mut db).unwrap();
+        let generated: Vec<String> = plan.generated().iter().map(|op| op.to_string()).collect();
+        assert_eq!(generated, vec!["drop table legacy", "create table posts"]);
+        let checksum = plan.checksum.clone();
+
+        plan.edit(vec![
+            PlanDecision {
+                operation: generated[1].clone(),
+                index: 1,
+                excluded: false,
+                note: None,
+            },
+            PlanDecision {
+                operation: generated[0].clone(),
+                index: 0,
+                excluded: true,
+                note: Some("still read by reports".to_string()),
+            },
+        ]);
+        assert!(plan.diff.drop_tables.is_empty());
+        assert!(plan.diff.data_loss_warning.is_empty());
+        plan.write_migration().unwrap();
+
+        let written = &engine.load_migrations().unwrap()[0];
+        assert!(!written.up_sql().unwrap().contains("legacy"));
+        assert_eq!(written.meta.plan, plan.decisions());
+        assert_eq!(written.meta.checksum.as_ref(), Some(&checksum));
+    }
+
+    #[test]
+    fn test_reset_drops_everything_then_reapplies_every_migration() {
+        use crate::db::MemoryBackend;
+        let (_dir, engine) = project(&[
+            ("1", "CREATE TABLE a (id int);"),
+            ("2", "CREATE TABLE b (id int);"),
+        ]);
+        let current: Schema = serde_json::from_str(
+            r#"{"tables": {"a": {"columns": {"id": {"name": "id", "type": "int"}}}}}"#,
+        )
+        .unwrap();
+        let mut db = MemoryBackend::new(DbSchema::from_json_schema(&current));
+
+        let plan = engine.reset().plan(&mut db).unwrap();
+        assert_eq!(plan.migrations.len(), 2);
+        let report = engine.reset().run(&mut db, plan, &mut ()).unwrap();
+        let ids: Vec<&str> = report.applied.iter().map(|m| m.meta.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(
+            db.executed,
+            vec![
+                "DROP TABLE IF EXISTS \"a\" CASCADE",
+                "DROP TABLE IF EXISTS \"_stratus_migrations\" CASCADE",
+                "CREATE TABLE a (id int)",
+                "CREATE TABLE b (id int)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_push_resets_then_changes_in_one_transaction() {
+        use crate::db::MemoryBackend;
+        let (dir, engine) = project(&[]);
+        let root = dir.path();
+        std::fs::write(
+            root.join("schema.json"),
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap();
+        let current: Schema = serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+                "sessions": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+            }}"#,
+        )
+        .unwrap();
+        let mut db = MemoryBackend::new(DbSchema::from_json_schema(&current));
+
+        // Without a reset only the data loss of the diff is asked about
+        let plan = engine.push().plan(&mut db).unwrap();
+        assert_eq!(plan.diff.create_tables, vec!["posts"]);
+        assert_eq!(
+            plan.confirmations(),
+            vec![PushConfirmation::DataLoss(vec![
+                "Table 'sessions' will be dropped with all data".to_string()
+            ])]
+        );
+
+        // The reset is confirmed first, and users is recreated after it
+        let push = engine.push().force_reset(true);
+        let plan = push.plan(&mut db).unwrap();
+        assert_eq!(plan.reset_tables, vec!["posts", "users"]);
+        assert_eq!(plan.diff.create_tables, vec!["users", "posts"]);
+        assert!(matches!(
+            plan.confirmations().as_slice(),
+            [PushConfirmation::Reset(tables), PushConfirmation::DataLoss(_)]
+                if tables == &plan.reset_tables
+        ));
+        assert!(db.executed.is_empty());
+
+        // A failing statement rolls back the reset with the rest
+        db.fail_on = Some("CREATE TABLE users".to_string());
+        let err = push.run(&mut db, &plan, &mut ()).unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::StatementFailed { step: "Push", statement, .. } if statement > 2
+        ));
+        assert!(db.executed.is_empty() && !db.in_transaction());
+
+        db.fail_on = None;
+        push.run(&mut db, &plan, &mut ()).unwrap();
+        assert_eq!(
+            db.executed[..2],
+            [
+                "DROP TABLE IF EXISTS \"posts\" CASCADE",
+                "DROP TABLE IF EXISTS \"users\" CASCADE"
+            ]
+        );
+        let sessions = db.executed.iter().position(|s| s.contains("sessions"));
+        let users = db
+            .executed
+            .iter()
+            .position(|s| s.contains("CREATE TABLE users"));
+        assert!(sessions.is_some() && users > Some(1));
+    }
+
+    #[test]
+    fn test_push_and_reset_are_refused_by_the_environment() {
+        use crate::db::MemoryBackend;
+        let (dir, _) = project(&[]);
+        let root = dir.path();
+        std::fs::write(root.join("schema.json"), r#"{"tables": {}}"#).unwrap();
+        std::fs::write(
+            root.join("stratus.json"),
+            r#"{
+              "version": 1,
+              "datasources": { "main": { "url": "postgresql://localhost/main" } },
+              "environments": {
+                "staging": { "datasource": "main", "policies": { "denyDropTable": true } },
+                "production": {
+                  "datasource": "main", "forbidForceReset": true, "requireConfirmation": true
+                }
+              }
+            }"#,
+        )
+        .unwrap();
+        let build = |env: &str| {
+            Engine::builder()
+                .config_path(root.join("stratus.json"))
+                .schema(root.join("schema.json"))
+                .env(env)
+                .build()
+                .unwrap()
+        };
+        let current: Schema = serde_json::from_str(
+            r#"{"tables": {"sessions": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#,
+        )
+        .unwrap();
+        let mut db = MemoryBackend::new(DbSchema::from_json_schema(&current));
+
+        let staging = build("staging");
+        match staging.push().plan(&mut db) {
+            Err(EngineError::Policy { env, violations }) => {
+                assert_eq!(env, "staging");
+                assert_eq!(
+                    violations,
+                    vec!["db push drops table sessions (denyDropTable)"]
+                );
+            }
+            other => panic!("expected Policy, got {:?}", other),
+        }
+        assert!(staging.push().override_policy(true).plan(&mut db).is_ok());
+        match staging.reset().plan(&mut db) {
+            Err(EngineError::Policy { violations, .. }) => assert_eq!(
+                violations,
+                vec!["migrate reset drops table _stratus_migrations, sessions (denyDropTable)"]
+            ),
+            other => panic!("expected Policy, got {:?}", other),
+        }
+        assert!(staging.reset().override_policy(true).plan(&mut db).is_ok());
+
+        // The environment's confirmation is asked for last
+        let production = build("production");
+        let plan = production.push().plan(&mut db).unwrap();
+        assert!(matches!(
+            plan.confirmations().as_slice(),
+            [PushConfirmation::DataLoss(_), PushConfirmation::Environment(env)]
+                if env == "production"
+        ));
+        assert!(matches!(
+            production.push().force_reset(true).plan(&mut db),
+            Err(EngineError::ResetForbidden(env)) if env == "production"
+        ));
+        assert!(matches!(
+            production.reset().plan(&mut db),
+            Err(EngineError::ResetForbidden(env)) if env == "production"
+        ));
+        assert!(db.executed.is_empty());
+    }
+
+    #[test]
+    fn test_baseline_matches_the_existing_database() {
+        use crate::db::MemoryBackend;
+        let (dir, engine) = project(&[]);
+        let root = dir.path();
+        let existing: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "primaryKey": true},
+                "email": {"name": "email", "type": "text"}
+            }}}}"#,
+        )
+        .unwrap();
+        let mut db_schema = DbSchema::from_json_schema(&existing);
+        db_schema.dialect = "postgresql".to_string();
+        let mut db = MemoryBackend::new(db_schema.clone());
+
+        let migration = baseline(
+            &mut db,
+            &engine.migrations_table(),
+            engine.migrations_dir(),
+            &db_schema,
+        )
+        .unwrap();
+        assert_eq!(migration.meta.name, "baseline");
+        assert!(migration.up_sql().unwrap().contains("CREATE TABLE users"));
+        assert!(db.executed.is_empty());
+
+        // Nothing is pending and schema.json written from the database has no changes
+        std::fs::write(
+            root.join("schema.json"),
+            serde_json::to_string(&db_schema.to_json_schema()).unwrap(),
+        )
+        .unwrap();
+        assert!(engine.deploy().pending(&mut db).unwrap().is_empty());
+        assert!(!engine.sync().plan(&mut db).unwrap().diff.has_changes());
+
+        // A second baseline would duplicate the history
+        assert!(matches!(
+            baseline(
+                &mut db,
+                &engine.migrations_table(),
+                engine.migrations_dir(),
+                &db_schema
+            ),
+            Err(EngineError::HasMigrations(1))
+        ));
+    }
+
+    #[test]
+    fn test_generated_files_are_written_under_the_output_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("generated");
+        let files = vec![GeneratedFile {
+            path: dir.join("client.ts"),
+            contents: "export {};\n".to_string(),
+        }];
+
+        let held = OutputLock::acquire(&dir).unwrap();
+        match write_generated(&files) {
+            Err(EngineError::OutputLocked { holder, .. }) => {
+                assert_eq!(holder, Some(std::process::id()))
+            }
+            other => panic!("expected OutputLocked, got {:?}", other),
+        }
+        assert!(!files[0].path.exists());
+        drop(held);
+
+        write_generated(&files).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&files[0].path).unwrap(),
+            "export {};\n"
+        );
+        // Nothing but the output is left behind
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["client.ts"]);
+    }
+
+    #[test]
+    fn test_multi_target_datasource_expands_to_its_urls() {
+        let (dir, _) = project(&[]);
+        let root = dir.path();
+        let config_path = root.join("stratus.json");
+        std::fs::write(
+            &config_path,
+            r#"{
+              "version": 1,
+              "datasources": {
+                "main": { "url": "postgresql://localhost/main" },
+                "tenants": { "targets": { "urls": [
+                  "postgresql://localhost/a", "postgresql://localhost/b", "postgresql://localhost/a"
+                ] } }
+              }
+            }"#,
+        )
+        .unwrap();
+        let build = |datasource: &str, url: Option<&str>| {
+            let mut builder = Engine::builder()
+                .config_path(&config_path)
+                .datasource(datasource);
+            if let Some(url) = url {
+                builder = builder.url(url);
+            }
+            builder.build().unwrap()
+        };
+
+        let targets = build("tenants", None).deploy().targets().unwrap().unwrap();
+        assert_eq!(
+            targets.urls,
+            vec!["postgresql://localhost/a", "postgresql://localhost/b"]
+        );
+        assert_eq!(targets.concurrency, 4);
+        assert!(build("main", None).deploy().targets().unwrap().is_none());
+        assert!(build("tenants", Some("postgresql://localhost/one"))
+            .deploy()
+            .targets()
+            .unwrap()
+            .is_none());
+    }
+}
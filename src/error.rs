@@ -0,0 +1,188 @@
+/**
+ * Stratus Error Module
+ *
+ * One error type for library consumers, wrapping the error of each module,
+ * with a stable kind and code to match on failure categories.
+ */
+use crate::config::ConfigError;
+#[cfg(feature = "db")]
+use crate::db::DbError;
+#[cfg(feature = "db")]
+use crate::engine::EngineError;
+#[cfg(feature = "db")]
+use crate::migrate::MigrateError;
+use crate::parser::ParseError;
+use thiserror::Error;
+
+/// Any error from the Stratus library
+#[derive(Error, Debug)]
+pub enum StratusError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Db(#[from] DbError),
+
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Migrate(#[from] MigrateError),
+
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+
+    /// A generator rejected its options or inputs
+    #[error("{0}")]
+    Codegen(String),
+
+    /// A lifecycle hook failed or could not be run
+    #[error("{0}")]
+    Hook(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = StratusError> = std::result::Result<T, E>;
+
+/// Failure category of a [`StratusError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// stratus.json is missing or invalid, or names an unknown datasource
+    Config,
+    /// The database cannot be reached
+    Connection,
+    /// A statement or introspection query failed
+    Database,
+    /// schema.json, a query file or a migration is malformed
+    InvalidInput,
+    /// A migration failed to apply, or the migration history is inconsistent
+    Migration,
+    /// A generator failed
+    Codegen,
+    /// A lifecycle hook failed
+    Hook,
+    /// An environment's policies refuse the change
+    Policy,
+    /// A file could not be read or written
+    Io,
+}
+
+impl ErrorKind {
+    /// Stable code for the category, e.g. for logs and JSON output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Connection => "connection",
+            Self::Database => "database",
+            Self::InvalidInput => "invalid-input",
+            Self::Migration => "migration",
+            Self::Codegen => "codegen",
+            Self::Hook => "hook",
+            Self::Policy => "policy",
+            Self::Io => "io",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl StratusError {
+    /// Failure category, looking through the module error wrapped
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Config(_) => ErrorKind::Config,
+            #[cfg(feature = "db")]
+            Self::Db(e) => db_kind(e),
+            Self::Parse(_) => ErrorKind::InvalidInput,
+            #[cfg(feature = "db")]
+            Self::Migrate(e) => migrate_kind(e),
+            #[cfg(feature = "db")]
+            Self::Engine(e) => match e {
+                EngineError::Config(_)
+                | EngineError::NoConfig
+                | EngineError::DatasourceRequired
+                | EngineError::MissingUrl
+                | EngineError::NoGenerators => ErrorKind::Config,
+                EngineError::Db(e)
+                | EngineError::Introspect(e)
+                | EngineError::StatementFailed { source: e, .. } => db_kind(e),
+                EngineError::Migrate(e) => migrate_kind(e),
+                EngineError::Read { .. }
+                | EngineError::Write { .. }
+                | EngineError::OutputLocked { .. } => ErrorKind::Io,
+                EngineError::InvalidSchema { .. } | EngineError::Parse { .. } => {
+                    ErrorKind::InvalidInput
+                }
+                EngineError::MigrationFailed { .. } | EngineError::HasMigrations(_) => {
+                    ErrorKind::Migration
+                }
+                EngineError::Policy { .. } | EngineError::ResetForbidden(_) => ErrorKind::Policy,
+                EngineError::Shadow(e) => e.kind(),
+            },
+            Self::Codegen(_) => ErrorKind::Codegen,
+            Self::Hook(_) => ErrorKind::Hook,
+            Self::Io(_) => ErrorKind::Io,
+        }
+    }
+
+    /// Stable code of the failure category
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+#[cfg(feature = "db")]
+fn db_kind(e: &DbError) -> ErrorKind {
+    match e {
+        DbError::Connection(_) => ErrorKind::Connection,
+        DbError::Io(_) => ErrorKind::Io,
+        _ => ErrorKind::Database,
+    }
+}
+
+#[cfg(feature = "db")]
+fn migrate_kind(e: &MigrateError) -> ErrorKind {
+    match e {
+        MigrateError::Read { .. } | MigrateError::Write { .. } => ErrorKind::Io,
+        MigrateError::Meta { .. } => ErrorKind::InvalidInput,
+        MigrateError::Db { source, .. } => match db_kind(source) {
+            ErrorKind::Database => ErrorKind::Migration,
+            kind => kind,
+        },
+        MigrateError::History(_) | MigrateError::Resolve(_) => ErrorKind::Migration,
+        MigrateError::Git(_) => ErrorKind::Io,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds_look_through_wrappers() {
+        let err: StratusError = ConfigError::DatasourceNotFound("main".into()).into();
+        assert_eq!(err.kind(), ErrorKind::Config);
+        assert_eq!(err.to_string(), "Datasource not found: main");
+
+        #[cfg(feature = "db")]
+        {
+            let err: StratusError = EngineError::from(DbError::Connection("refused".into())).into();
+            assert_eq!(err.code(), "connection");
+        }
+
+        let err: StratusError = crate::format::format_typesql("SELECT 1;\n")
+            .unwrap_err()
+            .into();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,173 @@
+/**
+ * Stratus C FFI Module
+ *
+ * A C ABI over the parser and the TypeScript generator, for editors and
+ * toolchains that embed Stratus instead of spawning the CLI. The matching
+ * header is include/stratus.h.
+ *
+ * Strings are NUL-terminated UTF-8. Every returned `char *` is owned by the
+ * caller and released with `stratus_string_free`; NULL means failure, with
+ * the reason available from `stratus_last_error` on the same thread.
+ */
+use crate::codegen::GeneratorOptions;
+use crate::schema::Schema;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Hand a result to C: the string on success, NULL with the last error set on failure
+fn into_c(result: Result<String, String>) -> *mut c_char {
+    let result = result.and_then(|s| CString::new(s).map_err(|e| e.to_string()));
+    LAST_ERROR.with(|last| match result {
+        Ok(s) => {
+            *last.borrow_mut() = None;
+            s.into_raw()
+        }
+        Err(message) => {
+            *last.borrow_mut() = CString::new(message.replace('\0', " ")).ok();
+            std::ptr::null_mut()
+        }
+    })
+}
+
+/// Borrow a C string argument
+///
+/// # Safety
+/// `ptr` must be NULL or point to a NUL-terminated string.
+unsafe fn arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, String> {
+    if ptr.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("JSON serialization error: {}", e))
+}
+
+/// Parse TypeSQL; returns the queries as JSON.
+///
+/// # Safety
+/// `input` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn stratus_parse(input: *const c_char) -> *mut c_char {
+    into_c(arg(input, "input").and_then(|input| {
+        let (query_file, diagnostics) = crate::parser::parse_with_diagnostics(input);
+        match diagnostics
+            .iter()
+            .find(|d| d.severity == crate::diagnostics::Severity::Error)
+        {
+            Some(error) => Err(error.to_string()),
+            None => to_json(&query_file),
+        }
+    }))
+}
+
+/// Table names referenced by an SQL statement, as a JSON array.
+///
+/// # Safety
+/// `sql` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn stratus_extract_tables(sql: *const c_char) -> *mut c_char {
+    into_c(arg(sql, "sql").and_then(|sql| to_json(&crate::parser::extract_tables_from_sql(sql))))
+}
+
+/// Generate the TypeScript client for a TypeSQL file; `schema_json` may be NULL.
+///
+/// # Safety
+/// Both arguments must be NULL or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn stratus_generate_ts(
+    input: *const c_char,
+    schema_json: *const c_char,
+) -> *mut c_char {
+    let result = arg(input, "input").and_then(|input| {
+        let schema: Option<Schema> = if schema_json.is_null() {
+            None
+        } else {
+            let json = arg(schema_json, "schema_json")?;
+            Some(serde_json::from_str(json).map_err(|e| format!("Invalid schema: {}", e))?)
+        };
+        let query_file = crate::parser::parse(input).map_err(|e| e.to_string())?;
+        crate::codegen::generate(
+            "ts",
+            Some(&query_file),
+            schema.as_ref(),
+            &GeneratorOptions::default(),
+        )
+    });
+    into_c(result)
+}
+
+/// Why the last call on this thread returned NULL, or NULL after a success.
+///
+/// The string is owned by the caller.
+#[no_mangle]
+pub extern "C" fn stratus_last_error() -> *mut c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .clone()
+            .map_or(std::ptr::null_mut(), CString::into_raw)
+    })
+}
+
+/// Release a string returned by this library; NULL is ignored.
+///
+/// # Safety
+/// `s` must be NULL or a pointer returned by a `stratus_*` function, freed once.
+#[no_mangle]
+pub unsafe extern "C" fn stratus_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Library version; static, do not free
+#[no_mangle]
+pub extern "C" fn stratus_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(s: *mut c_char) -> Option<String> {
+        (!s.is_null()).then(|| unsafe {
+            let owned = CStr::from_ptr(s).to_str().unwrap().to_string();
+            stratus_string_free(s);
+            owned
+        })
+    }
+
+    #[test]
+    fn test_round_trip_through_c_strings() {
+        let input =
+            CString::new("# name: GetUser :one id: number\nSELECT * FROM users WHERE id = $1;\n")
+                .unwrap();
+        let parsed = take(unsafe { stratus_parse(input.as_ptr()) }).unwrap();
+        assert!(parsed.contains("\"GetUser\""));
+        assert_eq!(take(stratus_last_error()), None);
+
+        let sql = CString::new("SELECT * FROM users JOIN posts ON true").unwrap();
+        let tables = take(unsafe { stratus_extract_tables(sql.as_ptr()) }).unwrap();
+        assert!(tables.contains("users") && tables.contains("posts"));
+
+        let ts = take(unsafe { stratus_generate_ts(input.as_ptr(), std::ptr::null()) }).unwrap();
+        assert!(ts.contains("GetUser") || ts.contains("getUser"));
+
+        let bad = CString::new("# name: A :one\n").unwrap();
+        assert!(take(unsafe { stratus_parse(bad.as_ptr()) }).is_none());
+        assert!(take(stratus_last_error()).unwrap().contains("empty-query"));
+        assert!(take(unsafe { stratus_parse(std::ptr::null()) }).is_none());
+        assert_eq!(take(stratus_last_error()).unwrap(), "input is NULL");
+
+        let version = unsafe { CStr::from_ptr(stratus_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+}
@@ -0,0 +1,416 @@
+/**
+ * Stratus TypeSQL Formatter Module
+ *
+ * Formats TypeSQL query files: canonical headers, uppercase keywords and one
+ * clause per line in query bodies.
+ */
+use crate::parser::{parse_header, ParseError};
+
+/// Indentation of continuation lines (AND/OR, text after a comment)
+const INDENT: &str = "  ";
+
+/// Keywords written in uppercase
+pub(crate) const KEYWORDS: &[&str] = &[
+    "all",
+    "and",
+    "as",
+    "asc",
+    "between",
+    "by",
+    "case",
+    "conflict",
+    "cross",
+    "delete",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "exists",
+    "false",
+    "for",
+    "from",
+    "full",
+    "group",
+    "having",
+    "ilike",
+    "in",
+    "inner",
+    "insert",
+    "intersect",
+    "into",
+    "is",
+    "join",
+    "left",
+    "like",
+    "limit",
+    "not",
+    "nothing",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "returning",
+    "right",
+    "select",
+    "set",
+    "then",
+    "true",
+    "union",
+    "update",
+    "using",
+    "values",
+    "when",
+    "where",
+    "with",
+];
+
+/// Keywords that start a new line at the top level of a query
+const CLAUSES: &[&str] = &[
+    "select",
+    "from",
+    "where",
+    "group",
+    "having",
+    "order",
+    "limit",
+    "offset",
+    "insert",
+    "update",
+    "delete",
+    "values",
+    "set",
+    "returning",
+    "union",
+    "intersect",
+    "except",
+    "with",
+    "left",
+    "right",
+    "inner",
+    "full",
+    "cross",
+    "join",
+];
+
+/// Words after which a clause keyword continues the same clause
+/// (`DELETE FROM`, `LEFT JOIN`, `DO UPDATE`, `UNION SELECT`, `FOR UPDATE`, ...)
+const NO_BREAK_AFTER: &[&str] = &[
+    "delete", "distinct", "do", "left", "right", "inner", "full", "cross", "outer", "within", "for",
+];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum TokenKind {
+    Word,
+    /// String literal, quoted identifier or dollar-quoted body
+    Quoted,
+    /// `--` comment, which must end its line
+    LineComment,
+    /// Punctuation, operators, numbers and `$n` parameters
+    Other,
+}
+
+#[derive(Debug)]
+pub(crate) struct Token<'a> {
+    pub(crate) kind: TokenKind,
+    pub(crate) text: &'a str,
+    space_before: bool,
+}
+
+pub(crate) fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut space_before = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let kind = match bytes[i] {
+            b if b.is_ascii_whitespace() => {
+                space_before = true;
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                TokenKind::LineComment
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = sql[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| i + 2 + end + 2);
+                TokenKind::Quoted
+            }
+            quote @ (b'\'' | b'"') => {
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == quote {
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+                TokenKind::Quoted
+            }
+            b'$' => {
+                let tag_len = bytes[i + 1..]
+                    .iter()
+                    .position(|b| !(b.is_ascii_alphanumeric() || *b == b'_'));
+                match tag_len {
+                    // $1, $2, ...: a parameter
+                    _ if bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => {
+                        i += 1;
+                        while i < bytes.len() && bytes[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                        TokenKind::Other
+                    }
+                    // $tag$ ... $tag$
+                    Some(len) if bytes[i + 1 + len] == b'$' => {
+                        let tag = &sql[i..i + len + 2];
+                        i = sql[i + tag.len()..]
+                            .find(tag)
+                            .map_or(bytes.len(), |end| i + tag.len() + end + tag.len());
+                        TokenKind::Quoted
+                    }
+                    _ => {
+                        i += 1;
+                        TokenKind::Other
+                    }
+                }
+            }
+            b if b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80 => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] >= 0x80)
+                {
+                    i += 1;
+                }
+                TokenKind::Word
+            }
+            _ => {
+                i += 1;
+                TokenKind::Other
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            text: &sql[start..i],
+            space_before,
+        });
+        space_before = false;
+    }
+
+    tokens
+}
+
+/// Format one query body: uppercase keywords, one top-level clause per line
+/// and AND/OR conditions on indented continuation lines.
+pub fn format_query_body(sql: &str) -> String {
+    let tokens = tokenize(sql);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0usize;
+    let mut prev_word = String::new();
+    let mut in_between = false;
+    let mut after_comment = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let mut text = token.text.to_string();
+        let mut new_line: Option<&str> = None;
+
+        if after_comment {
+            new_line = Some(INDENT);
+            after_comment = false;
+        }
+
+        match token.kind {
+            TokenKind::Word => {
+                let lower = token.text.to_ascii_lowercase();
+                let qualified = i > 0 && tokens[i - 1].text == ".";
+                if KEYWORDS.contains(&lower.as_str()) && !qualified {
+                    text = lower.to_ascii_uppercase();
+                }
+
+                if depth == 0 && !qualified && i > 0 {
+                    let next_word = tokens[i + 1..]
+                        .iter()
+                        .find(|t| t.kind == TokenKind::Word)
+                        .map(|t| t.text.to_ascii_lowercase());
+                    let starts_clause = CLAUSES.contains(&lower.as_str())
+                        && !NO_BREAK_AFTER.contains(&prev_word.as_str())
+                        || (lower == "on" && next_word.as_deref() == Some("conflict"));
+                    if starts_clause {
+                        new_line = Some("");
+                    } else if lower == "and" && in_between {
+                        in_between = false;
+                    } else if lower == "and" || lower == "or" {
+                        new_line = Some(INDENT);
+                    }
+                }
+                if lower == "between" {
+                    in_between = true;
+                }
+                prev_word = lower;
+            }
+            TokenKind::LineComment => after_comment = true,
+            TokenKind::Other if token.text == "(" => depth += 1,
+            TokenKind::Other if token.text == ")" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+
+        match new_line {
+            Some(indent) if !line.trim().is_empty() => {
+                lines.push(std::mem::take(&mut line));
+                line.push_str(indent);
+            }
+            _ if token.space_before && !line.trim().is_empty() => line.push(' '),
+            _ => {}
+        }
+        line.push_str(&text);
+    }
+
+    if !line.trim().is_empty() {
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Format a TypeSQL file.
+///
+/// Headers are rewritten as `# name: X :kind param: type`; queries are
+/// separated by one blank line. Fails on a line that isn't a query header.
+pub fn format_typesql(input: &str) -> Result<String, ParseError> {
+    let mut blocks = Vec::new();
+    let mut lines = input.lines().enumerate().peekable();
+
+    loop {
+        while lines.next_if(|(_, l)| l.trim().is_empty()).is_some() {}
+        let Some((n, header_line)) = lines.next() else {
+            break;
+        };
+        let (name, return_type, params, rest) =
+            parse_header(header_line).ok_or(ParseError::InvalidHeader { line: n + 1 })?;
+
+        let mut body = Vec::new();
+        while let Some((_, l)) = lines.next_if(|(_, l)| !l.trim().is_empty()) {
+            body.push(l);
+        }
+
+        let mut header = format!("# name: {} :{}", name, return_type);
+        for param in &params {
+            header.push_str(&format!(" {}: {}", param.name, param.type_));
+        }
+        if !rest.is_empty() {
+            header.push(' ');
+            header.push_str(rest);
+        }
+
+        let sql = format_query_body(&body.join("\n"));
+        blocks.push(if sql.is_empty() {
+            header
+        } else {
+            format!("{}\n{}", header, sql)
+        });
+    }
+
+    if blocks.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(blocks.join("\n\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_header_and_body() {
+        let input = "#name:GetUser   id:number\nselect id, name from users where id = $1 and active = true;\n";
+        assert_eq!(
+            format_typesql(input).unwrap(),
+            "# name: GetUser :one id: number\n\
+             SELECT id, name\n\
+             FROM users\n\
+             WHERE id = $1\n  AND active = TRUE;\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_literals_and_joins() {
+        let body = format_query_body(
+            "select u.id, 'from where' as label, \"Order\".total::numeric from users u \
+             left join \"Order\" on \"Order\".user_id = u.id \
+             where u.created_at between $1 and $2 order by u.id desc",
+        );
+        assert_eq!(
+            body,
+            "SELECT u.id, 'from where' AS label, \"Order\".total::numeric\n\
+             FROM users u\n\
+             LEFT JOIN \"Order\" ON \"Order\".user_id = u.id\n\
+             WHERE u.created_at BETWEEN $1 AND $2\n\
+             ORDER BY u.id DESC"
+        );
+    }
+
+    #[test]
+    fn test_format_subqueries_and_conflicts() {
+        let body = format_query_body(
+            "insert into users (id, name) values ($1, $2) on conflict (id) do update set name = $2 returning id",
+        );
+        assert_eq!(
+            body,
+            "INSERT INTO users (id, name)\n\
+             VALUES ($1, $2)\n\
+             ON CONFLICT (id) DO UPDATE\n\
+             SET name = $2\n\
+             RETURNING id"
+        );
+
+        let body = format_query_body(
+            "delete from posts where user_id in (select id from users where banned)",
+        );
+        assert_eq!(
+            body,
+            "DELETE FROM posts\nWHERE user_id IN (SELECT id FROM users WHERE banned)"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent_and_preserves_queries() {
+        let input = include_str!("../examples/queries.sql");
+        let formatted = format_typesql(input).unwrap();
+        assert_eq!(format_typesql(&formatted).unwrap(), formatted);
+
+        let before = crate::parser::parse(input).unwrap();
+        let after = crate::parser::parse(&formatted).unwrap();
+        assert_eq!(before.queries.len(), after.queries.len());
+        for (b, a) in before.queries.iter().zip(&after.queries) {
+            assert_eq!(b.name, a.name);
+            assert_eq!(b.params, a.params);
+            let words = |sql: &str| {
+                sql.to_lowercase()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            assert_eq!(words(&b.sql), words(&a.sql));
+        }
+    }
+
+    #[test]
+    fn test_format_rejects_bad_header() {
+        let err = format_typesql("SELECT 1;\n").unwrap_err();
+        assert_eq!(err, ParseError::InvalidHeader { line: 1 });
+        assert!(err.to_string().starts_with("line 1:"));
+    }
+}
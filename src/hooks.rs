@@ -0,0 +1,83 @@
+/**
+ * Stratus Lifecycle Hooks Module
+ *
+ * Runs the shell commands configured under `hooks` in stratus.json.
+ */
+use std::path::Path;
+use std::process::Command;
+
+/// Hook fired before migrations are applied
+pub const PRE_MIGRATE: &str = "preMigrate";
+/// Hook fired after code generation wrote its outputs
+pub const POST_GENERATE: &str = "postGenerate";
+
+/// Run hook commands in order, stopping at the first failure.
+///
+/// Every command receives `STRATUS_HOOK` plus the given variables.
+pub fn run_hooks(
+    event: &str,
+    commands: &[String],
+    dir: &Path,
+    vars: &[(&str, String)],
+) -> Result<(), String> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    for command in commands {
+        tracing::info!(hook = event, "{}", command);
+        let mut cmd = shell(command);
+        cmd.current_dir(dir).env("STRATUS_HOOK", event);
+        for (key, value) in vars {
+            cmd.env(key, value);
+        }
+
+        let status = cmd
+            .status()
+            .map_err(|e| format!("Failed to run {} hook '{}': {}", event, command, e))?;
+        if !status.success() {
+            return Err(format!("{} hook '{}' failed ({})", event, command, status));
+        }
+    }
+
+    Ok(())
+}
+
+/// Build a command that runs `command` through the platform shell
+#[cfg(windows)]
+pub(crate) fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
+
+#[cfg(not(windows))]
+pub(crate) fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_run_hooks_env_and_failure() {
+        let dir = std::env::temp_dir();
+        let vars = [("STRATUS_MIGRATION_IDS", "1_a,2_b".to_string())];
+
+        let ok = vec![
+            r#"test "$STRATUS_HOOK" = preMigrate && test "$STRATUS_MIGRATION_IDS" = 1_a,2_b"#
+                .to_string(),
+        ];
+        assert!(run_hooks(PRE_MIGRATE, &ok, &dir, &vars).is_ok());
+
+        let failing = vec!["exit 3".to_string(), "echo never".to_string()];
+        let err = run_hooks(PRE_MIGRATE, &failing, &dir, &vars).unwrap_err();
+        assert!(err.contains("exit 3"));
+    }
+}
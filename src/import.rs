@@ -0,0 +1,481 @@
+/**
+ * Stratus Query Import Module
+ *
+ * Turns the raw SQL an application already runs into named TypeSQL stubs,
+ * for `stratus import-queries`. Statements come from pg_stat_statements or
+ * a log; constants become `$n` parameters, statements that differ only in
+ * their constants or formatting become one stub, and the most frequently
+ * run come first. Stub headers declare no parameters, so they are named and
+ * typed from the columns they meet, as for any header without them.
+ */
+use crate::analyze::range;
+use crate::ast::Query;
+use crate::format::{format_query_body, tokenize, TokenKind};
+use crate::infer::{check_cardinality, infer, table_refs, Cardinality};
+use crate::schema::Schema;
+use crate::stats::normalize_sql;
+use std::collections::{HashMap, HashSet};
+
+/// Words before a literal that make it a typed constant, as in `interval '1 day'`
+const TYPED_LITERALS: &[&str] = &["date", "interval", "time", "timestamp", "timestamptz"];
+
+/// Words that end an `ORDER BY` or `GROUP BY` list, whose numbers are positions
+const BY_LIST_END: &[&str] = &[
+    "except",
+    "fetch",
+    "for",
+    "having",
+    "intersect",
+    "limit",
+    "offset",
+    "union",
+    "window",
+];
+
+/// A statement as run, with how many times it ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub sql: String,
+    pub calls: u64,
+}
+
+/// Which statements become stubs
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Leave out statements run fewer times
+    pub min_calls: u64,
+    /// Keep only this many of the most frequently run
+    pub limit: Option<usize>,
+    /// Leave out statements on these tables, e.g. the migrations table
+    pub ignore_tables: Vec<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            min_calls: 1,
+            limit: None,
+            ignore_tables: Vec::new(),
+        }
+    }
+}
+
+/// A generated query and how many times its statements ran
+#[derive(Debug, Clone)]
+pub struct Stub {
+    pub query: Query,
+    pub calls: u64,
+}
+
+/// The statements of a log, counted.
+///
+/// Reads PostgreSQL logs (`statement:` and `execute` lines, with their
+/// continuation lines) and MySQL general logs (`Query` lines). Text with
+/// none of those is read as plain SQL, one statement after another.
+pub fn from_log(log: &str) -> Vec<Statement> {
+    let mut logged: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+    let mut found = false;
+    for line in log.lines() {
+        if let Some(sql) = logged_statement(line) {
+            logged.extend(current.take());
+            current = Some(sql.to_string());
+            found = true;
+        } else if let Some(sql) = current.as_mut().filter(|_| line.starts_with([' ', '\t'])) {
+            sql.push('\n');
+            sql.push_str(line);
+        } else {
+            logged.extend(current.take());
+        }
+    }
+    logged.extend(current);
+    if !found {
+        logged = vec![log.to_string()];
+    }
+
+    let mut statements: Vec<Statement> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for sql in logged
+        .iter()
+        .flat_map(|sql| crate::migrate::split_statements(sql))
+    {
+        let sql = sql.trim().trim_end_matches(';').trim();
+        match seen.get(sql) {
+            Some(&index) => statements[index].calls += 1,
+            None => {
+                seen.insert(sql.to_string(), statements.len());
+                statements.push(Statement {
+                    sql: sql.to_string(),
+                    calls: 1,
+                });
+            }
+        }
+    }
+    statements
+}
+
+/// The SQL of a log line that records a statement
+fn logged_statement(line: &str) -> Option<&str> {
+    if let Some(pos) = line.find("statement: ") {
+        return Some(&line[pos + "statement: ".len()..]);
+    }
+    if let Some(pos) = line.find(" execute ") {
+        return line[pos + " execute ".len()..]
+            .split_once(": ")
+            .map(|(_, sql)| sql);
+    }
+    line.split_once("\tQuery\t").map(|(_, sql)| sql)
+}
+
+/// Stubs for the statements worth importing, most frequently run first.
+///
+/// Only SELECT, INSERT, UPDATE, DELETE and WITH statements on tables are
+/// kept; catalog queries, statements on `ignore_tables` and statements that
+/// match one of `existing` are left out. With a schema, a SELECT that pins a
+/// unique key is `:one`.
+pub fn stubs(
+    statements: &[Statement],
+    existing: &[Query],
+    schema: Option<&Schema>,
+    options: &ImportOptions,
+) -> Vec<Stub> {
+    let known: HashSet<String> = existing.iter().map(|q| normalize_sql(&q.sql)).collect();
+    let mut names: HashSet<String> = existing.iter().map(|q| q.name.clone()).collect();
+
+    // (normalized, SQL as first seen, calls)
+    let mut candidates: Vec<(String, String, u64)> = Vec::new();
+    for statement in statements {
+        let sql = rewrite(&statement.sql, false);
+        if !importable(&sql, options) {
+            continue;
+        }
+        let normalized = normalize_sql(&sql);
+        if known.contains(&normalized) {
+            continue;
+        }
+        match candidates.iter_mut().find(|(n, _, _)| *n == normalized) {
+            Some(candidate) => candidate.2 += statement.calls,
+            None => candidates.push((normalized, sql, statement.calls)),
+        }
+    }
+    candidates.sort_by_key(|(_, _, calls)| std::cmp::Reverse(*calls));
+
+    candidates
+        .into_iter()
+        .filter(|(_, _, calls)| *calls >= options.min_calls)
+        .take(options.limit.unwrap_or(usize::MAX))
+        .map(|(_, sql, calls)| {
+            let parameterized = rewrite(&sql, true);
+            let cardinality = cardinality(&sql, &parameterized, schema);
+            let mut query = Query {
+                name: String::new(),
+                return_type: cardinality.as_str().to_string(),
+                sql: parameterized,
+                params: Vec::new(),
+                cache: None,
+            };
+            let name = name(&query, schema);
+            let mut unique = name.clone();
+            let mut n = 2;
+            while !names.insert(unique.clone()) {
+                unique = format!("{}{}", name, n);
+                n += 1;
+            }
+            query.name = unique;
+            Stub { query, calls }
+        })
+        .collect()
+}
+
+/// The stubs as a TypeSQL file
+pub fn render(stubs: &[Stub]) -> String {
+    let blocks: Vec<String> = stubs
+        .iter()
+        .map(|stub| {
+            format!(
+                "# name: {} :{}\n{};",
+                stub.query.name,
+                stub.query.return_type,
+                format_query_body(&stub.query.sql)
+            )
+        })
+        .collect();
+    if blocks.is_empty() {
+        return String::new();
+    }
+    blocks.join("\n\n") + "\n"
+}
+
+/// The SQL on one line without comments or a trailing semicolon; with
+/// `parameterize`, constants become `$n` numbered after the existing ones
+fn rewrite(sql: &str, parameterize: bool) -> String {
+    let tokens = tokenize(sql);
+    let mut next = tokens
+        .iter()
+        .filter_map(|t| t.text.strip_prefix('$')?.parse::<usize>().ok())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    let mut end = None;
+    let mut previous = "";
+    let mut by_list = false;
+    let mut skip = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+            end = Some(range(sql, token).end);
+            continue;
+        }
+        let text = token.text;
+        let comment = token.kind == TokenKind::LineComment
+            || (token.kind == TokenKind::Quoted && text.starts_with("/*"));
+        if comment {
+            continue;
+        }
+        let span = range(sql, token);
+        if end.is_some_and(|end| end != span.start) {
+            out.push(' ');
+        }
+        end = Some(span.end);
+
+        let number =
+            token.kind == TokenKind::Word && text.starts_with(|c: char| c.is_ascii_digit());
+        let string = token.kind == TokenKind::Quoted && text.starts_with('\'');
+        if token.kind == TokenKind::Word && !number {
+            let lower = text.to_ascii_lowercase();
+            if lower == "by" {
+                by_list = true;
+            } else if BY_LIST_END.contains(&lower.as_str()) {
+                by_list = false;
+            }
+        }
+        let keep =
+            TYPED_LITERALS.contains(&previous.to_ascii_lowercase().as_str()) || (number && by_list);
+        if parameterize && (number || string) && !keep {
+            next += 1;
+            out.push_str(&format!("${}", next));
+            // The fraction of a decimal, which tokenizes as `9` `.` `5`
+            let adjacent = |j: usize| {
+                tokens
+                    .get(j)
+                    .is_some_and(|t| range(sql, t).start == range(sql, &tokens[j - 1]).end)
+            };
+            if number
+                && adjacent(i + 1)
+                && tokens[i + 1].text == "."
+                && adjacent(i + 2)
+                && tokens[i + 2].text.starts_with(|c: char| c.is_ascii_digit())
+            {
+                skip = 2;
+            }
+        } else {
+            out.push_str(text);
+        }
+        previous = text;
+    }
+    let trimmed = out.trim_end_matches([';', ' ']).len();
+    out.truncate(trimmed);
+    out
+}
+
+/// A data statement that reads or writes an application table
+fn importable(sql: &str, options: &ImportOptions) -> bool {
+    let tokens = tokenize(sql);
+    let Some(first) = tokens.first() else {
+        return false;
+    };
+    let verb = first.text.to_ascii_lowercase();
+    if !matches!(
+        verb.as_str(),
+        "select" | "insert" | "update" | "delete" | "with"
+    ) {
+        return false;
+    }
+    let catalog = tokens.iter().any(|t| {
+        t.kind == TokenKind::Word
+            && matches!(
+                t.text.to_ascii_lowercase().as_str(),
+                "pg_catalog" | "information_schema"
+            )
+    });
+    let tables = table_refs(sql);
+    !catalog
+        && !tables.is_empty()
+        && !tables
+            .iter()
+            .any(|t| t.name.starts_with("pg_") || options.ignore_tables.contains(&t.name))
+}
+
+fn verb(sql: &str) -> String {
+    tokenize(sql)
+        .first()
+        .map(|t| t.text.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// `:one` for a single row: an INSERT ... RETURNING, a SELECT of only
+/// aggregates, one with LIMIT 1, or one pinning a unique key of the schema.
+/// LIMIT is read from the statement as run, the rest from its parameterized SQL.
+fn cardinality(sql: &str, parameterized: &str, schema: Option<&Schema>) -> Cardinality {
+    let returning = tokenize(sql)
+        .iter()
+        .any(|t| t.text.eq_ignore_ascii_case("returning"));
+    match verb(sql).as_str() {
+        "insert" if returning => return Cardinality::One,
+        "update" | "delete" if returning => return Cardinality::Many,
+        "insert" | "update" | "delete" => return Cardinality::Exec,
+        _ => {}
+    }
+    let query = |return_type: &str| Query {
+        name: String::new(),
+        return_type: return_type.to_string(),
+        sql: parameterized.to_string(),
+        params: Vec::new(),
+        cache: None,
+    };
+    let empty = Schema::default();
+    let single_row = check_cardinality(&query("many"), schema.unwrap_or(&empty))
+        .iter()
+        .any(|w| w.code == "many-single-row");
+    let limited = tokenize(sql)
+        .windows(2)
+        .any(|w| w[0].text.eq_ignore_ascii_case("limit") && w[1].text == "1");
+    let pinned = schema.is_some_and(|schema| {
+        let tables = table_refs(parameterized);
+        !tables.is_empty()
+            && tables.iter().all(|t| schema.tables.contains_key(&t.name))
+            && check_cardinality(&query("one"), schema).is_empty()
+    });
+    if single_row || limited || pinned {
+        Cardinality::One
+    } else {
+        Cardinality::Many
+    }
+}
+
+/// A name from the verb, the first table and, for reads and deletes, the
+/// columns the parameters are compared with: `GetUsersById`, `CreateOrders`
+fn name(query: &Query, schema: Option<&Schema>) -> String {
+    let cardinality = Cardinality::from_return_type(&query.return_type);
+    let verb = verb(&query.sql);
+    let prefix = match verb.as_str() {
+        "insert" => "Create",
+        "update" => "Update",
+        "delete" => "Delete",
+        _ if cardinality == Cardinality::One => "Get",
+        _ => "List",
+    };
+    let table = table_refs(&query.sql)
+        .first()
+        .map_or_else(|| "Rows".to_string(), |t| pascal_case(&t.name));
+
+    let mut columns: Vec<String> = Vec::new();
+    if matches!(verb.as_str(), "select" | "with" | "delete") {
+        for param in infer(query, schema).params {
+            // Parameters that meet no column are named `param<n>`
+            if param.name != format!("param{}", param.ordinal)
+                && !matches!(param.name.as_str(), "limit" | "offset")
+                && !columns.contains(&param.name)
+            {
+                columns.push(param.name);
+            }
+        }
+    }
+    columns.truncate(2);
+    let by: Vec<String> = columns.iter().map(|c| pascal_case(c)).collect();
+    if by.is_empty() {
+        format!("{}{}", prefix, table)
+    } else {
+        format!("{}{}By{}", prefix, table, by.join("And"))
+    }
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_statements_become_named_stubs() {
+        let log = "\
+2026-10-16 09:00:01 UTC [41] LOG:  statement: SELECT id, email FROM users WHERE id = 7
+2026-10-16 09:00:02 UTC [41] LOG:  duration: 0.210 ms  statement: select id, email
+\tfrom users where id = 12;
+2026-10-16 09:00:03 UTC [41] LOG:  execute <unnamed>: SELECT * FROM orders WHERE user_id = $1 AND status = 'open' ORDER BY 1 LIMIT 20
+2026-10-16 09:00:03 UTC [41] DETAIL:  parameters: $1 = '7'
+2026-10-16 09:00:04 UTC [41] LOG:  statement: INSERT INTO orders (user_id, total) VALUES (7, 9.5) RETURNING id
+2026-10-16 09:00:05 UTC [41] LOG:  statement: SELECT 1
+2026-10-16 09:00:06 UTC [41] LOG:  statement: SELECT relname FROM pg_catalog.pg_class
+2026-10-16 09:00:07 UTC [41] LOG:  statement: SELECT count(*) FROM orders WHERE created_at > now() - interval '1 day'
+2026-10-16 09:00:08 UTC [41] LOG:  statement: DELETE FROM _stratus_migrations WHERE id = '1'
+2026-10-16 09:00:09 UTC [41] LOG:  statement: SELECT name FROM products -- hot path
+";
+        let statements = from_log(log);
+        assert_eq!(statements.len(), 9);
+        assert!(statements[1]
+            .sql
+            .starts_with("select id, email\n\tfrom users"));
+
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isPrimaryKey": true},
+                "email": {"name": "email", "type": "text"}
+            }}}}"#,
+        )
+        .unwrap();
+        let existing = vec![Query {
+            name: "ListProducts".to_string(),
+            return_type: "many".to_string(),
+            sql: "SELECT name FROM products".to_string(),
+            params: Vec::new(),
+            cache: None,
+        }];
+        let options = ImportOptions {
+            ignore_tables: vec!["_stratus_migrations".to_string()],
+            ..ImportOptions::default()
+        };
+        let stubs = stubs(&statements, &existing, Some(&schema), &options);
+        let summary: Vec<(&str, &str, u64)> = stubs
+            .iter()
+            .map(|s| (s.query.name.as_str(), s.query.return_type.as_str(), s.calls))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("GetUsersById", "one", 2),
+                ("ListOrdersByUserIdAndStatus", "many", 1),
+                ("CreateOrders", "one", 1),
+                ("GetOrders", "one", 1),
+            ]
+        );
+        assert_eq!(
+            stubs[1].query.sql,
+            "SELECT * FROM orders WHERE user_id = $1 AND status = $2 ORDER BY 1 LIMIT $3"
+        );
+        assert_eq!(
+            stubs[2].query.sql,
+            "INSERT INTO orders (user_id, total) VALUES ($1, $2) RETURNING id"
+        );
+        assert!(stubs[3].query.sql.ends_with("interval '1 day'"));
+
+        let file = render(&stubs);
+        assert!(file.starts_with(
+            "# name: GetUsersById :one\nSELECT id, email\nFROM users\nWHERE id = $1;\n\n"
+        ));
+        let parsed = crate::parser::parse(&file).unwrap();
+        assert_eq!(parsed.queries.len(), 4);
+        assert_eq!(from_log("SELECT * FROM a; SELECT * FROM a;\n").len(), 1);
+    }
+}
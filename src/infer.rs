@@ -0,0 +1,919 @@
+/**
+ * Stratus Type Inference Module
+ *
+ * The typed signature of a query: its cardinality, the types of its
+ * parameters (from the columns they are compared with) and its result
+ * columns with their schema types. A header that declares no parameters
+ * has them inferred: `$n` is named and typed after the column it meets.
+ * Generators and the language server read types from here rather than each
+ * working them out from the SQL, and `check_cardinality` warns when a
+ * `:one` or `:many` does not match what the SQL returns.
+ */
+use crate::analyze::{range, table_ref, TABLE_KEYWORDS};
+use crate::ast::Query;
+use crate::format::{tokenize, Token, TokenKind, KEYWORDS};
+use crate::parser::extract_select_columns;
+use crate::schema::{Column, Schema};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// How many rows a query returns, from its `:one`, `:many` or `:exec` directive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cardinality {
+    One,
+    Many,
+    Exec,
+}
+
+impl Cardinality {
+    /// Unknown directives read as `:one`, like a header without one
+    pub fn from_return_type(return_type: &str) -> Self {
+        match return_type {
+            "many" => Self::Many,
+            "exec" => Self::Exec,
+            _ => Self::One,
+        }
+    }
+
+    /// The directive without its colon
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::One => "one",
+            Self::Many => "many",
+            Self::Exec => "exec",
+        }
+    }
+}
+
+/// A query parameter with what is known of its type
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedParam {
+    pub name: String,
+    /// 1-based position, as in `$1`
+    pub ordinal: usize,
+    /// TypeSQL type from the header, such as `number`; None when inferred
+    pub declared: Option<String>,
+    /// Table and column the parameter is compared with or assigned to
+    pub column: Option<(String, String)>,
+    /// SQL type of that column (`bigint` for LIMIT and OFFSET)
+    pub sql_type: Option<String>,
+}
+
+impl TypedParam {
+    /// The schema column the parameter stands for, or a bare column of its SQL type
+    pub fn sql_column(&self, schema: Option<&Schema>) -> Option<Column> {
+        let from_schema = self
+            .column
+            .as_ref()
+            .and_then(|(table, column)| schema?.tables.get(table)?.columns.get(column).cloned());
+        from_schema.or_else(|| {
+            Some(Column {
+                column_name: self.name.clone(),
+                data_type: self.sql_type.clone()?,
+                ..Column::default()
+            })
+        })
+    }
+}
+
+/// Where a result column was named
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnOrigin {
+    /// Expanded from `*` or `table.*`
+    Wildcard,
+    /// Named with its table, as in `users.email`
+    Qualified,
+    /// Named alone and taken from the first table
+    Unqualified,
+    /// Every column of the table, when no select list could be read
+    Table,
+}
+
+/// A column of the result row
+#[derive(Debug, Clone)]
+pub struct ResultColumn {
+    /// Property name, unique within the row
+    pub name: String,
+    pub table: String,
+    /// Name in the SQL
+    pub column_name: String,
+    /// The schema column; None when the table or column is not in the schema
+    pub column: Option<Column>,
+    /// Whether the table is in the schema
+    pub table_known: bool,
+    pub origin: ColumnOrigin,
+}
+
+/// The result row of a query
+#[derive(Debug, Clone)]
+pub enum Row {
+    /// No schema was given
+    NoSchema,
+    /// The query reads no table the schema knows; the first table named, if any
+    Unresolved(Option<String>),
+    Columns(Vec<ResultColumn>),
+}
+
+/// Everything known about a query's types
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub cardinality: Cardinality,
+    pub params: Vec<TypedParam>,
+    pub row: Row,
+}
+
+/// A `:one` or `:many` that the query's SQL does not bear out
+#[derive(Debug, Clone)]
+pub struct CardinalityWarning {
+    pub code: &'static str,
+    pub message: String,
+    /// Byte range in the SQL the warning points at
+    pub range: Range<usize>,
+}
+
+/// A table named in a query and the alias it is read through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRef {
+    pub name: String,
+    /// The table name itself when unaliased
+    pub alias: String,
+}
+
+/// Infer the signature of a query against an optional schema
+pub fn infer(query: &Query, schema: Option<&Schema>) -> Signature {
+    let uses = param_uses(&query.sql, schema);
+    let typed = |ordinal: usize, name: String, declared: Option<String>| {
+        let used = uses.iter().find(|u| u.ordinal == ordinal);
+        let column = used.and_then(|u| Some((u.table.clone()?, u.column.clone())));
+        let sql_type = used.and_then(|u| u.sql_type.clone()).or_else(|| {
+            let (table, column) = column.as_ref()?;
+            Some(
+                schema?
+                    .tables
+                    .get(table)?
+                    .columns
+                    .get(column)?
+                    .data_type
+                    .clone(),
+            )
+        });
+        TypedParam {
+            name,
+            ordinal,
+            declared,
+            column,
+            sql_type,
+        }
+    };
+
+    let params = if query.params.is_empty() {
+        let mut ordinals: Vec<usize> = tokenize(&query.sql).iter().filter_map(ordinal).collect();
+        ordinals.sort_unstable();
+        ordinals.dedup();
+        let mut names = HashSet::new();
+        ordinals
+            .into_iter()
+            .map(|n| {
+                let name = uses
+                    .iter()
+                    .find(|u| u.ordinal == n)
+                    .map_or_else(|| format!("param{}", n), |u| u.column.clone());
+                let name = if names.insert(name.clone()) {
+                    name
+                } else {
+                    format!("{}_{}", name, n)
+                };
+                typed(n, name, None)
+            })
+            .collect()
+    } else {
+        query
+            .params
+            .iter()
+            .map(|p| typed(p.ordinal, p.name.clone(), Some(p.type_.clone())))
+            .collect()
+    };
+    Signature {
+        cardinality: Cardinality::from_return_type(&query.return_type),
+        params,
+        row: schema.map_or(Row::NoSchema, |schema| row(&query.sql, schema)),
+    }
+}
+
+/// Aggregate functions: a select list of nothing else, without GROUP BY, is one row
+const AGGREGATES: &[&str] = &[
+    "count",
+    "sum",
+    "avg",
+    "min",
+    "max",
+    "bool_and",
+    "bool_or",
+    "every",
+    "array_agg",
+    "string_agg",
+    "json_agg",
+    "jsonb_agg",
+    "json_object_agg",
+    "jsonb_object_agg",
+];
+
+/// Check a SELECT against its cardinality: `:one` should filter on a unique
+/// key of a table it reads (or say LIMIT 1), and `:many` should not select
+/// only aggregates without GROUP BY, which always yields a single row.
+///
+/// Other statements are not checked, nor queries on tables the schema lacks.
+pub fn check_cardinality(query: &Query, schema: &Schema) -> Vec<CardinalityWarning> {
+    let sql = query.sql.as_str();
+    let tokens: Vec<Token<'_>> = tokenize(sql)
+        .into_iter()
+        .filter(|t| t.kind != TokenKind::LineComment)
+        .collect();
+    if tokens
+        .first()
+        .is_none_or(|t| !t.text.eq_ignore_ascii_case("select"))
+    {
+        return Vec::new();
+    }
+
+    // Indexes of the tokens outside parentheses
+    let mut depth = 0usize;
+    let mut top = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            _ if depth == 0 => top.push(i),
+            _ => {}
+        }
+    }
+    let is = |i: usize, word: &str| tokens[i].text.eq_ignore_ascii_case(word);
+    let find = |word: &str| top.iter().position(|&i| is(i, word));
+    let from = find("from");
+
+    // Only aggregates in the select list, and no GROUP BY
+    let select_list = &top[1..from.unwrap_or(top.len())];
+    let aggregates: Vec<usize> = select_list
+        .split(|&i| tokens[i].text == ",")
+        .filter_map(|item| {
+            let first = *item.iter().find(|&&i| !is(i, "distinct"))?;
+            (AGGREGATES.contains(&tokens[first].text.to_ascii_lowercase().as_str())
+                && tokens.get(first + 1).is_some_and(|t| t.text == "("))
+            .then_some(first)
+        })
+        .collect();
+    let single_row = !aggregates.is_empty()
+        && aggregates.len() == select_list.split(|&i| tokens[i].text == ",").count()
+        && !select_list.iter().any(|&i| is(i, "over"))
+        && find("group").is_none();
+
+    let cardinality = Cardinality::from_return_type(&query.return_type);
+    if cardinality == Cardinality::Many && single_row {
+        return vec![CardinalityWarning {
+            code: "many-single-row",
+            message: "only aggregates without GROUP BY: this query returns a single row; use :one"
+                .to_string(),
+            range: range(sql, &tokens[aggregates[0]]),
+        }];
+    }
+    if cardinality != Cardinality::One || single_row {
+        return Vec::new();
+    }
+    let Some(from) = from else {
+        return Vec::new();
+    };
+    let limited = top
+        .windows(2)
+        .any(|w| is(w[0], "limit") && tokens[w[1]].text == "1")
+        || find("fetch").is_some();
+    let refs = from_tables(sql);
+    if limited || refs.is_empty() || refs.iter().any(|t| !schema.tables.contains_key(&t.name)) {
+        return Vec::new();
+    }
+
+    // Columns the WHERE clause pins to a single value
+    let mut pinned: Vec<(String, String)> = Vec::new();
+    let filter = find("where").map_or(&top[..0], |w| {
+        let rest = &top[w + 1..];
+        let end = rest
+            .iter()
+            .position(|&i| {
+                [
+                    "group", "order", "limit", "offset", "having", "union", "window", "for",
+                ]
+                .iter()
+                .any(|k| is(i, k))
+            })
+            .unwrap_or(rest.len());
+        &rest[..end]
+    });
+    if !filter.iter().any(|&i| is(i, "or")) {
+        let value = |i: usize| {
+            let token = &tokens[i];
+            ordinal(token).is_some()
+                || (token.kind == TokenKind::Quoted && token.text.starts_with('\''))
+                || token.text.starts_with(|c: char| c.is_ascii_digit())
+                || is(i, "true")
+                || is(i, "false")
+        };
+        for &i in filter {
+            if tokens[i].text != "=" || i == 0 || ["<", ">", "!"].contains(&tokens[i - 1].text) {
+                continue;
+            }
+            let column = if tokens.get(i + 1).is_some_and(|_| value(i + 1)) {
+                column_before(&tokens, i - 1)
+            } else if value(i - 1) {
+                column_after(&tokens, i + 1)
+            } else {
+                None
+            };
+            if let Some((qualifier, name)) = column {
+                if let Some(table) = column_table(qualifier.as_deref(), &name, &refs, Some(schema))
+                {
+                    pinned.push((table, name));
+                }
+            }
+        }
+    }
+    let unique = refs.iter().any(|t| {
+        schema.tables[&t.name].unique_keys().iter().any(|key| {
+            key.iter().all(|c| {
+                pinned
+                    .iter()
+                    .any(|(table, name)| *table == t.name && name == c)
+            })
+        })
+    });
+    if unique {
+        return Vec::new();
+    }
+    vec![CardinalityWarning {
+        code: "one-not-unique",
+        message: format!(
+            "`:one` query does not filter on a unique key of '{}'; add LIMIT 1 or use :many",
+            refs[0].name
+        ),
+        range: range(sql, tokens.get(top[from] + 1).unwrap_or(&tokens[top[from]])),
+    }]
+}
+
+fn row(sql: &str, schema: &Schema) -> Row {
+    let refs = from_tables(sql);
+    let tables: Vec<String> = refs.iter().map(|t| t.name.clone()).collect();
+    let selected = extract_select_columns(sql);
+    if tables.is_empty() || selected.is_empty() {
+        // No select list: the whole of the first table
+        let Some(table) = tables.first().and_then(|t| schema.tables.get_key_value(t)) else {
+            return Row::Unresolved(tables.first().cloned());
+        };
+        return Row::Columns(
+            table
+                .1
+                .columns
+                .iter()
+                .map(|(name, column)| ResultColumn {
+                    name: name.clone(),
+                    table: table.0.clone(),
+                    column_name: name.clone(),
+                    column: Some(column.clone()),
+                    table_known: true,
+                    origin: ColumnOrigin::Table,
+                })
+                .collect(),
+        );
+    }
+
+    let mut columns = Vec::new();
+    // Property names in use, and `table.column` already expanded from wildcards
+    let mut used = HashSet::new();
+    let mut expanded = HashSet::new();
+    let mut push =
+        |table: &str, name: &str, property: &str, column: Option<&Column>, known, origin| {
+            columns.push(ResultColumn {
+                name: unique_name(property, table, &mut used),
+                table: table.to_string(),
+                column_name: name.to_string(),
+                column: column.cloned(),
+                table_known: known,
+                origin,
+            });
+        };
+
+    for col in &selected {
+        if col.is_wildcard {
+            let wildcard_tables = match &col.table_name {
+                Some(qualifier) => vec![qualified(qualifier, &refs, schema)],
+                None => tables.clone(),
+            };
+            for table_name in &wildcard_tables {
+                let Some(table) = schema.tables.get(table_name) else {
+                    continue;
+                };
+                for (name, column) in &table.columns {
+                    if expanded.insert(format!("{}.{}", table_name, name)) {
+                        push(
+                            table_name,
+                            name,
+                            name,
+                            Some(column),
+                            true,
+                            ColumnOrigin::Wildcard,
+                        );
+                    }
+                }
+            }
+            continue;
+        }
+
+        // Unqualified columns belong to the first table, in join order, that has them
+        let table_name = match &col.table_name {
+            Some(qualifier) => qualified(qualifier, &refs, schema),
+            None => match tables
+                .iter()
+                .find(|t| {
+                    schema
+                        .tables
+                        .get(*t)
+                        .is_some_and(|t| t.columns.contains_key(&col.column_name))
+                })
+                .or_else(|| tables.first())
+            {
+                Some(table) => table.clone(),
+                None => continue,
+            },
+        };
+        let origin = if col.table_name.is_some() {
+            ColumnOrigin::Qualified
+        } else {
+            ColumnOrigin::Unqualified
+        };
+        let table = schema.tables.get(&table_name);
+        let column = table.and_then(|t| t.columns.get(&col.column_name));
+        push(
+            &table_name,
+            &col.column_name,
+            col.output_name(),
+            column,
+            table.is_some(),
+            origin,
+        );
+    }
+    Row::Columns(columns)
+}
+
+/// The table a select-list qualifier stands for, or the qualifier itself
+fn qualified(qualifier: &str, refs: &[TableRef], schema: &Schema) -> String {
+    resolve(qualifier, refs, schema).map_or_else(|| qualifier.to_string(), String::clone)
+}
+
+/// A property name not yet used, prefixed with the table on conflict
+fn unique_name(column_name: &str, table_name: &str, used: &mut HashSet<String>) -> String {
+    let mut name = column_name.to_string();
+    let mut counter = 1;
+    while used.contains(&name) {
+        name = format!("{}_{}_{}", table_name, column_name, counter);
+        counter += 1;
+    }
+    used.insert(name.clone());
+    name
+}
+
+/// Tables named after FROM, JOIN, INTO and UPDATE, in order, with their aliases
+pub fn table_refs(sql: &str) -> Vec<TableRef> {
+    refs(sql, |_, _| true)
+}
+
+/// Tables the outer statement reads, after its FROM and JOINs, in join order
+fn from_tables(sql: &str) -> Vec<TableRef> {
+    refs(sql, |keyword, depth| {
+        depth == 0 && matches!(keyword, "from" | "join")
+    })
+}
+
+/// Table references whose keyword and parenthesis depth pass `keep`
+fn refs(sql: &str, keep: impl Fn(&str, usize) -> bool) -> Vec<TableRef> {
+    let tokens = tokenize(sql);
+    let mut tables = Vec::new();
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        let keyword = token.text.to_ascii_lowercase();
+        if token.kind != TokenKind::Word
+            || !TABLE_KEYWORDS.contains(&keyword.as_str())
+            || !keep(&keyword, depth)
+            || (i > 0 && tokens[i - 1].text.eq_ignore_ascii_case("distinct"))
+        {
+            continue;
+        }
+        let Some(name) = table_ref(&tokens, i + 1, true) else {
+            continue;
+        };
+        let mut next = i + 2;
+        if tokens
+            .get(next)
+            .is_some_and(|t| t.text.eq_ignore_ascii_case("as"))
+        {
+            next += 1;
+        }
+        let alias = tokens
+            .get(next)
+            .and_then(|t| match t.kind {
+                TokenKind::Word => Some(t.text.to_ascii_lowercase())
+                    .filter(|word| !KEYWORDS.contains(&word.as_str())),
+                TokenKind::Quoted if t.text.starts_with('"') => {
+                    Some(t.text.trim_matches('"').replace("\"\"", "\""))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| name.clone());
+        tables.push(TableRef { name, alias });
+    }
+    tables
+}
+
+/// The table a qualifier names, by alias or else by table name
+pub fn resolve<'a>(
+    qualifier: &str,
+    tables: &'a [TableRef],
+    schema: &'a Schema,
+) -> Option<&'a String> {
+    tables
+        .iter()
+        .find(|t| t.alias == qualifier)
+        .map(|t| &t.name)
+        .or_else(|| schema.tables.get_key_value(qualifier).map(|(k, _)| k))
+}
+
+/// Operators that compare a column with a parameter
+const COMPARISONS: &[&str] = &["=", "<>", "!=", "<", ">", "<=", ">=", "like", "ilike"];
+
+/// What a `$n` is compared with, assigned to or inserted into
+struct ParamUse {
+    ordinal: usize,
+    column: String,
+    /// The column's table, when the schema has it
+    table: Option<String>,
+    /// A type known without the schema
+    sql_type: Option<String>,
+}
+
+/// Every use of a `$n` that says what it stands for, in order
+fn param_uses(sql: &str, schema: Option<&Schema>) -> Vec<ParamUse> {
+    let tokens = tokenize(sql);
+    let tables = table_refs(sql);
+    let mut found = Vec::new();
+
+    // `column op $n` and `$n op column`
+    for (i, token) in tokens.iter().enumerate() {
+        let Some(ordinal) = ordinal(token) else {
+            continue;
+        };
+        let previous = (i > 0).then(|| tokens[i - 1].text.to_ascii_lowercase());
+        if let Some(keyword @ ("limit" | "offset")) = previous.as_deref() {
+            found.push(ParamUse {
+                ordinal,
+                column: keyword.to_string(),
+                table: None,
+                sql_type: Some("bigint".to_string()),
+            });
+            continue;
+        }
+        let column = if let Some(end) = comparison_before(&tokens, i) {
+            column_before(&tokens, end)
+        } else if let Some(start) = comparison_after(&tokens, i) {
+            column_after(&tokens, start)
+        } else {
+            None
+        };
+        if let Some((qualifier, name)) = column {
+            found.push(ParamUse {
+                ordinal,
+                table: column_table(qualifier.as_deref(), &name, &tables, schema),
+                column: name,
+                sql_type: None,
+            });
+        }
+    }
+
+    // INSERT INTO t (a, b) VALUES ($1, $2)
+    let lower: Vec<String> = tokens.iter().map(|t| t.text.to_ascii_lowercase()).collect();
+    if let (Some(into), Some(values)) = (
+        lower.iter().position(|t| t == "into"),
+        lower.iter().position(|t| t == "values"),
+    ) {
+        if let Some(table) = table_ref(&tokens, into + 1, true) {
+            let names: Vec<&str> = list(&tokens, into + 2).iter().map(|t| t.text).collect();
+            let values = list(&tokens, values + 1);
+            for (name, value) in names.iter().zip(values) {
+                if let Some(ordinal) = ordinal(value) {
+                    let column = name.to_ascii_lowercase();
+                    let known = schema
+                        .and_then(|s| s.tables.get(&table))
+                        .is_some_and(|t| t.columns.contains_key(&column));
+                    found.push(ParamUse {
+                        ordinal,
+                        table: known.then(|| table.clone()),
+                        column,
+                        sql_type: None,
+                    });
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Whether tokens spell a comparison operator; `<=` and the like come a character per token
+fn is_comparison(tokens: &[Token<'_>]) -> bool {
+    let op: String = tokens.iter().map(|t| t.text.to_ascii_lowercase()).collect();
+    COMPARISONS.contains(&op.as_str())
+}
+
+/// Index of the token before a comparison that ends just before `tokens[i]`
+fn comparison_before(tokens: &[Token<'_>], i: usize) -> Option<usize> {
+    (1..=2.min(i))
+        .rev()
+        .find(|len| is_comparison(&tokens[i - len..i]))
+        .and_then(|len| (i - len).checked_sub(1))
+}
+
+/// Index of the token after a comparison that starts just after `tokens[i]`
+fn comparison_after(tokens: &[Token<'_>], i: usize) -> Option<usize> {
+    (1..=2)
+        .rev()
+        .find(|len| tokens.get(i + 1..i + 1 + len).is_some_and(is_comparison))
+        .map(|len| i + 1 + len)
+}
+
+/// `n` of a `$n` token
+fn ordinal(token: &Token<'_>) -> Option<usize> {
+    token.text.strip_prefix('$')?.parse().ok()
+}
+
+/// `[qualifier.]column` ending at `tokens[i]`
+fn column_before(tokens: &[Token<'_>], i: usize) -> Option<(Option<String>, String)> {
+    let name = tokens.get(i).filter(|t| t.kind == TokenKind::Word)?;
+    let qualifier =
+        (i >= 2 && tokens[i - 1].text == ".").then(|| tokens[i - 2].text.to_ascii_lowercase());
+    Some((qualifier, name.text.to_ascii_lowercase()))
+}
+
+/// `[qualifier.]column` starting at `tokens[i]`
+fn column_after(tokens: &[Token<'_>], i: usize) -> Option<(Option<String>, String)> {
+    let first = tokens.get(i).filter(|t| t.kind == TokenKind::Word)?;
+    match (tokens.get(i + 1), tokens.get(i + 2)) {
+        (Some(dot), Some(name)) if dot.text == "." && name.kind == TokenKind::Word => Some((
+            Some(first.text.to_ascii_lowercase()),
+            name.text.to_ascii_lowercase(),
+        )),
+        _ => Some((None, first.text.to_ascii_lowercase())),
+    }
+}
+
+/// The table a column belongs to: its qualifier's, else the first named table that has it
+pub(crate) fn column_table(
+    qualifier: Option<&str>,
+    name: &str,
+    tables: &[TableRef],
+    schema: Option<&Schema>,
+) -> Option<String> {
+    let schema = schema?;
+    let has = |table: &String| {
+        schema
+            .tables
+            .get(table)
+            .is_some_and(|t| t.columns.contains_key(name))
+    };
+    match qualifier {
+        Some(q) => resolve(q, tables, schema).filter(|t| has(t)).cloned(),
+        None => tables.iter().map(|t| &t.name).find(|t| has(t)).cloned(),
+    }
+}
+
+/// Non-punctuation tokens of a parenthesized list at `tokens[i]`
+fn list<'a, 't>(tokens: &'t [Token<'a>], i: usize) -> Vec<&'t Token<'a>> {
+    if tokens.get(i).is_none_or(|t| t.text != "(") {
+        return Vec::new();
+    }
+    tokens[i + 1..]
+        .iter()
+        .take_while(|t| t.text != ")")
+        .filter(|t| t.text != ",")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint", "isPrimaryKey": true}, "email": {"name": "email", "type": "text", "isUnique": true}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}, "user_id": {"name": "user_id", "type": "bigint"}, "title": {"name": "title", "type": "text"}}}
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    fn signature(source: &str) -> Signature {
+        let query = crate::parser::parse(source).unwrap().queries.remove(0);
+        infer(&query, Some(&schema()))
+    }
+
+    #[test]
+    fn test_param_types_come_from_compared_columns() {
+        let sig = signature(
+            "# name: Find :many id: number title: string\n\
+             SELECT p.title FROM posts p JOIN users u ON u.id = p.user_id WHERE $1 = u.id AND p.title LIKE $2;\n",
+        );
+        assert_eq!(sig.cardinality, Cardinality::Many);
+        let typed: Vec<_> = sig
+            .params
+            .iter()
+            .map(|p| (p.name.as_str(), p.column.clone(), p.sql_type.as_deref()))
+            .collect();
+        assert_eq!(
+            typed,
+            vec![
+                ("id", Some(("users".into(), "id".into())), Some("bigint")),
+                (
+                    "title",
+                    Some(("posts".into(), "title".into())),
+                    Some("text")
+                ),
+            ]
+        );
+
+        let sig = signature(
+            "# name: Add :exec user_id: number title: string\n\
+             INSERT INTO posts (user_id, title) VALUES ($1, $2);\n",
+        );
+        assert_eq!(sig.cardinality, Cardinality::Exec);
+        assert_eq!(sig.params[1].column, Some(("posts".into(), "title".into())));
+        assert_eq!(sig.params[0].sql_type.as_deref(), Some("bigint"));
+    }
+
+    #[test]
+    fn test_undeclared_params_are_inferred() {
+        let sig = signature(
+            "# name: Page :many\n\
+             SELECT * FROM posts WHERE user_id = $1 AND id > $2 AND id <> $3 AND $4 LIKE 'x' \
+             LIMIT $5 OFFSET $6;\n",
+        );
+        let typed: Vec<_> = sig
+            .params
+            .iter()
+            .map(|p| {
+                (
+                    p.name.as_str(),
+                    p.declared.as_deref(),
+                    p.sql_type.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            typed,
+            vec![
+                ("user_id", None, Some("bigint")),
+                ("id", None, Some("bigint")),
+                ("id_3", None, Some("bigint")),
+                ("param4", None, None),
+                ("limit", None, Some("bigint")),
+                ("offset", None, Some("bigint")),
+            ]
+        );
+        assert_eq!(sig.params[0].sql_column(None).unwrap().data_type, "bigint");
+
+        // Without a schema the names are still taken from the SQL
+        let query = crate::parser::parse("# name: Q :one\nSELECT 1 FROM t WHERE t.code = $1;\n")
+            .unwrap()
+            .queries
+            .remove(0);
+        let sig = infer(&query, None);
+        assert_eq!(sig.params[0].name, "code");
+        assert!(sig.params[0].sql_column(None).is_none());
+    }
+
+    #[test]
+    fn test_result_columns() {
+        let Row::Columns(columns) =
+            signature("# name: Q :one\nSELECT users.id, posts.id, email, nope, \"u\".id AS user_id FROM users \"u\" JOIN posts ON true;\n").row
+        else {
+            panic!("expected columns");
+        };
+        let names: Vec<_> = columns
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.table.as_str(),
+                    c.column.is_some(),
+                    c.origin,
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("id", "users", true, ColumnOrigin::Qualified),
+                ("posts_id_1", "posts", true, ColumnOrigin::Qualified),
+                ("email", "users", true, ColumnOrigin::Unqualified),
+                ("nope", "users", false, ColumnOrigin::Unqualified),
+                ("user_id", "users", true, ColumnOrigin::Qualified),
+            ]
+        );
+
+        assert!(matches!(
+            signature("# name: Q :one\nDELETE FROM comments;\n").row,
+            Row::Unresolved(Some(table)) if table == "comments"
+        ));
+        let query = crate::parser::parse("# name: Q :one\nSELECT 1;\n")
+            .unwrap()
+            .queries
+            .remove(0);
+        assert!(matches!(infer(&query, None).row, Row::NoSchema));
+    }
+
+    #[test]
+    fn test_wildcards_expand_in_join_order() {
+        let names = |sql: &str| -> Vec<(String, String)> {
+            let Row::Columns(columns) = signature(sql).row else {
+                panic!("expected columns");
+            };
+            columns
+                .into_iter()
+                .map(|c| (c.table, c.column_name))
+                .collect()
+        };
+        let joined =
+            names("# name: Q :many\nSELECT * FROM posts p JOIN users u ON u.id = p.user_id;\n");
+        let split = joined.iter().position(|(t, _)| t == "users").unwrap();
+        assert!(split > 0 && joined[..split].iter().all(|(t, _)| t == "posts"));
+        assert!(joined[split..].iter().all(|(t, _)| t == "users"));
+        assert!(joined.contains(&("users".into(), "email".into())));
+
+        let aliased = names(
+            "# name: Q :many\nSELECT u.*, p.title FROM users u JOIN posts p ON true WHERE u.id IN (SELECT user_id FROM comments);\n",
+        );
+        assert!(aliased.iter().any(|(t, c)| t == "users" && c == "email"));
+        assert_eq!(aliased.last().unwrap(), &("posts".into(), "title".into()));
+        assert!(aliased.iter().all(|(t, _)| t != "comments"));
+    }
+
+    #[test]
+    fn test_cardinality_matches_the_sql() {
+        let warnings = |source: &str| -> Vec<&'static str> {
+            let query = crate::parser::parse(source).unwrap().queries.remove(0);
+            check_cardinality(&query, &schema())
+                .iter()
+                .map(|w| w.code)
+                .collect()
+        };
+        let fine = [
+            "# name: Q :one id: number\nSELECT * FROM users WHERE id = $1;\n",
+            "# name: Q :one\nSELECT u.email FROM users u WHERE 'a@b.c' = u.email;\n",
+            "# name: Q :one\nSELECT * FROM posts ORDER BY id DESC LIMIT 1;\n",
+            "# name: Q :one\nSELECT count(*) FROM posts;\n",
+            "# name: Q :one\nSELECT now();\n",
+            "# name: Q :many\nSELECT user_id, count(*) FROM posts GROUP BY user_id;\n",
+            "# name: Q :many\nSELECT count(*) OVER () FROM posts;\n",
+            "# name: Q :one\nSELECT * FROM ghosts WHERE a = 1;\n",
+        ];
+        for source in fine {
+            assert_eq!(warnings(source), Vec::<&str>::new(), "{}", source);
+        }
+        assert_eq!(
+            warnings("# name: Q :one id: number\nSELECT * FROM posts WHERE user_id = $1;\n"),
+            vec!["one-not-unique"]
+        );
+        assert_eq!(
+            warnings("# name: Q :one\nSELECT * FROM users WHERE id = 1 OR id = 2;\n"),
+            vec!["one-not-unique"]
+        );
+        assert_eq!(
+            warnings("# name: Q :many\nSELECT count(*), max(id) FROM posts;\n"),
+            vec!["many-single-row"]
+        );
+    }
+
+    #[test]
+    fn test_unique_name_no_conflict() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_name("id", "users", &mut used), "id");
+        assert!(used.contains("id"));
+    }
+
+    #[test]
+    fn test_unique_name_with_conflicts() {
+        let mut used = HashSet::new();
+        used.insert("id".to_string());
+        assert_eq!(unique_name("id", "orders", &mut used), "orders_id_1");
+        assert_eq!(unique_name("id", "orders", &mut used), "orders_id_2");
+        assert!(used.contains("orders_id_1"));
+    }
+}
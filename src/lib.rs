@@ -1,9 +1,64 @@
+//! Stratus: TypeSQL code generation and schema migrations.
+//!
+//! The library reports what it does through `tracing` spans: `introspect`,
+//! `diff`, `apply` (with one `migration` span per migration) and `codegen`,
+//! carrying counts and `elapsed_ms` as fields. It never installs a subscriber;
+//! applications choose their own, e.g. `tracing_subscriber::fmt().init()`.
+//!
+//! Parsing, analysis and code generation build without a database driver;
+//! `data`, `db`, `migrate`, `engine`, `notify`, `policy`, `registry`,
+//! `snapshot`, `stats` and `studio` need the `db` feature, and the `stratus` binary the `cli`
+//! feature (the default).
+//! The `testing` feature adds a Docker-backed test database.
+
+pub mod advise;
+pub mod analyze;
 pub mod ast;
+pub mod audit;
 pub mod codegen;
 pub mod config;
+#[cfg(feature = "db")]
+pub mod data;
+#[cfg(feature = "db")]
 pub mod db;
+pub mod diagnostics;
+pub mod dialect;
+pub mod diff;
+#[cfg(feature = "db")]
+pub mod engine;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod hooks;
+#[cfg(feature = "db")]
+pub mod import;
+pub mod infer;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "db")]
 pub mod migrate;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(feature = "db")]
+pub mod notify;
+pub mod output;
 pub mod parser;
+#[cfg(feature = "db")]
+pub mod policy;
+#[cfg(feature = "db")]
+pub mod registry;
 pub mod schema;
+#[cfg(feature = "db")]
+pub mod snapshot;
+pub mod sql;
+#[cfg(feature = "db")]
+pub mod stats;
+#[cfg(feature = "db")]
+pub mod studio;
+#[cfg(feature = "testing")]
+pub mod testing;
 #[cfg(feature = "wasm")]
 pub mod wasm;
+
+pub use error::{ErrorKind, StratusError};
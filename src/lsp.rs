@@ -0,0 +1,931 @@
+/**
+ * Stratus Language Server Module
+ *
+ * A TypeSQL language server over stdio: open query files are parsed and
+ * checked against schema.json as they change, and the parser and analyzer
+ * diagnostics are published to the editor. Table and column names from the
+ * schema are offered as completions, and hovering a query name or column
+ * shows the types that `generate` would emit for it. Go to definition
+ * jumps from a table or column name to where schema.json defines it, and
+ * semantic tokens let editors highlight headers, keywords, parameters and
+ * table names without a generic SQL grammar.
+ *
+ * schema.json is loaded once and reloaded when the editor reports that it
+ * changed, through a file watcher registered with the client.
+ */
+use crate::analyze::{table_ref, TABLE_KEYWORDS};
+use crate::diagnostics::Severity;
+use crate::format::{tokenize, Token, TokenKind, KEYWORDS};
+use crate::infer::{resolve, table_refs};
+use crate::schema::{Schema, SourcePositions};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+    DidChangeWatchedFilesRegistrationOptions, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, FileSystemWatcher, GlobPattern, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    InitializeParams, InitializeResult, InitializedParams, Location, MarkupContent, MarkupKind,
+    MessageType, NumberOrString, OneOf, Position, Range, Registration, SemanticToken,
+    SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, ServerInfo, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+
+/// Run the language server on stdin/stdout until the client disconnects
+pub fn serve_stdio(schema_path: PathBuf) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let schema = RwLock::new(load_schema(&schema_path));
+        let (service, socket) = LspService::new(|client| Backend {
+            client,
+            schema_path,
+            schema,
+            documents: Mutex::default(),
+        });
+        Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
+            .serve(service)
+            .await;
+    });
+    Ok(())
+}
+
+/// schema.json as loaded, with where its tables and columns are defined
+struct LoadedSchema {
+    schema: Schema,
+    positions: SourcePositions,
+    json: String,
+    uri: Option<Url>,
+}
+
+/// schema.json, or None when it is missing or invalid
+fn load_schema(path: &std::path::Path) -> Option<LoadedSchema> {
+    let json = std::fs::read_to_string(path).ok()?;
+    let (schema, positions) = crate::schema::load_with_positions(&json).ok()?;
+    let uri = std::fs::canonicalize(path)
+        .ok()
+        .and_then(|path| Url::from_file_path(path).ok());
+    Some(LoadedSchema {
+        schema,
+        positions,
+        json,
+        uri,
+    })
+}
+
+impl LoadedSchema {
+    /// Where a table or column is defined
+    fn location(&self, target: &SchemaRef) -> Option<Location> {
+        let span = match target {
+            SchemaRef::Table(table) => self.positions.tables.get(table)?,
+            SchemaRef::Column(table, column) => self
+                .positions
+                .columns
+                .get(&(table.clone(), column.clone()))?,
+        };
+        let lines: Vec<&str> = self.json.lines().collect();
+        Some(Location::new(
+            self.uri.clone()?,
+            Range::new(
+                position(&lines, span.line, span.column),
+                position(&lines, span.end_line, span.end_column),
+            ),
+        ))
+    }
+}
+
+struct Backend {
+    client: Client,
+    schema_path: PathBuf,
+    schema: RwLock<Option<LoadedSchema>>,
+    /// Open documents: text and version
+    documents: Mutex<HashMap<Url, (String, i32)>>,
+}
+
+impl Backend {
+    async fn check(&self, uri: Url, text: String, version: i32) {
+        let found = diagnostics(
+            &text,
+            self.schema.read().unwrap().as_ref().map(|s| &s.schema),
+        );
+        self.documents
+            .lock()
+            .unwrap()
+            .insert(uri.clone(), (text, version));
+        self.client
+            .publish_diagnostics(uri, found, Some(version))
+            .await;
+    }
+
+    /// Ask the client to report changes to schema.json
+    async fn watch_schema(&self) -> std::result::Result<(), String> {
+        let path =
+            std::fs::canonicalize(&self.schema_path).unwrap_or_else(|_| self.schema_path.clone());
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String(path.to_string_lossy().into_owned()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "stratus-schema".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: Some(serde_json::to_value(options).map_err(|e| e.to_string())?),
+        };
+        self.client
+            .register_capability(vec![registration])
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::FULL,
+                )),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: SemanticTokensLegend {
+                                token_types: TOKEN_TYPES.to_vec(),
+                                token_modifiers: Vec::new(),
+                            },
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            ..SemanticTokensOptions::default()
+                        },
+                    ),
+                ),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![" ".to_string(), ".".to_string()]),
+                    ..CompletionOptions::default()
+                }),
+                ..ServerCapabilities::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "stratus".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        if let Err(e) = self.watch_schema().await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("Could not watch schema.json ({}); restart to reload it", e),
+                )
+                .await;
+        }
+        if self.schema.read().unwrap().is_none() {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "{} not found or invalid; only checking query syntax",
+                        self.schema_path.display()
+                    ),
+                )
+                .await;
+        }
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let doc = params.text_document;
+        self.check(doc.uri, doc.text, doc.version).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // Full sync: the last change holds the whole document
+        if let Some(change) = params.content_changes.into_iter().last() {
+            let doc = params.text_document;
+            self.check(doc.uri, change.text, doc.version).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.lock().unwrap().remove(&uri);
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+    }
+
+    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+        *self.schema.write().unwrap() = load_schema(&self.schema_path);
+        let open: Vec<(Url, (String, i32))> =
+            self.documents.lock().unwrap().clone().into_iter().collect();
+        for (uri, (text, version)) in open {
+            self.check(uri, text, version).await;
+        }
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let at = params.text_document_position;
+        let documents = self.documents.lock().unwrap();
+        let schema = self.schema.read().unwrap();
+        let (Some((text, _)), Some(schema)) =
+            (documents.get(&at.text_document.uri), schema.as_ref())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(CompletionResponse::Array(completions(
+            text,
+            at.position,
+            &schema.schema,
+        ))))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let at = params.text_document_position_params;
+        let documents = self.documents.lock().unwrap();
+        let Some((text, _)) = documents.get(&at.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(hover(
+            text,
+            at.position,
+            self.schema.read().unwrap().as_ref().map(|s| &s.schema),
+        ))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let documents = self.documents.lock().unwrap();
+        let Some((text, _)) = documents.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(text),
+        })))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let at = params.text_document_position_params;
+        let documents = self.documents.lock().unwrap();
+        let schema = self.schema.read().unwrap();
+        let (Some((text, _)), Some(schema)) =
+            (documents.get(&at.text_document.uri), schema.as_ref())
+        else {
+            return Ok(None);
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        Ok(schema_ref(&lines, at.position, &schema.schema)
+            .and_then(|(target, _)| schema.location(&target))
+            .map(GotoDefinitionResponse::Scalar))
+    }
+}
+
+/// Semantic token types; a token's `token_type` indexes this list
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::STRING,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::NUMBER,
+];
+
+/// A token found in a document: byte range and semantic token type
+type Found = (std::ops::Range<usize>, SemanticTokenType);
+
+/// Semantic tokens of a TypeSQL document, delta-encoded as LSP expects.
+///
+/// Headers yield the `name:` and return type directives (macro), the query
+/// name (function) and parameter names and types; SQL yields keywords, `$n`
+/// parameters, table names, strings, comments and numbers.
+fn semantic_tokens(text: &str) -> Vec<SemanticToken> {
+    let mut found: Vec<Found> = Vec::new();
+    let mut offset = 0;
+    let mut sql_start = None;
+    for line in text.split_inclusive('\n') {
+        let header = line.trim_start().starts_with('#')
+            && crate::parser::parse_header(line.trim_end()).is_some();
+        if header {
+            if let Some(start) = sql_start.take() {
+                sql_tokens(text, start..offset, &mut found);
+            }
+            header_tokens(text, offset..offset + line.len(), &mut found);
+        } else if sql_start.is_none() {
+            sql_start = Some(offset);
+        }
+        offset += line.len();
+    }
+    if let Some(start) = sql_start {
+        sql_tokens(text, start..offset, &mut found);
+    }
+
+    // Split multi-line tokens, then encode each relative to the previous one
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut data = Vec::new();
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for (range, kind) in found {
+        let token_type = TOKEN_TYPES.iter().position(|t| *t == kind).unwrap_or(0) as u32;
+        let mut start = range.start;
+        while start < range.end {
+            let line = line_starts.partition_point(|&s| s <= start) - 1;
+            let end = text[start..range.end]
+                .find('\n')
+                .map_or(range.end, |i| start + i);
+            let column = utf16_len(&text[line_starts[line]..start]);
+            let length = utf16_len(text[start..end].trim_end_matches('\r'));
+            if length > 0 {
+                let line = line as u32;
+                data.push(SemanticToken {
+                    delta_line: line - prev_line,
+                    delta_start: if line == prev_line {
+                        column - prev_start
+                    } else {
+                        column
+                    },
+                    length,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                });
+                (prev_line, prev_start) = (line, column);
+            }
+            start = end + 1;
+        }
+    }
+    data
+}
+
+/// Tokens of the header line at `range` in `text`
+fn header_tokens(text: &str, range: std::ops::Range<usize>, found: &mut Vec<Found>) {
+    let line = &text[range.clone()];
+    let tokens = tokenize(line);
+    let at = |t: &Token<'_>| {
+        let start = range.start + (t.text.as_ptr() as usize - line.as_ptr() as usize);
+        start..start + t.text.len()
+    };
+    let colon = |i: usize| tokens.get(i).is_some_and(|t| t.text == ":");
+    // Index of the query name
+    let mut name = None;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Word {
+            continue;
+        }
+        let kind = if name.is_none() && token.text == "name" && colon(i + 1) {
+            SemanticTokenType::MACRO
+        } else if name.is_none() {
+            name = Some(i);
+            SemanticTokenType::FUNCTION
+        } else if name.is_some_and(|n| n + 2 == i) && colon(i - 1) {
+            // The return type directive, `:one`, `:many`, ...
+            found.push((
+                at(&tokens[i - 1]).start..at(token).end,
+                SemanticTokenType::MACRO,
+            ));
+            continue;
+        } else if colon(i + 1) {
+            SemanticTokenType::PARAMETER
+        } else if i > 0 && colon(i - 1) {
+            SemanticTokenType::TYPE
+        } else {
+            continue;
+        };
+        found.push((at(token), kind));
+    }
+}
+
+/// Tokens of the SQL at `range` in `text`
+fn sql_tokens(text: &str, range: std::ops::Range<usize>, found: &mut Vec<Found>) {
+    let sql = &text[range.clone()];
+    let tokens = tokenize(sql);
+    let mut tables = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let lower = token.text.to_ascii_lowercase();
+        if token.kind == TokenKind::Word
+            && TABLE_KEYWORDS.contains(&lower.as_str())
+            && table_ref(&tokens, i + 1, true).is_some()
+        {
+            tables.push(i + 1);
+        }
+        let kind = match token.kind {
+            _ if tables.contains(&i) => SemanticTokenType::STRUCT,
+            TokenKind::Word if KEYWORDS.contains(&lower.as_str()) => SemanticTokenType::KEYWORD,
+            TokenKind::Word if token.text.starts_with(|c: char| c.is_ascii_digit()) => {
+                SemanticTokenType::NUMBER
+            }
+            TokenKind::LineComment => SemanticTokenType::COMMENT,
+            TokenKind::Quoted if token.text.starts_with("/*") => SemanticTokenType::COMMENT,
+            TokenKind::Quoted if !token.text.starts_with('"') => SemanticTokenType::STRING,
+            TokenKind::Other if token.text.len() > 1 && token.text.starts_with('$') => {
+                SemanticTokenType::PARAMETER
+            }
+            _ => continue,
+        };
+        let start = range.start + (token.text.as_ptr() as usize - sql.as_ptr() as usize);
+        found.push((start..start + token.text.len(), kind));
+    }
+}
+
+/// Keywords followed by a column name or expression
+const COLUMN_KEYWORDS: &[&str] = &[
+    "select",
+    "where",
+    "and",
+    "or",
+    "on",
+    "by",
+    "set",
+    "having",
+    "returning",
+    "distinct",
+];
+
+/// What the cursor is positioned to name
+#[derive(Debug, PartialEq)]
+enum Slot {
+    Table,
+    /// A column, of the table or alias before a `.` if there is one
+    Column(Option<String>),
+}
+
+/// Completions at `position`: tables after FROM/JOIN, columns after SELECT/WHERE
+fn completions(text: &str, position: Position, schema: &Schema) -> Vec<CompletionItem> {
+    let lines: Vec<&str> = text.lines().collect();
+    let line = position.line as usize;
+    let current = lines.get(line).copied().unwrap_or("");
+    let Some(sql) = sql_lines(&lines, line) else {
+        return Vec::new();
+    };
+    let mut before = lines[sql.start..line].join("\n");
+    before.push('\n');
+    before.push_str(&current[..byte_offset(current, position.character)]);
+    let block = lines[sql].join("\n");
+
+    let Some(slot) = slot(&before) else {
+        return Vec::new();
+    };
+    let tables = table_refs(&block);
+    match slot {
+        Slot::Table => {
+            let mut names: Vec<&String> = schema.tables.keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .map(|name| CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::STRUCT),
+                    detail: Some(format!(
+                        "table ({} columns)",
+                        schema.tables[name].columns.len()
+                    )),
+                    ..CompletionItem::default()
+                })
+                .collect()
+        }
+        Slot::Column(qualifier) => {
+            let mut names: Vec<&String> = match &qualifier {
+                Some(q) => resolve(q, &tables, schema).into_iter().collect(),
+                None if tables.is_empty() => schema.tables.keys().collect(),
+                None => tables.iter().map(|t| &t.name).collect(),
+            };
+            names.sort();
+            names.dedup();
+            names
+                .into_iter()
+                .filter_map(|name| schema.tables.get(name).map(|t| (name, t)))
+                .flat_map(|(name, table)| {
+                    let mut columns: Vec<_> = table.columns.iter().collect();
+                    columns.sort_by_key(|(column, _)| column.as_str());
+                    columns.into_iter().map(move |(column, c)| CompletionItem {
+                        label: column.clone(),
+                        kind: Some(CompletionItemKind::FIELD),
+                        detail: Some(format!("{}.{}: {}", name, column, c.data_type)),
+                        ..CompletionItem::default()
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// Hover at `position`: the generated types of a query name in a header, or
+/// the type of a table or column named in the SQL
+fn hover(text: &str, position: Position, schema: Option<&Schema>) -> Option<Hover> {
+    let lines: Vec<&str> = text.lines().collect();
+    let line = position.line as usize;
+    let current = *lines.get(line)?;
+    let word = word_at(current, byte_offset(current, position.character))?;
+    let name = &current[word.clone()];
+    let range = Some(Range::new(
+        Position::new(position.line, utf16_len(&current[..word.start])),
+        Position::new(position.line, utf16_len(&current[..word.end])),
+    ));
+    let markdown = |value: String| Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range,
+    };
+
+    if sql_lines(&lines, line).is_none() {
+        let mut ignored = crate::diagnostics::Diagnostics::new();
+        let block = crate::parser::query_blocks(text, &mut ignored)
+            .into_iter()
+            .find(|b| b.header_line == line + 1 && b.query.name == name)?;
+        let types = crate::codegen::ts::query_types(
+            &block.query,
+            schema,
+            &crate::codegen::GeneratorOptions::default(),
+        );
+        let mut value = format!(
+            "**{}** `:{}`\n\n```typescript\n{}```",
+            block.query.name, block.query.return_type, types
+        );
+        for param in crate::infer::infer(&block.query, schema).params {
+            if let (Some((table, column)), Some(sql_type)) = (param.column, param.sql_type) {
+                value.push_str(&format!(
+                    "\n- `${}` {}: `{}.{}` {}",
+                    param.ordinal, param.name, table, column, sql_type
+                ));
+            }
+        }
+        return Some(markdown(value));
+    }
+
+    let schema = schema?;
+    let (table, name) = match schema_ref(&lines, position, schema)?.0 {
+        SchemaRef::Table(table) => {
+            return Some(markdown(format!(
+                "table `{}` ({} columns)",
+                table,
+                schema.tables[&table].columns.len()
+            )));
+        }
+        SchemaRef::Column(table, column) => (table, column),
+    };
+    let column = &schema.tables[&table].columns[&name];
+    let ts_type = crate::codegen::ts::map_sql_type_to_ts(
+        column,
+        &crate::codegen::GeneratorOptions::default(),
+    );
+    let mut sql_type = column.data_type.clone();
+    if column.is_not_null() {
+        sql_type.push_str(" not null");
+    }
+    Some(markdown(format!(
+        "```typescript\n{}: {}\n```\n`{}.{}` {}",
+        name, ts_type, table, name, sql_type
+    )))
+}
+
+/// A schema table or column named in a query
+#[derive(Debug, PartialEq)]
+enum SchemaRef {
+    Table(String),
+    /// Table and column name
+    Column(String, String),
+}
+
+/// The schema table or column named at `position` in a query's SQL, and the range of the name
+fn schema_ref(lines: &[&str], position: Position, schema: &Schema) -> Option<(SchemaRef, Range)> {
+    let line = position.line as usize;
+    let current = *lines.get(line)?;
+    let sql = sql_lines(lines, line)?;
+    let word = word_at(current, byte_offset(current, position.character))?;
+    let range = Range::new(
+        Position::new(position.line, utf16_len(&current[..word.start])),
+        Position::new(position.line, utf16_len(&current[..word.end])),
+    );
+    let name = current[word.clone()].to_ascii_lowercase();
+    let tables = table_refs(&lines[sql].join("\n"));
+    let qualifier = current[..word.start]
+        .strip_suffix('.')
+        .and_then(|rest| word_at(rest, rest.len()).map(|q| rest[q].to_ascii_lowercase()));
+    if qualifier.is_none() {
+        if let Some(table) = resolve(&name, &tables, schema) {
+            return Some((SchemaRef::Table(table.clone()), range));
+        }
+    }
+    let candidates: Vec<&String> = match &qualifier {
+        Some(q) => resolve(q, &tables, schema).into_iter().collect(),
+        None => tables.iter().map(|t| &t.name).collect(),
+    };
+    let table = candidates.into_iter().find(|table| {
+        schema
+            .tables
+            .get(*table)
+            .is_some_and(|t| t.columns.contains_key(&name))
+    })?;
+    Some((SchemaRef::Column(table.clone(), name), range))
+}
+
+/// Lines of SQL in the query block around `line`; None on a header line.
+/// Query blocks are separated by blank lines.
+fn sql_lines(lines: &[&str], line: usize) -> Option<std::ops::Range<usize>> {
+    let start = lines[..line.min(lines.len())]
+        .iter()
+        .rposition(|l| l.trim().is_empty())
+        .map_or(0, |i| i + 1);
+    let end = lines
+        .iter()
+        .skip(line + 1)
+        .position(|l| l.trim().is_empty())
+        .map_or(lines.len(), |i| line + 1 + i);
+    let sql_start = if lines
+        .get(start)
+        .is_some_and(|l| l.trim_start().starts_with('#'))
+    {
+        start + 1
+    } else {
+        start
+    };
+    (line >= sql_start).then(|| sql_start..end.max(sql_start))
+}
+
+/// Byte range of the identifier touching byte offset `at`
+fn word_at(line: &str, at: usize) -> Option<std::ops::Range<usize>> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let start = line[..at]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map_or(at, |(i, _)| i);
+    let end = line[at..]
+        .find(|c: char| !is_word(c))
+        .map_or(line.len(), |i| at + i);
+    (start < end).then_some(start..end)
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.chars().map(char::len_utf16).sum::<usize>() as u32
+}
+
+/// What the SQL before the cursor leaves room for, ignoring a half-typed name
+fn slot(before: &str) -> Option<Slot> {
+    let mut tokens = tokenize(before);
+    let typing = before.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+    if typing && tokens.last().is_some_and(|t| t.kind == TokenKind::Word) {
+        tokens.pop();
+    }
+    let last = tokens.last()?;
+    if last.text == "." {
+        let qualifier = tokens
+            .iter()
+            .rev()
+            .nth(1)
+            .filter(|t| t.kind == TokenKind::Word)?;
+        return Some(Slot::Column(Some(qualifier.text.to_ascii_lowercase())));
+    }
+    let clause = |token: &Token<'_>| {
+        let lower = token.text.to_ascii_lowercase();
+        if TABLE_KEYWORDS.contains(&lower.as_str()) {
+            Some(Slot::Table)
+        } else if COLUMN_KEYWORDS.contains(&lower.as_str()) {
+            Some(Slot::Column(None))
+        } else {
+            None
+        }
+    };
+    match last.kind {
+        TokenKind::Word => clause(last),
+        // After a comma or operator, the nearest clause keyword decides
+        TokenKind::Other if last.text != ")" => tokens
+            .iter()
+            .rev()
+            .filter(|t| t.kind == TokenKind::Word)
+            .find_map(clause),
+        _ => None,
+    }
+}
+
+/// Byte offset in `line` of a UTF-16 column
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character as usize {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Diagnostics of a TypeSQL document, in LSP form
+fn diagnostics(text: &str, schema: Option<&Schema>) -> Vec<Diagnostic> {
+    let found = match schema {
+        Some(schema) => crate::analyze::check_source(text, schema),
+        None => crate::parser::parse_with_diagnostics(text).1,
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    found
+        .iter()
+        .map(|d| Diagnostic {
+            range: Range::new(
+                position(&lines, d.span.line, d.span.column),
+                position(&lines, d.span.end_line, d.span.end_column),
+            ),
+            severity: Some(match d.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Info => DiagnosticSeverity::INFORMATION,
+            }),
+            code: Some(NumberOrString::String(d.code.to_string())),
+            source: Some("stratus".to_string()),
+            message: d.message.clone(),
+            ..Diagnostic::default()
+        })
+        .collect()
+}
+
+/// LSP position (0-based, UTF-16 columns) of a 1-based line and character column
+fn position(lines: &[&str], line: usize, column: usize) -> Position {
+    let text = lines.get(line.saturating_sub(1)).copied().unwrap_or("");
+    let character: usize = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum();
+    Position::new(line.saturating_sub(1) as u32, character as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics_use_lsp_positions() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {"id": {"name": "id", "type": "bigint"}}}}}"#,
+        )
+        .unwrap();
+        let text = "# name: GetUser :one id: number\nSELECT '😀', id FROM user WHERE id = $1;\n";
+
+        let found = diagnostics(text, Some(&schema));
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].range,
+            Range::new(Position::new(1, 21), Position::new(1, 25))
+        );
+        assert_eq!(found[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            found[0].code,
+            Some(NumberOrString::String("unknown-table".to_string()))
+        );
+
+        let found = diagnostics("SELECT 1;\n", None);
+        assert_eq!(
+            found[0].code,
+            Some(NumberOrString::String("invalid-header".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_completions_follow_the_clause() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {
+                "users": {"columns": {"id": {"name": "id", "type": "bigint"}, "email": {"name": "email", "type": "text"}}},
+                "posts": {"columns": {"id": {"name": "id", "type": "bigint"}, "title": {"name": "title", "type": "text"}}}
+            }}"#,
+        )
+        .unwrap();
+        let labels = |text: &str, line: u32, character: u32| -> Vec<String> {
+            completions(text, Position::new(line, character), &schema)
+                .into_iter()
+                .map(|item| item.label)
+                .collect()
+        };
+
+        let text = "# name: GetUser :one\nSELECT  FROM users u\nWHERE u.\n";
+        assert_eq!(labels(text, 1, 7), vec!["email", "id"]);
+        assert_eq!(labels(text, 1, 13), vec!["posts", "users"]);
+        assert_eq!(labels(text, 1, 15), vec!["posts", "users"]);
+        assert_eq!(labels(text, 2, 8), vec!["email", "id"]);
+        assert_eq!(labels(text, 2, 5), Vec::<String>::new());
+        assert!(labels(text, 0, 20).is_empty());
+
+        let text = "# name: A :many\nSELECT p.id, \nFROM posts p JOIN users ON true;\n";
+        assert_eq!(labels(text, 1, 13), vec!["id", "title", "email", "id"]);
+    }
+
+    #[test]
+    fn test_hover_shows_generated_types() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"tables": {"users": {"columns": {
+                "id": {"name": "id", "type": "bigint", "isNotNull": true},
+                "email": {"name": "email", "type": "text"}
+            }}}}"#,
+        )
+        .unwrap();
+        let text = "# name: GetUser :one id: number\nSELECT u.email FROM users u WHERE id = $1;\n";
+        let value = |line: u32, character: u32| {
+            hover(text, Position::new(line, character), Some(&schema)).map(|h| match h.contents {
+                HoverContents::Markup(m) => m.value,
+                _ => unreachable!(),
+            })
+        };
+
+        let query = value(0, 10).unwrap();
+        assert!(query.starts_with("**GetUser** `:one`"));
+        assert!(query.contains("export interface GetUserParams {\n  id: number;\n}"));
+        assert!(query.contains("export type GetUserResult"));
+        assert!(query.ends_with("\n- `$1` id: `users.id` bigint"));
+        assert_eq!(
+            value(1, 10).unwrap(),
+            "```typescript\nemail: string\n```\n`users.email` text"
+        );
+        assert!(value(1, 35)
+            .unwrap()
+            .ends_with("`users.id` bigint not null"));
+        assert_eq!(value(1, 21).unwrap(), "table `users` (2 columns)");
+        assert_eq!(value(0, 3), None);
+        assert_eq!(
+            hover(text, Position::new(1, 10), None).map(|h| h.range),
+            None
+        );
+    }
+
+    #[test]
+    fn test_definition_points_into_schema_json() {
+        let json = "{\"tables\": {\n  \"users\": {\"columns\": {\n    \"email\": {\"name\": \"email\", \"type\": \"text\"}\n  }}\n}}\n";
+        let (schema, positions) = crate::schema::load_with_positions(json).unwrap();
+        let loaded = LoadedSchema {
+            schema,
+            positions,
+            json: json.to_string(),
+            uri: Url::parse("file:///app/schema.json").ok(),
+        };
+        let lines = ["# name: A :many", "SELECT u.email FROM users u;"];
+        let definition = |character: u32| {
+            let (target, _) = schema_ref(&lines, Position::new(1, character), &loaded.schema)?;
+            loaded.location(&target).map(|l| l.range)
+        };
+
+        assert_eq!(
+            definition(10),
+            Some(Range::new(Position::new(2, 4), Position::new(2, 11)))
+        );
+        assert_eq!(
+            definition(22),
+            Some(Range::new(Position::new(1, 2), Position::new(1, 9)))
+        );
+        assert_eq!(definition(2), None);
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let text =
+            "# name: GetUser :one id: number\nSELECT 'a\nb', 1 -- x\nFROM users WHERE id = $1;\n";
+        let tokens: Vec<(u32, u32, u32, &str)> = semantic_tokens(text)
+            .iter()
+            .map(|t| {
+                (
+                    t.delta_line,
+                    t.delta_start,
+                    t.length,
+                    TOKEN_TYPES[t.token_type as usize].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (0, 2, 4, "macro"),
+                (0, 6, 7, "function"),
+                (0, 8, 4, "macro"),
+                (0, 5, 2, "parameter"),
+                (0, 4, 6, "type"),
+                (1, 0, 6, "keyword"),
+                (0, 7, 2, "string"),
+                (1, 0, 2, "string"),
+                (0, 4, 1, "number"),
+                (0, 2, 4, "comment"),
+                (1, 0, 4, "keyword"),
+                (0, 5, 5, "struct"),
+                (0, 6, 5, "keyword"),
+                (0, 11, 2, "parameter"),
+            ]
+        );
+    }
+}
@@ -1,24 +1,44 @@
 use clap::{Parser, Subcommand};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use stratus::output::Mark;
 
 #[derive(Parser, Debug)]
 #[command(name = "stratus")]
 #[command(author = "Stratus Team")]
 #[command(version = "0.1.0")]
 #[command(about = "Multi-language TypeSQL compiler and database toolkit", long_about = None)]
+#[command(after_help = EXIT_CODES_HELP)]
 struct Args {
+    /// Log more: -v shows phase timings, -vv every SQL statement (STRATUS_LOG overrides)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Disable colored output (NO_COLOR is also honored)
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Use plain ASCII output without emoji, e.g. for CI logs
+    #[arg(long, global = true)]
+    ascii: bool,
+    /// Path to stratus.json (default: searched for upward from the current directory)
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Introspect the database even when the datasource enables introspectionCache (STRATUS_NO_CACHE also works)
+    #[arg(long, global = true)]
+    no_cache: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Generate code from TypeSQL queries
+    /// Generate code from TypeSQL queries (runs all configured generators without --input)
     #[command(name = "generate")]
     Generate {
         #[arg(short, long)]
-        input: PathBuf,
+        input: Option<PathBuf>,
         #[arg(short, long)]
         output: Option<PathBuf>,
         #[arg(short, long, default_value = "ts")]
@@ -34,6 +54,116 @@ enum Commands {
         input: PathBuf,
     },
 
+    /// Format TypeSQL query files
+    #[command(name = "fmt")]
+    Fmt {
+        /// Files to format (defaults to the query files in stratus.json)
+        files: Vec<PathBuf>,
+        /// Report files that need formatting without rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Run the TypeSQL language server on stdio, for editors
+    #[command(name = "lsp")]
+    Lsp {
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+    },
+
+    /// Show the query plan of a named TypeSQL query
+    #[command(name = "explain")]
+    Explain {
+        /// Query name, as in `# name: GetUser`
+        query: String,
+        /// Run the query and report actual timings (changes are rolled back)
+        #[arg(long)]
+        analyze: bool,
+        /// Parameter value as NAME=VALUE (defaults to a sample value for its type)
+        #[arg(short, long = "param", value_name = "NAME=VALUE")]
+        params: Vec<String>,
+        /// TypeSQL file to search (defaults to the query files in stratus.json)
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+
+    /// Report which named TypeSQL queries are slow or unused, from pg_stat_statements
+    Stats {
+        /// Mean execution time in milliseconds from which a query counts as slow
+        #[arg(long, default_value_t = 100.0)]
+        slow_ms: f64,
+        /// Output format: text or json
+        #[arg(long, default_value = "text")]
+        format: String,
+        /// TypeSQL file to read (defaults to the query files in stratus.json)
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+
+    /// Suggest indexes for the columns TypeSQL queries filter, join and sort on
+    Advise {
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// TypeSQL file to read (defaults to the query files in stratus.json)
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// Write the CREATE INDEX statements to this file, e.g. a migration
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Output format: text or json
+        #[arg(long, default_value = "text")]
+        format: String,
+    },
+
+    /// Turn the SQL an application already runs into named TypeSQL query stubs
+    #[command(name = "import-queries")]
+    ImportQueries {
+        /// Where statements come from: pg_stat_statements, or a SQL or database log file
+        #[arg(long)]
+        from: String,
+        /// TypeSQL file to append the stubs to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Path to schema.json, to tell single-row queries and name parameters
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Leave out statements run fewer times
+        #[arg(long, default_value_t = 1)]
+        min_calls: u64,
+        /// Import at most this many of the most frequently run statements
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+
     /// Generate types from schema only
     #[command(name = "gen-types")]
     GenTypes {
@@ -56,6 +186,81 @@ enum Commands {
         schema: Option<PathBuf>,
     },
 
+    /// CI gate: validate schema and queries, and check generated code, migration history and drift
+    #[command(name = "check")]
+    Check {
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Datasource for the migration and drift checks
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+        /// Skip the checks that need a database
+        #[arg(long)]
+        offline: bool,
+        /// Also audit SQL escaping: queries that splice text into SQL instead
+        /// of using parameters, and schema.json names DDL writes unquoted
+        #[arg(long)]
+        audit: bool,
+    },
+
+    /// Check config, database, schema, migrations and generated code
+    #[command(name = "doctor")]
+    Doctor {
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+
+    /// Watch schema.json and query files, re-running diff and codegen on change
+    #[command(name = "dev")]
+    Dev {
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Apply non-destructive schema changes to the dev database
+        #[arg(long)]
+        apply: bool,
+        /// Database connection string (overrides datasource)
+        #[arg(long)]
+        url: Option<String>,
+        /// Datasource name from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose datasource is used
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+
+    /// Browse schema.json and the live database in a local web UI
+    #[command(name = "studio")]
+    Studio {
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 5555)]
+        port: u16,
+        /// Address to bind
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Database URL (overrides datasource)
+        #[arg(long)]
+        url: Option<String>,
+        /// Datasource name from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose datasource is browsed
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+
     /// Initialize stratus configuration
     #[command(name = "init")]
     Init {
@@ -68,6 +273,17 @@ enum Commands {
         /// Output path for stratus.json
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// Adopt an existing database: write schema.json from it and record a
+        /// baseline migration as applied
+        #[arg(long, requires = "url")]
+        from_db: bool,
+    },
+
+    /// Inspect and edit stratus.json
+    #[command(name = "config")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
 
     /// Sync schema to database and create migration
@@ -85,6 +301,22 @@ enum Commands {
         /// Skip applying to database (generate only)
         #[arg(long)]
         dry_run: bool,
+        /// Print the SQL the migration would contain and exit without writing anything
+        #[arg(long)]
+        print_sql_only: bool,
+        /// Reorder, exclude or annotate the plan's operations before the
+        /// migration is written (recorded in meta.json)
+        #[arg(short, long)]
+        interactive: bool,
+        /// Target environment from stratus.json
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
+        /// Skip confirmation
+        #[arg(long)]
+        yes: bool,
+        /// Apply even when the environment's policies refuse the SQL
+        #[arg(long)]
+        override_policy: bool,
         /// Target datasource from stratus.json
         #[arg(short, long)]
         datasource: Option<String>,
@@ -106,12 +338,18 @@ enum Commands {
         /// Skip confirmation
         #[arg(long)]
         yes: bool,
+        /// Apply even when the environment's policies refuse the SQL
+        #[arg(long)]
+        override_policy: bool,
         /// Target datasource from stratus.json
         #[arg(short, long)]
         datasource: Option<String>,
         /// Database connection string (overrides stratus.json)
         #[arg(short, long)]
         url: Option<String>,
+        /// Targets of a multi-target datasource deployed at the same time
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: Option<u64>,
     },
 
     /// ==================== Database Commands ====================
@@ -129,9 +367,109 @@ enum Commands {
         #[command(subcommand)]
         command: MigrateCommands,
     },
+
+    /// Publish and fetch schema.json versions in a schema registry
+    #[command(name = "schema")]
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SchemaCommands {
+    /// Publish schema.json as a new version
+    #[command(name = "push")]
+    Push {
+        /// Version to publish, e.g. 1.4.0
+        #[arg(long)]
+        version: String,
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Registry URL (overrides stratus.json)
+        #[arg(long)]
+        registry: Option<String>,
+        /// Schema name in the registry (overrides stratus.json)
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Fetch a published version into schema.json
+    #[command(name = "pull")]
+    Pull {
+        /// Version to fetch (default: the pinned version, else latest)
+        #[arg(long)]
+        version: Option<String>,
+        /// Output path (default: the schema path)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Registry URL (overrides stratus.json)
+        #[arg(long)]
+        registry: Option<String>,
+        /// Schema name in the registry (overrides stratus.json)
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// List the schema.json snapshots saved by sync and deploy, newest first
+    #[command(name = "log")]
+    Log,
+
+    /// Diff schema.json against a snapshot, without a database
+    #[command(name = "diff")]
+    Diff {
+        /// Snapshot timestamp, or a date or time: the latest snapshot taken by then is used
+        #[arg(long, value_name = "TIME")]
+        at: String,
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Output format: text, sql or json (the operation plan)
+        #[arg(long, default_value = "text")]
+        format: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the configuration
+    #[command(name = "show")]
+    Show {
+        /// Print the fully-resolved configuration for a datasource/env
+        #[arg(long)]
+        resolve: bool,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Target environment from stratus.json
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
+    },
+
+    /// Print a single value (e.g. datasources.primary.url)
+    #[command(name = "get")]
+    Get {
+        /// Dotted key
+        key: String,
+    },
+
+    /// Set a single value and save stratus.json
+    #[command(name = "set")]
+    Set {
+        /// Dotted key
+        key: String,
+        /// New value (parsed as JSON, otherwise stored as a string)
+        value: String,
+    },
+
+    /// Check stratus.json for unknown keys and invalid values
+    #[command(name = "validate")]
+    Validate,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum DbCommands {
     /// Push schema state to database (prototype mode)
     #[command(name = "push")]
@@ -148,9 +486,24 @@ enum DbCommands {
         /// Force reset database
         #[arg(long)]
         force_reset: bool,
+        /// Print the DDL that would be executed and exit
+        #[arg(long, conflicts_with = "force_reset")]
+        print_sql_only: bool,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Target environment from stratus.json
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
         /// Database connection string
         #[arg(short, long)]
         url: Option<String>,
+        /// Skip confirmation prompts
+        #[arg(long)]
+        yes: bool,
+        /// Apply even when the environment's policies refuse the SQL
+        #[arg(long)]
+        override_policy: bool,
     },
 
     /// Pull schema from database to schema.json
@@ -159,13 +512,115 @@ enum DbCommands {
         /// Output path for schema.json
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// Source datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose datasource and connection settings are used
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
+        /// Database connection string
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+
+    /// Load rows from a CSV or JSON file into a table
+    #[command(name = "load")]
+    DbLoad {
+        /// Table to load into
+        #[arg(short, long)]
+        table: String,
+        /// Data file (.csv, .json, .jsonl, or binary COPY data as .pgcopy);
+        /// headers name the columns
+        #[arg(short, long)]
+        file: PathBuf,
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Target environment from stratus.json
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
+        /// Database connection string
+        #[arg(short, long)]
+        url: Option<String>,
+        /// Rows per COPY
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+    },
+
+    /// Export a table's rows as CSV or JSON lines
+    #[command(name = "export")]
+    DbExport {
+        /// Table to export
+        #[arg(short, long)]
+        table: String,
+        /// Output format: csv, jsonl or binary (Postgres COPY format)
+        #[arg(long, default_value = "csv")]
+        format: String,
+        /// SQL condition selecting the rows to export
+        #[arg(short, long = "where", value_name = "CONDITION")]
+        filter: Option<String>,
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Target environment from stratus.json
+        #[arg(short, long, value_name = "ENV")]
+        env: Option<String>,
         /// Database connection string
         #[arg(short, long)]
         url: Option<String>,
+        /// Rows fetched per round trip
+        #[arg(long, default_value_t = 1000)]
+        fetch_size: usize,
+    },
+
+    /// Copy a masked sample of another database's rows into this one
+    #[command(name = "sample")]
+    DbSample {
+        /// Source environment from stratus.json
+        #[arg(
+            long,
+            value_name = "ENV",
+            required_unless_present_any = ["from_url", "from_datasource"]
+        )]
+        from: Option<String>,
+        /// Source datasource from stratus.json
+        #[arg(long)]
+        from_datasource: Option<String>,
+        /// Source database connection string
+        #[arg(long)]
+        from_url: Option<String>,
+        /// Target environment from stratus.json (default: the default datasource)
+        #[arg(long, value_name = "ENV")]
+        to: Option<String>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Target database connection string
+        #[arg(short, long)]
+        url: Option<String>,
+        /// Path to schema.json
+        #[arg(short, long)]
+        schema: Option<PathBuf>,
+        /// Empty the sampled tables in the target first
+        #[arg(long)]
+        truncate: bool,
+        /// Skip confirmation prompts
+        #[arg(long)]
+        yes: bool,
     },
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum MigrateCommands {
     /// Create and apply migrations during development
     #[command(name = "dev")]
@@ -182,17 +637,33 @@ enum MigrateCommands {
         /// Create empty migration (no schema changes)
         #[arg(long)]
         create_only: bool,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
         /// Database connection string
         #[arg(short, long)]
         url: Option<String>,
+        /// Shadow database used to replay migrations (overrides stratus.json)
+        #[arg(long)]
+        shadow_database_url: Option<String>,
     },
 
-    /// Apply pending migrations to database
+    /// Apply pending migrations to database (exits 7 when applied migrations
+    /// were edited or removed)
     #[command(name = "deploy")]
     MigrateDeploy {
         /// Path to schema.json
         #[arg(short, long)]
         schema: Option<PathBuf>,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings and policies are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Apply even when the environment's policies refuse the SQL
+        #[arg(long)]
+        override_policy: bool,
         /// Database connection string
         #[arg(short, long)]
         url: Option<String>,
@@ -204,61 +675,1926 @@ enum MigrateCommands {
         /// Path to schema.json
         #[arg(short, long)]
         schema: Option<PathBuf>,
-        /// Skip confirmation
-        #[arg(long)]
+        /// Skip confirmation, unless the environment requires it
+        #[arg(long, visible_alias = "yes")]
         force: bool,
         /// Skip seed
         #[arg(long)]
         skip_seed: bool,
+        /// Target datasource from stratus.json
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose datasource, connection settings, seed and
+        /// policies are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Reset even when the environment's policies refuse the drops
+        #[arg(long)]
+        override_policy: bool,
         /// Database connection string
         #[arg(short, long)]
         url: Option<String>,
     },
 
-    /// Check migration status
+    /// Check migration status (exits 3 when migrations are pending)
     #[command(name = "status")]
     MigrateStatus {
         /// Path to schema.json
         #[arg(short, long)]
         schema: Option<PathBuf>,
+        /// Datasource whose migrations table is read
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+
+    /// Check this branch's migrations against a base ref before merging
+    /// (exits 7 on conflicts)
+    #[command(name = "check")]
+    MigrateCheck {
+        /// Git ref the branch will merge into
+        #[arg(long, default_value = "origin/main")]
+        base: String,
     },
 
     /// Show the difference between two schemas
     #[command(name = "diff")]
     MigrateDiff {
-        /// From schema (current database or file)
+        /// From schema: `db` for the current database (default), or a file
         #[arg(short, long, value_name = "SCHEMA")]
         from: Option<String>,
-        /// To schema (target schema file)
+        /// To schema (default: the project's schema.json)
         #[arg(short, long, value_name = "SCHEMA")]
         to: Option<PathBuf>,
-        /// Database connection string
+        /// Output format: text, sql or json (the operation plan)
+        #[arg(long, default_value = "text")]
+        format: String,
+        /// Datasource introspected for `--from db`
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings and policies are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string for `--from db` (overrides stratus.json)
         #[arg(short, long)]
         url: Option<String>,
-        /// Save to migration file
+        /// Write the diff as a new migration directory
         #[arg(long)]
         save: bool,
-        /// Migration name
+        /// Name of the saved migration (default: derived from the changes)
         #[arg(short, long)]
         name: Option<String>,
+        /// Show the diff even when the environment's policies refuse its SQL
+        #[arg(long)]
+        override_policy: bool,
     },
 
-    /// Resolve migration issues
+    /// Resolve migration issues after a failed deploy, without running any SQL
     #[command(name = "resolve")]
     MigrateResolve {
-        /// Issue to resolve
+        /// `applied`: record the migration as applied (finished by hand);
+        /// `rolled-back`: forget it so the next deploy runs it again
         #[arg(short, long)]
         issue: String,
         /// Migration ID
         #[arg(short, long)]
         migration: Option<String>,
-    },
-}
-
-fn main() {
-    let args = Args::parse();
-
-    match args.command {
+        /// Datasource whose migrations table is updated
+        #[arg(short, long)]
+        datasource: Option<String>,
+        /// Environment whose connection settings are used
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Database connection string (overrides stratus.json)
+        #[arg(short, long)]
+        url: Option<String>,
+    },
+}
+
+// ==================== Errors ====================
+
+/// Exit codes, stable across releases (sysexits.h values where one fits)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    /// Command failed (SQL error, failed migration, failing hook)
+    Failure = 1,
+    /// The database differs from schema.json (`stratus check`)
+    Drift = 2,
+    /// Migrations are waiting to be applied (`stratus migrate status`)
+    Pending = 3,
+    /// schema.json is invalid (`stratus check`)
+    InvalidSchema = 4,
+    /// A query references tables or columns missing from the schema (`stratus check`)
+    InvalidQueries = 5,
+    /// Generated code is missing or out of date (`stratus check`)
+    StaleCode = 6,
+    /// Applied migrations are missing locally or were edited (`stratus check`, `migrate status`),
+    /// or conflict with the base branch (`migrate check`)
+    MigrationMismatch = 7,
+    /// The environment's policies refuse the change (sync, deploy, db push,
+    /// migrate diff, migrate reset)
+    Policy = 8,
+    /// Invalid arguments, or a required flag such as --yes is missing
+    Usage = 64,
+    /// An input file is malformed (schema.json, TypeSQL, migrations)
+    DataErr = 65,
+    /// An input file does not exist or cannot be read
+    NoInput = 66,
+    /// The database cannot be reached
+    Unavailable = 69,
+    /// An output file cannot be written
+    CantCreate = 73,
+    /// stratus.json is missing or invalid
+    Config = 78,
+}
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0   success
+  1   command failed (SQL error, failed migration, failing hook)
+  2   drift: database differs from schema.json (check)
+  3   pending migrations exist (migrate status)
+  4   schema.json invalid, or has names DDL emits unquoted (check --audit)
+  5   query references unknown tables or columns, or splices text into
+      SQL (check --audit)
+  6   generated code missing or stale (check)
+  7   applied migrations missing locally or edited (check, migrate status),
+      or migrations conflict with the base branch (migrate check)
+  8   environment policies refuse the change (sync, deploy, db push,
+      migrate diff, migrate reset)
+  64  invalid arguments or missing confirmation flag
+  65  malformed input file (schema.json, TypeSQL, migrations)
+  66  input file not found or unreadable
+  69  database unreachable
+  73  output file cannot be written
+  78  stratus.json missing or invalid
+`stratus check` exits with the code of its first failing check, in the order
+schema, queries, generated code, migrations, drift.";
+
+/// A user-facing error: what went wrong, how to fix it, and the exit code
+#[derive(Debug)]
+struct CliError {
+    code: ExitCode,
+    message: String,
+    hint: Option<String>,
+}
+
+type CliResult<T = ()> = Result<T, CliError>;
+
+impl CliError {
+    fn new(code: ExitCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    fn report(&self) {
+        eprintln!("Error: {}", self.message);
+        if let Some(hint) = &self.hint {
+            eprintln!("  hint: {}", hint);
+        }
+    }
+}
+
+impl From<stratus::config::ConfigError> for CliError {
+    fn from(e: stratus::config::ConfigError) -> Self {
+        config_error(e, false)
+    }
+}
+
+/// A stratus.json error; while `validating`, the error is already the
+/// details `config validate` would give, so it does not point there
+fn config_error(e: stratus::config::ConfigError, validating: bool) -> CliError {
+    use stratus::config::ConfigError;
+    let hint = match &e {
+        ConfigError::NotFound(_) => Some("Run `stratus init` to create stratus.json"),
+        ConfigError::ParseError(_) | ConfigError::InvalidConfig(_) if !validating => {
+            Some("Run `stratus config validate` for details")
+        }
+        ConfigError::DatasourceNotFound(_) => {
+            Some("List datasources with `stratus config get datasources`")
+        }
+        ConfigError::DatasourceRequired => {
+            return CliError::new(ExitCode::Usage, e.to_string())
+                .with_hint("Pass --datasource or --env");
+        }
+        _ => None,
+    };
+    let err = CliError::new(ExitCode::Config, e.to_string());
+    match hint {
+        Some(hint) => err.with_hint(hint),
+        None => err,
+    }
+}
+
+impl From<stratus::db::DbError> for CliError {
+    fn from(e: stratus::db::DbError) -> Self {
+        match e {
+            stratus::db::DbError::Connection(_) => Self::new(ExitCode::Unavailable, e.to_string())
+                .with_hint("Check the database URL and that the server is running"),
+            _ => Self::new(ExitCode::Failure, e.to_string()),
+        }
+    }
+}
+
+impl From<stratus::engine::EngineError> for CliError {
+    fn from(e: stratus::engine::EngineError) -> Self {
+        use stratus::engine::EngineError;
+        let message = e.to_string();
+        match e {
+            EngineError::Config(e) => e.into(),
+            EngineError::Db(e) => e.into(),
+            EngineError::NoConfig => Self::new(ExitCode::Config, message)
+                .with_hint("Pass --url, or run `stratus init` to create stratus.json"),
+            EngineError::DatasourceRequired => {
+                Self::new(ExitCode::Usage, message).with_hint("Pass --datasource or --env")
+            }
+            EngineError::MissingUrl => missing_url(),
+            EngineError::Read { source, .. } => {
+                let err = Self::new(ExitCode::NoInput, message);
+                if source.kind() == std::io::ErrorKind::NotFound {
+                    err.with_hint("Check the path, or set it in stratus.json")
+                } else {
+                    err
+                }
+            }
+            EngineError::InvalidSchema { path, .. } => Self::new(ExitCode::DataErr, message)
+                .with_hint(format!(
+                    "Run `stratus validate --schema {}` for details",
+                    path.display()
+                )),
+            EngineError::Parse { .. } => Self::new(ExitCode::DataErr, message),
+            EngineError::Migrate(e) => e.into(),
+            EngineError::Write { .. } => Self::new(ExitCode::CantCreate, message),
+            EngineError::OutputLocked { .. } => Self::new(ExitCode::CantCreate, message)
+                .with_hint("Wait for it to finish (or stop `stratus dev`), then run again"),
+            EngineError::MigrationFailed { applied, .. } => Self::new(ExitCode::Failure, message)
+                .with_hint(format!(
+                    "{} earlier migration(s) were applied; fix the SQL and re-run",
+                    applied
+                )),
+            EngineError::NoGenerators => Self::new(ExitCode::Config, message)
+                .with_hint("Pass --input, or add a `generators` section"),
+            EngineError::Introspect(_) => Self::new(ExitCode::Failure, message),
+            EngineError::Policy { .. } => Self::new(ExitCode::Policy, message)
+                .with_hint("Re-run with --override-policy to apply it anyway"),
+            EngineError::ResetForbidden(_) => Self::new(ExitCode::Usage, message),
+            EngineError::Shadow(_) => Self::new(ExitCode::DataErr, message).with_hint(
+                "Check the shadow database URL; the migration history must apply cleanly to an empty database",
+            ),
+            EngineError::StatementFailed { step, .. } => Self::new(ExitCode::Failure, message)
+                .with_hint(format!(
+                    "No changes were applied; the {} was rolled back",
+                    step.to_lowercase()
+                )),
+            EngineError::HasMigrations(_) => Self::new(ExitCode::CantCreate, message)
+                .with_hint("It is already managed by Stratus; run `stratus init` without --from-db and add its migrations"),
+        }
+    }
+}
+
+impl From<stratus::stats::StatsError> for CliError {
+    fn from(e: stratus::stats::StatsError) -> Self {
+        match e {
+            stratus::stats::StatsError::NotInstalled => Self::new(ExitCode::Failure, e.to_string())
+                .with_hint(
+                    "Add pg_stat_statements to shared_preload_libraries, restart, \
+                     then run CREATE EXTENSION pg_stat_statements",
+                ),
+            stratus::stats::StatsError::Db(e) => e.into(),
+        }
+    }
+}
+
+impl From<stratus::snapshot::SnapshotError> for CliError {
+    fn from(e: stratus::snapshot::SnapshotError) -> Self {
+        use stratus::snapshot::SnapshotError;
+        let message = e.to_string();
+        match e {
+            SnapshotError::Read { .. } => Self::new(ExitCode::NoInput, message),
+            SnapshotError::Write { .. } => Self::new(ExitCode::CantCreate, message),
+            SnapshotError::Invalid { .. } => Self::new(ExitCode::DataErr, message),
+            SnapshotError::InvalidTime(_) => Self::new(ExitCode::Usage, message),
+            SnapshotError::NotFound(_) => Self::new(ExitCode::NoInput, message)
+                .with_hint("Run `stratus schema log` to list the snapshots"),
+        }
+    }
+}
+
+impl From<stratus::registry::RegistryError> for CliError {
+    fn from(e: stratus::registry::RegistryError) -> Self {
+        use stratus::registry::RegistryError;
+        match &e {
+            RegistryError::InvalidName { .. } => Self::new(ExitCode::Usage, e.to_string()),
+            RegistryError::Exists { .. } => Self::new(ExitCode::Failure, e.to_string())
+                .with_hint("Published versions cannot change; push a new version"),
+            RegistryError::Unauthorized(_) => Self::new(ExitCode::Failure, e.to_string())
+                .with_hint(
+                    "Set the token in STRATUS_REGISTRY_TOKEN, or name its variable in registry.tokenEnv",
+                ),
+            RegistryError::Transport(_) => Self::new(ExitCode::Unavailable, e.to_string()),
+            RegistryError::InvalidSchema(_) => Self::new(ExitCode::DataErr, e.to_string()),
+            RegistryError::NotFound { .. } | RegistryError::Http { .. } => {
+                Self::new(ExitCode::Failure, e.to_string())
+            }
+        }
+    }
+}
+
+impl From<stratus::migrate::MigrateError> for CliError {
+    fn from(e: stratus::migrate::MigrateError) -> Self {
+        use stratus::migrate::MigrateError;
+        let code = match &e {
+            MigrateError::Read { .. } | MigrateError::Meta { .. } => ExitCode::DataErr,
+            MigrateError::Write { .. } => ExitCode::CantCreate,
+            MigrateError::Db { .. } => ExitCode::Failure,
+            MigrateError::History(_) => {
+                return Self::new(ExitCode::MigrationMismatch, e.to_string())
+                    .with_hint("Restore the missing or edited migration files")
+            }
+            MigrateError::Git(_) => ExitCode::Failure,
+            MigrateError::Resolve(_) => ExitCode::Usage,
+        };
+        Self::new(code, e.to_string())
+    }
+}
+
+/// Read an input file
+fn read_input(path: &Path) -> CliResult<String> {
+    fs::read_to_string(path).map_err(|e| {
+        let err = CliError::new(
+            ExitCode::NoInput,
+            format!("Cannot read {}: {}", path.display(), e),
+        );
+        if e.kind() == std::io::ErrorKind::NotFound {
+            err.with_hint("Check the path, or set it in stratus.json")
+        } else {
+            err
+        }
+    })
+}
+
+/// Read and parse a TypeSQL file
+fn read_queries(path: &Path) -> CliResult<stratus::ast::QueryFile> {
+    Ok(stratus::engine::read_queries(path)?)
+}
+
+/// Load the migrations directory
+fn read_migrations(dir: &Path) -> CliResult<Vec<stratus::migrate::Migration>> {
+    Ok(stratus::migrate::read_migrations(&dir.to_path_buf())?)
+}
+
+/// Write an output file, creating parent directories
+fn write_output(path: &Path, contents: &str) -> CliResult {
+    Ok(stratus::engine::write_output(path, contents)?)
+}
+
+/// Registry client and schema name from the flags, else stratus.json
+fn registry_client(
+    config: Option<&stratus::config::ConfigManager>,
+    registry: Option<String>,
+    name: Option<String>,
+) -> CliResult<(stratus::registry::RegistryClient, String)> {
+    let configured = config.and_then(|c| c.registry());
+    let missing = |what: &str, key: &str| {
+        CliError::new(
+            ExitCode::Usage,
+            format!("No schema registry {} given", what),
+        )
+        .with_hint(format!(
+            "Pass --{}, or set registry.{} in stratus.json",
+            what, key
+        ))
+    };
+    let url = registry
+        .or_else(|| configured.map(|r| r.url.clone()))
+        .ok_or_else(|| missing("registry", "url"))?;
+    let name = name
+        .or_else(|| configured.map(|r| r.name.clone()))
+        .ok_or_else(|| missing("name", "name"))?;
+    let token_env = configured
+        .and_then(|r| r.token_env.as_deref())
+        .unwrap_or("STRATUS_REGISTRY_TOKEN");
+    let token = std::env::var(token_env).ok().filter(|t| !t.is_empty());
+    Ok((stratus::registry::RegistryClient::new(&url, token)?, name))
+}
+
+/// Error for an unknown `--language` value
+fn unsupported_language(language: &str, supported: &str) -> CliError {
+    CliError::new(
+        ExitCode::Usage,
+        format!("Unsupported language: {}", language),
+    )
+    .with_hint(format!("Supported languages: {}", supported))
+}
+
+/// Error for commands that need a database URL but got none
+fn missing_url() -> CliError {
+    CliError::new(ExitCode::Usage, "No database URL provided")
+        .with_hint("Pass --url, set DATABASE_URL, or configure a datasource in stratus.json")
+}
+
+/// `require, rootCert /etc/ca.pem` for `config show --resolve`
+fn describe_ssl(ssl: &stratus::config::SslConfig) -> String {
+    let mut parts = Vec::new();
+    if let Some(mode) = ssl.mode {
+        parts.push(mode.as_str().to_string());
+    }
+    for (key, path) in [
+        ("rootCert", &ssl.root_cert),
+        ("cert", &ssl.cert),
+        ("key", &ssl.key),
+    ] {
+        if let Some(path) = path {
+            parts.push(format!("{} {}", key, path));
+        }
+    }
+    if parts.is_empty() {
+        "(as the URL says)".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// `statementTimeout 5000, searchPath app, public` for `config show --resolve`
+fn describe_connection(connection: &stratus::config::ConnectionOptions) -> String {
+    let mut parts = Vec::new();
+    if let Some(seconds) = connection.connect_timeout {
+        parts.push(format!("connectTimeout {}", seconds));
+    }
+    if let Some(ms) = connection.statement_timeout {
+        parts.push(format!("statementTimeout {}", ms));
+    }
+    if let Some(name) = &connection.application_name {
+        parts.push(format!("applicationName {}", name));
+    }
+    if let Some(path) = &connection.search_path {
+        parts.push(format!("searchPath {}", path.join(", ")));
+    }
+    if parts.is_empty() {
+        "(defaults)".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Ask before a destructive step.
+///
+/// `assume_yes` answers for the user; without a terminal the answer is no, so
+/// automation must opt in with a flag.
+fn confirm(prompt: &str, assume_yes: bool) -> CliResult<bool> {
+    use std::io::IsTerminal;
+
+    if assume_yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .map_err(|e| CliError::new(ExitCode::Failure, format!("Prompt failed: {}", e)))
+}
+
+/// Let the user pick, order and annotate the operations of a sync plan.
+///
+/// Returns no decisions when the plan is kept as generated.
+fn edit_plan(
+    operations: &[stratus::db::Operation],
+) -> CliResult<Vec<stratus::migrate::PlanDecision>> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Err(
+            CliError::new(ExitCode::Usage, "--interactive needs a terminal")
+                .with_hint("Run sync without --interactive and edit up.sql instead"),
+        );
+    }
+    let prompt_failed =
+        |e: dialoguer::Error| CliError::new(ExitCode::Failure, format!("Prompt failed: {}", e));
+    let labels: Vec<String> = operations.iter().map(ToString::to_string).collect();
+
+    let included = dialoguer::MultiSelect::new()
+        .with_prompt("Operations to include (space toggles)")
+        .items(&labels)
+        .defaults(&vec![true; labels.len()])
+        .interact()
+        .map_err(prompt_failed)?;
+    let mut order = included.clone();
+    if included.len() > 1 {
+        let items: Vec<&String> = included.iter().map(|&i| &labels[i]).collect();
+        order = dialoguer::Sort::new()
+            .with_prompt("Order to run them (space picks an operation up, arrows move it)")
+            .items(&items)
+            .interact()
+            .map_err(prompt_failed)?
+            .into_iter()
+            .map(|i| included[i])
+            .collect();
+    }
+    let excluded = (0..labels.len()).filter(|i| !included.contains(i));
+    let mut decisions: Vec<stratus::migrate::PlanDecision> = order
+        .iter()
+        .map(|&i| (i, false))
+        .chain(excluded.map(|i| (i, true)))
+        .map(|(index, excluded)| stratus::migrate::PlanDecision {
+            operation: labels[index].clone(),
+            index,
+            excluded,
+            note: None,
+        })
+        .collect();
+
+    loop {
+        let mut items: Vec<String> = decisions
+            .iter()
+            .map(|d| {
+                let skip = if d.excluded { "skip " } else { "" };
+                match &d.note {
+                    Some(note) => format!("{}{}  # {}", skip, d.operation, note),
+                    None => format!("{}{}", skip, d.operation),
+                }
+            })
+            .collect();
+        items.push("Done".to_string());
+        let choice = dialoguer::Select::new()
+            .with_prompt("Annotate an operation")
+            .items(&items)
+            .default(items.len() - 1)
+            .interact()
+            .map_err(prompt_failed)?;
+        let Some(decision) = decisions.get_mut(choice) else {
+            break;
+        };
+        let note: String = dialoguer::Input::new()
+            .with_prompt("Note (empty clears it)")
+            .with_initial_text(decision.note.clone().unwrap_or_default())
+            .allow_empty(true)
+            .interact_text()
+            .map_err(prompt_failed)?;
+        decision.note = Some(note.trim().to_string()).filter(|note| !note.is_empty());
+    }
+
+    let unchanged = decisions
+        .iter()
+        .enumerate()
+        .all(|(i, d)| d.index == i && !d.excluded && d.note.is_none());
+    if unchanged {
+        decisions.clear();
+    }
+    Ok(decisions)
+}
+
+/// Report policy violations; refuse unless --override-policy was given
+fn enforce_policies(
+    violations: Vec<String>,
+    env: Option<&str>,
+    override_policy: bool,
+) -> CliResult {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let mark = if override_policy {
+        Mark::Warn
+    } else {
+        Mark::Fail
+    };
+    for violation in &violations {
+        println!("{} {}", mark, violation);
+    }
+    if override_policy {
+        println!("   Policies overridden with --override-policy");
+        println!();
+        return Ok(());
+    }
+    Err(CliError::new(
+        ExitCode::Policy,
+        format!(
+            "Environment '{}' policies refuse this change",
+            env.unwrap_or_default()
+        ),
+    )
+    .with_hint("Re-run with --override-policy to apply it anyway"))
+}
+
+/// Run the configured seed script or command against the database
+fn run_seed(
+    config: &stratus::config::ConfigManager,
+    seed: &stratus::config::SeedConfig,
+    client: &mut dyn stratus::db::DbBackend,
+    db_url: &str,
+) -> CliResult {
+    if let Some(script) = &seed.script {
+        let path = config.resolve_path(script);
+        println!("Seeding from {}...", path.display());
+        let sql = read_input(&path)?;
+        client
+            .execute(&sql)
+            .map_err(|e| CliError::new(ExitCode::Failure, format!("Seed failed: {}", e)))?;
+    } else if let Some(command) = &seed.command {
+        println!("Seeding with `{}`...", command);
+        stratus::hooks::run_hooks(
+            "seed",
+            std::slice::from_ref(command),
+            config.base_dir(),
+            &[("DATABASE_URL", db_url.to_string())],
+        )
+        .map_err(|e| CliError::new(ExitCode::Failure, e))?;
+    }
+    Ok(())
+}
+
+/// Look up the `--env` entry in stratus.json, if any
+fn resolve_environment<'a>(
+    config: Option<&'a stratus::config::ConfigManager>,
+    env: Option<&str>,
+) -> Option<&'a stratus::config::EnvironmentConfig> {
+    config.zip(env).and_then(|(cfg, e)| cfg.get_environment(e))
+}
+
+/// Resolve the datasource name from --datasource or the `--env` mapping
+fn resolve_datasource(
+    config: Option<&stratus::config::ConfigManager>,
+    datasource: Option<String>,
+    env: Option<&str>,
+) -> CliResult<Option<String>> {
+    match config {
+        Some(cfg) => Ok(cfg.resolve_datasource_name(datasource.as_deref(), env)?),
+        None => Ok(datasource),
+    }
+}
+
+/// Load stratus.json if present; an existing but invalid file is an error.
+///
+/// `path` comes from --config and must exist; without it stratus.json is
+/// searched for upward from the current directory.
+fn load_config(path: Option<&Path>) -> CliResult<Option<stratus::config::ConfigManager>> {
+    match stratus::config::ConfigManager::load(path) {
+        Ok(config) => Ok(Some(config)),
+        Err(stratus::config::ConfigError::NotFound(_)) if path.is_none() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Engine for a command with the usual --schema, --datasource, --env and --url flags
+fn engine(
+    config_file: Option<&Path>,
+    schema: Option<PathBuf>,
+    datasource: Option<String>,
+    env: Option<String>,
+    url: Option<String>,
+    no_cache: bool,
+) -> CliResult<stratus::engine::Engine> {
+    let mut builder = stratus::engine::Engine::builder().no_cache(no_cache);
+    if let Some(path) = config_file {
+        builder = builder.config_path(path);
+    }
+    if let Some(schema) = schema {
+        builder = builder.schema(schema);
+    }
+    if let Some(datasource) = datasource {
+        builder = builder.datasource(datasource);
+    }
+    if let Some(env) = env {
+        builder = builder.env(env);
+    }
+    if let Some(url) = url {
+        builder = builder.url(url);
+    }
+    Ok(builder.build()?)
+}
+
+/// schema.json path: the flag, else stratus.json's schema path, else ./schema.json
+fn resolve_schema_path(
+    config: Option<&stratus::config::ConfigManager>,
+    flag: Option<PathBuf>,
+) -> PathBuf {
+    flag.or_else(|| config.map(|cfg| cfg.get_schema_path()))
+        .unwrap_or_else(|| PathBuf::from("schema.json"))
+}
+
+/// Migrations directory from stratus.json, else ./migrations
+fn resolve_migrations_dir(config: Option<&stratus::config::ConfigManager>) -> PathBuf {
+    config
+        .map(|cfg| cfg.get_migrations_path())
+        .unwrap_or_else(|| PathBuf::from("migrations"))
+}
+
+/// Migrations table from stratus.json, or the default
+fn migrations_table(
+    config: Option<&stratus::config::ConfigManager>,
+) -> stratus::migrate::MigrationsTable {
+    config.map(|c| c.migrations_table()).unwrap_or_default()
+}
+
+/// Connect to the database
+fn connect(db_config: &stratus::db::DbConfig) -> CliResult<stratus::db::StratusClient> {
+    println!("Connecting to database...");
+    let client = stratus::db::StratusClient::connect(db_config)?;
+    println!("Connected successfully.");
+    println!();
+    Ok(client)
+}
+
+/// Connect through the client for the datasource's provider, for the
+/// commands that work on every provider
+fn connect_backend(
+    db_config: &stratus::db::DbConfig,
+) -> CliResult<Box<dyn stratus::db::DbBackend>> {
+    println!("Connecting to database...");
+    let client = stratus::db::connect(db_config)?;
+    println!("Connected successfully.");
+    println!();
+    Ok(client)
+}
+
+/// Save schema.json to the snapshot history after a sync or deploy; a
+/// failure only warns, as the database has already changed
+fn save_snapshot(engine: &stratus::engine::Engine) {
+    match engine.snapshot() {
+        Ok(Some(snapshot)) => println!("  Schema snapshot: {}", snapshot.path.display()),
+        Ok(None) => {}
+        Err(e) => eprintln!("{} Could not save a schema snapshot: {}", Mark::Warn, e),
+    }
+}
+
+/// One line for `schema log`: tables created (+), dropped (-) and changed (~)
+fn change_summary(diff: &stratus::diff::SchemaDiff) -> String {
+    let mut parts: Vec<String> = diff
+        .create_tables
+        .iter()
+        .map(|t| format!("+{}", t))
+        .collect();
+    parts.extend(diff.drop_tables.iter().map(|t| format!("-{}", t)));
+    let mut changed: Vec<&String> = diff
+        .create_columns
+        .keys()
+        .chain(diff.alter_columns.keys())
+        .chain(diff.drop_columns.keys())
+        .collect();
+    changed.sort();
+    changed.dedup();
+    parts.extend(changed.into_iter().map(|t| format!("~{}", t)));
+    if !diff.create_roles.is_empty() || !diff.grants.is_empty() || !diff.revokes.is_empty() {
+        parts.push("privileges".to_string());
+    }
+    if parts.is_empty() {
+        "no table changes".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Progress bar over SQL statements; hidden when stderr is not a terminal
+struct StatementBar(indicatif::ProgressBar);
+
+fn statement_progress(total: usize) -> StatementBar {
+    let bar = indicatif::ProgressBar::new(total as u64);
+    bar.set_style(
+        indicatif::ProgressStyle::with_template(
+            "  [{bar:30}] {pos}/{len} statements, {elapsed} elapsed, ETA {eta}  {msg}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> "),
+    );
+    StatementBar(bar)
+}
+
+/// Progress bar over copied rows, counting up when the total is unknown;
+/// hidden when stderr is not a terminal
+fn row_progress(total: Option<u64>) -> indicatif::ProgressBar {
+    let (bar, template) = match total {
+        Some(total) => (
+            indicatif::ProgressBar::new(total),
+            "  [{bar:30}] {pos}/{len} rows, {elapsed} elapsed, ETA {eta}  {msg}",
+        ),
+        None => (
+            indicatif::ProgressBar::no_length(),
+            "  {spinner} {pos} rows, {elapsed} elapsed  {msg}",
+        ),
+    };
+    bar.set_style(
+        indicatif::ProgressStyle::with_template(template)
+            .expect("valid progress template")
+            .progress_chars("=> "),
+    );
+    bar
+}
+
+/// Move a row bar to a COPY's running count
+fn copy_progress(bar: &indicatif::ProgressBar, count: stratus::db::CopyCount) {
+    bar.set_position(count.rows);
+    bar.set_message(indicatif::HumanBytes(count.bytes).to_string());
+}
+
+impl StatementBar {
+    /// Print a line above the bar, or plainly when the bar is hidden
+    fn println(&self, line: String) {
+        if self.0.is_hidden() {
+            println!("{}", line);
+        } else {
+            self.0.println(line);
+        }
+    }
+}
+
+impl stratus::engine::Progress for StatementBar {
+    fn start(&mut self, statements: usize) {
+        self.0.set_length(statements as u64);
+    }
+
+    fn statement(&mut self, summary: &str) {
+        self.0.set_message(summary.to_string());
+    }
+
+    fn statement_done(&mut self) {
+        self.0.inc(1);
+    }
+
+    fn migration_applied(
+        &mut self,
+        migration: &stratus::migrate::Migration,
+        elapsed: std::time::Duration,
+    ) {
+        self.println(format!(
+            "  [{}] {}... OK ({:.2?})",
+            migration.meta.id, migration.meta.name, elapsed
+        ));
+    }
+
+    fn migration_failed(&mut self, migration: &stratus::migrate::Migration) {
+        self.0.abandon();
+        self.println(format!(
+            "  [{}] {}... FAILED",
+            migration.meta.id, migration.meta.name
+        ));
+    }
+}
+
+/// `init --from-db`: scaffold a project around an existing database.
+///
+/// Nothing is written when stratus.json, schema.json or migrations already
+/// exist, or the database already records migrations, so an existing project
+/// is never overwritten.
+fn init_from_db(config_path: &Path, url: &str, datasource: &str) -> CliResult {
+    let planned = stratus::config::ConfigManager::with_defaults(config_path, Some(url), datasource);
+    let schema_path = planned.get_schema_path();
+    let migrations_dir = planned.get_migrations_path();
+    let existing = if config_path.exists() {
+        Some(config_path)
+    } else if schema_path.exists() {
+        Some(schema_path.as_path())
+    } else if std::fs::read_dir(&migrations_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        Some(migrations_dir.as_path())
+    } else {
+        None
+    };
+    if let Some(path) = existing {
+        return Err(CliError::new(
+            ExitCode::CantCreate,
+            format!("{} already exists", path.display()),
+        )
+        .with_hint("Run init --from-db in a new directory, or pass --output for stratus.json"));
+    }
+
+    let table = planned.migrations_table();
+    let mut client = connect_backend(&stratus::engine::connection_config(
+        Some(&planned),
+        Some(datasource),
+        None,
+        url,
+        false,
+    ))?;
+    let db_schema = stratus::engine::introspect(&mut *client, &table)?;
+
+    let migration = stratus::engine::baseline(&mut *client, &table, &migrations_dir, &db_schema)?;
+    println!(
+        "{} Recorded baseline migration {}_{} as applied",
+        Mark::Ok,
+        migration.meta.id,
+        migration.meta.name
+    );
+
+    let contents = serde_json::to_string_pretty(&db_schema.to_json_schema())
+        .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+    write_output(&schema_path, &contents)?;
+    println!(
+        "{} Wrote {} ({} table(s), {} enum(s))",
+        Mark::Ok,
+        schema_path.display(),
+        db_schema.tables.len(),
+        db_schema.enums.len()
+    );
+
+    stratus::config::ConfigManager::create_default(config_path, Some(url), datasource)
+        .map_err(|e| CliError::new(ExitCode::CantCreate, e.to_string()))?;
+    println!("{} Created stratus.json configuration", Mark::Ok);
+    println!();
+    println!("Next steps:");
+    println!("  1. Review {}", schema_path.display());
+    println!(
+        "  2. Edit it and run: stratus sync --datasource {}",
+        datasource
+    );
+    Ok(())
+}
+
+/// Run every generator configured in stratus.json
+fn generate_all(config_file: Option<&Path>, schema_override: Option<PathBuf>) -> CliResult {
+    let config = stratus::config::ConfigManager::load(config_file).map_err(|e| {
+        CliError::from(e).with_hint("Pass --input, or configure generators in stratus.json")
+    })?;
+
+    let generators = config.generators().len();
+    if generators == 0 {
+        return Err(stratus::engine::EngineError::NoGenerators.into());
+    }
+    // Queries from `queries.include`, used by generators without an `input`
+    let query_files = config
+        .config()
+        .queries
+        .is_some()
+        .then(|| config.query_files());
+
+    let mut builder = stratus::engine::Engine::builder().config(config);
+    if let Some(schema) = schema_override {
+        builder = builder.schema(schema);
+    }
+    let engine = builder.build()?;
+
+    stratus::output::header("⚙️", "Stratus Generate");
+    println!("Generators: {}", generators);
+    if let Some(query_files) = query_files {
+        println!("Query files: {}", query_files?.len());
+    }
+    println!();
+
+    let mut failed = false;
+    let mut outputs = Vec::new();
+    for output in engine.generate().run()? {
+        match output.result {
+            Ok(files) => {
+                for file in files {
+                    println!(
+                        "  {} {} -> {}",
+                        Mark::Ok,
+                        output.provider,
+                        file.path.display()
+                    );
+                    outputs.push(file.path);
+                }
+            }
+            Err(e) => {
+                eprintln!("  {} {}: {}", Mark::Fail, output.provider, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        return Err(CliError::new(
+            ExitCode::Failure,
+            "One or more generators failed",
+        ));
+    }
+
+    let output_paths = std::env::join_paths(&outputs)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    run_hooks(
+        engine.config(),
+        stratus::hooks::POST_GENERATE,
+        &[("STRATUS_OUTPUT_PATHS", output_paths)],
+    )
+}
+
+/// Run lifecycle hooks from stratus.json
+fn run_hooks(
+    config: Option<&stratus::config::ConfigManager>,
+    event: &str,
+    vars: &[(&str, String)],
+) -> CliResult {
+    let Some(cfg) = config else {
+        return Ok(());
+    };
+    let commands = if event == stratus::hooks::PRE_MIGRATE {
+        &cfg.hooks().pre_migrate
+    } else {
+        &cfg.hooks().post_generate
+    };
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    println!("Running {} hooks...", event);
+    stratus::hooks::run_hooks(event, commands, cfg.base_dir(), vars)
+        .map_err(|e| CliError::new(ExitCode::Failure, e))
+}
+
+// ==================== Fmt ====================
+
+/// Query files from stratus.json: `queries` plus generator inputs
+fn config_query_files(config: &stratus::config::ConfigManager) -> CliResult<Vec<PathBuf>> {
+    let mut files = config.query_files()?;
+    for input in config.generators().iter().filter_map(|g| g.input.as_ref()) {
+        let path = config.resolve_path(input);
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn fmt(config_file: Option<&Path>, files: Vec<PathBuf>, check: bool) -> CliResult {
+    let files = if files.is_empty() {
+        let config = load_config(config_file)?.ok_or_else(|| {
+            CliError::new(ExitCode::Usage, "No files to format")
+                .with_hint("Pass .sql files, or run from a directory with stratus.json")
+        })?;
+        config_query_files(&config)?
+    } else {
+        files
+    };
+    if files.is_empty() {
+        return Err(CliError::new(ExitCode::Usage, "No query files to format")
+            .with_hint("Set queries in stratus.json, or pass .sql files"));
+    }
+
+    let mut changed = 0;
+    for file in &files {
+        let original = read_input(file)?;
+        let formatted = stratus::format::format_typesql(&original).map_err(|e| {
+            CliError::new(
+                ExitCode::DataErr,
+                format!("Cannot format {}: {}", file.display(), e),
+            )
+        })?;
+        if formatted == original {
+            continue;
+        }
+
+        changed += 1;
+        if check {
+            println!("{} {} is not formatted", Mark::Fail, file.display());
+        } else {
+            write_output(file, &formatted)?;
+            println!("{} Formatted {}", Mark::Ok, file.display());
+        }
+    }
+
+    if check && changed > 0 {
+        return Err(CliError::new(
+            ExitCode::Failure,
+            format!("{} of {} file(s) need formatting", changed, files.len()),
+        )
+        .with_hint("Run `stratus fmt` to fix them"));
+    }
+    if changed == 0 {
+        println!("{} file(s) already formatted", files.len());
+    }
+    Ok(())
+}
+
+// ==================== Explain ====================
+
+/// Find a query by name across TypeSQL files
+fn find_query(files: &[PathBuf], name: &str) -> CliResult<(PathBuf, stratus::ast::Query)> {
+    if files.is_empty() {
+        return Err(CliError::new(ExitCode::Usage, "No query files to search")
+            .with_hint("Pass --input, or set queries in stratus.json"));
+    }
+
+    let mut names = Vec::new();
+    for file in files {
+        let parsed = read_queries(file)?;
+        if let Some(query) = parsed.queries.iter().find(|q| q.name == name) {
+            return Ok((file.clone(), query.clone()));
+        }
+        names.extend(parsed.queries.into_iter().map(|q| q.name));
+    }
+
+    let err = CliError::new(ExitCode::DataErr, format!("Query '{}' not found", name));
+    Err(
+        match stratus::config::suggest(name, names.iter().map(String::as_str)) {
+            Some(similar) => err.with_hint(format!("Did you mean '{}'?", similar)),
+            None => err.with_hint(format!("Searched {} file(s)", files.len())),
+        },
+    )
+}
+
+/// SQL literal standing in for a parameter of the given TypeSQL type
+fn sample_value(type_: &str, value: Option<&str>) -> String {
+    let quote = |v: &str| format!("'{}'", v.replace('\'', "''"));
+    match (type_.to_ascii_lowercase().as_str(), value) {
+        ("number" | "int" | "integer" | "bigint" | "float" | "decimal", Some(v)) => v.to_string(),
+        ("number" | "int" | "integer" | "bigint" | "float" | "decimal", None) => "1".to_string(),
+        ("boolean" | "bool", Some(v)) => v.to_string(),
+        ("boolean" | "bool", None) => "true".to_string(),
+        ("date" | "datetime" | "timestamp", None) => "now()".to_string(),
+        ("uuid", None) => quote("00000000-0000-0000-0000-000000000000"),
+        ("json" | "jsonb", None) => quote("{}"),
+        (_, Some(v)) => quote(v),
+        (_, None) => quote("sample"),
+    }
+}
+
+/// Replace `$n` placeholders with --param values or per-type samples
+fn bind_sample_params(
+    query: &stratus::ast::Query,
+    schema: Option<&stratus::schema::Schema>,
+    overrides: &[String],
+) -> CliResult<String> {
+    let params = stratus::infer::infer(query, schema).params;
+    let mut values = std::collections::HashMap::new();
+    for item in overrides {
+        let (name, value) = item.split_once('=').ok_or_else(|| {
+            CliError::new(
+                ExitCode::Usage,
+                format!("Invalid --param '{}': expected NAME=VALUE", item),
+            )
+        })?;
+        if !params.iter().any(|p| p.name == name) {
+            return Err(CliError::new(
+                ExitCode::Usage,
+                format!("Query '{}' has no parameter '{}'", query.name, name),
+            ));
+        }
+        values.insert(name, value);
+    }
+
+    let mut sql = String::new();
+    let mut rest = query.sql.as_str();
+    while let Some(pos) = rest.find('$') {
+        sql.push_str(&rest[..pos]);
+        let digits = rest[pos + 1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len() - pos - 1);
+        let param = rest[pos + 1..pos + 1 + digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| params.iter().find(|p| p.ordinal == n));
+        match param {
+            Some(p) => sql.push_str(&sample_value(
+                p.declared
+                    .as_deref()
+                    .or(p.sql_type.as_deref())
+                    .unwrap_or(""),
+                values.get(p.name.as_str()).copied(),
+            )),
+            None => sql.push_str(&rest[pos..pos + 1 + digits]),
+        }
+        rest = &rest[pos + 1 + digits..];
+    }
+    sql.push_str(rest);
+    Ok(sql)
+}
+
+// ==================== Doctor ====================
+
+/// Pass/fail checklist printed by `stratus doctor`
+#[derive(Default)]
+struct Checklist {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl Checklist {
+    fn pass(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("  {} {}: {}", Mark::Ok, check, detail);
+        self.passed += 1;
+    }
+
+    fn fail(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("  {} {}: {}", Mark::Fail, check, detail);
+        self.failed += 1;
+    }
+
+    fn skip(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("  {} {}: {}", Mark::Skip, check, detail);
+        self.skipped += 1;
+    }
+}
+
+fn doctor(config_file: Option<&Path>, env: Option<&str>, no_cache: bool) -> CliResult {
+    stratus::output::header("🩺", "Stratus Doctor");
+
+    let mut checks = Checklist::default();
+
+    // Config file
+    let config = match stratus::config::ConfigManager::load(config_file) {
+        Ok(cfg) => {
+            checks.pass("Config", format!("{} is valid", cfg.path().display()));
+            Some(cfg)
+        }
+        Err(stratus::config::ConfigError::NotFound(_)) if config_file.is_none() => {
+            checks.skip("Config", "no stratus.json found; using defaults");
+            None
+        }
+        Err(e) => {
+            checks.fail("Config", e);
+            None
+        }
+    };
+
+    // Datasources
+    let mut targets = Vec::new();
+    match &config {
+        Some(cfg) => {
+            for name in cfg.datasource_names() {
+                match cfg.datasource_url(name) {
+                    Ok(url) => targets.push((name.clone(), url)),
+                    Err(e) => checks.fail(&format!("Datasource '{}'", name), e),
+                }
+            }
+        }
+        None => {
+            if let Ok(url) = std::env::var("DATABASE_URL") {
+                targets.push(("DATABASE_URL".to_string(), url));
+            }
+        }
+    }
+    if targets.is_empty() && !config.as_ref().is_some_and(|cfg| cfg.has_datasources()) {
+        checks.skip("Database", "no datasources configured");
+    }
+    let mut clients = Vec::new();
+    for (name, url) in targets {
+        let check = format!("Datasource '{}'", name);
+        let datasource = config.as_ref().map(|_| name.as_str());
+        let db_config =
+            stratus::engine::connection_config(config.as_ref(), datasource, env, &url, no_cache);
+        match stratus::db::StratusClient::connect(&db_config).and_then(|mut client| {
+            client.ping()?;
+            Ok(client)
+        }) {
+            Ok(client) => {
+                checks.pass(&check, "reachable");
+                clients.push((name, client));
+            }
+            Err(e) => checks.fail(&check, e),
+        }
+    }
+
+    // Schema
+    let schema_path = resolve_schema_path(config.as_ref(), None);
+    let schema = match stratus::engine::read_schema(&schema_path) {
+        Ok(schema) => {
+            checks.pass(
+                "Schema",
+                format!("{} ({} tables)", schema_path.display(), schema.tables.len()),
+            );
+            Some(schema)
+        }
+        Err(e) => {
+            checks.fail("Schema", e.to_string());
+            None
+        }
+    };
+
+    // Migrations against each reachable database
+    let migrations_dir = resolve_migrations_dir(config.as_ref());
+    if !migrations_dir.exists() {
+        checks.skip(
+            "Migrations",
+            format!("{} does not exist yet", migrations_dir.display()),
+        );
+    } else {
+        match stratus::migrate::read_migrations(&migrations_dir) {
+            Err(e) => checks.fail("Migrations", e),
+            Ok(migrations) if clients.is_empty() => checks.skip(
+                "Migrations",
+                format!(
+                    "{} on disk; no reachable database to compare with",
+                    migrations.len()
+                ),
+            ),
+            Ok(migrations) => {
+                let table = migrations_table(config.as_ref());
+                for (name, client) in &mut clients {
+                    let check = format!("Migrations ({})", name);
+                    match check_migrations(client, &table, &migrations) {
+                        Ok(detail) => checks.pass(&check, detail),
+                        Err(e) => checks.fail(&check, e),
+                    }
+                }
+            }
+        }
+    }
+
+    // Generated code
+    match &config {
+        Some(cfg) if !cfg.generators().is_empty() => {
+            if let Err(e) = check_generated(cfg, schema.as_ref(), &mut checks) {
+                checks.fail("Generated code", e.message);
+            }
+        }
+        _ => checks.skip("Generated code", "no generators configured"),
+    }
+
+    println!();
+    println!(
+        "{} passed, {} failed, {} skipped",
+        checks.passed, checks.failed, checks.skipped
+    );
+
+    if checks.failed > 0 {
+        return Err(CliError::new(
+            ExitCode::Failure,
+            format!("{} check(s) failed", checks.failed),
+        ));
+    }
+    Ok(())
+}
+
+/// Compare the migrations directory with the database's migrations table
+fn check_migrations(
+    client: &mut stratus::db::StratusClient,
+    table: &stratus::migrate::MigrationsTable,
+    migrations: &[stratus::migrate::Migration],
+) -> Result<String, String> {
+    if !stratus::migrate::migrations_table_exists(client, table).map_err(|e| e.to_string())? {
+        return Ok(format!(
+            "{} on disk, none applied yet ({} not created)",
+            migrations.len(),
+            table.qualified_name()
+        ));
+    }
+
+    // Adds the columns newer versions record to an older table
+    stratus::migrate::ensure_migrations_table(client, table).map_err(|e| e.to_string())?;
+    let problems =
+        stratus::migrate::check_history(client, table, migrations).map_err(|e| e.to_string())?;
+    if !problems.is_empty() {
+        return Err(problems.join("; "));
+    }
+
+    let mut tracked = migrations.to_vec();
+    stratus::migrate::load_applied(client, table, &mut tracked).map_err(|e| e.to_string())?;
+    let applied = tracked.iter().filter(|m| m.applied).count();
+    Ok(format!(
+        "{} on disk, {} applied, {} pending",
+        tracked.len(),
+        applied,
+        tracked.len() - applied
+    ))
+}
+
+/// Re-run every generator in memory and compare with the files on disk
+fn check_generated(
+    config: &stratus::config::ConfigManager,
+    schema: Option<&stratus::schema::Schema>,
+    checks: &mut Checklist,
+) -> CliResult {
+    if config.generators().is_empty() {
+        return Ok(());
+    }
+    for output in stratus::engine::render_generators(config, schema)? {
+        let check = format!("Generated code ({})", output.provider);
+        let files = match output.result {
+            Ok(files) => files,
+            Err(e) => {
+                checks.fail(&check, e);
+                continue;
+            }
+        };
+        for file in files {
+            match fs::read_to_string(&file.path) {
+                Ok(existing) if existing == file.contents => {
+                    checks.pass(&check, format!("{} is up to date", file.path.display()))
+                }
+                Ok(_) => checks.fail(
+                    &check,
+                    format!("{} is stale; run `stratus generate`", file.path.display()),
+                ),
+                Err(_) => checks.fail(
+                    &check,
+                    format!("{} is missing; run `stratus generate`", file.path.display()),
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print diagnostics below a check line as `path:line:column: severity[code]: message`
+fn print_diagnostics(path: &Path, diagnostics: &stratus::diagnostics::Diagnostics) {
+    for diagnostic in diagnostics {
+        println!("      {}:{}", path.display(), diagnostic);
+    }
+}
+
+/// One-line summary of a schema diff
+fn diff_counts(diff: &stratus::db::SchemaDiff) -> String {
+    let mut counts = format!(
+        "+{} table(s), +{} column(s), ~{} table(s), -{} table(s), -{} column(s)",
+        diff.create_tables.len(),
+        diff.create_columns.values().map(Vec::len).sum::<usize>(),
+        diff.alter_tables.len(),
+        diff.drop_tables.len(),
+        diff.drop_columns.values().map(Vec::len).sum::<usize>(),
+    );
+    let foreign_keys = diff.add_foreign_keys.len() + diff.drop_foreign_keys.len();
+    if foreign_keys > 0 {
+        counts.push_str(&format!(", {} foreign key change(s)", foreign_keys));
+    }
+    let constraints = diff.add_constraints.len() + diff.drop_constraints.len();
+    if constraints > 0 {
+        counts.push_str(&format!(", {} constraint change(s)", constraints));
+    }
+    let indexes = diff.create_indexes.len() + diff.drop_indexes.len();
+    if indexes > 0 {
+        counts.push_str(&format!(", {} index change(s)", indexes));
+    }
+    let enums = diff.create_enums.len()
+        + diff.add_enum_values.values().map(Vec::len).sum::<usize>()
+        + diff.drop_enums.len();
+    if enums > 0 {
+        counts.push_str(&format!(", {} enum change(s)", enums));
+    }
+    let privileges = diff.create_roles.len() + diff.grants.len() + diff.revokes.len();
+    if privileges > 0 {
+        counts.push_str(&format!(", {} role/privilege change(s)", privileges));
+    }
+    counts
+}
+
+// ==================== Check ====================
+
+/// Options of `stratus check`
+struct CheckArgs {
+    schema: Option<PathBuf>,
+    datasource: Option<String>,
+    env: Option<String>,
+    url: Option<String>,
+    offline: bool,
+    audit: bool,
+    no_cache: bool,
+}
+
+/// Run every CI check; the exit code is that of the first failing class
+fn check(config_file: Option<&Path>, args: CheckArgs) -> CliResult {
+    stratus::output::header("🔎", "Stratus Check");
+
+    let config = load_config(config_file)?;
+    let mut checks = Checklist::default();
+    let mut failures = Vec::new();
+
+    // Schema
+    let schema_path = resolve_schema_path(config.as_ref(), args.schema);
+    let schema = match stratus::engine::read_schema(&schema_path) {
+        Ok(schema) => {
+            checks.pass(
+                "Schema",
+                format!("{} ({} tables)", schema_path.display(), schema.tables.len()),
+            );
+            Some(schema)
+        }
+        Err(e) => {
+            checks.fail("Schema", e.to_string());
+            failures.push(ExitCode::InvalidSchema);
+            None
+        }
+    };
+    if let (true, Some(schema)) = (args.audit, &schema) {
+        let diagnostics = stratus::audit::audit_schema(&read_input(&schema_path)?, schema);
+        if diagnostics.is_empty() {
+            checks.pass("Schema audit", "no names written unquoted");
+        } else {
+            checks.fail(
+                "Schema audit",
+                format!("{} name(s) unsafe unquoted", diagnostics.len()),
+            );
+            failures.push(ExitCode::InvalidSchema);
+        }
+        print_diagnostics(&schema_path, &diagnostics);
+    }
+
+    // Queries against the schema
+    let failed = checks.failed;
+    let query_files = match config.as_ref().map(config_query_files).transpose() {
+        Ok(files) => files.unwrap_or_default(),
+        Err(e) => {
+            checks.fail("Queries", e.message);
+            Vec::new()
+        }
+    };
+    if query_files.is_empty() && checks.failed == failed {
+        checks.skip("Queries", "no query files configured");
+    }
+    for file in &query_files {
+        let check = format!("Queries ({})", file.display());
+        let source = match read_input(file) {
+            Ok(source) => source,
+            Err(e) => {
+                checks.fail(&check, e.message);
+                continue;
+            }
+        };
+        let Some(schema) = &schema else {
+            checks.skip(&check, "schema unavailable");
+            continue;
+        };
+        let diagnostics = stratus::analyze::check_source(&source, schema);
+        let errors = diagnostics.count(stratus::diagnostics::Severity::Error);
+        if errors == 0 {
+            let queries = stratus::parser::parse_with_diagnostics(&source).0.queries;
+            checks.pass(&check, format!("{} queries", queries.len()));
+        } else {
+            checks.fail(&check, format!("{} error(s)", errors));
+        }
+        print_diagnostics(file, &diagnostics);
+        if args.audit {
+            let check = format!("Query audit ({})", file.display());
+            let diagnostics = stratus::audit::audit_source(&source);
+            if diagnostics.is_empty() {
+                checks.pass(&check, "parameters only");
+            } else {
+                checks.fail(
+                    &check,
+                    format!("{} place(s) splice text into SQL", diagnostics.len()),
+                );
+            }
+            print_diagnostics(file, &diagnostics);
+        }
+    }
+    if checks.failed > failed {
+        failures.push(ExitCode::InvalidQueries);
+    }
+
+    // Generated code
+    let failed = checks.failed;
+    match &config {
+        Some(cfg) if !cfg.generators().is_empty() => {
+            if let Err(e) = check_generated(cfg, schema.as_ref(), &mut checks) {
+                checks.fail("Generated code", e.message);
+            }
+        }
+        _ => checks.skip("Generated code", "no generators configured"),
+    }
+    if checks.failed > failed {
+        failures.push(ExitCode::StaleCode);
+    }
+
+    // Migration history and drift need a database
+    let client = if args.offline {
+        checks.skip("Database", "--offline; migration and drift checks skipped");
+        None
+    } else {
+        let datasource = resolve_datasource(config.as_ref(), args.datasource, args.env.as_deref())?;
+        let db_url = if args.url.is_some() || datasource.is_some() {
+            Some(stratus::engine::resolve_database_url(
+                config.as_ref(),
+                datasource.as_deref(),
+                args.url.as_deref(),
+            )?)
+        } else {
+            std::env::var("DATABASE_URL").ok()
+        };
+        match db_url {
+            None => {
+                checks.skip(
+                    "Database",
+                    "no datasource configured; migration and drift checks skipped",
+                );
+                None
+            }
+            Some(url) => {
+                let db_config = stratus::engine::connection_config(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    args.env.as_deref(),
+                    &url,
+                    args.no_cache,
+                );
+                match stratus::db::StratusClient::connect(&db_config) {
+                    Ok(client) => Some(client),
+                    Err(e) => {
+                        checks.fail("Database", e);
+                        failures.push(ExitCode::Unavailable);
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    if let Some(mut client) = client {
+        let table = migrations_table(config.as_ref());
+        let migrations_dir = resolve_migrations_dir(config.as_ref());
+
+        let failed = checks.failed;
+        if !migrations_dir.exists() {
+            checks.skip(
+                "Migrations",
+                format!("{} does not exist yet", migrations_dir.display()),
+            );
+        } else {
+            match stratus::migrate::read_migrations(&migrations_dir) {
+                Ok(migrations) => match check_migrations(&mut client, &table, &migrations) {
+                    Ok(detail) => checks.pass("Migrations", detail),
+                    Err(e) => checks.fail("Migrations", e),
+                },
+                Err(e) => checks.fail("Migrations", e),
+            }
+        }
+        if checks.failed > failed {
+            failures.push(ExitCode::MigrationMismatch);
+        }
+
+        match &schema {
+            Some(schema) => match stratus::engine::introspect(&mut client, &table) {
+                Ok(db_schema) => {
+                    let diff = stratus::db::compare_schemas(schema, &db_schema);
+                    if diff.has_changes() {
+                        checks.fail(
+                            "Drift",
+                            format!("database differs from schema.json: {}", diff_counts(&diff)),
+                        );
+                        failures.push(ExitCode::Drift);
+                    } else {
+                        checks.pass("Drift", "database matches schema.json");
+                    }
+                }
+                Err(e) => {
+                    let e = CliError::from(e);
+                    checks.fail("Drift", &e.message);
+                    failures.push(e.code);
+                }
+            },
+            None => checks.skip("Drift", "schema unavailable"),
+        }
+    }
+
+    println!();
+    println!(
+        "{} passed, {} failed, {} skipped",
+        checks.passed, checks.failed, checks.skipped
+    );
+
+    match failures.first() {
+        Some(&code) => Err(CliError::new(
+            code,
+            format!("{} check(s) failed", checks.failed),
+        )),
+        None => Ok(()),
+    }
+}
+
+// ==================== Dev ====================
+
+/// State carried between `stratus dev` rebuilds
+struct DevSession {
+    config: Option<stratus::config::ConfigManager>,
+    schema_path: PathBuf,
+    client: Option<stratus::db::StratusClient>,
+    table: stratus::migrate::MigrationsTable,
+    apply: bool,
+}
+
+impl DevSession {
+    /// Files whose changes trigger a rebuild, as absolute paths
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.schema_path.clone()];
+        if let Some(cfg) = &self.config {
+            files.extend(cfg.query_files().unwrap_or_default());
+            files.extend(
+                cfg.generators()
+                    .iter()
+                    .filter_map(|g| g.input.as_ref())
+                    .map(|input| cfg.resolve_path(input)),
+            );
+        }
+        files
+            .into_iter()
+            .filter_map(|f| std::path::absolute(f).ok())
+            .collect()
+    }
+
+    /// Re-run the diff against the database and every generator
+    fn rebuild(&mut self) {
+        let schema = match stratus::engine::read_schema(&self.schema_path) {
+            Ok(schema) => Some(schema),
+            Err(e) => {
+                println!("  {} {}", Mark::Fail, e);
+                None
+            }
+        };
+        if let Some(schema) = &schema {
+            if let Err(e) = self.check_database(schema) {
+                println!("  {} {}", Mark::Fail, e.message);
+            }
+        }
+        if let Err(e) = self.generate(schema.as_ref()) {
+            println!("  {} {}", Mark::Fail, e.message);
+        }
+    }
+
+    fn check_database(&mut self, schema: &stratus::schema::Schema) -> CliResult {
+        let Some(client) = self.client.as_mut() else {
+            return Ok(());
+        };
+        let db_schema = stratus::engine::introspect(client, &self.table)?;
+        let diff = stratus::db::compare_schemas(schema, &db_schema);
+        if !diff.has_changes() {
+            println!("  {} Database in sync", Mark::Ok);
+            return Ok(());
+        }
+
+        println!("  ~ Database differs: {}", diff_counts(&diff));
+
+        if !self.apply || diff.operations.is_empty() {
+            return Ok(());
+        }
+        // Only changes that create or add are applied unreviewed
+        if !diff
+            .operations
+            .iter()
+            .all(stratus::diff::Operation::is_additive)
+        {
+            println!("  ! Changes that drop, alter or grant not applied; run `stratus db push` to review them");
+            return Ok(());
+        }
+
+        let sql = diff.sql();
+        let mut bar = statement_progress(stratus::migrate::split_statements(&sql).len());
+        client.begin()?;
+        if let Err((n, e)) = stratus::engine::execute_script(client, &sql, &mut bar) {
+            let _ = client.rollback();
+            bar.0.abandon();
+            return Err(CliError::new(
+                ExitCode::Failure,
+                format!("Applying statement {} failed: {}", n, e),
+            ));
+        }
+        client.commit()?;
+        bar.0.finish_and_clear();
+        println!("  {} Applied changes to the database", Mark::Ok);
+        Ok(())
+    }
+
+    fn generate(&self, schema: Option<&stratus::schema::Schema>) -> CliResult {
+        let Some(cfg) = self.config.as_ref().filter(|c| !c.generators().is_empty()) else {
+            return Ok(());
+        };
+
+        for output in stratus::engine::render_generators(cfg, schema)? {
+            let files = match output.result {
+                Ok(files) => files,
+                Err(e) => {
+                    println!("  {} {}: {}", Mark::Fail, output.provider, e);
+                    continue;
+                }
+            };
+            // Leave unchanged outputs alone so other watchers stay quiet
+            let changed: Vec<_> = files
+                .iter()
+                .filter(|file| {
+                    fs::read_to_string(&file.path).ok().as_deref() != Some(file.contents.as_str())
+                })
+                .collect();
+            if changed.is_empty() {
+                println!("  {} {} unchanged", Mark::Ok, output.provider);
+            }
+            let changed: Vec<_> = changed.into_iter().cloned().collect();
+            stratus::engine::write_generated(&changed)?;
+            for file in changed {
+                println!(
+                    "  {} {} -> {}",
+                    Mark::Ok,
+                    output.provider,
+                    file.path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Watch inputs and rebuild on every change until interrupted
+fn dev_watch(mut session: DevSession) -> CliResult {
+    use notify::Watcher;
+
+    let watch_error =
+        |e: notify::Error| CliError::new(ExitCode::Failure, format!("Watch failed: {}", e));
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+
+    // Watch directories so editors that replace files are still seen
+    let mut dirs: Vec<PathBuf> = session
+        .watched_files()
+        .iter()
+        .filter_map(|f| f.parent().map(Path::to_path_buf))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in &dirs {
+        watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+    }
+
+    println!(
+        "[{}] Initial build",
+        chrono::Local::now().format("%H:%M:%S")
+    );
+    session.rebuild();
+    println!(
+        "\nWatching {} director(ies). Press Ctrl+C to stop.",
+        dirs.len()
+    );
+
+    while let Ok(event) = rx.recv() {
+        let watched = session.watched_files();
+        let changed: Vec<PathBuf> = event
+            .into_iter()
+            .flat_map(|e| e.paths)
+            .filter(|p| watched.contains(p))
+            .collect();
+        let Some(first) = changed.first() else {
+            continue;
+        };
+
+        // Let a burst of writes settle before rebuilding once
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        while rx.try_recv().is_ok() {}
+
+        println!(
+            "\n[{}] Changed: {}",
+            chrono::Local::now().format("%H:%M:%S"),
+            first.display()
+        );
+        session.rebuild();
+    }
+
+    Ok(())
+}
+
+// ==================== Logging ====================
+
+/// Install the stderr log subscriber for the requested verbosity
+fn init_logging(verbose: u8, quiet: bool) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::EnvFilter;
+
+    // Dependencies stay at warn until -vvv so driver chatter doesn't drown the SQL log
+    let level = match (quiet, verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn",
+        (false, 1) => "warn,stratus=info",
+        (false, 2) => "warn,stratus=debug",
+        (false, _) => "trace",
+    };
+    let filter = EnvFilter::try_from_env("STRATUS_LOG").unwrap_or_else(|_| EnvFilter::new(level));
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(stratus::output::stderr_color())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_timer(tracing_subscriber::fmt::time::Uptime::default())
+        .init();
+}
+
+fn main() {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            let _ = e.print();
+            let code = if e.use_stderr() {
+                ExitCode::Usage as i32
+            } else {
+                0
+            };
+            std::process::exit(code);
+        }
+    };
+
+    stratus::output::init(!args.no_color, args.ascii);
+    init_logging(args.verbose, args.quiet);
+
+    if let Err(e) = run(args) {
+        e.report();
+        std::process::exit(e.code as i32);
+    }
+}
+
+fn run(args: Args) -> CliResult {
+    let config_file = args.config.as_deref();
+    let no_cache = args.no_cache || std::env::var_os("STRATUS_NO_CACHE").is_some();
+    match args.command {
         // ==================== Generate ====================
         Commands::Generate {
             input,
@@ -266,25 +2602,58 @@ fn main() {
             language,
             schema,
         } => {
-            let input_str = fs::read_to_string(&input).expect("Failed to read input file");
-            let ast = stratus::parser::parse(&input_str).expect("Failed to parse");
+            let Some(input) = input else {
+                return generate_all(config_file, schema);
+            };
 
-            let schema_data = schema.as_ref().map(|s| {
-                let schema_str = fs::read_to_string(s).expect("Failed to read schema");
-                serde_json::from_str(&schema_str).expect("Failed to parse schema")
-            });
+            let ast = read_queries(&input)?;
+            let schema_data = schema
+                .as_deref()
+                .map(stratus::engine::read_schema)
+                .transpose()?;
 
             let output_str = match language.as_str() {
-                "ts" | "typescript" => stratus::codegen::generate_ts(&ast, schema_data.as_ref()),
-                "py" | "python" => stratus::codegen::generate_py(&ast, schema_data.as_ref()),
-                "sql" => stratus::codegen::generate_sql(&ast),
-                _ => panic!("Unsupported language: {}", language),
+                "ts" | "typescript" | "py" | "python" | "sql" => stratus::codegen::generate(
+                    &language,
+                    Some(&ast),
+                    schema_data.as_ref(),
+                    &stratus::codegen::GeneratorOptions::default(),
+                )
+                .map_err(|e| CliError::new(ExitCode::Failure, e))?,
+                _ => return Err(unsupported_language(&language, "ts, py, sql")),
             };
 
             match output {
                 Some(path) => {
-                    fs::write(&path, &output_str).expect("Failed to write output");
+                    let mut files = vec![stratus::engine::GeneratedFile {
+                        path: path.clone(),
+                        contents: output_str,
+                    }];
+                    if let Some(runtime) = stratus::codegen::runtime::emitted(
+                        &language,
+                        &stratus::codegen::GeneratorOptions::default(),
+                    ) {
+                        files.push(stratus::engine::GeneratedFile {
+                            path: path.with_file_name(runtime.file_name),
+                            contents: runtime.source.to_string(),
+                        });
+                    }
+                    stratus::engine::write_generated(&files)?;
                     println!("Generated {} -> {}", language, path.display());
+                    if let Some(runtime) = files.get(1) {
+                        println!("Generated runtime -> {}", runtime.path.display());
+                    }
+                    let paths: Vec<PathBuf> = files.into_iter().map(|file| file.path).collect();
+
+                    let config = load_config(config_file)?;
+                    let output_paths = std::env::join_paths(&paths)
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    run_hooks(
+                        config.as_ref(),
+                        stratus::hooks::POST_GENERATE,
+                        &[("STRATUS_OUTPUT_PATHS", output_paths)],
+                    )?;
                 }
                 None => {
                     print!("{}", output_str);
@@ -294,30 +2663,376 @@ fn main() {
 
         // ==================== Parse ====================
         Commands::Parse { input } => {
-            let input_str = fs::read_to_string(&input).expect("Failed to read input file");
-            let ast = stratus::parser::parse(&input_str).expect("Failed to parse");
+            let (ast, diagnostics) = stratus::parser::parse_with_diagnostics(&read_input(&input)?);
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{}", input.display(), diagnostic);
+            }
+            if diagnostics.has_errors() {
+                return Err(CliError::new(
+                    ExitCode::DataErr,
+                    format!("Failed to parse {}", input.display()),
+                ));
+            }
             println!("{:#?}", ast);
         }
 
+        // ==================== Fmt ====================
+        Commands::Fmt { files, check } => fmt(config_file, files, check)?,
+
+        // ==================== Language Server ====================
+        Commands::Lsp { schema } => {
+            let schema_path = resolve_schema_path(load_config(config_file)?.as_ref(), schema);
+            stratus::lsp::serve_stdio(schema_path)
+                .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+        }
+
+        // ==================== Explain ====================
+        Commands::Explain {
+            query,
+            analyze,
+            params,
+            input,
+            datasource,
+            env,
+            url,
+        } => {
+            let config = load_config(config_file)?;
+            let files = match (input, config.as_ref()) {
+                (Some(input), _) => vec![input],
+                (None, Some(cfg)) => config_query_files(cfg)?,
+                (None, None) => Vec::new(),
+            };
+            let (file, query) = find_query(&files, &query)?;
+            // Parameters the header leaves out are typed from the schema, when there is one
+            let schema: Option<stratus::schema::Schema> =
+                std::fs::read_to_string(resolve_schema_path(config.as_ref(), None))
+                    .ok()
+                    .and_then(|json| serde_json::from_str(&json).ok());
+            let sql = bind_sample_params(&query, schema.as_ref(), &params)?;
+
+            let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+            let db_url = stratus::engine::resolve_database_url(
+                config.as_ref(),
+                datasource.as_deref(),
+                url.as_deref(),
+            )?;
+            let db_config = stratus::engine::connection_config(
+                config.as_ref(),
+                datasource.as_deref(),
+                env.as_deref(),
+                &db_url,
+                no_cache,
+            );
+
+            stratus::output::header("🔍", "Stratus Explain");
+            println!("Query: {} ({})", query.name, file.display());
+            println!();
+            stratus::output::print_sql(&sql);
+            println!();
+
+            let mut client = connect(&db_config)?;
+            let plan = client.explain(&sql, analyze)?;
+            println!("{}", if analyze { "Plan (analyzed):" } else { "Plan:" });
+            for line in &plan {
+                println!("  {}", stratus::output::highlight_plan(line));
+            }
+        }
+
+        // ==================== Stats ====================
+        Commands::Stats {
+            slow_ms,
+            format,
+            input,
+            datasource,
+            env,
+            url,
+        } => {
+            if !matches!(format.as_str(), "text" | "json") {
+                return Err(CliError::new(
+                    ExitCode::Usage,
+                    format!("Unknown format '{}': use text or json", format),
+                ));
+            }
+            let config = load_config(config_file)?;
+            let files = match (input, config.as_ref()) {
+                (Some(input), _) => vec![input],
+                (None, Some(cfg)) => config_query_files(cfg)?,
+                (None, None) => Vec::new(),
+            };
+            if files.is_empty() {
+                return Err(CliError::new(ExitCode::Usage, "No query files to read")
+                    .with_hint("Pass --input, or set queries in stratus.json"));
+            }
+            let mut queries = Vec::new();
+            for file in &files {
+                let parsed = read_queries(file)?;
+                queries.extend(parsed.queries.into_iter().map(|q| (file.clone(), q)));
+            }
+
+            let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+            let db_url = stratus::engine::resolve_database_url(
+                config.as_ref(),
+                datasource.as_deref(),
+                url.as_deref(),
+            )?;
+            let db_config = stratus::engine::connection_config(
+                config.as_ref(),
+                datasource.as_deref(),
+                env.as_deref(),
+                &db_url,
+                no_cache,
+            );
+            let mut client = stratus::db::StratusClient::connect(&db_config)?;
+            let statements = stratus::stats::read_statements(&mut client)?;
+            let usage = stratus::stats::usage(&queries, &statements, slow_ms);
+
+            if format == "json" {
+                let json = serde_json::to_string_pretty(&usage)
+                    .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+                println!("{}", json);
+                return Ok(());
+            }
+
+            stratus::output::header("📈", "Stratus Stats");
+            println!("Queries: {} in {} file(s)", queries.len(), files.len());
+            println!("Slow from: {} ms mean", slow_ms);
+            println!();
+            let width = usage.iter().map(|u| u.name.len()).max().unwrap_or(0);
+            for query in &usage {
+                let Some(stats) = &query.stats else {
+                    println!("  {} {:width$}  unused", Mark::Skip, query.name);
+                    continue;
+                };
+                let mark = match query.status {
+                    stratus::stats::UsageStatus::Slow => Mark::Warn,
+                    _ => Mark::Ok,
+                };
+                println!(
+                    "  {} {:width$}  {:>8} calls  {:>10.2} ms mean  {:>12.1} ms total",
+                    mark,
+                    query.name,
+                    stats.calls,
+                    stats.mean_ms(),
+                    stats.total_ms
+                );
+            }
+            let count = |status| usage.iter().filter(|u| u.status == status).count();
+            println!();
+            println!(
+                "{} slow, {} unused of {} queries.",
+                count(stratus::stats::UsageStatus::Slow),
+                count(stratus::stats::UsageStatus::Unused),
+                usage.len()
+            );
+        }
+
+        // ==================== Advise ====================
+        Commands::Advise {
+            schema,
+            input,
+            output,
+            format,
+        } => {
+            if !matches!(format.as_str(), "text" | "json") {
+                return Err(CliError::new(
+                    ExitCode::Usage,
+                    format!("Unknown format '{}': use text or json", format),
+                ));
+            }
+            let config = load_config(config_file)?;
+            let schema =
+                stratus::engine::read_schema(&resolve_schema_path(config.as_ref(), schema))?;
+            let files = match (input, config.as_ref()) {
+                (Some(input), _) => vec![input],
+                (None, Some(cfg)) => config_query_files(cfg)?,
+                (None, None) => Vec::new(),
+            };
+            if files.is_empty() {
+                return Err(CliError::new(ExitCode::Usage, "No query files to read")
+                    .with_hint("Pass --input, or set queries in stratus.json"));
+            }
+            let mut queries = Vec::new();
+            for file in &files {
+                queries.extend(read_queries(file)?.queries);
+            }
+            let advice = stratus::advise::advise_indexes(&queries, &schema);
+            let dialect = stratus::advise::dialect(&schema);
+            let sql: String = advice
+                .iter()
+                .map(|a| format!("{}\n", a.sql(dialect)))
+                .collect();
+            if let Some(path) = &output {
+                write_output(path, &sql)?;
+            }
+
+            if format == "json" {
+                let json = serde_json::to_string_pretty(&advice)
+                    .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+                println!("{}", json);
+                return Ok(());
+            }
+
+            stratus::output::header("🧭", "Stratus Advise");
+            println!("Queries: {} in {} file(s)", queries.len(), files.len());
+            println!();
+            if advice.is_empty() {
+                println!("{} Every query filters on an indexed column.", Mark::Ok);
+                return Ok(());
+            }
+            for index in &advice {
+                println!(
+                    "  {} {} ({}) for {}",
+                    Mark::Warn,
+                    index.table,
+                    index.columns.join(", "),
+                    index.queries.join(", ")
+                );
+            }
+            println!();
+            match &output {
+                Some(path) => println!("{} index(es) -> {}", advice.len(), path.display()),
+                None => print!("{}", sql),
+            }
+        }
+
+        // ==================== Import Queries ====================
+        Commands::ImportQueries {
+            from,
+            output,
+            schema,
+            min_calls,
+            limit,
+            datasource,
+            env,
+            url,
+        } => {
+            let config = load_config(config_file)?;
+            let from_database = from == "pg_stat_statements";
+            if !from_database && (url.is_some() || datasource.is_some() || env.is_some()) {
+                return Err(CliError::new(
+                    ExitCode::Usage,
+                    "--url, --datasource and --env only apply with --from pg_stat_statements",
+                ));
+            }
+
+            // Queries already written are not imported again
+            let mut files = match config.as_ref() {
+                Some(cfg) => config_query_files(cfg)?,
+                None => Vec::new(),
+            };
+            if let Some(path) = output.as_ref().filter(|p| p.exists()) {
+                if !files.contains(path) {
+                    files.push(path.clone());
+                }
+            }
+            let mut existing = Vec::new();
+            for file in &files {
+                existing.extend(read_queries(file)?.queries);
+            }
+            let schema = match schema {
+                Some(path) => Some(stratus::engine::read_schema(&path)?),
+                None => {
+                    let path = resolve_schema_path(config.as_ref(), None);
+                    if path.exists() {
+                        Some(stratus::engine::read_schema(&path)?)
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            let statements: Vec<stratus::import::Statement> = if from_database {
+                let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+                let db_url = stratus::engine::resolve_database_url(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    url.as_deref(),
+                )?;
+                let db_config = stratus::engine::connection_config(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    env.as_deref(),
+                    &db_url,
+                    no_cache,
+                );
+                let mut client = stratus::db::StratusClient::connect(&db_config)?;
+                stratus::stats::read_raw_statements(&mut client)?
+                    .into_iter()
+                    .map(|(sql, stats)| stratus::import::Statement {
+                        sql,
+                        calls: stats.calls,
+                    })
+                    .collect()
+            } else {
+                stratus::import::from_log(&read_input(Path::new(&from))?)
+            };
+            let options = stratus::import::ImportOptions {
+                min_calls,
+                limit,
+                ignore_tables: vec![migrations_table(config.as_ref()).name],
+            };
+            let stubs = stratus::import::stubs(&statements, &existing, schema.as_ref(), &options);
+            let typesql = stratus::import::render(&stubs);
+
+            let Some(path) = output else {
+                print!("{}", typesql);
+                eprintln!(
+                    "{} query stub(s) from {} statement(s)",
+                    stubs.len(),
+                    statements.len()
+                );
+                return Ok(());
+            };
+            stratus::output::header("📥", "Stratus Import Queries");
+            println!("From: {}", from);
+            println!("Statements: {}", statements.len());
+            println!();
+            if stubs.is_empty() {
+                println!("{} No new queries to import.", Mark::Ok);
+                return Ok(());
+            }
+            let contents = match fs::read_to_string(&path) {
+                Ok(current) if !current.trim().is_empty() => {
+                    format!("{}\n\n{}", current.trim_end(), typesql)
+                }
+                _ => typesql,
+            };
+            write_output(&path, &contents)?;
+            let width = stubs.iter().map(|s| s.query.name.len()).max().unwrap_or(0);
+            for stub in &stubs {
+                println!(
+                    "  {} {:width$}  :{:<4}  {:>8} calls",
+                    Mark::Ok,
+                    stub.query.name,
+                    stub.query.return_type,
+                    stub.calls
+                );
+            }
+            println!();
+            println!("{} query stub(s) -> {}", stubs.len(), path.display());
+            println!("Review the names and cardinalities, then run: stratus generate");
+        }
+
         // ==================== Gen Types ====================
         Commands::GenTypes {
             schema,
             output,
             language,
         } => {
-            let schema_str = fs::read_to_string(&schema).expect("Failed to read schema");
-            let schema: stratus::schema::Schema =
-                serde_json::from_str(&schema_str).expect("Failed to parse schema");
+            let schema = stratus::engine::read_schema(&schema)?;
 
             let output_str = match language.as_str() {
                 "ts" | "typescript" => stratus::codegen::generate_ts_types_only(&schema),
                 "py" | "python" => stratus::codegen::generate_py_types_only(&schema),
-                _ => panic!("Unsupported language: {}", language),
+                _ => return Err(unsupported_language(&language, "ts, py")),
             };
 
             match output {
                 Some(path) => {
-                    fs::write(&path, &output_str).expect("Failed to write output");
+                    stratus::engine::write_generated(&[stratus::engine::GeneratedFile {
+                        path: path.clone(),
+                        contents: output_str,
+                    }])?;
                     println!("Generated types -> {}", path.display());
                 }
                 None => {
@@ -326,6 +3041,159 @@ fn main() {
             }
         }
 
+        // ==================== Check ====================
+        Commands::Check {
+            schema,
+            datasource,
+            env,
+            url,
+            offline,
+            audit,
+        } => check(
+            config_file,
+            CheckArgs {
+                schema,
+                datasource,
+                env,
+                url,
+                offline,
+                audit,
+                no_cache,
+            },
+        )?,
+
+        // ==================== Doctor ====================
+        Commands::Doctor { env } => doctor(config_file, env.as_deref(), no_cache)?,
+
+        // ==================== Dev ====================
+        Commands::Dev {
+            schema,
+            apply,
+            url,
+            datasource,
+            env,
+        } => {
+            let config = load_config(config_file)?;
+            let schema_path = resolve_schema_path(config.as_ref(), schema);
+
+            // Auto-apply is for throwaway dev databases only
+            let environment = resolve_environment(config.as_ref(), env.as_deref());
+            if apply && environment.is_some_and(|e| e.require_confirmation) {
+                return Err(CliError::new(
+                    ExitCode::Usage,
+                    format!(
+                        "--apply is not allowed for environment '{}'",
+                        env.as_deref().unwrap_or_default()
+                    ),
+                )
+                .with_hint("Environments that require confirmation must use `stratus deploy`"));
+            }
+
+            let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+            let db_url = if url.is_some() || datasource.is_some() {
+                Some(stratus::engine::resolve_database_url(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    url.as_deref(),
+                )?)
+            } else {
+                std::env::var("DATABASE_URL").ok()
+            };
+
+            stratus::output::header("👀", "Stratus Dev");
+            println!("Schema: {}", schema_path.display());
+            if apply {
+                println!("Auto-apply: non-destructive changes");
+            }
+
+            let client = match &db_url {
+                Some(db_url) => {
+                    let db_config = stratus::engine::connection_config(
+                        config.as_ref(),
+                        datasource.as_deref(),
+                        env.as_deref(),
+                        db_url,
+                        no_cache,
+                    );
+                    Some(connect(&db_config)?)
+                }
+                None if apply => return Err(missing_url()),
+                None => {
+                    println!("Database: not connected (diffs are skipped)");
+                    println!();
+                    None
+                }
+            };
+
+            dev_watch(DevSession {
+                table: migrations_table(config.as_ref()),
+                config,
+                schema_path,
+                client,
+                apply,
+            })?;
+        }
+
+        // ==================== Studio ====================
+        Commands::Studio {
+            schema,
+            port,
+            host,
+            url,
+            datasource,
+            env,
+        } => {
+            let config = load_config(config_file)?;
+            let schema_path = resolve_schema_path(config.as_ref(), schema);
+
+            // The database is optional; without one only schema.json is shown
+            let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+            let db_url = if url.is_some() || datasource.is_some() {
+                Some(stratus::engine::resolve_database_url(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    url.as_deref(),
+                )?)
+            } else {
+                std::env::var("DATABASE_URL").ok()
+            };
+
+            stratus::output::header("🎨", "Stratus Studio");
+            println!("Schema: {}", schema_path.display());
+
+            let client = match &db_url {
+                Some(db_url) => {
+                    let db_config = stratus::engine::connection_config(
+                        config.as_ref(),
+                        datasource.as_deref(),
+                        env.as_deref(),
+                        db_url,
+                        no_cache,
+                    );
+                    Some(connect(&db_config)?)
+                }
+                None => {
+                    println!(
+                        "Database: not connected (pass --url, --datasource or set DATABASE_URL)"
+                    );
+                    println!();
+                    None
+                }
+            };
+
+            let addr = format!("{}:{}", host, port);
+            let studio = stratus::studio::Studio::new(
+                schema_path,
+                client,
+                migrations_table(config.as_ref()),
+            );
+            println!("Open http://{}", addr);
+            println!("Press Ctrl+C to stop.");
+            studio
+                .serve(&addr)
+                .map_err(|e| CliError::new(ExitCode::Unavailable, e))?;
+        }
+
         // ==================== Benchmark ====================
         Commands::Benchmark => {
             println!("Running benchmark comparison...");
@@ -338,74 +3206,30 @@ fn main() {
             println!();
             println!("TODO: Implement full benchmark suite");
         }
-
-        // ==================== Validate ====================
-        Commands::Validate { schema } => {
-            let schema_path = schema.unwrap_or_else(|| PathBuf::from("schema.json"));
-            let schema_str = match fs::read_to_string(&schema_path) {
-                Ok(s) => s,
-                Err(_) => {
-                    eprintln!(
-                        "Error: Could not read schema file: {}",
-                        schema_path.display()
-                    );
-                    std::process::exit(1);
-                }
-            };
-
-            let parsed: serde_json::Value = match serde_json::from_str(&schema_str) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("Error: Invalid JSON - {}", e);
-                    std::process::exit(1);
-                }
-            };
-
-            // Basic structure validation
-            if let Some(obj) = parsed.as_object() {
-                let mut errors: Vec<String> = Vec::new();
-
-                if !obj.contains_key("version") {
-                    errors.push("Missing required field: 'version'".to_string());
-                }
-                if !obj.contains_key("tables") {
-                    errors.push("Missing required field: 'tables'".to_string());
-                } else if let Some(tables) = obj.get("tables").and_then(|t| t.as_object()) {
-                    for (table_name, table) in tables {
-                        if let Some(cols) = table.get("columns").and_then(|c| c.as_object()) {
-                            for (col_name, col) in cols {
-                                if !col.is_object() {
-                                    errors.push(format!(
-                                        "Table '{}' column '{}' must be an object",
-                                        table_name, col_name
-                                    ));
-                                }
-                            }
-                        }
-                    }
-                }
-
-                if errors.is_empty() {
-                    println!("✓ Schema is valid: {}", schema_path.display());
-                    println!(
-                        "  Version: {:?}",
-                        obj.get("version").and_then(|v| v.as_str())
-                    );
-                    println!(
-                        "  Tables: {}",
-                        obj.get("tables")
-                            .map(|t| t.as_object().map(|o| o.len()).unwrap_or(0))
-                            .unwrap_or(0)
-                    );
-                    if let Some(enums) = obj.get("enums").and_then(|e| e.as_object()) {
+
+        // ==================== Validate ====================
+        Commands::Validate { schema } => {
+            let schema_path = resolve_schema_path(load_config(config_file)?.as_ref(), schema);
+            let schema_str = read_input(&schema_path)?;
+
+            let (schema, diagnostics) = stratus::schema::validate(&schema_str);
+            match schema {
+                Some(schema) if !diagnostics.has_errors() => {
+                    println!("{} Schema is valid: {}", Mark::Ok, schema_path.display());
+                    println!("  Version: {:?}", schema.version);
+                    println!("  Tables: {}", schema.tables.len());
+                    if let Some(enums) = &schema.enums {
                         println!("  Enums: {}", enums.len());
                     }
-                } else {
-                    eprintln!("Error: Schema validation failed");
-                    for error in &errors {
-                        eprintln!("  - {}", error);
+                }
+                _ => {
+                    for diagnostic in &diagnostics {
+                        eprintln!("{}:{}", schema_path.display(), diagnostic);
                     }
-                    std::process::exit(1);
+                    return Err(CliError::new(
+                        ExitCode::DataErr,
+                        format!("Schema validation failed: {}", schema_path.display()),
+                    ));
                 }
             }
         }
@@ -415,11 +3239,13 @@ fn main() {
             url,
             datasource,
             output,
+            from_db,
         } => {
-            let config_path = output.unwrap_or_else(|| PathBuf::from("stratus.json"));
+            let config_path = output
+                .or_else(|| config_file.map(Path::to_path_buf))
+                .unwrap_or_else(|| PathBuf::from("stratus.json"));
 
-            println!("\n🚀  Stratus Init");
-            println!("{}", "=".repeat(50));
+            stratus::output::header("🚀", "Stratus Init");
             println!("Output: {}", config_path.display());
             println!("Datasource: {}", datasource);
             if let Some(ref url) = url {
@@ -429,183 +3255,178 @@ fn main() {
             }
             println!();
 
-            match stratus::config::ConfigManager::create_default(
+            if from_db {
+                let url = url.as_deref().ok_or_else(missing_url)?;
+                return init_from_db(&config_path, url, &datasource);
+            }
+
+            stratus::config::ConfigManager::create_default(
                 &config_path,
                 url.as_deref(),
                 &datasource,
-            ) {
-                Ok(_) => {
-                    println!("✓ Created stratus.json configuration");
-                    println!();
-                    println!("Next steps:");
-                    println!("  1. Edit stratus.json to configure database URL");
-                    println!("  2. Create your schema.json in the schema/ directory");
-                    println!("  3. Run: stratus sync --datasource {}", datasource);
+            )
+            .map_err(|e| CliError::new(ExitCode::CantCreate, e.to_string()))?;
+
+            println!("{} Created stratus.json configuration", Mark::Ok);
+            println!();
+            println!("Next steps:");
+            println!("  1. Edit stratus.json to configure database URL");
+            println!("  2. Create your schema.json in the schema/ directory");
+            println!("  3. Run: stratus sync --datasource {}", datasource);
+        }
+
+        // ==================== Config Command ====================
+        Commands::Config { command } => {
+            let validating = matches!(command, ConfigCommands::Validate);
+            let mut config = stratus::config::ConfigManager::load(config_file)
+                .map_err(|e| config_error(e, validating))?;
+
+            match command {
+                ConfigCommands::Show { resolve: false, .. } => {
+                    let content = serde_json::to_string_pretty(config.config())
+                        .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+                    println!("{}", content);
                 }
-                Err(e) => {
-                    eprintln!("Error creating configuration: {}", e);
-                    std::process::exit(1);
+                ConfigCommands::Show {
+                    resolve: true,
+                    datasource,
+                    env,
+                } => {
+                    let overrides = stratus::config::ConfigOverrides {
+                        datasource,
+                        env,
+                        ..Default::default()
+                    };
+                    let resolved = stratus::config::resolve_config(Some(&config), &overrides)?;
+
+                    println!("Config: {}", config.path().display());
+                    if let Some(datasource) = &resolved.datasource {
+                        println!("Datasource: {}", datasource);
+                    }
+                    println!("URL: {}", stratus::config::redact_url(&resolved.url));
+                    println!("Provider: {}", resolved.provider);
+                    if let Some(shadow) = &resolved.shadow_url {
+                        println!("Shadow: {}", stratus::config::redact_url(shadow));
+                    }
+                    println!("SSL: {}", describe_ssl(&resolved.ssl));
+                    println!("Connection: {}", describe_connection(&resolved.connection));
+                    println!("Schema: {}", resolved.schema_path.display());
+                    println!("Migrations: {}", resolved.migrations_path.display());
+                    match &resolved.seed {
+                        Some(stratus::config::SeedConfig {
+                            script: Some(script),
+                            ..
+                        }) => println!("Seed: script {}", script),
+                        Some(stratus::config::SeedConfig {
+                            command: Some(command),
+                            ..
+                        }) => println!("Seed: command {}", command),
+                        _ => println!("Seed: (none)"),
+                    }
+                }
+                ConfigCommands::Get { key } => match config.get_value(&key) {
+                    Some(serde_json::Value::String(s)) => println!("{}", s),
+                    Some(value) => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&value)
+                            .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?
+                    ),
+                    None => {
+                        return Err(CliError::new(
+                            ExitCode::Config,
+                            format!("Key not found: {}", key),
+                        )
+                        .with_hint("Run `stratus config show` to list keys"));
+                    }
+                },
+                ConfigCommands::Set { key, value } => {
+                    config.set_value(&key, &value)?;
+                    config.save()?;
+                    println!("{} Set {} in {}", Mark::Ok, key, config.path().display());
+                }
+                ConfigCommands::Validate => {
+                    let cfg = config.config();
+                    println!("{} {} is valid", Mark::Ok, config.path().display());
+                    println!("  Datasources: {}", cfg.datasources.len());
+                    println!("  Environments: {}", cfg.environments.len());
+                    println!("  Generators: {}", config.generators().len());
                 }
             }
         }
 
         // ==================== Sync Command ====================
         Commands::Sync {
-            schema: schema_override,
+            schema,
             name,
             force,
             dry_run,
-            datasource: datasource_override,
-            url: url_override,
+            print_sql_only,
+            interactive,
+            env,
+            yes,
+            override_policy,
+            datasource,
+            url,
         } => {
-            // Try to load configuration
-            let config = stratus::config::ConfigManager::load(None).ok();
-
-            // Determine schema path
-            let schema_path = if let Some(ref s) = schema_override {
-                s.clone()
-            } else if let Some(ref cfg) = config {
-                cfg.get_schema_path()
-            } else {
-                PathBuf::from("schema.json")
-            };
-
-            // Determine migrations directory
-            let migrations_dir = if let Some(ref cfg) = config {
-                cfg.get_migrations_path()
-            } else {
-                PathBuf::from("migrations")
-            };
-
-            // Determine database URL
-            let db_url = if let Some(ds_name) = &datasource_override {
-                if let Some(ref cfg) = config {
-                    let ds = cfg.get_datasource(ds_name).unwrap_or_else(|| {
-                        eprintln!("Error: Datasource '{}' not found in stratus.json", ds_name);
-                        std::process::exit(1);
-                    });
-                    url_override.clone().unwrap_or(ds.url.clone())
-                } else {
-                    url_override.clone().unwrap_or_else(|| {
-                        eprintln!(
-                            "Error: stratus.json not found. Use --url or create stratus.json"
-                        );
-                        std::process::exit(1);
-                    })
+            let engine = engine(config_file, schema, datasource, env.clone(), url, no_cache)?;
+            let db_config = engine.db_config()?;
+
+            // Only the SQL goes to stdout, and nothing is written
+            if print_sql_only {
+                let parsed_schema = engine.load_schema()?;
+                let mut client = stratus::db::connect(&db_config)?;
+                let db_schema = engine.introspect(&mut *client)?;
+                let diff = stratus::db::compare_schemas(&parsed_schema, &db_schema);
+                if !override_policy {
+                    engine.enforce_plan_policies("sync", &diff)?;
                 }
-            } else if let Some(ref url) = url_override {
-                url.clone()
-            } else if let Some(ref _cfg) = config {
-                // When using stratus.json, datasource is required
-                eprintln!("Error: Datasource required. Use --datasource flag or configure in stratus.json");
-                std::process::exit(1);
-            } else {
-                std::env::var("DATABASE_URL").ok().unwrap_or_else(|| {
-                    eprintln!(
-                        "Error: No database URL provided. Use --url or set DATABASE_URL env var."
-                    );
-                    std::process::exit(1);
-                })
-            };
+                stratus::output::print_sql(&diff.sql());
+                return Ok(());
+            }
 
-            println!("\n🔄  Stratus Sync");
-            println!("{}", "=".repeat(50));
-            println!("Schema: {}", schema_path.display());
-            println!("Migrations: {}", migrations_dir.display());
-            if let Some(ref ds) = datasource_override {
+            // Fail on a bad schema before connecting
+            engine.load_schema()?;
+
+            stratus::output::header("🔄", "Stratus Sync");
+            println!("Schema: {}", engine.schema_path().display());
+            println!("Migrations: {}", engine.migrations_dir().display());
+            if let Some(ds) = engine.datasource() {
                 println!("Datasource: {}", ds);
             }
-            if url_override.is_some() {
+            if engine.has_url_override() {
                 println!("URL: (CLI override)");
             }
             println!();
 
-            // Load schema
-            if !schema_path.exists() {
-                eprintln!("Error: Schema file not found: {}", schema_path.display());
-                std::process::exit(1);
-            }
-            let schema_str = fs::read_to_string(&schema_path).expect("Failed to read schema file");
-            let parsed_schema: stratus::schema::Schema =
-                serde_json::from_str(&schema_str).expect("Failed to parse schema");
-
-            // Connect to database
-            println!("Connecting to database...");
-            let db_config = stratus::db::DbConfig {
-                connection_string: db_url.clone(),
-                max_connections: 5,
-            };
-
-            let mut client = match stratus::db::StratusClient::connect(&db_config) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Error: Failed to connect to database: {}", e);
-                    std::process::exit(1);
-                }
-            };
-            println!("Connected successfully.");
-            println!();
-
-            // Load existing migrations
-            let existing_migrations = stratus::migrate::load_migrations(&migrations_dir)
-                .expect("Failed to load migrations");
-
-            // Introspect current database schema
-            println!("Introspecting database schema...");
-            let db_schema = match client.get_schema() {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error: Failed to introspect database: {}", e);
-                    std::process::exit(1);
-                }
-            };
-
-            // Calculate diff
-            let diff = stratus::db::compare_schemas(&parsed_schema, &db_schema);
-            stratus::db::print_diff_summary(&diff);
+            let mut client = connect_backend(&db_config)?;
 
-            if !diff.has_changes() {
-                println!("✓ Database is in sync with schema.json");
-                return;
+            let mut sync = engine.sync().force(force).override_policy(override_policy);
+            if let Some(name) = name {
+                sync = sync.name(name);
             }
+            println!("Introspecting database schema...");
+            let mut plan = sync.plan(&mut *client)?;
+            stratus::db::print_diff_summary(&plan.diff);
 
-            // Check for existing migrations with same checksum
-            let diff_checksum = diff.checksum();
-            if !force {
-                for m in &existing_migrations {
-                    if m.meta.checksum == Some(diff_checksum.clone()) {
-                        println!(
-                            "\n⚠️  Migration already exists with same changes: {}",
-                            m.meta.name
-                        );
-                        println!("   Use --force to re-apply");
-                        return;
-                    }
-                }
+            if !plan.diff.has_changes() {
+                println!("{} Database is in sync with schema.json", Mark::Ok);
+                return Ok(());
             }
 
-            // Check for conflicts with existing migrations
-            let mut potential_conflicts = Vec::new();
-            for m in &existing_migrations {
-                // Check if this migration affects the same tables
-                let migration_affects_tables = diff
-                    .create_tables
-                    .iter()
-                    .any(|table| m.up_sql.contains(table))
-                    || diff
-                        .drop_tables
-                        .iter()
-                        .any(|table| m.up_sql.contains(table));
-
-                if migration_affects_tables {
-                    potential_conflicts.push(m.meta.name.clone());
-                }
+            if let Some(existing) = &plan.duplicate {
+                println!(
+                    "\n{} Migration already exists with same changes: {}",
+                    Mark::Warn,
+                    existing
+                );
+                println!("   Use --force to re-apply");
+                return Ok(());
             }
 
-            if !potential_conflicts.is_empty() {
-                println!("\n⚠️  Potential conflicts detected!");
+            if !plan.conflicts.is_empty() {
+                println!("\n{} Potential conflicts detected!", Mark::Warn);
                 println!("   These existing migrations affect similar tables:");
-                for conflict in &potential_conflicts {
+                for conflict in &plan.conflicts {
                     println!("   - {}", conflict);
                 }
                 println!();
@@ -614,80 +3435,112 @@ fn main() {
                 println!();
             }
 
-            // Generate migration name
-            let migration_name = name.unwrap_or_else(|| {
-                stratus::migrate::generate_migration_name(
-                    &db_schema.to_json_schema(),
-                    &parsed_schema,
-                )
-            });
-
-            // Generate up/down SQL
-            let up_sql = diff.sql.clone();
-            let down_sql = diff.generate_rollback();
-
-            // Create migration
-            match stratus::migrate::create_migration(
-                &migrations_dir,
-                &migration_name,
-                &up_sql,
-                &down_sql,
-                "postgresql",
-                Some(diff_checksum),
-            ) {
-                Ok(m) => {
+            if interactive {
+                let decisions = edit_plan(plan.generated())?;
+                if !decisions.is_empty() {
+                    plan.edit(decisions);
+                    println!();
+                    for decision in plan.decisions().iter().filter(|d| d.excluded) {
+                        match &decision.note {
+                            Some(note) => println!("  skip {} ({})", decision.operation, note),
+                            None => println!("  skip {}", decision.operation),
+                        }
+                    }
+                    if !plan.diff.has_changes() {
+                        println!(
+                            "{} Every operation was excluded; no migration written.",
+                            Mark::Ok
+                        );
+                        return Ok(());
+                    }
                     println!();
-                    println!("✓ Created migration: {}_{}", m.meta.id, m.meta.name);
-                    println!(
-                        "  File: {}/{}_{}/up.sql",
-                        migrations_dir.display(),
-                        m.meta.id,
-                        m.meta.name
-                    );
-                    println!(
-                        "  File: {}/{}_{}/down.sql",
-                        migrations_dir.display(),
-                        m.meta.id,
-                        m.meta.name
-                    );
-                    println!("  Status: draft (editable until applied)");
-                }
-                Err(e) => {
-                    eprintln!("Error creating migration: {}", e);
-                    std::process::exit(1);
                 }
             }
 
+            println!("SQL to apply:");
+            stratus::output::print_sql_preview(&plan.diff.sql());
+
+            let migration = plan.write_migration()?;
+            let migrations_dir = engine.migrations_dir().display();
+            println!();
+            println!(
+                "{} Created migration: {}_{}",
+                Mark::Ok,
+                migration.meta.id,
+                migration.meta.name
+            );
+            println!(
+                "  File: {}/{}_{}/up.sql",
+                migrations_dir, migration.meta.id, migration.meta.name
+            );
+            println!(
+                "  File: {}/{}_{}/down.sql",
+                migrations_dir, migration.meta.id, migration.meta.name
+            );
+            println!("  Status: draft (editable until applied)");
+
             if dry_run {
                 println!("\n[DRY RUN] Skipping database application");
-                return;
+                return Ok(());
+            }
+
+            println!();
+            let violations =
+                engine.policy_violations(&[stratus::policy::Change::migration(&migration)?]);
+            if let Err(e) = enforce_policies(violations, env.as_deref(), override_policy) {
+                println!("   The migration was created but not applied.");
+                return Err(e);
+            }
+
+            if engine
+                .environment()
+                .map(|e| e.require_confirmation)
+                .unwrap_or(false)
+                && !confirm(
+                    &format!(
+                        "Apply {}_{} to '{}'?",
+                        migration.meta.id,
+                        migration.meta.name,
+                        env.as_deref().unwrap_or_default()
+                    ),
+                    yes,
+                )?
+            {
+                println!();
+                println!("   The migration was created but not applied.");
+                return Err(CliError::new(
+                    ExitCode::Usage,
+                    format!(
+                        "Environment '{}' requires confirmation",
+                        env.as_deref().unwrap_or_default()
+                    ),
+                )
+                .with_hint("Re-run with --yes to apply"));
             }
 
             // Apply migration
             println!();
             println!("Applying migration...");
 
-            // Use transaction for atomicity
-            client.begin().expect("Failed to begin transaction");
+            run_hooks(
+                engine.config(),
+                stratus::hooks::PRE_MIGRATE,
+                &[("STRATUS_MIGRATION_IDS", migration.meta.id.clone())],
+            )?;
 
-            match client.execute(&up_sql) {
-                Ok(_) => {
-                    client.commit().expect("Failed to commit");
-                    println!("✓ Applied migration successfully");
-                }
-                Err(e) => {
-                    let _ = client.rollback();
-                    eprintln!("\n✗ Error applying migration: {}", e);
-                    std::process::exit(1);
-                }
-            }
+            let mut bar = statement_progress(0);
+            let elapsed = engine.apply(&mut *client, &[&migration], &mut bar)?;
+            bar.0.finish_and_clear();
+            println!(
+                "{} Applied migration successfully in {:.2?}",
+                Mark::Ok,
+                elapsed
+            );
+            save_snapshot(&engine);
 
             println!();
             println!("Next steps:");
-            println!(
-                "  1. Review migration files in: {}",
-                migrations_dir.display()
-            );
+            println!("  1. Review migration files in: {}", migrations_dir);
             println!("  2. Edit up.sql/down.sql if needed");
             println!("  3. Commit and create PR for team review");
             println!("  4. After PR merge, run: stratus deploy");
@@ -695,166 +3548,248 @@ fn main() {
 
         // ==================== Deploy Command ====================
         Commands::Deploy {
-            schema: schema_override,
+            schema,
             env,
             yes,
-            datasource: datasource_override,
-            url: url_override,
+            override_policy,
+            datasource,
+            url,
+            concurrency,
         } => {
-            // Try to load configuration
-            let config = stratus::config::ConfigManager::load(None).ok();
-
-            // Determine schema path
-            let schema_path = if let Some(ref s) = schema_override {
-                s.clone()
-            } else if let Some(ref cfg) = config {
-                cfg.get_schema_path()
-            } else {
-                PathBuf::from("schema.json")
-            };
-
-            // Determine migrations directory
-            let migrations_dir = if let Some(ref cfg) = config {
-                cfg.get_migrations_path()
-            } else {
-                PathBuf::from("migrations")
-            };
-
-            // Determine database URL
-            let db_url = if let Some(ds_name) = &datasource_override {
-                if let Some(ref cfg) = config {
-                    let ds = cfg.get_datasource(ds_name).unwrap_or_else(|| {
-                        eprintln!("Error: Datasource '{}' not found in stratus.json", ds_name);
-                        std::process::exit(1);
-                    });
-                    url_override.clone().unwrap_or(ds.url.clone())
-                } else {
-                    url_override.clone().unwrap_or_else(|| {
-                        eprintln!(
-                            "Error: stratus.json not found. Use --url or create stratus.json"
-                        );
-                        std::process::exit(1);
-                    })
-                }
-            } else if let Some(ref url) = url_override {
-                url.clone()
-            } else if let Some(ref _cfg) = config {
-                eprintln!("Error: Datasource required. Use --datasource flag or configure in stratus.json");
-                std::process::exit(1);
-            } else {
-                std::env::var("DATABASE_URL").ok().unwrap_or_else(|| {
-                    eprintln!(
-                        "Error: No database URL provided. Use --url or set DATABASE_URL env var."
-                    );
-                    std::process::exit(1);
-                })
-            };
+            let engine = engine(config_file, schema, datasource, env.clone(), url, no_cache)?;
 
-            let env_name = env.unwrap_or_else(|| "unknown".to_string());
-            println!("\n🚀  Stratus Deploy");
-            println!("{}", "=".repeat(50));
+            let env_name = env.clone().unwrap_or_else(|| "unknown".to_string());
+            stratus::output::header("🚀", "Stratus Deploy");
             println!("Environment: {}", env_name);
-            println!("Schema: {}", schema_path.display());
-            println!("Migrations: {}", migrations_dir.display());
-            if let Some(ref ds) = datasource_override {
+            println!("Schema: {}", engine.schema_path().display());
+            println!("Migrations: {}", engine.migrations_dir().display());
+            if let Some(ds) = engine.datasource() {
                 println!("Datasource: {}", ds);
             }
-            if url_override.is_some() {
+            if engine.has_url_override() {
                 println!("URL: (CLI override)");
             }
             println!();
 
-            // Load migrations
-            let migrations = stratus::migrate::load_migrations(&migrations_dir)
-                .expect("Failed to load migrations");
+            // Report the outcome to the webhooks under `notifications`
+            let started = std::time::Instant::now();
+            let notify = |migrations: &[&stratus::migrate::Migration], error: Option<&CliError>| {
+                let Some(config) = engine.config() else {
+                    return;
+                };
+                let summary = stratus::notify::DeploySummary {
+                    environment: env_name.clone(),
+                    datasource: engine.datasource().map(str::to_string),
+                    outcome: if error.is_some() {
+                        stratus::config::DeployOutcome::Failure
+                    } else {
+                        stratus::config::DeployOutcome::Success
+                    },
+                    migrations: migrations
+                        .iter()
+                        .map(|m| format!("{}_{}", m.meta.id, m.meta.name))
+                        .collect(),
+                    duration_ms: started.elapsed().as_millis(),
+                    error: error.map(|e| e.message.clone()),
+                };
+                for failure in stratus::notify::notify_deploy(config.notifications(), &summary) {
+                    println!("{} {}", Mark::Warn, failure);
+                }
+            };
 
-            // Filter pending migrations (draft or reviewed, not applied)
-            let pending_migrations: Vec<&stratus::migrate::Migration> = migrations
-                .iter()
-                .filter(|m| !m.applied && m.meta.status != "failed")
-                .collect();
+            // For production (or environments requiring it), require --yes
+            let is_production = env_name.to_lowercase() == "production";
+            let requires_confirmation = engine
+                .environment()
+                .map(|e| e.require_confirmation)
+                .unwrap_or(is_production);
+            let confirm_deploy = |prompt: String| -> CliResult {
+                if requires_confirmation && is_production {
+                    println!("{} This is a PRODUCTION deployment!", Mark::Warn);
+                }
+                if requires_confirmation && !confirm(&prompt, yes)? {
+                    return Err(CliError::new(
+                        ExitCode::Usage,
+                        format!("Environment '{}' requires confirmation", env_name),
+                    )
+                    .with_hint(format!(
+                        "Re-run with --yes: stratus deploy --env={} --yes",
+                        env_name
+                    )));
+                }
+                Ok(())
+            };
+
+            // A multi-target datasource deploys to each of its databases
+            let targets = engine
+                .deploy()
+                .targets()
+                .map_err(CliError::from)
+                .inspect_err(|e| notify(&[], Some(e)))?;
+            if let Some(mut targets) = targets {
+                if let Some(concurrency) = concurrency {
+                    targets.concurrency = concurrency as usize;
+                }
+                println!(
+                    "Targets: {} ({} at a time)",
+                    targets.urls.len(),
+                    targets.concurrency
+                );
+                println!();
+                if targets.urls.is_empty() {
+                    println!("{} No targets to deploy to.", Mark::Ok);
+                    notify(&[], None);
+                    return Ok(());
+                }
+                confirm_deploy(format!(
+                    "Apply pending migrations to {} target(s) of '{}'?",
+                    targets.urls.len(),
+                    env_name
+                ))?;
+
+                // Each target has its own pending set, so hooks see every local migration
+                let local = engine.load_migrations()?;
+                let ids: Vec<&str> = local.iter().map(|m| m.meta.id.as_str()).collect();
+                run_hooks(
+                    engine.config(),
+                    stratus::hooks::PRE_MIGRATE,
+                    &[("STRATUS_MIGRATION_IDS", ids.join(","))],
+                )
+                .inspect_err(|e| notify(&[], Some(e)))?;
+
+                println!("Applying migrations...");
+                let reports = engine
+                    .deploy()
+                    .override_policy(override_policy)
+                    .run_targets(&targets, |report| match &report.result {
+                        Ok(r) if r.applied.is_empty() => {
+                            println!("  {} {} up to date", Mark::Skip, report.url)
+                        }
+                        Ok(r) => println!(
+                            "  {} {} applied {} migration(s) in {:.2?}",
+                            Mark::Ok,
+                            report.url,
+                            r.applied.len(),
+                            r.elapsed
+                        ),
+                        Err(e) => println!("  {} {} {}", Mark::Fail, report.url, e),
+                    });
+
+                let mut applied: Vec<&stratus::migrate::Migration> = Vec::new();
+                for migration in reports
+                    .iter()
+                    .filter_map(|r| r.result.as_ref().ok())
+                    .flat_map(|r| &r.applied)
+                {
+                    if !applied.iter().any(|m| m.meta.id == migration.meta.id) {
+                        applied.push(migration);
+                    }
+                }
+                applied.sort_by(|a, b| a.meta.id.cmp(&b.meta.id));
+                let failed = reports.iter().filter(|r| r.result.is_err()).count();
+                println!();
+                println!(
+                    "Deployed {} of {} targets in {:.2?}",
+                    reports.len() - failed,
+                    reports.len(),
+                    started.elapsed()
+                );
+                if failed > 0 {
+                    let error = CliError::new(
+                        ExitCode::Failure,
+                        format!("{} of {} targets failed", failed, reports.len()),
+                    )
+                    .with_hint("Fix the failed targets and re-run; up-to-date targets are skipped");
+                    notify(&applied, Some(&error));
+                    return Err(error);
+                }
+                notify(&applied, None);
+                return Ok(());
+            }
 
+            let db_config = engine.db_config()?;
+            let mut client = connect_backend(&db_config).inspect_err(|e| notify(&[], Some(e)))?;
+
+            // Applied migrations must be unchanged, then pending ones: draft or
+            // reviewed, not applied
+            engine
+                .deploy()
+                .verify(&mut *client)
+                .map_err(CliError::from)
+                .inspect_err(|e| notify(&[], Some(e)))?;
+            let pending = engine
+                .deploy()
+                .pending(&mut *client)
+                .map_err(CliError::from)
+                .inspect_err(|e| notify(&[], Some(e)))?;
+            let pending_migrations: Vec<&stratus::migrate::Migration> = pending.iter().collect();
+
+            // An up-to-date database is a successful deploy too
             if pending_migrations.is_empty() {
-                println!("✓ No pending migrations to apply.");
-                return;
+                println!("{} No pending migrations to apply.", Mark::Ok);
+                notify(&[], None);
+                return Ok(());
             }
 
             println!("Found {} pending migrations:", pending_migrations.len());
             for m in &pending_migrations {
                 let status = if m.meta.status == "reviewed" {
-                    "✓ reviewed"
+                    format!("{} reviewed", Mark::Ok)
                 } else {
-                    "○ draft"
+                    format!("{} draft", Mark::Skip)
                 };
                 println!("  [{}] {} {}", m.meta.id, m.meta.name, status);
             }
             println!();
 
-            // For production, require --yes or manual confirmation
-            let is_production = env_name.to_lowercase() == "production";
-            if is_production && !yes {
-                println!("⚠️  This is a PRODUCTION deployment!");
-                println!();
-                println!("To confirm, run with --yes flag:");
-                println!("  stratus deploy --env=production --yes");
-                std::process::exit(1);
-            }
-
-            // Connect to database
-            println!("Connecting to database...");
-            let db_config = stratus::db::DbConfig {
-                connection_string: db_url.clone(),
-                max_connections: 5,
-            };
-
-            let mut client = match stratus::migrate::StratusClient::connect(&db_config) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Error: Failed to connect to database: {}", e);
-                    std::process::exit(1);
-                }
-            };
-            println!("Connected successfully.");
-            println!();
+            let changes: Vec<stratus::policy::Change> = pending_migrations
+                .iter()
+                .map(|m| stratus::policy::Change::migration(m))
+                .collect::<Result<_, _>>()?;
+            enforce_policies(
+                engine.policy_violations(&changes),
+                env.as_deref(),
+                override_policy,
+            )
+            .inspect_err(|e| notify(&pending_migrations, Some(e)))?;
+
+            confirm_deploy(format!(
+                "Apply {} migration(s) to '{}'?",
+                pending_migrations.len(),
+                env_name
+            ))?;
 
             // Apply migrations in transaction
             println!("Applying migrations...");
 
-            let mut applied_count = 0;
-            let mut failed = false;
-
-            for m in pending_migrations {
-                print!("  [{}] {}... ", m.meta.id, m.meta.name);
-
-                // Begin transaction for each migration
-                client.begin().expect("Failed to begin transaction");
-
-                match client.execute(&m.up_sql) {
-                    Ok(_) => {
-                        client.commit().expect("Failed to commit");
-                        println!("OK");
-                        applied_count += 1;
-                    }
-                    Err(e) => {
-                        let _ = client.rollback();
-                        println!("FAILED");
-                        eprintln!("\n✗ Error applying migration {}: {}", m.meta.name, e);
-                        failed = true;
-                        break;
-                    }
-                }
-            }
-
+            let pending_ids: Vec<&str> = pending_migrations
+                .iter()
+                .map(|m| m.meta.id.as_str())
+                .collect();
+            run_hooks(
+                engine.config(),
+                stratus::hooks::PRE_MIGRATE,
+                &[("STRATUS_MIGRATION_IDS", pending_ids.join(","))],
+            )
+            .inspect_err(|e| notify(&pending_migrations, Some(e)))?;
+
+            // Each migration runs in its own transaction
+            let mut bar = statement_progress(0);
+            let elapsed = engine
+                .apply(&mut *client, &pending_migrations, &mut bar)
+                .map_err(CliError::from)
+                .inspect_err(|e| notify(&pending_migrations, Some(e)))?;
+            bar.0.finish_and_clear();
             println!();
+            notify(&pending_migrations, None);
 
-            if failed {
-                eprintln!("✗ Deployment failed!");
-                eprintln!("   Some migrations were not applied.");
-                eprintln!("   Check the errors above and resolve manually.");
-                std::process::exit(1);
-            }
-
-            println!("✓ Successfully applied {} migration(s)", applied_count);
+            println!(
+                "{} Successfully applied {} migration(s) in {:.2?}",
+                Mark::Ok,
+                pending_migrations.len(),
+                elapsed
+            );
+            save_snapshot(&engine);
             println!();
             println!("Next steps:");
             println!("  1. Verify the application works correctly");
@@ -872,213 +3807,589 @@ fn main() {
                     skip_generate: _,
                     accept_data_loss,
                     force_reset,
+                    print_sql_only,
+                    datasource,
+                    env,
+                    url,
+                    yes,
+                    override_policy,
+                } => {
+                    // Resolve URL and safety settings through --env when configured
+                    let engine = engine(config_file, schema, datasource, env, url, no_cache)?;
+                    let push = engine
+                        .push()
+                        .force_reset(force_reset)
+                        .override_policy(override_policy);
+                    let db_config = engine.db_config()?;
+
+                    // Only the SQL goes to stdout
+                    if print_sql_only {
+                        let mut client = stratus::db::connect(&db_config)?;
+                        let plan = push.plan(&mut *client)?;
+                        stratus::output::print_sql(&plan.sql());
+                        return Ok(());
+                    }
+
+                    stratus::output::header("🌱", "DB Push");
+                    println!("Schema: {}", engine.schema_path().display());
+                    println!();
+
+                    let mut client = connect_backend(&db_config)?;
+                    println!("Introspecting current database schema...");
+                    let plan = push.plan(&mut *client)?;
+                    println!("Found {} tables in database.", plan.db_schema.tables.len());
+                    println!("Tables: {}", plan.schema.tables.len());
+                    println!();
+
+                    if !plan.reset_tables.is_empty() {
+                        println!("{} Force reset mode - these tables and ALL their data will be dropped:", Mark::Warn);
+                        for table_name in &plan.reset_tables {
+                            println!("  - {}", table_name);
+                        }
+                        println!();
+                    }
+                    stratus::db::print_diff_summary(&plan.diff);
+
+                    if !plan.has_changes() {
+                        println!("{} Database schema is in sync.", Mark::Ok);
+                        return Ok(());
+                    }
+
+                    let sql = plan.sql();
+                    println!("SQL to execute:");
+                    stratus::output::print_sql_preview(&sql);
+                    println!();
+
+                    enforce_policies(
+                        engine.policy_violations(&[stratus::policy::Change::unreviewed(
+                            "db push", &sql,
+                        )]),
+                        engine.env(),
+                        override_policy,
+                    )?;
+
+                    for confirmation in plan.confirmations() {
+                        let (prompt, assume_yes, error) = match confirmation {
+                            stratus::engine::PushConfirmation::Reset(tables) => (
+                                format!("Drop {} table(s)?", tables.len()),
+                                yes,
+                                CliError::new(ExitCode::Usage, "Force reset requires confirmation")
+                                    .with_hint("Confirm interactively, or pass --yes"),
+                            ),
+                            stratus::engine::PushConfirmation::DataLoss(_) => {
+                                println!("{} Data loss would occur; this will drop:", Mark::Warn);
+                                for table in &plan.diff.drop_tables {
+                                    println!("  - table {}", table);
+                                }
+                                for (table, columns) in &plan.diff.drop_columns {
+                                    for col in columns {
+                                        println!("  - column {}.{}", table, col);
+                                    }
+                                }
+                                println!();
+                                (
+                                    "Push these changes and lose data?".to_string(),
+                                    accept_data_loss || yes,
+                                    CliError::new(ExitCode::Usage, "Push would lose data")
+                                        .with_hint(
+                                            "Confirm interactively, or pass --accept-data-loss",
+                                        ),
+                                )
+                            }
+                            stratus::engine::PushConfirmation::Environment(env) => (
+                                format!("Push these changes to '{}'?", env),
+                                yes,
+                                CliError::new(
+                                    ExitCode::Usage,
+                                    format!("Environment '{}' requires confirmation", env),
+                                )
+                                .with_hint("Confirm interactively, or pass --yes"),
+                            ),
+                        };
+                        if !confirm(&prompt, assume_yes)? {
+                            return Err(error);
+                        }
+                    }
+
+                    println!("\n{}Executing DDL...", stratus::output::icon("🚀"));
+                    println!("{}", "-".repeat(50));
+
+                    let mut bar = statement_progress(0);
+                    let elapsed = push
+                        .run(&mut *client, &plan, &mut bar)
+                        .inspect_err(|_| bar.0.abandon())?;
+                    bar.0.finish_and_clear();
+                    println!(
+                        "\n{} Successfully pushed schema to database in {:.2?}.",
+                        Mark::Ok,
+                        elapsed
+                    );
+
+                    println!();
+                    println!("Tables created/updated:");
+                    for table in &plan.diff.create_tables {
+                        println!("  + {}", table);
+                    }
+                    for (table, columns) in &plan.diff.create_columns {
+                        for col in columns {
+                            println!("  + {}.{}", table, col.name);
+                        }
+                    }
+                }
+
+                DbCommands::DbPull {
+                    output,
+                    datasource,
+                    env,
+                    url,
+                } => {
+                    let engine = engine(config_file, output, datasource, env, url, no_cache)?;
+                    let output_path = engine.schema_path().to_path_buf();
+
+                    stratus::output::header("🔄", "DB Pull");
+                    println!("Output: {}", output_path.display());
+
+                    let mut client = connect_backend(&engine.db_config()?)?;
+
+                    // Introspect schema
+                    println!("Introspecting database schema...");
+                    let db_schema = engine.introspect(&mut *client)?;
+
+                    // Convert to JSON schema format
+                    let json_schema = serde_json::to_string_pretty(&db_schema)
+                        .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?;
+                    write_output(&output_path, &json_schema)?;
+
+                    println!("{} Pulled schema from database.", Mark::Ok);
+                    println!();
+                    println!("Found {} tables:", db_schema.tables.len());
+                    for (table_name, table) in &db_schema.tables {
+                        println!("  + {} ({} columns)", table_name, table.columns.len());
+                    }
+
+                    if !db_schema.enums.is_empty() {
+                        println!();
+                        println!("Found {} enums:", db_schema.enums.len());
+                        for (enum_name, values) in &db_schema.enums {
+                            println!("  + {} = {:?}", enum_name, values);
+                        }
+                    }
+                }
+
+                DbCommands::DbLoad {
+                    table,
+                    file,
+                    schema,
+                    datasource,
+                    env,
                     url,
+                    batch_size,
                 } => {
-                    let schema_path = schema.unwrap_or_else(|| PathBuf::from("schema.json"));
-                    let schema_str =
-                        fs::read_to_string(&schema_path).expect("Failed to read schema file");
-                    let parsed_schema: stratus::schema::Schema =
-                        serde_json::from_str(&schema_str).expect("Failed to parse schema");
-
-                    println!("\n🌱  DB Push");
-                    println!("{}", "=".repeat(50));
-                    println!("Schema: {}", schema_path.display());
-                    println!("Tables: {}", parsed_schema.tables.len());
-                    println!();
+                    let engine = engine(config_file, schema, datasource, env, url, no_cache)?;
+                    let parsed_schema = engine.load_schema()?;
+                    let data_error = |e: stratus::data::DataError| {
+                        CliError::new(ExitCode::DataErr, format!("{}: {}", file.display(), e))
+                    };
+                    let format = stratus::data::DataFormat::from_path(&file).map_err(data_error)?;
+
+                    // Binary COPY data goes to the server untouched
+                    if format == stratus::data::DataFormat::Binary {
+                        let columns: Vec<String> = parsed_schema
+                            .tables
+                            .get(&table)
+                            .ok_or_else(|| {
+                                data_error(stratus::data::DataError::UnknownTable(table.clone()))
+                            })?
+                            .columns
+                            .keys()
+                            .cloned()
+                            .collect();
+                        let mut input = fs::File::open(&file)
+                            .map(std::io::BufReader::new)
+                            .map_err(|e| {
+                                CliError::new(
+                                    ExitCode::NoInput,
+                                    format!("Cannot read {}: {}", file.display(), e),
+                                )
+                            })?;
+                        stratus::output::header("📥", "DB Load");
+                        println!("File: {}", file.display());
+                        println!("Table: {}", table);
+                        println!();
 
-                    // Get database URL
-                    let db_url = url.or_else(|| std::env::var("DATABASE_URL").ok());
-                    if db_url.is_none() {
-                        eprintln!("Error: No database URL provided. Use --url or set DATABASE_URL env var.");
-                        std::process::exit(1);
+                        let mut client = connect(&engine.db_config()?)?;
+                        let bar = row_progress(None);
+                        let loaded = client.copy_in(
+                            &table,
+                            &columns,
+                            stratus::db::CopyFormat::Binary,
+                            &mut input,
+                            |count| copy_progress(&bar, count),
+                        )?;
+                        bar.finish_and_clear();
+                        println!("{} Loaded {} rows into {}", Mark::Ok, loaded.rows, table);
+                        return Ok(());
                     }
-                    let db_url = db_url.unwrap();
 
-                    println!("Connecting to database...");
-                    let db_config = stratus::db::DbConfig {
-                        connection_string: db_url.clone(),
-                        max_connections: 5,
-                    };
-
-                    let mut client = match stratus::db::StratusClient::connect(&db_config) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            eprintln!("Error: Failed to connect to database: {}", e);
-                            std::process::exit(1);
-                        }
-                    };
+                    let records = stratus::data::Records::parse(&read_input(&file)?, format)
+                        .map_err(data_error)?;
 
-                    println!("Connected successfully.");
+                    stratus::output::header("📥", "DB Load");
+                    println!("File: {}", file.display());
+                    println!("Table: {}", table);
                     println!();
 
-                    // Force reset mode - drop all tables and recreate
-                    if force_reset {
-                        println!("⚠️  Force reset mode - dropping all tables!");
+                    let prepared = stratus::data::prepare(records, &table, &parsed_schema)
+                        .map_err(data_error)?;
+                    let mut client = connect(&engine.db_config()?)?;
+                    let bar = row_progress(Some(prepared.rows.len() as u64));
+                    let loaded = client.copy_rows(
+                        &table,
+                        &prepared.columns,
+                        &prepared.rows,
+                        batch_size,
+                        |count| copy_progress(&bar, count),
+                    )?;
+                    bar.finish_and_clear();
+                    println!("{} Loaded {} rows into {}", Mark::Ok, loaded, table);
+
+                    if !prepared.rejected.is_empty() {
                         println!();
-
-                        // Drop all existing tables
-                        for (table_name, _) in &parsed_schema.tables {
-                            let drop_sql = format!("DROP TABLE IF EXISTS {} CASCADE;", table_name);
-                            print!("  Dropping {}... ", table_name);
-                            if let Err(e) = client.execute(&drop_sql) {
-                                println!("FAILED: {}", e);
-                            } else {
-                                println!("OK");
-                            }
+                        println!("{} Rejected {} rows:", Mark::Warn, prepared.rejected.len());
+                        for rejected in prepared.rejected.iter().take(20) {
+                            println!("  ! line {}: {}", rejected.line, rejected.reason);
                         }
-                        println!();
+                        if prepared.rejected.len() > 20 {
+                            println!("  ... and {} more", prepared.rejected.len() - 20);
+                        }
+                        return Err(CliError::new(
+                            ExitCode::DataErr,
+                            format!("{} rows were not loaded", prepared.rejected.len()),
+                        )
+                        .with_hint("Fix the rejected rows and load them again"));
                     }
+                }
 
-                    // Get current database schema
-                    println!("Introspecting current database schema...");
-                    let db_schema = match client.get_schema() {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("Error: Failed to introspect database: {}", e);
-                            std::process::exit(1);
+                DbCommands::DbExport {
+                    table,
+                    format,
+                    filter,
+                    output,
+                    schema,
+                    datasource,
+                    env,
+                    url,
+                    fetch_size,
+                } => {
+                    let engine = engine(config_file, schema, datasource, env, url, no_cache)?;
+                    let parsed_schema = engine.load_schema()?;
+                    let format = stratus::data::DataFormat::from_name(&format)
+                        .map_err(|e| CliError::new(ExitCode::Usage, e.to_string()))?;
+                    let export = stratus::data::Export::new(
+                        &table,
+                        &parsed_schema,
+                        format,
+                        filter.as_deref(),
+                    )
+                    .map_err(|e| CliError::new(ExitCode::DataErr, e.to_string()))?;
+                    let db_config = engine.db_config()?;
+
+                    // Without --output only the rows go to stdout
+                    let (mut client, mut out): (_, Box<dyn std::io::Write>) = match &output {
+                        Some(path) => {
+                            stratus::output::header("📤", "DB Export");
+                            println!("Table: {}", table);
+                            println!("Output: {}", path.display());
+                            println!();
+                            let file = fs::File::create(path).map_err(|e| {
+                                CliError::new(
+                                    ExitCode::CantCreate,
+                                    format!("Cannot write {}: {}", path.display(), e),
+                                )
+                            })?;
+                            (
+                                connect(&db_config)?,
+                                Box::new(std::io::BufWriter::new(file)),
+                            )
                         }
+                        None => (
+                            stratus::db::StratusClient::connect(&db_config)?,
+                            Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+                        ),
                     };
-                    println!("Found {} tables in database.", db_schema.tables.len());
-                    println!();
 
-                    // Compare schemas
-                    let diff = stratus::db::compare_schemas(&parsed_schema, &db_schema);
-                    stratus::db::print_diff_summary(&diff);
+                    let write_failed = |e: std::io::Error| {
+                        CliError::new(ExitCode::CantCreate, format!("Cannot write export: {}", e))
+                    };
+                    // The bar shares the terminal with the rows unless they go to a file
+                    let bar = match output {
+                        Some(_) => row_progress(None),
+                        None => indicatif::ProgressBar::hidden(),
+                    };
+                    let exported = match export.copy_format() {
+                        // CSV and binary are written by the server
+                        Some(copy_format) => {
+                            match client.copy_out(&export.sql, copy_format, &mut out, |count| {
+                                copy_progress(&bar, count)
+                            }) {
+                                Ok(count) => count.rows,
+                                Err(stratus::db::DbError::Io(e)) => return Err(write_failed(e)),
+                                Err(e) => return Err(e.into()),
+                            }
+                        }
+                        None => {
+                            let mut write_error = None;
+                            export.write_header(&mut out).map_err(write_failed)?;
+                            let exported =
+                                client.query_each_values(&export.sql, fetch_size, |row| {
+                                    match export.write_row(&mut out, &row) {
+                                        Ok(()) => {
+                                            bar.inc(1);
+                                            std::ops::ControlFlow::Continue(())
+                                        }
+                                        Err(e) => {
+                                            write_error = Some(e);
+                                            std::ops::ControlFlow::Break(())
+                                        }
+                                    }
+                                })?;
+                            if let Some(e) = write_error {
+                                return Err(write_failed(e));
+                            }
+                            exported
+                        }
+                    };
+                    bar.finish_and_clear();
+                    out.flush().map_err(write_failed)?;
 
-                    if !diff.has_changes() {
-                        println!("✓ Database schema is in sync.");
-                        return;
+                    if let Some(path) = &output {
+                        println!(
+                            "{} Exported {} rows to {}",
+                            Mark::Ok,
+                            exported,
+                            path.display()
+                        );
                     }
+                }
 
-                    // Check for data loss
-                    if !diff.data_loss_warning.is_empty() && !accept_data_loss {
-                        println!("\n⚠️  Data loss would occur!");
-                        println!("Use --accept-data-loss to proceed anyway.");
-                        std::process::exit(1);
+                DbCommands::DbSample {
+                    from,
+                    from_datasource,
+                    from_url,
+                    to,
+                    datasource,
+                    url,
+                    schema,
+                    truncate,
+                    yes,
+                } => {
+                    let source = engine(
+                        config_file,
+                        schema.clone(),
+                        from_datasource,
+                        from,
+                        from_url,
+                        no_cache,
+                    )?;
+                    let target =
+                        engine(config_file, schema, datasource, to.clone(), url, no_cache)?;
+                    let source_config = source.db_config()?;
+                    let target_config = target.db_config()?;
+                    if source_config.connection_string.trim_end_matches('/')
+                        == target_config.connection_string.trim_end_matches('/')
+                    {
+                        return Err(CliError::new(
+                            ExitCode::Usage,
+                            "The sample source and target are the same database",
+                        ));
                     }
 
-                    // Execute DDL
-                    if diff.sql.is_empty() {
-                        println!("No DDL to execute.");
-                        return;
-                    }
+                    let parsed_schema = target.load_schema()?;
+                    let sample_config = target.config().and_then(|c| c.sample());
+                    let mut sampler = stratus::data::Sampler::new(
+                        &parsed_schema,
+                        sample_config,
+                        stratus::data::mask_secret(sample_config).as_deref(),
+                    )
+                    .map_err(|e| CliError::new(ExitCode::Config, e.to_string()))?;
 
-                    // Check for data loss
-                    if !diff.data_loss_warning.is_empty() && !accept_data_loss {
-                        println!("\n⚠️  Data loss would occur!");
-                        println!("Use --accept-data-loss to proceed anyway.");
-                        std::process::exit(1);
-                    }
+                    stratus::output::header("🧪", "DB Sample");
+                    println!("Tables: {}", sampler.tables.len());
+                    println!();
 
-                    // Execute DDL
-                    if diff.sql.is_empty() {
-                        println!("No DDL to execute.");
-                        return;
+                    if target.environment().is_some_and(|e| e.require_confirmation)
+                        && !confirm(
+                            &format!(
+                                "Copy sampled rows into '{}'?",
+                                to.as_deref().unwrap_or_default()
+                            ),
+                            yes,
+                        )?
+                    {
+                        return Err(CliError::new(
+                            ExitCode::Usage,
+                            format!(
+                                "Environment '{}' requires confirmation",
+                                to.as_deref().unwrap_or_default()
+                            ),
+                        )
+                        .with_hint("Confirm interactively, or pass --yes"));
                     }
 
-                    // Check for data loss
-                    if !diff.data_loss_warning.is_empty() && !accept_data_loss {
-                        println!("\n⚠️  Data loss would occur!");
-                        println!("Use --accept-data-loss to proceed anyway.");
-                        std::process::exit(1);
+                    let mut source_client = connect(&source_config)?;
+                    let mut target_client = connect(&target_config)?;
+                    if truncate {
+                        let tables: Vec<String> = sampler
+                            .tables
+                            .iter()
+                            .map(|t| stratus::migrate::quote_ident(&t.name))
+                            .collect();
+                        target_client
+                            .execute(&format!("TRUNCATE {} CASCADE", tables.join(", ")))?;
                     }
 
-                    // Execute DDL
-                    if diff.sql.is_empty() {
-                        println!("No DDL to execute.");
-                        return;
+                    // Masked rows are held per table, so limits bound memory
+                    for index in 0..sampler.tables.len() {
+                        let sql = sampler.select_sql(index);
+                        let mut rows = Vec::new();
+                        source_client.query_each_values(&sql, 1000, |row| {
+                            rows.push(sampler.take(index, row));
+                            std::ops::ControlFlow::Continue(())
+                        })?;
+                        let table = &sampler.tables[index];
+                        let bar = row_progress(Some(rows.len() as u64));
+                        let copied = target_client.copy_rows(
+                            &table.name,
+                            &table.columns,
+                            &rows,
+                            1000,
+                            |count| copy_progress(&bar, count),
+                        )?;
+                        bar.finish_and_clear();
+                        println!("  + {}: {} rows", table.name, copied);
                     }
+                    println!();
+                    println!("{} Sampled {} tables.", Mark::Ok, sampler.tables.len());
+                }
+            }
+        }
 
-                    println!("\n🚀  Executing DDL...");
-                    println!("{}", "-".repeat(50));
-
-                    // Execute in transaction
-                    client.begin().expect("Failed to begin transaction");
+        // ==================== Schema Registry ====================
+        Commands::Schema { command } => {
+            let config = load_config(config_file)?;
+            match command {
+                SchemaCommands::Push {
+                    version,
+                    schema,
+                    registry,
+                    name,
+                } => {
+                    let schema_path = resolve_schema_path(config.as_ref(), schema);
+                    let contents = read_input(&schema_path)?;
+                    stratus::engine::read_schema(&schema_path)?;
+                    let (client, name) = registry_client(config.as_ref(), registry, name)?;
 
-                    match client.execute(&diff.sql) {
-                        Ok(_) => {
-                            client.commit().expect("Failed to commit");
-                            println!("\n✓ Successfully pushed schema to database.");
-                        }
-                        Err(e) => {
-                            let _ = client.rollback();
-                            eprintln!("\n✗ Error executing DDL: {}", e);
-                            std::process::exit(1);
-                        }
+                    let checksum = client.push(&name, &version, &contents)?;
+                    println!(
+                        "{} Published {}@{} (sha256 {})",
+                        Mark::Ok,
+                        name,
+                        version,
+                        &checksum[..12]
+                    );
+                }
+                SchemaCommands::Pull {
+                    version,
+                    output,
+                    registry,
+                    name,
+                } => {
+                    let pinned = config
+                        .as_ref()
+                        .and_then(|c| c.registry())
+                        .and_then(|r| r.version.clone());
+                    let version = version
+                        .or(pinned)
+                        .unwrap_or_else(|| stratus::registry::LATEST.to_string());
+                    let output_path = resolve_schema_path(config.as_ref(), output);
+                    let (client, name) = registry_client(config.as_ref(), registry, name)?;
+
+                    let pulled = client.pull(&name, &version)?;
+                    write_output(&output_path, &pulled.contents)?;
+                    println!(
+                        "{} Pulled {}@{} into {} (sha256 {})",
+                        Mark::Ok,
+                        name,
+                        pulled.version,
+                        output_path.display(),
+                        &pulled.checksum[..12]
+                    );
+                }
+                SchemaCommands::Log => {
+                    let dir = stratus::snapshot::snapshot_dir(config.as_ref());
+                    let snapshots = stratus::snapshot::list(&dir)?;
+                    stratus::output::header("📜", "Schema Log");
+                    println!("Snapshots: {}", dir.display());
+                    println!();
+                    if snapshots.is_empty() {
+                        println!(
+                            "No snapshots yet; sync and deploy save one when schema.json changed."
+                        );
+                        return Ok(());
                     }
 
-                    println!();
-                    println!("Tables created/updated:");
-                    for table in &diff.create_tables {
-                        println!("  + {}", table);
+                    // Each snapshot is summarized against the one before it
+                    let mut lines = Vec::new();
+                    let mut previous: Option<stratus::schema::Schema> = None;
+                    for snapshot in &snapshots {
+                        let schema = snapshot.schema()?;
+                        let summary = match &previous {
+                            Some(previous) => {
+                                change_summary(&stratus::diff::diff_schemas(previous, &schema))
+                            }
+                            None => "first snapshot".to_string(),
+                        };
+                        lines.push(format!(
+                            "{}  {}  {} table(s)  {}",
+                            snapshot.timestamp,
+                            snapshot.time(),
+                            schema.tables.len(),
+                            summary
+                        ));
+                        previous = Some(schema);
                     }
-                    for (table, columns) in &diff.create_columns {
-                        for col in columns {
-                            println!("  + {}.{}", table, col.name);
-                        }
+                    for line in lines.iter().rev() {
+                        println!("  {}", line);
                     }
                 }
-
-                DbCommands::DbPull { output, url } => {
-                    let output_path = output.unwrap_or_else(|| PathBuf::from("schema.json"));
-
-                    println!("\n🔄  DB Pull");
-                    println!("{}", "=".repeat(50));
-                    println!("Output: {}", output_path.display());
-
-                    // Get database URL
-                    let db_url = url.or_else(|| std::env::var("DATABASE_URL").ok());
-                    if db_url.is_none() {
-                        eprintln!("Error: No database URL provided. Use --url or set DATABASE_URL env var.");
-                        std::process::exit(1);
-                    }
-                    let db_url = db_url.unwrap();
-
-                    println!("Connecting to database...");
-                    let db_config = stratus::db::DbConfig {
-                        connection_string: db_url.clone(),
-                        max_connections: 5,
-                    };
-
-                    let mut client = match stratus::db::StratusClient::connect(&db_config) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            eprintln!("Error: Failed to connect to database: {}", e);
-                            std::process::exit(1);
-                        }
-                    };
-
-                    println!("Connected successfully.");
-                    println!();
-
-                    // Introspect schema
-                    println!("Introspecting database schema...");
-                    let db_schema = match client.get_schema() {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("Error: Failed to introspect database: {}", e);
-                            std::process::exit(1);
-                        }
-                    };
-
-                    // Convert to JSON schema format
-                    let json_schema = serde_json::to_string_pretty(&db_schema)
-                        .expect("Failed to serialize schema");
-
-                    fs::write(&output_path, &json_schema).expect("Failed to write schema file");
-
-                    println!("✓ Pulled schema from database.");
-                    println!();
-                    println!("Found {} tables:", db_schema.tables.len());
-                    for (table_name, table) in &db_schema.tables {
-                        println!("  + {} ({} columns)", table_name, table.columns.len());
+                SchemaCommands::Diff { at, schema, format } => {
+                    if !matches!(format.as_str(), "text" | "sql" | "json") {
+                        return Err(CliError::new(
+                            ExitCode::Usage,
+                            format!("Unknown format '{}': use text, sql or json", format),
+                        ));
                     }
-
-                    if !db_schema.enums.is_empty() {
-                        println!();
-                        println!("Found {} enums:", db_schema.enums.len());
-                        for (enum_name, values) in &db_schema.enums {
-                            println!("  + {} = {:?}", enum_name, values);
+                    let dir = stratus::snapshot::snapshot_dir(config.as_ref());
+                    let snapshot = stratus::snapshot::at(&dir, &at)?;
+                    let schema_path = resolve_schema_path(config.as_ref(), schema);
+                    let current = stratus::engine::read_schema(&schema_path)?;
+                    let diff = stratus::diff::diff_schemas(&snapshot.schema()?, &current);
+
+                    match format.as_str() {
+                        "json" => println!(
+                            "{}",
+                            serde_json::to_string_pretty(&diff.with_sql())
+                                .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?
+                        ),
+                        "sql" => stratus::output::print_sql(&diff.sql()),
+                        _ => {
+                            stratus::output::header("📐", "Schema Diff");
+                            println!("From: {} ({})", snapshot.timestamp, snapshot.time());
+                            println!("To:   {}", schema_path.display());
+                            stratus::db::print_diff_summary(&diff);
+                            if diff.has_changes() {
+                                stratus::output::print_sql_preview(&diff.sql());
+                            }
                         }
                     }
                 }
@@ -1092,260 +4403,567 @@ fn main() {
                 name,
                 skip_generate: _,
                 create_only,
+                datasource,
                 url,
+                shadow_database_url,
             } => {
-                let schema_path = schema.unwrap_or_else(|| PathBuf::from("schema.json"));
-                let migrations_dir = PathBuf::from("migrations");
+                let engine = engine(config_file, schema, datasource, None, url, no_cache)?;
+                let mut dev = engine.dev();
+                if let Some(name) = name {
+                    dev = dev.name(name);
+                }
+                if let Some(url) = shadow_database_url {
+                    dev = dev.shadow_database_url(url);
+                }
+                let migrations_dir = engine.migrations_dir();
 
-                println!("\n🛠️  Migrate Dev");
-                println!("{}", "=".repeat(50));
-                println!("Schema: {}", schema_path.display());
+                stratus::output::header("🛠️", "Migrate Dev");
+                println!("Schema: {}", engine.schema_path().display());
                 println!("Migrations: {}", migrations_dir.display());
                 println!();
 
-                // Get database URL
-                let db_url = url.or_else(|| std::env::var("DATABASE_URL").ok());
-                let db_config = if let Some(url) = db_url {
-                    Some(stratus::db::DbConfig {
-                        connection_string: url,
-                        max_connections: 5,
-                    })
-                } else {
-                    None
-                };
-
-                // Load schema
-                let schema_str =
-                    fs::read_to_string(&schema_path).expect("Failed to read schema file");
-                let parsed_schema: stratus::schema::Schema =
-                    serde_json::from_str(&schema_str).expect("Failed to parse schema");
-
-                // Load existing migrations
-                let existing_migrations = stratus::migrate::load_migrations(&migrations_dir)
-                    .expect("Failed to load migrations");
-
+                // Fail on a bad schema before anything is written
+                engine.load_schema()?;
+                let existing_migrations = engine.load_migrations()?;
                 println!("Existing migrations: {}", existing_migrations.len());
-
-                // Show status
                 stratus::migrate::print_migration_status(&existing_migrations);
 
                 // If create_only flag, just create an empty migration
                 if create_only {
-                    let migration_name = name.unwrap_or_else(|| "empty-migration".to_string());
-                    let up_sql = "-- Empty migration\n-- Add your SQL here";
-                    let down_sql = "-- Empty migration rollback";
+                    let m = dev.create_empty()?;
+                    println!(
+                        "{} Created empty migration: {}_{}",
+                        Mark::Ok,
+                        m.meta.id,
+                        m.meta.name
+                    );
+                    return Ok(());
+                }
 
-                    match stratus::migrate::create_migration(
-                        &migrations_dir,
-                        &migration_name,
-                        up_sql,
-                        down_sql,
-                        "postgresql",
-                        None,
-                    ) {
-                        Ok(m) => {
-                            println!("✓ Created empty migration: {}_{}", m.meta.id, m.meta.name);
-                        }
-                        Err(e) => {
-                            eprintln!("Error creating migration: {}", e);
-                        }
+                // Need database connection for full migration workflow
+                let db_config = engine.db_config().map_err(|e| match e {
+                    stratus::engine::EngineError::MissingUrl => missing_url().with_hint(
+                        "Dev mode needs a database: pass --url, --datasource or set DATABASE_URL",
+                    ),
+                    e => e.into(),
+                })?;
+                let mut client = connect_backend(&db_config)?;
+
+                println!("Introspecting current database schema...");
+                if dev.shadow_url().is_some() {
+                    println!("Replaying migrations on shadow database...");
+                }
+                let plan = dev.plan(&mut *client)?;
+                if let Some(shadow) = &plan.shadow {
+                    println!(
+                        "{} Replayed {} migration(s) cleanly.",
+                        Mark::Ok,
+                        shadow.replayed
+                    );
+                    if shadow.drift.has_changes() {
+                        println!(
+                            "{} Drift detected: the database differs from the migration history.",
+                            Mark::Warn
+                        );
+                        stratus::db::print_diff_summary(&shadow.drift);
                     }
-                    return;
+                    println!();
                 }
 
-                // Need database connection for full migration workflow
-                if db_config.is_none() {
-                    eprintln!(
-                        "Error: No database URL provided. Use --url or set DATABASE_URL env var."
+                stratus::db::print_diff_summary(&plan.diff);
+                if !plan.diff.has_changes() {
+                    println!(
+                        "{} Database schema is in sync. No migration needed.",
+                        Mark::Ok
                     );
-                    eprintln!("For dev mode, a database connection is required.");
-                    std::process::exit(1);
+                    return Ok(());
                 }
 
-                let mut db_config = db_config.unwrap();
-                let mut client = match stratus::db::StratusClient::connect(&db_config) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Error: Failed to connect to database: {}", e);
-                        std::process::exit(1);
-                    }
-                };
+                let m = plan.write_migration()?;
+                println!();
+                println!(
+                    "{} Created migration: {}_{}",
+                    Mark::Ok,
+                    m.meta.id,
+                    m.meta.name
+                );
+                println!(
+                    "  File: {}/{}_{}/up.sql",
+                    migrations_dir.display(),
+                    m.meta.id,
+                    m.meta.name
+                );
 
-                println!("Connected to database.");
+                // Apply pending migrations
                 println!();
+                println!("Applying pending migrations...");
+                let pending = engine.deploy().pending(&mut *client)?;
+                let pending_ids: Vec<&str> = pending.iter().map(|m| m.meta.id.as_str()).collect();
+                run_hooks(
+                    engine.config(),
+                    stratus::hooks::PRE_MIGRATE,
+                    &[("STRATUS_MIGRATION_IDS", pending_ids.join(","))],
+                )?;
+
+                let mut bar = statement_progress(0);
+                let elapsed =
+                    engine.apply(&mut *client, &pending.iter().collect::<Vec<_>>(), &mut bar)?;
+                bar.0.finish_and_clear();
 
-                // Introspect current database schema
-                println!("Introspecting current database schema...");
-                let db_schema = match client.get_schema() {
-                    Ok(s) => s,
-                    Err(e) => {
-                        eprintln!("Error: Failed to introspect database: {}", e);
-                        std::process::exit(1);
-                    }
-                };
+                println!();
+                println!("{} Migration complete in {:.2?}.", Mark::Ok, elapsed);
+            }
 
-                // Compare schemas
-                let diff = stratus::db::compare_schemas(&parsed_schema, &db_schema);
-                stratus::db::print_diff_summary(&diff);
+            MigrateCommands::MigrateDeploy {
+                schema,
+                datasource,
+                env,
+                override_policy,
+                url,
+            } => {
+                let engine = engine(config_file, schema, datasource, env.clone(), url, no_cache)?;
 
-                if !diff.has_changes() {
-                    println!("✓ Database schema is in sync. No migration needed.");
-                    return;
+                stratus::output::header("🚀", "Migrate Deploy");
+                println!("Migrations: {}", engine.migrations_dir().display());
+                if let Some(ds) = engine.datasource() {
+                    println!("Datasource: {}", ds);
                 }
+                println!();
 
-                // Generate migration name
-                let migration_name = name.unwrap_or_else(|| {
-                    stratus::migrate::generate_migration_name(
-                        &db_schema.to_json_schema(),
-                        &parsed_schema,
-                    )
-                });
-
-                // Create migration
-                let down_sql = format!(
-                    "-- Rollback for {}\n{}",
-                    migration_name,
-                    diff.generate_rollback()
-                );
+                let db_config = engine.db_config()?;
+                let mut client = connect_backend(&db_config)?;
 
-                match stratus::migrate::create_migration(
-                    &migrations_dir,
-                    &migration_name,
-                    &diff.sql,
-                    &down_sql,
-                    "postgresql",
-                    None,
-                ) {
-                    Ok(m) => {
-                        println!();
-                        println!("✓ Created migration: {}_{}", m.meta.id, m.meta.name);
-                        println!(
-                            "  File: {}/{}_{}/up.sql",
-                            migrations_dir.display(),
-                            m.meta.id,
-                            m.meta.name
-                        );
-                    }
-                    Err(e) => {
-                        eprintln!("Error creating migration: {}", e);
-                        std::process::exit(1);
+                let pending = engine.deploy().pending(&mut *client)?;
+                if !pending.is_empty() {
+                    println!("Pending migrations: {}", pending.len());
+                    for m in &pending {
+                        println!("  [{}] {}", m.meta.id, m.meta.name);
                     }
+                    println!();
+                    let pending_ids: Vec<&str> =
+                        pending.iter().map(|m| m.meta.id.as_str()).collect();
+                    run_hooks(
+                        engine.config(),
+                        stratus::hooks::PRE_MIGRATE,
+                        &[("STRATUS_MIGRATION_IDS", pending_ids.join(","))],
+                    )?;
+                    println!("Applying migrations...");
                 }
 
-                // Apply pending migrations
-                println!();
-                println!("Applying pending migrations...");
-                let updated_migrations = stratus::migrate::load_migrations(&migrations_dir)
-                    .expect("Failed to reload migrations");
-
-                for migration in updated_migrations.iter().filter(|m| !m.applied) {
-                    print!("  Applying {}... ", migration.meta.name);
-                    match client.execute(&migration.up_sql) {
-                        Ok(_) => {
-                            println!("OK");
-                        }
-                        Err(e) => {
-                            println!("FAILED: {}", e);
-                            eprintln!("Error applying migration: {}", e);
-                            std::process::exit(1);
-                        }
-                    }
+                // Checks the history and the policies, then one transaction
+                // per migration; a failure keeps the ones before it
+                let mut bar = statement_progress(0);
+                let report = engine
+                    .deploy()
+                    .override_policy(override_policy)
+                    .run(&mut *client, &mut bar)?;
+                bar.0.finish_and_clear();
+
+                if report.applied.is_empty() {
+                    println!("{} No pending migrations to apply.", Mark::Ok);
+                    return Ok(());
                 }
-
-                println!();
-                println!("✓ Migration complete.");
-            }
-
-            MigrateCommands::MigrateDeploy { schema: _, url: _ } => {
-                println!("\n🚀  Migrate Deploy");
-                println!("{}", "=".repeat(50));
-                println!("Applying pending migrations to database...");
                 println!();
-                println!("TODO: Implement migration deployment");
+                println!(
+                    "{} Applied {} migration(s) in {:.2?}.",
+                    Mark::Ok,
+                    report.applied.len(),
+                    report.elapsed
+                );
             }
 
             MigrateCommands::MigrateReset {
                 schema,
-                force: _,
-                skip_seed: _,
-                url: _,
+                force,
+                skip_seed,
+                datasource,
+                env,
+                override_policy,
+                url,
             } => {
-                let schema_path = schema.unwrap_or_else(|| PathBuf::from("schema.json"));
-                let migrations_dir = PathBuf::from("migrations");
-
-                println!("\n⚠️  Migrate Reset");
-                println!("{}", "=".repeat(50));
-                println!("This will:");
-                println!("  1. Drop all tables in the database");
-                println!("  2. Re-create all tables from migrations");
-                println!("  3. ALL DATA WILL BE LOST");
+                let engine = engine(config_file, schema, datasource, env, url, no_cache)?;
+                let reset = engine.reset().override_policy(override_policy);
+
+                stratus::output::header("⚠️", "Migrate Reset");
+                println!("Schema: {}", engine.schema_path().display());
+                println!("Migrations: {}", engine.migrations_dir().display());
                 println!();
-                println!("Schema: {}", schema_path.display());
-                println!("Migrations: {}", migrations_dir.display());
+
+                let db_config = engine.db_config()?;
+                let mut client = connect_backend(&db_config)?;
+                let dialect = db_config.provider.dialect();
+
+                // Show exactly what goes away before asking
+                let plan = reset.plan(&mut *client)?;
+                let mut tables: Vec<&String> = plan.db_schema.tables.keys().collect();
+                tables.sort();
+                let mut enums: Vec<&String> = plan.db_schema.enums.keys().collect();
+                enums.sort();
+
+                println!("This will drop, with ALL their data:");
+                for name in &tables {
+                    println!("  - table {}", name);
+                }
+                for name in &enums {
+                    println!("  - type {}", name);
+                }
+                println!(
+                    "  - migration history {}",
+                    engine.migrations_table().qualified_name_in(dialect)
+                );
+                println!("and then re-apply {} migration(s).", plan.migrations.len());
+                println!();
+
+                if !confirm("Reset the database?", force)? {
+                    return Err(
+                        CliError::new(ExitCode::Usage, "Reset requires confirmation")
+                            .with_hint("Confirm interactively, or pass --force"),
+                    );
+                }
+                // --force does not answer for an environment requiring confirmation
+                if let Some(env) = engine
+                    .env()
+                    .filter(|_| engine.environment().is_some_and(|e| e.require_confirmation))
+                {
+                    if !confirm(&format!("Reset '{}'?", env), false)? {
+                        return Err(CliError::new(
+                            ExitCode::Usage,
+                            format!("Environment '{}' requires confirmation", env),
+                        )
+                        .with_hint("Run migrate reset from a terminal to confirm it"));
+                    }
+                }
+
+                println!("Dropping database objects, then applying migrations...");
+                let mut bar = statement_progress(0);
+                let report = reset.run(&mut *client, plan, &mut bar).map_err(|e| {
+                    bar.0.abandon();
+                    let reset_failed =
+                        matches!(e, stratus::engine::EngineError::StatementFailed { .. });
+                    let err = CliError::from(e);
+                    // MySQL commits each DDL statement; the others roll it all back
+                    if reset_failed
+                        && db_config.provider == stratus::config::DatabaseProvider::Mysql
+                    {
+                        err.with_hint(
+                            "MySQL cannot roll back DROP TABLE; tables dropped before the error are gone",
+                        )
+                    } else {
+                        err
+                    }
+                })?;
+                bar.0.finish_and_clear();
                 println!();
-                println!("Use --force to skip confirmation");
+
+                let config = engine.config();
+                let seed = config.and_then(|cfg| cfg.get_seed(engine.env()));
+                match (seed, config) {
+                    (Some(seed), Some(cfg)) if !skip_seed => {
+                        run_seed(cfg, seed, &mut *client, &engine.database_url()?)?
+                    }
+                    (Some(_), _) => println!("Seed: skipped"),
+                    _ => {}
+                }
+
+                println!(
+                    "{} Database reset; applied {} migration(s) in {:.2?}.",
+                    Mark::Ok,
+                    report.applied.len(),
+                    report.elapsed
+                );
             }
 
-            MigrateCommands::MigrateStatus { schema: _ } => {
-                let migrations_dir = PathBuf::from("migrations");
+            MigrateCommands::MigrateStatus {
+                schema: _,
+                datasource,
+                env,
+                url,
+            } => {
+                let config = load_config(config_file)?;
+                let migrations_dir = resolve_migrations_dir(config.as_ref());
+
+                // Applied state needs a database; without one only the files are listed
+                let datasource = resolve_datasource(config.as_ref(), datasource, env.as_deref())?;
+                let db_url = if url.is_some() || datasource.is_some() {
+                    Some(stratus::engine::resolve_database_url(
+                        config.as_ref(),
+                        datasource.as_deref(),
+                        url.as_deref(),
+                    )?)
+                } else {
+                    std::env::var("DATABASE_URL").ok()
+                };
 
-                println!("\n📊  Migrate Status");
-                println!("{}", "=".repeat(50));
+                stratus::output::header("📊", "Migrate Status");
                 println!("Migrations: {}", migrations_dir.display());
                 println!();
 
-                let migrations = stratus::migrate::load_migrations(&migrations_dir)
-                    .expect("Failed to load migrations");
+                let mut migrations = read_migrations(&migrations_dir)?;
+                let Some(db_url) = db_url else {
+                    println!("Database: not connected; applied state unknown");
+                    println!("  Pass --url or --datasource, or set DATABASE_URL");
+                    println!();
+                    for m in &migrations {
+                        println!("  [{}] {}", m.meta.id, m.meta.name);
+                    }
+                    return Ok(());
+                };
+
+                let db_config = stratus::engine::connection_config(
+                    config.as_ref(),
+                    datasource.as_deref(),
+                    env.as_deref(),
+                    &db_url,
+                    no_cache,
+                );
+                let mut client = connect_backend(&db_config)?;
+                let table = migrations_table(config.as_ref());
+                let history = if stratus::migrate::migrations_table_exists(&mut *client, &table)? {
+                    // Adds the columns newer versions record to an older table
+                    stratus::migrate::ensure_migrations_table(&mut *client, &table)?;
+                    stratus::migrate::load_applied(&mut *client, &table, &mut migrations)?;
+                    stratus::migrate::check_history(&mut *client, &table, &migrations)?
+                } else {
+                    Vec::new()
+                };
 
                 stratus::migrate::print_migration_status(&migrations);
+
+                if !history.is_empty() {
+                    for problem in &history {
+                        println!("{} {}", Mark::Fail, problem);
+                    }
+                    return Err(CliError::new(
+                        ExitCode::MigrationMismatch,
+                        "The migrations table does not match the migrations directory",
+                    )
+                    .with_hint("Restore the missing or edited migration files"));
+                }
+                let pending = migrations.iter().filter(|m| !m.applied).count();
+                if pending > 0 {
+                    return Err(CliError::new(
+                        ExitCode::Pending,
+                        format!("{} pending migration(s)", pending),
+                    )
+                    .with_hint("Run `stratus deploy` to apply them"));
+                }
+            }
+
+            MigrateCommands::MigrateCheck { base } => {
+                let config = load_config(config_file)?;
+                let migrations_dir = resolve_migrations_dir(config.as_ref());
+
+                stratus::output::header("🔀", "Migrate Check");
+                let fork_point = stratus::migrate::merge_base(&base).map_err(|e| {
+                    CliError::from(e).with_hint("Run `git fetch` to update the base ref")
+                })?;
+                let fork = stratus::migrate::load_migrations_at(&migrations_dir, &fork_point)?;
+                let on_base = stratus::migrate::load_migrations_at(&migrations_dir, &base)?;
+                let local = read_migrations(&migrations_dir)?;
+                let check = stratus::migrate::check_branch(&fork, &on_base, &local)?;
+
+                println!("Migrations: {}", migrations_dir.display());
+                println!("Base: {} (forked at {:.12})", base, fork_point);
+                println!();
+                println!("Added on this branch: {}", check.added.len());
+                for m in &check.added {
+                    println!("  + {}_{}", m.meta.id, m.meta.name);
+                }
+                println!("Landed on {} since: {}", base, check.landed.len());
+                for m in &check.landed {
+                    println!("  + {}_{}", m.meta.id, m.meta.name);
+                }
+                println!();
+
+                if check.problems.is_empty() {
+                    println!("{} No migration conflicts with {}", Mark::Ok, base);
+                    return Ok(());
+                }
+                for problem in &check.problems {
+                    println!("{} {}", Mark::Fail, problem);
+                }
+                return Err(CliError::new(
+                    ExitCode::MigrationMismatch,
+                    format!("{} migration conflict(s) with {}", check.problems.len(), base),
+                )
+                .with_hint(format!(
+                    "Merge {} into this branch, then recreate the branch's migrations with `stratus migrate dev`",
+                    base
+                )));
             }
 
             MigrateCommands::MigrateDiff {
-                from: _,
+                from,
                 to,
-                url: _,
-                save: _,
-                name: _,
+                format,
+                datasource,
+                env,
+                url,
+                save,
+                name,
+                override_policy,
             } => {
-                println!("\n📐  Migrate Diff");
-                println!("{}", "=".repeat(50));
-
-                if let Some(schema_path) = to {
-                    let schema_str =
-                        fs::read_to_string(&schema_path).expect("Failed to read schema file");
-                    let parsed_schema: stratus::schema::Schema =
-                        serde_json::from_str(&schema_str).expect("Failed to parse schema");
-
-                    println!("\nSchema: {}", schema_path.display());
-                    println!("Tables: {}", parsed_schema.tables.len());
-
-                    for (name, table) in &parsed_schema.tables {
-                        println!("  + {}", name);
-                        for col in table.columns.keys() {
-                            println!("    - {}", col);
+                if !matches!(format.as_str(), "text" | "sql" | "json") {
+                    return Err(CliError::new(
+                        ExitCode::Usage,
+                        format!("Unknown format '{}': use text, sql or json", format),
+                    ));
+                }
+                let from_db = matches!(from.as_deref(), None | Some("db"));
+                if !from_db && (url.is_some() || datasource.is_some()) {
+                    return Err(CliError::new(
+                        ExitCode::Usage,
+                        "--url and --datasource only apply when diffing from the database",
+                    )
+                    .with_hint("Drop them, or pass --from db"));
+                }
+                let engine = engine(config_file, to, datasource, env, url, no_cache)?;
+                let desired = engine.load_schema()?;
+
+                let (from_label, current) = match from.as_deref() {
+                    None | Some("db") => {
+                        // Connect quietly: stdout is the diff itself for sql and json
+                        let db_config = engine.db_config().map_err(|e| {
+                            CliError::from(e).with_hint(
+                                "Pass --url or --datasource, or diff two files with --from <schema.json>",
+                            )
+                        })?;
+                        let mut client = stratus::db::connect(&db_config)?;
+                        let label = match engine.datasource() {
+                            Some(name) => format!("database ({})", name),
+                            None => "database".to_string(),
+                        };
+                        (label, engine.introspect(&mut *client)?)
+                    }
+                    Some(path) => (
+                        path.to_string(),
+                        stratus::db::DbSchema::from_json_schema(&stratus::engine::read_schema(
+                            Path::new(path),
+                        )?),
+                    ),
+                };
+                let diff = stratus::db::compare_schemas(&desired, &current);
+                if !override_policy {
+                    engine.enforce_plan_policies("migrate diff", &diff)?;
+                }
+
+                match format.as_str() {
+                    "json" => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&diff.with_sql())
+                            .map_err(|e| CliError::new(ExitCode::Failure, e.to_string()))?
+                    ),
+                    "sql" => stratus::output::print_sql(&diff.sql()),
+                    _ => {
+                        stratus::output::header("📐", "Migrate Diff");
+                        println!("From: {}", from_label);
+                        println!("To:   {}", engine.schema_path().display());
+                        stratus::db::print_diff_summary(&diff);
+                        if diff.has_changes() {
+                            stratus::output::print_sql_preview(&diff.sql());
                         }
                     }
+                }
 
-                    println!();
-                    println!("TODO: Compare with database and generate SQL diff");
-                    println!("Use --save to create migration file");
-                } else {
-                    println!("\nUsage:");
-                    println!("  stratus migrate diff --from db --to schema.json");
-                    println!("  stratus migrate diff --from schema_v1.json --to schema_v2.json");
+                if save && !diff.has_changes() {
+                    eprintln!("{} No changes, so no migration was saved.", Mark::Ok);
+                } else if save {
+                    let migration_name = name.unwrap_or_else(|| {
+                        stratus::migrate::generate_migration_name(
+                            &current.to_json_schema(),
+                            &desired,
+                        )
+                    });
+                    let down_sql = format!(
+                        "-- Rollback for {}\n{}",
+                        migration_name,
+                        diff.generate_rollback()
+                    );
+                    let m = stratus::migrate::create_migration(
+                        &engine.migrations_dir().to_path_buf(),
+                        &migration_name,
+                        &diff.sql(),
+                        &down_sql,
+                        diff.sql_dialect().name(),
+                        None,
+                    )
+                    .map_err(|e| {
+                        CliError::new(
+                            ExitCode::CantCreate,
+                            format!("Error creating migration: {}", e),
+                        )
+                    })?;
+                    // Keep stdout to the diff itself for sql and json
+                    eprintln!(
+                        "{} Created migration: {}/{}_{}",
+                        Mark::Ok,
+                        engine.migrations_dir().display(),
+                        m.meta.id,
+                        m.meta.name
+                    );
                 }
             }
 
             MigrateCommands::MigrateResolve {
-                issue: _,
-                migration: _,
+                issue,
+                migration,
+                datasource,
+                env,
+                url,
             } => {
-                println!("\n🔧  Migrate Resolve");
-                println!("{}", "=".repeat(50));
-                println!("Resolve migration issues like failed migrations.");
+                if !matches!(issue.as_str(), "applied" | "rolled-back") {
+                    return Err(CliError::new(
+                        ExitCode::Usage,
+                        format!("Unknown issue '{}': use applied or rolled-back", issue),
+                    ));
+                }
+                let id = migration.ok_or_else(|| {
+                    CliError::new(ExitCode::Usage, "--migration is required")
+                        .with_hint("Pass the migration ID shown by `stratus migrate status`")
+                })?;
+                let engine = engine(config_file, None, datasource, env, url, no_cache)?;
+
+                stratus::output::header("🔧", "Migrate Resolve");
+                println!("Migrations: {}", engine.migrations_dir().display());
                 println!();
-                println!("TODO: Implement migration resolution");
+
+                let mut migrations = engine.load_migrations()?;
+                let migration = migrations
+                    .iter_mut()
+                    .find(|m| m.meta.id == id || format!("{}_{}", m.meta.id, m.meta.name) == id)
+                    .ok_or_else(|| {
+                        CliError::new(ExitCode::Usage, format!("No migration '{}'", id))
+                            .with_hint("Pass the migration ID shown by `stratus migrate status`")
+                    })?;
+
+                let db_config = engine.db_config()?;
+                let mut client = connect_backend(&db_config)?;
+                let table = engine.migrations_table();
+                if issue == "applied" {
+                    stratus::migrate::resolve_applied(&mut *client, &table, migration)?;
+                    println!(
+                        "{} Recorded {}_{} as applied; its SQL was not run.",
+                        Mark::Ok,
+                        migration.meta.id,
+                        migration.meta.name
+                    );
+                } else {
+                    stratus::migrate::resolve_rolled_back(
+                        &mut *client,
+                        &table,
+                        engine.migrations_dir(),
+                        migration,
+                    )?;
+                    println!(
+                        "{} Recorded {}_{} as rolled back; the next deploy applies it again.",
+                        Mark::Ok,
+                        migration.meta.id,
+                        migration.meta.name
+                    );
+                }
             }
         },
     }
+
+    Ok(())
 }
@@ -3,11 +3,78 @@
  *
  * Handles migration file generation, management, and application.
  */
+use crate::db::{AppliedMigration, DbBackend};
+use crate::dialect::{Dialect, Postgres};
+use crate::output::Mark;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// Migration errors
+#[derive(Error, Debug)]
+pub enum MigrateError {
+    #[error("Failed to read {}: {}", .path.display(), .source)]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write {}: {}", .path.display(), .source)]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid {}: {}", .path.display(), .source)]
+    Meta {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    /// A statement against the database (or the migrations table) failed
+    #[error("{context}: {source}")]
+    Db {
+        context: String,
+        source: crate::db::DbError,
+    },
+
+    /// The migrations table disagrees with the migrations available
+    #[error("{0}")]
+    History(String),
+
+    /// Reading migrations from another git ref failed
+    #[error("{0}")]
+    Git(String),
+
+    /// `migrate resolve` has nothing to do for the migration
+    #[error("{0}")]
+    Resolve(String),
+}
+
+pub type MigrateResult<T> = Result<T, MigrateError>;
+
+fn read_error(path: &Path) -> impl FnOnce(std::io::Error) -> MigrateError + '_ {
+    move |source| MigrateError::Read {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn write_error(path: &Path) -> impl FnOnce(std::io::Error) -> MigrateError + '_ {
+    move |source| MigrateError::Write {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn db_error(context: impl Into<String>) -> impl FnOnce(crate::db::DbError) -> MigrateError {
+    let context = context.into();
+    move |source| MigrateError::Db { context, source }
+}
 
 fn default_status() -> String {
     "draft".to_string()
@@ -24,7 +91,8 @@ pub struct MigrationMeta {
     pub created_at: String,
     /// Database dialect
     pub dialect: String,
-    /// SHA256 checksum of the migration SQL (for deduplication)
+    /// Checksum of the schema change the migration was generated from (for
+    /// deduplication); see `SchemaDiff::checksum`
     pub checksum: Option<String>,
     /// Migration status: draft, reviewed, applied, failed
     #[serde(default = "default_status")]
@@ -33,21 +101,113 @@ pub struct MigrationMeta {
     pub created_by: Option<String>,
     /// When the migration was applied (if applied)
     pub applied_at: Option<String>,
+    /// Operations of the generated plan as edited with `sync --interactive`,
+    /// in the order written; empty when the plan was taken as generated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plan: Vec<PlanDecision>,
+}
+
+/// What the author decided about one operation of a generated plan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanDecision {
+    /// The operation, e.g. `drop table sessions`
+    pub operation: String,
+    /// Its position in the plan as generated
+    pub index: usize,
+    /// Left out of up.sql
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub excluded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
 /// Migration file
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Migration {
     /// Migration metadata
     pub meta: MigrationMeta,
     /// Up migration SQL (schema changes)
-    pub up_sql: String,
+    up_sql: SqlFile,
     /// Down migration SQL (rollback)
-    pub down_sql: String,
+    down_sql: SqlFile,
+    /// SHA256 of the up SQL as loaded, to spot edits made after it was applied
+    pub sql_checksum: String,
     /// Applied status
     pub applied: bool,
     /// When the migration was applied (if applied)
     pub applied_at: Option<String>,
+    /// How long it took to apply, as recorded in the migrations table
+    pub duration_ms: Option<u64>,
+}
+
+impl Migration {
+    /// A migration not yet applied
+    pub fn new(
+        meta: MigrationMeta,
+        up_sql: impl Into<String>,
+        down_sql: impl Into<String>,
+    ) -> Self {
+        let up_sql = up_sql.into();
+        Self {
+            meta,
+            sql_checksum: calculate_checksum(&up_sql),
+            up_sql: SqlFile::loaded(up_sql),
+            down_sql: SqlFile::loaded(down_sql.into()),
+            applied: false,
+            applied_at: None,
+            duration_ms: None,
+        }
+    }
+
+    /// Up migration SQL, read from up.sql on first use
+    pub fn up_sql(&self) -> MigrateResult<&str> {
+        self.up_sql.get()
+    }
+
+    /// Down migration SQL, read from down.sql on first use
+    pub fn down_sql(&self) -> MigrateResult<&str> {
+        self.down_sql.get()
+    }
+
+    /// The checksum recorded when applied: the one in meta.json, else the SQL's
+    pub fn checksum(&self) -> &str {
+        self.meta.checksum.as_deref().unwrap_or(&self.sql_checksum)
+    }
+}
+
+/// SQL of a migration, either known or read from its file when first needed
+#[derive(Debug, Clone, Default)]
+struct SqlFile {
+    /// File holding the SQL; a missing file reads as empty
+    path: Option<PathBuf>,
+    text: OnceLock<String>,
+}
+
+impl SqlFile {
+    fn loaded(text: String) -> Self {
+        Self {
+            path: None,
+            text: OnceLock::from(text),
+        }
+    }
+
+    fn lazy(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            text: OnceLock::new(),
+        }
+    }
+
+    fn get(&self) -> MigrateResult<&str> {
+        if let Some(text) = self.text.get() {
+            return Ok(text);
+        }
+        let text = match &self.path {
+            Some(path) if path.exists() => fs::read_to_string(path).map_err(read_error(path))?,
+            _ => String::new(),
+        };
+        Ok(self.text.get_or_init(|| text))
+    }
 }
 
 /// Migration manifest
@@ -69,36 +229,31 @@ pub fn create_migration(
     down_sql: &str,
     dialect: &str,
     checksum: Option<String>,
-) -> Result<Migration, String> {
+) -> MigrateResult<Migration> {
     // Create migrations directory if needed
     if !migrations_dir.exists() {
-        fs::create_dir_all(migrations_dir)
-            .map_err(|e| format!("Failed to create migrations directory: {}", e))?;
+        fs::create_dir_all(migrations_dir).map_err(write_error(migrations_dir))?;
     }
 
     // Generate migration ID (timestamp + random)
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| format!("Time error: {}", e))?
-        .as_secs();
+    let timestamp = chrono::Utc::now().timestamp();
     let random_suffix = rand::random::<u32>();
     let id = format!("{:}_{}", timestamp, random_suffix);
 
     // Format name (kebab-case)
-    let formatted_name = name.to_lowercase().replace('_', "-").replace(' ', "-");
+    let formatted_name = name.to_lowercase().replace(['_', ' '], "-");
 
     // Create migration directory
     let migration_dir = migrations_dir.join(format!("{}_{}", id, formatted_name));
-    fs::create_dir_all(&migration_dir)
-        .map_err(|e| format!("Failed to create migration directory: {}", e))?;
+    fs::create_dir_all(&migration_dir).map_err(write_error(&migration_dir))?;
 
     // Write up.sql
     let up_path = migration_dir.join("up.sql");
-    fs::write(&up_path, up_sql).map_err(|e| format!("Failed to write up.sql: {}", e))?;
+    fs::write(&up_path, up_sql).map_err(write_error(&up_path))?;
 
     // Write down.sql
     let down_path = migration_dir.join("down.sql");
-    fs::write(&down_path, down_sql).map_err(|e| format!("Failed to write down.sql: {}", e))?;
+    fs::write(&down_path, down_sql).map_err(write_error(&down_path))?;
 
     // Write meta.json
     let meta = MigrationMeta {
@@ -110,20 +265,32 @@ pub fn create_migration(
         status: "draft".to_string(),
         created_by: std::env::var("USER").ok(),
         applied_at: None,
+        plan: Vec::new(),
     };
+    write_meta(&migration_dir, &meta)?;
 
+    Ok(Migration::new(meta, up_sql, down_sql))
+}
+
+/// Record the edited plan of a migration just created in its meta.json
+pub fn record_plan(
+    migrations_dir: &Path,
+    migration: &mut Migration,
+    plan: Vec<PlanDecision>,
+) -> MigrateResult<()> {
+    migration.meta.plan = plan;
+    let migration_dir =
+        migrations_dir.join(format!("{}_{}", migration.meta.id, migration.meta.name));
+    write_meta(&migration_dir, &migration.meta)
+}
+
+fn write_meta(migration_dir: &Path, meta: &MigrationMeta) -> MigrateResult<()> {
     let meta_path = migration_dir.join("meta.json");
-    let meta_json = serde_json::to_string_pretty(&meta)
-        .map_err(|e| format!("Failed to serialize meta: {}", e))?;
-    fs::write(&meta_path, meta_json).map_err(|e| format!("Failed to write meta.json: {}", e))?;
-
-    Ok(Migration {
-        meta,
-        up_sql: up_sql.to_string(),
-        down_sql: down_sql.to_string(),
-        applied: false,
-        applied_at: None,
-    })
+    let meta_json = serde_json::to_string_pretty(meta).map_err(|source| MigrateError::Meta {
+        path: meta_path.clone(),
+        source,
+    })?;
+    fs::write(&meta_path, meta_json).map_err(write_error(&meta_path))
 }
 
 /// Calculate SHA256 checksum of SQL content
@@ -133,68 +300,228 @@ pub fn calculate_checksum(sql: &str) -> String {
     format!("sha256:{:x}", hasher.finalize())
 }
 
+/// Cache of parsed meta.json and SQL checksums for a migrations directory,
+/// kept beside it under `.stratus/cache` with the introspection cache
+pub fn cache_path(migrations_dir: &Path) -> PathBuf {
+    let name = migrations_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "migrations".to_string());
+    migrations_dir
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(".stratus")
+        .join("cache")
+        .join(format!("migrations-{}.json", name))
+}
+
+/// What loading a migration directory produced, valid while its files are unchanged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedMigration {
+    /// Modification time and size of meta.json, up.sql and down.sql
+    fingerprint: String,
+    meta: MigrationMeta,
+    sql_checksum: String,
+}
+
 /// Load all migrations from directory
-pub fn load_migrations(migrations_dir: &PathBuf) -> Result<Vec<Migration>, String> {
+///
+/// Directories are read and hashed in parallel. Parsed metadata and
+/// checksums are cached at [`cache_path`] by file modification time, so only
+/// migrations changed since the last load are parsed and hashed again; the
+/// SQL of unchanged ones is read when first used.
+pub fn load_migrations(migrations_dir: &PathBuf) -> MigrateResult<Vec<Migration>> {
+    load(migrations_dir, true)
+}
+
+/// [`load_migrations`] for commands that only report, such as `migrate
+/// status`: the cache is used but left as it is
+pub fn read_migrations(migrations_dir: &PathBuf) -> MigrateResult<Vec<Migration>> {
+    load(migrations_dir, false)
+}
+
+fn load(migrations_dir: &PathBuf, write_cache: bool) -> MigrateResult<Vec<Migration>> {
     if !migrations_dir.exists() {
         return Ok(Vec::new());
     }
 
-    let mut migrations: Vec<Migration> = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(migrations_dir).map_err(read_error(migrations_dir))? {
+        let path = entry.map_err(read_error(migrations_dir))?.path();
+        if path.is_dir() && path.join("meta.json").exists() {
+            dirs.push(path);
+        }
+    }
 
-    // Read directory entries
-    let entries = fs::read_dir(migrations_dir)
-        .map_err(|e| format!("Failed to read migrations directory: {}", e))?;
+    let cache_path = cache_path(migrations_dir);
+    let cache: HashMap<String, CachedMigration> = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
 
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Directory error: {}", e))?;
-        let path = entry.path();
+    // Scoped threads over one chunk each, as Deploy::run_targets does; the
+    // loader is too small to be worth a thread pool dependency
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = dirs.len().div_ceil(threads).max(1);
+    let loaded: Vec<MigrateResult<(String, CachedMigration, Migration)>> =
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = dirs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let cache = &cache;
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|dir| load_dir(dir, cache))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("migration loader panicked"))
+                .collect()
+        });
 
-        if !path.is_dir() {
-            continue;
+    let mut migrations = Vec::with_capacity(loaded.len());
+    let mut fresh = HashMap::with_capacity(loaded.len());
+    for result in loaded {
+        let (key, cached, migration) = result?;
+        fresh.insert(key, cached);
+        migrations.push(migration);
+    }
+    let stale = fresh.len() != cache.len()
+        || fresh.iter().any(|(key, entry)| {
+            cache
+                .get(key)
+                .is_none_or(|c| c.fingerprint != entry.fingerprint)
+        });
+    if stale && write_cache {
+        // Only a speed-up: an unwritable directory loads the slow way next time
+        if let Ok(json) = serde_json::to_string(&fresh) {
+            if let Some(dir) = cache_path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(&cache_path, json);
         }
+    }
 
-        // Load meta.json
-        let meta_path = path.join("meta.json");
-        if !meta_path.exists() {
-            continue;
+    // Sort by ID (timestamp-based)
+    migrations.sort_by(|a, b| a.meta.id.cmp(&b.meta.id));
+
+    Ok(migrations)
+}
+
+/// Load one migration directory, reusing its cache entry when the files are unchanged
+fn load_dir(
+    dir: &Path,
+    cache: &HashMap<String, CachedMigration>,
+) -> MigrateResult<(String, CachedMigration, Migration)> {
+    let key = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let meta_path = dir.join("meta.json");
+    let up_path = dir.join("up.sql");
+    let down_path = dir.join("down.sql");
+    let fingerprint = [&meta_path, &up_path, &down_path]
+        .iter()
+        .map(|path| match fs::metadata(path) {
+            Ok(metadata) => {
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                format!(
+                    "{}.{}+{}",
+                    modified.as_secs(),
+                    modified.subsec_nanos(),
+                    metadata.len()
+                )
+            }
+            Err(_) => "-".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let migration = match cache.get(&key).filter(|c| c.fingerprint == fingerprint) {
+        Some(cached) => Migration {
+            meta: cached.meta.clone(),
+            up_sql: SqlFile::lazy(up_path),
+            down_sql: SqlFile::lazy(down_path),
+            sql_checksum: cached.sql_checksum.clone(),
+            applied: false,
+            applied_at: None,
+            duration_ms: None,
+        },
+        None => {
+            let read = |path: &Path| SqlFile::lazy(path.to_path_buf()).get().map(str::to_string);
+            migration(
+                &meta_path,
+                &read(&meta_path)?,
+                read(&up_path)?,
+                read(&down_path)?,
+            )?
         }
+    };
+    let cached = CachedMigration {
+        fingerprint,
+        meta: migration.meta.clone(),
+        sql_checksum: migration.sql_checksum.clone(),
+    };
+    Ok((key, cached, migration))
+}
 
-        let meta_json = fs::read_to_string(&meta_path)
-            .map_err(|e| format!("Failed to read meta.json: {}", e))?;
-        let meta: MigrationMeta = serde_json::from_str(&meta_json)
-            .map_err(|e| format!("Failed to parse meta.json: {}", e))?;
+/// Load migrations embedded with `include_dir!`, laid out like a migrations directory
+pub fn load_embedded(dir: &include_dir::Dir<'_>) -> MigrateResult<Vec<Migration>> {
+    let mut migrations = Vec::new();
 
-        // Load up.sql
-        let up_sql = if path.join("up.sql").exists() {
-            fs::read_to_string(path.join("up.sql"))
-                .map_err(|e| format!("Failed to read up.sql: {}", e))?
-        } else {
-            String::new()
+    for migration_dir in dir.dirs() {
+        let file = |name: &str| {
+            migration_dir
+                .files()
+                .find(|f| f.path().file_name() == Some(name.as_ref()))
+                .map(|f| {
+                    f.contents_utf8().ok_or_else(|| MigrateError::Read {
+                        path: f.path().to_path_buf(),
+                        source: std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "not valid UTF-8",
+                        ),
+                    })
+                })
+                .transpose()
         };
 
-        // Load down.sql
-        let down_sql = if path.join("down.sql").exists() {
-            fs::read_to_string(path.join("down.sql"))
-                .map_err(|e| format!("Failed to read down.sql: {}", e))?
-        } else {
-            String::new()
+        let Some(meta_json) = file("meta.json")? else {
+            continue;
         };
-
-        migrations.push(Migration {
-            meta: meta.clone(),
-            up_sql,
-            down_sql,
-            applied: false,
-            applied_at: None,
-        });
+        migrations.push(migration(
+            &migration_dir.path().join("meta.json"),
+            meta_json,
+            file("up.sql")?.unwrap_or_default().to_string(),
+            file("down.sql")?.unwrap_or_default().to_string(),
+        )?);
     }
 
-    // Sort by ID (timestamp-based)
     migrations.sort_by(|a, b| a.meta.id.cmp(&b.meta.id));
-
     Ok(migrations)
 }
 
+fn migration(
+    meta_path: &Path,
+    meta_json: &str,
+    up_sql: String,
+    down_sql: String,
+) -> MigrateResult<Migration> {
+    let meta = serde_json::from_str(meta_json).map_err(|source| MigrateError::Meta {
+        path: meta_path.to_path_buf(),
+        source,
+    })?;
+    Ok(Migration::new(meta, up_sql, down_sql))
+}
+
 /// Get pending migrations (not yet applied)
 pub fn get_pending_migrations(migrations: &[Migration]) -> Vec<&Migration> {
     migrations.iter().filter(|m| !m.applied).collect()
@@ -209,7 +536,7 @@ pub fn generate_migration_name(from: &crate::schema::Schema, to: &crate::schema:
         .tables
         .keys()
         .filter(|k| !from.tables.contains_key(*k))
-        .map(|k| k.clone())
+        .cloned()
         .collect();
 
     if !new_tables.is_empty() {
@@ -221,7 +548,7 @@ pub fn generate_migration_name(from: &crate::schema::Schema, to: &crate::schema:
         .tables
         .keys()
         .filter(|k| !to.tables.contains_key(*k))
-        .map(|k| k.clone())
+        .cloned()
         .collect();
 
     if !dropped_tables.is_empty() {
@@ -246,22 +573,522 @@ pub fn print_migration_status(migrations: &[Migration]) {
     let pending_count = migrations.len() - applied_count;
 
     println!("Total migrations: {}", migrations.len());
-    println!("  ✓ Applied: {}", applied_count);
-    println!("  ○ Pending: {}", pending_count);
+    println!("  {} Applied: {}", Mark::Ok, applied_count);
+    println!("  {} Pending: {}", Mark::Skip, pending_count);
     println!();
 
+    if applied_count > 0 {
+        println!("Applied migrations:");
+        for m in migrations.iter().filter(|m| m.applied) {
+            let duration = m
+                .duration_ms
+                .map(|ms| format!(" in {} ms", ms))
+                .unwrap_or_default();
+            println!(
+                "  [{}] {} at {}{}",
+                m.meta.id,
+                m.meta.name,
+                m.applied_at.as_deref().unwrap_or("?"),
+                duration
+            );
+        }
+        println!();
+    }
+
     if pending_count > 0 {
         println!("Pending migrations:");
         for m in migrations.iter().filter(|m| !m.applied) {
             println!("  [{}] {}", m.meta.id, m.meta.name);
         }
     } else {
-        println!("✓ All migrations are up to date.");
+        println!("{} All migrations are up to date.", Mark::Ok);
     }
 
     println!();
 }
 
+/// Replay migrations on a shadow database to check they apply cleanly.
+///
+/// The tables and enum types the shadow database holds are dropped first, by
+/// the script `migrate reset` runs, so it must never point at a database
+/// holding real data; [`crate::config::check_shadow_url`] guards against
+/// the primary one. The migrations are then applied as deploy applies them,
+/// one transaction each.
+pub fn replay_on_shadow(
+    shadow: &mut dyn DbBackend,
+    dialect: &dyn Dialect,
+    table: &MigrationsTable,
+    migrations: &[Migration],
+) -> crate::error::Result<usize> {
+    let existing = crate::engine::introspect(shadow, table)?;
+    shadow
+        .begin()
+        .map_err(db_error("Failed to reset shadow database"))?;
+    if let Err(e) = shadow.execute(&reset_sql(dialect, &existing, table)) {
+        let _ = shadow.rollback();
+        return Err(db_error("Failed to reset shadow database")(e).into());
+    }
+    shadow
+        .commit()
+        .map_err(db_error("Failed to reset shadow database"))?;
+
+    crate::engine::apply_migrations(
+        shadow,
+        table,
+        &migrations.iter().collect::<Vec<_>>(),
+        &mut (),
+    )?;
+    Ok(migrations.len())
+}
+
+pub use crate::config::DEFAULT_MIGRATIONS_TABLE;
+
+/// Location of the table recording applied migrations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationsTable {
+    /// Database schema holding the table (search path when unset)
+    pub schema: Option<String>,
+    /// Table name
+    pub name: String,
+}
+
+impl Default for MigrationsTable {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            name: DEFAULT_MIGRATIONS_TABLE.to_string(),
+        }
+    }
+}
+
+impl MigrationsTable {
+    /// Quoted, schema-qualified table name
+    pub fn qualified_name(&self) -> String {
+        self.qualified_name_in(&Postgres)
+    }
+
+    /// The qualified name quoted for another dialect
+    pub fn qualified_name_in(&self, dialect: &dyn Dialect) -> String {
+        match &self.schema {
+            Some(schema) => format!(
+                "{}.{}",
+                dialect.quote_ident(schema),
+                dialect.quote_ident(&self.name)
+            ),
+            None => dialect.quote_ident(&self.name),
+        }
+    }
+
+    /// Remove the table from an introspected `public` schema so diffs ignore it
+    pub fn exclude_from(&self, schema: &mut crate::db::DbSchema) {
+        if self.schema.as_deref().unwrap_or("public") == "public" {
+            schema.tables.shift_remove(&self.name);
+        }
+    }
+}
+
+/// Quote an SQL identifier for PostgreSQL
+pub fn quote_ident(ident: &str) -> String {
+    Postgres.quote_ident(ident)
+}
+
+/// Quote an SQL string literal for PostgreSQL
+pub fn quote_literal(value: &str) -> String {
+    Postgres.quote_literal(value)
+}
+
+/// Script `migrate reset` runs in one transaction: drops every table and
+/// enum type of `schema`, then the migrations table, with foreign keys out
+/// of the way as in [`drop_tables_sql`].
+pub fn reset_sql(
+    dialect: &dyn Dialect,
+    schema: &crate::db::DbSchema,
+    table: &MigrationsTable,
+) -> String {
+    let mut tables: Vec<&String> = schema.tables.keys().collect();
+    tables.sort();
+    let mut drops: String = tables
+        .into_iter()
+        .map(|name| format!("{}\n", dialect.drop_table(&dialect.quote_ident(name))))
+        .collect();
+    // Only PostgreSQL has enum types apart from the columns using them
+    let mut enums: Vec<&String> = schema.enums.keys().collect();
+    enums.sort();
+    for name in enums {
+        drops.push_str(&format!(
+            "DROP TYPE IF EXISTS {} CASCADE;\n",
+            dialect.quote_ident(name)
+        ));
+    }
+    drops.push_str(&dialect.drop_table(&table.qualified_name_in(dialect)));
+    drops.push('\n');
+    without_foreign_key_checks(dialect, drops)
+}
+
+/// Drops `tables`, for a run inside a transaction. Foreign keys between the
+/// tables are cascaded (PostgreSQL), switched off (MySQL) or checked only at
+/// commit (SQLite), so the drop order does not matter.
+pub fn drop_tables_sql(dialect: &dyn Dialect, tables: &[String]) -> String {
+    let drops: String = tables
+        .iter()
+        .map(|name| format!("{}\n", dialect.drop_table(&dialect.quote_ident(name))))
+        .collect();
+    without_foreign_key_checks(dialect, drops)
+}
+
+fn without_foreign_key_checks(dialect: &dyn Dialect, drops: String) -> String {
+    use crate::config::DatabaseProvider;
+
+    if drops.is_empty() {
+        return drops;
+    }
+    match dialect.provider() {
+        DatabaseProvider::Postgresql => drops,
+        DatabaseProvider::Mysql => format!(
+            "SET FOREIGN_KEY_CHECKS = 0;\n{}SET FOREIGN_KEY_CHECKS = 1;\n",
+            drops
+        ),
+        DatabaseProvider::Sqlite => format!("PRAGMA defer_foreign_keys = ON;\n{}", drops),
+    }
+}
+
+/// Create the migrations table (and its schema) if missing
+pub fn ensure_migrations_table(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+) -> MigrateResult<()> {
+    client
+        .create_migrations_table(table)
+        .map_err(db_error("Failed to create migrations table"))
+}
+
+/// Mark migrations recorded in the migrations table as applied
+pub fn load_applied(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations: &mut [Migration],
+) -> MigrateResult<()> {
+    let rows = client
+        .applied_migrations(table)
+        .map_err(db_error("Failed to read migrations table"))?;
+
+    let applied: HashMap<&str, &AppliedMigration> =
+        rows.iter().map(|row| (row.id.as_str(), row)).collect();
+    for m in migrations.iter_mut() {
+        if let Some(row) = applied.get(m.meta.id.as_str()) {
+            m.applied = true;
+            m.applied_at = Some(row.applied_at.clone());
+            m.duration_ms = row.duration_ms;
+        }
+    }
+
+    Ok(())
+}
+
+/// IDs recorded in the migrations table
+pub fn applied_ids(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+) -> MigrateResult<std::collections::HashSet<String>> {
+    let rows = client
+        .applied_migrations(table)
+        .map_err(db_error("Failed to read migrations table"))?;
+    Ok(rows.into_iter().map(|row| row.id).collect())
+}
+
+/// Take the session-level advisory lock serializing migration runs against this migrations table.
+///
+/// Blocks until any other process applying migrations releases it. Locks
+/// nest; every call needs a matching [`unlock`].
+pub fn lock(client: &mut dyn DbBackend, table: &MigrationsTable) -> MigrateResult<()> {
+    client
+        .lock_migrations(table)
+        .map_err(db_error("Failed to lock migrations table"))
+}
+
+/// Release the lock taken by [`lock`]
+pub fn unlock(client: &mut dyn DbBackend, table: &MigrationsTable) -> MigrateResult<()> {
+    client
+        .unlock_migrations(table)
+        .map_err(db_error("Failed to unlock migrations table"))
+}
+
+/// Applies migrations embedded in the application binary, e.g. at startup.
+///
+/// Uses the same migrations table, checksums and lock as `stratus deploy`, so
+/// several instances starting at once apply each migration exactly once:
+///
+/// ```ignore
+/// static MIGRATIONS: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/migrations");
+///
+/// let config = stratus::engine::connection_config(None, None, None, &url, false);
+/// let mut client = StratusClient::connect(&config)?;
+/// let applied = Runner::new(&MIGRATIONS).run(&mut client)?;
+/// ```
+pub struct Runner<'a> {
+    dir: &'a include_dir::Dir<'a>,
+    table: MigrationsTable,
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(dir: &'a include_dir::Dir<'a>) -> Self {
+        Self {
+            dir,
+            table: MigrationsTable::default(),
+        }
+    }
+
+    /// Record applied migrations in this table instead of `_stratus_migrations`
+    pub fn table(mut self, table: MigrationsTable) -> Self {
+        self.table = table;
+        self
+    }
+
+    /// Every embedded migration, ordered by ID
+    pub fn migrations(&self) -> MigrateResult<Vec<Migration>> {
+        load_embedded(self.dir)
+    }
+
+    /// Embedded migrations not yet applied (and not marked failed), creating the migrations table if needed
+    pub fn pending(&self, client: &mut dyn DbBackend) -> MigrateResult<Vec<Migration>> {
+        let mut migrations = self.migrations()?;
+        ensure_migrations_table(client, &self.table)?;
+        load_applied(client, &self.table, &mut migrations)?;
+        Ok(migrations
+            .into_iter()
+            .filter(|m| !m.applied && m.meta.status != "failed")
+            .collect())
+    }
+
+    /// Apply every pending migration, one transaction each; returns the ones applied.
+    ///
+    /// Fails if an applied migration is missing from the binary or changed since.
+    pub fn run(&self, client: &mut dyn DbBackend) -> crate::error::Result<Vec<Migration>> {
+        lock(client, &self.table)?;
+        let result = self.run_locked(client);
+        let _ = unlock(client, &self.table);
+        result
+    }
+
+    fn run_locked(&self, client: &mut dyn DbBackend) -> crate::error::Result<Vec<Migration>> {
+        let pending = self.pending(client)?;
+        let problems = check_history(client, &self.table, &self.migrations()?)?;
+        if let Some(problem) = problems.into_iter().next() {
+            return Err(MigrateError::History(problem).into());
+        }
+        crate::engine::apply_migrations(
+            client,
+            &self.table,
+            &pending.iter().collect::<Vec<_>>(),
+            &mut (),
+        )?;
+        Ok(pending)
+    }
+}
+
+/// Whether the migrations table exists yet, without creating it
+pub fn migrations_table_exists(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+) -> MigrateResult<bool> {
+    client
+        .migrations_table_exists(table)
+        .map_err(db_error("Failed to look up migrations table"))
+}
+
+/// Compare the migrations table with the migrations on disk.
+///
+/// Returns one message per applied migration that is missing locally or whose checksum changed.
+pub fn check_history(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations: &[Migration],
+) -> MigrateResult<Vec<String>> {
+    let rows = client
+        .applied_migrations(table)
+        .map_err(db_error("Failed to read migrations table"))?;
+
+    let mut problems = Vec::new();
+    for row in &rows {
+        match migrations.iter().find(|m| m.meta.id == row.id) {
+            None => problems.push(format!(
+                "{}_{} was applied but is missing locally",
+                row.id, row.name
+            )),
+            Some(m) => {
+                if let Some(recorded) = &row.checksum {
+                    if recorded != m.checksum() {
+                        problems.push(format!(
+                            "{}_{} changed after it was applied (checksum mismatch)",
+                            row.id, row.name
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Record a migration as applied, with how long its SQL took; run inside
+/// the migration's transaction
+pub fn record_applied(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migration: &Migration,
+    duration: std::time::Duration,
+) -> MigrateResult<()> {
+    client
+        .insert_applied(table, migration, Some(duration))
+        .map_err(db_error(format!(
+            "Failed to record migration {}",
+            migration.meta.id
+        )))
+}
+
+/// Record a migration as applied without running its SQL, e.g. after
+/// finishing a partly failed migration by hand
+pub fn resolve_applied(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migration: &Migration,
+) -> MigrateResult<()> {
+    ensure_migrations_table(client, table)?;
+    if applied_ids(client, table)?.contains(&migration.meta.id) {
+        return Err(MigrateError::Resolve(format!(
+            "{}_{} is already applied",
+            migration.meta.id, migration.meta.name
+        )));
+    }
+    client
+        .insert_applied(table, migration, None)
+        .map_err(db_error(format!(
+            "Failed to record migration {}",
+            migration.meta.id
+        )))
+}
+
+/// Forget a migration whose changes were rolled back by hand: its row in the
+/// migrations table and a `failed` status in its meta.json both go, so the
+/// next deploy runs it again
+pub fn resolve_rolled_back(
+    client: &mut dyn DbBackend,
+    table: &MigrationsTable,
+    migrations_dir: &Path,
+    migration: &mut Migration,
+) -> MigrateResult<()> {
+    ensure_migrations_table(client, table)?;
+    let recorded = applied_ids(client, table)?.contains(&migration.meta.id);
+    let failed = migration.meta.status == "failed";
+    if !recorded && !failed {
+        return Err(MigrateError::Resolve(format!(
+            "{}_{} is neither applied nor marked failed",
+            migration.meta.id, migration.meta.name
+        )));
+    }
+    if recorded {
+        client
+            .delete_applied(table, &migration.meta.id)
+            .map_err(db_error(format!(
+                "Failed to remove migration {}",
+                migration.meta.id
+            )))?;
+    }
+    if failed {
+        migration.meta.status = default_status();
+        let dir = migrations_dir.join(format!("{}_{}", migration.meta.id, migration.meta.name));
+        write_meta(&dir, &migration.meta)?;
+    }
+    Ok(())
+}
+
+/// Split an SQL script into individual statements.
+///
+/// Semicolons inside quotes, dollar-quoted bodies and comments don't split;
+/// segments that are only comments or whitespace are dropped.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut has_code = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' => {
+                let quote = bytes[i];
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == quote {
+                        // A doubled quote is an escaped quote
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+                has_code = true;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 1;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            b'$' => {
+                // $tag$ ... $tag$, where the tag is empty or an identifier
+                let tag_end = bytes[i + 1..]
+                    .iter()
+                    .position(|b| !(b.is_ascii_alphanumeric() || *b == b'_'))
+                    .map(|p| i + 1 + p);
+                match tag_end {
+                    Some(end) if bytes[end] == b'$' && !bytes[i + 1].is_ascii_digit() => {
+                        let tag = &sql[i..=end];
+                        i = match sql[end + 1..].find(tag) {
+                            Some(close) => end + close + tag.len(),
+                            None => bytes.len() - 1,
+                        };
+                    }
+                    _ => {}
+                }
+                has_code = true;
+            }
+            b';' => {
+                if has_code {
+                    statements.push(sql[start..i].trim());
+                }
+                start = i + 1;
+                has_code = false;
+            }
+            b if !b.is_ascii_whitespace() => has_code = true,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if has_code {
+        statements.push(sql[start..].trim());
+    }
+    statements
+}
+
 /// Format SQL with basic indentation
 pub fn format_sql(sql: &str) -> String {
     // Basic SQL formatting
@@ -297,5 +1124,606 @@ pub fn format_sql(sql: &str) -> String {
     formatted
 }
 
+/// Run git in the current directory and return its stdout
+fn git(args: &[&str], stdin: Option<Vec<u8>>) -> MigrateResult<Vec<u8>> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let failed = |e: std::io::Error| MigrateError::Git(format!("cannot run git: {}", e));
+    let mut child = Command::new("git")
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    // Written from a thread: git answers while it reads, and would block on a full pipe
+    let writer = stdin.zip(child.stdin.take()).map(|(input, mut pipe)| {
+        std::thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        })
+    });
+    let output = child.wait_with_output().map_err(failed)?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        return Err(MigrateError::Git(format!(
+            "git {}: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// The commit where HEAD forked from `base`
+pub fn merge_base(base: &str) -> MigrateResult<String> {
+    let out = git(&["merge-base", "HEAD", base], None)?;
+    Ok(String::from_utf8_lossy(&out).trim().to_string())
+}
+
+/// Load a migrations directory as committed at a git ref, without touching
+/// the working tree
+pub fn load_migrations_at(migrations_dir: &Path, git_ref: &str) -> MigrateResult<Vec<Migration>> {
+    let dir = migrations_dir.to_string_lossy();
+    let tree = git(&["ls-tree", "-r", "-z", git_ref, "--", &dir], None)?;
+
+    // "<mode> blob <oid>\t<path>" per file, grouped by migration directory
+    let mut files: BTreeMap<String, [Option<String>; 3]> = BTreeMap::new();
+    for entry in tree.split(|b| *b == 0).filter(|e| !e.is_empty()) {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((info, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let path = Path::new(path);
+        let slot = match path.file_name().and_then(|n| n.to_str()) {
+            Some("meta.json") => 0,
+            Some("up.sql") => 1,
+            Some("down.sql") => 2,
+            _ => continue,
+        };
+        let Some(parent) = path.parent().and_then(|p| p.file_name()) else {
+            continue;
+        };
+        let oid = info.split(' ').nth(2).unwrap_or_default().to_string();
+        files
+            .entry(parent.to_string_lossy().into_owned())
+            .or_default()[slot] = Some(oid);
+    }
+
+    let oids: Vec<&String> = files.values().flatten().flatten().collect();
+    let mut input = String::new();
+    for oid in &oids {
+        input.push_str(oid);
+        input.push('\n');
+    }
+    let batch = git(&["cat-file", "--batch"], Some(input.into_bytes()))?;
+
+    // "<oid> <type> <size>\n<contents>\n" per object, in request order
+    let mut blobs = HashMap::new();
+    let mut rest = &batch[..];
+    for oid in oids {
+        let header_end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+        let header = String::from_utf8_lossy(&rest[..header_end]);
+        let size: usize = header
+            .rsplit(' ')
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| MigrateError::Git(format!("unexpected cat-file output: {}", header)))?;
+        let start = header_end + 1;
+        let end = (start + size).min(rest.len());
+        blobs.insert(oid, String::from_utf8_lossy(&rest[start..end]).into_owned());
+        rest = &rest[(end + 1).min(rest.len())..];
+    }
+
+    let mut migrations = Vec::new();
+    for (name, [meta, up, down]) in &files {
+        let Some(meta) = meta else {
+            continue;
+        };
+        let blob = |oid: &Option<String>| {
+            oid.as_ref()
+                .and_then(|oid| blobs.get(oid))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let meta_path = PathBuf::from(format!("{}:{}/{}/meta.json", git_ref, dir, name));
+        migrations.push(migration(&meta_path, &blobs[meta], blob(up), blob(down))?);
+    }
+    migrations.sort_by(|a, b| a.meta.id.cmp(&b.meta.id));
+    Ok(migrations)
+}
+
+/// How a branch's migrations line up with its base ref
+#[derive(Debug)]
+pub struct BranchCheck<'a> {
+    /// Migrations added on the branch since it forked
+    pub added: Vec<&'a Migration>,
+    /// Migrations that landed on the base ref since the branch forked
+    pub landed: Vec<&'a Migration>,
+    /// Problems the merge would cause
+    pub problems: Vec<String>,
+}
+
+/// Compare the migrations at the fork point, on the base ref and on the branch.
+///
+/// Flags migrations that share a timestamp, branch migrations that would sort
+/// before ones already on the base, branch and base migrations that change the
+/// same tables, and forked migrations edited or removed on the branch.
+pub fn check_branch<'a>(
+    fork: &[Migration],
+    base: &'a [Migration],
+    local: &'a [Migration],
+) -> MigrateResult<BranchCheck<'a>> {
+    let at_fork = |m: &Migration| fork.iter().find(|f| f.meta.id == m.meta.id);
+    let added: Vec<&Migration> = local.iter().filter(|m| at_fork(m).is_none()).collect();
+    let landed: Vec<&Migration> = base.iter().filter(|m| at_fork(m).is_none()).collect();
+    let label = |m: &Migration| format!("{}_{}", m.meta.id, m.meta.name);
+    let mut problems = Vec::new();
+
+    for f in fork {
+        match local.iter().find(|m| m.meta.id == f.meta.id) {
+            None => problems.push(format!("{} was removed on this branch", label(f))),
+            Some(m) if m.sql_checksum != f.sql_checksum => {
+                problems.push(format!("{} was edited on this branch", label(m)))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Ids are "<timestamp>_<random>"; a shared timestamp leaves the order to chance
+    let timestamp = |m: &Migration| m.meta.id.split('_').next().unwrap_or_default().to_string();
+    for (i, a) in added.iter().enumerate() {
+        let others = local
+            .iter()
+            .filter(|m| at_fork(m).is_some())
+            .chain(added[i + 1..].iter().copied())
+            .chain(landed.iter().copied());
+        for other in others {
+            if other.meta.id == a.meta.id {
+                problems.push(format!("{} and {} share an id", label(a), label(other)));
+            } else if timestamp(other) == timestamp(a) {
+                problems.push(format!(
+                    "{} and {} share the timestamp {}",
+                    label(a),
+                    label(other),
+                    timestamp(a)
+                ));
+            }
+        }
+    }
+
+    if let Some(last) = landed.last() {
+        for a in added.iter().filter(|a| a.meta.id < last.meta.id) {
+            problems.push(format!(
+                "{} sorts before {}, which landed on the base after this branch forked",
+                label(a),
+                label(last)
+            ));
+        }
+    }
+
+    for a in &added {
+        let tables = tables_touched(a.up_sql()?);
+        for b in &landed {
+            let shared: Vec<String> = tables_touched(b.up_sql()?)
+                .intersection(&tables)
+                .cloned()
+                .collect();
+            if !shared.is_empty() {
+                problems.push(format!(
+                    "{} and {} both change {}",
+                    label(a),
+                    label(b),
+                    shared.join(", ")
+                ));
+            }
+        }
+    }
+
+    Ok(BranchCheck {
+        added,
+        landed,
+        problems,
+    })
+}
+
+/// Word, quoted identifier or punctuation in a statement; strings and comments are dropped
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Punct(char),
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' || c == '"' {
+            let mut text = String::new();
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == c {
+                    if chars.get(i + 1) == Some(&c) {
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                text.push(chars[i]);
+                i += 1;
+            }
+            i += 1;
+            if c == '"' {
+                tokens.push(Token::Quoted(text));
+            }
+        } else if c == '$' {
+            // A dollar-quoted body holds code, not the statement's targets
+            let tag_len = chars[i + 1..]
+                .iter()
+                .position(|c| !(c.is_alphanumeric() || *c == '_'))
+                .unwrap_or(chars.len() - i - 1);
+            if chars.get(i + 1 + tag_len) == Some(&'$') {
+                let tag: String = chars[i..=i + 1 + tag_len].iter().collect();
+                let body: String = chars[i + tag.len()..].iter().collect();
+                i += tag.len() + body.find(&tag).map_or(body.len(), |p| p + tag.len());
+            } else {
+                i += 1;
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '$')) {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Tables an SQL script creates, alters, drops or writes to.
+///
+/// Only the statement's target counts: `CREATE INDEX ... ON t` touches `t`,
+/// a foreign key's `REFERENCES t` does not. Unquoted names are lowercased and
+/// the `public.` schema is dropped.
+pub fn tables_touched(sql: &str) -> BTreeSet<String> {
+    split_statements(sql)
+        .into_iter()
+        .flat_map(|statement| statement_tables(statement).1)
+        .collect()
+}
+
+/// Tables an SQL script drops with `DROP TABLE`, named as in [`tables_touched`]
+pub fn tables_dropped(sql: &str) -> BTreeSet<String> {
+    split_statements(sql)
+        .into_iter()
+        .map(statement_tables)
+        .filter(|(drops, _)| *drops)
+        .flat_map(|(_, tables)| tables)
+        .collect()
+}
+
+/// The tables a statement targets, and whether it drops them
+fn statement_tables(statement: &str) -> (bool, Vec<String>) {
+    let tokens = tokenize(statement);
+    let is = |i: usize, word: &str| matches!(tokens.get(i), Some(Token::Word(w)) if w.eq_ignore_ascii_case(word));
+    let skip = |mut i: usize, words: &[&str]| {
+        while words.iter().any(|w| is(i, w)) {
+            i += 1;
+        }
+        i
+    };
+    // The (possibly qualified) name at `i`, and the index after it
+    let name = |mut i: usize| -> Option<(String, usize)> {
+        let mut parts = Vec::new();
+        loop {
+            match tokens.get(i)? {
+                Token::Word(w) => parts.push(w.to_lowercase()),
+                Token::Quoted(q) => parts.push(q.clone()),
+                Token::Punct(_) => return None,
+            }
+            i += 1;
+            if tokens.get(i) != Some(&Token::Punct('.')) {
+                break;
+            }
+            i += 1;
+        }
+        if parts.len() > 1 && parts[0] == "public" {
+            parts.remove(0);
+        }
+        Some((parts.join("."), i))
+    };
+    let names = |mut i: usize, list: bool| {
+        let mut tables = Vec::new();
+        while let Some((table, next)) = name(i) {
+            tables.push(table);
+            if !list || tokens.get(next) != Some(&Token::Punct(',')) {
+                break;
+            }
+            i = next + 1;
+        }
+        tables
+    };
+
+    let modifiers = ["GLOBAL", "LOCAL", "TEMP", "TEMPORARY", "UNLOGGED", "UNIQUE"];
+    let tables = if is(0, "CREATE") {
+        let i = skip(1, &modifiers);
+        if is(i, "TABLE") {
+            names(skip(i + 1, &["IF", "NOT", "EXISTS"]), false)
+        } else if is(i, "INDEX") {
+            (i + 1..tokens.len())
+                .find(|&j| is(j, "ON"))
+                .map(|on| names(skip(on + 1, &["ONLY"]), false))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    } else if is(0, "ALTER") && is(1, "TABLE") {
+        names(skip(2, &["IF", "EXISTS", "ONLY"]), false)
+    } else if is(0, "DROP") && is(1, "TABLE") {
+        return (true, names(skip(2, &["IF", "EXISTS"]), true));
+    } else if is(0, "TRUNCATE") {
+        names(skip(1, &["TABLE", "ONLY"]), true)
+    } else if is(0, "INSERT") && is(1, "INTO") {
+        names(2, false)
+    } else if is(0, "DELETE") && is(1, "FROM") {
+        names(skip(2, &["ONLY"]), false)
+    } else if is(0, "UPDATE") {
+        names(skip(1, &["ONLY"]), false)
+    } else {
+        Vec::new()
+    };
+    (false, tables)
+}
+
 // Re-export StratusClient from db module for convenience
 pub use crate::db::StratusClient;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        let sql = "CREATE TABLE a (id int);\n\
+                   -- comment; not a statement\n\
+                   INSERT INTO a VALUES (1); INSERT INTO b VALUES ('x;''y');\n\
+                   /* block; /* nested; */ */\n\
+                   CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql;\n\
+                   DO $$ BEGIN PERFORM 1; END $$";
+        assert_eq!(
+            split_statements(sql),
+            vec![
+                "CREATE TABLE a (id int)",
+                "-- comment; not a statement\nINSERT INTO a VALUES (1)",
+                "INSERT INTO b VALUES ('x;''y')",
+                "/* block; /* nested; */ */\nCREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql",
+                "DO $$ BEGIN PERFORM 1; END $$",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_statements_skips_comment_only_segments() {
+        assert!(split_statements("-- Empty migration\n-- Add your SQL here").is_empty());
+        assert!(split_statements(" ; ;\n").is_empty());
+        assert_eq!(split_statements("SELECT $1, $2"), vec!["SELECT $1, $2"]);
+    }
+
+    #[test]
+    fn test_load_migrations_caches_unchanged_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dir = root.join("migrations");
+        for id in ["3", "1", "2"] {
+            let migration_dir = dir.join(format!("{}_m", id));
+            fs::create_dir_all(&migration_dir).unwrap();
+            fs::write(
+                migration_dir.join("meta.json"),
+                format!(r#"{{"id": "{}", "name": "m", "created_at": "", "dialect": "postgresql", "checksum": null}}"#, id),
+            )
+            .unwrap();
+            fs::write(migration_dir.join("up.sql"), format!("SELECT {};", id)).unwrap();
+        }
+
+        // Read-only commands leave the cache alone
+        assert_eq!(read_migrations(&dir).unwrap().len(), 3);
+        assert!(!cache_path(&dir).exists());
+
+        let first = load_migrations(&dir).unwrap();
+        let ids: Vec<&str> = first.iter().map(|m| m.meta.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        assert_eq!(first[0].checksum(), calculate_checksum("SELECT 1;"));
+        let cache_file = root
+            .join(".stratus")
+            .join("cache")
+            .join("migrations-migrations.json");
+        assert_eq!(cache_path(&dir), cache_file);
+        assert!(cache_file.exists());
+
+        // A cached entry stands in for meta.json until the files change
+        let cache = fs::read_to_string(&cache_file).unwrap();
+        fs::write(
+            &cache_file,
+            cache.replace(r#""name":"m""#, r#""name":"cached""#),
+        )
+        .unwrap();
+        let cached = load_migrations(&dir).unwrap();
+        assert!(cached.iter().all(|m| m.meta.name == "cached"));
+        // and its SQL is read when first used
+        fs::remove_file(dir.join("3_m").join("up.sql")).unwrap();
+        fs::create_dir(dir.join("3_m").join("up.sql")).unwrap();
+        assert_eq!(cached[0].up_sql().unwrap(), "SELECT 1;");
+        assert!(matches!(cached[2].up_sql(), Err(MigrateError::Read { .. })));
+        fs::remove_dir(dir.join("3_m").join("up.sql")).unwrap();
+
+        fs::write(dir.join("2_m").join("up.sql"), "SELECT 'edited';").unwrap();
+        let edited = load_migrations(&dir).unwrap();
+        assert_eq!(edited[1].meta.name, "m");
+        assert_eq!(edited[1].up_sql().unwrap(), "SELECT 'edited';");
+        assert_eq!(edited[1].checksum(), calculate_checksum("SELECT 'edited';"));
+    }
+
+    #[test]
+    fn test_resolve_applied_and_rolled_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dir = root.join("migrations");
+        for (id, status) in [("1", "reviewed"), ("2", "failed")] {
+            let migration_dir = dir.join(format!("{}_m", id));
+            fs::create_dir_all(&migration_dir).unwrap();
+            fs::write(
+                migration_dir.join("meta.json"),
+                format!(r#"{{"id": "{}", "name": "m", "created_at": "", "dialect": "postgresql", "checksum": null, "status": "{}"}}"#, id, status),
+            )
+            .unwrap();
+            fs::write(migration_dir.join("up.sql"), "ALTER TABLE t BROKEN;").unwrap();
+        }
+        let mut migrations = load_migrations(&dir).unwrap();
+        let table = MigrationsTable::default();
+        let mut db = crate::db::MemoryBackend::default();
+
+        // Finished by hand: recorded without running, and only once
+        resolve_applied(&mut db, &table, &migrations[0]).unwrap();
+        assert!(db.executed.is_empty());
+        let row = &db.migrations.as_ref().unwrap()[0];
+        assert_eq!((row.id.as_str(), row.duration_ms), ("1", None));
+        assert!(matches!(
+            resolve_applied(&mut db, &table, &migrations[0]),
+            Err(MigrateError::Resolve(_))
+        ));
+
+        resolve_rolled_back(&mut db, &table, &dir, &mut migrations[0]).unwrap();
+        assert!(db.migrations.as_ref().unwrap().is_empty());
+        assert!(matches!(
+            resolve_rolled_back(&mut db, &table, &dir, &mut migrations[0]),
+            Err(MigrateError::Resolve(_))
+        ));
+
+        // A failed migration goes back to draft, so deploys pick it up again
+        resolve_rolled_back(&mut db, &table, &dir, &mut migrations[1]).unwrap();
+        assert_eq!(load_migrations(&dir).unwrap()[1].meta.status, "draft");
+    }
+
+    #[test]
+    fn test_load_embedded_orders_by_id() {
+        use include_dir::{Dir, DirEntry, File};
+
+        const META_2: &[u8] = br#"{"id": "2", "name": "b", "created_at": "", "dialect": "postgresql", "checksum": null}"#;
+        const META_1: &[u8] = br#"{"id": "1", "name": "a", "created_at": "", "dialect": "postgresql", "checksum": null}"#;
+        static DIR: Dir = Dir::new(
+            "",
+            &[
+                DirEntry::Dir(Dir::new(
+                    "2_b",
+                    &[DirEntry::File(File::new("2_b/meta.json", META_2))],
+                )),
+                DirEntry::Dir(Dir::new(
+                    "1_a",
+                    &[
+                        DirEntry::File(File::new("1_a/meta.json", META_1)),
+                        DirEntry::File(File::new("1_a/up.sql", b"CREATE TABLE a (id int);")),
+                    ],
+                )),
+                DirEntry::Dir(Dir::new("notes", &[])),
+            ],
+        );
+
+        let migrations = Runner::new(&DIR).migrations().unwrap();
+        let ids: Vec<&str> = migrations.iter().map(|m| m.meta.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(migrations[0].up_sql().unwrap(), "CREATE TABLE a (id int);");
+        assert_eq!(migrations[0].meta.status, "draft");
+        assert_eq!(migrations[1].up_sql().unwrap(), "");
+    }
+
+    #[test]
+    fn test_tables_touched() {
+        let sql = "CREATE TABLE IF NOT EXISTS \"Orders\" (id int REFERENCES users(id));\n\
+                   -- ALTER TABLE ignored\n\
+                   ALTER TABLE ONLY public.Users ADD COLUMN age int;\n\
+                   CREATE UNIQUE INDEX CONCURRENTLY idx ON items (sku);\n\
+                   DROP TABLE IF EXISTS a, b CASCADE;\n\
+                   UPDATE audit.log SET n = 'DELETE FROM x';\n\
+                   CREATE FUNCTION f() RETURNS trigger AS $$ BEGIN INSERT INTO hidden VALUES (1); END $$ LANGUAGE plpgsql";
+        let tables: Vec<String> = tables_touched(sql).into_iter().collect();
+        assert_eq!(
+            tables,
+            vec!["Orders", "a", "audit.log", "b", "items", "users"]
+        );
+        let dropped: Vec<String> = tables_dropped(sql).into_iter().collect();
+        assert_eq!(dropped, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_check_branch() {
+        let m = |id: &str, name: &str, up: &str| {
+            Migration::new(
+                serde_json::from_value(serde_json::json!({
+                    "id": id, "name": name, "created_at": "", "dialect": "postgresql", "checksum": null
+                }))
+                .unwrap(),
+                up,
+                "",
+            )
+        };
+        let fork = vec![
+            m("100_1", "init", "CREATE TABLE users (id int);"),
+            m("110_1", "posts", "CREATE TABLE posts (id int);"),
+        ];
+        let base = vec![
+            fork[0].clone(),
+            fork[1].clone(),
+            m("300_1", "user-email", "ALTER TABLE users ADD email text;"),
+        ];
+
+        let clean = vec![
+            fork[0].clone(),
+            fork[1].clone(),
+            m("400_1", "tags", "CREATE TABLE tags (id int);"),
+        ];
+        let check = check_branch(&fork, &base, &clean).unwrap();
+        assert_eq!(check.added.len(), 1);
+        assert_eq!(check.landed[0].meta.name, "user-email");
+        assert!(check.problems.is_empty(), "{:?}", check.problems);
+
+        let conflicting = vec![
+            fork[0].clone(),
+            m("200_1", "user-age", "ALTER TABLE users ADD age int;"),
+            m("300_2", "orders", "CREATE TABLE orders (id int);"),
+        ];
+        let problems = check_branch(&fork, &base, &conflicting).unwrap().problems;
+        assert_eq!(
+            problems,
+            vec![
+                "110_1_posts was removed on this branch",
+                "300_2_orders and 300_1_user-email share the timestamp 300",
+                "200_1_user-age sorts before 300_1_user-email, which landed on the base after this branch forked",
+                "200_1_user-age and 300_1_user-email both change users",
+            ]
+        );
+    }
+}
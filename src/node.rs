@@ -0,0 +1,74 @@
+/**
+ * Stratus Node.js Module
+ *
+ * Native bindings for Node build tools via napi-rs: parse, generate and diff
+ * take and return plain JS objects instead of JSON strings.
+ */
+use crate::codegen::GeneratorOptions;
+use crate::schema::Schema;
+use napi::{Error, Result};
+use napi_derive::napi;
+use serde_json::Value;
+
+fn invalid(what: &str, e: impl std::fmt::Display) -> Error {
+    Error::from_reason(format!("Invalid {}: {}", what, e))
+}
+
+fn schema(value: Value) -> Result<Schema> {
+    serde_json::from_value(value).map_err(|e| invalid("schema", e))
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| Error::from_reason(e.to_string()))
+}
+
+/// Parse TypeSQL into `{ queries: [...] }`; throws on the first error diagnostic
+#[napi]
+pub fn parse(input: String) -> Result<Value> {
+    let (query_file, diagnostics) = crate::parser::parse_with_diagnostics(&input);
+    if let Some(error) = diagnostics
+        .iter()
+        .find(|d| d.severity == crate::diagnostics::Severity::Error)
+    {
+        return Err(Error::from_reason(error.to_string()));
+    }
+    to_value(&query_file)
+}
+
+/// Diagnostics for a TypeSQL file, checked against a schema when given
+#[napi]
+pub fn check(input: String, schema_json: Option<Value>) -> Result<Value> {
+    let diagnostics = match schema_json {
+        Some(value) => crate::analyze::check_source(&input, &schema(value)?),
+        None => crate::parser::parse_with_diagnostics(&input).1,
+    };
+    to_value(&diagnostics)
+}
+
+/// Run a generator (`ts`, `py`, `sql`, `ts-types`, `py-types`) and return the code
+#[napi]
+pub fn generate(
+    provider: String,
+    input: Option<String>,
+    schema_json: Option<Value>,
+    options: Option<Value>,
+) -> Result<String> {
+    let query_file = input
+        .map(|input| crate::parser::parse(&input))
+        .transpose()
+        .map_err(|e| Error::from_reason(e.to_string()))?;
+    let schema = schema_json.map(schema).transpose()?;
+    let options: GeneratorOptions = options
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(|e| invalid("options", e))?
+        .unwrap_or_default();
+    crate::codegen::generate(&provider, query_file.as_ref(), schema.as_ref(), &options)
+        .map_err(Error::from_reason)
+}
+
+/// Changes from one schema.json to another, with the migration SQL
+#[napi]
+pub fn diff(from: Value, to: Value) -> Result<Value> {
+    to_value(&crate::diff::diff_schemas(&schema(from)?, &schema(to)?).with_sql())
+}
@@ -0,0 +1,193 @@
+/**
+ * Stratus Output Module
+ *
+ * Status marks and section headers for CLI output, with switches for color
+ * and for plain-ASCII logs (CI systems that mangle emoji).
+ */
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Configure output styling for the process.
+///
+/// Color is used only when `color` is set, NO_COLOR is unset or empty and
+/// the stream written to is a terminal.
+pub fn init(color: bool, ascii: bool) {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    COLOR.store(color && !no_color, Ordering::Relaxed);
+    ASCII.store(ascii, Ordering::Relaxed);
+}
+
+/// Whether ANSI colors may be written to stdout
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed) && std::io::stdout().is_terminal()
+}
+
+/// Whether ANSI colors may be written to stderr
+pub fn stderr_color() -> bool {
+    COLOR.load(Ordering::Relaxed) && std::io::stderr().is_terminal()
+}
+
+/// Whether output is restricted to ASCII
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// Status mark printed before a result line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mark {
+    Ok,
+    Fail,
+    Skip,
+    Warn,
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unicode, plain, ansi) = match self {
+            Mark::Ok => ("✓", "[ok]", "32"),
+            Mark::Fail => ("✗", "[fail]", "31"),
+            Mark::Skip => ("○", "[skip]", "2"),
+            // The emoji renders two columns wide; pad to keep text aligned
+            Mark::Warn => ("⚠️ ", "[warn]", "33"),
+        };
+        let text = if ascii() { plain } else { unicode };
+        f.write_str(&paint(ansi, text))
+    }
+}
+
+/// Wrap text in an ANSI SGR code when stdout color is enabled
+fn paint(code: &str, text: &str) -> String {
+    if color() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Highlight one line of SQL: uppercase keywords, string literals and
+/// `--` comments. Returns the line unchanged when color is off.
+pub fn highlight_sql(line: &str) -> String {
+    if !color() {
+        return line.to_string();
+    }
+
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let len = if rest.starts_with("--") {
+            out.push_str(&paint("2", rest));
+            rest.len()
+        } else if c == '\'' {
+            let len = rest[1..].find('\'').map_or(rest.len(), |end| end + 2);
+            out.push_str(&paint("32", &rest[..len]));
+            len
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            // Generated DDL writes keywords in uppercase and identifiers in lowercase
+            if len > 1 && word.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                out.push_str(&paint("1;34", word));
+            } else {
+                out.push_str(word);
+            }
+            len
+        } else {
+            out.push(c);
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Print an SQL script as is, highlighted on a terminal
+pub fn print_sql(sql: &str) {
+    for line in sql.trim_end().lines() {
+        println!("{}", highlight_sql(line));
+    }
+}
+
+/// Highlight one EXPLAIN plan line: node names bold, estimates dimmed
+pub fn highlight_plan(line: &str) -> String {
+    if !color() {
+        return line.to_string();
+    }
+    let indent = line.len() - line.trim_start().len();
+    let (prefix, rest) = line.split_at(indent);
+    let (arrow, rest) = match rest.strip_prefix("->  ") {
+        Some(rest) => ("->  ", rest),
+        None => ("", rest),
+    };
+    match rest.find("  (") {
+        Some(pos) => format!(
+            "{}{}{}{}",
+            prefix,
+            arrow,
+            paint("1", &rest[..pos]),
+            paint("2", &rest[pos..])
+        ),
+        None => line.to_string(),
+    }
+}
+
+/// Print a DDL script statement by statement, marking each line with `+`
+/// for additions, `-` for removals and `~` for other changes
+#[cfg(feature = "db")]
+pub fn print_sql_preview(sql: &str) {
+    for statement in crate::migrate::split_statements(sql) {
+        let upper = statement
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("--"))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_start()
+            .to_ascii_uppercase();
+        let (marker, code) = if upper.starts_with("DROP") || upper.contains(" DROP ") {
+            ("-", "31")
+        } else if upper.starts_with("CREATE") || upper.contains(" ADD ") {
+            ("+", "32")
+        } else {
+            ("~", "33")
+        };
+        let statement = format!("{};", statement);
+        for line in statement.lines() {
+            println!("{} {}", paint(code, marker), highlight_sql(line));
+        }
+    }
+}
+
+/// An emoji followed by its spacing, or nothing in ASCII mode
+pub fn icon(icon: &str) -> String {
+    if ascii() {
+        String::new()
+    } else {
+        format!("{}  ", icon)
+    }
+}
+
+/// Print a command header: a blank line, icon and title, then a rule
+pub fn header(emoji: &str, title: &str) {
+    println!("\n{}{}", icon(emoji), title);
+    println!("{}", "=".repeat(50));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks_follow_mode() {
+        init(false, false);
+        assert_eq!(Mark::Ok.to_string(), "✓");
+        init(false, true);
+        assert_eq!(Mark::Fail.to_string(), "[fail]");
+        assert!(format!("{} done", Mark::Warn).is_ascii());
+        init(false, false);
+    }
+}
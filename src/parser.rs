@@ -1,5 +1,17 @@
-use crate::ast::{Param, Query, QueryFile};
+use crate::ast::{CacheDirective, Param, Query, QueryFile};
+use crate::diagnostics::{Diagnostic, Diagnostics, Span};
+use std::collections::HashMap;
+use std::ops::Range;
 use std::str::Lines;
+use thiserror::Error;
+
+/// TypeSQL parse errors
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// `line` is 1-based
+    #[error("line {line}: expected a query header like `# name: GetUser :one id: number`")]
+    InvalidHeader { line: usize },
+}
 
 fn is_whitespace(c: char) -> bool {
     c == ' ' || c == '\t'
@@ -56,22 +68,13 @@ fn parse_param(line: &str) -> Option<(&str, (String, String))> {
     Some((rest, (name, type_)))
 }
 
-fn parse_query(lines: &mut Lines) -> Option<Query> {
-    // Find header line
-    let header_line = lines.next()?;
-    let header_line = header_line.trim();
-
-    // Skip empty lines
-    if header_line.is_empty() {
-        return parse_query(lines);
-    }
-
+/// Parse a query header line (`# name: X :one id: number`, `#` optional).
+///
+/// Returns the name, return type, params and any trailing text after them.
+pub(crate) fn parse_header(line: &str) -> Option<(String, String, Vec<Param>, &str)> {
     // Check for comment
-    let header = if header_line.starts_with('#') {
-        &header_line[1..]
-    } else {
-        header_line
-    };
+    let header = line.trim();
+    let header = header.strip_prefix('#').unwrap_or(header);
 
     // Parse name
     let (rest, name) = parse_name(header)?;
@@ -89,6 +92,70 @@ fn parse_query(lines: &mut Lines) -> Option<Query> {
         current = trim_ws(rest_after);
     }
 
+    Some((name, return_type, params, current.trim_end()))
+}
+
+/// Parse the directives after a header's params, such as `@cache(ttl=60s)`.
+///
+/// Trailing text that isn't a directive is left alone.
+pub(crate) fn parse_directives(text: &str) -> Result<Option<CacheDirective>, String> {
+    let mut cache = None;
+    for word in text.split_whitespace().filter(|w| w.starts_with('@')) {
+        let (name, args) = match word.split_once('(') {
+            Some((name, args)) => (
+                name,
+                args.strip_suffix(')')
+                    .ok_or_else(|| format!("`{}` is missing its closing parenthesis", word))?,
+            ),
+            None => (word, ""),
+        };
+        match name {
+            "@cache" => {
+                let ttl_seconds = args
+                    .strip_prefix("ttl=")
+                    .and_then(parse_duration)
+                    .ok_or_else(|| format!("expected `@cache(ttl=60s)`, found `{}`", word))?;
+                cache = Some(CacheDirective { ttl_seconds });
+            }
+            _ => return Err(format!("unknown directive `{}`", name)),
+        }
+    }
+    Ok(cache)
+}
+
+/// Seconds in `90`, `90s`, `15m`, `2h` or `1d`; zero is not a duration
+fn parse_duration(text: &str) -> Option<u64> {
+    let (digits, unit) = text.split_at(
+        text.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len()),
+    );
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    digits
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(scale)
+        .filter(|&seconds| seconds > 0)
+}
+
+fn parse_query(lines: &mut Lines) -> Option<Query> {
+    // Find header line
+    let header_line = lines.next()?;
+    let header_line = header_line.trim();
+
+    // Skip empty lines
+    if header_line.is_empty() {
+        return parse_query(lines);
+    }
+
+    let (name, return_type, params, rest) = parse_header(header_line)?;
+    let cache = parse_directives(rest).ok().flatten();
+
     // Parse SQL lines
     let mut sql_parts = Vec::<String>::new();
     for line in lines {
@@ -106,10 +173,11 @@ fn parse_query(lines: &mut Lines) -> Option<Query> {
         return_type,
         sql,
         params,
+        cache,
     })
 }
 
-pub fn parse(input: &str) -> Result<QueryFile, String> {
+pub fn parse(input: &str) -> Result<QueryFile, ParseError> {
     let mut lines = input.lines();
     let mut queries = Vec::new();
 
@@ -120,46 +188,293 @@ pub fn parse(input: &str) -> Result<QueryFile, String> {
     Ok(QueryFile { queries })
 }
 
+/// Where a line of a query's SQL sits in the source file
+#[derive(Debug, Clone)]
+struct SqlLine {
+    /// 1-based line number
+    line: usize,
+    /// 1-based column of the first non-blank character
+    column: usize,
+    /// Byte offset of the line's trimmed text in `Query::sql`
+    offset: usize,
+}
+
+/// A parsed query with its position in the source file
+#[derive(Debug)]
+pub(crate) struct QueryBlock<'a> {
+    pub(crate) query: Query,
+    /// 1-based line number of the header
+    pub(crate) header_line: usize,
+    header: &'a str,
+    lines: Vec<SqlLine>,
+}
+
+impl QueryBlock<'_> {
+    /// Source span of a byte range of `query.sql`
+    pub(crate) fn span(&self, range: Range<usize>) -> Span {
+        let Some(line) = self.lines.iter().rev().find(|l| l.offset <= range.start) else {
+            return self.header_span(None);
+        };
+        let sql = &self.query.sql;
+        Span::on_line(
+            line.line,
+            line.column + sql[line.offset..range.start].chars().count(),
+            sql[range].chars().count(),
+        )
+    }
+
+    /// Span of `word` in the header (searched after the query name), else the whole header
+    fn header_span(&self, word: Option<&str>) -> Span {
+        let name_end = self
+            .header
+            .find("name:")
+            .and_then(|pos| {
+                let after = pos + "name:".len();
+                self.header[after..]
+                    .find(&self.query.name)
+                    .map(|p| after + p)
+            })
+            .map(|pos| (pos, pos + self.query.name.len()));
+        let found = match word {
+            None => name_end,
+            Some(word) => name_end.and_then(|(_, end)| {
+                self.header[end..]
+                    .find(word)
+                    .map(|p| (end + p, end + p + word.len()))
+            }),
+        };
+        match found {
+            Some((start, end)) => Span::on_line(
+                self.header_line,
+                self.header[..start].chars().count() + 1,
+                self.header[start..end].chars().count(),
+            ),
+            None => Span::on_line(self.header_line, 1, self.header.chars().count()),
+        }
+    }
+}
+
+/// Split a TypeSQL file into queries, reporting blocks without a valid header.
+///
+/// Unlike [`parse`], a bad block doesn't end parsing; the next blank-line
+/// separated block is tried.
+pub(crate) fn query_blocks<'a>(
+    input: &'a str,
+    diagnostics: &mut Diagnostics,
+) -> Vec<QueryBlock<'a>> {
+    let mut blocks = Vec::new();
+    let mut lines = input.lines().enumerate().peekable();
+
+    loop {
+        while lines.next_if(|(_, l)| l.trim().is_empty()).is_some() {}
+        let Some((n, header)) = lines.next() else {
+            break;
+        };
+
+        let mut sql_lines = Vec::new();
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        while let Some((i, l)) = lines.next_if(|(_, l)| !l.trim().is_empty()) {
+            let text = l.trim();
+            let indent = &l[..l.len() - l.trim_start().len()];
+            sql_lines.push(SqlLine {
+                line: i + 1,
+                column: indent.chars().count() + 1,
+                offset,
+            });
+            offset += text.len() + 1;
+            parts.push(text);
+        }
+
+        let Some((name, return_type, params, rest)) = parse_header(header) else {
+            diagnostics.push(Diagnostic::error(
+                "invalid-header",
+                Span::on_line(n + 1, 1, header.trim_end().chars().count()),
+                "expected a query header like `# name: GetUser :one id: number`",
+            ));
+            continue;
+        };
+        let cache = parse_directives(rest).unwrap_or_else(|message| {
+            let start = rest.as_ptr() as usize - header.as_ptr() as usize;
+            diagnostics.push(Diagnostic::error(
+                "invalid-directive",
+                Span::on_line(
+                    n + 1,
+                    header[..start].chars().count() + 1,
+                    rest.chars().count(),
+                ),
+                message,
+            ));
+            None
+        });
+        blocks.push(QueryBlock {
+            query: Query {
+                name,
+                return_type,
+                sql: parts.join(" "),
+                params,
+                cache,
+            },
+            header_line: n + 1,
+            header,
+            lines: sql_lines,
+        });
+    }
+
+    blocks
+}
+
+/// Parse a TypeSQL file, collecting every problem instead of stopping at the first.
+///
+/// Reports invalid headers, queries without SQL, duplicate query names and
+/// `$n` placeholders that don't match the declared parameters.
+pub fn parse_with_diagnostics(input: &str) -> (QueryFile, Diagnostics) {
+    let mut diagnostics = Diagnostics::new();
+    let blocks = query_blocks(input, &mut diagnostics);
+
+    let mut defined: HashMap<&str, usize> = HashMap::new();
+    for block in &blocks {
+        let query = &block.query;
+        match defined.get(query.name.as_str()) {
+            Some(line) => diagnostics.push(Diagnostic::error(
+                "duplicate-query",
+                block.header_span(None),
+                format!("query '{}' is already defined on line {}", query.name, line),
+            )),
+            None => {
+                defined.insert(&query.name, block.header_line);
+            }
+        }
+
+        if query.sql.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                "empty-query",
+                block.header_span(None),
+                format!("query '{}' has no SQL", query.name),
+            ));
+            continue;
+        }
+
+        let mut used = Vec::new();
+        for token in crate::format::tokenize(&query.sql) {
+            let Some(n) = token
+                .text
+                .strip_prefix('$')
+                .and_then(|n| n.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            // A header without parameters leaves them to be inferred
+            if !query.params.is_empty() && n > query.params.len() && !used.contains(&n) {
+                let start = token.text.as_ptr() as usize - query.sql.as_ptr() as usize;
+                diagnostics.push(Diagnostic::error(
+                    "undeclared-param",
+                    block.span(start..start + token.text.len()),
+                    format!(
+                        "${} is used but query '{}' declares {} parameter(s)",
+                        n,
+                        query.name,
+                        query.params.len()
+                    ),
+                ));
+            }
+            used.push(n);
+        }
+        for param in &query.params {
+            if !used.contains(&param.ordinal) {
+                diagnostics.push(Diagnostic::warning(
+                    "unused-param",
+                    block.header_span(Some(&param.name)),
+                    format!(
+                        "parameter '{}' (${}) is not used",
+                        param.name, param.ordinal
+                    ),
+                ));
+            }
+        }
+    }
+
+    diagnostics.sort();
+    let queries = blocks.into_iter().map(|b| b.query).collect();
+    (QueryFile { queries }, diagnostics)
+}
+
 /// Represents a parsed SELECT column
 #[derive(Debug, Clone)]
 pub struct SelectColumn {
     pub table_name: Option<String>,
+    /// Column name, or the SQL of an expression
     pub column_name: String,
+    /// Name given with AS
+    pub alias: Option<String>,
     pub is_wildcard: bool,
 }
 
-/// Extract tables from FROM clause
+impl SelectColumn {
+    /// `*`, or `table.*` with a qualifier
+    pub fn wildcard(table_name: Option<String>) -> Self {
+        Self {
+            table_name,
+            column_name: "*".to_string(),
+            alias: None,
+            is_wildcard: true,
+        }
+    }
+
+    /// Name of the column in the result row
+    pub fn output_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.column_name)
+    }
+}
+
+/// Byte offset of an ASCII keyword, ignoring case
+///
+/// Offsets into `to_lowercase()` copies are not offsets into the original,
+/// since lowercasing changes the length of some characters (`İ`, `K`).
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Extract the tables a query reads or writes, in order, without CTE names
+///
+/// SQL the parser rejects falls back to scanning the FROM clause.
 pub fn extract_tables_from_sql(sql: &str) -> Vec<String> {
+    match crate::sql::parse_statement(sql) {
+        Ok(statement) => crate::sql::tables(&statement),
+        Err(_) => scan_tables(sql),
+    }
+}
+
+/// Tables after FROM and JOIN, by keyword search
+fn scan_tables(sql: &str) -> Vec<String> {
     let mut tables = Vec::new();
 
     // Find FROM keyword
-    if let Some(from_pos) = sql.to_lowercase().find("from") {
+    if let Some(from_pos) = find_ignore_case(sql, "from") {
         let after_from = &sql[from_pos + 4..];
 
         // Find WHERE to limit our parsing
-        let before_where = if let Some(where_pos) = after_from.to_lowercase().find("where") {
+        let before_where = if let Some(where_pos) = find_ignore_case(after_from, "where") {
             &after_from[..where_pos]
         } else {
             after_from
         };
 
-        // Trim and work with lowercase version
         let trimmed = before_where.trim();
-        let lower_trimmed = trimmed.to_lowercase();
 
-        let join_parts: Vec<&str> = if lower_trimmed.starts_with("join ") {
+        let join_parts: Vec<&str> = if find_ignore_case(trimmed, "join ") == Some(0) {
             // Edge case: starts with JOIN (no table before)
-            vec!["", &trimmed[4..].trim_start()]
-        } else if lower_trimmed.contains(" join ") {
-            // Space before and after JOIN
-            // Find position in lowercase, then use same position in original
-            let pos = lower_trimmed.find(" join ").unwrap();
-            let join_delim = &trimmed[pos..pos + 5]; // 5 = " join ".len()
+            vec!["", trimmed[4..].trim_start()]
+        } else if let Some(pos) = find_ignore_case(trimmed, " join ") {
+            // Space before and after JOIN, split on it as spelled in the original
+            let join_delim = &trimmed[pos..pos + 5]; // 5 = " join".len()
             trimmed.split(join_delim).collect()
-        } else if lower_trimmed.contains("join ") {
+        } else if let Some(pos) = find_ignore_case(trimmed, "join ") {
             // Space after JOIN (but no space before)
-            let pos = lower_trimmed.find("join ").unwrap();
-            let join_delim = &trimmed[pos..pos + 4]; // 4 = "join ".len()
+            let join_delim = &trimmed[pos..pos + 4]; // 4 = "join".len()
             let parts: Vec<&str> = trimmed.split(join_delim).collect();
             if parts.len() >= 2 {
                 vec![parts[0], parts[1]]
@@ -224,16 +539,27 @@ pub fn extract_tables_from_sql(sql: &str) -> Vec<String> {
     tables
 }
 
-/// Extract SELECT columns from SQL query
+/// Extract the columns of a query's outer select list
+///
+/// SQL the parser rejects falls back to splitting the text between SELECT
+/// and FROM on commas.
 pub fn extract_select_columns(sql: &str) -> Vec<SelectColumn> {
+    match crate::sql::parse_statement(sql) {
+        Ok(statement) => crate::sql::select_columns(&statement),
+        Err(_) => scan_select_columns(sql),
+    }
+}
+
+/// Select-list columns, by keyword search
+fn scan_select_columns(sql: &str) -> Vec<SelectColumn> {
     let mut columns = Vec::new();
 
     // Find SELECT keyword
-    if let Some(select_pos) = sql.to_lowercase().find("select") {
+    if let Some(select_pos) = find_ignore_case(sql, "select") {
         let after_select = &sql[select_pos + 6..];
 
         // Find FROM keyword to get end of SELECT clause
-        let from_pos = after_select.to_lowercase().find("from");
+        let from_pos = find_ignore_case(after_select, "from");
         let select_content = if let Some(pos) = from_pos {
             &after_select[..pos]
         } else {
@@ -251,22 +577,13 @@ pub fn extract_select_columns(sql: &str) -> Vec<SelectColumn> {
 
             // Check for wildcard
             if part == "*" {
-                columns.push(SelectColumn {
-                    table_name: None,
-                    column_name: "*".to_string(),
-                    is_wildcard: true,
-                });
+                columns.push(SelectColumn::wildcard(None));
                 continue;
             }
 
             // Check for table.*
-            if part.ends_with(".*") {
-                let table_name = &part[..part.len() - 2];
-                columns.push(SelectColumn {
-                    table_name: Some(table_name.to_string()),
-                    column_name: "*".to_string(),
-                    is_wildcard: true,
-                });
+            if let Some(table_name) = part.strip_suffix(".*") {
+                columns.push(SelectColumn::wildcard(Some(table_name.to_string())));
                 continue;
             }
 
@@ -277,12 +594,14 @@ pub fn extract_select_columns(sql: &str) -> Vec<SelectColumn> {
                 columns.push(SelectColumn {
                     table_name: Some(table_name.to_string()),
                     column_name: col_name.to_string(),
+                    alias: None,
                     is_wildcard: false,
                 });
             } else {
                 columns.push(SelectColumn {
                     table_name: None,
                     column_name: part.to_string(),
+                    alias: None,
                     is_wildcard: false,
                 });
             }
@@ -318,6 +637,31 @@ mod tests {
         assert_eq!(qf.queries.len(), 2);
     }
 
+    #[test]
+    fn test_parse_with_diagnostics() {
+        let input = "# name: GetUser :one id: number\nSELECT * FROM users\n  WHERE id = $2;\n\n\
+                     -- stray comment\n\n\
+                     # name: GetUser :many\n\n\
+                     # name: ListUsers :many\nSELECT * FROM users;\n";
+        let (qf, diagnostics) = parse_with_diagnostics(input);
+        assert_eq!(qf.queries.len(), 3);
+
+        let found: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "1:22: warning[unused-param]: parameter 'id' ($1) is not used",
+                "3:14: error[undeclared-param]: $2 is used but query 'GetUser' declares 1 parameter(s)",
+                "5:1: error[invalid-header]: expected a query header like `# name: GetUser :one id: number`",
+                "7:9: error[duplicate-query]: query 'GetUser' is already defined on line 1",
+                "7:9: error[empty-query]: query 'GetUser' has no SQL",
+            ]
+        );
+
+        let (_, diagnostics) = parse_with_diagnostics(include_str!("../examples/queries.sql"));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
     #[test]
     fn test_parse_params() {
         let input = "# name: GetUserById :one id: number\nSELECT * FROM users WHERE id = $1;\n";
@@ -329,4 +673,44 @@ mod tests {
         assert_eq!(q.params[0].name, "id");
         assert_eq!(q.params[0].type_, "number");
     }
+
+    #[test]
+    fn test_parse_cache_directive() {
+        let input = "# name: GetUser :one id: number @cache(ttl=5m)\nSELECT * FROM users WHERE id = $1;\n\n\
+                     # name: ListUsers :many @cache(ttl=soon)\nSELECT * FROM users;\n";
+        let qf = parse(input).unwrap();
+        assert_eq!(
+            qf.queries[0].cache,
+            Some(CacheDirective { ttl_seconds: 300 })
+        );
+        assert_eq!(qf.queries[0].params.len(), 1);
+        assert_eq!(qf.queries[1].cache, None);
+
+        let (_, diagnostics) = parse_with_diagnostics(input);
+        let found: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec!["4:25: error[invalid-directive]: expected `@cache(ttl=60s)`, found `@cache(ttl=soon)`"]
+        );
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("0s"), None);
+        assert_eq!(
+            parse_directives("@memo"),
+            Err("unknown directive `@memo`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_survives_characters_that_change_length_when_lowercased() {
+        // `İ` lowercases to three bytes and `K` (Kelvin) to one
+        assert_eq!(extract_tables_from_sql("İfrom"), Vec::<String>::new());
+        assert_eq!(
+            extract_tables_from_sql("SELECT 'İİ' FROM users JOIN posts ON true WHERE K = 1"),
+            vec!["users", "posts"]
+        );
+        assert!(extract_select_columns("İselect").is_empty());
+        let columns = extract_select_columns("SELECT 'K', u.id FROM users u");
+        assert_eq!(columns[0].column_name, "'K'");
+        assert_eq!(columns[1].table_name.as_deref(), Some("u"));
+    }
 }
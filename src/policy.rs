@@ -0,0 +1,128 @@
+/**
+ * Stratus Policy Module
+ *
+ * Checks the SQL about to be applied to an environment against the
+ * `policies` declared for it in stratus.json. Sync, deploy, db push and
+ * migrate reset refuse to apply SQL that breaks a rule, and sync, db push
+ * and migrate diff refuse a plan they generate that breaks one, unless
+ * --override-policy is given.
+ */
+use crate::config::PolicyConfig;
+use crate::migrate::{split_statements, tables_dropped, MigrateResult, Migration};
+
+/// SQL about to be applied, with its review status
+#[derive(Debug, Clone)]
+pub struct Change<'a> {
+    /// Migration id and name, or what produced the SQL
+    pub name: String,
+    pub sql: &'a str,
+    /// Migration status; `None` for SQL that never was a migration
+    pub status: Option<&'a str>,
+}
+
+impl<'a> Change<'a> {
+    pub fn migration(migration: &'a Migration) -> MigrateResult<Self> {
+        Ok(Self {
+            name: format!("{}_{}", migration.meta.id, migration.meta.name),
+            sql: migration.up_sql()?,
+            status: Some(&migration.meta.status),
+        })
+    }
+
+    /// SQL applied without a migration, e.g. by `db push`
+    pub fn unreviewed(name: impl Into<String>, sql: &'a str) -> Self {
+        Self {
+            name: name.into(),
+            sql,
+            status: None,
+        }
+    }
+}
+
+/// Check a batch of changes; returns one message per broken rule
+pub fn check(policies: &PolicyConfig, changes: &[Change]) -> Vec<String> {
+    let mut violations = Vec::new();
+    for change in changes {
+        if policies.deny_drop_table {
+            let dropped: Vec<String> = tables_dropped(change.sql).into_iter().collect();
+            if !dropped.is_empty() {
+                violations.push(format!(
+                    "{} drops table {} (denyDropTable)",
+                    change.name,
+                    dropped.join(", ")
+                ));
+            }
+        }
+        if policies.require_reviewed && change.status != Some("reviewed") {
+            violations.push(format!(
+                "{} is {}, not reviewed (requireReviewed)",
+                change.name,
+                change.status.unwrap_or("unreviewed SQL")
+            ));
+        }
+    }
+    if let Some(max) = policies.max_statements {
+        let statements: usize = changes.iter().map(|c| split_statements(c.sql).len()).sum();
+        if statements > max {
+            violations.push(format!(
+                "{} statements exceed the limit of {} (maxStatements)",
+                statements, max
+            ));
+        }
+    }
+    violations
+}
+
+/// Check SQL generated from a schema diff before it is written or applied:
+/// every rule but requireReviewed, which is checked once the SQL is applied
+pub fn check_plan(policies: &PolicyConfig, change: &Change) -> Vec<String> {
+    let policies = PolicyConfig {
+        require_reviewed: false,
+        ..policies.clone()
+    };
+    check(&policies, std::slice::from_ref(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_policies() {
+        let policies: PolicyConfig = serde_json::from_str(
+            r#"{ "denyDropTable": true, "requireReviewed": true, "maxStatements": 2 }"#,
+        )
+        .unwrap();
+        let safe = Change {
+            name: "1_add_age".to_string(),
+            sql: "ALTER TABLE users ADD age int;",
+            status: Some("reviewed"),
+        };
+        assert!(check(&policies, std::slice::from_ref(&safe)).is_empty());
+
+        let push = Change::unreviewed(
+            "db push",
+            "DROP TABLE IF EXISTS sessions; ALTER TABLE users DROP COLUMN age;",
+        );
+        assert_eq!(
+            check(&policies, &[safe, push]),
+            vec![
+                "db push drops table sessions (denyDropTable)",
+                "db push is unreviewed SQL, not reviewed (requireReviewed)",
+                "3 statements exceed the limit of 2 (maxStatements)",
+            ]
+        );
+        assert!(check(
+            &PolicyConfig::default(),
+            &[Change::unreviewed("x", "DROP TABLE t")]
+        )
+        .is_empty());
+
+        // A plan is not refused for being unreviewed, only once it is applied
+        let plan = Change::unreviewed("sync", "DROP TABLE IF EXISTS sessions;");
+        assert_eq!(
+            check_plan(&policies, &plan),
+            vec!["sync drops table sessions (denyDropTable)"]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+/**
+ * Stratus Schema Registry Module
+ *
+ * Publishes schema.json versions to a central HTTP registry and fetches them
+ * back, so services that only consume generated types can pin a published
+ * version. The protocol is two calls:
+ *
+ *   PUT {registry}/schemas/{name}/versions/{version}   body: schema.json
+ *   GET {registry}/schemas/{name}/versions/{version}   (`latest` allowed)
+ *
+ * A published version is immutable: a second PUT is answered with 409.
+ */
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Version name that `pull` resolves to the newest published version
+pub const LATEST: &str = "latest";
+
+/// Registry errors
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("Invalid {kind} '{value}': use letters, digits, '.', '_' and '-'")]
+    InvalidName { kind: &'static str, value: String },
+
+    #[error("{name}@{version} is already published")]
+    Exists { name: String, version: String },
+
+    #[error("{name}@{version} is not in the registry")]
+    NotFound { name: String, version: String },
+
+    #[error("The registry rejected the token (HTTP {0})")]
+    Unauthorized(u16),
+
+    #[error("Registry returned HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+
+    #[error("Cannot reach the registry: {0}")]
+    Transport(String),
+
+    #[error("The registry sent an invalid schema: {0}")]
+    InvalidSchema(String),
+}
+
+pub type RegistryResult<T> = Result<T, RegistryError>;
+
+/// A schema fetched from the registry
+#[derive(Debug, Clone)]
+pub struct PulledSchema {
+    /// The version served, which `latest` resolves to when the registry says
+    pub version: String,
+    pub contents: String,
+    /// sha256 of `contents`
+    pub checksum: String,
+}
+
+/// Client for one registry
+pub struct RegistryClient {
+    base: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl RegistryClient {
+    pub fn new(url: &str, token: Option<String>) -> RegistryResult<Self> {
+        let tls =
+            native_tls::TlsConnector::new().map_err(|e| RegistryError::Transport(e.to_string()))?;
+        Ok(Self {
+            base: url.trim_end_matches('/').to_string(),
+            token,
+            agent: ureq::AgentBuilder::new()
+                .tls_connector(Arc::new(tls))
+                .timeout(Duration::from_secs(30))
+                .build(),
+        })
+    }
+
+    fn url(&self, name: &str, version: &str) -> RegistryResult<String> {
+        check_name("schema name", name)?;
+        check_name("version", version)?;
+        Ok(format!(
+            "{}/schemas/{}/versions/{}",
+            self.base, name, version
+        ))
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, url)
+            .set("Accept", "application/json");
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Publish `contents` as `name@version`; returns its checksum
+    pub fn push(&self, name: &str, version: &str, contents: &str) -> RegistryResult<String> {
+        if version == LATEST {
+            return Err(RegistryError::InvalidName {
+                kind: "version",
+                value: version.to_string(),
+            });
+        }
+        let checksum = checksum(contents);
+        self.request("PUT", &self.url(name, version)?)
+            .set("Content-Type", "application/json")
+            .set("X-Stratus-Checksum", &checksum)
+            .send_string(contents)
+            .map_err(|e| http_error(e, name, version))?;
+        Ok(checksum)
+    }
+
+    /// Fetch `name@version`, or the newest version for `latest`
+    pub fn pull(&self, name: &str, version: &str) -> RegistryResult<PulledSchema> {
+        let response = self
+            .request("GET", &self.url(name, version)?)
+            .call()
+            .map_err(|e| http_error(e, name, version))?;
+        let served = response
+            .header("X-Stratus-Version")
+            .unwrap_or(version)
+            .to_string();
+        let expected = response.header("X-Stratus-Checksum").map(str::to_string);
+        let contents = response
+            .into_string()
+            .map_err(|e| RegistryError::Transport(e.to_string()))?;
+
+        let checksum = checksum(&contents);
+        if expected.is_some_and(|expected| expected != checksum) {
+            return Err(RegistryError::InvalidSchema(
+                "checksum does not match the published one".to_string(),
+            ));
+        }
+        serde_json::from_str::<crate::schema::Schema>(&contents)
+            .map_err(|e| RegistryError::InvalidSchema(e.to_string()))?;
+        Ok(PulledSchema {
+            version: served,
+            contents,
+            checksum,
+        })
+    }
+}
+
+/// sha256 of a schema, as published
+pub fn checksum(contents: &str) -> String {
+    Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn check_name(kind: &'static str, value: &str) -> RegistryResult<()> {
+    let valid = !value.is_empty()
+        && !value.starts_with('.')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(RegistryError::InvalidName {
+            kind,
+            value: value.to_string(),
+        })
+    }
+}
+
+fn http_error(error: ureq::Error, name: &str, version: &str) -> RegistryError {
+    match error {
+        ureq::Error::Status(401 | 403, response) => RegistryError::Unauthorized(response.status()),
+        ureq::Error::Status(404, _) => RegistryError::NotFound {
+            name: name.to_string(),
+            version: version.to_string(),
+        },
+        ureq::Error::Status(409, _) => RegistryError::Exists {
+            name: name.to_string(),
+            version: version.to_string(),
+        },
+        ureq::Error::Status(status, response) => RegistryError::Http {
+            status,
+            message: response.into_string().unwrap_or_default(),
+        },
+        ureq::Error::Transport(t) => RegistryError::Transport(t.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A registry holding versions in memory, accepting one token
+    fn serve() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            let mut versions: HashMap<String, String> = HashMap::new();
+            for mut request in server.incoming_requests() {
+                let authorized = request
+                    .headers()
+                    .iter()
+                    .any(|h| h.field.equiv("Authorization") && h.value == "Bearer s3cret");
+                let path = request.url().to_string();
+                let response = match (request.method(), authorized) {
+                    (_, false) => tiny_http::Response::from_string("").with_status_code(401),
+                    (tiny_http::Method::Put, _) if versions.contains_key(&path) => {
+                        tiny_http::Response::from_string("").with_status_code(409)
+                    }
+                    (tiny_http::Method::Put, _) => {
+                        let mut body = String::new();
+                        request.as_reader().read_to_string(&mut body).unwrap();
+                        versions.insert(path, body);
+                        tiny_http::Response::from_string("").with_status_code(201)
+                    }
+                    _ => {
+                        let latest = path.strip_suffix("/latest").map(|prefix| {
+                            versions
+                                .keys()
+                                .filter(|k| k.starts_with(prefix))
+                                .max()
+                                .cloned()
+                                .unwrap_or_default()
+                        });
+                        let key = latest.as_ref().unwrap_or(&path);
+                        match versions.get(key) {
+                            Some(body) => {
+                                let version = key.rsplit('/').next().unwrap();
+                                tiny_http::Response::from_string(body.clone()).with_header(
+                                    tiny_http::Header::from_bytes("X-Stratus-Version", version)
+                                        .unwrap(),
+                                )
+                            }
+                            None => tiny_http::Response::from_string("").with_status_code(404),
+                        }
+                    }
+                };
+                let _ = request.respond(response);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_push_and_pull_round_trip() {
+        let url = serve();
+        let client = RegistryClient::new(&url, Some("s3cret".to_string())).unwrap();
+        let v1 = r#"{"tables": {}}"#;
+        let v2 = r#"{"tables": {"users": {"columns": {}}}}"#;
+
+        assert_eq!(client.push("shop", "1.0.0", v1).unwrap(), checksum(v1));
+        client.push("shop", "1.1.0", v2).unwrap();
+        assert!(matches!(
+            client.push("shop", "1.0.0", v2),
+            Err(RegistryError::Exists { .. })
+        ));
+
+        let pinned = client.pull("shop", "1.0.0").unwrap();
+        assert_eq!(pinned.contents, v1);
+        let latest = client.pull("shop", LATEST).unwrap();
+        assert_eq!(
+            (latest.version.as_str(), latest.contents.as_str()),
+            ("1.1.0", v2)
+        );
+        assert!(matches!(
+            client.pull("shop", "9.9.9"),
+            Err(RegistryError::NotFound { .. })
+        ));
+
+        let anonymous = RegistryClient::new(&url, None).unwrap();
+        assert!(matches!(
+            anonymous.pull("shop", "1.0.0"),
+            Err(RegistryError::Unauthorized(401))
+        ));
+        assert!(matches!(
+            client.push("shop", "../x", v1),
+            Err(RegistryError::InvalidName { .. })
+        ));
+        assert!(client.push("shop", LATEST, v1).is_err());
+    }
+}
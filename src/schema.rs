@@ -1,21 +1,84 @@
-use serde::Deserialize;
+use crate::diagnostics::{Diagnostic, Diagnostics, Span};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Schema {
     pub version: Option<String>,
     pub dialect: Option<String>,
     #[serde(default)]
     pub comment: Option<String>,
-    pub tables: HashMap<String, Table>,
-    pub enums: Option<HashMap<String, Vec<String>>>,
+    pub tables: IndexMap<String, Table>,
+    pub enums: Option<IndexMap<String, Vec<String>>>,
+    /// Named JSON Schemas for the contents of json/jsonb columns
+    #[serde(default)]
+    pub shapes: IndexMap<String, serde_json::Value>,
+    /// Roles whose privileges are managed here; other roles are left alone
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Privileges of the managed roles on tables and columns
+    #[serde(default)]
+    pub grants: Vec<Grant>,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+/// Table privileges a grant may list, besides `ALL`
+pub const TABLE_PRIVILEGES: [&str; 7] = [
+    "SELECT",
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "TRUNCATE",
+    "REFERENCES",
+    "TRIGGER",
+];
+
+/// The privileges that can be limited to columns
+pub const COLUMN_PRIVILEGES: [&str; 4] = ["SELECT", "INSERT", "UPDATE", "REFERENCES"];
+
+/// Privileges of a role on a table, or on some of its columns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    pub role: String,
+    pub table: String,
+    /// Any of [`TABLE_PRIVILEGES`], or `ALL`
+    pub privileges: Vec<String>,
+    /// Columns the privileges are limited to; the whole table when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
+}
+
+impl Grant {
+    /// The privileges uppercased, with `ALL` expanded to those that apply
+    pub fn privilege_list(&self) -> Vec<String> {
+        let all: &[&str] = if self.columns.is_empty() {
+            &TABLE_PRIVILEGES
+        } else {
+            &COLUMN_PRIVILEGES
+        };
+        let mut list: Vec<String> = Vec::new();
+        for privilege in &self.privileges {
+            let privilege = privilege.to_ascii_uppercase();
+            let expanded = match privilege.as_str() {
+                "ALL" | "ALL PRIVILEGES" => all.iter().map(|p| p.to_string()).collect(),
+                _ => vec![privilege],
+            };
+            for privilege in expanded {
+                if !list.contains(&privilege) {
+                    list.push(privilege);
+                }
+            }
+        }
+        list
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Table {
     #[serde(default)]
     pub comment: Option<String>,
-    pub columns: HashMap<String, Column>,
+    pub columns: IndexMap<String, Column>,
     pub indexes: Option<Vec<Index>>,
     pub constraints: Option<Vec<TableConstraint>>,
     #[serde(default)]
@@ -24,9 +87,38 @@ pub struct Table {
     pub partitions: Vec<Partition>,
     #[serde(default)]
     pub inherits: Vec<String>,
+    /// Rows as a discriminated union keyed by an enum column, in generated types
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variants: Option<TableVariants>,
+}
+
+/// The rows of a table told apart by the value of an enum column, each
+/// value with its own shapes for the table's JSON columns
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TableVariants {
+    /// The enum column whose value picks the variant
+    pub discriminator: String,
+    /// Per enum value, the shape of each JSON column in rows with that value,
+    /// given as a column's `shape` is, or `null` for a column left empty;
+    /// values without a case keep the columns' own types
+    #[serde(default)]
+    pub cases: IndexMap<String, IndexMap<String, serde_json::Value>>,
+}
+
+impl TableVariants {
+    /// The JSON columns some case gives a shape for, in order of appearance
+    pub fn payload_columns(&self) -> Vec<&str> {
+        let mut columns: Vec<&str> = Vec::new();
+        for column in self.cases.values().flat_map(|case| case.keys()) {
+            if !columns.contains(&column.as_str()) {
+                columns.push(column);
+            }
+        }
+        columns
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Column {
     #[serde(rename = "name")]
     pub column_name: String,
@@ -59,9 +151,46 @@ pub struct Column {
     pub attributes: ColumnAttributes,
     #[serde(default)]
     pub references: Option<ForeignKey>,
+    /// Contents of a json/jsonb column: a shape name, a `$ref` or an inline JSON Schema
+    #[serde(default)]
+    pub shape: Option<serde_json::Value>,
+    /// How `stratus db sample` hides this column's values
+    #[serde(default)]
+    pub mask: Option<MaskRule>,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+/// A masking rule for personal data copied out of production
+///
+/// Every rule but `null`, `redact` and `fixed` derives its value from a keyed
+/// hash of the original, so equal inputs mask to equal outputs and joins still
+/// line up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskRule {
+    /// Replace with NULL
+    Null,
+    /// Replace with a hex digest (a number for integer columns)
+    Hash,
+    /// Replace with `user_<digest>@example.com`
+    Email,
+    /// Replace with `Person <digest>`
+    Name,
+    /// Replace with a `+1555` number
+    Phone,
+    /// Replace every character with `*`
+    Redact,
+    /// Replace with this value
+    Fixed(String),
+}
+
+impl MaskRule {
+    /// Whether the rule derives its value from a hash of the original
+    pub fn is_hashed(&self) -> bool {
+        matches!(self, Self::Hash | Self::Email | Self::Name | Self::Phone)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ColumnAttributes {
     #[serde(default)]
     pub is_identity: bool,
@@ -73,21 +202,21 @@ pub struct ColumnAttributes {
     pub compression: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Identity {
     pub sequence: Option<SequenceOptions>,
     #[serde(default)]
     pub always: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneratedAs {
     #[serde(default)]
     pub always: bool,
     pub expression: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SequenceOptions {
     #[serde(default)]
     pub start: Option<i64>,
@@ -101,7 +230,7 @@ pub struct SequenceOptions {
     pub cycle: bool,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TableOptions {
     pub tablespace: Option<String>,
     pub fillfactor: Option<u32>,
@@ -111,7 +240,7 @@ pub struct TableOptions {
     pub autovacuum_enabled: Option<bool>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Index {
     pub name: String,
     pub columns: Vec<String>,
@@ -122,38 +251,45 @@ pub struct Index {
     pub method: Option<IndexMethod>,
     pub tablespace: Option<String>,
     pub with: Option<IndexWithOptions>,
+    /// The predicate of a partial index
+    #[serde(rename = "where", alias = "where_clause")]
     pub where_clause: Option<String>,
     pub nulls_not_distinct: Option<bool>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TableConstraint {
     pub name: Option<String>,
     #[serde(rename = "constraintType")]
     pub constraint_type: ConstraintType,
     #[serde(default)]
     pub columns: Vec<String>,
+    /// The CHECK expression, or what follows EXCLUDE, as in
+    /// `USING gist (room WITH =, during WITH &&)`
     pub expression: Option<String>,
     pub references: Option<ForeignKey>,
+    /// UNIQUE, EXCLUDE and foreign key constraints only (PostgreSQL)
     #[serde(default)]
     pub deferrable: bool,
-    #[serde(default)]
+    #[serde(default, rename = "initiallyDeferred", alias = "initially_deferred")]
     pub initially_deferred: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForeignKey {
     pub table: String,
+    /// Referenced column; for a table constraint over several columns, the
+    /// referenced columns in order, comma-separated
     pub column: String,
-    #[serde(default)]
+    #[serde(default, rename = "onDelete", alias = "on_delete")]
     pub on_delete: Option<OnDeleteAction>,
-    #[serde(default)]
+    #[serde(default, rename = "onUpdate", alias = "on_update")]
     pub on_update: Option<OnUpdateAction>,
-    #[serde(default)]
+    #[serde(default, rename = "match", alias = "matchType")]
     pub match_type: Option<MatchType>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Partition {
     pub name: String,
     pub partition_type: PartitionType,
@@ -164,9 +300,10 @@ pub struct Partition {
     pub tablespace: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum IndexMethod {
     #[serde(rename = "btree")]
+    #[default]
     BTree,
     #[serde(rename = "hash")]
     Hash,
@@ -182,13 +319,23 @@ pub enum IndexMethod {
     Other,
 }
 
-impl Default for IndexMethod {
-    fn default() -> Self {
-        IndexMethod::BTree
+impl IndexMethod {
+    /// The method as `USING` names it, e.g. `gin`; `None` for one Stratus
+    /// does not know
+    pub fn sql(&self) -> Option<&'static str> {
+        match self {
+            Self::BTree => Some("btree"),
+            Self::Hash => Some("hash"),
+            Self::GiST => Some("gist"),
+            Self::SPGiST => Some("spgist"),
+            Self::GIN => Some("gin"),
+            Self::BRIN => Some("brin"),
+            Self::Other => None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConstraintType {
     #[serde(rename = "primary key")]
     PrimaryKey,
@@ -202,7 +349,32 @@ pub enum ConstraintType {
     ForeignKey,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl ConstraintType {
+    /// The keyword that introduces the constraint, e.g. `CHECK`
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Self::PrimaryKey => "PRIMARY KEY",
+            Self::Unique => "UNIQUE",
+            Self::Check => "CHECK",
+            Self::Exclude => "EXCLUDE",
+            Self::ForeignKey => "FOREIGN KEY",
+        }
+    }
+
+    /// The constraint type a catalog reports, e.g. `UNIQUE`
+    pub fn from_sql(keyword: &str) -> Option<Self> {
+        match keyword.trim().to_uppercase().as_str() {
+            "PRIMARY KEY" => Some(Self::PrimaryKey),
+            "UNIQUE" => Some(Self::Unique),
+            "CHECK" => Some(Self::Check),
+            "EXCLUDE" => Some(Self::Exclude),
+            "FOREIGN KEY" => Some(Self::ForeignKey),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum OnDeleteAction {
     #[serde(rename = "cascade")]
     Cascade,
@@ -215,10 +387,35 @@ pub enum OnDeleteAction {
     #[serde(rename = "noAction")]
     NoAction,
     #[serde(other)]
+    #[default]
     None,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl OnDeleteAction {
+    /// The action as SQL spells it; `None` for NO ACTION, the default
+    pub fn sql(&self) -> Option<&'static str> {
+        match self {
+            Self::Cascade => Some("CASCADE"),
+            Self::SetNull => Some("SET NULL"),
+            Self::SetDefault => Some("SET DEFAULT"),
+            Self::Restrict => Some("RESTRICT"),
+            Self::NoAction | Self::None => None,
+        }
+    }
+
+    /// The action a catalog reports, e.g. `SET NULL`
+    pub fn from_sql(rule: &str) -> Self {
+        match rule.trim().to_uppercase().as_str() {
+            "CASCADE" => Self::Cascade,
+            "SET NULL" => Self::SetNull,
+            "SET DEFAULT" => Self::SetDefault,
+            "RESTRICT" => Self::Restrict,
+            _ => Self::NoAction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum OnUpdateAction {
     #[serde(rename = "cascade")]
     Cascade,
@@ -231,10 +428,35 @@ pub enum OnUpdateAction {
     #[serde(rename = "noAction")]
     NoAction,
     #[serde(other)]
+    #[default]
     None,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl OnUpdateAction {
+    /// The action as SQL spells it; `None` for NO ACTION, the default
+    pub fn sql(&self) -> Option<&'static str> {
+        match self {
+            Self::Cascade => Some("CASCADE"),
+            Self::SetNull => Some("SET NULL"),
+            Self::SetDefault => Some("SET DEFAULT"),
+            Self::Restrict => Some("RESTRICT"),
+            Self::NoAction | Self::None => None,
+        }
+    }
+
+    /// The action a catalog reports, e.g. `SET NULL`
+    pub fn from_sql(rule: &str) -> Self {
+        match rule.trim().to_uppercase().as_str() {
+            "CASCADE" => Self::Cascade,
+            "SET NULL" => Self::SetNull,
+            "SET DEFAULT" => Self::SetDefault,
+            "RESTRICT" => Self::Restrict,
+            _ => Self::NoAction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MatchType {
     #[serde(rename = "full")]
     Full,
@@ -244,7 +466,7 @@ pub enum MatchType {
     Simple,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PartitionType {
     #[serde(rename = "range")]
     Range,
@@ -254,7 +476,7 @@ pub enum PartitionType {
     Hash,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StorageType {
     #[serde(rename = "plain")]
     Plain,
@@ -266,15 +488,41 @@ pub enum StorageType {
     Main,
 }
 
-impl Default for OnDeleteAction {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
-impl Default for OnUpdateAction {
-    fn default() -> Self {
-        Self::None
+impl Table {
+    /// Column sets that identify at most one row: the primary key, unique
+    /// columns, and unique indexes and constraints; partial indexes excluded
+    pub fn unique_keys(&self) -> Vec<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut primary: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|(_, c)| c.is_primary_key())
+            .map(|(name, _)| name.clone())
+            .collect();
+        if !primary.is_empty() {
+            primary.sort();
+            keys.push(primary);
+        }
+        for (name, column) in &self.columns {
+            if column.is_unique() {
+                keys.push(vec![name.clone()]);
+            }
+        }
+        for index in self.indexes.iter().flatten() {
+            if index.unique && index.where_clause.is_none() {
+                keys.push(index.columns.clone());
+            }
+        }
+        for constraint in self.constraints.iter().flatten() {
+            if matches!(
+                constraint.constraint_type,
+                ConstraintType::PrimaryKey | ConstraintType::Unique
+            ) && !constraint.columns.is_empty()
+            {
+                keys.push(constraint.columns.clone());
+            }
+        }
+        keys
     }
 }
 
@@ -302,9 +550,93 @@ impl Column {
         }
         base
     }
+
+    /// The type as written, with a `[]` for each of `arrayDimensions` it
+    /// does not spell out
+    pub fn declared_type(&self) -> Cow<'_, str> {
+        let written = self.data_type.matches("[]").count();
+        match self.element_type().1.checked_sub(written) {
+            Some(missing) if missing > 0 => Cow::Owned(format!(
+                "{}{}",
+                self.data_type.trim_end(),
+                "[]".repeat(missing)
+            )),
+            _ => Cow::Borrowed(&self.data_type),
+        }
+    }
+
+    /// The element type, lowercased, and how many array dimensions wrap it,
+    /// from `arrayDimensions` or `[]` suffixes on the type
+    pub fn element_type(&self) -> (String, usize) {
+        let mut base = self.data_type.trim();
+        let mut dims = 0;
+        while let Some(inner) = base.strip_suffix("[]") {
+            base = inner.trim_end();
+            dims += 1;
+        }
+        (
+            base.to_lowercase(),
+            dims.max(self.array_dimensions.unwrap_or(0)),
+        )
+    }
+
+    /// The shape this column's JSON follows, if one is given
+    ///
+    /// Inline shapes are named by their `title`, or else after the column.
+    pub fn shape_name(&self) -> Option<String> {
+        match self.shape.as_ref()? {
+            serde_json::Value::String(name) => Some(name.clone()),
+            shape => Some(
+                shape
+                    .get("$ref")
+                    .or_else(|| shape.get("title"))
+                    .and_then(|v| v.as_str())
+                    .map_or_else(|| self.column_name.clone(), |s| ref_name(s).to_string()),
+            ),
+        }
+    }
+
+    /// The named shape this column refers to, as opposed to one given inline
+    fn shape_ref(&self) -> Option<&str> {
+        match self.shape.as_ref()? {
+            serde_json::Value::String(name) => Some(name),
+            shape => shape.get("$ref")?.as_str().map(ref_name),
+        }
+    }
+}
+
+/// The shape name a `$ref` such as `#/shapes/Address` points at
+pub fn ref_name(reference: &str) -> &str {
+    reference.rsplit('/').next().unwrap_or(reference)
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Schema {
+    /// Every shape to generate a type for: the named shapes, then those given
+    /// inline on columns, sorted by name within each and without duplicates
+    pub fn shapes(&self) -> Vec<(String, &serde_json::Value)> {
+        let mut shapes: Vec<_> = self.shapes.iter().map(|(n, s)| (n.clone(), s)).collect();
+        shapes.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut inline = Vec::new();
+        for table in self.tables.values() {
+            for column in table.columns.values() {
+                if let (Some(shape), None) = (&column.shape, column.shape_ref()) {
+                    if let Some(name) = column.shape_name() {
+                        inline.push((name, shape));
+                    }
+                }
+            }
+        }
+        inline.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, shape) in inline {
+            if !shapes.iter().any(|(n, _)| *n == name) {
+                shapes.push((name, shape));
+            }
+        }
+        shapes
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexWithOptions {
     pub fillfactor: Option<u32>,
     pub deduplicate_items: Option<bool>,
@@ -313,6 +645,329 @@ pub struct IndexWithOptions {
     pub pages_per_range: Option<u32>,
 }
 
+impl IndexWithOptions {
+    /// The storage parameters set, as `WITH (...)` lists them
+    pub fn parameters(&self) -> Vec<String> {
+        let switch = |on: bool| if on { "on" } else { "off" };
+        let mut parameters = Vec::new();
+        if let Some(fillfactor) = self.fillfactor {
+            parameters.push(format!("fillfactor = {}", fillfactor));
+        }
+        if let Some(on) = self.deduplicate_items {
+            parameters.push(format!("deduplicate_items = {}", switch(on)));
+        }
+        if let Some(on) = self.buffering {
+            parameters.push(format!("buffering = {}", switch(on)));
+        }
+        if let Some(on) = self.fastupdate {
+            parameters.push(format!("fastupdate = {}", switch(on)));
+        }
+        if let Some(pages) = self.pages_per_range {
+            parameters.push(format!("pages_per_range = {}", pages));
+        }
+        parameters
+    }
+}
+
+/// Check schema.json content: well-formed JSON, the schema.json structure,
+/// foreign keys that point at existing tables and columns, column shapes
+/// that name a defined shape, and table variants keyed by an enum column.
+///
+/// Returns the schema when it could be read, with every problem found.
+/// Syntax and type errors are positioned exactly; other problems point at
+/// the name they concern.
+pub fn validate(json: &str) -> (Option<Schema>, Diagnostics) {
+    let mut diagnostics = Diagnostics::new();
+    let at = |e: &serde_json::Error| Span::on_line(e.line().max(1), e.column().max(1), 1);
+    // serde_json appends the position, which the span already carries
+    let message = |e: &serde_json::Error| {
+        let text = e.to_string();
+        match text.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_string(),
+            None => text,
+        }
+    };
+
+    let value: serde_json::Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error("invalid-json", at(&e), message(&e)));
+            return (None, diagnostics);
+        }
+    };
+    if !value.is_object() {
+        diagnostics.push(Diagnostic::error(
+            "invalid-schema",
+            Span::on_line(1, 1, 1),
+            "schema.json must be an object",
+        ));
+        return (None, diagnostics);
+    }
+    for field in ["version", "tables"] {
+        if value.get(field).is_none() {
+            diagnostics.push(Diagnostic::error(
+                "missing-field",
+                Span::on_line(1, 1, 1),
+                format!("missing required field '{}'", field),
+            ));
+        }
+    }
+
+    let schema: Schema = match serde_json::from_str(json) {
+        Ok(schema) => schema,
+        Err(e) => {
+            // Already reported above
+            if message(&e) != "missing field `tables`" {
+                diagnostics.push(Diagnostic::error("invalid-schema", at(&e), message(&e)));
+            }
+            return (None, diagnostics);
+        }
+    };
+
+    let mut tables: Vec<_> = schema.tables.iter().collect();
+    tables.sort_by_key(|(name, _)| *name);
+    for (table_name, table) in tables {
+        let mut columns: Vec<_> = table.columns.iter().collect();
+        columns.sort_by_key(|(name, _)| *name);
+        for (column_name, column) in columns {
+            if let Some(shape) = column.shape_ref() {
+                if !schema.shapes.contains_key(shape) {
+                    diagnostics.push(Diagnostic::error(
+                        "unknown-shape",
+                        locate(json, &[table_name, "columns", column_name, "shape"]),
+                        format!(
+                            "{}.{} uses unknown shape '{}'",
+                            table_name, column_name, shape
+                        ),
+                    ));
+                }
+            }
+            let Some(fk) = &column.references else {
+                continue;
+            };
+            let message = match schema.tables.get(&fk.table) {
+                None => format!(
+                    "{}.{} references unknown table '{}'",
+                    table_name, column_name, fk.table
+                ),
+                Some(target) if !target.columns.contains_key(&fk.column) => format!(
+                    "{}.{} references unknown column '{}.{}'",
+                    table_name, column_name, fk.table, fk.column
+                ),
+                Some(_) => continue,
+            };
+            let span = locate(json, &[table_name, "columns", column_name, "references"]);
+            diagnostics.push(Diagnostic::error("unknown-reference", span, message));
+        }
+        if let Some(variants) = &table.variants {
+            validate_variants(json, &schema, table_name, table, variants, &mut diagnostics);
+        }
+    }
+
+    for grant in &schema.grants {
+        let span = locate(json, &["grants"]);
+        let mut problems = Vec::new();
+        if !schema.roles.contains(&grant.role) {
+            problems.push((
+                "unknown-role",
+                format!("grant to '{}', which is not in roles", grant.role),
+            ));
+        }
+        match schema.tables.get(&grant.table) {
+            None => problems.push((
+                "unknown-reference",
+                format!("grant on unknown table '{}'", grant.table),
+            )),
+            Some(table) => {
+                for column in grant
+                    .columns
+                    .iter()
+                    .filter(|c| !table.columns.contains_key(*c))
+                {
+                    problems.push((
+                        "unknown-reference",
+                        format!("grant on unknown column '{}.{}'", grant.table, column),
+                    ));
+                }
+            }
+        }
+        let allowed: &[&str] = if grant.columns.is_empty() {
+            &TABLE_PRIVILEGES
+        } else {
+            &COLUMN_PRIVILEGES
+        };
+        for privilege in grant.privilege_list() {
+            if !allowed.contains(&privilege.as_str()) {
+                problems.push((
+                    "invalid-privilege",
+                    format!(
+                        "{} cannot be granted on {}{}",
+                        privilege,
+                        if grant.columns.is_empty() {
+                            ""
+                        } else {
+                            "columns of "
+                        },
+                        grant.table
+                    ),
+                ));
+            }
+        }
+        for (code, message) in problems {
+            diagnostics.push(Diagnostic::error(code, span, message));
+        }
+    }
+
+    diagnostics.sort();
+    (Some(schema), diagnostics)
+}
+
+/// Check a table's variants: the discriminator is a column of an enum type,
+/// each case is one of its values, and cases give shapes to JSON columns
+fn validate_variants(
+    json: &str,
+    schema: &Schema,
+    table_name: &str,
+    table: &Table,
+    variants: &TableVariants,
+    diagnostics: &mut Diagnostics,
+) {
+    let discriminator = &variants.discriminator;
+    let values = match table.columns.get(discriminator) {
+        None => {
+            diagnostics.push(Diagnostic::error(
+                "unknown-reference",
+                locate(json, &[table_name, "variants", "discriminator"]),
+                format!(
+                    "{} variants are keyed by unknown column '{}'",
+                    table_name, discriminator
+                ),
+            ));
+            return;
+        }
+        Some(column) => match schema
+            .enums
+            .as_ref()
+            .and_then(|enums| enums.get(&column.data_type))
+        {
+            Some(values) => values,
+            None => {
+                diagnostics.push(Diagnostic::error(
+                    "invalid-variants",
+                    locate(json, &[table_name, "variants", "discriminator"]),
+                    format!(
+                        "{}.{} keys variants but its type '{}' is not an enum",
+                        table_name, discriminator, column.data_type
+                    ),
+                ));
+                return;
+            }
+        },
+    };
+
+    for (value, case) in &variants.cases {
+        let at = |keys: &[&str]| {
+            let mut path = vec![table_name, "variants", "cases", value.as_str()];
+            path.extend(keys);
+            locate(json, &path)
+        };
+        if !values.contains(value) {
+            diagnostics.push(Diagnostic::error(
+                "invalid-variants",
+                at(&[]),
+                format!(
+                    "{} variant '{}' is not a value of enum '{}'",
+                    table_name, value, table.columns[discriminator].data_type
+                ),
+            ));
+        }
+        for (column_name, shape) in case {
+            let is_json = table
+                .columns
+                .get(column_name)
+                .is_some_and(|c| matches!(c.element_type().0.as_str(), "json" | "jsonb"));
+            if !is_json {
+                diagnostics.push(Diagnostic::error(
+                    "invalid-variants",
+                    at(&[column_name]),
+                    format!(
+                        "{} variant '{}' gives a shape to '{}', which is not a json column",
+                        table_name, value, column_name
+                    ),
+                ));
+                continue;
+            }
+            let name = match shape {
+                serde_json::Value::String(name) => Some(name.as_str()),
+                shape => shape.get("$ref").and_then(|r| r.as_str()).map(ref_name),
+            };
+            if let Some(name) = name.filter(|n| !schema.shapes.contains_key(*n)) {
+                diagnostics.push(Diagnostic::error(
+                    "unknown-shape",
+                    at(&[column_name]),
+                    format!(
+                        "{} variant '{}' uses unknown shape '{}'",
+                        table_name, value, name
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Where the tables and columns of a schema.json source are defined
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourcePositions {
+    /// Span of each table's key
+    pub tables: HashMap<String, Span>,
+    /// Span of each column's key, by table and column name
+    pub columns: HashMap<(String, String), Span>,
+}
+
+/// Parse schema.json, recording where each table and column is defined
+pub fn load_with_positions(json: &str) -> serde_json::Result<(Schema, SourcePositions)> {
+    let schema: Schema = serde_json::from_str(json)?;
+    let mut positions = SourcePositions::default();
+    for (table_name, table) in &schema.tables {
+        positions
+            .tables
+            .insert(table_name.clone(), locate(json, &["tables", table_name]));
+        for column_name in table.columns.keys() {
+            positions.columns.insert(
+                (table_name.clone(), column_name.clone()),
+                locate(json, &["tables", table_name, "columns", column_name]),
+            );
+        }
+    }
+    Ok((schema, positions))
+}
+
+/// Position of the last of a path of object keys, each searched after the previous one
+pub(crate) fn locate(json: &str, keys: &[&str]) -> Span {
+    let mut offset = 0;
+    for key in keys {
+        let quoted = format!("\"{}\"", key);
+        // A key, not a string value that happens to match it
+        let found = json[offset..].match_indices(&quoted).find(|(i, _)| {
+            json[offset + i + quoted.len()..]
+                .trim_start()
+                .starts_with(':')
+        });
+        match found {
+            Some((found, _)) => offset += found,
+            None => break,
+        }
+    }
+    let before = &json[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    Span::on_line(
+        line,
+        column,
+        keys.last().map_or(1, |k| k.chars().count() + 2),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -350,6 +1005,17 @@ mod tests {
                 "settings": {
                   "name": "settings",
                   "type": "jsonb"
+                },
+                "team_id": {
+                  "name": "team_id",
+                  "type": "bigint",
+                  "references": {
+                    "table": "teams",
+                    "column": "id",
+                    "onDelete": "setNull",
+                    "onUpdate": "cascade",
+                    "match": "full"
+                  }
                 }
               },
               "indexes": [
@@ -384,5 +1050,123 @@ mod tests {
 
         let tags = users.columns.get("tags").unwrap();
         assert_eq!(tags.array_dimensions, Some(1));
+
+        let team = users.columns["team_id"].references.as_ref().unwrap();
+        assert!(matches!(team.on_delete, Some(OnDeleteAction::SetNull)));
+        assert!(matches!(team.on_update, Some(OnUpdateAction::Cascade)));
+        assert!(matches!(team.match_type, Some(MatchType::Full)));
+    }
+
+    #[test]
+    fn test_validate_reports_positions() {
+        let found = |json: &str| -> Vec<String> {
+            validate(json).1.iter().map(|d| d.to_string()).collect()
+        };
+
+        assert_eq!(
+            found("{\n  \"version\": \"1\",\n  \"tables\": {\n}"),
+            vec!["4:1: error[invalid-json]: EOF while parsing an object"]
+        );
+        assert_eq!(
+            found(r#"{"tables": {}}"#),
+            vec!["1:1: error[missing-field]: missing required field 'version'"]
+        );
+        assert_eq!(
+            found("{\"version\": \"1\", \"tables\": {\n  \"users\": {\"columns\": {\"id\": {\"name\": \"id\"}}}\n}}"),
+            vec!["2:44: error[invalid-schema]: missing field `type`"]
+        );
+
+        let json = r#"{"version": "1", "tables": {
+  "users": {"columns": {"id": {"name": "id", "type": "bigint"}}},
+  "posts": {"columns": {
+    "author_id": {"name": "author_id", "type": "bigint", "references": {"table": "users", "column": "uid"}}
+  }}
+}}"#;
+        let (schema, diagnostics) = validate(json);
+        assert!(schema.is_some());
+        assert_eq!(
+            diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+            vec!["4:58: error[unknown-reference]: posts.author_id references unknown column 'users.uid'"]
+        );
+        let json = r#"{"version": "1", "tables": {
+  "users": {"columns": {"prefs": {"name": "prefs", "type": "jsonb", "shape": "settings"}}}
+}}"#;
+        assert_eq!(
+            found(json),
+            vec!["2:69: error[unknown-shape]: users.prefs uses unknown shape 'settings'"]
+        );
+        let json = r#"{"version": "1", "tables": {
+  "events": {"columns": {
+    "kind": {"name": "kind", "type": "event_kind"},
+    "note": {"name": "note", "type": "text"},
+    "payload": {"name": "payload", "type": "jsonb"}
+  }, "variants": {"discriminator": "kind", "cases": {
+    "signup": {"payload": "signup"},
+    "refund": {"note": null}
+  }}}
+}, "enums": {"event_kind": ["signup", "purchase"]}}"#;
+        assert_eq!(
+            found(json),
+            vec![
+                "7:16: error[unknown-shape]: events variant 'signup' uses unknown shape 'signup'",
+                "8:5: error[invalid-variants]: events variant 'refund' is not a value of enum 'event_kind'",
+                "8:16: error[invalid-variants]: events variant 'refund' gives a shape to 'note', which is not a json column",
+            ]
+        );
+        let json = json.replace("\"discriminator\": \"kind\"", "\"discriminator\": \"note\"");
+        assert_eq!(
+            found(&json),
+            vec!["6:19: error[invalid-variants]: events.note keys variants but its type 'text' is not an enum"]
+        );
+        let json = r#"{"version": "1", "tables": {
+  "users": {"columns": {"id": {"name": "id", "type": "bigint"}}}
+},
+"roles": ["app"],
+"grants": [
+  {"role": "app", "table": "users", "privileges": ["all"]},
+  {"role": "web", "table": "users", "privileges": ["DELETE"], "columns": ["id", "name"]}
+]}"#;
+        let (schema, diagnostics) = validate(json);
+        assert_eq!(
+            schema.unwrap().grants[0].privilege_list().len(),
+            TABLE_PRIVILEGES.len()
+        );
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "5:1: error[unknown-role]: grant to 'web', which is not in roles",
+                "5:1: error[unknown-reference]: grant on unknown column 'users.name'",
+                "5:1: error[invalid-privilege]: DELETE cannot be granted on columns of users",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_with_positions() {
+        let json = r#"{
+  "tables": {
+    "posts": {"columns": {"user_id": {"name": "user_id", "type": "bigint", "references": {"table": "users", "column": "id"}}}},
+    "users": {
+      "columns": {
+        "id": {"name": "id", "type": "bigint"}
+      }
+    }
+  }
+}"#;
+        let (schema, positions) = load_with_positions(json).unwrap();
+        assert_eq!(schema.tables.len(), 2);
+        assert_eq!(positions.tables["users"], Span::on_line(4, 5, 7));
+        assert_eq!(positions.tables["posts"], Span::on_line(3, 5, 7));
+        assert_eq!(
+            positions.columns[&("users".to_string(), "id".to_string())],
+            Span::on_line(6, 9, 4)
+        );
+        assert_eq!(
+            positions.columns[&("posts".to_string(), "user_id".to_string())],
+            Span::on_line(3, 27, 9)
+        );
     }
 }
@@ -0,0 +1,222 @@
+/**
+ * Stratus Schema Snapshot Module
+ *
+ * A history of schema.json kept in `.stratus/snapshots` next to stratus.json:
+ * each sync or deploy that sees a new version of the schema saves it as
+ * `<timestamp>.json`, so the schema can be diffed against any earlier point
+ * without a database. Timestamps are UTC, as in `20261016T184132Z`, and sort
+ * in time order.
+ */
+use crate::config::ConfigManager;
+use crate::schema::Schema;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Directory of the snapshots, relative to the project root
+pub const SNAPSHOT_DIR: &str = ".stratus/snapshots";
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Snapshot errors
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Cannot read {}: {}", .path.display(), .source)]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Cannot write {}: {}", .path.display(), .source)]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid snapshot {}: {}", .path.display(), .message)]
+    Invalid { path: PathBuf, message: String },
+
+    #[error("Invalid time '{0}': use a snapshot timestamp such as 20261016T184132Z, or a date such as 2026-10-16")]
+    InvalidTime(String),
+
+    #[error("No schema snapshot at or before {0}")]
+    NotFound(String),
+}
+
+pub type SnapshotResult<T> = Result<T, SnapshotError>;
+
+/// One saved version of schema.json
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// When it was taken, as in its file name
+    pub timestamp: String,
+    pub path: PathBuf,
+}
+
+impl Snapshot {
+    /// The schema.json text as saved
+    pub fn contents(&self) -> SnapshotResult<String> {
+        fs::read_to_string(&self.path).map_err(|source| SnapshotError::Read {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    pub fn schema(&self) -> SnapshotResult<Schema> {
+        serde_json::from_str(&self.contents()?).map_err(|e| SnapshotError::Invalid {
+            path: self.path.clone(),
+            message: e.to_string(),
+        })
+    }
+
+    /// When it was taken, e.g. `2026-10-16 18:41:32 UTC`
+    pub fn time(&self) -> String {
+        chrono::NaiveDateTime::parse_from_str(&self.timestamp, TIMESTAMP_FORMAT)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|_| self.timestamp.clone())
+    }
+}
+
+/// The snapshot directory of a project: next to stratus.json, else in the
+/// current directory
+pub fn snapshot_dir(config: Option<&ConfigManager>) -> PathBuf {
+    match config {
+        Some(cfg) => cfg.base_dir().join(SNAPSHOT_DIR),
+        None => PathBuf::from(SNAPSHOT_DIR),
+    }
+}
+
+/// The snapshots in `dir`, oldest first; none when it does not exist
+pub fn list(dir: &Path) -> SnapshotResult<Vec<Snapshot>> {
+    let read_error = |source| SnapshotError::Read {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(read_error(e)),
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(read_error)?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(timestamp) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        snapshots.push(Snapshot {
+            timestamp: timestamp.to_string(),
+            path,
+        });
+    }
+    snapshots.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Ok(snapshots)
+}
+
+/// Save `contents` as a snapshot taken now, unless the latest one already
+/// holds the same schema
+pub fn record(dir: &Path, contents: &str) -> SnapshotResult<Option<Snapshot>> {
+    let timestamp = chrono::Utc::now().format(TIMESTAMP_FORMAT).to_string();
+    record_at(dir, contents, &timestamp)
+}
+
+fn record_at(dir: &Path, contents: &str, timestamp: &str) -> SnapshotResult<Option<Snapshot>> {
+    if let Some(latest) = list(dir)?.pop() {
+        if latest.contents()? == contents {
+            return Ok(None);
+        }
+    }
+    let write_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| SnapshotError::Write { path, source }
+    };
+    fs::create_dir_all(dir).map_err(write_error(dir))?;
+    let path = dir.join(format!("{}.json", timestamp));
+    fs::write(&path, contents).map_err(write_error(&path))?;
+    Ok(Some(Snapshot {
+        timestamp: timestamp.to_string(),
+        path,
+    }))
+}
+
+/// The snapshot in effect at `time`: the latest one taken at or before it
+///
+/// `time` is a timestamp or the start of one, with or without separators:
+/// `2026-10-16` picks the last snapshot of that day, `2026-10-16T18:41` the
+/// last of that minute.
+pub fn at(dir: &Path, time: &str) -> SnapshotResult<Snapshot> {
+    let key: String = time
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let valid = key.len() >= 4
+        && key.len() <= 16
+        && key
+            .char_indices()
+            .all(|(i, c)| c.is_ascii_digit() || (i == 8 && c == 'T') || (i == 15 && c == 'Z'));
+    if !valid {
+        return Err(SnapshotError::InvalidTime(time.to_string()));
+    }
+    list(dir)?
+        .into_iter()
+        .rev()
+        .find(|snapshot| {
+            let prefix = &snapshot.timestamp[..key.len().min(snapshot.timestamp.len())];
+            prefix <= key.as_str()
+        })
+        .ok_or_else(|| SnapshotError::NotFound(time.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_find_snapshots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("snapshots");
+
+        assert!(list(&dir).unwrap().is_empty());
+        let first = record_at(&dir, r#"{"tables": {}}"#, "20261015T090000Z").unwrap();
+        assert!(first.is_some());
+        // The same schema again is no new snapshot
+        assert!(record_at(&dir, r#"{"tables": {}}"#, "20261015T120000Z")
+            .unwrap()
+            .is_none());
+        record_at(
+            &dir,
+            r#"{"tables": {"users": {"columns": {}}}}"#,
+            "20261016T184132Z",
+        )
+        .unwrap();
+
+        let snapshots = list(&dir).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].time(), "2026-10-16 18:41:32 UTC");
+        assert!(snapshots[1].schema().unwrap().tables.contains_key("users"));
+
+        assert_eq!(
+            at(&dir, "2026-10-15").unwrap().timestamp,
+            "20261015T090000Z"
+        );
+        assert_eq!(
+            at(&dir, "2026-10-16T18:41").unwrap().timestamp,
+            "20261016T184132Z"
+        );
+        assert_eq!(
+            at(&dir, "20261016T184131Z").unwrap().timestamp,
+            "20261015T090000Z"
+        );
+        assert!(matches!(
+            at(&dir, "2026-10-14"),
+            Err(SnapshotError::NotFound(_))
+        ));
+        assert!(matches!(
+            at(&dir, "yesterday"),
+            Err(SnapshotError::InvalidTime(_))
+        ));
+    }
+}
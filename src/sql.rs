@@ -0,0 +1,223 @@
+/**
+ * Stratus SQL Module
+ *
+ * Parses the SQL of TypeSQL queries into a statement AST (sqlparser's,
+ * re-exported as `ast`), so tables and select-list columns are read from
+ * the structure of the statement rather than by scanning for keywords:
+ * CTEs, subqueries, aliases and quoted identifiers come out right. Code
+ * generators can read the same AST through `Query::statement`.
+ */
+use crate::parser::SelectColumn;
+pub use sqlparser::ast;
+use sqlparser::ast::{
+    Expr, Ident, ObjectName, SelectItem, SetExpr, Statement, TableFactor, Visit, Visitor,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::ops::ControlFlow;
+use thiserror::Error;
+
+/// SQL parse errors
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SqlError {
+    #[error("{0}")]
+    Syntax(String),
+
+    #[error("Expected one statement, found {0}")]
+    StatementCount(usize),
+}
+
+/// Parse SQL holding exactly one statement
+pub fn parse_statement(sql: &str) -> Result<Statement, SqlError> {
+    let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, sql)
+        .map_err(|e| SqlError::Syntax(e.to_string()))?;
+    match statements.len() {
+        1 => Ok(statements.remove(0)),
+        count => Err(SqlError::StatementCount(count)),
+    }
+}
+
+/// Identifier as the database sees it: unquoted names fold to lowercase
+pub fn ident_name(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_ascii_lowercase(),
+    }
+}
+
+/// Dotted name, as in `public.users`
+pub fn object_name(name: &ObjectName) -> String {
+    name.0.iter().map(ident_name).collect::<Vec<_>>().join(".")
+}
+
+/// Tables a statement reads or writes, in order of appearance, without
+/// CTE names or table functions
+pub fn tables(statement: &Statement) -> Vec<String> {
+    #[derive(Default)]
+    struct Tables {
+        relations: Vec<String>,
+        ctes: Vec<String>,
+        functions: Vec<String>,
+    }
+
+    impl Visitor for Tables {
+        type Break = ();
+
+        fn pre_visit_query(&mut self, query: &ast::Query) -> ControlFlow<()> {
+            if let Some(with) = &query.with {
+                self.ctes.extend(
+                    with.cte_tables
+                        .iter()
+                        .map(|cte| ident_name(&cte.alias.name)),
+                );
+            }
+            ControlFlow::Continue(())
+        }
+
+        fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<()> {
+            if let TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            } = factor
+            {
+                self.functions.push(object_name(name));
+            }
+            ControlFlow::Continue(())
+        }
+
+        fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<()> {
+            let name = object_name(relation);
+            if !self.relations.contains(&name) {
+                self.relations.push(name);
+            }
+            ControlFlow::Continue(())
+        }
+    }
+
+    let mut visitor = Tables::default();
+    let _ = statement.visit(&mut visitor);
+    let Tables {
+        relations,
+        ctes,
+        functions,
+    } = visitor;
+    relations
+        .into_iter()
+        .filter(|name| !ctes.contains(name) && !functions.contains(name))
+        .collect()
+}
+
+/// Select list of a query's outer SELECT; empty for other statements
+pub fn select_columns(statement: &Statement) -> Vec<SelectColumn> {
+    let Statement::Query(query) = statement else {
+        return Vec::new();
+    };
+    // The first branch of a UNION names the columns
+    let mut body = query.body.as_ref();
+    let select = loop {
+        match body {
+            SetExpr::Select(select) => break select,
+            SetExpr::Query(query) => body = query.body.as_ref(),
+            SetExpr::SetOperation { left, .. } => body = left.as_ref(),
+            _ => return Vec::new(),
+        }
+    };
+    select.projection.iter().map(select_column).collect()
+}
+
+fn select_column(item: &SelectItem) -> SelectColumn {
+    let (expr, alias) = match item {
+        SelectItem::Wildcard(_) => return SelectColumn::wildcard(None),
+        SelectItem::QualifiedWildcard(name, _) => {
+            return SelectColumn::wildcard(Some(object_name(name)))
+        }
+        SelectItem::UnnamedExpr(expr) => (expr, None),
+        SelectItem::ExprWithAlias { expr, alias } => (expr, Some(ident_name(alias))),
+    };
+    let (table_name, column_name) = match expr {
+        Expr::Identifier(ident) => (None, ident_name(ident)),
+        Expr::CompoundIdentifier(parts) if parts.len() > 1 => {
+            let (column, qualifier) = parts.split_last().expect("two or more parts");
+            // Only the table matters for `schema.table.column`
+            (qualifier.last().map(ident_name), ident_name(column))
+        }
+        expr => (None, expr.to_string()),
+    };
+    SelectColumn {
+        table_name,
+        column_name,
+        alias,
+        is_wildcard: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(sql: &str) -> Statement {
+        parse_statement(sql).unwrap()
+    }
+
+    #[test]
+    fn test_tables_skip_ctes_and_functions() {
+        assert_eq!(
+            tables(&statement(
+                "WITH recent AS (SELECT * FROM posts WHERE created_at > $1::timestamptz)
+                 SELECT u.id, r.title FROM \"Users\" u
+                 JOIN recent r ON r.user_id = u.id
+                 LEFT JOIN (SELECT user_id FROM comments) c ON c.user_id = u.id
+                 CROSS JOIN generate_series(1, 3) g
+                 WHERE u.email ILIKE $2 AND EXISTS (SELECT 1 FROM Follows f WHERE f.user_id = u.id)"
+            )),
+            vec!["posts", "Users", "comments", "follows"]
+        );
+        assert_eq!(
+            tables(&statement(
+                "INSERT INTO audit (user_id) SELECT id FROM public.users WHERE id = $1 RETURNING id"
+            )),
+            vec!["audit", "public.users"]
+        );
+        assert_eq!(
+            tables(&statement(
+                "UPDATE users SET name = $1 FROM teams WHERE teams.id = users.team_id"
+            )),
+            vec!["users", "teams"]
+        );
+        assert_eq!(
+            parse_statement("SELECT 1; SELECT 2"),
+            Err(SqlError::StatementCount(2))
+        );
+    }
+
+    #[test]
+    fn test_select_columns_keep_aliases() {
+        let columns = select_columns(&statement(
+            "SELECT u.*, p.\"Title\" AS title, count(*) AS total, email, 'x'
+             FROM users u JOIN posts p ON true UNION SELECT * FROM users",
+        ));
+        let summary: Vec<_> = columns
+            .iter()
+            .map(|c| {
+                (
+                    c.table_name.as_deref(),
+                    c.column_name.as_str(),
+                    c.alias.as_deref(),
+                    c.is_wildcard,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("u"), "*", None, true),
+                (Some("p"), "Title", Some("title"), false),
+                (None, "count(*)", Some("total"), false),
+                (None, "email", None, false),
+                (None, "'x'", None, false),
+            ]
+        );
+        assert!(select_columns(&statement("DELETE FROM users")).is_empty());
+    }
+}
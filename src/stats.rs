@@ -0,0 +1,267 @@
+/**
+ * Stratus Query Statistics Module
+ *
+ * Matches the named TypeSQL queries against pg_stat_statements, so
+ * `stratus stats` can point at the queries that are slow or never run.
+ * Statements are matched on normalized SQL: comments dropped, whitespace
+ * collapsed, keywords lowercased, and constants and parameters replaced by
+ * `?`, as pg_stat_statements does with constants.
+ */
+use crate::ast::Query;
+use crate::db::{DbError, StratusClient};
+use crate::format::{tokenize, TokenKind};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Statistics errors
+#[derive(Error, Debug)]
+pub enum StatsError {
+    #[error("The pg_stat_statements extension is not installed in this database")]
+    NotInstalled,
+
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+pub type StatsResult<T> = Result<T, StatsError>;
+
+/// Execution counters of one normalized statement
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatementStats {
+    pub calls: u64,
+    pub total_ms: f64,
+    pub rows: u64,
+}
+
+impl StatementStats {
+    pub fn mean_ms(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.total_ms / self.calls as f64
+        }
+    }
+}
+
+/// How a named query is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageStatus {
+    /// Mean time at or above the threshold
+    Slow,
+    Ok,
+    /// Never run since the statistics were reset
+    Unused,
+}
+
+/// A named query with the statistics of its statement, if it ran
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryUsage {
+    pub name: String,
+    pub file: PathBuf,
+    pub status: UsageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatementStats>,
+}
+
+/// SQL in the form statements are matched on
+pub fn normalize_sql(sql: &str) -> String {
+    let mut words = Vec::new();
+    for token in tokenize(sql) {
+        let text = token.text;
+        match token.kind {
+            TokenKind::LineComment => {}
+            TokenKind::Quoted if text.starts_with("/*") => {}
+            // Identifiers keep their case; literals and $$ bodies are constants
+            TokenKind::Quoted if text.starts_with('"') => words.push(text.to_string()),
+            TokenKind::Quoted => words.push("?".to_string()),
+            TokenKind::Word if text.starts_with(|c: char| c.is_ascii_digit()) => {
+                words.push("?".to_string())
+            }
+            TokenKind::Word => words.push(text.to_lowercase()),
+            TokenKind::Other if text.starts_with('$') && text.len() > 1 => {
+                words.push("?".to_string())
+            }
+            TokenKind::Other => words.push(text.to_string()),
+        }
+    }
+    while words.last().is_some_and(|w| w == ";") {
+        words.pop();
+    }
+    words.join(" ")
+}
+
+/// Read pg_stat_statements for the current database, keyed by normalized SQL
+///
+/// Statements that normalize alike, e.g. the same query sent with different
+/// literals, are added together.
+pub fn read_statements(client: &mut StratusClient) -> StatsResult<Vec<(String, StatementStats)>> {
+    let mut statements: IndexMap<String, StatementStats> = IndexMap::new();
+    for (sql, row) in read_raw_statements(client)? {
+        let stats = statements.entry(normalize_sql(&sql)).or_default();
+        stats.calls += row.calls;
+        stats.total_ms += row.total_ms;
+        stats.rows += row.rows;
+    }
+    Ok(statements.into_iter().collect())
+}
+
+/// Read pg_stat_statements for the current database, one entry per row with
+/// its SQL as recorded: constants already replaced by `$n` parameters
+pub fn read_raw_statements(
+    client: &mut StratusClient,
+) -> StatsResult<Vec<(String, StatementStats)>> {
+    let installed =
+        client.query("SELECT extversion FROM pg_extension WHERE extname = 'pg_stat_statements'")?;
+    let version = installed
+        .first()
+        .and_then(|row| row.get("extversion"))
+        .ok_or(StatsError::NotInstalled)?;
+    // Renamed in 1.8 (PostgreSQL 13), when planning time was split out
+    let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+    let total = if (major.parse().unwrap_or(0), minor.parse().unwrap_or(0)) < (1u32, 8u32) {
+        "total_time"
+    } else {
+        "total_exec_time"
+    };
+    let rows = client.query(&format!(
+        "SELECT query, calls::text AS calls, {}::text AS total_ms, rows::text AS rows
+         FROM pg_stat_statements
+         WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database())",
+        total
+    ))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let number = |key: &str| {
+                row.get(key)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(0.0)
+            };
+            let stats = StatementStats {
+                calls: number("calls") as u64,
+                total_ms: number("total_ms"),
+                rows: number("rows") as u64,
+            };
+            (row.get("query").cloned().unwrap_or_default(), stats)
+        })
+        .collect())
+}
+
+/// Match named queries with their statements; slow queries come first,
+/// slowest by total time first, then the rest, then the unused ones
+pub fn usage(
+    queries: &[(PathBuf, Query)],
+    statements: &[(String, StatementStats)],
+    slow_ms: f64,
+) -> Vec<QueryUsage> {
+    let mut usage: Vec<QueryUsage> = queries
+        .iter()
+        .map(|(file, query)| {
+            let sql = normalize_sql(&query.sql);
+            let stats = statements
+                .iter()
+                .find(|(s, stats)| *s == sql && stats.calls > 0)
+                .map(|(_, stats)| stats.clone());
+            let status = match &stats {
+                None => UsageStatus::Unused,
+                Some(stats) if stats.mean_ms() >= slow_ms => UsageStatus::Slow,
+                Some(_) => UsageStatus::Ok,
+            };
+            QueryUsage {
+                name: query.name.clone(),
+                file: file.clone(),
+                status,
+                stats,
+            }
+        })
+        .collect();
+    let rank = |status: UsageStatus| match status {
+        UsageStatus::Slow => 0,
+        UsageStatus::Ok => 1,
+        UsageStatus::Unused => 2,
+    };
+    usage.sort_by(|a, b| {
+        let total = |u: &QueryUsage| u.stats.as_ref().map_or(0.0, |s| s.total_ms);
+        rank(a.status)
+            .cmp(&rank(b.status))
+            .then(total(b).total_cmp(&total(a)))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, sql: &str) -> (PathBuf, Query) {
+        (
+            PathBuf::from("queries.sql"),
+            Query {
+                name: name.to_string(),
+                return_type: "many".to_string(),
+                sql: sql.to_string(),
+                params: Vec::new(),
+                cache: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_queries_match_normalized_statements() {
+        assert_eq!(
+            normalize_sql(
+                "SELECT id, \"Name\"  -- who\n FROM users\n WHERE email = $1 AND kind = 'x' LIMIT 10;"
+            ),
+            "select id , \"Name\" from users where email = ? and kind = ? limit ?"
+        );
+        assert_eq!(
+            normalize_sql(
+                "select id, \"Name\" /* c */ from USERS where email = $1 and kind = $2 limit $3"
+            ),
+            normalize_sql(
+                "SELECT id, \"Name\" FROM users WHERE email = $1 AND kind = 'x' LIMIT 10"
+            )
+        );
+
+        let statements = vec![
+            (
+                normalize_sql("SELECT * FROM orders WHERE user_id = $1"),
+                StatementStats {
+                    calls: 4,
+                    total_ms: 1000.0,
+                    rows: 40,
+                },
+            ),
+            (
+                normalize_sql("SELECT * FROM users WHERE id = $1"),
+                StatementStats {
+                    calls: 100,
+                    total_ms: 50.0,
+                    rows: 100,
+                },
+            ),
+        ];
+        let queries = vec![
+            query("GetUser", "SELECT *\nFROM users\nWHERE id = $1;"),
+            query("ListArchived", "SELECT * FROM archive"),
+            query("ListOrders", "select * from orders where user_id = $1"),
+        ];
+        let usage = usage(&queries, &statements, 100.0);
+        let summary: Vec<(&str, UsageStatus)> =
+            usage.iter().map(|u| (u.name.as_str(), u.status)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ListOrders", UsageStatus::Slow),
+                ("GetUser", UsageStatus::Ok),
+                ("ListArchived", UsageStatus::Unused),
+            ]
+        );
+        assert_eq!(usage[0].stats.as_ref().unwrap().mean_ms(), 250.0);
+    }
+}
@@ -0,0 +1,192 @@
+// Stratus Studio: browses schema.json and the live database side by side.
+const state = { info: {}, schema: null, db: null, selected: null };
+
+const escape = (value) =>
+  String(value ?? "").replace(/[&<>"']/g, (c) => `&#${c.charCodeAt(0)};`);
+
+async function fetchJson(url) {
+  const res = await fetch(url);
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error || res.statusText);
+  return body;
+}
+
+function tableNames() {
+  const names = new Set([
+    ...Object.keys(state.schema?.tables ?? {}),
+    ...Object.keys(state.db?.tables ?? {}),
+  ]);
+  return [...names].sort();
+}
+
+function badge(name) {
+  const inSchema = name in (state.schema?.tables ?? {});
+  const inDb = name in (state.db?.tables ?? {});
+  if (!state.db || !state.schema || (inSchema && inDb)) return "";
+  return `<span class="badge missing">${inSchema ? "not in db" : "not in schema"}</span>`;
+}
+
+function renderTables() {
+  const filter = document.getElementById("filter").value.toLowerCase();
+  document.getElementById("tables").innerHTML = tableNames()
+    .filter((name) => name.toLowerCase().includes(filter))
+    .map(
+      (name) =>
+        `<li data-table="${escape(name)}" class="${name === state.selected ? "active" : ""}">` +
+        `<span>${escape(name)}</span>${badge(name)}</li>`
+    )
+    .join("");
+}
+
+function grid(headers, rows) {
+  if (rows.length === 0) return `<p class="muted">None.</p>`;
+  return (
+    `<table><tr>${headers.map((h) => `<th>${escape(h)}</th>`).join("")}</tr>` +
+    rows.map((r) => `<tr>${r.join("")}</tr>`).join("") +
+    `</table>`
+  );
+}
+
+const cell = (value, cls = "") => `<td class="${cls}">${escape(value)}</td>`;
+
+function columnsSection(schemaTable, dbTable) {
+  const names = new Set([
+    ...Object.keys(schemaTable?.columns ?? {}),
+    ...Object.keys(dbTable?.columns ?? {}),
+  ]);
+  const rows = [...names].map((name) => {
+    const s = schemaTable?.columns?.[name];
+    const d = dbTable?.columns?.[name];
+    const pk = s?.isPrimaryKey || d?.is_primary_key || dbTable?.primary_key?.includes(name);
+    const sNull = s ? (s.isNotNull || s.isPrimaryKey ? "NOT NULL" : "NULL") : "";
+    const dNull = d ? (d.is_nullable ? "NULL" : "NOT NULL") : "";
+    const differs = s && d && sNull !== dNull ? "diff" : "";
+    return [
+      cell(name + (pk ? " 🔑" : "")),
+      cell(s ? s.type + (s.size ? `(${s.size})` : "") : "—", "mono"),
+      cell(d ? d.data_type + (d.size ? `(${d.size})` : "") : "—", "mono"),
+      cell(sNull || "—", differs),
+      cell(dNull || "—", differs),
+      cell(s?.default ?? d?.default_value ?? "", "mono"),
+    ];
+  });
+  const headers = ["Column", "schema.json type", "Database type", "schema.json", "Database", "Default"];
+  return `<h3>Columns</h3>${grid(headers, rows)}`;
+}
+
+function indexesSection(schemaTable, dbTable) {
+  const rows = [
+    ...(schemaTable?.indexes ?? []).map((i) => [
+      cell(i.name),
+      cell("schema.json"),
+      cell(i.unique ? "yes" : ""),
+      cell(`(${i.columns.join(", ")})`, "mono"),
+    ]),
+    ...(dbTable?.indexes ?? []).map((i) => [
+      cell(i.name),
+      cell("database"),
+      cell(i.unique ? "yes" : ""),
+      cell(i.definition, "mono"),
+    ]),
+  ];
+  return `<h3>Indexes</h3>${grid(["Name", "Source", "Unique", "Definition"], rows)}`;
+}
+
+// Foreign keys as { from: [table, columns], to: [table, columns], source }
+function relations() {
+  const found = [];
+  for (const [table, t] of Object.entries(state.schema?.tables ?? {})) {
+    for (const [column, c] of Object.entries(t.columns ?? {})) {
+      if (c.references) {
+        found.push({ from: [table, [column]], to: [c.references.table, [c.references.column]], source: "schema.json" });
+      }
+    }
+    for (const c of t.constraints ?? []) {
+      if (c.constraintType === "foreign key" && c.references) {
+        found.push({ from: [table, c.columns], to: [c.references.table, [c.references.column]], source: "schema.json" });
+      }
+    }
+  }
+  for (const [table, t] of Object.entries(state.db?.tables ?? {})) {
+    for (const fk of t.foreign_keys ?? []) {
+      found.push({ from: [table, fk.columns], to: [fk.references_table, fk.references_columns], source: "database" });
+    }
+  }
+  return found;
+}
+
+function relationsSection(name) {
+  const ref = ([table, columns]) => `${table}(${columns.join(", ")})`;
+  const rows = relations()
+    .filter((r) => r.from[0] === name || r.to[0] === name)
+    .map((r) => [
+      cell(r.from[0] === name ? "→ references" : "← referenced by"),
+      cell(r.from[0] === name ? ref(r.to) : ref(r.from), "mono"),
+      cell(r.source),
+    ]);
+  return `<h3>Relations</h3>${grid(["Direction", "Table", "Source"], rows)}`;
+}
+
+function renderDetail() {
+  const name = state.selected;
+  const schemaTable = state.schema?.tables?.[name];
+  const dbTable = state.db?.tables?.[name];
+  const preview = dbTable
+    ? `<h3>Rows</h3><button id="preview">Preview rows</button><div id="rows"></div>`
+    : "";
+  document.getElementById("detail").innerHTML =
+    `<h2>${escape(name)}</h2>` +
+    (schemaTable?.comment ? `<p class="muted">${escape(schemaTable.comment)}</p>` : "") +
+    columnsSection(schemaTable, dbTable) +
+    indexesSection(schemaTable, dbTable) +
+    relationsSection(name) +
+    preview;
+}
+
+async function previewRows() {
+  const target = document.getElementById("rows");
+  target.innerHTML = `<p class="muted">Loading…</p>`;
+  try {
+    const { rows } = await fetchJson(`/api/rows?table=${encodeURIComponent(state.selected)}&limit=50`);
+    const headers = rows.length ? Object.keys(rows[0]) : [];
+    const format = (v) => (v !== null && typeof v === "object" ? JSON.stringify(v) : v ?? "NULL");
+    target.innerHTML = grid(headers, rows.map((row) => headers.map((h) => cell(format(row[h]), "mono"))));
+  } catch (e) {
+    target.innerHTML = `<p class="error">${escape(e.message)}</p>`;
+  }
+}
+
+async function load() {
+  state.info = await fetchJson("/api/info");
+  const sources = [];
+  try {
+    state.schema = await fetchJson("/api/schema");
+    sources.push(escape(state.info.schema));
+  } catch (e) {
+    sources.push(`<span class="error">${escape(e.message)}</span>`);
+  }
+  if (state.info.database) {
+    try {
+      state.db = await fetchJson("/api/db");
+      sources.push("live database");
+    } catch (e) {
+      sources.push(`<span class="error">${escape(e.message)}</span>`);
+    }
+  }
+  document.getElementById("sources").innerHTML = `<span class="muted">${sources.join(" · ")}</span>`;
+  renderTables();
+}
+
+document.getElementById("filter").addEventListener("input", renderTables);
+document.getElementById("tables").addEventListener("click", (event) => {
+  const item = event.target.closest("li");
+  if (!item) return;
+  state.selected = item.dataset.table;
+  renderTables();
+  renderDetail();
+});
+document.getElementById("detail").addEventListener("click", (event) => {
+  if (event.target.id === "preview") previewRows();
+});
+
+load();
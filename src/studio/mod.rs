@@ -0,0 +1,288 @@
+/**
+ * Stratus Studio Module
+ *
+ * Serves a small local web UI for browsing schema.json and the live database.
+ */
+use crate::db::StratusClient;
+use crate::migrate::MigrationsTable;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tiny_http::{Header, Method, Response, Server, StatusCode};
+
+const INDEX_HTML: &str = include_str!("index.html");
+const APP_JS: &str = include_str!("app.js");
+const STYLE_CSS: &str = include_str!("style.css");
+
+/// Default number of rows returned by a preview
+const DEFAULT_PREVIEW_ROWS: i64 = 50;
+/// Upper bound on rows returned by a preview
+const MAX_PREVIEW_ROWS: i64 = 500;
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+/// Local schema and data browser
+pub struct Studio {
+    schema_path: PathBuf,
+    client: Option<StratusClient>,
+    migrations_table: MigrationsTable,
+}
+
+impl Studio {
+    /// Create a studio; without a client only schema.json is browsable
+    pub fn new(
+        schema_path: PathBuf,
+        client: Option<StratusClient>,
+        migrations_table: MigrationsTable,
+    ) -> Self {
+        Self {
+            schema_path,
+            client,
+            migrations_table,
+        }
+    }
+
+    /// Serve requests on `addr` until the process is stopped
+    pub fn serve(mut self, addr: &str) -> Result<(), String> {
+        let server = Server::http(addr).map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+        let bind_host = host_name(addr);
+
+        for request in server.incoming_requests() {
+            let host = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Host"))
+                .map(|header| header.value.as_str());
+            let response = if host.is_some_and(|host| allowed_host(host, bind_host)) {
+                self.handle(request.method(), request.url())
+            } else {
+                error(403, "Host not allowed")
+            };
+            tracing::debug!(
+                method = %request.method(),
+                url = request.url(),
+                status = response.status_code().0,
+                "studio request"
+            );
+            if let Err(e) = request.respond(response) {
+                tracing::warn!("Failed to send studio response: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Route one request
+    fn handle(&mut self, method: &Method, url: &str) -> HttpResponse {
+        if *method != Method::Get {
+            return error(405, "Only GET is supported");
+        }
+
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        match path {
+            "/" | "/index.html" => asset("text/html; charset=utf-8", INDEX_HTML),
+            "/app.js" => asset("text/javascript; charset=utf-8", APP_JS),
+            "/style.css" => asset("text/css; charset=utf-8", STYLE_CSS),
+            "/api/info" => json(
+                200,
+                serde_json::json!({
+                    "schema": self.schema_path.display().to_string(),
+                    "database": self.client.is_some(),
+                })
+                .to_string(),
+            ),
+            "/api/schema" => self.schema(),
+            "/api/db" => self.database(),
+            "/api/rows" => self.rows(query),
+            _ => error(404, "Not found"),
+        }
+    }
+
+    /// schema.json as written, re-read on every request
+    fn schema(&self) -> HttpResponse {
+        match std::fs::read_to_string(&self.schema_path) {
+            Ok(contents) => json(200, contents),
+            Err(e) => error(
+                404,
+                &format!("Cannot read {}: {}", self.schema_path.display(), e),
+            ),
+        }
+    }
+
+    /// Live database schema with indexes and foreign keys per table
+    fn database(&mut self) -> HttpResponse {
+        let Some(client) = self.client.as_mut() else {
+            return error(503, "No database connected");
+        };
+
+        let result = client.get_schema().and_then(|mut schema| {
+            self.migrations_table.exclude_from(&mut schema);
+            let mut indexes = client.get_indexes()?;
+            let mut foreign_keys = client.get_foreign_keys()?;
+
+            let mut value = serde_json::to_value(&schema).unwrap_or_default();
+            if let Some(tables) = value["tables"].as_object_mut() {
+                for (name, table) in tables.iter_mut() {
+                    table["indexes"] =
+                        serde_json::to_value(indexes.remove(name).unwrap_or_default())
+                            .unwrap_or_default();
+                    table["foreign_keys"] =
+                        serde_json::to_value(foreign_keys.remove(name).unwrap_or_default())
+                            .unwrap_or_default();
+                }
+            }
+            Ok(value)
+        });
+
+        match result {
+            Ok(value) => json(200, value.to_string()),
+            Err(e) => error(500, &e.to_string()),
+        }
+    }
+
+    /// First rows of a table
+    fn rows(&mut self, query: &str) -> HttpResponse {
+        let Some(client) = self.client.as_mut() else {
+            return error(503, "No database connected");
+        };
+        let Some(table) = query_param(query, "table") else {
+            return error(400, "Missing 'table' parameter");
+        };
+        let limit = query_param(query, "limit")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(DEFAULT_PREVIEW_ROWS)
+            .clamp(1, MAX_PREVIEW_ROWS);
+
+        match client.preview_rows(&table, limit) {
+            Ok(rows) => json(200, serde_json::json!({ "rows": rows }).to_string()),
+            Err(e) => error(400, &e.to_string()),
+        }
+    }
+}
+
+/// Whether a Host header names this machine: localhost, a loopback address or
+/// the address the studio is bound to. Any other name is a site that pointed
+/// its DNS at the studio (DNS rebinding) to read the database from a browser
+fn allowed_host(host: &str, bind_host: &str) -> bool {
+    let host = host_name(host);
+    host.eq_ignore_ascii_case("localhost")
+        || host == "127.0.0.1"
+        || host == "::1"
+        || host.eq_ignore_ascii_case(bind_host)
+}
+
+/// The host of a `host:port` pair, without the brackets of an IPv6 address
+fn host_name(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match authority.rsplit_once(':') {
+        Some((host, _)) if !host.contains(':') => host,
+        _ => authority,
+    }
+}
+
+fn respond(status: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    let header = Header::from_bytes("Content-Type", content_type).expect("valid header");
+    Response::from_data(body)
+        .with_status_code(StatusCode(status))
+        .with_header(header)
+}
+
+fn asset(content_type: &str, body: &'static str) -> HttpResponse {
+    respond(200, content_type, body.as_bytes().to_vec())
+}
+
+fn json(status: u16, body: String) -> HttpResponse {
+    respond(status, "application/json", body.into_bytes())
+}
+
+fn error(status: u16, message: &str) -> HttpResponse {
+    json(status, serde_json::json!({ "error": message }).to_string())
+}
+
+/// Look up a percent-encoded query string parameter
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 3 <= bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn studio() -> Studio {
+        Studio::new(
+            PathBuf::from("/nonexistent/schema.json"),
+            None,
+            MigrationsTable::default(),
+        )
+    }
+
+    #[test]
+    fn test_query_param_decodes() {
+        let query = "table=order%20items&limit=10&empty=";
+        assert_eq!(query_param(query, "table").as_deref(), Some("order items"));
+        assert_eq!(query_param(query, "limit").as_deref(), Some("10"));
+        assert_eq!(query_param(query, "empty").as_deref(), Some(""));
+        assert_eq!(query_param(query, "missing"), None);
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("a%2"), "a%2");
+    }
+
+    #[test]
+    fn test_routes_without_database() {
+        let mut studio = studio();
+        let status =
+            |s: &mut Studio, method: Method, url: &str| s.handle(&method, url).status_code().0;
+
+        assert_eq!(status(&mut studio, Method::Get, "/"), 200);
+        assert_eq!(status(&mut studio, Method::Get, "/app.js"), 200);
+        assert_eq!(status(&mut studio, Method::Get, "/api/info"), 200);
+        assert_eq!(status(&mut studio, Method::Get, "/api/schema"), 404);
+        assert_eq!(status(&mut studio, Method::Get, "/api/db"), 503);
+        assert_eq!(
+            status(&mut studio, Method::Get, "/api/rows?table=users"),
+            503
+        );
+        assert_eq!(status(&mut studio, Method::Get, "/nope"), 404);
+        assert_eq!(status(&mut studio, Method::Post, "/"), 405);
+    }
+
+    #[test]
+    fn test_only_local_hosts_are_allowed() {
+        assert!(allowed_host("localhost:5555", "127.0.0.1"));
+        assert!(allowed_host("127.0.0.1:5555", "127.0.0.1"));
+        assert!(allowed_host("[::1]:5555", "127.0.0.1"));
+        assert!(allowed_host("LOCALHOST", "127.0.0.1"));
+        assert!(allowed_host("10.0.0.5:5555", host_name("10.0.0.5:5555")));
+        assert!(!allowed_host("attacker.example:5555", "127.0.0.1"));
+        assert!(!allowed_host("10.0.0.5:5555", "127.0.0.1"));
+    }
+}
@@ -0,0 +1,186 @@
+/**
+ * Stratus Testing Module
+ *
+ * Throwaway PostgreSQL databases for integration tests, in Docker through
+ * testcontainers. A test database starts empty, optionally has a migrations
+ * directory deployed and a schema.json pushed to it, and comes back with a
+ * connected client. The container is removed when the database is dropped.
+ *
+ * ```no_run
+ * let mut db = stratus::testing::TestDatabase::builder()
+ *     .migrations_dir("migrations")
+ *     .start()?;
+ * let rows = db.client().query("SELECT count(*)::text AS n FROM users")?;
+ * # Ok::<(), Box<dyn std::error::Error>>(())
+ * ```
+ */
+use crate::db::{DbError, StratusClient};
+use crate::engine::{Engine, EngineBuilder, EngineError};
+use std::path::PathBuf;
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::SyncRunner;
+use testcontainers::{Container, GenericImage, ImageExt, TestcontainersError};
+use thiserror::Error;
+
+/// Image tag used unless [`TestDatabaseBuilder::tag`] says otherwise
+pub const DEFAULT_TAG: &str = "16-alpine";
+
+/// Logged twice by the postgres image: once by the initdb server, once when really ready
+const READY: &str = "database system is ready to accept connections";
+
+/// Testing errors
+#[derive(Error, Debug)]
+pub enum TestingError {
+    #[error("Cannot start the database container: {0}")]
+    Container(#[from] TestcontainersError),
+
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+
+    #[error(transparent)]
+    Db(#[from] DbError),
+
+    #[error("Pushing the schema failed at statement {statement}: {source}")]
+    Push { statement: usize, source: DbError },
+}
+
+pub type TestingResult<T> = Result<T, TestingError>;
+
+/// Builder for [`TestDatabase`]
+#[derive(Debug, Default)]
+pub struct TestDatabaseBuilder {
+    tag: Option<String>,
+    migrations_dir: Option<PathBuf>,
+    schema: Option<PathBuf>,
+}
+
+impl TestDatabaseBuilder {
+    /// Tag of the `postgres` image
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Deploy the migrations in this directory once the database is up
+    pub fn migrations_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.migrations_dir = Some(path.into());
+        self
+    }
+
+    /// Create what this schema.json describes, after any migrations
+    pub fn schema(mut self, path: impl Into<PathBuf>) -> Self {
+        self.schema = Some(path.into());
+        self
+    }
+
+    /// Start the container, wait for PostgreSQL and set up the database
+    pub fn start(self) -> TestingResult<TestDatabase> {
+        let tag = self.tag.as_deref().unwrap_or(DEFAULT_TAG);
+        let container = GenericImage::new("postgres", tag)
+            .with_exposed_port(5432.tcp())
+            .with_wait_for(WaitFor::message_on_stderr(READY))
+            .with_wait_for(WaitFor::message_on_stdout(READY))
+            .with_env_var("POSTGRES_HOST_AUTH_METHOD", "trust")
+            .start()?;
+        let url = format!(
+            "postgresql://postgres@{}:{}/postgres",
+            container.get_host()?,
+            container.get_host_port_ipv4(5432)?
+        );
+
+        let mut builder = Engine::builder().without_config().url(&url);
+        if let Some(dir) = &self.migrations_dir {
+            builder = builder.migrations_dir(dir);
+        }
+        if let Some(schema) = &self.schema {
+            builder = builder.schema(schema);
+        }
+        let engine = builder.build()?;
+        let mut client = engine.connect()?;
+
+        if self.migrations_dir.is_some() {
+            engine.deploy().run(&mut client, &mut ())?;
+        }
+        if self.schema.is_some() {
+            let diff = crate::db::compare_schemas(
+                &engine.load_schema()?,
+                &engine.introspect(&mut client)?,
+            );
+            crate::engine::execute_script(&mut client, &diff.sql(), &mut ())
+                .map_err(|(statement, source)| TestingError::Push { statement, source })?;
+        }
+
+        Ok(TestDatabase {
+            client,
+            url,
+            _container: container,
+        })
+    }
+}
+
+/// A PostgreSQL database in a container of its own
+pub struct TestDatabase {
+    // Declared before the container so it disconnects first
+    client: StratusClient,
+    url: String,
+    _container: Container<GenericImage>,
+}
+
+impl TestDatabase {
+    pub fn builder() -> TestDatabaseBuilder {
+        TestDatabaseBuilder::default()
+    }
+
+    /// An empty database with the default image
+    pub fn start() -> TestingResult<Self> {
+        Self::builder().start()
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The client connected when the database was set up
+    pub fn client(&mut self) -> &mut StratusClient {
+        &mut self.client
+    }
+
+    /// Another connection to the database
+    pub fn connect(&self) -> TestingResult<StratusClient> {
+        Ok(self.engine().build()?.connect()?)
+    }
+
+    /// An engine builder aimed at this database, without stratus.json
+    pub fn engine(&self) -> EngineBuilder {
+        Engine::builder().without_config().url(&self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs Docker"]
+    fn test_schema_is_pushed_to_a_fresh_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let schema = dir.join("schema.json");
+        std::fs::write(
+            &schema,
+            r#"{"tables": {"users": {"columns": {"email": {"name": "email", "type": "text", "isNotNull": true}}}}}"#,
+        )
+        .unwrap();
+
+        let mut db = TestDatabase::builder().schema(&schema).start().unwrap();
+        db.client()
+            .execute("INSERT INTO users (email) VALUES ('a@example.com')")
+            .unwrap();
+        let rows = db
+            .connect()
+            .unwrap()
+            .query("SELECT email FROM users")
+            .unwrap();
+        assert_eq!(rows[0]["email"], "a@example.com");
+    }
+}
@@ -6,7 +6,19 @@
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
+use crate::codegen::GeneratorOptions;
 use crate::parser::{extract_select_columns, extract_tables_from_sql, parse, SelectColumn};
+use crate::schema::Schema;
+
+fn schema_from_json(json: &str) -> Result<Schema, String> {
+    serde_json::from_str(json).map_err(|e| format!("Invalid schema: {}", e))
+}
+
+fn options_from_json(json: Option<String>) -> Result<GeneratorOptions, String> {
+    json.map_or(Ok(GeneratorOptions::default()), |json| {
+        serde_json::from_str(&json).map_err(|e| format!("Invalid generator options: {}", e))
+    })
+}
 
 /// Parse TypeSQL content and return JSON string
 ///
@@ -18,10 +30,141 @@ use crate::parser::{extract_select_columns, extract_tables_from_sql, parse, Sele
 #[cfg(feature = "wasm")]
 #[wasm_bindgen]
 pub fn parse_typesql(input: &str) -> Result<String, String> {
-    let result = parse(input)?;
+    let result = parse(input).map_err(|e| e.to_string())?;
     serde_json::to_string(&result).map_err(|e| format!("JSON serialization error: {}", e))
 }
 
+/// Parse TypeSQL content and report problems, checked against a schema when given
+///
+/// # Arguments
+/// * `input` - TypeSQL content
+/// * `schema_json` - Optional schema.json content
+///
+/// # Returns
+/// JSON array of diagnostics with severity, span, message and code
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn check_typesql(input: &str, schema_json: Option<String>) -> Result<String, String> {
+    let diagnostics = match schema_json {
+        Some(json) => crate::analyze::check_source(input, &schema_from_json(&json)?),
+        None => crate::parser::parse_with_diagnostics(input).1,
+    };
+    serde_json::to_string(&diagnostics).map_err(|e| format!("JSON serialization error: {}", e))
+}
+
+/// Generate the TypeScript client for a TypeSQL file
+///
+/// # Arguments
+/// * `input` - TypeSQL content
+/// * `schema_json` - Optional schema.json content, for typed results
+/// * `options_json` - Optional generator options, as in stratus.json `generators[].options`
+///
+/// # Returns
+/// TypeScript source
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn generate_ts(
+    input: &str,
+    schema_json: Option<String>,
+    options_json: Option<String>,
+) -> Result<String, String> {
+    generate("ts", input, schema_json, options_json)
+}
+
+/// Generate the Python client for a TypeSQL file
+///
+/// # Arguments
+/// * `input` - TypeSQL content
+/// * `schema_json` - Optional schema.json content, for typed results
+/// * `options_json` - Optional generator options
+///
+/// # Returns
+/// Python source
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn generate_py(
+    input: &str,
+    schema_json: Option<String>,
+    options_json: Option<String>,
+) -> Result<String, String> {
+    generate("py", input, schema_json, options_json)
+}
+
+/// Generate TypeScript types for every table of a schema
+///
+/// # Arguments
+/// * `schema_json` - schema.json content
+/// * `options_json` - Optional generator options
+///
+/// # Returns
+/// TypeScript source
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn generate_ts_types_only(
+    schema_json: &str,
+    options_json: Option<String>,
+) -> Result<String, String> {
+    let schema = schema_from_json(schema_json)?;
+    crate::codegen::generate(
+        "ts-types",
+        None,
+        Some(&schema),
+        &options_from_json(options_json)?,
+    )
+}
+
+fn generate(
+    provider: &str,
+    input: &str,
+    schema_json: Option<String>,
+    options_json: Option<String>,
+) -> Result<String, String> {
+    let query_file = parse(input).map_err(|e| e.to_string())?;
+    let schema = schema_json.as_deref().map(schema_from_json).transpose()?;
+    crate::codegen::generate(
+        provider,
+        Some(&query_file),
+        schema.as_ref(),
+        &options_from_json(options_json)?,
+    )
+}
+
+/// Validate schema.json content
+///
+/// # Arguments
+/// * `json` - schema.json content
+///
+/// # Returns
+/// JSON object `{ valid, diagnostics }`; diagnostics cover syntax errors,
+/// structure errors and foreign keys to unknown tables or columns
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn validate_schema(json: &str) -> Result<String, String> {
+    let (schema, diagnostics) = crate::schema::validate(json);
+    serde_json::to_string(&serde_json::json!({
+        "valid": schema.is_some() && !diagnostics.has_errors(),
+        "diagnostics": diagnostics,
+    }))
+    .map_err(|e| format!("JSON serialization error: {}", e))
+}
+
+/// Diff two versions of a schema
+///
+/// # Arguments
+/// * `from_json` - schema.json content before the change
+/// * `to_json` - schema.json content after the change
+///
+/// # Returns
+/// JSON object with the tables and columns created and dropped, data loss
+/// warnings and the migration SQL
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn diff_schemas(from_json: &str, to_json: &str) -> Result<String, String> {
+    let diff =
+        crate::diff::diff_schemas(&schema_from_json(from_json)?, &schema_from_json(to_json)?);
+    serde_json::to_string(&diff.with_sql()).map_err(|e| format!("JSON serialization error: {}", e))
+}
+
 /// Extract table names from SQL query
 ///
 /// # Arguments
@@ -42,7 +185,7 @@ pub fn extract_tables(sql: &str) -> Result<String, String> {
 /// * `sql` - SELECT query string
 ///
 /// # Returns
-/// JSON array of column objects with table_name, column_name, is_wildcard
+/// JSON array of column objects with table_name, column_name, alias, is_wildcard
 #[cfg(feature = "wasm")]
 #[wasm_bindgen]
 pub fn extract_columns(sql: &str) -> Result<String, String> {
@@ -53,6 +196,7 @@ pub fn extract_columns(sql: &str) -> Result<String, String> {
             serde_json::json!({
                 "table_name": c.table_name,
                 "column_name": c.column_name,
+                "alias": c.alias,
                 "is_wildcard": c.is_wildcard,
             })
         })
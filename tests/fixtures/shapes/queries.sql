@@ -0,0 +1,8 @@
+# name: AccountByHandle :one handle: string
+SELECT id, address, prefs, scores, tags FROM accounts WHERE handle = $1;
+
+# name: CountAccounts :one
+SELECT count(*) AS total FROM accounts;
+
+# name: ListAccounts :many limit: number
+SELECT * FROM accounts ORDER BY id LIMIT $1;
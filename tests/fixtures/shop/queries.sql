@@ -0,0 +1,38 @@
+# name: GetUser :one id: number @cache(ttl=60s)
+SELECT id, name, email, created_at FROM users WHERE id = $1;
+
+# name: ListUsers :many limit: number offset: number
+SELECT id, name, email FROM users ORDER BY created_at DESC LIMIT $1 OFFSET $2;
+
+# name: CreateUser :one name: string email: string
+INSERT INTO users (name, email) VALUES ($1, $2) RETURNING id, name, email;
+
+# name: UpdateUser :one id: number name: string
+UPDATE users SET name = $2 WHERE id = $1 RETURNING id, name, email;
+
+# name: DeleteUser :one id: number
+DELETE FROM users WHERE id = $1 RETURNING id;
+
+# name: GetUserPosts :many user_id: number
+SELECT id, title, content, created_at FROM posts WHERE user_id = $1 ORDER BY created_at DESC;
+
+# name: ListUserPostsAfter :many user_id: number after: number limit: number
+SELECT id, title FROM posts WHERE user_id = $1 AND id > $2 ORDER BY id LIMIT $3;
+
+# name: GetUserWithOrders :many id: number
+SELECT users.*, orders.* FROM users JOIN orders ON users.id = orders.user_id WHERE users.id = $1;
+
+# name: GetUserInfo :one id: number
+SELECT users.id, users.email, users.username FROM users WHERE users.id = $1;
+
+# name: GetOrderDetails :many user_id: number
+SELECT 
+    orders.id,
+    orders.order_number,
+    orders.total_amount,
+    orders.status,
+    users.email,
+    users.username
+FROM orders
+JOIN users ON orders.user_id = users.id
+WHERE orders.user_id = $1;
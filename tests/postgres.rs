@@ -0,0 +1,230 @@
+//! Tests against a real PostgreSQL, in Docker through `stratus::testing`
+//!
+//! They need the `testing` feature and a Docker daemon, so they are ignored
+//! by default: `cargo test --features testing -- --ignored`.
+#![cfg(feature = "testing")]
+
+use std::ops::ControlFlow;
+use stratus::db::CopyFormat;
+use stratus::testing::TestDatabase;
+
+const SHOP: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/shop");
+
+#[test]
+#[ignore = "needs Docker"]
+fn test_shop_fixture_pushes() {
+    let mut db = TestDatabase::builder()
+        .schema(format!("{}/schema.json", SHOP))
+        .start()
+        .unwrap();
+    let client = db.client();
+    client
+        .execute(
+            "INSERT INTO users (email, username, password_hash, tags)
+             VALUES ('ann@example.com', 'ann', 'x', '{admin}')",
+        )
+        .unwrap();
+    // The email check is a real regex, not one matching a backslash
+    assert!(client
+        .execute(
+            "INSERT INTO users (email, username, password_hash) VALUES ('ann@example', 'bob', 'x')"
+        )
+        .is_err());
+
+    // Pushing again changes nothing
+    let engine = db
+        .engine()
+        .schema(format!("{}/schema.json", SHOP))
+        .build()
+        .unwrap();
+    let diff = stratus::db::compare_schemas(
+        &engine.load_schema().unwrap(),
+        &engine.introspect(db.client()).unwrap(),
+    );
+    assert!(!diff.has_changes(), "{}", diff.sql());
+}
+
+#[test]
+#[ignore = "needs Docker"]
+fn test_introspection_reads_the_catalog() {
+    let mut db = TestDatabase::start().unwrap();
+    let client = db.client();
+    client
+        .execute(
+            "CREATE TYPE mood AS ENUM ('happy', 'sad');
+             CREATE TABLE teams (id BIGINT PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE users (
+                 id BIGINT PRIMARY KEY,
+                 team_id BIGINT,
+                 email VARCHAR(120) NOT NULL,
+                 mood mood,
+                 CONSTRAINT users_team_fk FOREIGN KEY (team_id) REFERENCES teams (id)
+                     ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED,
+                 CONSTRAINT users_email_key UNIQUE (email) DEFERRABLE,
+                 CONSTRAINT users_email_check CHECK (email <> '')
+             );
+             CREATE TABLE bookings (
+                 during TSTZRANGE,
+                 CONSTRAINT no_overlap EXCLUDE USING gist (during WITH &&)
+             );
+             CREATE ROLE reader;
+             GRANT SELECT ON users TO reader;
+             GRANT UPDATE (email) ON users TO reader;",
+        )
+        .unwrap();
+
+    let schema = client.get_schema().unwrap();
+    // Columns and primary keys of every table, read in one query each
+    assert_eq!(schema.tables.len(), 3);
+    let users = &schema.tables["users"];
+    assert_eq!(users.primary_key, vec!["id"]);
+    assert_eq!(users.columns["email"].size, Some(120));
+    assert!(!users.columns["email"].is_nullable);
+    assert_eq!(schema.tables["teams"].primary_key, vec!["id"]);
+
+    assert_eq!(schema.enums["mood"], vec!["happy", "sad"]);
+
+    let team = &users.foreign_keys[0];
+    assert_eq!(team.name, "users_team_fk");
+    assert_eq!(team.columns, vec!["team_id"]);
+    assert_eq!(team.references_table, "teams");
+    assert_eq!(team.on_delete.as_deref(), Some("CASCADE"));
+    assert!(team.deferrable && team.initially_deferred);
+
+    let constraint = |table: &str, name: &str| {
+        schema.tables[table]
+            .constraints
+            .iter()
+            .find(|c| c.name == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("no constraint {}", name))
+    };
+    let email = constraint("users", "users_email_key");
+    assert_eq!(email.columns, vec!["email"]);
+    assert!(email.deferrable && !email.initially_deferred);
+    assert!(constraint("users", "users_email_check")
+        .expression
+        .is_some());
+    assert!(constraint("bookings", "no_overlap")
+        .expression
+        .unwrap()
+        .contains("gist"));
+
+    assert!(schema.roles.contains(&"reader".to_string()));
+    let grants: Vec<(Option<&str>, &str)> = schema
+        .grants
+        .iter()
+        .filter(|g| g.role == "reader")
+        .map(|g| (g.column.as_deref(), g.privilege.as_str()))
+        .collect();
+    assert_eq!(grants, vec![(None, "SELECT"), (Some("email"), "UPDATE")]);
+}
+
+#[test]
+#[ignore = "needs Docker"]
+fn test_copy_in_and_out() {
+    let mut db = TestDatabase::start().unwrap();
+    let client = db.client();
+    client
+        .execute("CREATE TABLE notes (id INTEGER, body TEXT)")
+        .unwrap();
+    let columns = vec!["id".to_string(), "body".to_string()];
+
+    let rows = vec![
+        vec![Some("1".to_string()), Some("tab\there".to_string())],
+        vec![Some("2".to_string()), None],
+        vec![Some("3".to_string()), Some("line\nbreak".to_string())],
+    ];
+    let mut batches = 0;
+    let copied = client
+        .copy_rows("notes", &columns, &rows, 2, |_| batches += 1)
+        .unwrap();
+    assert_eq!((copied, batches), (3, 2));
+
+    let csv = "id,body\n4,\"a, b\"\n";
+    let count = client
+        .copy_in(
+            "notes",
+            &columns,
+            CopyFormat::Csv { header: true },
+            &mut csv.as_bytes(),
+            |_| {},
+        )
+        .unwrap();
+    assert_eq!(count.rows, 1);
+
+    let mut out = Vec::new();
+    let count = client
+        .copy_out(
+            "SELECT id, body FROM notes ORDER BY id",
+            CopyFormat::Text,
+            &mut out,
+            |_| {},
+        )
+        .unwrap();
+    assert_eq!(count.rows, 4);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "1\ttab\\there\n2\t\\N\n3\tline\\nbreak\n4\ta, b\n"
+    );
+}
+
+#[test]
+#[ignore = "needs Docker"]
+fn test_query_each_fetches_in_batches() {
+    let mut db = TestDatabase::start().unwrap();
+    let client = db.client();
+    let sql = "SELECT n::text AS n FROM generate_series(1, 10) n";
+
+    let mut seen = Vec::new();
+    let count = client
+        .query_each(sql, 3, |row| {
+            seen.push(row["n"].clone());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert_eq!(count, 10);
+    assert_eq!(seen.first().map(String::as_str), Some("1"));
+    assert_eq!(seen.last().map(String::as_str), Some("10"));
+
+    // Breaking stops the fetching, and the connection stays usable
+    let count = client
+        .query_each_values(sql, 3, |row| {
+            if row[0].as_deref() == Some("4") {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+    assert_eq!(count, 4);
+    assert_eq!(
+        client.query("SELECT 1::text AS one").unwrap()[0]["one"],
+        "1"
+    );
+}
+
+#[test]
+#[ignore = "needs Docker"]
+fn test_errors_carry_the_server_detail() {
+    let mut db = TestDatabase::start().unwrap();
+    let client = db.client();
+    client
+        .execute("CREATE TABLE items (qty INTEGER CONSTRAINT chk_qty CHECK (qty > 0))")
+        .unwrap();
+
+    let error = client
+        .execute("INSERT INTO items VALUES (-5)")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("violates check constraint \"chk_qty\""),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains("detail: Failing row contains (-5)."),
+        "{}",
+        error
+    );
+}
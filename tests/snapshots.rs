@@ -0,0 +1,28 @@
+//! Golden-file tests for the built-in generators
+//!
+//! Every fixture under tests/fixtures is rendered by every provider and
+//! compared with tests/snapshots. After an intended change to generated code,
+//! review and accept the new output with `cargo insta review` (or rerun with
+//! `INSTA_UPDATE=always`).
+
+use stratus::codegen::{fixture::Fixture, GeneratorOptions, PROVIDERS};
+
+#[test]
+fn test_generators_match_snapshots() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    let fixtures = Fixture::all(root).unwrap();
+    assert!(!fixtures.is_empty(), "no fixtures under {}", root);
+
+    for fixture in &fixtures {
+        for provider in PROVIDERS {
+            let needs_schema = provider.ends_with("-types");
+            if needs_schema && fixture.schema.is_none() {
+                continue;
+            }
+            let output = fixture
+                .render(provider, &GeneratorOptions::default())
+                .unwrap_or_else(|e| panic!("{} with {}: {}", fixture.name, provider, e));
+            insta::assert_snapshot!(fixture.snapshot_name(provider), output);
+        }
+    }
+}